@@ -0,0 +1,279 @@
+//! The commands that use the argument list: `:args`, `:argadd`,
+//! `:argdelete`, `:argedit`, `:argument`, `:next`, `:Next`, `:first`,
+//! `:last`, `:argdo`, `:arglocal` and `:argglobal`.  Editing a file is left
+//! to the caller, see [`ArgAction`].
+
+use std::path::Path;
+
+use rust_autocmd::glob_to_regex;
+
+use crate::expand::{expand_args, split_args};
+use crate::{is_locked, ArgList, WindowArgs};
+
+/// Command names with the length they can be abbreviated to.
+const COMMANDS: &[(&str, usize)] = &[
+    ("args", 2),
+    ("argadd", 4),
+    ("argdelete", 4),
+    ("argdo", 5),
+    ("argedit", 4),
+    ("argglobal", 4),
+    ("arglocal", 4),
+    ("argument", 4),
+    ("next", 1),
+    ("Next", 1),
+    ("previous", 4),
+    ("first", 3),
+    ("rewind", 3),
+    ("last", 2),
+];
+
+/// What the caller has to do after a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgAction {
+    None,
+    /// Show the argument list, for `:args`.
+    Message(String),
+    /// Edit argument `idx` of the window's list, `bang` to discard changes.
+    /// When the buffer may be abandoned the caller makes it the current
+    /// argument.
+    Edit { idx: usize, name: String, bang: bool },
+    /// `:argdo`: edit each argument and execute `cmd` in it.
+    ArgDo { cmd: String, bang: bool },
+}
+
+/// The full name of argument list command `name`, which may be abbreviated.
+pub fn command_name(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .find(|(full, min)| name.len() >= *min && full.starts_with(name))
+        .map(|(full, _)| *full)
+}
+
+struct Cmd<'a> {
+    name: &'static str,
+    range: &'a str,
+    bang: bool,
+    arg: &'a str,
+}
+
+/// Execute command line `line` when it is an argument list command, None
+/// when it is not.  `cur_name` is the name of the current buffer, for
+/// `:argadd` without a name, and file names are relative to `cwd`.
+pub fn execute(
+    line: &str,
+    global: &mut ArgList,
+    win: &mut WindowArgs,
+    cur_name: &str,
+    cwd: &Path,
+) -> Option<Result<ArgAction, String>> {
+    let line = line.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+    let range_len = line.find(|c: char| !"0123456789.$%,+- ".contains(c)).unwrap_or(line.len());
+    let (range, rest) = line.split_at(range_len);
+    let name_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+    let name = command_name(&rest[..name_len])?;
+    let rest = &rest[name_len..];
+    let (bang, arg) = match rest.strip_prefix('!') {
+        Some(arg) => (true, arg),
+        None => (false, rest),
+    };
+    let cmd = Cmd { name, range: range.trim(), bang, arg: arg.trim() };
+    Some(run(&cmd, global, win, cur_name, cwd))
+}
+
+fn run(cmd: &Cmd, global: &mut ArgList, win: &mut WindowArgs, cur_name: &str, cwd: &Path) -> Result<ArgAction, String> {
+    let changes = match cmd.name {
+        "argadd" | "argdelete" | "argedit" => true,
+        "args" | "next" | "arglocal" | "argglobal" => !cmd.arg.is_empty(),
+        _ => false,
+    };
+    if changes && is_locked() {
+        return Err("E1156: Cannot change the argument list recursively".to_string());
+    }
+    let len = win.list(global).len();
+    let idx = win.idx as isize;
+    let count = parse_range(cmd.range, win.idx, len)?.map(|(_, end)| end);
+    match cmd.name {
+        "args" if cmd.arg.is_empty() => Ok(ArgAction::Message(list_message(win.list(global), win.idx))),
+        "args" | "next" if !cmd.arg.is_empty() => {
+            *win.list_mut(global) = ArgList::from_names(expand_args(cmd.arg, cwd)?);
+            goto(global, win, 0, cmd.bang)
+        }
+        "next" => goto(global, win, idx + count.unwrap_or(1) as isize, cmd.bang),
+        "Next" | "previous" => goto(global, win, idx - count.unwrap_or(1) as isize, cmd.bang),
+        "first" | "rewind" => goto(global, win, 0, cmd.bang),
+        "last" => goto(global, win, len as isize - 1, cmd.bang),
+        "argument" => {
+            let nr = match cmd.arg {
+                "" => count.unwrap_or(win.idx + 1),
+                arg => arg.parse().map_err(|_| format!("E488: Trailing characters: {}", arg))?,
+            };
+            goto(global, win, nr as isize - 1, cmd.bang)
+        }
+        "argadd" => {
+            let names = match cmd.arg {
+                "" if cur_name.is_empty() => return Ok(ArgAction::None),
+                "" => vec![cur_name.to_string()],
+                arg => expand_args(arg, cwd)?,
+            };
+            let at = count.map_or(if len == 0 { 0 } else { win.idx + 1 }, |n| n.min(len));
+            add(global, win, at, names);
+            Ok(ArgAction::None)
+        }
+        "argedit" => {
+            if cmd.arg.is_empty() {
+                return Err("E471: Argument required".to_string());
+            }
+            let names = expand_args(cmd.arg, cwd)?;
+            if let [name] = names.as_slice() {
+                if let Some(pos) = win.list(global).position(name) {
+                    return goto(global, win, pos as isize, cmd.bang);
+                }
+            }
+            let at = if len == 0 { 0 } else { win.idx + 1 };
+            add(global, win, at, names);
+            goto(global, win, at as isize, cmd.bang)
+        }
+        "argdelete" => {
+            argdelete(cmd, global, win)?;
+            Ok(ArgAction::None)
+        }
+        "argdo" => {
+            if cmd.arg.is_empty() {
+                return Err("E471: Argument required".to_string());
+            }
+            Ok(ArgAction::ArgDo { cmd: cmd.arg.to_string(), bang: cmd.bang })
+        }
+        "arglocal" if cmd.arg.is_empty() => {
+            if win.local.is_none() {
+                win.local = Some(global.clone());
+            }
+            Ok(ArgAction::None)
+        }
+        "arglocal" => {
+            win.local = Some(ArgList::from_names(expand_args(cmd.arg, cwd)?));
+            goto(global, win, 0, cmd.bang)
+        }
+        _ => {
+            win.local = None;
+            if cmd.arg.is_empty() {
+                win.idx = win.idx.min(global.len().saturating_sub(1));
+                return Ok(ArgAction::None);
+            }
+            *global = ArgList::from_names(expand_args(cmd.arg, cwd)?);
+            goto(global, win, 0, cmd.bang)
+        }
+    }
+}
+
+/// `:args` output: the names with the current one in [].
+fn list_message(list: &ArgList, idx: usize) -> String {
+    let names: Vec<String> = list
+        .names()
+        .iter()
+        .enumerate()
+        .map(|(i, name)| if i == idx { format!("[{}]", name) } else { name.clone() })
+        .collect();
+    names.join(" ")
+}
+
+/// Edit argument `argn`.
+fn goto(global: &ArgList, win: &WindowArgs, argn: isize, bang: bool) -> Result<ArgAction, String> {
+    let list = win.list(global);
+    let len = list.len();
+    if argn < 0 || argn as usize >= len {
+        return Err(if len <= 1 {
+            "E163: There is only one file to edit"
+        } else if argn < 0 {
+            "E164: Cannot go before first file"
+        } else {
+            "E165: Cannot go beyond last file"
+        }
+        .to_string());
+    }
+    let idx = argn as usize;
+    let name = list.get(idx).unwrap_or_default().to_string();
+    Ok(ArgAction::Edit { idx, name, bang })
+}
+
+/// Insert `names` before argument `at`, the current argument stays the same.
+fn add(global: &mut ArgList, win: &mut WindowArgs, at: usize, names: Vec<String>) {
+    let was_empty = win.list(global).is_empty();
+    let count = names.len();
+    win.list_mut(global).insert(at, names);
+    if at <= win.idx && !was_empty {
+        win.idx += count;
+    }
+}
+
+fn argdelete(cmd: &Cmd, global: &mut ArgList, win: &mut WindowArgs) -> Result<(), String> {
+    let idx = win.idx;
+    let list = win.list_mut(global);
+    let len = list.len();
+    let mut new_idx = idx;
+    if cmd.arg.is_empty() {
+        let Some((start, end)) = parse_range(cmd.range, idx, len)? else {
+            return Err("E471: Argument required".to_string());
+        };
+        if start == 0 || start > end || end > len {
+            return Err("E16: Invalid range".to_string());
+        }
+        list.delete(start - 1, end);
+        if idx >= end {
+            new_idx = idx - (end - start + 1);
+        } else if idx >= start - 1 {
+            new_idx = start - 1;
+        }
+    } else {
+        for pat in split_args(cmd.arg) {
+            let regex = glob_to_regex(&pat)?;
+            let deleted = list.delete_if(|name| regex.is_match(name));
+            if deleted.is_empty() {
+                return Err(format!("E480: No match: {}", pat));
+            }
+            new_idx -= deleted.iter().filter(|&&d| d < new_idx).count();
+        }
+    }
+    win.idx = new_idx.min(list.len().saturating_sub(1));
+    Ok(())
+}
+
+/// The first and last argument numbers in `range`, starting at one.  A
+/// single number is used for both.  `idx` is the current argument.
+fn parse_range(range: &str, idx: usize, len: usize) -> Result<Option<(usize, usize)>, String> {
+    let range: String = range.chars().filter(|c| !c.is_whitespace()).collect();
+    if range.is_empty() {
+        return Ok(None);
+    }
+    if range == "%" {
+        return Ok(Some((1, len)));
+    }
+    let addrs = range.split(',').map(|a| address(a, idx, len)).collect::<Result<Vec<_>, _>>()?;
+    match addrs.as_slice() {
+        [nr] => Ok(Some((*nr, *nr))),
+        [start, end] => Ok(Some((*start, *end))),
+        _ => Err("E16: Invalid range".to_string()),
+    }
+}
+
+/// An argument number: a number, "." for the current, "$" for the last,
+/// followed by "+N" or "-N".
+fn address(addr: &str, idx: usize, len: usize) -> Result<usize, String> {
+    let digits = addr.find(|c: char| !c.is_ascii_digit()).unwrap_or(addr.len());
+    let (mut nr, mut rest) = match addr.as_bytes().first() {
+        Some(b'.') => (idx as isize + 1, &addr[1..]),
+        Some(b'$') => (len as isize, &addr[1..]),
+        Some(b'0'..=b'9') => (addr[..digits].parse().map_err(|_| "E16: Invalid range")?, &addr[digits..]),
+        _ => (idx as isize + 1, addr),
+    };
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let digits = rest[1..].find(|c: char| !c.is_ascii_digit()).map_or(rest.len(), |i| i + 1);
+        let n: isize = if digits == 1 { 1 } else { rest[1..digits].parse().map_err(|_| "E16: Invalid range")? };
+        nr += if sign == '+' { n } else { -n };
+        rest = &rest[digits..];
+    }
+    if !rest.is_empty() || nr < 0 {
+        return Err("E16: Invalid range".to_string());
+    }
+    Ok(nr as usize)
+}
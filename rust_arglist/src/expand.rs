@@ -0,0 +1,223 @@
+//! Expanding the file names given to `:args` and friends: `*`, `?`, `[abc]`
+//! and `{a,b}` in a path segment, `**` for any number of directories, `~`
+//! for the home directory and `` `cmd` `` for the output of a shell command.
+
+use std::path::Path;
+use std::process::Command;
+
+use rust_autocmd::glob_to_regex;
+
+/// Split `arg` into file names at white space.  A backslash escapes the
+/// next character and is kept, so that expanding can tell `\*` from `*`.
+/// A name in backticks may contain white space.
+pub fn split_args(arg: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut name = String::new();
+    let mut chars = arg.chars();
+    let mut in_backtick = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                name.push(c);
+                name.extend(chars.next());
+            }
+            '`' => {
+                in_backtick = !in_backtick;
+                name.push(c);
+            }
+            c if c.is_whitespace() && !in_backtick => {
+                if !name.is_empty() {
+                    names.push(std::mem::take(&mut name));
+                }
+            }
+            c => name.push(c),
+        }
+    }
+    if !name.is_empty() {
+        names.push(name);
+    }
+    names
+}
+
+/// Split `arg` and expand the names, relative to directory `cwd`.  The
+/// matches of a pattern are sorted, a pattern that matches nothing is kept.
+pub fn expand_args(arg: &str, cwd: &Path) -> Result<Vec<String>, String> {
+    let mut result = Vec::new();
+    for word in split_args(arg) {
+        if word.len() >= 2 && word.starts_with('`') && word.ends_with('`') {
+            result.extend(backtick(&word[1..word.len() - 1], cwd)?);
+            continue;
+        }
+        let word = expand_tilde(&word);
+        let found = if has_wildcard(&word) { glob(&word, cwd)? } else { Vec::new() };
+        if found.is_empty() {
+            result.push(unescape(&word));
+        } else {
+            result.extend(found);
+        }
+    }
+    Ok(result)
+}
+
+/// The file names `cmd` writes, separated by white space.  A backslash
+/// before a space or tab makes it part of the name.
+fn backtick(cmd: &str, cwd: &Path) -> Result<Vec<String>, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .current_dir(cwd)
+        .output()
+        .map_err(|_| format!("E475: Invalid argument: `{}`", cmd))?;
+    let mut names = Vec::new();
+    let mut name = String::new();
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|&c| c == ' ' || c == '\t') => name.extend(chars.next()),
+            c if c.is_whitespace() => {
+                if !name.is_empty() {
+                    names.push(std::mem::take(&mut name));
+                }
+            }
+            c => name.push(c),
+        }
+    }
+    if !name.is_empty() {
+        names.push(name);
+    }
+    Ok(names)
+}
+
+fn expand_tilde(word: &str) -> String {
+    match (word.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home, rest),
+        _ => word.to_string(),
+    }
+}
+
+fn has_wildcard(word: &str) -> bool {
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' | '{' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn unescape(word: &str) -> String {
+    let mut result = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            result.extend(chars.next());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// The files matching `pat`.  Names starting with a dot only match a
+/// segment that starts with a dot.
+fn glob(pat: &str, cwd: &Path) -> Result<Vec<String>, String> {
+    // Each entry is a directory prefix ending in "/", or empty for `cwd`.
+    let (mut found, rest) = match pat.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pat),
+    };
+    let segments: Vec<&str> = rest.split('/').filter(|s| !s.is_empty()).collect();
+    for (i, seg) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        let regex = if has_wildcard(seg) && *seg != "**" { Some(glob_to_regex(seg)?) } else { None };
+        let mut next = Vec::new();
+        for prefix in &found {
+            let dir = cwd.join(if prefix.is_empty() { "." } else { prefix });
+            if *seg == "**" {
+                next.push(prefix.clone());
+                subdirs(&dir, prefix, &mut next);
+                continue;
+            }
+            let Some(regex) = &regex else {
+                next.push(format!("{}{}{}", prefix, unescape(seg), if last { "" } else { "/" }));
+                continue;
+            };
+            for (name, is_dir) in entries(&dir) {
+                if (name.starts_with('.') && !seg.starts_with('.')) || !regex.is_match(&name) {
+                    continue;
+                }
+                if last {
+                    next.push(format!("{}{}", prefix, name));
+                } else if is_dir {
+                    next.push(format!("{}{}/", prefix, name));
+                }
+            }
+        }
+        found = next;
+    }
+    let mut result: Vec<String> = found
+        .into_iter()
+        .map(|f| if f.len() > 1 { f.trim_end_matches('/').to_string() } else { f })
+        .filter(|f| !f.is_empty() && cwd.join(f).symlink_metadata().is_ok())
+        .collect();
+    result.sort();
+    result.dedup();
+    Ok(result)
+}
+
+/// The names in directory `dir` and whether they are directories.
+fn entries(dir: &Path) -> Vec<(String, bool)> {
+    let Ok(read) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    read.flatten()
+        .map(|e| (e.file_name().to_string_lossy().into_owned(), e.path().is_dir()))
+        .collect()
+}
+
+/// Add the directories below `dir`, recursively, for `**`.  Hidden
+/// directories and symbolic links are skipped.
+fn subdirs(dir: &Path, prefix: &str, out: &mut Vec<String>) {
+    let Ok(read) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in read.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || !entry.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        let sub = format!("{}{}/", prefix, name);
+        out.push(sub.clone());
+        subdirs(&entry.path(), &sub, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.rs", "b.txt", ".hidden.rs", "src/lib.rs", "src/x/y.rs", "src/c.txt"] {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let expand = |arg: &str| expand_args(arg, dir.path()).unwrap();
+        assert_eq!(expand("*.rs b.txt"), ["a.rs", "b.txt"]);
+        assert_eq!(expand("**/*.rs"), ["a.rs", "src/lib.rs", "src/x/y.rs"]);
+        assert_eq!(expand("src/*.{rs,txt}"), ["src/c.txt", "src/lib.rs"]);
+        assert_eq!(expand("src/?/*"), ["src/x/y.rs"]);
+        assert_eq!(expand(".*.rs"), [".hidden.rs"]);
+        assert_eq!(expand("*.c new\\ file \\*.rs"), ["*.c", "new file", "*.rs"]);
+        assert_eq!(expand("`ls src/*.txt` a.rs"), ["src/c.txt", "a.rs"]);
+        assert_eq!(expand("`printf '%s\\n' 'one  two' 'three\\ 4' 'x\\y'`"), ["one", "two", "three 4", "x\\y"]);
+        assert_eq!(split_args("a  `echo b c`\tx\\ y"), ["a", "`echo b c`", "x\\ y"]);
+    }
+}
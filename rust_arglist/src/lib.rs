@@ -1,16 +1,105 @@
+//! The argument list: the files given on the command line or with `:args`,
+//! the commands that edit and change it, and wildcard expansion of file
+//! names.
+
+mod cmd;
+mod expand;
+
 use std::ffi::{CStr};
 use std::os::raw::{c_char, c_int};
 use std::sync::{Mutex, OnceLock};
 
+pub use cmd::{command_name, execute, ArgAction};
+pub use expand::{expand_args, split_args};
+
 #[repr(C)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct ArgList {
     args: Vec<String>,
 }
 
 impl ArgList {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self { args: Vec::new() }
     }
+
+    pub fn from_names(names: Vec<String>) -> Self {
+        Self { args: names }
+    }
+
+    pub fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&str> {
+        self.args.get(idx).map(String::as_str)
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.args.iter().position(|a| a == name)
+    }
+
+    /// Insert `names` before argument `at`.
+    pub fn insert(&mut self, at: usize, names: Vec<String>) {
+        let at = at.min(self.args.len());
+        self.args.splice(at..at, names);
+    }
+
+    /// Delete arguments `start` to `end`, not including `end`.
+    pub fn delete(&mut self, start: usize, end: usize) {
+        let end = end.min(self.args.len());
+        if start < end {
+            self.args.drain(start..end);
+        }
+    }
+
+    /// Delete the arguments for which `f` returns true, returns the indexes
+    /// they had.
+    pub fn delete_if(&mut self, mut f: impl FnMut(&str) -> bool) -> Vec<usize> {
+        let mut deleted = Vec::new();
+        let mut idx = 0;
+        self.args.retain(|a| {
+            let delete = f(a);
+            if delete {
+                deleted.push(idx);
+            }
+            idx += 1;
+            !delete
+        });
+        deleted
+    }
+}
+
+/// The argument list of a window: the global one, or its own after
+/// `:arglocal`, and the index of the argument it edits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowArgs {
+    pub local: Option<ArgList>,
+    pub idx: usize,
+}
+
+impl WindowArgs {
+    pub fn list<'a>(&'a self, global: &'a ArgList) -> &'a ArgList {
+        self.local.as_ref().unwrap_or(global)
+    }
+
+    pub fn list_mut<'a>(&'a mut self, global: &'a mut ArgList) -> &'a mut ArgList {
+        self.local.as_mut().unwrap_or(global)
+    }
+}
+
+/// Whether the argument list may not be changed, while autocommands that
+/// use it are executed.
+pub fn is_locked() -> bool {
+    rs_arglist_locked() != 0
 }
 
 static ARGLIST_LOCKED: OnceLock<Mutex<bool>> = OnceLock::new();
@@ -0,0 +1,65 @@
+use std::path::Path;
+
+use rust_arglist::{execute, ArgAction, ArgList, WindowArgs};
+
+fn exec(line: &str, global: &mut ArgList, win: &mut WindowArgs) -> Result<ArgAction, String> {
+    let result = execute(line, global, win, "cur.txt", Path::new(".")).expect("an arglist command");
+    if let Ok(ArgAction::Edit { idx, .. }) = result {
+        win.idx = idx;
+    }
+    result
+}
+
+fn names(list: &ArgList) -> Vec<&str> {
+    list.names().iter().map(String::as_str).collect()
+}
+
+fn edit(idx: usize, name: &str) -> Result<ArgAction, String> {
+    Ok(ArgAction::Edit { idx, name: name.to_string(), bang: false })
+}
+
+#[test]
+fn navigate_and_change() {
+    let mut global = ArgList::new();
+    let mut win = WindowArgs::default();
+    assert_eq!(exec("args a b c", &mut global, &mut win), edit(0, "a"));
+    assert_eq!(exec("n", &mut global, &mut win), edit(1, "b"));
+    assert_eq!(exec("args", &mut global, &mut win), Ok(ArgAction::Message("a [b] c".to_string())));
+    assert_eq!(exec("2n", &mut global, &mut win), Err("E165: Cannot go beyond last file".to_string()));
+    assert_eq!(exec("la", &mut global, &mut win), edit(2, "c"));
+    assert_eq!(exec("3N", &mut global, &mut win), Err("E164: Cannot go before first file".to_string()));
+    assert_eq!(exec("argu 2", &mut global, &mut win), edit(1, "b"));
+
+    exec("0argadd x", &mut global, &mut win).unwrap();
+    exec("$arga", &mut global, &mut win).unwrap();
+    assert_eq!(names(&global), ["x", "a", "b", "c", "cur.txt"]);
+    assert_eq!(win.idx, 2);
+    exec("argd c *.txt", &mut global, &mut win).unwrap();
+    assert_eq!(exec("argd zz", &mut global, &mut win), Err("E480: No match: zz".to_string()));
+    exec("1,2argd", &mut global, &mut win).unwrap();
+    assert_eq!((names(&global), win.idx), (vec!["b"], 0));
+    assert_eq!(exec("argd", &mut global, &mut win), Err("E471: Argument required".to_string()));
+    assert_eq!(exec("first", &mut global, &mut win), edit(0, "b"));
+    exec("%argd", &mut global, &mut win).unwrap();
+    assert_eq!(exec("next", &mut global, &mut win), Err("E163: There is only one file to edit".to_string()));
+    assert_eq!(exec("argedit y", &mut global, &mut win), edit(0, "y"));
+    assert!(execute("nohlsearch", &mut global, &mut win, "", Path::new(".")).is_none());
+}
+
+#[test]
+fn local_arglist() {
+    let mut global = ArgList::from_names(vec!["a".into(), "b".into()]);
+    let mut win = WindowArgs::default();
+    exec("arglocal", &mut global, &mut win).unwrap();
+    exec("argadd c", &mut global, &mut win).unwrap();
+    assert_eq!(names(win.list(&global)), ["a", "c", "b"]);
+    assert_eq!(names(&global), ["a", "b"]);
+    exec("arglocal! x y", &mut global, &mut win).unwrap();
+    assert_eq!(names(win.list(&global)), ["x", "y"]);
+    exec("argglobal", &mut global, &mut win).unwrap();
+    assert_eq!(win.local, None);
+    assert_eq!(
+        exec("argdo! s/a/b/ | update", &mut global, &mut win),
+        Ok(ArgAction::ArgDo { cmd: "s/a/b/ | update".to_string(), bang: true })
+    );
+}
@@ -0,0 +1,194 @@
+//! Autocommand event names.
+
+use std::fmt;
+
+macro_rules! events {
+    ($($name:ident),* $(,)?) => {
+        /// An event that triggers autocommands.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum Event {
+            $($name,)*
+        }
+
+        /// All events, in alphabetical order.
+        pub const ALL_EVENTS: &[Event] = &[$(Event::$name,)*];
+
+        impl Event {
+            /// The name as it is used in `:autocmd`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Event::$name => stringify!($name),)*
+                }
+            }
+        }
+    };
+}
+
+events! {
+    BufAdd,
+    BufDelete,
+    BufEnter,
+    BufFilePost,
+    BufFilePre,
+    BufHidden,
+    BufLeave,
+    BufNew,
+    BufNewFile,
+    BufReadCmd,
+    BufReadPost,
+    BufReadPre,
+    BufUnload,
+    BufWinEnter,
+    BufWinLeave,
+    BufWipeout,
+    BufWriteCmd,
+    BufWritePost,
+    BufWritePre,
+    CmdlineChanged,
+    CmdlineEnter,
+    CmdlineLeave,
+    CmdwinEnter,
+    CmdwinLeave,
+    ColorScheme,
+    ColorSchemePre,
+    CompleteChanged,
+    CompleteDone,
+    CompleteDonePre,
+    CursorHold,
+    CursorHoldI,
+    CursorMoved,
+    CursorMovedI,
+    DiffUpdated,
+    DirChanged,
+    DirChangedPre,
+    EncodingChanged,
+    ExitPre,
+    FileAppendCmd,
+    FileAppendPost,
+    FileAppendPre,
+    FileChangedRO,
+    FileChangedShell,
+    FileChangedShellPost,
+    FileReadCmd,
+    FileReadPost,
+    FileReadPre,
+    FileType,
+    FileWriteCmd,
+    FileWritePost,
+    FileWritePre,
+    FilterReadPost,
+    FilterReadPre,
+    FilterWritePost,
+    FilterWritePre,
+    FocusGained,
+    FocusLost,
+    FuncUndefined,
+    GUIEnter,
+    GUIFailed,
+    InsertChange,
+    InsertCharPre,
+    InsertEnter,
+    InsertLeave,
+    InsertLeavePre,
+    MenuPopup,
+    ModeChanged,
+    OptionSet,
+    QuickFixCmdPost,
+    QuickFixCmdPre,
+    QuitPre,
+    RemoteReply,
+    SafeState,
+    SafeStateAgain,
+    SessionLoadPost,
+    SessionWritePost,
+    ShellCmdPost,
+    ShellFilterPost,
+    SigUSR1,
+    SourceCmd,
+    SourcePost,
+    SourcePre,
+    SpellFileMissing,
+    StdinReadPost,
+    StdinReadPre,
+    SwapExists,
+    Syntax,
+    TabClosed,
+    TabEnter,
+    TabLeave,
+    TabNew,
+    TermChanged,
+    TermResponse,
+    TerminalOpen,
+    TerminalWinOpen,
+    TextChanged,
+    TextChangedI,
+    TextChangedP,
+    TextChangedT,
+    TextYankPost,
+    User,
+    VimEnter,
+    VimLeave,
+    VimLeavePre,
+    VimResized,
+    VimResume,
+    VimSuspend,
+    WinClosed,
+    WinEnter,
+    WinLeave,
+    WinNew,
+    WinNewPre,
+    WinResized,
+    WinScrolled,
+}
+
+/// Old names that are still accepted.
+const ALIASES: &[(&str, Event)] = &[
+    ("BufCreate", Event::BufAdd),
+    ("BufRead", Event::BufReadPost),
+    ("BufWrite", Event::BufWritePre),
+    ("FileEncoding", Event::EncodingChanged),
+];
+
+impl Event {
+    /// Find an event by name, ignoring case like Vim does.
+    pub fn from_name(name: &str) -> Option<Event> {
+        ALL_EVENTS
+            .iter()
+            .map(|&event| (event.name(), event))
+            .chain(ALIASES.iter().copied())
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, event)| event)
+    }
+
+    /// Whether the pattern of the event is matched against a file name.
+    /// For other events it is matched against e.g. the file type.
+    pub fn matches_file(self) -> bool {
+        !matches!(
+            self,
+            Event::ColorScheme
+                | Event::ColorSchemePre
+                | Event::DirChanged
+                | Event::DirChangedPre
+                | Event::FileType
+                | Event::FuncUndefined
+                | Event::ModeChanged
+                | Event::OptionSet
+                | Event::QuickFixCmdPost
+                | Event::QuickFixCmdPre
+                | Event::RemoteReply
+                | Event::SpellFileMissing
+                | Event::Syntax
+                | Event::TermResponse
+                | Event::User
+                | Event::WinClosed
+                | Event::WinResized
+                | Event::WinScrolled
+        )
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
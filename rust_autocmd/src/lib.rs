@@ -1,26 +1,493 @@
+//! Autocommands: `:autocmd`, `:augroup` and the matching done for
+//! `:doautocmd`.
+//!
+//! [`AutoCmds`] keeps the autocommands and decides which ones run for an
+//! event; executing them is up to the caller.  [`AutoCmds::apply`] returns
+//! the commands to execute, each one is executed between
+//! [`AutoCmds::enter`] and [`AutoCmds::leave`], which keeps track of the
+//! nesting.  While autocommands are executing, an event only triggers
+//! autocommands when the executing one was defined with `++nested`.
+
 use libc::{c_char, c_int};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use std::collections::HashMap;
-use std::ffi::{CStr, CString};
+use std::ffi::CStr;
 use std::sync::Mutex;
 
+mod event;
+mod pattern;
+
+pub use event::{Event, ALL_EVENTS};
+pub use pattern::glob_to_regex;
+
+/// How deep autocommands can trigger other autocommands.
+pub const MAX_NESTING: usize = 10;
+
+/// The ID of the default group, used when no `:augroup` is in effect.
+pub const DEFAULT_GROUP: usize = 0;
+
+#[derive(Debug)]
 struct AutoCmd {
-    cmd: CString,
+    cmd: String,
     once: bool,
     nested: bool,
+    /// The script the autocommand was defined in.
+    sid: usize,
 }
 
+#[derive(Debug)]
 struct AutoPat {
+    group: usize,
+    /// The pattern as shown by `:autocmd`, "<buffer=N>" for a buffer-local
+    /// autocommand.
     pattern: String,
-    regex: Regex,
+    /// The buffer number of a buffer-local autocommand.
+    buflocal: Option<usize>,
+    /// None for a buffer-local autocommand.
+    regex: Option<Regex>,
     cmds: Vec<AutoCmd>,
 }
 
-type Event = i32;
+impl AutoPat {
+    fn matches(&self, fname: &str, buf: usize) -> bool {
+        match (&self.regex, self.buflocal) {
+            (_, Some(nr)) => nr == buf,
+            (Some(regex), None) => pattern::matches(&self.pattern, regex, fname),
+            (None, None) => false,
+        }
+    }
+}
+
+/// An autocommand to execute, as returned by [`AutoCmds::apply`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoCmdRun {
+    pub event: Event,
+    /// The pattern that matched.
+    pub pattern: String,
+    pub cmd: String,
+    pub nested: bool,
+    /// The script the autocommand was defined in, zero when not sourcing.
+    pub sid: usize,
+    /// What the pattern matched, for `<amatch>`.
+    pub amatch: String,
+    /// The file name, for `<afile>`.
+    pub afile: String,
+    /// The buffer number, for `<abuf>`.
+    pub abuf: usize,
+}
+
+/// What [`AutoCmds::enter`] changed, to be passed to [`AutoCmds::leave`].
+#[derive(Debug)]
+#[must_use]
+pub struct NestState {
+    nested: bool,
+    abuf: Option<usize>,
+}
+
+/// All defined autocommands and groups.
+#[derive(Debug)]
+pub struct AutoCmds {
+    /// Group names by ID, None for a deleted group.  The default group has
+    /// no name.
+    groups: Vec<Option<String>>,
+    current_group: usize,
+    pats: HashMap<Event, Vec<AutoPat>>,
+    /// Number of autocommands being executed.
+    nesting: usize,
+    /// The autocommand being executed was defined with `++nested`.
+    nested: bool,
+    /// The buffer of the autocommand being executed, for `<buffer=abuf>`.
+    abuf: Option<usize>,
+}
+
+/// The arguments of `:autocmd` after the group.
+struct AutoCmdArgs<'a> {
+    /// None for all events.
+    events: Option<Vec<Event>>,
+    pattern: &'a str,
+    once: bool,
+    nested: bool,
+    cmd: &'a str,
+}
+
+/// Split off the first word, which ends at white space that is not
+/// preceded by a backslash.
+fn split_word(arg: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (i, c) in arg.char_indices() {
+        if c.is_whitespace() && !escaped {
+            return (&arg[..i], arg[i..].trim_start());
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    (arg, "")
+}
+
+/// Split a pattern at commas that are not inside {} and not escaped.
+fn split_patterns(pat: &str) -> Vec<&str> {
+    let mut pats = Vec::new();
+    let mut start = 0;
+    let mut braces = 0;
+    let mut escaped = false;
+    for (i, c) in pat.char_indices() {
+        match c {
+            _ if escaped => {}
+            '{' => braces += 1,
+            '}' => braces -= 1,
+            ',' if braces == 0 => {
+                pats.push(&pat[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    pats.push(&pat[start..]);
+    pats.retain(|p| !p.is_empty());
+    pats
+}
+
+impl AutoCmds {
+    pub fn new() -> Self {
+        AutoCmds {
+            groups: vec![None],
+            current_group: DEFAULT_GROUP,
+            pats: HashMap::new(),
+            nesting: 0,
+            nested: false,
+            abuf: None,
+        }
+    }
+
+    /// The ID of group `name`.
+    pub fn group_id(&self, name: &str) -> Option<usize> {
+        self.groups.iter().position(|g| g.as_deref() == Some(name))
+    }
+
+    /// The names of the defined groups.
+    pub fn group_names(&self) -> impl Iterator<Item = &str> {
+        self.groups.iter().flatten().map(String::as_str)
+    }
+
+    /// The group that new autocommands are added to.
+    pub fn current_group(&self) -> usize {
+        self.current_group
+    }
+
+    /// `:augroup[!] {name}`.  Without a name the group names are returned,
+    /// to be listed.  "END" goes back to the default group, with the bang
+    /// the group and its autocommands are deleted.
+    pub fn do_augroup(&mut self, arg: &str, bang: bool) -> Result<Vec<String>, String> {
+        let name = arg.trim();
+        if bang {
+            if name.is_empty() {
+                return Err("E471: Argument required".to_string());
+            }
+            let Some(id) = self.group_id(name) else {
+                return Err(format!("E367: No such group: \"{}\"", name));
+            };
+            if id == self.current_group {
+                return Err("E936: Cannot delete the current group".to_string());
+            }
+            for pats in self.pats.values_mut() {
+                pats.retain(|ap| ap.group != id);
+            }
+            self.groups[id] = None;
+        } else if name.is_empty() {
+            let names: Vec<&str> = self.groups.iter().flatten().map(String::as_str).collect();
+            return Ok(if names.is_empty() { Vec::new() } else { vec![names.join("  ")] });
+        } else if name.eq_ignore_ascii_case("end") {
+            self.current_group = DEFAULT_GROUP;
+        } else if let Some(id) = self.group_id(name) {
+            self.current_group = id;
+        } else {
+            self.groups.push(Some(name.to_string()));
+            self.current_group = self.groups.len() - 1;
+        }
+        Ok(Vec::new())
+    }
+
+    /// `:autocmd[!] [group] [{event} [{pat} [++once] [++nested] [{cmd}]]]`.
+    /// Defines, removes or lists autocommands; returns the lines of a
+    /// listing.  `curbuf` is the buffer number used for `<buffer>`, `sid`
+    /// the script being sourced.
+    pub fn do_autocmd(&mut self, arg: &str, bang: bool, curbuf: usize, sid: usize) -> Result<Vec<String>, String> {
+        let (word, rest) = split_word(arg.trim_start());
+        let (group, arg) = match self.group_id(word).filter(|_| !word.is_empty()) {
+            Some(id) => (Some(id), rest),
+            None => (None, arg.trim_start()),
+        };
+        let args = self.parse_args(arg, group.is_none())?;
+        let mut pats = Vec::new();
+        for pat in split_patterns(args.pattern) {
+            pats.push(self.normalize_pattern(pat, curbuf)?);
+        }
+        let events = args.events.clone().unwrap_or_else(|| ALL_EVENTS.to_vec());
+
+        if bang {
+            let group = group.unwrap_or(self.current_group);
+            for event in &events {
+                if let Some(list) = self.pats.get_mut(event) {
+                    list.retain(|ap| {
+                        ap.group != group || !(pats.is_empty() || pats.iter().any(|(p, _)| *p == ap.pattern))
+                    });
+                }
+            }
+        }
+        if args.cmd.is_empty() {
+            return Ok(if bang { Vec::new() } else { self.list(group, &events, &pats) });
+        }
+        if args.events.is_none() {
+            return Err("E1155: Cannot define autocommands for ALL events".to_string());
+        }
+        if pats.is_empty() {
+            return Err("E471: Argument required".to_string());
+        }
+        let group = group.unwrap_or(self.current_group);
+        for event in events {
+            for (pattern, buflocal) in &pats {
+                self.add(event, group, pattern, *buflocal, &args, sid)?;
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    fn parse_args<'a>(&self, arg: &'a str, no_group: bool) -> Result<AutoCmdArgs<'a>, String> {
+        let (word, rest) = split_word(arg);
+        let events = if word.is_empty() || word == "*" {
+            None
+        } else {
+            let mut events = Vec::new();
+            for name in word.split(',') {
+                match Event::from_name(name) {
+                    Some(event) => events.push(event),
+                    None if no_group && events.is_empty() => {
+                        return Err(format!("E216: No such group or event: {}", arg))
+                    }
+                    None => return Err(format!("E216: No such event: {}", name)),
+                }
+            }
+            Some(events)
+        };
+        let (pattern, mut rest) = split_word(rest);
+        let mut once = false;
+        let mut nested = false;
+        loop {
+            let (word, after) = split_word(rest);
+            let flag = match word {
+                "++once" => &mut once,
+                "++nested" | "nested" => &mut nested,
+                _ => break,
+            };
+            if *flag {
+                return Err(format!("E983: Duplicate argument: {}", word));
+            }
+            *flag = true;
+            rest = after;
+        }
+        Ok(AutoCmdArgs { events, pattern, once, nested, cmd: rest })
+    }
+
+    /// Turn "<buffer>", "<buffer=N>" and "<buffer=abuf>" into "<buffer=N>"
+    /// and the buffer number.
+    fn normalize_pattern(&self, pat: &str, curbuf: usize) -> Result<(String, Option<usize>), String> {
+        let Some(rest) = pat.strip_prefix("<buffer").and_then(|p| p.strip_suffix('>')) else {
+            return Ok((pat.to_string(), None));
+        };
+        let nr = match rest.strip_prefix('=') {
+            None if rest.is_empty() => Some(curbuf),
+            Some("abuf") => self.abuf,
+            Some(n) => n.parse().ok().filter(|&n| n > 0),
+            None => None,
+        };
+        match nr {
+            Some(nr) => Ok((format!("<buffer={}>", nr), Some(nr))),
+            None => Err(format!("E680: <buffer={}>: invalid buffer number", rest.trim_start_matches('='))),
+        }
+    }
+
+    fn add(
+        &mut self,
+        event: Event,
+        group: usize,
+        pattern: &str,
+        buflocal: Option<usize>,
+        args: &AutoCmdArgs,
+        sid: usize,
+    ) -> Result<(), String> {
+        let cmd = AutoCmd { cmd: args.cmd.to_string(), once: args.once, nested: args.nested, sid };
+        let list = self.pats.entry(event).or_default();
+        // Only the last pattern of the event is extended, to keep the order
+        // in which the autocommands were defined.
+        if let Some(ap) = list.last_mut().filter(|ap| ap.group == group && ap.pattern == pattern) {
+            ap.cmds.push(cmd);
+            return Ok(());
+        }
+        let regex = match buflocal {
+            Some(_) => None,
+            None => Some(glob_to_regex(pattern)?),
+        };
+        list.push(AutoPat { group, pattern: pattern.to_string(), buflocal, regex, cmds: vec![cmd] });
+        Ok(())
+    }
+
+    /// The lines `:autocmd` shows for `events` and `pats`, all groups when
+    /// `group` is None.
+    fn list(&self, group: Option<usize>, events: &[Event], pats: &[(String, Option<usize>)]) -> Vec<String> {
+        let mut lines = vec!["--- Autocommands ---".to_string()];
+        for event in events {
+            let mut last_group = None;
+            for ap in self.pats.get(event).into_iter().flatten() {
+                if group.is_some_and(|g| g != ap.group)
+                    || !(pats.is_empty() || pats.iter().any(|(p, _)| *p == ap.pattern))
+                {
+                    continue;
+                }
+                if last_group != Some(ap.group) {
+                    lines.push(match self.groups[ap.group].as_deref() {
+                        Some(name) => format!("{}  {}", name, event),
+                        None => event.to_string(),
+                    });
+                    last_group = Some(ap.group);
+                }
+                let mut first = Some(&ap.pattern).filter(|p| p.len() < 10);
+                if first.is_none() {
+                    lines.push(format!("    {}", ap.pattern));
+                }
+                for ac in &ap.cmds {
+                    let flags = match (ac.once, ac.nested) {
+                        (true, true) => "++once ++nested ",
+                        (true, false) => "++once ",
+                        (false, true) => "++nested ",
+                        (false, false) => "",
+                    };
+                    lines.push(format!("    {:<10}{}{}", first.take().map_or("", String::as_str), flags, ac.cmd));
+                }
+            }
+        }
+        lines
+    }
+
+    /// The arguments of `:doautocmd [group] {event}[,{event}] [{fname}]`:
+    /// the group, the events and the file name, empty when not given.
+    pub fn do_args<'a>(&self, arg: &'a str) -> Result<(Option<usize>, Vec<Event>, &'a str), String> {
+        let arg = arg.trim_start();
+        let arg = arg.strip_prefix("<nomodeline>").map_or(arg, str::trim_start);
+        let (word, rest) = split_word(arg);
+        let (group, arg) = match self.group_id(word).filter(|_| !word.is_empty()) {
+            Some(id) => (Some(id), rest),
+            None => (None, arg),
+        };
+        let (word, fname) = split_word(arg);
+        if word == "*" {
+            return Err("E217: Can't execute autocommands for ALL events".to_string());
+        }
+        match self.parse_args(word, group.is_none())?.events {
+            Some(events) => Ok((group, events, fname)),
+            None => Err("E471: Argument required".to_string()),
+        }
+    }
+
+    /// Remove the buffer-local autocommands of buffer `buf`, when it is
+    /// wiped out.
+    pub fn buf_deleted(&mut self, buf: usize) {
+        for pats in self.pats.values_mut() {
+            pats.retain(|ap| ap.buflocal != Some(buf));
+        }
+    }
+
+    /// Whether there is an autocommand for `event` that matches `fname` or
+    /// buffer `buf`.
+    pub fn has_autocmd(&self, event: Event, fname: &str, buf: usize) -> bool {
+        self.pats.get(&event).into_iter().flatten().any(|ap| ap.matches(fname, buf))
+    }
+
+    /// Whether there is an autocommand for `event`, in `group` and with
+    /// exactly `pattern` when given, like Vim's au_exists().
+    pub fn has_event(&self, event: Event, group: Option<usize>, pattern: Option<&str>) -> bool {
+        self.pats
+            .get(&event)
+            .into_iter()
+            .flatten()
+            .any(|ap| group.is_none_or(|g| ap.group == g) && pattern.is_none_or(|p| ap.pattern == p))
+    }
+
+    /// Whether autocommands are being executed.
+    pub fn is_busy(&self) -> bool {
+        self.nesting > 0
+    }
+
+    /// The autocommands to execute for `event` with file name `fname` in
+    /// buffer `buf`, in the order they were defined; only those of `group`
+    /// when given.  While autocommands are executing none are returned,
+    /// unless `force` is set or the executing autocommand is nested.
+    /// Autocommands defined with `++once` are removed.
+    pub fn apply(
+        &mut self,
+        event: Event,
+        fname: &str,
+        buf: usize,
+        force: bool,
+        group: Option<usize>,
+    ) -> Result<Vec<AutoCmdRun>, String> {
+        if self.is_busy() && !(force || self.nested) {
+            return Ok(Vec::new());
+        }
+        if self.nesting >= MAX_NESTING {
+            return Err("E218: Autocommand nesting too deep".to_string());
+        }
+        let mut runs = Vec::new();
+        let Some(list) = self.pats.get_mut(&event) else {
+            return Ok(runs);
+        };
+        for ap in list.iter_mut() {
+            if group.is_some_and(|g| g != ap.group) || !ap.matches(fname, buf) {
+                continue;
+            }
+            for ac in &ap.cmds {
+                runs.push(AutoCmdRun {
+                    event,
+                    pattern: ap.pattern.clone(),
+                    cmd: ac.cmd.clone(),
+                    nested: ac.nested,
+                    sid: ac.sid,
+                    amatch: fname.to_string(),
+                    afile: fname.to_string(),
+                    abuf: buf,
+                });
+            }
+            ap.cmds.retain(|ac| !ac.once);
+        }
+        list.retain(|ap| !ap.cmds.is_empty());
+        Ok(runs)
+    }
+
+    /// Start executing `run`.
+    pub fn enter(&mut self, run: &AutoCmdRun) -> NestState {
+        let state = NestState { nested: self.nested, abuf: self.abuf };
+        self.nesting += 1;
+        self.nested = run.nested;
+        self.abuf = Some(run.abuf);
+        state
+    }
+
+    /// Done executing the autocommand that [`AutoCmds::enter`] was called
+    /// for.
+    pub fn leave(&mut self, state: NestState) {
+        self.nesting -= 1;
+        self.nested = state.nested;
+        self.abuf = state.abuf;
+    }
+}
+
+impl Default for AutoCmds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-static AUTOCMDS: Lazy<Mutex<HashMap<Event, Vec<AutoPat>>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
+static AUTOCMDS: Lazy<Mutex<AutoCmds>> = Lazy::new(|| Mutex::new(AutoCmds::new()));
 
 fn ptr_to_str<'a>(ptr: *const c_char) -> Option<&'a str> {
     if ptr.is_null() {
@@ -29,24 +496,129 @@ fn ptr_to_str<'a>(ptr: *const c_char) -> Option<&'a str> {
     unsafe { CStr::from_ptr(ptr).to_str().ok() }
 }
 
+/// Define autocommands like `:autocmd`.  Returns FAIL (zero) for an error.
+#[no_mangle]
+pub extern "C" fn rust_autocmd_define(arg: *const c_char, forceit: c_int) -> c_int {
+    let Some(arg) = ptr_to_str(arg) else {
+        return 0;
+    };
+    AUTOCMDS.lock().unwrap().do_autocmd(arg, forceit != 0, 0, 0).is_ok() as c_int
+}
+
+/// Apply the autocommands for event number `event`, the index in
+/// [`ALL_EVENTS`], to file `name`.  Returns the number of commands that
+/// match.
 #[no_mangle]
 pub extern "C" fn rust_autocmd_do(event: c_int, name: *const c_char) -> c_int {
     let name = match ptr_to_str(name) {
         Some(s) => s,
         None => return 0,
     };
-    let mut map = AUTOCMDS.lock().unwrap();
-    let mut done = 0;
-    if let Some(list) = map.get_mut(&event) {
-        for ap in list.iter_mut() {
-            if ap.regex.is_match(name) {
-                done += ap.cmds.len() as c_int;
-                if ap.cmds.iter().any(|c| c.once) {
-                    ap.cmds.retain(|c| !c.once);
-                }
-            }
+    let Some(&event) = usize::try_from(event).ok().and_then(|i| ALL_EVENTS.get(i)) else {
+        return 0;
+    };
+    let mut autocmds = AUTOCMDS.lock().unwrap();
+    autocmds.apply(event, name, 0, false, None).map_or(0, |runs| runs.len() as c_int)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmds(runs: &[AutoCmdRun]) -> Vec<&str> {
+        runs.iter().map(|run| run.cmd.as_str()).collect()
+    }
+
+    #[test]
+    fn define_and_apply() {
+        let mut ac = AutoCmds::new();
+        ac.do_autocmd("BufRead *.txt setlocal tw=78", false, 1, 0).unwrap();
+        ac.do_autocmd("bufreadpost *.txt,*.md let x = 1 | let y = 2", false, 1, 0).unwrap();
+        ac.do_autocmd("FileType python ++once echo 'py'", false, 1, 0).unwrap();
+        let runs = ac.apply(Event::BufReadPost, "/tmp/a.txt", 1, false, None).unwrap();
+        assert_eq!(cmds(&runs), ["setlocal tw=78", "let x = 1 | let y = 2"]);
+        assert_eq!(runs[0].amatch, "/tmp/a.txt");
+        assert_eq!(cmds(&ac.apply(Event::BufReadPost, "a.md", 1, false, None).unwrap()), ["let x = 1 | let y = 2"]);
+        assert!(ac.apply(Event::BufWritePre, "a.txt", 1, false, None).unwrap().is_empty());
+
+        assert_eq!(ac.apply(Event::FileType, "python", 1, false, None).unwrap().len(), 1);
+        assert!(ac.apply(Event::FileType, "python", 1, false, None).unwrap().is_empty());
+
+        assert_eq!(
+            ac.do_autocmd("BufReadPost", false, 1, 0).unwrap(),
+            [
+                "--- Autocommands ---",
+                "BufReadPost",
+                "    *.txt     setlocal tw=78",
+                "              let x = 1 | let y = 2",
+                "    *.md      let x = 1 | let y = 2",
+            ]
+        );
+        ac.do_autocmd("BufReadPost *.txt", true, 1, 0).unwrap();
+        assert_eq!(cmds(&ac.apply(Event::BufReadPost, "a.md", 1, false, None).unwrap()), ["let x = 1 | let y = 2"]);
+        assert!(ac.apply(Event::BufReadPost, "a.txt", 1, false, None).unwrap().is_empty());
+
+        assert_eq!(ac.do_autocmd("NoSuch * x", false, 1, 0).unwrap_err(), "E216: No such group or event: NoSuch * x");
+        assert_eq!(ac.do_autocmd("* * x", false, 1, 0).unwrap_err(), "E1155: Cannot define autocommands for ALL events");
+        assert_eq!(ac.do_autocmd("User x ++once ++once x", false, 1, 0).unwrap_err(), "E983: Duplicate argument: ++once");
+    }
+
+    #[test]
+    fn groups_and_buffer_local() {
+        let mut ac = AutoCmds::new();
+        ac.do_augroup("mine", false).unwrap();
+        ac.do_autocmd("", true, 1, 0).unwrap();
+        ac.do_autocmd("User Foo echo 'mine'", false, 1, 0).unwrap();
+        ac.do_autocmd("CursorHold <buffer> echo 'local'", false, 3, 0).unwrap();
+        ac.do_augroup("END", false).unwrap();
+        ac.do_autocmd("User Foo echo 'default'", false, 1, 0).unwrap();
+        assert_eq!(ac.do_augroup("", false).unwrap(), ["mine"]);
+
+        let mine = ac.group_id("mine");
+        assert_eq!(cmds(&ac.apply(Event::User, "Foo", 1, false, mine).unwrap()), ["echo 'mine'"]);
+        assert_eq!(ac.apply(Event::User, "Foo", 1, false, None).unwrap().len(), 2);
+        assert!(ac.apply(Event::CursorHold, "x", 1, false, None).unwrap().is_empty());
+        assert_eq!(ac.apply(Event::CursorHold, "x", 3, false, None).unwrap().len(), 1);
+        assert_eq!(ac.do_autocmd("mine CursorHold", false, 1, 0).unwrap()[1..], ["mine  CursorHold", "    <buffer=3>", "              echo 'local'"]);
+
+        // "autocmd!" in the default group leaves the other group alone.
+        ac.do_autocmd("", true, 1, 0).unwrap();
+        assert_eq!(ac.apply(Event::User, "Foo", 1, false, None).unwrap().len(), 1);
+        ac.buf_deleted(3);
+        assert!(!ac.has_autocmd(Event::CursorHold, "x", 3));
+
+        assert_eq!(ac.do_augroup("nosuch", true).unwrap_err(), "E367: No such group: \"nosuch\"");
+        ac.do_augroup("mine", false).unwrap();
+        assert_eq!(ac.do_augroup("mine", true).unwrap_err(), "E936: Cannot delete the current group");
+        ac.do_augroup("END", false).unwrap();
+        ac.do_augroup("mine", true).unwrap();
+        assert!(ac.apply(Event::User, "Foo", 1, false, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn nesting() {
+        let mut ac = AutoCmds::new();
+        ac.do_autocmd("User A doautocmd User B", false, 1, 0).unwrap();
+        ac.do_autocmd("User B ++nested doautocmd User A", false, 1, 0).unwrap();
+        let runs = ac.apply(Event::User, "A", 1, false, None).unwrap();
+        let outer = ac.enter(&runs[0]);
+        // Not nested: "User B" is not triggered from "User A".
+        assert!(ac.apply(Event::User, "B", 1, false, None).unwrap().is_empty());
+        assert_eq!(ac.apply(Event::User, "B", 1, true, None).unwrap().len(), 1);
+        ac.leave(outer);
+
+        let mut states = Vec::new();
+        let mut result = Ok(Vec::new());
+        for _ in 0..=MAX_NESTING {
+            result = ac.apply(Event::User, "B", 1, true, None);
+            let Ok(runs) = &result else { break };
+            states.push(ac.enter(&runs[0]));
         }
-        list.retain(|ap| !ap.cmds.is_empty());
+        assert_eq!(result.unwrap_err(), "E218: Autocommand nesting too deep");
+        assert_eq!(states.len(), MAX_NESTING);
+        for state in states.into_iter().rev() {
+            ac.leave(state);
+        }
+        assert!(!ac.is_busy());
     }
-    done
 }
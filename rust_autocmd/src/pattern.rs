@@ -0,0 +1,111 @@
+//! Autocommand file patterns: Vim glob syntax converted to a regex.
+
+use regex::Regex;
+
+/// Convert a file pattern to a regex that matches the whole name.
+/// `*` matches any text, `?` one character, `{a,b}` one of the
+/// alternatives and `[abc]` one of the characters.  A backslash makes the
+/// next character literal.
+pub fn glob_to_regex(pat: &str) -> Result<Regex, String> {
+    let mut re = String::from("^");
+    let mut chars = pat.chars().peekable();
+    let mut braces = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                while chars.peek() == Some(&'*') {
+                    chars.next();
+                }
+                re.push_str(".*");
+            }
+            '?' => re.push('.'),
+            '{' => {
+                braces += 1;
+                re.push_str("(?:");
+            }
+            '}' if braces > 0 => {
+                braces -= 1;
+                re.push(')');
+            }
+            ',' if braces > 0 => re.push('|'),
+            '[' => {
+                // Without a matching "]" the "[" is literal.
+                let start = chars.clone();
+                let mut class = String::from("[");
+                if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                    class.push('^');
+                }
+                let mut closed = false;
+                let mut empty = true;
+                for c in chars.by_ref() {
+                    // A "]" right after the "[" is part of the class.
+                    if c == ']' && !empty {
+                        closed = true;
+                        break;
+                    }
+                    empty = false;
+                    if c == '[' || c == '\\' {
+                        class.push('\\');
+                    }
+                    class.push(c);
+                }
+                if closed {
+                    re.push_str(&class);
+                    re.push(']');
+                } else {
+                    chars = start;
+                    re.push_str("\\[");
+                }
+            }
+            '\\' => {
+                let next = chars.next().unwrap_or('\\');
+                re.push_str(&regex::escape(&next.to_string()));
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if braces > 0 {
+        return Err(format!("E220: Missing }}: {}", pat));
+    }
+    re.push('$');
+    Regex::new(&re).map_err(|_| format!("E475: Invalid argument: {}", pat))
+}
+
+/// Whether file pattern `pat` matches `fname`.  A pattern without a "/"
+/// only matches the last part of the file name.
+pub fn matches(pat: &str, regex: &Regex, fname: &str) -> bool {
+    if regex.is_match(fname) {
+        return true;
+    }
+    if pat.contains('/') {
+        return false;
+    }
+    fname.rsplit('/').next().is_some_and(|tail| regex.is_match(tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob_match(pat: &str, fname: &str) -> bool {
+        matches(pat, &glob_to_regex(pat).unwrap(), fname)
+    }
+
+    #[test]
+    fn glob_syntax() {
+        assert!(glob_match("*.txt", "notes.txt"));
+        assert!(glob_match("*.txt", "/tmp/dir/notes.txt"));
+        assert!(!glob_match("*.txt", "notes.txt.bak"));
+        assert!(!glob_match("*.txt", "notesXtxt"));
+        assert!(glob_match("*.{c,h}", "main.h"));
+        assert!(!glob_match("*.{c,h}", "main.o"));
+        assert!(glob_match("file?.[ch]", "file1.c"));
+        assert!(!glob_match("file?.[!ch]", "file1.c"));
+        assert!(glob_match("/tmp/*", "/tmp/dir/notes.txt"));
+        assert!(!glob_match("/tmp/*", "/home/tmp/x"));
+        assert!(glob_match("a\\*b", "a*b"));
+        assert!(!glob_match("a\\*b", "axb"));
+        assert!(glob_match("[x", "[x"));
+        assert!(glob_to_regex("*.{c,h").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! The commands that work on the buffer list: `:ls`, `:buffers`, `:files`,
+//! `:badd`, `:bdelete`, `:bwipeout` and `:bunload`.  Showing another buffer
+//! in the windows of a removed buffer is left to the caller, see
+//! [`BufAction`].
+
+use crate::list::BufferList;
+
+/// Command names with the length they can be abbreviated to.
+const COMMANDS: &[(&str, usize)] = &[
+    ("ls", 2),
+    ("buffers", 7),
+    ("files", 5),
+    ("badd", 3),
+    ("bdelete", 2),
+    ("bwipeout", 2),
+    ("bunload", 3),
+];
+
+/// What the caller has to do after a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufAction {
+    None,
+    /// Show these lines, for `:ls`.
+    Message(Vec<String>),
+    /// `:badd` added buffer `nr`, or listed it again.
+    Added(usize),
+    /// Buffers `nrs` were unloaded, deleted or wiped out; windows showing
+    /// them show [`BufferList::replacement`] now.  `msg` is the message to
+    /// give, if any.
+    Removed { nrs: Vec<usize>, wiped: bool, msg: Option<String> },
+}
+
+/// The full name of buffer list command `name`, which may be abbreviated.
+pub fn command_name(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .find(|(full, min)| name.len() >= *min && full.starts_with(name))
+        .map(|(full, _)| *full)
+}
+
+/// Execute command line `line` when it is a buffer list command, None when
+/// it is not.
+pub fn execute(line: &str, list: &mut BufferList) -> Option<Result<BufAction, String>> {
+    let line = line.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+    let range_len = line.find(|c: char| !"0123456789.$%, ".contains(c)).unwrap_or(line.len());
+    let (range, rest) = line.split_at(range_len);
+    let name_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+    let name = command_name(&rest[..name_len])?;
+    let rest = &rest[name_len..];
+    let (bang, arg) = match rest.strip_prefix('!') {
+        Some(arg) => (true, arg.trim()),
+        None => (false, rest.trim()),
+    };
+    Some(match name {
+        "ls" | "buffers" | "files" => list.list(bang, arg).map(BufAction::Message),
+        "badd" => badd(arg, list),
+        _ => remove(name, range.trim(), bang, arg, list),
+    })
+}
+
+/// `:badd [+lnum] {fname}`: add a listed buffer that is not loaded.
+fn badd(arg: &str, list: &mut BufferList) -> Result<BufAction, String> {
+    let (lnum, name) = match arg.strip_prefix('+') {
+        Some(rest) => {
+            let (lnum, name) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            (Some(lnum.parse().map_err(|_| format!("E474: Invalid argument: +{}", lnum))?), name.trim())
+        }
+        None => (None, arg),
+    };
+    if name.is_empty() {
+        return Err("E471: Argument required".to_string());
+    }
+    let nr = list.add(&name.replace("\\ ", " "), true);
+    if let (Some(lnum), Some(buf)) = (lnum, list.get_mut(nr)) {
+        buf.lnum = lnum;
+    }
+    Ok(BufAction::Added(nr))
+}
+
+/// `:bunload`, `:bdelete` and `:bwipeout` with a range of buffer numbers,
+/// or buffer numbers and names, or nothing for the current buffer.
+fn remove(name: &str, range: &str, bang: bool, arg: &str, list: &mut BufferList) -> Result<BufAction, String> {
+    let nrs = if !range.is_empty() {
+        if !arg.is_empty() {
+            return Err(format!("E488: Trailing characters: {}", arg));
+        }
+        let (start, end) = parse_range(range, list)?;
+        // A range skips buffers that do not exist or are not listed.
+        let listed = |nr: &usize| list.get(*nr).is_some_and(|b| b.listed || name == "bwipeout");
+        (start..=end).filter(listed).collect()
+    } else if arg.is_empty() {
+        vec![list.current()]
+    } else {
+        arg.split_whitespace().map(|word| list.lookup(word)).collect::<Result<Vec<_>, _>>()?
+    };
+    let mut removed = Vec::new();
+    for nr in nrs {
+        let Some(buf) = list.get(nr) else { continue };
+        if (name == "bdelete" && !buf.listed) || (name == "bunload" && !buf.loaded) {
+            continue;
+        }
+        if name == "bunload" && nr == list.current() && list.replacement(nr).is_none() {
+            return Err("E90: Cannot unload last buffer".to_string());
+        }
+        match name {
+            "bunload" => list.unload(nr, bang)?,
+            "bdelete" => list.delete(nr, bang)?,
+            _ => list.wipe(nr, bang)?,
+        }
+        removed.push(nr);
+    }
+    let (what, err) = match name {
+        "bunload" => ("unloaded", "E515: No buffers were unloaded"),
+        "bdelete" => ("deleted", "E516: No buffers were deleted"),
+        _ => ("wiped out", "E517: No buffers were wiped out"),
+    };
+    if removed.is_empty() {
+        return Err(err.to_string());
+    }
+    let msg = (removed.len() >= 2).then(|| format!("{} buffers {}", removed.len(), what));
+    Ok(BufAction::Removed { nrs: removed, wiped: name == "bwipeout", msg })
+}
+
+/// The first and last buffer numbers in `range`: "%" for all buffers, or
+/// one or two addresses, a number, "." for the current or "$" for the last
+/// buffer.
+fn parse_range(range: &str, list: &BufferList) -> Result<(usize, usize), String> {
+    let last = list.iter().last().map_or(0, |b| b.nr);
+    let address = |addr: &str| match addr.trim() {
+        "." => Ok(list.current()),
+        "$" => Ok(last),
+        addr => addr.parse().map_err(|_| "E16: Invalid range".to_string()),
+    };
+    if range == "%" {
+        return Ok((1, last));
+    }
+    match range.split_once(',') {
+        Some((start, end)) => {
+            let (start, end) = (address(start)?, address(end)?);
+            if start > end {
+                return Err("E493: Backwards range given".to_string());
+            }
+            Ok((start, end))
+        }
+        None => address(range).map(|nr| (nr, nr)),
+    }
+}
@@ -5,6 +5,12 @@ use std::sync::{
     Mutex, OnceLock,
 };
 
+mod cmd;
+mod list;
+
+pub use cmd::{command_name, execute, BufAction};
+pub use list::{Buf, BufHidden, BufType, BufferList};
+
 // Maintain a list of all allocated buffers so that they can be released safely
 // from Rust.  The buffers are allocated using libc and can therefore be freed
 // here as well, avoiding the need for C code to perform the deallocation.
@@ -37,12 +43,12 @@ struct BufferRecord {
 // Global storage of allocated buffers.  We wrap the Mutex in a newtype so that
 // we can provide manual `Send` and `Sync` implementations even though
 // `NonNull<T>` itself does not implement these traits.
-struct BufferList(Mutex<Vec<BufferRecord>>);
+struct BufferRegistry(Mutex<Vec<BufferRecord>>);
 
-unsafe impl Send for BufferList {}
-unsafe impl Sync for BufferList {}
+unsafe impl Send for BufferRegistry {}
+unsafe impl Sync for BufferRegistry {}
 
-static BUFFERS: OnceLock<BufferList> = OnceLock::new();
+static BUFFERS: OnceLock<BufferRegistry> = OnceLock::new();
 
 // Counter similar to Vim's 'top_file_num', used by get_highest_fnum().
 static TOP_FILE_NUM: AtomicI32 = AtomicI32::new(1);
@@ -66,7 +72,7 @@ pub extern "C" fn buf_alloc(size: usize) -> *mut FileBuffer {
             fnum: next_top_file_num(),
         };
         BUFFERS
-            .get_or_init(|| BufferList(Mutex::new(Vec::new())))
+            .get_or_init(|| BufferRegistry(Mutex::new(Vec::new())))
             .0
             .lock()
             .unwrap()
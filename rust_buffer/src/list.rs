@@ -0,0 +1,537 @@
+//! The buffer list: the buffers by number, whether they are listed, loaded
+//! and shown in a window, their 'buftype' and 'bufhidden', the current and
+//! the alternate buffer, and the output of `:ls`.
+//!
+//! The list does not hold the text.  The editor keeps it up to date with
+//! the number of windows and the changed state of each buffer, and loads
+//! or frees the text when a buffer becomes loaded or unloaded.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The 'buftype' option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BufType {
+    #[default]
+    Normal,
+    NoFile,
+    NoWrite,
+    AcWrite,
+    Quickfix,
+    Help,
+    Terminal,
+    Prompt,
+    Popup,
+}
+
+impl BufType {
+    pub fn from_name(name: &str) -> Option<BufType> {
+        Some(match name {
+            "" => BufType::Normal,
+            "nofile" => BufType::NoFile,
+            "nowrite" => BufType::NoWrite,
+            "acwrite" => BufType::AcWrite,
+            "quickfix" => BufType::Quickfix,
+            "help" => BufType::Help,
+            "terminal" => BufType::Terminal,
+            "prompt" => BufType::Prompt,
+            "popup" => BufType::Popup,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BufType::Normal => "",
+            BufType::NoFile => "nofile",
+            BufType::NoWrite => "nowrite",
+            BufType::AcWrite => "acwrite",
+            BufType::Quickfix => "quickfix",
+            BufType::Help => "help",
+            BufType::Terminal => "terminal",
+            BufType::Prompt => "prompt",
+            BufType::Popup => "popup",
+        }
+    }
+
+    /// Whether `:write` may write the buffer to its file.  An "acwrite"
+    /// buffer is written by BufWriteCmd autocommands.
+    pub fn can_write(self) -> bool {
+        matches!(self, BufType::Normal | BufType::AcWrite | BufType::Help)
+    }
+
+    /// Whether the buffer is kept in memory when it is abandoned, like with
+    /// 'hidden' set.
+    fn always_hidden(self) -> bool {
+        matches!(self, BufType::Terminal | BufType::Prompt | BufType::Popup)
+    }
+}
+
+/// The 'bufhidden' option: what happens to a buffer when it is no longer
+/// shown in a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BufHidden {
+    /// Follow the 'hidden' option.
+    #[default]
+    Default,
+    Hide,
+    Unload,
+    Delete,
+    Wipe,
+}
+
+impl BufHidden {
+    pub fn from_name(name: &str) -> Option<BufHidden> {
+        Some(match name {
+            "" => BufHidden::Default,
+            "hide" => BufHidden::Hide,
+            "unload" => BufHidden::Unload,
+            "delete" => BufHidden::Delete,
+            "wipe" => BufHidden::Wipe,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BufHidden::Default => "",
+            BufHidden::Hide => "hide",
+            BufHidden::Unload => "unload",
+            BufHidden::Delete => "delete",
+            BufHidden::Wipe => "wipe",
+        }
+    }
+}
+
+/// An entry of the buffer list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Buf {
+    pub nr: usize,
+    /// The file name, empty for a buffer without a name.
+    pub name: String,
+    /// Shown by `:ls`, reset by `:bdelete`.
+    pub listed: bool,
+    /// The text is in memory.
+    pub loaded: bool,
+    /// The number of windows showing the buffer.
+    pub windows: usize,
+    pub changed: bool,
+    pub modifiable: bool,
+    pub readonly: bool,
+    pub buftype: BufType,
+    pub bufhidden: BufHidden,
+    /// The cursor line when the buffer was last shown.
+    pub lnum: usize,
+    /// When the buffer was last entered, in seconds since the epoch.
+    pub lastused: u64,
+    /// Orders buffers entered in the same second.
+    used_seq: u64,
+}
+
+impl Buf {
+    /// Loaded but not shown in a window.
+    pub fn hidden(&self) -> bool {
+        self.loaded && self.windows == 0
+    }
+
+    /// The name `:ls` shows, for a buffer without a name a description.
+    pub fn display_name(&self) -> String {
+        if !self.name.is_empty() {
+            return self.name.clone();
+        }
+        match self.buftype {
+            BufType::Quickfix => "[Quickfix List]",
+            BufType::NoFile => "[Scratch]",
+            BufType::Prompt => "[Prompt]",
+            BufType::Popup => "[Popup]",
+            _ => "[No Name]",
+        }
+        .to_string()
+    }
+}
+
+/// All buffers, in the order of their numbers.
+#[derive(Debug, Clone, Default)]
+pub struct BufferList {
+    bufs: Vec<Buf>,
+    /// The number the next buffer gets.
+    top: usize,
+    cur: usize,
+    alt: usize,
+    seq: u64,
+}
+
+impl BufferList {
+    pub fn new() -> Self {
+        BufferList { bufs: Vec::new(), top: 1, cur: 0, alt: 0, seq: 0 }
+    }
+
+    /// Add a buffer for file `name`, not loaded, like `:badd`.  When there
+    /// already is a buffer for `name` that one is returned, listed again
+    /// when `listed` is set.  Returns the number.
+    pub fn add(&mut self, name: &str, listed: bool) -> usize {
+        if let Some(buf) = self.bufs.iter_mut().find(|b| !name.is_empty() && b.name == name) {
+            buf.listed |= listed;
+            return buf.nr;
+        }
+        let nr = self.top;
+        self.top += 1;
+        self.bufs.push(Buf {
+            nr,
+            name: name.to_string(),
+            listed,
+            loaded: false,
+            windows: 0,
+            changed: false,
+            modifiable: true,
+            readonly: false,
+            buftype: BufType::Normal,
+            bufhidden: BufHidden::Default,
+            lnum: 1,
+            lastused: 0,
+            used_seq: 0,
+        });
+        nr
+    }
+
+    pub fn get(&self, nr: usize) -> Option<&Buf> {
+        self.bufs.iter().find(|b| b.nr == nr)
+    }
+
+    pub fn get_mut(&mut self, nr: usize) -> Option<&mut Buf> {
+        self.bufs.iter_mut().find(|b| b.nr == nr)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Buf> {
+        self.bufs.iter()
+    }
+
+    /// The number of the current buffer, zero when there is none.
+    pub fn current(&self) -> usize {
+        self.cur
+    }
+
+    /// The number of the alternate buffer, for CTRL-^ and "#".
+    pub fn alternate(&self) -> Option<usize> {
+        Some(self.alt).filter(|&nr| self.get(nr).is_some())
+    }
+
+    /// Make `nr` the current buffer without changing the alternate one,
+    /// when going to another window.
+    pub fn set_current(&mut self, nr: usize) {
+        self.cur = nr;
+    }
+
+    /// Edit buffer `nr` in the current window: it is loaded and the buffer
+    /// that was current becomes the alternate buffer.
+    pub fn enter(&mut self, nr: usize) {
+        if nr != self.cur && self.get(self.cur).is_some() {
+            self.alt = self.cur;
+        }
+        self.cur = nr;
+        self.seq += 1;
+        let seq = self.seq;
+        if let Some(buf) = self.get_mut(nr) {
+            buf.loaded = true;
+            buf.lastused = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            buf.used_seq = seq;
+        }
+    }
+
+    /// Buffer `nr` is no longer shown in a window, `others` windows still
+    /// show it.  With 'hidden', 'bufhidden' "hide" or another window it
+    /// stays loaded, otherwise it is unloaded, deleted or wiped out as
+    /// 'bufhidden' says.  A changed buffer that would be unloaded gives E37
+    /// unless `force` is set, its changes are lost then.
+    pub fn abandon(&mut self, nr: usize, others: usize, hidden: bool, force: bool) -> Result<(), String> {
+        let Some(buf) = self.get_mut(nr) else {
+            return Ok(());
+        };
+        buf.windows = others;
+        let keep = match buf.bufhidden {
+            BufHidden::Hide => true,
+            BufHidden::Default => hidden || buf.buftype.always_hidden(),
+            _ => false,
+        };
+        if keep || others > 0 {
+            return Ok(());
+        }
+        if buf.changed && !force {
+            return Err("E37: No write since last change (add ! to override)".to_string());
+        }
+        match buf.bufhidden {
+            BufHidden::Delete => self.delete(nr, true),
+            BufHidden::Wipe => self.wipe(nr, true),
+            _ => self.unload(nr, true),
+        }
+    }
+
+    fn check_changed(&self, nr: usize, force: bool) -> Result<(), String> {
+        match self.get(nr) {
+            Some(buf) if buf.changed && !force => {
+                Err(format!("E89: No write since last change for buffer {} (add ! to override)", nr))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// `:bunload`: free the text, the buffer stays in the list.
+    pub fn unload(&mut self, nr: usize, force: bool) -> Result<(), String> {
+        self.check_changed(nr, force)?;
+        if let Some(buf) = self.get_mut(nr) {
+            buf.loaded = false;
+            buf.changed = false;
+        }
+        Ok(())
+    }
+
+    /// `:bdelete`: unload and remove from the list, the number remains.
+    pub fn delete(&mut self, nr: usize, force: bool) -> Result<(), String> {
+        self.unload(nr, force)?;
+        if let Some(buf) = self.get_mut(nr) {
+            buf.listed = false;
+        }
+        Ok(())
+    }
+
+    /// `:bwipeout`: remove the buffer completely.
+    pub fn wipe(&mut self, nr: usize, force: bool) -> Result<(), String> {
+        self.check_changed(nr, force)?;
+        self.bufs.retain(|b| b.nr != nr);
+        if self.alt == nr {
+            self.alt = 0;
+        }
+        Ok(())
+    }
+
+    /// The buffer to show in the windows of buffer `nr` when it goes away:
+    /// the alternate buffer when it is listed, otherwise the next listed
+    /// buffer or the one before it.
+    pub fn replacement(&self, nr: usize) -> Option<usize> {
+        let listed = |b: &&Buf| b.listed && b.nr != nr;
+        if let Some(alt) = self.alternate().and_then(|a| self.get(a)).filter(listed) {
+            return Some(alt.nr);
+        }
+        let next = self.bufs.iter().filter(listed).find(|b| b.nr > nr);
+        next.or_else(|| self.bufs.iter().rev().filter(listed).find(|b| b.nr < nr)).map(|b| b.nr)
+    }
+
+    /// The buffer `expr` refers to, like bufnr(): a number, "%" for the
+    /// current buffer, "#" for the alternate one, "$" for the last one or
+    /// a name.  A full match of the name is preferred, otherwise it may
+    /// match part of one name; "^" and "$" anchor at the start and end.
+    /// E86 for a number without a buffer, E94 when no name matches.
+    pub fn lookup(&self, expr: &str) -> Result<usize, String> {
+        let found = match expr {
+            "%" | "" => Some(self.cur),
+            "#" => self.alternate(),
+            "$" => self.bufs.last().map(|b| b.nr),
+            _ if expr.bytes().all(|c| c.is_ascii_digit()) => {
+                let nr = expr.parse().unwrap_or(0);
+                return self.get(nr).map(|b| b.nr).ok_or_else(|| format!("E86: Buffer {} does not exist", nr));
+            }
+            _ => {
+                if let Some(buf) = self.bufs.iter().find(|b| b.name == expr) {
+                    return Ok(buf.nr);
+                }
+                let (start, pat) = expr.strip_prefix('^').map_or((false, expr), |p| (true, p));
+                let (end, pat) = pat.strip_suffix('$').map_or((false, pat), |p| (true, p));
+                let matching: Vec<&Buf> = self
+                    .bufs
+                    .iter()
+                    .filter(|b| match (start, end) {
+                        (true, true) => b.name == pat,
+                        (true, false) => b.name.starts_with(pat),
+                        (false, true) => b.name.ends_with(pat),
+                        (false, false) => b.name.contains(pat),
+                    })
+                    .collect();
+                match matching.as_slice() {
+                    [buf] => Some(buf.nr),
+                    [] => None,
+                    _ => return Err(format!("E93: More than one match for {}", expr)),
+                }
+            }
+        };
+        found.filter(|&nr| self.get(nr).is_some()).ok_or_else(|| format!("E94: No matching buffer for {}", expr))
+    }
+
+    /// The lines of `:ls[!] [flags]`.  Each flag only lists buffers that
+    /// have it: "u" unlisted, "%" current, "#" alternate, "a" active, "h"
+    /// hidden, "-" not modifiable, "=" readonly, "+" changed, "R", "F" and
+    /// "?" terminal buffers.  "t" sorts by the time last used.
+    pub fn list(&self, bang: bool, flags: &str) -> Result<Vec<String>, String> {
+        if let Some(c) = flags.chars().find(|c| !"u%#ah-=+xRF?t".contains(*c)) {
+            return Err(format!("E488: Trailing characters: {}", c));
+        }
+        let mut bufs: Vec<&Buf> = self
+            .bufs
+            .iter()
+            .filter(|b| b.listed || bang || flags.contains('u'))
+            .filter(|b| {
+                flags.chars().all(|c| match c {
+                    'u' => !b.listed,
+                    '%' => b.nr == self.cur,
+                    '#' => b.nr == self.alt,
+                    'a' => b.loaded && b.windows > 0,
+                    'h' => b.hidden(),
+                    '-' => !b.modifiable,
+                    '=' => b.readonly,
+                    '+' => b.changed,
+                    'x' => false,
+                    'R' | 'F' | '?' => b.buftype == BufType::Terminal,
+                    _ => true,
+                })
+            })
+            .collect();
+        if flags.contains('t') {
+            bufs.sort_by_key(|b| std::cmp::Reverse(b.used_seq));
+        }
+        Ok(bufs.into_iter().map(|b| self.list_line(b)).collect())
+    }
+
+    /// One line of `:ls`, like `  1 %a + "name"    line 3`.
+    fn list_line(&self, buf: &Buf) -> String {
+        let mut line = format!(
+            "{:3}{}{}{}{}{} \"{}\"",
+            buf.nr,
+            if buf.listed { ' ' } else { 'u' },
+            if buf.nr == self.cur { '%' } else if buf.nr == self.alt { '#' } else { ' ' },
+            if !buf.loaded { ' ' } else if buf.windows == 0 { 'h' } else { 'a' },
+            if buf.buftype == BufType::Terminal {
+                '?'
+            } else if !buf.modifiable {
+                '-'
+            } else if buf.readonly {
+                '='
+            } else {
+                ' '
+            },
+            if buf.changed { '+' } else { ' ' },
+            buf.display_name()
+        );
+        // "line N" goes in column 40 or after the name.
+        let width = line.chars().count();
+        line.push_str(&" ".repeat(40usize.saturating_sub(width).max(1)));
+        line.push_str(&format!("line {}", if buf.loaded || buf.lnum > 0 { buf.lnum } else { 0 }));
+        line
+    }
+
+    /// Set buffer option `arg` of buffer `nr`, as given to `:set`:
+    /// 'buftype', 'bufhidden', 'buflisted', 'modifiable' or 'readonly'.
+    /// None for another option.
+    pub fn set_option(&mut self, nr: usize, arg: &str) -> Option<Result<(), String>> {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg, None),
+        };
+        let (name, on) = match name.strip_prefix("no") {
+            Some(rest) if value.is_none() => (rest, false),
+            _ => (name, true),
+        };
+        let buf = self.get_mut(nr)?;
+        let invalid = || Err(format!("E474: Invalid argument: {}", arg));
+        let result = match (name, value) {
+            ("buftype" | "bt", Some(value)) => match BufType::from_name(value) {
+                Some(bt) => {
+                    buf.buftype = bt;
+                    Ok(())
+                }
+                None => invalid(),
+            },
+            ("bufhidden" | "bh", Some(value)) => match BufHidden::from_name(value) {
+                Some(bh) => {
+                    buf.bufhidden = bh;
+                    Ok(())
+                }
+                None => invalid(),
+            },
+            ("buflisted" | "bl", None) => {
+                buf.listed = on;
+                Ok(())
+            }
+            ("modifiable" | "ma", None) => {
+                buf.modifiable = on;
+                Ok(())
+            }
+            ("readonly" | "ro", None) => {
+                buf.readonly = on;
+                Ok(())
+            }
+            ("buftype" | "bt" | "bufhidden" | "bh", None) => invalid(),
+            ("buflisted" | "bl" | "modifiable" | "ma" | "readonly" | "ro", Some(_)) => invalid(),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn states_and_listing() {
+        let mut list = BufferList::new();
+        let one = list.add("one.txt", true);
+        list.enter(one);
+        list.get_mut(one).unwrap().windows = 1;
+        let two = list.add("dir/two.txt", true);
+        assert_eq!(list.add("one.txt", true), one);
+        list.enter(two);
+        list.get_mut(two).unwrap().windows = 1;
+        list.get_mut(two).unwrap().changed = true;
+        list.abandon(one, 0, true, false).unwrap();
+        let help = list.add("help.txt", false);
+        list.set_option(help, "bt=help").unwrap().unwrap();
+        assert_eq!(
+            list.list(false, "").unwrap(),
+            [
+                "  1 #h   \"one.txt\"                      line 1",
+                "  2 %a + \"dir/two.txt\"                  line 1",
+            ]
+        );
+        assert_eq!(list.list(true, "u").unwrap(), ["  3u     \"help.txt\"                     line 1"]);
+        assert_eq!(list.list(false, "+").unwrap().len(), 1);
+        assert_eq!(list.list(false, "z"), Err("E488: Trailing characters: z".to_string()));
+
+        assert_eq!(list.lookup("#"), Ok(one));
+        assert_eq!(list.lookup("two"), Ok(two));
+        assert_eq!(list.lookup("^one"), Ok(one));
+        assert_eq!(list.lookup(".txt"), Err("E93: More than one match for .txt".to_string()));
+        assert_eq!(list.lookup("7"), Err("E86: Buffer 7 does not exist".to_string()));
+        let bogus = list.set_option(one, "bufhidden=bogus");
+        assert_eq!(bogus, Some(Err("E474: Invalid argument: bufhidden=bogus".to_string())));
+        assert_eq!(list.set_option(one, "tabstop=4"), None);
+    }
+
+    #[test]
+    fn abandon_and_remove() {
+        let mut list = BufferList::new();
+        let one = list.add("one", true);
+        let two = list.add("two", true);
+        list.enter(one);
+        list.enter(two);
+        list.get_mut(one).unwrap().changed = true;
+        let abandon = list.abandon(one, 0, false, false);
+        assert_eq!(abandon, Err("E37: No write since last change (add ! to override)".to_string()));
+        list.abandon(one, 0, false, true).unwrap();
+        assert!(!list.get(one).unwrap().loaded && !list.get(one).unwrap().changed);
+
+        list.set_option(two, "bufhidden=wipe").unwrap().unwrap();
+        list.abandon(two, 1, false, false).unwrap();
+        assert!(list.get(two).is_some());
+        list.abandon(two, 0, false, false).unwrap();
+        assert!(list.get(two).is_none());
+        assert_eq!(list.replacement(one), None);
+
+        let three = list.add("three", true);
+        list.get_mut(three).unwrap().changed = true;
+        assert_eq!(
+            list.delete(three, false),
+            Err("E89: No write since last change for buffer 3 (add ! to override)".to_string())
+        );
+        list.delete(three, true).unwrap();
+        assert!(!list.get(three).unwrap().listed);
+        assert_eq!(list.replacement(three), Some(one));
+    }
+}
@@ -0,0 +1,47 @@
+use rust_buffer::{execute, BufAction, BufferList};
+
+fn exec(line: &str, list: &mut BufferList) -> Result<BufAction, String> {
+    execute(line, list).expect("a buffer list command")
+}
+
+#[test]
+fn add_list_and_remove() {
+    let mut list = BufferList::new();
+    let main = list.add("main.rs", true);
+    list.enter(main);
+    list.get_mut(main).unwrap().windows = 1;
+    assert_eq!(exec("badd +12 lib.rs", &mut list), Ok(BufAction::Added(2)));
+    assert_eq!(exec("bad other\\ file.rs", &mut list), Ok(BufAction::Added(3)));
+    assert_eq!(exec("badd", &mut list), Err("E471: Argument required".to_string()));
+    assert_eq!(list.get(2).unwrap().lnum, 12);
+    assert_eq!(
+        exec("ls", &mut list),
+        Ok(BufAction::Message(vec![
+            "  1 %a   \"main.rs\"                      line 1".to_string(),
+            "  2      \"lib.rs\"                       line 12".to_string(),
+            "  3      \"other file.rs\"                line 1".to_string(),
+        ]))
+    );
+
+    list.enter(2);
+    list.get_mut(2).unwrap().changed = true;
+    assert_eq!(exec("bun 3", &mut list), Err("E515: No buffers were unloaded".to_string()));
+    assert_eq!(
+        exec("bd 2", &mut list),
+        Err("E89: No write since last change for buffer 2 (add ! to override)".to_string())
+    );
+    assert_eq!(
+        exec("bd! lib", &mut list),
+        Ok(BufAction::Removed { nrs: vec![2], wiped: false, msg: None })
+    );
+    assert_eq!(list.replacement(2), Some(1));
+    assert_eq!(exec("files!", &mut list).map(|a| matches!(a, BufAction::Message(l) if l.len() == 3)), Ok(true));
+    assert_eq!(exec("bd 2", &mut list), Err("E516: No buffers were deleted".to_string()));
+    assert_eq!(exec("bd nothing", &mut list), Err("E94: No matching buffer for nothing".to_string()));
+    assert_eq!(
+        exec("%bw", &mut list),
+        Ok(BufAction::Removed { nrs: vec![1, 2, 3], wiped: true, msg: Some("3 buffers wiped out".to_string()) })
+    );
+    assert_eq!(list.iter().count(), 0);
+    assert!(execute("bnext", &mut list).is_none());
+}
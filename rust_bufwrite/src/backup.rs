@@ -0,0 +1,208 @@
+//! Backup files and replacing a file safely: 'backupcopy', 'backupdir' and
+//! 'backupext', and keeping the permissions, owner and extended attributes
+//! of a file that is written through a new file.
+
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 'backupcopy': how the backup is made and the file written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackupCopy {
+    /// Copy the file when writing a new file would lose something: it has
+    /// hard links, or its owner could not be kept.
+    Auto,
+    /// Copy the file to the backup and overwrite the original.
+    Yes,
+    /// Write a new file and rename it over the original, the original
+    /// becomes the backup.
+    No,
+}
+
+impl BackupCopy {
+    /// The value of 'backupcopy', "auto", "yes" or "no" optionally
+    /// followed by ",breaksymlink" or ",breakhardlink", which are accepted
+    /// but have no effect.
+    pub fn parse(value: &str) -> Option<BackupCopy> {
+        let mut words = value.split(',');
+        let copy = match words.next()? {
+            "auto" => BackupCopy::Auto,
+            "yes" => BackupCopy::Yes,
+            "no" => BackupCopy::No,
+            _ => return None,
+        };
+        words.all(|w| matches!(w, "breaksymlink" | "breakhardlink")).then_some(copy)
+    }
+}
+
+/// Expand a leading "~/" to the home directory.
+fn expand_home(dir: &str) -> PathBuf {
+    match (dir.strip_prefix("~/").or((dir == "~").then_some("")), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(dir),
+    }
+}
+
+/// The names the backup of `path` can get, one for each directory in
+/// 'backupdir': "." is the directory of the file, a directory ending in
+/// "//" gets the full path of the file with "%" for each "/".
+pub fn backup_names(path: &Path, backupdir: &str, backupext: &str) -> Vec<PathBuf> {
+    let Some(file_name) = path.file_name() else {
+        return Vec::new();
+    };
+    let mut name = file_name.to_os_string();
+    name.push(backupext);
+    backupdir
+        .split(',')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| {
+            if dir == "." {
+                return path.with_file_name(&name);
+            }
+            if let Some(dir) = dir.strip_suffix("//") {
+                let full = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+                let full = format!("{}{}", full.to_string_lossy().replace('/', "%"), backupext);
+                return expand_home(dir).join(full);
+            }
+            expand_home(dir).join(&name)
+        })
+        .collect()
+}
+
+/// Make a backup of `path` in the first of `names` where it can be made.
+/// With `copy` the file is copied, otherwise the backup is a hard link to
+/// it when possible.  A name that is `path` itself is skipped.  Returns the
+/// backup, or the Vim error when none could be made.
+pub fn make_backup(path: &Path, names: &[PathBuf], copy: bool) -> Result<PathBuf, String> {
+    let real = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    for name in names {
+        if name.parent().is_some_and(|dir| !dir.as_os_str().is_empty() && !dir.is_dir()) {
+            continue;
+        }
+        if fs::canonicalize(name).is_ok_and(|name| name == real) {
+            continue;
+        }
+        // An old backup is replaced.
+        let _ = fs::remove_file(name);
+        if !copy && fs::hard_link(path, name).is_ok() {
+            return Ok(name.clone());
+        }
+        if fs::copy(path, name).is_ok() {
+            return Ok(name.clone());
+        }
+    }
+    Err(if copy {
+        "E509: Cannot create backup file (add ! to override)".to_string()
+    } else {
+        "E510: Can't make backup file (add ! to override)".to_string()
+    })
+}
+
+/// Whether the file with `meta` must be overwritten to keep what it is,
+/// for "auto" in 'backupcopy': it has other hard links, or writing a new
+/// file would give it another owner.
+#[cfg(unix)]
+pub fn must_copy(path: &Path, meta: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let uid = unsafe { libc::geteuid() };
+    let dir_writable = path.parent().is_some_and(|dir| {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let Ok(dir) = std::ffi::CString::new(dir.as_os_str().as_encoded_bytes()) else {
+            return false;
+        };
+        unsafe { libc::access(dir.as_ptr(), libc::W_OK) == 0 }
+    });
+    meta.nlink() > 1 || (uid != 0 && meta.uid() != uid) || !dir_writable
+}
+
+#[cfg(not(unix))]
+pub fn must_copy(_path: &Path, _meta: &Metadata) -> bool {
+    false
+}
+
+/// Give `to` the permissions, owner, group and extended attributes of the
+/// file with `meta` at `from`.  POSIX ACLs are not copied.  Failing to set
+/// the owner is not an error, the file then belongs to the user.
+pub fn copy_metadata(from: &Path, meta: &Metadata, to: &File) -> io::Result<()> {
+    to.set_permissions(meta.permissions())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{fchown, MetadataExt};
+        if fchown(to, Some(meta.uid()), Some(meta.gid())).is_err() {
+            let _ = fchown(to, None, Some(meta.gid()));
+        }
+    }
+    copy_xattrs(from, to);
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn copy_xattrs(from: &Path, to: &File) {
+    use std::ffi::CString;
+    use std::os::fd::AsRawFd;
+    use std::os::raw::{c_char, c_void};
+
+    let Ok(from) = CString::new(from.as_os_str().as_encoded_bytes()) else {
+        return;
+    };
+    let size = unsafe { libc::listxattr(from.as_ptr(), std::ptr::null_mut(), 0) };
+    if size <= 0 {
+        return;
+    }
+    let mut names = vec![0u8; size as usize];
+    let size = unsafe { libc::listxattr(from.as_ptr(), names.as_mut_ptr() as *mut c_char, names.len()) };
+    if size <= 0 {
+        return;
+    }
+    for name in names[..size as usize].split(|&b| b == 0) {
+        if name.is_empty() || name.starts_with(b"system.posix_acl_") {
+            continue;
+        }
+        let Ok(name) = CString::new(name) else { continue };
+        let len = unsafe { libc::getxattr(from.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if len < 0 {
+            continue;
+        }
+        let mut value = vec![0u8; len as usize];
+        let value_ptr = value.as_mut_ptr() as *mut c_void;
+        let len = unsafe { libc::getxattr(from.as_ptr(), name.as_ptr(), value_ptr, value.len()) };
+        if len >= 0 {
+            unsafe { libc::fsetxattr(to.as_raw_fd(), name.as_ptr(), value.as_ptr() as *const c_void, len as usize, 0) };
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_xattrs(_from: &Path, _to: &File) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_backups() {
+        assert_eq!(BackupCopy::parse("yes,breaksymlink"), Some(BackupCopy::Yes));
+        assert_eq!(BackupCopy::parse("maybe"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "old").unwrap();
+        let bdir = format!("{}/none,{}//,.", dir.path().display(), dir.path().display());
+        let names = backup_names(&path, &bdir, ".bak");
+        let full = format!("{}.bak", path.display()).replace('/', "%");
+        assert_eq!(names[1], dir.path().join(full));
+        assert_eq!(names[2], dir.path().join("file.txt.bak"));
+
+        // The first directory does not exist.
+        let backup = make_backup(&path, &names, false).unwrap();
+        assert_eq!(backup, names[1]);
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "old");
+        assert!(make_backup(&path, &names[..1], true).unwrap_err().starts_with("E509"));
+
+        // A backup name that is the file itself is not used.
+        let names = backup_names(&path, ".", "");
+        assert_eq!(names, [path.as_path()]);
+        assert!(make_backup(&path, &names, false).unwrap_err().starts_with("E510"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+    }
+}
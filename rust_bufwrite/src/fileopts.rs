@@ -0,0 +1,242 @@
+//! The buffer options that say how the text of a buffer is written:
+//! 'fileformat', 'fileencoding', 'bomb', 'endofline', 'fixendofline',
+//! 'binary', 'cryptmethod' and 'key'.
+
+use std::path::Path;
+
+use rust_crypt::CryptMethod;
+use rust_fileio::{canonical_name, write_file, FileArgs, FileFormat, ReadResult, WriteOptions, WriteResult};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileOptions {
+    pub fileformat: FileFormat,
+    pub fileencoding: String,
+    pub bomb: bool,
+    /// 'endofline': the last line of the file had a line break.
+    pub endofline: bool,
+    /// 'fixendofline': write a line break after the last line anyway.
+    pub fixendofline: bool,
+    pub binary: bool,
+    pub cryptmethod: CryptMethod,
+    /// 'key': the passphrase the file is encrypted with, empty when it is
+    /// not.
+    pub key: String,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        FileOptions {
+            fileformat: FileFormat::Unix,
+            fileencoding: String::new(),
+            bomb: false,
+            endofline: true,
+            fixendofline: true,
+            binary: false,
+            cryptmethod: CryptMethod::default(),
+            key: String::new(),
+        }
+    }
+}
+
+impl FileOptions {
+    /// The options of a buffer just read into from `result`, keeping
+    /// 'fixendofline', 'binary' and 'key' of `self`.  'cryptmethod' is the
+    /// one the file was encrypted with, also "zip", which is upgraded when
+    /// the file is written.
+    pub fn read(&self, result: &ReadResult) -> FileOptions {
+        FileOptions {
+            fileformat: result.fileformat,
+            fileencoding: result.fileencoding.clone(),
+            bomb: result.bomb,
+            endofline: result.eol,
+            cryptmethod: result.cryptmethod.unwrap_or(self.cryptmethod),
+            ..self.clone()
+        }
+    }
+
+    /// How to write the text, with the `++` arguments of `args`.  With
+    /// 'binary' set or 'fixendofline' reset the last line only gets a line
+    /// break when 'endofline' is set.  'binary' also writes the bytes
+    /// unconverted with NL line breaks.  "zip" can only be read, the file
+    /// is encrypted with "xchacha20scrypt" instead.
+    pub fn write_options(&self, args: &FileArgs) -> WriteOptions {
+        let binary = args.bin.unwrap_or(self.binary);
+        let fixeol = self.fixendofline && !binary;
+        WriteOptions {
+            fileencoding: match &args.enc {
+                _ if binary => String::new(),
+                Some(enc) => enc.clone(),
+                None => self.fileencoding.clone(),
+            },
+            bomb: self.bomb && !binary,
+            eol: fixeol || self.endofline,
+            fileformat: if binary { FileFormat::Unix } else { args.ff.unwrap_or(self.fileformat) },
+            key: self.key.clone(),
+            cryptmethod: match self.cryptmethod {
+                CryptMethod::Zip => CryptMethod::XChaCha20Scrypt,
+                method => method,
+            },
+        }
+    }
+
+    /// Handle the `:set` argument `arg` when it is one of these options.
+    /// Returns the message, the value for `:set {option}?`, or the error.
+    /// None for other options.
+    pub fn set(&mut self, arg: &str) -> Option<Result<String, String>> {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.strip_suffix('?').unwrap_or(arg), None),
+        };
+        let query = arg.ends_with('?');
+        let (name, on) = match name.strip_prefix("no") {
+            Some(rest) if value.is_none() && !query && is_bool(rest) => (rest, false),
+            _ => (name, true),
+        };
+        let invalid = || Err(format!("E474: Invalid argument: {}", arg));
+        let result = match (name, value) {
+            ("fileformat" | "ff", None) => Ok(format!("  fileformat={}", self.fileformat.name())),
+            ("fileformat" | "ff", Some(value)) => match FileFormat::from_name(value) {
+                Some(ff) => {
+                    self.fileformat = ff;
+                    Ok(String::new())
+                }
+                None => invalid(),
+            },
+            ("fileencoding" | "fenc", None) => Ok(format!("  fileencoding={}", self.fileencoding)),
+            ("fileencoding" | "fenc", Some(value)) => {
+                self.fileencoding = canonical_name(value);
+                Ok(String::new())
+            }
+            ("cryptmethod" | "cm", None) => Ok(format!("  cryptmethod={}", self.cryptmethod.name())),
+            ("cryptmethod" | "cm", Some(value)) => match CryptMethod::from_name(value) {
+                Some(method) => {
+                    self.cryptmethod = method;
+                    Ok(String::new())
+                }
+                None => invalid(),
+            },
+            // The key itself is never shown.
+            ("key", None) => Ok(format!("  key={}", if self.key.is_empty() { "" } else { "*****" })),
+            ("key", Some(value)) => {
+                self.key = value.to_string();
+                Ok(String::new())
+            }
+            (name, None) if is_bool(name) => {
+                let option = match name {
+                    "endofline" | "eol" => &mut self.endofline,
+                    "fixendofline" | "fixeol" => &mut self.fixendofline,
+                    "binary" | "bin" => &mut self.binary,
+                    _ => &mut self.bomb,
+                };
+                let full = bool_name(name);
+                if query {
+                    Ok(format!("  {}{}", if *option { "" } else { "no" }, full))
+                } else {
+                    *option = on;
+                    Ok(String::new())
+                }
+            }
+            (name, Some(_)) if is_bool(name) => invalid(),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+fn is_bool(name: &str) -> bool {
+    matches!(name, "endofline" | "eol" | "fixendofline" | "fixeol" | "binary" | "bin" | "bomb")
+}
+
+fn bool_name(name: &str) -> &'static str {
+    match name {
+        "endofline" | "eol" => "endofline",
+        "fixendofline" | "fixeol" => "fixendofline",
+        "binary" | "bin" => "binary",
+        _ => "bomb",
+    }
+}
+
+/// Write `lines` to `path` as the options `opts` and the `++` arguments
+/// `args` say.
+pub fn buf_write<L: AsRef<[u8]>>(
+    path: &Path,
+    lines: &[L],
+    opts: &FileOptions,
+    args: &FileArgs,
+) -> Result<WriteResult, String> {
+    write_file(path, lines, &opts.write_options(args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_fileio::{read_file, ReadOptions};
+
+    #[test]
+    fn eol_and_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text");
+        std::fs::write(&path, b"a\r\nb").unwrap();
+        let result = read_file(&path, &ReadOptions::default()).unwrap();
+        let mut opts = FileOptions::default().read(&result);
+        assert_eq!((opts.fileformat, opts.endofline), (FileFormat::Dos, false));
+
+        // 'fixendofline' adds the missing line break, like Vim.
+        let written = buf_write(&path, &result.lines, &opts, &FileArgs::default()).unwrap();
+        assert_eq!(written.message("x"), "\"x\" [dos] 2L, 6B written");
+        assert_eq!(opts.set("nofixeol"), Some(Ok(String::new())));
+        buf_write(&path, &result.lines, &opts, &FileArgs::default()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"a\r\nb");
+
+        assert_eq!(opts.set("ff=unix"), Some(Ok(String::new())));
+        assert_eq!(opts.set("bin"), Some(Ok(String::new())));
+        assert_eq!(opts.set("binary?"), Some(Ok("  binary".into())));
+        assert_eq!(opts.set("ff?"), Some(Ok("  fileformat=unix".into())));
+        assert_eq!(opts.set("ff=foo"), Some(Err("E474: Invalid argument: ff=foo".into())));
+        assert_eq!(opts.set("ts=8"), None);
+        opts.fileencoding = "latin1".into();
+        let written = buf_write(&path, &["é", "b"], &opts, &FileArgs::default()).unwrap();
+        assert_eq!(written.message("x"), "\"x\" [noeol] 2L, 4B written");
+        assert_eq!(std::fs::read(&path).unwrap(), "é\nb".as_bytes());
+
+        let (args, _) = FileArgs::parse("++nobin ++ff=mac").unwrap();
+        buf_write(&path, &["é", "b"], &opts, &args).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"\xe9\rb");
+    }
+
+    #[test]
+    fn encryption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text");
+        let mut opts = FileOptions::default();
+        assert_eq!(opts.set("key?"), Some(Ok("  key=".into())));
+        assert_eq!(opts.set("key=secret"), Some(Ok(String::new())));
+        assert_eq!(opts.set("key?"), Some(Ok("  key=*****".into())));
+        assert_eq!(opts.set("cm?"), Some(Ok("  cryptmethod=xchacha20scrypt".into())));
+        assert_eq!(opts.set("cm=blowfish"), Some(Err("E474: Invalid argument: cm=blowfish".into())));
+        let written = buf_write(&path, &["a"], &opts, &FileArgs::default()).unwrap();
+        assert!(written.message("x").starts_with("\"x\" [xchacha20scrypt][New] 1L"));
+
+        let result = read_file(&path, &ReadOptions { key: Some("secret".into()), ..ReadOptions::default() }).unwrap();
+        let read = FileOptions { cryptmethod: CryptMethod::Zip, ..opts.clone() }.read(&result);
+        assert_eq!((result.lines, read.cryptmethod), (vec![b"a".to_vec()], CryptMethod::XChaCha20Scrypt));
+    }
+
+    #[test]
+    fn upgrade_zip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text");
+        let mut data = b"VimCrypt~01!".to_vec();
+        data.extend(rust_crypto_zip::zip_encrypt(b"old\n", b"secret").unwrap());
+        std::fs::write(&path, data).unwrap();
+        let read_opts = ReadOptions { key: Some("secret".into()), ..ReadOptions::default() };
+        let result = read_file(&path, &read_opts).unwrap();
+        let opts = FileOptions { key: "secret".into(), ..FileOptions::default() }.read(&result);
+        assert_eq!(opts.cryptmethod, CryptMethod::Zip);
+
+        let written = buf_write(&path, &result.lines, &opts, &FileArgs::default()).unwrap();
+        assert!(written.message("x").starts_with("\"x\" [xchacha20scrypt] 1L"), "{}", written.message("x"));
+        let result = read_file(&path, &read_opts).unwrap();
+        assert_eq!((result.lines, result.cryptmethod), (vec![b"old".to_vec()], Some(CryptMethod::XChaCha20Scrypt)));
+    }
+}
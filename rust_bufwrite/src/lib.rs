@@ -1,6 +1,14 @@
 use std::ffi::CStr;
 use std::os::raw::{c_char, c_int};
 
+mod backup;
+mod fileopts;
+mod write;
+
+pub use backup::{backup_names, BackupCopy};
+pub use fileopts::{buf_write, FileOptions};
+pub use write::{do_write, WriteBuffer, WriteCmd, WriteSettings, WriteTarget, Written};
+
 /// Dummy bufwrite function exposed to C.
 /// Returns 0 on success, -1 on error.
 #[no_mangle]
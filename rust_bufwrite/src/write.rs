@@ -0,0 +1,434 @@
+//! The `:write` command: writing the whole buffer or a range of lines to a
+//! file, appending to a file with `:w >>` or giving the lines to a shell
+//! command with `:w !cmd`, with backups and autocommands.
+//!
+//! A file is not truncated and written in place unless 'backupcopy' asks
+//! for it: the text goes to a new file next to it, which then replaces the
+//! original with a rename.  A crash or a full disk leaves the original
+//! intact.  Symbolic links are followed, the file they point to is
+//! replaced.
+
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use rust_autocmd::Event;
+use rust_fileio::{check_write, write_lines, FileArgs, WriteOptions, WriteResult};
+use rust_job::{run_job_input, JobConfig};
+
+use crate::backup::{backup_names, copy_metadata, make_backup, must_copy, BackupCopy};
+use crate::FileOptions;
+
+/// The global options used when writing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteSettings {
+    /// 'backup': keep the backup after writing.
+    pub backup: bool,
+    /// 'writebackup': make a backup while writing.
+    pub writebackup: bool,
+    pub backupcopy: BackupCopy,
+    pub backupdir: String,
+    pub backupext: String,
+    /// 'shell' and 'shellcmdflag', for `:w !cmd`.
+    pub shell: String,
+    pub shellcmdflag: String,
+}
+
+impl Default for WriteSettings {
+    fn default() -> Self {
+        WriteSettings {
+            backup: false,
+            writebackup: true,
+            backupcopy: BackupCopy::Auto,
+            backupdir: ".,~/tmp,~/".to_string(),
+            backupext: "~".to_string(),
+            shell: "sh".to_string(),
+            shellcmdflag: "-c".to_string(),
+        }
+    }
+}
+
+/// Where `:write` writes to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WriteTarget {
+    /// The file of the buffer.
+    Buffer,
+    File(String),
+    /// `:w !cmd`
+    Command(String),
+}
+
+/// The argument of `:write`: `[++opt] [>>] [file]` or `[++opt] !cmd`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteCmd {
+    pub args: FileArgs,
+    /// `:w >>`
+    pub append: bool,
+    pub target: WriteTarget,
+}
+
+impl WriteCmd {
+    pub fn parse(arg: &str) -> Result<WriteCmd, String> {
+        let (args, rest) = FileArgs::parse(arg)?;
+        if let Some(cmd) = rest.strip_prefix('!') {
+            return Ok(WriteCmd { args, append: false, target: WriteTarget::Command(cmd.trim().to_string()) });
+        }
+        let (append, rest) = match rest.strip_prefix(">>") {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, rest),
+        };
+        let target = if rest.is_empty() { WriteTarget::Buffer } else { WriteTarget::File(rest.trim_end().to_string()) };
+        Ok(WriteCmd { args, append, target })
+    }
+}
+
+/// The buffer `:write` writes, and the autocommands it triggers.
+pub trait WriteBuffer {
+    /// The file name of the buffer, empty when it has none.
+    fn name(&self) -> &str;
+
+    fn line_count(&self) -> usize;
+
+    /// Line `lnum`, one based.
+    fn line(&self, lnum: usize) -> &str;
+
+    /// Execute the autocommands for `event` and file `fname`.  Returns
+    /// whether there were any.  Errors are reported by the implementation,
+    /// they do not stop the write.
+    fn apply_autocmds(&mut self, _event: Event, _fname: &str) -> bool {
+        false
+    }
+}
+
+/// What `:write` did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Written {
+    /// The messages to show: the file message or the output of the command.
+    pub messages: Vec<String>,
+    /// The whole buffer was written to its file, or to a file that becomes
+    /// its file when it has none: 'modified' is to be reset.
+    pub reset_modified: bool,
+}
+
+/// The Pre, Cmd and Post events for writing, like Vim: FileAppend* for
+/// `:w >>`, BufWrite* for the whole buffer, FileWrite* for some lines.
+fn write_events(append: bool, whole: bool) -> (Event, Event, Event) {
+    if append {
+        (Event::FileAppendPre, Event::FileAppendCmd, Event::FileAppendPost)
+    } else if whole {
+        (Event::BufWritePre, Event::BufWriteCmd, Event::BufWritePost)
+    } else {
+        (Event::FileWritePre, Event::FileWriteCmd, Event::FileWritePost)
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || matches!((fs::canonicalize(a), fs::canonicalize(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Execute `:[range]write[!] {cmd}` for `buf`.  `range` is the first and
+/// last line, None for the whole buffer.
+pub fn do_write(
+    buf: &mut impl WriteBuffer,
+    range: Option<(usize, usize)>,
+    cmd: &WriteCmd,
+    forceit: bool,
+    opts: &FileOptions,
+    settings: &WriteSettings,
+) -> Result<Written, String> {
+    let (start, end) = range.unwrap_or((1, buf.line_count()));
+    if range.is_some() && (start < 1 || start > end || end > buf.line_count()) {
+        return Err("E16: Invalid range".to_string());
+    }
+    let whole = start == 1 && end == buf.line_count();
+
+    let fname = match &cmd.target {
+        WriteTarget::Command(shell_cmd) => return filter_write(buf, start, end, shell_cmd, settings),
+        WriteTarget::Buffer => buf.name().to_string(),
+        WriteTarget::File(name) => name.clone(),
+    };
+    if fname.is_empty() {
+        return Err("E32: No file name".to_string());
+    }
+    let path = PathBuf::from(&fname);
+    let other = buf.name().is_empty() || !same_file(&path, Path::new(buf.name()));
+    if path.is_dir() {
+        return Err(format!("E502: \"{}\" is a directory", fname));
+    }
+    if !cmd.append && !forceit {
+        if other && path.exists() {
+            return Err("E13: File exists (add ! to override)".to_string());
+        }
+        if !other && !whole {
+            return Err("E140: Use ! to write partial buffer".to_string());
+        }
+    }
+    let reset_modified = whole && !cmd.append && (!other || buf.name().is_empty());
+
+    let (pre, cmd_event, post) = write_events(cmd.append, whole);
+    // A *Cmd autocommand does the writing instead, and must reset
+    // 'modified' itself.
+    if buf.apply_autocmds(cmd_event, &fname) {
+        return Ok(Written::default());
+    }
+    buf.apply_autocmds(pre, &fname);
+    // The Pre autocommands may have changed the text.
+    let end = if whole { buf.line_count() } else { end.min(buf.line_count()) };
+    let lines: Vec<String> = (start..=end).map(|lnum| buf.line(lnum).to_string()).collect();
+
+    let write_opts = opts.write_options(&cmd.args);
+    check_write(&lines, &write_opts)?;
+    let result = if cmd.append {
+        append_file(&path, &lines, &write_opts)?
+    } else {
+        write_file_safely(&path, &lines, &write_opts, forceit, settings)?
+    };
+    buf.apply_autocmds(post, &fname);
+    Ok(Written { messages: vec![result.message(&fname)], reset_modified })
+}
+
+/// `:w >> file`: add the lines to the end of the file, without a byte order
+/// mark.  An encrypted file cannot be added to.
+fn append_file(path: &Path, lines: &[String], opts: &WriteOptions) -> Result<WriteResult, String> {
+    if !opts.key.is_empty() {
+        return Err("E474: Cannot append to an encrypted file".to_string());
+    }
+    let new = !path.exists();
+    let open_error = |_| format!("E212: Can't open file for writing: {}", path.display());
+    let mut file = OpenOptions::new().append(true).create(true).open(path).map_err(open_error)?;
+    let opts = WriteOptions { bomb: false, ..opts.clone() };
+    let bytes = write_lines(&mut file, lines, &opts)?;
+    Ok(WriteResult { new, appended: true, ..WriteResult::new(lines.len(), bytes, &opts) })
+}
+
+/// Write `lines` to `path` with a backup as 'backup', 'writebackup' and
+/// 'backupcopy' say.  When there is no need to overwrite the file in place
+/// a new file is written and renamed over it.
+fn write_file_safely(
+    path: &Path,
+    lines: &[String],
+    opts: &WriteOptions,
+    forceit: bool,
+    settings: &WriteSettings,
+) -> Result<WriteResult, String> {
+    let open_error = || format!("E212: Can't open file for writing: {}", path.display());
+    let write_error = |_| "E514: Write error (file system full?)".to_string();
+    let real = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let meta = fs::metadata(&real).ok();
+    let copy = match (settings.backupcopy, &meta) {
+        (_, None) => false,
+        (BackupCopy::Yes, _) => true,
+        (BackupCopy::No, _) => false,
+        (BackupCopy::Auto, Some(meta)) => must_copy(&real, meta),
+    };
+
+    let mut backup = None;
+    if meta.is_some() && (settings.backup || settings.writebackup) {
+        let names = backup_names(&real, &settings.backupdir, &settings.backupext);
+        match make_backup(&real, &names, copy) {
+            Ok(name) => backup = Some(name),
+            Err(msg) if !forceit => return Err(msg),
+            Err(_) => {}
+        }
+    }
+
+    let bytes = if copy {
+        let mut file = OpenOptions::new().write(true).truncate(true).open(&real).map_err(|_| open_error())?;
+        let bytes = write_lines(&mut file, lines, opts)?;
+        file.sync_all().map_err(write_error)?;
+        bytes
+    } else {
+        let dir = real.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let name = real.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let tmp = dir.join(format!(".{}.{}.tmp", name, std::process::id()));
+        let written = (|| -> Result<u64, String> {
+            let mut file = File::create(&tmp).map_err(|_| open_error())?;
+            if let Some(meta) = &meta {
+                let _ = copy_metadata(&real, meta, &file);
+            }
+            let bytes = write_lines(&mut file, lines, opts)?;
+            file.sync_all().map_err(write_error)?;
+            fs::rename(&tmp, &real).map_err(|_| open_error())?;
+            Ok(bytes)
+        })();
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written?
+    };
+
+    // With only 'writebackup' the backup is deleted once the file is
+    // written.
+    if let (Some(backup), false) = (backup, settings.backup) {
+        let _ = fs::remove_file(backup);
+    }
+    Ok(WriteResult { new: meta.is_none(), ..WriteResult::new(lines.len(), bytes, opts) })
+}
+
+/// `:w !cmd`: execute `cmd` with 'shell' and the lines as its input.  The
+/// output is returned as the messages.
+fn filter_write(
+    buf: &mut impl WriteBuffer,
+    start: usize,
+    end: usize,
+    shell_cmd: &str,
+    settings: &WriteSettings,
+) -> Result<Written, String> {
+    let fname = buf.name().to_string();
+    buf.apply_autocmds(Event::FilterWritePre, &fname);
+    let mut input = Vec::new();
+    for lnum in start..=end.min(buf.line_count()) {
+        input.extend_from_slice(buf.line(lnum).as_bytes());
+        input.push(b'\n');
+    }
+    let mut args: Vec<String> = settings.shellcmdflag.split_whitespace().map(str::to_string).collect();
+    args.push(shell_cmd.to_string());
+    let config = JobConfig { cmd: settings.shell.clone(), args };
+    let (code, output) = run_job_input(config, &input).map_err(|_| format!("E282: Cannot execute {}", settings.shell))?;
+    buf.apply_autocmds(Event::FilterWritePost, &fname);
+    let mut messages: Vec<String> = String::from_utf8_lossy(&output).lines().map(str::to_string).collect();
+    if code != 0 {
+        messages.push(format!("shell returned {}", code));
+    }
+    Ok(Written { messages, reset_modified: false })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestBuffer {
+        name: String,
+        lines: Vec<String>,
+        events: Vec<String>,
+        /// Events that have an autocommand.
+        defined: Vec<Event>,
+        /// BufWritePre adds a line.
+        add_line: bool,
+    }
+
+    impl WriteBuffer for TestBuffer {
+        fn name(&self) -> &str {
+            &self.name
+        }
+        fn line_count(&self) -> usize {
+            self.lines.len()
+        }
+        fn line(&self, lnum: usize) -> &str {
+            &self.lines[lnum - 1]
+        }
+        fn apply_autocmds(&mut self, event: Event, _fname: &str) -> bool {
+            self.events.push(event.name().to_string());
+            if event == Event::BufWritePre && self.add_line {
+                self.lines.push("added".to_string());
+            }
+            self.defined.contains(&event)
+        }
+    }
+
+    fn buffer(name: &Path) -> TestBuffer {
+        let lines = vec!["one".to_string(), "two".to_string(), "three".to_string()];
+        TestBuffer { name: name.display().to_string(), lines, events: Vec::new(), defined: Vec::new(), add_line: true }
+    }
+
+    fn write(
+        buf: &mut TestBuffer,
+        range: Option<(usize, usize)>,
+        arg: &str,
+        bang: bool,
+        s: &WriteSettings,
+    ) -> Result<Written, String> {
+        do_write(buf, range, &WriteCmd::parse(arg).unwrap(), bang, &FileOptions::default(), s)
+    }
+
+    #[test]
+    fn parse_args() {
+        let cmd = WriteCmd::parse("++ff=dos >> out.txt").unwrap();
+        assert_eq!((cmd.append, cmd.target), (true, WriteTarget::File("out.txt".into())));
+        assert_eq!(WriteCmd::parse("!sort -r").unwrap().target, WriteTarget::Command("sort -r".into()));
+        assert_eq!(WriteCmd::parse("").unwrap().target, WriteTarget::Buffer);
+    }
+
+    #[test]
+    fn write_and_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        let mut buf = buffer(&path);
+        let s = WriteSettings::default();
+        let done = write(&mut buf, None, "", false, &s).unwrap();
+        assert!(done.reset_modified);
+        assert_eq!(buf.events, ["BufWriteCmd", "BufWritePre", "BufWritePost"]);
+        assert_eq!(done.messages, [format!("\"{}\" [New] 4L, 20B written", path.display())]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\nthree\nadded\n");
+
+        buf.events.clear();
+        assert_eq!(write(&mut buf, Some((2, 3)), "", false, &s).unwrap_err(), "E140: Use ! to write partial buffer");
+        let part = dir.path().join("part");
+        let done = write(&mut buf, Some((2, 3)), &part.display().to_string(), false, &s).unwrap();
+        assert!(!done.reset_modified);
+        assert_eq!(fs::read_to_string(&part).unwrap(), "two\nthree\n");
+        let arg = format!(">> {}", part.display());
+        let done = write(&mut buf, Some((1, 1)), &arg, false, &s).unwrap();
+        assert_eq!(done.messages, [format!("\"{}\" 1L, 4B appended", part.display())]);
+        assert_eq!(fs::read_to_string(&part).unwrap(), "two\nthree\none\n");
+        let events = ["FileWriteCmd", "FileWritePre", "FileWritePost"];
+        assert_eq!(buf.events, [&events[..], &["FileAppendCmd", "FileAppendPre", "FileAppendPost"]].concat());
+        let other = part.display().to_string();
+        assert_eq!(write(&mut buf, None, &other, false, &s).unwrap_err(), "E13: File exists (add ! to override)");
+
+        // A FileWriteCmd autocommand writes instead.
+        buf.defined.push(Event::FileWriteCmd);
+        buf.events.clear();
+        assert_eq!(write(&mut buf, Some((1, 1)), &other, true, &s).unwrap(), Written::default());
+        assert_eq!(buf.events, ["FileWriteCmd"]);
+        assert_eq!(fs::read_to_string(&part).unwrap(), "two\nthree\none\n");
+
+        let done = write(&mut buf, Some((1, 2)), "!tr a-z A-Z", false, &s).unwrap();
+        assert_eq!(done.messages, ["ONE", "TWO"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn backups_and_links() {
+        use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "old\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let link = dir.path().join("link");
+        symlink(&path, &link).unwrap();
+        let mut buf = TestBuffer { add_line: false, ..buffer(&link) };
+        let mut s = WriteSettings { backup: true, backupdir: ".".into(), ..WriteSettings::default() };
+
+        // A new file replaces the old one, through the symlink.
+        let ino = fs::metadata(&path).unwrap().ino();
+        write(&mut buf, None, "", false, &s).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!((meta.ino() != ino, meta.mode() & 0o777), (true, 0o640));
+        assert_eq!(fs::read_to_string(dir.path().join("file~")).unwrap(), "old\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\nthree\n");
+
+        // With 'backupcopy' "yes" the file is overwritten.
+        s.backupcopy = BackupCopy::Yes;
+        let ino = meta.ino();
+        write(&mut buf, Some((1, 1)), "", true, &s).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().ino(), ino);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\n");
+        assert_eq!(fs::read_to_string(dir.path().join("file~")).unwrap(), "one\ntwo\nthree\n");
+
+        // Only 'writebackup': no backup is left.  A file with hard links is
+        // overwritten.
+        s = WriteSettings { backupdir: ".".into(), ..WriteSettings::default() };
+        fs::remove_file(dir.path().join("file~")).unwrap();
+        fs::hard_link(&path, dir.path().join("hard")).unwrap();
+        write(&mut buf, None, "", false, &s).unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("hard")).unwrap(), fs::read_to_string(&path).unwrap());
+        assert!(!dir.path().join("file~").exists());
+
+        s.backupdir = dir.path().join("none").display().to_string();
+        let err = write(&mut buf, None, "", false, &s).unwrap_err();
+        assert_eq!(err, "E509: Cannot create backup file (add ! to override)");
+        write(&mut buf, None, "", true, &s).unwrap();
+    }
+}
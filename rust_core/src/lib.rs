@@ -1,119 +1,15 @@
 #![allow(unsafe_op_in_unsafe_fn)]
 
 use std::collections::HashMap;
-use std::ffi::{CStr, CString};
+use std::ffi::CStr;
 use std::os::raw::{c_char, c_void};
 use std::sync::{Mutex, OnceLock};
 
-#[repr(C)]
-#[derive(Clone, Copy)]
-pub enum Vartype {
-    VAR_UNKNOWN = 0,
-    VAR_ANY,
-    VAR_VOID,
-    VAR_BOOL,
-    VAR_SPECIAL,
-    VAR_NUMBER,
-    VAR_FLOAT,
-    VAR_STRING,
-}
-
-#[repr(C)]
-pub union ValUnion {
-    pub v_number: i64,
-    pub v_float: f64,
-    pub v_string: *mut c_char,
-}
-
-#[repr(C)]
-pub struct typval_T {
-    pub v_type: Vartype,
-    pub v_lock: c_char,
-    pub vval: ValUnion,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum Value {
-    Number(i64),
-    Float(f64),
-    Str(String),
-}
-
-impl Value {
-    pub fn as_number(&self) -> Result<i64, ()> {
-        match self {
-            Value::Number(n) => Ok(*n),
-            Value::Float(f) => Ok(*f as i64),
-            Value::Str(s) => s.parse().map_err(|_| ()),
-        }
-    }
-
-    pub fn as_float(&self) -> Result<f64, ()> {
-        match self {
-            Value::Number(n) => Ok(*n as f64),
-            Value::Float(f) => Ok(*f),
-            Value::Str(s) => s.parse().map_err(|_| ()),
-        }
-    }
-
-    pub fn to_string(&self) -> String {
-        match self {
-            Value::Number(n) => n.to_string(),
-            Value::Float(f) => f.to_string(),
-            Value::Str(s) => s.clone(),
-        }
-    }
-}
-
-impl std::fmt::Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Value::Number(n) => write!(f, "{}", n),
-            Value::Float(fl) => write!(f, "{}", fl),
-            Value::Str(s) => write!(f, "{}", s),
-        }
-    }
-}
-
-pub unsafe fn to_typval(val: Value, out: *mut typval_T) {
-    match val {
-        Value::Number(n) => {
-            (*out).v_type = Vartype::VAR_NUMBER;
-            (*out).v_lock = 0;
-            (*out).vval.v_number = n;
-        }
-        Value::Float(fl) => {
-            (*out).v_type = Vartype::VAR_FLOAT;
-            (*out).v_lock = 0;
-            (*out).vval.v_float = fl;
-        }
-        Value::Str(s) => {
-            (*out).v_type = Vartype::VAR_STRING;
-            (*out).v_lock = 0;
-            let cstr = CString::new(s).unwrap();
-            (*out).vval.v_string = cstr.into_raw();
-        }
-    }
-}
-
-pub unsafe fn from_typval(tv: *const typval_T) -> Option<Value> {
-    if tv.is_null() {
-        return None;
-    }
-    match (*tv).v_type {
-        Vartype::VAR_NUMBER => Some(Value::Number((*tv).vval.v_number)),
-        Vartype::VAR_FLOAT => Some(Value::Float((*tv).vval.v_float)),
-        Vartype::VAR_STRING => {
-            if (*tv).vval.v_string.is_null() {
-                Some(Value::Str(String::new()))
-            } else {
-                let cstr = CStr::from_ptr((*tv).vval.v_string);
-                cstr.to_str().ok().map(|s| Value::Str(s.to_string()))
-            }
-        }
-        _ => None,
-    }
-}
+pub use rust_typval::{
+    format_float, from_typval, to_typval, tv_free, Blob, BlobRef, Channel, Class, ClassRef, Dict, DictRef, Job,
+    JobStatus, List, ListRef, Object, ObjectRef, Partial, Special, Tuple, TupleRef, Value, VarLock,
+};
+pub use rust_typval::{gc, typval_T, typval_vval as ValUnion, vartype_T as Vartype};
 
 #[no_mangle]
 pub unsafe extern "C" fn tv_number(n: i64, out: *mut typval_T) {
@@ -131,19 +27,6 @@ pub unsafe extern "C" fn tv_string(s: *const c_char, out: *mut typval_T) {
     to_typval(Value::Str(val), out);
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn tv_free(tv: *mut typval_T) {
-    if tv.is_null() {
-        return;
-    }
-    if let Vartype::VAR_STRING = (*tv).v_type {
-        if !(*tv).vval.v_string.is_null() {
-            let _ = CString::from_raw((*tv).vval.v_string);
-        }
-    }
-    (*tv).v_type = Vartype::VAR_UNKNOWN;
-}
-
 static ALLOCATIONS: OnceLock<Mutex<HashMap<usize, Vec<u8>>>> = OnceLock::new();
 
 fn allocations() -> &'static Mutex<HashMap<usize, Vec<u8>>> {
@@ -195,6 +78,7 @@ pub extern "C" fn mem_realloc_rs(ptr: *mut c_void, size: usize) -> *mut c_void {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::rc::Rc;
 
     #[test]
     fn roundtrip_number() {
@@ -215,6 +99,45 @@ mod tests {
         }
     }
 
+    #[test]
+    fn list_typval_shares_the_list() {
+        let list = Value::new_list(vec![Value::Number(1), Value::Str("a'b".into())]);
+        assert_eq!(list.to_string(), "[1, 'a''b']");
+        let mut tv = typval_T { v_type: Vartype::VAR_UNKNOWN, v_lock: 0, vval: ValUnion { v_number: 0 } };
+        unsafe {
+            to_typval(list.clone(), &mut tv);
+            let back = from_typval(&tv as *const typval_T).unwrap();
+            tv_free(&mut tv);
+            match (&back, &list) {
+                (Value::List(a), Value::List(b)) => {
+                    assert!(Rc::ptr_eq(a, b));
+                    assert_eq!(Rc::strong_count(a), 2);
+                }
+                _ => panic!("expected a list"),
+            }
+        }
+    }
+
+    #[test]
+    fn funcref_and_partial_typval() {
+        let mut tv = typval_T { v_type: Vartype::VAR_UNKNOWN, v_lock: 0, vval: ValUnion { v_number: 0 } };
+        let funcref = Value::Func(Rc::new(Partial::new("len")));
+        let mut pt = Partial::new("add");
+        pt.args.push(Value::Number(1));
+        let partial = Value::Func(Rc::new(pt));
+        assert_eq!(partial.to_string(), "function('add', [1])");
+        unsafe {
+            to_typval(funcref.clone(), &mut tv);
+            assert!(matches!(tv.v_type, Vartype::VAR_FUNC));
+            assert_eq!(from_typval(&tv as *const typval_T), Some(funcref));
+            tv_free(&mut tv);
+            to_typval(partial.clone(), &mut tv);
+            assert!(matches!(tv.v_type, Vartype::VAR_PARTIAL));
+            assert_eq!(from_typval(&tv as *const typval_T), Some(partial));
+            tv_free(&mut tv);
+        }
+    }
+
     #[test]
     fn alloc_and_free() {
         let p = vim_alloc_rs(10);
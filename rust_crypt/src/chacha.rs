@@ -0,0 +1,149 @@
+//! ChaCha20, HChaCha20 and XChaCha20-Poly1305.
+//!
+//! ring provides ChaCha20-Poly1305 with a 96 bit nonce.  The 192 bit nonce
+//! of XChaCha20 is large enough to be picked at random for every write: the
+//! first 128 bits and the key give a subkey with HChaCha20, the rest is the
+//! nonce for ChaCha20 with that subkey.
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+
+/// The size of the Poly1305 tag after the ciphertext.
+pub const TAG_SIZE: usize = 16;
+
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+fn rounds(x: &mut [u32; 16]) {
+    for _ in 0..10 {
+        quarter_round(x, 0, 4, 8, 12);
+        quarter_round(x, 1, 5, 9, 13);
+        quarter_round(x, 2, 6, 10, 14);
+        quarter_round(x, 3, 7, 11, 15);
+        quarter_round(x, 0, 5, 10, 15);
+        quarter_round(x, 1, 6, 11, 12);
+        quarter_round(x, 2, 7, 8, 13);
+        quarter_round(x, 3, 4, 9, 14);
+    }
+}
+
+fn words<const N: usize>(bytes: &[u8]) -> [u32; N] {
+    std::array::from_fn(|i| u32::from_le_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap()))
+}
+
+/// The initial state for `key` with `input` in the last four words.
+fn state(key: &[u8; 32], input: [u32; 4]) -> [u32; 16] {
+    let mut x = [0u32; 16];
+    x[..4].copy_from_slice(&CONSTANTS);
+    x[4..12].copy_from_slice(&words::<8>(key));
+    x[12..].copy_from_slice(&input);
+    x
+}
+
+/// The ChaCha20 block `counter` for `key` and the 96 bit `nonce`.
+fn chacha20_block(key: &[u8; 32], counter: u32, nonce: &[u8; 12]) -> [u8; 64] {
+    let n = words::<3>(nonce);
+    let input = state(key, [counter, n[0], n[1], n[2]]);
+    let mut x = input;
+    rounds(&mut x);
+    let mut out = [0u8; 64];
+    for (i, (w, init)) in x.iter().zip(input).enumerate() {
+        out[4 * i..4 * i + 4].copy_from_slice(&w.wrapping_add(init).to_le_bytes());
+    }
+    out
+}
+
+/// The subkey for `key` and the first 128 bits of an XChaCha20 nonce.
+pub fn hchacha20(key: &[u8; 32], nonce: &[u8; 16]) -> [u8; 32] {
+    let mut x = state(key, words::<4>(nonce));
+    rounds(&mut x);
+    let mut out = [0u8; 32];
+    for (i, w) in x[..4].iter().chain(&x[12..]).enumerate() {
+        out[4 * i..4 * i + 4].copy_from_slice(&w.to_le_bytes());
+    }
+    out
+}
+
+/// The subkey and the ChaCha20 nonce for an XChaCha20 `nonce`.
+fn subkey(key: &[u8; 32], nonce: &[u8; 24]) -> ([u8; 32], [u8; 12]) {
+    let mut short = [0u8; 12];
+    short[4..].copy_from_slice(&nonce[16..]);
+    (hchacha20(key, nonce[..16].try_into().unwrap()), short)
+}
+
+/// XOR `data` with the XChaCha20 key stream for `key` and `nonce`.  There
+/// is no authentication, for the blocks of a swap file, which must keep
+/// their size.
+pub fn xchacha20_xor(key: &[u8; 32], nonce: &[u8; 24], data: &mut [u8]) {
+    let (key, nonce) = subkey(key, nonce);
+    for (counter, chunk) in data.chunks_mut(64).enumerate() {
+        let stream = chacha20_block(&key, counter as u32, &nonce);
+        for (b, s) in chunk.iter_mut().zip(stream) {
+            *b ^= s;
+        }
+    }
+}
+
+fn aead_key(key: &[u8; 32], nonce: &[u8; 24]) -> (LessSafeKey, Nonce) {
+    let (key, nonce) = subkey(key, nonce);
+    let unbound = UnboundKey::new(&CHACHA20_POLY1305, &key).expect("a 256 bit key");
+    (LessSafeKey::new(unbound), Nonce::assume_unique_for_key(nonce))
+}
+
+/// Encrypt `data` in place with XChaCha20-Poly1305, appending the tag.
+/// `aad` is authenticated but not encrypted.
+pub fn seal(key: &[u8; 32], nonce: &[u8; 24], aad: &[u8], data: &mut Vec<u8>) {
+    let (key, nonce) = aead_key(key, nonce);
+    key.seal_in_place_append_tag(nonce, Aad::from(aad), data).expect("the text fits");
+}
+
+/// Decrypt what [`seal`] made.  None when the key is wrong or the data was
+/// changed.
+pub fn open(key: &[u8; 32], nonce: &[u8; 24], aad: &[u8], mut data: Vec<u8>) -> Option<Vec<u8>> {
+    let (key, nonce) = aead_key(key, nonce);
+    let len = key.open_in_place(nonce, Aad::from(aad), &mut data).ok()?.len();
+    data.truncate(len);
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_vectors() {
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        // RFC 8439 2.3.2
+        let nonce = [0, 0, 0, 9, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        assert_eq!(hex(&chacha20_block(&key, 1, &nonce)[..16]), "10f1e7e4d13b5915500fdd1fa32071c4");
+        // draft-irtf-cfrg-xchacha 2.2.1
+        let nonce = [0, 0, 0, 9, 0, 0, 0, 0x4a, 0, 0, 0, 0, 0x31, 0x41, 0x59, 0x27];
+        assert_eq!(hex(&hchacha20(&key, &nonce)), "82413b4227b27bfed30e42508a877d73a0f9e4d58a74a853c12ec41326d3ecdc");
+
+        let nonce = [7u8; 24];
+        let mut data = b"some text".to_vec();
+        seal(&key, &nonce, b"header", &mut data);
+        assert_eq!(data.len(), 9 + TAG_SIZE);
+        assert_eq!(open(&key, &nonce, b"other", data.clone()), None);
+        assert_eq!(open(&key, &nonce, b"header", data).unwrap(), b"some text");
+
+        let mut data = vec![1u8; 200];
+        xchacha20_xor(&key, &nonce, &mut data);
+        assert_ne!(data, vec![1u8; 200]);
+        xchacha20_xor(&key, &nonce, &mut data);
+        assert_eq!(data, vec![1u8; 200]);
+    }
+}
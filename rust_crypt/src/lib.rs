@@ -1,6 +1,34 @@
-use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM};
+//! Encryption of files, swap files and undo files with a passphrase: the
+//! 'key' and 'cryptmethod' options.
+//!
+//! The key is derived from the passphrase with scrypt and a random salt,
+//! every write uses a new random nonce.  See method.rs for the file format.
+
 use std::slice;
 
+mod chacha;
+mod method;
+mod scrypt;
+
+pub use method::{
+    decrypt, detect, encrypt, is_encrypted, random_bytes, CryptError, CryptKey, CryptMethod, HEADER_LEN, MAGIC_LEN,
+    NONCE_LEN, OVERHEAD, SALT_LEN,
+};
+pub use scrypt::ScryptParams;
+
+/// Copy `data` to the C buffer `output` of `output_len` bytes.  Returns the
+/// length, zero when it does not fit.
+fn copy_out(data: &[u8], output: *mut u8, output_len: usize) -> usize {
+    if data.len() > output_len {
+        return 0;
+    }
+    unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), output, data.len()) };
+    data.len()
+}
+
+/// Encrypt `input` with the passphrase `key` as xchacha20scrypt.  The
+/// output is `OVERHEAD` bytes longer than the input.  Returns the output
+/// length, zero on failure.
 #[no_mangle]
 pub extern "C" fn rust_crypt_encrypt(
     input: *const u8,
@@ -14,30 +42,17 @@ pub extern "C" fn rust_crypt_encrypt(
         return 0;
     }
     let data = unsafe { slice::from_raw_parts(input, input_len) };
-    let key_slice = unsafe { slice::from_raw_parts(key, key_len) };
-    if key_slice.len() != 32 {
+    let Ok(key) = std::str::from_utf8(unsafe { slice::from_raw_parts(key, key_len) }) else {
         return 0;
-    }
-    let unbound = match UnboundKey::new(&AES_256_GCM, key_slice) {
-        Ok(k) => k,
-        Err(_) => return 0,
     };
-    let key = LessSafeKey::new(unbound);
-    let nonce_bytes = [0u8; 12];
-    let nonce = Nonce::assume_unique_for_key(nonce_bytes);
-    let mut in_out = data.to_vec();
-    match key.seal_in_place_append_tag(nonce, Aad::empty(), &mut in_out) {
-        Ok(_) => {
-            if in_out.len() > output_len {
-                return 0;
-            }
-            unsafe { std::ptr::copy_nonoverlapping(in_out.as_ptr(), output, in_out.len()); }
-            in_out.len()
-        }
+    match encrypt(CryptMethod::XChaCha20Scrypt, key, data) {
+        Ok(enc) => copy_out(&enc, output, output_len),
         Err(_) => 0,
     }
 }
 
+/// Decrypt what starts with a "VimCrypt~" header with the passphrase `key`.
+/// Returns the output length, zero on failure.
 #[no_mangle]
 pub extern "C" fn rust_crypt_decrypt(
     input: *const u8,
@@ -50,27 +65,14 @@ pub extern "C" fn rust_crypt_decrypt(
     if input.is_null() || key.is_null() || output.is_null() {
         return 0;
     }
-    let mut in_out = unsafe { slice::from_raw_parts(input, input_len).to_vec() };
-    let key_slice = unsafe { slice::from_raw_parts(key, key_len) };
-    if key_slice.len() != 32 {
+    let data = unsafe { slice::from_raw_parts(input, input_len) };
+    let Ok(key) = std::str::from_utf8(unsafe { slice::from_raw_parts(key, key_len) }) else {
         return 0;
-    }
-    let unbound = match UnboundKey::new(&AES_256_GCM, key_slice) {
-        Ok(k) => k,
-        Err(_) => return 0,
     };
-    let key = LessSafeKey::new(unbound);
-    let nonce_bytes = [0u8; 12];
-    let nonce = Nonce::assume_unique_for_key(nonce_bytes);
-    let plaintext = match key.open_in_place(nonce, Aad::empty(), &mut in_out) {
-        Ok(p) => p,
-        Err(_) => return 0,
-    };
-    if plaintext.len() > output_len {
-        return 0;
+    match decrypt(key, data) {
+        Ok((_, text)) => copy_out(&text, output, output_len),
+        Err(_) => 0,
     }
-    unsafe { std::ptr::copy_nonoverlapping(plaintext.as_ptr(), output, plaintext.len()); }
-    plaintext.len()
 }
 
 #[cfg(test)]
@@ -79,9 +81,9 @@ mod tests {
 
     #[test]
     fn roundtrip() {
-        let key = [0u8; 32];
+        let key = b"passphrase";
         let msg = b"hello rust";
-        let mut enc = vec![0u8; msg.len() + 16];
+        let mut enc = vec![0u8; msg.len() + OVERHEAD];
         let enc_len = rust_crypt_encrypt(
             msg.as_ptr(), msg.len(), key.as_ptr(), key.len(), enc.as_mut_ptr(), enc.len());
         assert!(enc_len > 0);
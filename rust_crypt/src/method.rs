@@ -0,0 +1,247 @@
+//! 'cryptmethod': the encrypted file format.
+//!
+//! An encrypted file starts with "VimCrypt~" and two characters for the
+//! method.  New files are written with "xchacha20scrypt": after the magic
+//! come the salt and the scrypt parameters the key was derived with, then the
+//! random nonce of this write, then the text encrypted with
+//! XChaCha20-Poly1305.  The header is authenticated with the text, a wrong
+//! key or a changed file is detected.  Files encrypted with "zip" can still
+//! be read.
+//!
+//! This is not Vim's "xchacha20v2", which derives the key with Argon2id and
+//! uses the libsodium secretstream header: Vim cannot read these files and
+//! its xchacha20 files are an unknown method here.  The magic has a letter
+//! where Vim's have digits, so that the two are never mixed up.
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::chacha::{self, TAG_SIZE};
+use crate::scrypt::{scrypt, ScryptParams};
+
+pub const MAGIC_LEN: usize = 12;
+const MAGIC_PREFIX: &[u8] = b"VimCrypt~";
+pub const SALT_LEN: usize = 16;
+pub const NONCE_LEN: usize = 24;
+/// The size of the xchacha20scrypt header: magic, salt, log N, r, p and nonce.
+pub const HEADER_LEN: usize = MAGIC_LEN + SALT_LEN + 1 + 4 + 4 + NONCE_LEN;
+/// How much longer the encrypted text is than the text.
+pub const OVERHEAD: usize = HEADER_LEN + TAG_SIZE;
+
+/// A value of 'cryptmethod' that can be read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CryptMethod {
+    /// PKZIP, weak: only for reading old files.
+    Zip,
+    #[default]
+    XChaCha20Scrypt,
+}
+
+impl CryptMethod {
+    pub fn from_name(name: &str) -> Option<CryptMethod> {
+        match name {
+            "zip" => Some(CryptMethod::Zip),
+            "xchacha20scrypt" => Some(CryptMethod::XChaCha20Scrypt),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CryptMethod::Zip => "zip",
+            CryptMethod::XChaCha20Scrypt => "xchacha20scrypt",
+        }
+    }
+
+    fn magic(self) -> &'static [u8; MAGIC_LEN] {
+        match self {
+            CryptMethod::Zip => b"VimCrypt~01!",
+            CryptMethod::XChaCha20Scrypt => b"VimCrypt~s1!",
+        }
+    }
+}
+
+/// Why a text cannot be encrypted or decrypted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CryptError {
+    /// The header names a method that is not supported.
+    UnknownMethod,
+    /// Files are not written with this method any longer.
+    ReadOnly(CryptMethod),
+    /// The file ends inside the header.
+    Truncated,
+    /// The scrypt parameters in the header cost too much memory.
+    Params,
+    /// The key is wrong or the file was changed.
+    Failed,
+}
+
+impl std::fmt::Display for CryptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CryptError::UnknownMethod => f.write_str("E821: File is encrypted with unknown method"),
+            CryptError::ReadOnly(method) => {
+                write!(f, "E474: Invalid argument: cryptmethod={} can only be used for reading", method.name())
+            }
+            CryptError::Truncated => f.write_str("E1198: Decryption failed: Header incomplete!"),
+            CryptError::Params => f.write_str("E1196: Cannot decrypt header"),
+            CryptError::Failed => f.write_str("E1200: Decryption failed!"),
+        }
+    }
+}
+
+/// `N` random bytes, for a salt, a nonce or a seed.
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    SystemRandom::new().fill(&mut bytes).expect("the system has a random generator");
+    bytes
+}
+
+/// Whether `head`, the start of a file, is the header of an encrypted file.
+pub fn is_encrypted(head: &[u8]) -> bool {
+    head.starts_with(MAGIC_PREFIX)
+}
+
+/// The method of the encrypted file starting with `head`.
+pub fn detect(head: &[u8]) -> Option<CryptMethod> {
+    let magic = head.get(..MAGIC_LEN)?;
+    [CryptMethod::Zip, CryptMethod::XChaCha20Scrypt].into_iter().find(|m| m.magic() == magic)
+}
+
+/// A key derived from a passphrase with scrypt.  Deriving takes time, a
+/// swap file keeps the key.
+#[derive(Clone)]
+pub struct CryptKey {
+    key: [u8; 32],
+    salt: [u8; SALT_LEN],
+    params: ScryptParams,
+}
+
+impl std::fmt::Debug for CryptKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CryptKey").field("salt", &self.salt).field("params", &self.params).finish_non_exhaustive()
+    }
+}
+
+impl CryptKey {
+    /// The key for `passphrase` with a new random salt.
+    pub fn new(passphrase: &str, params: ScryptParams) -> CryptKey {
+        CryptKey::derive(passphrase, &random_bytes(), params)
+    }
+
+    /// The key for `passphrase` with `salt`, for reading.
+    pub fn derive(passphrase: &str, salt: &[u8; SALT_LEN], params: ScryptParams) -> CryptKey {
+        let mut key = [0u8; 32];
+        scrypt(passphrase.as_bytes(), salt, &params, &mut key);
+        CryptKey { key, salt: *salt, params }
+    }
+
+    pub fn salt(&self) -> &[u8; SALT_LEN] {
+        &self.salt
+    }
+
+    /// XOR `data` with the key stream for `nonce`.  A nonce must not be used
+    /// twice with the same key.
+    pub fn xor(&self, nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+        chacha::xchacha20_xor(&self.key, nonce, data);
+    }
+
+    /// Encrypt `data` as xchacha20scrypt with a new nonce.
+    pub fn encrypt(&self, data: &[u8]) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_LEN + data.len() + TAG_SIZE);
+        header.extend_from_slice(CryptMethod::XChaCha20Scrypt.magic());
+        header.extend_from_slice(&self.salt);
+        header.push(self.params.log_n);
+        header.extend_from_slice(&self.params.r.to_le_bytes());
+        header.extend_from_slice(&self.params.p.to_le_bytes());
+        let nonce: [u8; NONCE_LEN] = random_bytes();
+        header.extend_from_slice(&nonce);
+        let mut text = data.to_vec();
+        chacha::seal(&self.key, &nonce, &header, &mut text);
+        header.extend_from_slice(&text);
+        header
+    }
+}
+
+/// Encrypt `data` with `passphrase` as `method` says.
+pub fn encrypt(method: CryptMethod, passphrase: &str, data: &[u8]) -> Result<Vec<u8>, CryptError> {
+    match method {
+        CryptMethod::Zip => Err(CryptError::ReadOnly(method)),
+        CryptMethod::XChaCha20Scrypt => Ok(CryptKey::new(passphrase, ScryptParams::default()).encrypt(data)),
+    }
+}
+
+/// Decrypt the contents of an encrypted file with `passphrase`.  Returns
+/// the method it was encrypted with and the text.
+pub fn decrypt(passphrase: &str, data: &[u8]) -> Result<(CryptMethod, Vec<u8>), CryptError> {
+    if data.len() < MAGIC_LEN {
+        return Err(CryptError::Truncated);
+    }
+    let method = detect(data).ok_or(CryptError::UnknownMethod)?;
+    match method {
+        CryptMethod::Zip => {
+            let text = rust_crypto_zip::zip_decrypt(&data[MAGIC_LEN..], passphrase.as_bytes());
+            Ok((method, text.ok_or(CryptError::Failed)?))
+        }
+        CryptMethod::XChaCha20Scrypt => {
+            let header = data.get(..HEADER_LEN).ok_or(CryptError::Truncated)?;
+            let salt: [u8; SALT_LEN] = header[MAGIC_LEN..MAGIC_LEN + SALT_LEN].try_into().unwrap();
+            let at = MAGIC_LEN + SALT_LEN;
+            let params = ScryptParams {
+                log_n: header[at],
+                r: u32::from_le_bytes(header[at + 1..at + 5].try_into().unwrap()),
+                p: u32::from_le_bytes(header[at + 5..at + 9].try_into().unwrap()),
+            };
+            // Checked before anything is allocated for the key.
+            if !params.is_valid() {
+                return Err(CryptError::Params);
+            }
+            let nonce: [u8; NONCE_LEN] = header[HEADER_LEN - NONCE_LEN..].try_into().unwrap();
+            let key = CryptKey::derive(passphrase, &salt, params);
+            let text = chacha::open(&key.key, &nonce, header, data[HEADER_LEN..].to_vec());
+            Ok((method, text.ok_or(CryptError::Failed)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let data = encrypt(CryptMethod::XChaCha20Scrypt, "secret", b"some text\n").unwrap();
+        assert_eq!(detect(&data), Some(CryptMethod::XChaCha20Scrypt));
+        assert_eq!(data.len(), 10 + OVERHEAD);
+        // Every write gets a new salt and nonce.
+        assert_ne!(data, encrypt(CryptMethod::XChaCha20Scrypt, "secret", b"some text\n").unwrap());
+        assert_eq!(decrypt("secret", &data).unwrap(), (CryptMethod::XChaCha20Scrypt, b"some text\n".to_vec()));
+        assert_eq!(decrypt("wrong", &data), Err(CryptError::Failed));
+        let mut changed = data.clone();
+        changed[MAGIC_LEN] ^= 1;
+        assert_eq!(decrypt("secret", &changed), Err(CryptError::Failed));
+        assert_eq!(decrypt("secret", &data[..20]), Err(CryptError::Truncated));
+
+        assert_eq!(encrypt(CryptMethod::Zip, "secret", b"text"), Err(CryptError::ReadOnly(CryptMethod::Zip)));
+        assert!(is_encrypted(b"VimCrypt~03!"));
+        assert_eq!(detect(b"VimCrypt~05!"), None);
+        assert_eq!(decrypt("secret", b"VimCrypt~03!...."), Err(CryptError::UnknownMethod));
+    }
+
+    #[test]
+    fn read_zip() {
+        // "text\n" encrypted the way Vim does with 'cryptmethod' zip.
+        let mut data = b"VimCrypt~01!".to_vec();
+        data.extend(rust_crypto_zip::zip_encrypt(b"text\n", b"abc").unwrap());
+        assert_eq!(decrypt("abc", &data).unwrap(), (CryptMethod::Zip, b"text\n".to_vec()));
+    }
+
+    #[test]
+    fn costly_header() {
+        let mut data = encrypt(CryptMethod::XChaCha20Scrypt, "secret", b"text").unwrap();
+        // log N = 20 and r = 32 would take 4 Gbyte.
+        let at = MAGIC_LEN + SALT_LEN;
+        data[at] = 20;
+        data[at + 1..at + 5].copy_from_slice(&32u32.to_le_bytes());
+        assert_eq!(decrypt("secret", &data), Err(CryptError::Params));
+    }
+}
@@ -0,0 +1,146 @@
+//! The scrypt key derivation function (RFC 7914): deriving the key from the
+//! passphrase takes a lot of memory and time, so that guessing it does too.
+
+use std::num::NonZeroU32;
+
+use ring::pbkdf2;
+
+/// The cost of scrypt, stored in the header of an encrypted file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScryptParams {
+    /// N is 2 to the power `log_n`.
+    pub log_n: u8,
+    /// The block size: 128 * r bytes.
+    pub r: u32,
+    /// Parallelization.
+    pub p: u32,
+}
+
+impl Default for ScryptParams {
+    /// N = 2^15, r = 8: 32 Mbyte of memory.
+    fn default() -> Self {
+        ScryptParams { log_n: 15, r: 8, p: 1 }
+    }
+}
+
+/// The most memory deriving a key may take, 128 * r * N bytes.
+const MAX_MEMORY: u64 = 256 << 20;
+
+impl ScryptParams {
+    /// Parameters read from a file are only used when they can be afforded,
+    /// a crafted header must not make opening the file allocate gigabytes.
+    pub fn is_valid(&self) -> bool {
+        (1..=20).contains(&self.log_n)
+            && (1..=32).contains(&self.r)
+            && (1..=16).contains(&self.p)
+            && (128 * self.r as u64) << self.log_n <= MAX_MEMORY
+    }
+}
+
+/// The Salsa20/8 core.
+fn salsa20_8(b: &mut [u32; 16]) {
+    let mut x = *b;
+    let quarter = |x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize| {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    };
+    for _ in 0..4 {
+        quarter(&mut x, 0, 4, 8, 12);
+        quarter(&mut x, 5, 9, 13, 1);
+        quarter(&mut x, 10, 14, 2, 6);
+        quarter(&mut x, 15, 3, 7, 11);
+        quarter(&mut x, 0, 1, 2, 3);
+        quarter(&mut x, 5, 6, 7, 4);
+        quarter(&mut x, 10, 11, 8, 9);
+        quarter(&mut x, 15, 12, 13, 14);
+    }
+    for (b, x) in b.iter_mut().zip(x) {
+        *b = b.wrapping_add(x);
+    }
+}
+
+/// scryptBlockMix of `b`, 2 * r blocks of 16 words, into `out`.
+fn block_mix(b: &[u32], out: &mut [u32]) {
+    let blocks = b.len() / 16;
+    let mut x: [u32; 16] = b[b.len() - 16..].try_into().unwrap();
+    for i in 0..blocks {
+        for (x, b) in x.iter_mut().zip(&b[16 * i..16 * i + 16]) {
+            *x ^= b;
+        }
+        salsa20_8(&mut x);
+        // Even blocks go to the first half, odd ones to the second.
+        let at = (i / 2 + (i % 2) * blocks / 2) * 16;
+        out[at..at + 16].copy_from_slice(&x);
+    }
+}
+
+/// scryptROMix of `b` in place.
+fn ro_mix(b: &mut [u32], n: usize) {
+    let len = b.len();
+    let mut v = vec![0u32; len * n];
+    let mut x = b.to_vec();
+    let mut y = vec![0u32; len];
+    for i in 0..n {
+        v[i * len..(i + 1) * len].copy_from_slice(&x);
+        block_mix(&x, &mut y);
+        std::mem::swap(&mut x, &mut y);
+    }
+    for _ in 0..n {
+        let j = (x[len - 16] as usize) & (n - 1);
+        for (x, v) in x.iter_mut().zip(&v[j * len..(j + 1) * len]) {
+            *x ^= v;
+        }
+        block_mix(&x, &mut y);
+        std::mem::swap(&mut x, &mut y);
+    }
+    b.copy_from_slice(&x);
+}
+
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], out: &mut [u8]) {
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, NonZeroU32::MIN, salt, password, out);
+}
+
+/// Derive `out.len()` bytes from `password` and `salt`.
+pub fn scrypt(password: &[u8], salt: &[u8], params: &ScryptParams, out: &mut [u8]) {
+    let block_len = 128 * params.r as usize;
+    let mut b = vec![0u8; block_len * params.p as usize];
+    pbkdf2_sha256(password, salt, &mut b);
+    for chunk in b.chunks_mut(block_len) {
+        let mut words: Vec<u32> = chunk.chunks(4).map(|w| u32::from_le_bytes(w.try_into().unwrap())).collect();
+        ro_mix(&mut words, 1 << params.log_n);
+        for (bytes, w) in chunk.chunks_mut(4).zip(words) {
+            bytes.copy_from_slice(&w.to_le_bytes());
+        }
+    }
+    pbkdf2_sha256(password, &b, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        assert!(ScryptParams::default().is_valid());
+        assert!(ScryptParams { log_n: 18, r: 8, p: 1 }.is_valid());
+        assert!(!ScryptParams { log_n: 20, r: 32, p: 1 }.is_valid());
+        assert!(!ScryptParams { log_n: 19, r: 8, p: 1 }.is_valid());
+        assert!(!ScryptParams { log_n: 0, r: 8, p: 1 }.is_valid());
+    }
+
+    #[test]
+    fn rfc_7914_vectors() {
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let mut out = [0u8; 64];
+        scrypt(b"", b"", &ScryptParams { log_n: 4, r: 1, p: 1 }, &mut out);
+        assert_eq!(
+            hex(&out),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+             fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+        );
+        scrypt(b"password", b"NaCl", &ScryptParams { log_n: 10, r: 8, p: 16 }, &mut out);
+        assert_eq!(hex(&out[..16]), "fdbabe1c9d3472007856e7190d01e9fe");
+    }
+}
@@ -38,8 +38,9 @@ fn decrypt_byte(keys: &[u32; 3]) -> u8 {
     (((temp as u32) * ((temp ^ 1) as u32) >> 8) & 0xff) as u8
 }
 
-// Encrypt data using the traditional Zip crypto algorithm.
-fn zip_encrypt(data: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+// Encrypt data using the traditional Zip crypto algorithm.  Only kept for
+// the FFI and to test reading, new files are not encrypted with it.
+pub fn zip_encrypt(data: &[u8], key: &[u8]) -> Option<Vec<u8>> {
     make_crc_tab();
     let mut keys = [305419896u32, 591751049u32, 878082192u32];
     for &b in key {
@@ -54,8 +55,9 @@ fn zip_encrypt(data: &[u8], key: &[u8]) -> Option<Vec<u8>> {
     Some(out)
 }
 
-// Decrypt data using the traditional Zip crypto algorithm.
-fn zip_decrypt(data: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+// Decrypt data using the traditional Zip crypto algorithm, for reading
+// files written with 'cryptmethod' zip.
+pub fn zip_decrypt(data: &[u8], key: &[u8]) -> Option<Vec<u8>> {
     make_crc_tab();
     let mut keys = [305419896u32, 591751049u32, 878082192u32];
     for &b in key {
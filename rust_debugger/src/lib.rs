@@ -1,5 +1,6 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use std::ffi::CStr;
+use std::io::{self, BufRead, Write};
 use std::os::raw::{c_char, c_int};
 use once_cell::sync::Lazy;
 use std::sync::Mutex;
@@ -51,6 +52,245 @@ pub extern "C" fn rs_debugger_hit_breakpoint(file: *const c_char, line: c_int) -
     }
 }
 
+/// A breakpoint in a function, set with `:breakadd func [lnum] {name}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub nr: usize,
+    pub name: String,
+    /// Line in the function body, one based.
+    pub lnum: usize,
+}
+
+impl Breakpoint {
+    /// Whether the breakpoint is for function `name`.  A script-local
+    /// function also matches without its `<SNR>{sid}_` prefix.
+    pub fn matches(&self, name: &str) -> bool {
+        if self.name == name {
+            return true;
+        }
+        name.strip_prefix("<SNR>")
+            .and_then(|rest| rest.split_once('_'))
+            .is_some_and(|(sid, short)| sid.bytes().all(|b| b.is_ascii_digit()) && short == self.name)
+    }
+}
+
+/// A command typed at the debug prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugCommand {
+    Cont,
+    Next,
+    Step,
+    Finish,
+    Quit,
+    Backtrace,
+    Up(usize),
+    Down(usize),
+    Frame(usize),
+    /// Any other command, e.g. `echo name` to inspect a variable.
+    Ex(String),
+}
+
+impl DebugCommand {
+    /// Parse a debug prompt command.  Like in Vim the commands can be
+    /// abbreviated: "c", "n", "s", "f", "q", "bt", "where", "u", "d".
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        let (cmd, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let count = || arg.trim().parse().unwrap_or(1);
+        let is = |full: &str, min: usize| cmd.len() >= min && full.starts_with(cmd);
+        let parsed = if cmd.is_empty() {
+            return None;
+        } else if is("cont", 1) {
+            Self::Cont
+        } else if is("next", 1) {
+            Self::Next
+        } else if is("step", 1) {
+            Self::Step
+        } else if is("finish", 1) {
+            Self::Finish
+        } else if is("quit", 1) {
+            Self::Quit
+        } else if cmd == "bt" || is("backtrace", 2) || is("where", 1) {
+            Self::Backtrace
+        } else if is("up", 1) {
+            Self::Up(count())
+        } else if is("down", 1) {
+            Self::Down(count())
+        } else if is("frame", 2) {
+            Self::Frame(arg.trim().parse().unwrap_or(0))
+        } else {
+            Self::Ex(line.to_string())
+        };
+        Some(parsed)
+    }
+}
+
+/// Where the debug prompt gets its commands from.
+pub trait DebugInput {
+    /// The next command line, `None` at end of input.
+    fn read_line(&mut self) -> Option<String>;
+}
+
+/// Read debug commands from stdin, prompting with ">".
+pub struct StdinInput;
+
+impl DebugInput for StdinInput {
+    fn read_line(&mut self) -> Option<String> {
+        print!(">");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end().to_string()),
+        }
+    }
+}
+
+/// Scripted debug commands, used for testing.
+impl DebugInput for VecDeque<String> {
+    fn read_line(&mut self) -> Option<String> {
+        self.pop_front()
+    }
+}
+
+/// How execution continues after leaving the debug prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StepMode {
+    /// Run until the next breakpoint.
+    #[default]
+    Run,
+    /// Stop at the next line, also in called functions.
+    Step,
+    /// Stop at the next line at call depth `depth` or lower.
+    Next(usize),
+    /// Stop when returning below call depth `depth`.
+    Finish(usize),
+}
+
+/// Function breakpoints and the state of the debug prompt.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    last_nr: usize,
+    pub mode: StepMode,
+    last_command: Option<DebugCommand>,
+    input: Box<dyn DebugInput>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new(Box::new(StdinInput))
+    }
+}
+
+impl Debugger {
+    pub fn new(input: Box<dyn DebugInput>) -> Self {
+        Self { breakpoints: Vec::new(), last_nr: 0, mode: StepMode::Run, last_command: None, input }
+    }
+
+    pub fn set_input(&mut self, input: Box<dyn DebugInput>) {
+        self.input = input;
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Execute `:breakadd {arg}`.  Returns the number of the new breakpoint.
+    pub fn ex_breakadd(&mut self, arg: &str) -> Result<usize, String> {
+        let (name, lnum) = parse_func_arg(arg)?;
+        self.last_nr += 1;
+        self.breakpoints.push(Breakpoint { nr: self.last_nr, name, lnum: lnum.unwrap_or(1) });
+        Ok(self.last_nr)
+    }
+
+    /// Execute `:breakdel {arg}`: a breakpoint number, `*` for all of them
+    /// or `func [lnum] {name}`.
+    pub fn ex_breakdel(&mut self, arg: &str) -> Result<(), String> {
+        let arg = arg.trim();
+        let before = self.breakpoints.len();
+        if arg == "*" {
+            self.breakpoints.clear();
+            return Ok(());
+        } else if let Ok(nr) = arg.parse::<usize>() {
+            self.breakpoints.retain(|bp| bp.nr != nr);
+        } else {
+            let (name, lnum) = parse_func_arg(arg)?;
+            self.breakpoints.retain(|bp| bp.name != name || lnum.is_some_and(|l| l != bp.lnum));
+        }
+        if self.breakpoints.len() == before {
+            return Err(format!("E161: Breakpoint not found: {}", arg));
+        }
+        Ok(())
+    }
+
+    /// The lines listed by `:breaklist`.
+    pub fn breaklist(&self) -> Vec<String> {
+        if self.breakpoints.is_empty() {
+            return vec!["No breakpoints defined".to_string()];
+        }
+        self.breakpoints
+            .iter()
+            .map(|bp| format!("{:3}  func {}  line {}", bp.nr, bp.name, bp.lnum))
+            .collect()
+    }
+
+    /// Whether a breakpoint is set at line `lnum` (one based) of `name`.
+    pub fn breakpoint_at(&self, name: &str, lnum: usize) -> bool {
+        self.breakpoints.iter().any(|bp| bp.lnum == lnum && bp.matches(name))
+    }
+
+    /// Whether the prompt is to be entered at a line executed at call
+    /// depth `depth` because of stepping.
+    pub fn step_stop(&self, depth: usize) -> bool {
+        match self.mode {
+            StepMode::Run => false,
+            StepMode::Step => true,
+            StepMode::Next(d) => depth <= d,
+            StepMode::Finish(d) => depth < d,
+        }
+    }
+
+    /// Read the next command at the debug prompt.  An empty line repeats the
+    /// last command, end of input continues execution.
+    pub fn read_command(&mut self) -> DebugCommand {
+        loop {
+            let Some(line) = self.input.read_line() else {
+                return DebugCommand::Cont;
+            };
+            match DebugCommand::parse(&line) {
+                Some(cmd) => {
+                    self.last_command = Some(cmd.clone());
+                    return cmd;
+                }
+                None => {
+                    if let Some(cmd) = self.last_command.clone() {
+                        return cmd;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parse "func [lnum] {name}", also accepting "func {name} {lnum}".
+fn parse_func_arg(arg: &str) -> Result<(String, Option<usize>), String> {
+    let invalid = || format!("E475: Invalid argument: {}", arg.trim());
+    let mut words = arg.split_whitespace();
+    if words.next() != Some("func") {
+        return Err(invalid());
+    }
+    let rest: Vec<&str> = words.collect();
+    match rest.as_slice() {
+        [name] => Ok((name.trim_end_matches("()").to_string(), None)),
+        [a, b] => match (a.parse::<usize>(), b.parse::<usize>()) {
+            (Ok(lnum), _) => Ok((b.trim_end_matches("()").to_string(), Some(lnum))),
+            (_, Ok(lnum)) => Ok((a.trim_end_matches("()").to_string(), Some(lnum))),
+            _ => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,4 +304,49 @@ mod tests {
         assert_eq!(rs_debugger_remove_breakpoint(file.as_ptr(), 10), 1);
         assert_eq!(rs_debugger_check_breakpoint(file.as_ptr(), 10), 0);
     }
+
+    #[test]
+    fn func_breakpoints() {
+        let mut dbg = Debugger::new(Box::new(VecDeque::new()));
+        assert_eq!(dbg.breaklist(), ["No breakpoints defined"]);
+        assert_eq!(dbg.ex_breakadd("func 3 Foo").unwrap(), 1);
+        assert_eq!(dbg.ex_breakadd("func Bar 2").unwrap(), 2);
+        assert_eq!(dbg.ex_breakadd("func Baz()").unwrap(), 3);
+        assert!(dbg.ex_breakadd("file x.vim").unwrap_err().starts_with("E475:"));
+        assert!(dbg.breakpoint_at("Foo", 3));
+        assert!(dbg.breakpoint_at("<SNR>12_Foo", 3));
+        assert!(!dbg.breakpoint_at("<SNR>12_Foo", 2));
+        assert!(dbg.breakpoint_at("Baz", 1));
+        assert_eq!(dbg.breaklist()[1], "  2  func Bar  line 2");
+
+        dbg.ex_breakdel("2").unwrap();
+        dbg.ex_breakdel("func Baz").unwrap();
+        assert_eq!(dbg.ex_breakdel("2").unwrap_err(), "E161: Breakpoint not found: 2");
+        assert_eq!(dbg.breakpoints().len(), 1);
+        dbg.ex_breakdel("*").unwrap();
+        assert!(dbg.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn prompt_commands() {
+        assert_eq!(DebugCommand::parse("c"), Some(DebugCommand::Cont));
+        assert_eq!(DebugCommand::parse("bt"), Some(DebugCommand::Backtrace));
+        assert_eq!(DebugCommand::parse("where"), Some(DebugCommand::Backtrace));
+        assert_eq!(DebugCommand::parse("up 2"), Some(DebugCommand::Up(2)));
+        assert_eq!(DebugCommand::parse("fr 1"), Some(DebugCommand::Frame(1)));
+        assert_eq!(DebugCommand::parse("echo x"), Some(DebugCommand::Ex("echo x".to_string())));
+        assert_eq!(DebugCommand::parse("  "), None);
+
+        let input: VecDeque<String> = ["next", "", "quit"].iter().map(|s| s.to_string()).collect();
+        let mut dbg = Debugger::new(Box::new(input));
+        assert_eq!(dbg.read_command(), DebugCommand::Next);
+        assert_eq!(dbg.read_command(), DebugCommand::Next);
+        assert_eq!(dbg.read_command(), DebugCommand::Quit);
+        assert_eq!(dbg.read_command(), DebugCommand::Cont);
+
+        dbg.mode = StepMode::Next(2);
+        assert!(dbg.step_stop(1) && dbg.step_stop(2) && !dbg.step_stop(3));
+        dbg.mode = StepMode::Finish(2);
+        assert!(dbg.step_stop(1) && !dbg.step_stop(2));
+    }
 }
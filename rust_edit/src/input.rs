@@ -0,0 +1,184 @@
+//! Typing text in Insert mode and on the command line, with abbreviations.
+//!
+//! The keys come from a [`TypeBuf`], with mappings expanded.  An
+//! abbreviation is checked for when a non-keyword character is typed, when
+//! the input ends with `<Esc>` or `<CR>`, and for CTRL-], which expands it
+//! without inserting anything.  A key typed after CTRL-V is inserted as it
+//! is and does not expand an abbreviation.
+
+use rust_map::keys::{BS, CAR, CTRL_RSB, CTRL_V, ESC, NL};
+use rust_map::{is_word_char, MapHost, Mode, TypeBuf};
+
+use crate::handle_backspace;
+
+/// A line being typed.
+#[derive(Debug, Clone)]
+pub struct LineInput {
+    text: String,
+    /// Byte index of the cursor.
+    cursor: usize,
+    /// [`Mode::INSERT`] or [`Mode::CMDLINE`].
+    mode: Mode,
+    /// Where Insert mode started: an abbreviation does not start before it.
+    start: usize,
+    /// CTRL-V was typed, the next key is inserted literally.
+    literal: bool,
+}
+
+impl LineInput {
+    /// Start Insert mode in `text` at byte `cursor`.
+    pub fn insert(text: &str, cursor: usize) -> Self {
+        LineInput { text: text.to_string(), cursor, mode: Mode::INSERT, start: cursor, literal: false }
+    }
+
+    /// Start typing a command line.
+    pub fn cmdline() -> Self {
+        LineInput { text: String::new(), cursor: 0, mode: Mode::CMDLINE, start: 0, literal: false }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Handle `key`, got from `typebuf` with buffer `buf` current.  Returns
+    /// the key that ends the input: `<Esc>`, `<CR>` or `<NL>`.  When it
+    /// expands an abbreviation the key comes again after it.
+    pub fn key(&mut self, key: u32, typebuf: &mut TypeBuf, buf: usize, host: &mut dyn MapHost) -> Option<u32> {
+        if self.literal {
+            self.literal = false;
+            self.put(key);
+            return None;
+        }
+        match key {
+            CTRL_V => self.literal = true,
+            BS => handle_backspace(&mut self.text, &mut self.cursor),
+            CTRL_RSB => {
+                self.check_abbr(key, typebuf, buf, host);
+            }
+            ESC | CAR | NL => {
+                if !self.check_abbr(key, typebuf, buf, host) {
+                    return Some(key);
+                }
+            }
+            _ => {
+                let word = char::from_u32(key).is_some_and(is_word_char);
+                if word || !self.check_abbr(key, typebuf, buf, host) {
+                    self.put(key);
+                }
+            }
+        }
+        None
+    }
+
+    fn put(&mut self, key: u32) {
+        // Special keys are not inserted.
+        if let Some(c) = char::from_u32(key) {
+            self.text.insert(self.cursor, c);
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    fn check_abbr(&self, key: u32, typebuf: &mut TypeBuf, buf: usize, host: &mut dyn MapHost) -> bool {
+        typebuf.check_abbr(key, &self.text[..self.cursor], self.mincol(), self.mode, buf, host)
+    }
+
+    /// Where an abbreviation may start.  On the command line that is after
+    /// leading white space and a "'<,'>" range, like Vim's ccheck_abbr().
+    fn mincol(&self) -> usize {
+        if self.mode != Mode::CMDLINE {
+            return self.start;
+        }
+        let text = self.text.trim_start_matches([' ', '\t']);
+        let skipped = self.text.len() - text.len();
+        let bytes = text.as_bytes();
+        if bytes.len() > 5 && bytes[0] == b'\'' && bytes[2] == b',' && bytes[3] == b'\'' {
+            return skipped + 5;
+        }
+        skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_map::{keys_to_string, parse_keys, KeySource, MapCmd, MapContext, MapTable, Mapping};
+    use std::collections::VecDeque;
+
+    struct Keys {
+        maps: MapTable,
+        abbrs: MapTable,
+        typed: VecDeque<u32>,
+    }
+
+    impl MapHost for Keys {
+        fn maps(&self) -> &MapTable {
+            &self.maps
+        }
+
+        fn abbrs(&self) -> &MapTable {
+            &self.abbrs
+        }
+
+        fn timeoutlen(&self) -> Option<u64> {
+            None
+        }
+
+        fn eval_map_expr(&mut self, map: &Mapping) -> String {
+            map.rhs.to_uppercase()
+        }
+
+        fn exec_map_cmd(&mut self, _map: &Mapping, _cmd: &str) {}
+    }
+
+    impl KeySource for Keys {
+        fn get_key(&mut self, _timeout: Option<u64>) -> Option<u32> {
+            self.typed.pop_front()
+        }
+    }
+
+    /// Type `typed` into `input`, returns the key that ended it.
+    fn type_keys(input: &mut LineInput, abbrs: &[&str], typed: &str) -> String {
+        let ctx = MapContext { curbuf: 1, sid: 0, leader: "\\", localleader: "" };
+        let mut keys = Keys { maps: MapTable::new(), abbrs: MapTable::new_abbr(), typed: parse_keys(typed, "", "").into() };
+        for arg in abbrs {
+            keys.abbrs.do_map(MapCmd::Map, Mode::INSERT | Mode::CMDLINE, arg, &ctx).unwrap();
+        }
+        let mut typebuf = TypeBuf::new();
+        while let Some(key) = typebuf.vgetc(input.mode, 1, &mut keys).unwrap() {
+            if let Some(end) = input.key(key, &mut typebuf, 1, &mut keys) {
+                return keys_to_string(&[end]);
+            }
+        }
+        String::new()
+    }
+
+    #[test]
+    fn insert_mode_abbreviations() {
+        let abbrs = ["teh the", "#i #include", "<expr> up upper"];
+        let mut input = LineInput::insert("x", 1);
+        assert_eq!(type_keys(&mut input, &abbrs, " teh teh<C-V> teh. #i <lt>io><Esc>"), "<Esc>");
+        assert_eq!(input.text(), "x the teh the. #include <io>");
+
+        // Ending Insert mode and CTRL-] expand, the start of the insert is
+        // a boundary.
+        let mut input = LineInput::insert("teh", 3);
+        assert_eq!(type_keys(&mut input, &abbrs, "teh<C-]> up<Esc>"), "<Esc>");
+        assert_eq!(input.text(), "tehthe UPPER");
+        assert_eq!(input.cursor(), input.text().len());
+    }
+
+    #[test]
+    fn cmdline_abbreviations() {
+        let abbrs = ["W w", "s substitute"];
+        let mut input = LineInput::cmdline();
+        assert_eq!(type_keys(&mut input, &abbrs, "'<,'>s/a/b<CR>"), "<CR>");
+        assert_eq!(input.text(), "'<,'>substitute/a/b");
+        let mut input = LineInput::cmdline();
+        assert_eq!(type_keys(&mut input, &abbrs, "  W<CR>"), "<CR>");
+        assert_eq!(input.text(), "  w");
+    }
+}
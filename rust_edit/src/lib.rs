@@ -1,7 +1,8 @@
 //! Editing utilities implemented in Rust.
 //!
-//! Provides backspace handling that is aware of UTF-8 characters
-//! and a simple completion trigger heuristic.
+//! Provides backspace handling that is aware of UTF-8 characters,
+//! a simple completion trigger heuristic and typing a line in Insert
+//! mode or on the command line.
 
 /// Equivalent of Vim's CTRL macro. Shared with C via cbindgen.
 #[no_mangle]
@@ -15,6 +16,10 @@ pub static BACKSPACE: u8 = b'h' & 0x1f;
 
 use std::os::raw::{c_char, c_int};
 
+mod input;
+
+pub use input::LineInput;
+
 const REPLACE_FLAG: c_int = 0x100;
 
 #[cfg(not(test))]
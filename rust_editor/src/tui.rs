@@ -1,4 +1,5 @@
-use std::fs;
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
@@ -10,28 +11,67 @@ use ratatui::style::{Style, Modifier};
 use ratatui::text::{Line, Span, Text};
 use ratatui::widgets::{Block, Borders, Paragraph};
 use regex::{Regex, RegexBuilder};
+use rust_arglist::{ArgAction, ArgList, WindowArgs};
+use rust_buffer::{BufAction, BufferList};
+use rust_bufwrite::{do_write, FileOptions, WriteBuffer, WriteCmd, WriteSettings, WriteTarget, Written};
+use rust_eval::Evaluator;
+use rust_fileio::{read_file, FileArgs, ReadOptions};
+use rust_session::{mksession, mkview, view_file_name, write_script, Frame, Session, SessionBuffer, SessionOptions, Window as SessionWindow};
+use rust_undo::{parse_step, read_undo_file, undo_file_name, write_undo_file, StepUnit, UndoBuffer, UndoTree};
 
 #[derive(Clone)]
-struct View { kind: ViewKind, cx: usize, cy: usize, scroll: usize, buf: Option<usize> }
+struct View { kind: ViewKind, cx: usize, cy: usize, scroll: usize, buf: Option<usize>, args: WindowArgs }
 
-fn open_file(path: &Path) -> Vec<String> {
-    match fs::read_to_string(path) {
-        Ok(content) => content.replace('\r', "").split('\n').map(|s| s.to_string()).collect(),
-        Err(_) => Vec::new(),
+/// Read `path` with the `++` arguments `args`, detecting 'fileencoding' and
+/// 'fileformat', decrypting with 'key'.  Returns the lines, the file options
+/// of the buffer with `opts` as the old ones, and the message.
+fn open_file(path: &Path, args: &FileArgs, opts: &FileOptions) -> (Vec<String>, FileOptions, Option<String>) {
+    let read_opts = ReadOptions { binary: opts.binary, key: Some(opts.key.clone()), ..ReadOptions::default() }.with_args(args);
+    match read_file(path, &read_opts) {
+        Ok(r) => {
+            let mut o = opts.read(&r);
+            o.binary = read_opts.binary;
+            (r.lines.iter().map(|l| String::from_utf8_lossy(l).into_owned()).collect(), o, Some(r.message(&path.display().to_string())))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Vec::new(), opts.clone(), None),
+        Err(e) => (Vec::new(), opts.clone(), Some(e.to_string())),
     }
 }
 
-fn save_file(path: &Path, lines: &Vec<String>) -> std::io::Result<()> {
-    let mut s = String::new();
-    for (i, line) in lines.iter().enumerate() {
-        s.push_str(line);
-        if i + 1 < lines.len() { s.push('\n'); }
-    }
-    fs::write(path, s)
+/// Whether `path` is encrypted and 'key' in `opts` is not set to read it.
+fn needs_key(path: &Path, opts: &FileOptions) -> bool {
+    let mut head = [0u8; rust_crypt::MAGIC_LEN];
+    opts.key.is_empty() && std::fs::File::open(path).and_then(|mut f| f.read_exact(&mut head)).is_ok() && rust_crypt::is_encrypted(&head)
+}
+
+/// キー入力待ち: `:X` の 1 回目と確認、暗号化ファイルを読むためのキー
+enum KeyPrompt { Set, Confirm(String), Read(PathBuf, Option<usize>) }
+
+/// `:write` が書くバッファ: ファイル名と行
+struct WriteLines<'a> { name: String, lines: &'a [String] }
+
+impl WriteBuffer for WriteLines<'_> {
+    fn name(&self) -> &str { &self.name }
+    fn line_count(&self) -> usize { self.lines.len() }
+    fn line(&self, lnum: usize) -> &str { &self.lines[lnum - 1] }
+}
+
+/// Execute `:w[!] {cmd}` for `lines` of the file `path`: with a backup and a
+/// rename, `:w >> file` and `:w !cmd` included.
+fn write_cmd(path: Option<&Path>, lines: &[String], opts: &FileOptions, cmd: &WriteCmd, forceit: bool) -> Result<Written, String> {
+    let mut buf = WriteLines { name: path.map(|p| p.display().to_string()).unwrap_or_default(), lines };
+    do_write(&mut buf, None, cmd, forceit, opts, &WriteSettings::default())
+}
+
+/// Write `lines` to `path` as 'fileformat', 'fileencoding', 'endofline' and
+/// friends in `opts` say.  Returns the message.
+fn save_file(path: &Path, lines: &[String], opts: &FileOptions, args: &FileArgs) -> Result<String, String> {
+    let cmd = WriteCmd { args: args.clone(), append: false, target: WriteTarget::Buffer };
+    write_cmd(Some(path), lines, opts, &cmd, true).map(|w| w.messages.join(" "))
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
-enum Mode { Normal, Insert, Command, SearchFwd, SearchBwd, VisualChar, VisualLine }
+enum Mode { Normal, Insert, Command, SearchFwd, SearchBwd, VisualChar, VisualLine, Key }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum SplitLayout { Horizontal, Vertical }
@@ -186,10 +226,11 @@ fn save_active(
     lines: &mut Vec<String>,
     filename: &mut Option<PathBuf>,
     modified: &mut bool,
+    opts: &FileOptions,
 ) -> Result<(), String> {
     with_active_mut(buffers, view_buf, lines, filename, modified, |ls, fname, m| {
         if let Some(ref p) = fname {
-            save_file(p, ls).map_err(|_| "write error".to_string())?;
+            save_file(p, ls, opts, &FileArgs::default())?;
             *m = false;
             Ok(())
         } else {
@@ -198,20 +239,200 @@ fn save_active(
     })
 }
 
+/// The default of 'directory': where swap files are looked for.
+const SWAP_DIRECTORY: &str = ".,~/tmp,/var/tmp,/tmp";
+
+/// `-r {file}`: the text recovered from the swap file of `path`, whether it
+/// differs from the file and the last message.
+fn recover_file(path: &Path) -> Result<(Vec<String>, bool, String), String> {
+    let swap = match rust_memline::find_swap_files(path, SWAP_DIRECTORY).into_iter().next() {
+        Some(swap) => swap,
+        None => return Err(format!("E305: No swap file found for {}", path.display())),
+    };
+    let recovered = rust_memline::recover(&swap, None)?;
+    let msg = recovered.messages.iter().rev().find(|m| m.starts_with("Recovery") || m.starts_with("E312")).cloned();
+    Ok((recovered.lines, recovered.changed, msg.unwrap_or_default()))
+}
+
+/// The default of 'undodir': undo files are written next to the file.
+const UNDO_DIRECTORY: &str = ".";
+
+/// The text of a buffer and the cursor, as undo sees them.
+struct UndoText<'a> { lines: &'a mut Vec<String>, cursor: (usize, usize) }
+
+impl UndoBuffer for UndoText<'_> {
+    fn line_count(&self) -> usize { self.lines.len() }
+    fn line(&self, lnum: usize) -> &str { &self.lines[lnum - 1] }
+    fn replace_lines(&mut self, top: usize, count: usize, lines: Vec<String>) -> Vec<String> { self.lines.splice(top..top + count, lines).collect() }
+    fn cursor(&self) -> (usize, usize) { self.cursor }
+    fn set_cursor(&mut self, lnum: usize, col: usize) { self.cursor = (lnum, col); }
+}
+
+/// Undo, redo or go back and forth in time in `lines` with `f`, updating the
+/// cursor.  Returns the message.
+fn undo_step(tree: &mut UndoTree, lines: &mut Vec<String>, cx: &mut usize, cy: &mut usize, f: impl FnOnce(&mut UndoTree, &mut UndoText) -> Result<String, String>) -> String {
+    let mut text = UndoText { lines, cursor: (*cy + 1, *cx + 1) };
+    let msg = f(tree, &mut text).unwrap_or_else(|e| e);
+    let (lnum, col) = text.cursor;
+    if lines.is_empty() { lines.push(String::new()); }
+    *cy = lnum.saturating_sub(1).min(lines.len() - 1);
+    *cx = col.saturating_sub(1).min(lines[*cy].len());
+    msg
+}
+
+/// `:undo [N]`, `:redo`, `:earlier {N}` or `:later {N}`: the full command
+/// name and the argument.
+fn undo_command(cmd: &str) -> Option<(&'static str, &str)> {
+    let c = cmd.trim_start_matches(':');
+    let len = c.find(|ch: char| !ch.is_ascii_alphabetic()).unwrap_or(c.len());
+    let name = &c[..len];
+    [("undo", 1), ("redo", 3), ("earlier", 2), ("later", 3)].into_iter()
+        .find(|(full, min)| name.len() >= *min && full.starts_with(name))
+        .map(|(full, _)| (full, c[len..].trim()))
+}
+
+/// `:mksession`, `:mkview` or `:loadview`: the full name, whether `!` was
+/// given and the argument.
+fn session_command(cmd: &str) -> Option<(&'static str, bool, &str)> {
+    let c = cmd.trim_start_matches(':');
+    let len = c.find(|ch: char| !ch.is_ascii_alphabetic()).unwrap_or(c.len());
+    let name = &c[..len];
+    let (bang, arg) = match c[len..].strip_prefix('!') { Some(rest) => (true, rest.trim()), None => (false, c[len..].trim()) };
+    [("mksession", 3), ("mkview", 5), ("loadview", 2)].into_iter()
+        .find(|(full, min)| name.len() >= *min && full.starts_with(name))
+        .map(|(full, _)| (full, bang, arg))
+}
+
+/// The name of the buffer of a view: its file name, empty without one.
+fn view_buffer_name(buf: Option<usize>, buffers: &[Buffer], filename: &Option<PathBuf>) -> String {
+    let name = match buf { Some(bi) => buffers.get(bi).and_then(|b| b.filename.clone()), None => filename.clone() };
+    name.map(|p| p.display().to_string()).unwrap_or_default()
+}
+
+/// The buffer slot of buffer number `nr`.
+fn slot_of(buf_nrs: &HashMap<Option<usize>, usize>, nr: usize) -> Option<Option<usize>> {
+    buf_nrs.iter().find(|(_, &n)| n == nr).map(|(&slot, _)| slot)
+}
+
+/// Show buffer slot `to` in window `win`, like Vim's do_buffer(): the buffer
+/// list decides whether the buffer the window showed stays loaded, with
+/// 'hidden' and 'bufhidden'.  Returns whether the text of `to` has to be
+/// read, it was not loaded.
+fn switch_buffer(buflist: &mut BufferList, buf_nrs: &HashMap<Option<usize>, usize>, views: &mut [View], win: usize, to: Option<usize>, hidden: bool, force: bool) -> Result<bool, String> {
+    let from = views[win].buf;
+    let to_nr = buf_nrs.get(&to).copied();
+    let read = to_nr.and_then(|nr| buflist.get(nr)).is_some_and(|b| !b.loaded);
+    if from != to {
+        let others = views.iter().enumerate().filter(|(i, v)| *i != win && v.kind == ViewKind::Normal && v.buf == from).count();
+        if let Some(&nr) = buf_nrs.get(&from) { buflist.abandon(nr, others, hidden, force)?; }
+        views[win].buf = to;
+    }
+    if let Some(nr) = to_nr { buflist.enter(nr); }
+    Ok(read)
+}
+
+/// Read the file of buffer slot `bi` into it, for a buffer that was not
+/// loaded.  Returns the message for the status line.
+fn read_slot(bi: Option<usize>, buffers: &mut [Buffer], lines: &mut Vec<String>, filename: &Option<PathBuf>, file_opts: &mut HashMap<Option<usize>, FileOptions>, undo_trees: &mut HashMap<Option<usize>, UndoTree>, undofile: bool) -> Option<String> {
+    let (target, name) = match bi { Some(i) => { let b = buffers.get_mut(i)?; (&mut b.lines, b.filename.clone()) } None => (lines, filename.clone()) };
+    let p = name?;
+    let (new_lines, o, msg) = open_file(&p, &FileArgs::default(), file_opts.entry(bi).or_default());
+    *target = if new_lines.is_empty() { vec![String::new()] } else { new_lines };
+    undo_trees.insert(bi, undo_read(&p, target, undofile, &o.key));
+    file_opts.insert(bi, o);
+    Some(msg.unwrap_or_else(|| format!("\"{}\" [New]", p.display())))
+}
+
+/// The window of the session for view `v`, `area` is where it is drawn.
+fn session_window(v: &View, name: String, area: Rect) -> SessionWindow {
+    let mut win = SessionWindow::new(&name, area.width as usize, area.height as usize);
+    win.lnum = v.cy + 1; win.col = v.cx; win.topline = v.scroll + 1;
+    win.arglist = v.args.local.as_ref().map(|l| l.names().to_vec());
+    win
+}
+
+/// The session for `:mksession`: the normal views in `layout` on a screen
+/// of `size`, with the buffer list and the argument list.
+fn session_of(views: &[View], cur_view: usize, layout: SplitLayout, buffers: &[Buffer], filename: &Option<PathBuf>, arglist: &ArgList, size: Rect) -> Session {
+    let mut session = Session::new(size.width as usize, size.height as usize);
+    let normal: Vec<(usize, &View)> = views.iter().enumerate().filter(|(_, v)| v.kind == ViewKind::Normal).collect();
+    let areas = split_rect(layout, Rect::new(0, 0, size.width, size.height.saturating_sub(2)), normal.len());
+    let mut wins: Vec<Frame> = normal.iter().zip(&areas).map(|((_, v), a)| Frame::Leaf(session_window(v, view_buffer_name(v.buf, buffers, filename), *a))).collect();
+    let tab = &mut session.tabpages[0];
+    tab.cur_win = normal.iter().position(|(i, _)| *i == cur_view).unwrap_or(0);
+    tab.layout = if wins.len() == 1 { wins.remove(0) } else if layout == SplitLayout::Vertical { Frame::Row(wins) } else { Frame::Col(wins) };
+    let names = filename.iter().chain(buffers.iter().filter_map(|b| b.filename.as_ref()));
+    session.buffers = names.map(|p| SessionBuffer { name: p.display().to_string(), lnum: 1 }).collect();
+    session.arglist = arglist.names().to_vec();
+    session.cwd = std::env::current_dir().ok().map(|d| d.display().to_string());
+    session
+}
+
+/// The file of `:mkview {arg}` for `name`: `arg` when it is a file name,
+/// otherwise in ~/.vim/view with the view number.
+fn view_file(name: &str, arg: &str) -> Result<PathBuf, String> {
+    if name.is_empty() { return Err("E32: No file name".into()); }
+    let nr = match arg.chars().next() { None => None, Some(c) if arg.len() == 1 && c.is_ascii_digit() => Some(c), Some(_) => return Ok(PathBuf::from(arg)) };
+    let full = std::fs::canonicalize(name).map(|p| p.display().to_string()).unwrap_or_else(|_| name.to_string());
+    let home = std::env::var("HOME").unwrap_or_default();
+    let full = match full.strip_prefix(&home) { Some(rest) if !home.is_empty() => format!("~{}", rest), _ => full };
+    Ok(view_file_name(&Path::new(&home).join(".vim/view"), &full, nr))
+}
+
+/// After writing `path`: remember the write for `:earlier 1f` and, with
+/// 'undofile', write the undo file, encrypted with `key` when not empty.
+/// Returns an error message.
+fn undo_written(tree: &mut UndoTree, path: &Path, lines: &Vec<String>, undofile: bool, key: &str) -> Option<String> {
+    tree.set_saved();
+    if !undofile || tree.is_empty() { return None; }
+    let undo_path = undo_file_name(path, UNDO_DIRECTORY, false)?;
+    write_undo_file(tree, lines, &undo_path, false, Some(key)).err().map(|e| e.to_string())
+}
+
+/// The undo tree for the text of `path` just read: from its undo file with
+/// 'undofile' set, when it was written for this text.
+fn undo_read(path: &Path, lines: &Vec<String>, undofile: bool, key: &str) -> UndoTree {
+    if undofile {
+        if let Some(undo_path) = undo_file_name(path, UNDO_DIRECTORY, true) {
+            if let Ok(tree) = read_undo_file(lines, &undo_path, Some(key)) { return tree; }
+        }
+    }
+    UndoTree::new()
+}
+
 pub fn run(args: &[String]) -> std::io::Result<()> {
     // initial state
-    let mut filename: Option<PathBuf> = None;
-    for a in args.iter().skip(1) { if !a.starts_with('-') { filename = Some(PathBuf::from(a)); break; } }
-    let mut lines: Vec<String> = match filename.as_ref() { Some(p) => open_file(p.as_path()), None => Vec::new() };
+    // ファイル引数がそのまま引数リストになる（ワイルドカードはシェルが展開済み）
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let mut arglist = ArgList::from_names(args.iter().skip(1).filter(|a| !a.starts_with('-')).cloned().collect());
+    let mut filename: Option<PathBuf> = arglist.get(0).map(PathBuf::from);
+    let recover = args.iter().skip(1).any(|a| a == "-r");
+    // `-r` without a file lists the swap files, like `vim -r`
+    if recover && filename.is_none() {
+        for line in rust_memline::recover_names(None, SWAP_DIRECTORY) { println!("{}", line); }
+        return Ok(());
+    }
+    let mut status: Option<String> = None;
+    let mut modified = false;
+    // バッファごとの 'fileformat' などのファイルオプション（None はアクティブ）
+    let mut file_opts: HashMap<Option<usize>, FileOptions> = HashMap::new();
+    let mut lines: Vec<String> = match filename.as_ref() {
+        Some(p) if recover => match recover_file(p) {
+            Ok((ls, changed, msg)) => { modified = changed; status = Some(msg); ls }
+            Err(msg) => { status = Some(msg); let (ls, o, _) = open_file(p, &FileArgs::default(), &FileOptions::default()); file_opts.insert(None, o); ls }
+        },
+        Some(p) => { let (ls, o, msg) = open_file(p, &FileArgs::default(), &FileOptions::default()); file_opts.insert(None, o); status = msg; ls }
+        None => Vec::new(),
+    };
     if lines.is_empty() { lines.push(String::new()); }
     let mut cx: usize = 0;
     let mut cy: usize = 0;
     let mut scroll: usize = 0;
-    let mut modified = false;
     // 保持用バッファリスト（アクティブは直下の lines/filename/modified）
     let mut buffers: Vec<Buffer> = Vec::new();
-    let mut status: Option<String> = None;
-    let mut mode = Mode::Normal;
+    // 暗号化されたファイルはキーを聞いてから読み直す
+    let mut key_prompt = match filename.as_ref() { Some(p) if !recover && needs_key(p, &FileOptions::default()) => Some(KeyPrompt::Read(p.clone(), None)), _ => None };
+    let mut mode = if key_prompt.is_some() { Mode::Key } else { Mode::Normal };
     let mut cmdline: String = String::new(); // used for :cmd and /search
     let mut tabstop: usize = 4;
     let mut search = SearchState::new();
@@ -231,26 +452,86 @@ pub fn run(args: &[String]) -> std::io::Result<()> {
     let mut last_pat: String = String::new();
     let mut last_repl: String = String::new();
     let mut last_flags: String = String::new();
-    // 単純な1段階のUndo
+    // 変更前のテキスト。次のループで undo ツリーに保存する
     let mut undo_snap: Option<UndoSnap> = None;
+    // バッファごとの undo ツリー（None はアクティブ）
+    let mut undo_trees: HashMap<Option<usize>, UndoTree> = HashMap::new();
+    let mut undofile = false;
 
     // window splits (logical only for now; rendering is single view)
-    let mut views: Vec<View> = vec![View { kind: ViewKind::Normal, cx, cy, scroll, buf: None }];
+    let mut views: Vec<View> = vec![View { kind: ViewKind::Normal, cx, cy, scroll, buf: None, args: WindowArgs::default() }];
     let mut cur_view: usize = 0;
     let mut layout = SplitLayout::Horizontal;
     let mut last_normal_view: usize = 0;
+    // `:argdo` で実行待ちのコマンド
+    let mut pending_cmds: VecDeque<String> = VecDeque::new();
+    // Vim と同じ番号と状態のバッファリスト。スロット（None はアクティブ）ごとのバッファ番号
+    let mut buflist = BufferList::new();
+    let mut buf_nrs: HashMap<Option<usize>, usize> = HashMap::new();
+    buf_nrs.insert(None, buflist.add(&filename.as_ref().map(|p| p.display().to_string()).unwrap_or_default(), true));
+    buflist.enter(buf_nrs[&None]);
+    let mut hidden = false;
+    // 次のループで行うバッファの切り替え: (ウィンドウ, バッファ番号, 変更を捨てる)
+    let mut buf_switches: Vec<(usize, usize, bool)> = Vec::new();
+    // `:ls` の出力（一覧ビューに表示する）
+    let mut ls_lines: Vec<String> = Vec::new();
+    // エディタ自身が扱わないコマンド（:let, :call, :source など）を実行し、timer_start() のタイマーを持つ
+    let mut ev = Evaluator::new();
+    let mut ev_seen: usize = 0;
 
     // setup terminal
-    terminal::enable_raw_mode().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    terminal::enable_raw_mode().map_err(std::io::Error::other)?;
     let mut stdout = std::io::stdout();
-    execute!(stdout, crossterm::terminal::EnterAlternateScreen, crossterm::cursor::Hide).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    execute!(stdout, crossterm::terminal::EnterAlternateScreen, crossterm::cursor::Hide).map_err(std::io::Error::other)?;
     let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let mut terminal = Terminal::new(backend).map_err(std::io::Error::other)?;
 
     loop {
+        // A change was made: save the text before it as an undo block.
+        if let Some(mut snap) = undo_snap.take() {
+            let tree = undo_trees.entry(snap.buf).or_default();
+            let count = snap.lines.len();
+            let text = UndoText { lines: &mut snap.lines, cursor: (snap.cy + 1, snap.cx + 1) };
+            tree.save(&text, 0, count + 1);
+            tree.sync(&text);
+        }
+        // バッファの切り替え（:b, :bn, CTRL-^ など）。離れるバッファは 'hidden' と 'bufhidden' に従う
+        for (win, nr, force) in std::mem::take(&mut buf_switches) {
+            let Some(to) = slot_of(&buf_nrs, nr) else { status = Some(format!("E86: Buffer {} does not exist", nr)); continue };
+            match switch_buffer(&mut buflist, &buf_nrs, &mut views, win, to, hidden, force) {
+                Err(e) => status = Some(e),
+                Ok(read) => {
+                    if read { status = read_slot(to, &mut buffers, &mut lines, &filename, &mut file_opts, &mut undo_trees, undofile); }
+                    let len = with_active_ro(&buffers, to, &lines, |l| l.len());
+                    let lnum = buflist.get(nr).map_or(1, |b| b.lnum);
+                    let v = &mut views[win];
+                    v.cy = (lnum.max(1) - 1).min(len.saturating_sub(1)); v.cx = 0; v.scroll = v.cy;
+                    if win == cur_view { cx = v.cx; cy = v.cy; scroll = v.scroll; }
+                }
+            }
+        }
+        // バッファリストを各スロットに合わせる。アンロードされて表示されていないバッファのテキストは捨てる
+        for (&slot, &nr) in &buf_nrs {
+            let shown = views.iter().filter(|v| v.kind == ViewKind::Normal && v.buf == slot).count();
+            let (text, changed, name) = match slot { Some(bi) => match buffers.get_mut(bi) { Some(b) => (&mut b.lines, &mut b.modified, &b.filename), None => continue }, None => (&mut lines, &mut modified, &filename) };
+            let entry = buflist.get_mut(nr);
+            if shown == 0 && !entry.as_ref().is_some_and(|b| b.loaded) && (*changed || text.len() > 1 || !text[0].is_empty()) {
+                *text = vec![String::new()]; *changed = false; undo_trees.remove(&slot);
+            }
+            if let Some(b) = entry {
+                b.changed = *changed; b.windows = shown;
+                b.name = name.as_ref().map(|p| p.display().to_string()).unwrap_or_default();
+                if slot == views[cur_view].buf { b.lnum = cy + 1; }
+            }
+        }
+        buf_nrs.retain(|_, nr| buflist.get(*nr).is_some());
+        if views[cur_view].kind == ViewKind::Normal { if let Some(&nr) = buf_nrs.get(&views[cur_view].buf) { buflist.set_current(nr); } }
+        // 期限が来たタイマーのコールバックを実行する。コマンドとコールバックの最後のメッセージを表示する
+        let next_timer = ev.run_timers();
+        if ev.output().len() > ev_seen { status = ev.output().last().cloned(); ev_seen = ev.output().len(); }
         terminal.draw(|f| {
             let size = f.size();
-            let show_cmd = matches!(mode, Mode::Command | Mode::SearchFwd | Mode::SearchBwd);
+            let show_cmd = matches!(mode, Mode::Command | Mode::SearchFwd | Mode::SearchBwd | Mode::Key);
             let content_rows = if show_cmd { size.height.saturating_sub(2) } else { size.height.saturating_sub(1) };
             let chunks = if show_cmd {
                 Layout::default().direction(Direction::Vertical)
@@ -317,13 +598,10 @@ pub fn run(args: &[String]) -> std::io::Result<()> {
                         let header = Line::from("Buffers:");
                         text.lines.push(header);
                         let mut start = v.scroll;
-                        for (idx, b) in buffers.iter().enumerate().skip(start) {
+                        for line in ls_lines.iter().skip(start) {
                             if text.lines.len() >= view_rows { break; }
-                            let name = b.filename.as_ref().map(|p| p.to_string_lossy().to_string()).unwrap_or_else(|| "[No Name]".into());
-                            let mark = if b.modified { '+' } else { ' ' };
-                            let line = format!(" {:>3} {} {}", idx, mark, name);
-                            if v.cy == (text.lines.len()) { text.lines.push(Line::from(Span::styled(line, sel_style))); }
-                            else { text.lines.push(Line::from(line)); }
+                            if v.cy == (text.lines.len()) { text.lines.push(Line::from(Span::styled(line.clone(), sel_style))); }
+                            else { text.lines.push(Line::from(line.clone())); }
                         }
                         while text.lines.len() < view_rows { text.lines.push(Line::from("~")); }
                     }
@@ -332,10 +610,14 @@ pub fn run(args: &[String]) -> std::io::Result<()> {
                             "Rust TUI Vim (mini) Help",
                             "",
                             ":e {file} / :e! {file} / :w / :wq / :q / :q!",
-                            ":badd {file} / :bn / :bp / :buffer {n} / :buffers",
+                            ":badd {file} / :bn / :bp / :buffer {n|name} / :ls[!] [flags] / CTRL-^",
+                            ":bdelete[!] / :bwipeout[!] / :bunload[!] [n|name] / :set hidden / :set buftype= bufhidden=",
+                            ":args {files} / :argadd / :argdelete {pat} / :next / :prev / :first / :last / :argdo {cmd}",
                             ":split / :vsplit / :only / :close / :wincmd w (Ctrl-W w)",
+                            ":mksession[!] {file} / :mkview {nr} / :loadview {nr}",
                             ":read {file} / :write [range] {file}",
                             ":%s/pat/repl/[g][i]  (:& / :&& で再実行)",
+                            ":let / :call / :echo / :source など (timer_start() のタイマーも動く)",
                             "検索: /pattern (?pattern) / n / N  (\\c:ignore, \\C:match)",
                             "モード: Normal / Insert / Visual(v/V) / Command(:)",
                             "操作: h j k l / 0 $ gg G / i a I A / o O / x J / dd yy cc / p P / D Y / u / .",
@@ -357,7 +639,7 @@ pub fn run(args: &[String]) -> std::io::Result<()> {
             // status
             let name = filename.as_ref().map(|p| p.to_string_lossy().to_string()).unwrap_or_else(|| "[No Name]".to_string());
             let m = if modified { " [+]" } else { "" };
-            let mode_tag = match mode { Mode::Normal => "[N]", Mode::Insert => "[I]", Mode::Command => ":", Mode::SearchFwd => "/", Mode::SearchBwd => "?", Mode::VisualChar => "[V]", Mode::VisualLine => "[VL]" };
+            let mode_tag = match mode { Mode::Normal => "[N]", Mode::Insert => "[I]", Mode::Command => ":", Mode::SearchFwd => "/", Mode::SearchBwd => "?", Mode::VisualChar => "[V]", Mode::VisualLine => "[VL]", Mode::Key => ":" };
             let right = status.clone().unwrap_or_default();
             let status_line = Line::from(vec![
                 Span::raw(format!(" {} {} - {}:{}{} ", mode_tag, name, cy + 1, cx + 1, m)),
@@ -368,12 +650,18 @@ pub fn run(args: &[String]) -> std::io::Result<()> {
 
             // command/search line
             if show_cmd {
-                let prompt = match mode { Mode::Command => ':', Mode::SearchFwd => '/', Mode::SearchBwd => '?', _ => ':' };
-                let cmd_p = Paragraph::new(Line::from(format!("{}{}", prompt, cmdline))).style(Style::default());
+                let prompt = match mode { Mode::Command => ":", Mode::SearchFwd => "/", Mode::SearchBwd => "?", _ => ":" };
+                // キーは表示しない
+                let (prompt, shown) = match (mode, &key_prompt) {
+                    (Mode::Key, Some(KeyPrompt::Confirm(_))) => ("Enter same key again: ", "*".repeat(cmdline.chars().count())),
+                    (Mode::Key, _) => ("Enter encryption key: ", "*".repeat(cmdline.chars().count())),
+                    _ => (prompt, cmdline.clone()),
+                };
+                let cmd_p = Paragraph::new(Line::from(format!("{}{}", prompt, shown))).style(Style::default());
                 f.render_widget(cmd_p, chunks[1]);
                 // place cursor at cmdline end
                 let Rect { x, y, .. } = chunks[1];
-                let pos = (x + 1 + cmdline.len() as u16, y);
+                let pos = (x + prompt.len() as u16 + shown.len() as u16, y);
                 f.set_cursor(pos.0, pos.1);
             } else {
                 // place cursor in content area of current view
@@ -399,536 +687,736 @@ pub fn run(args: &[String]) -> std::io::Result<()> {
                 let cur_x = x + (v.cx as u16);
                 f.set_cursor(cur_x, cur_y);
             }
-        }).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-        // input
-        if event::poll(Duration::from_millis(250)).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))? {
-            match event::read().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))? {
-                Event::Key(KeyEvent { code, modifiers, .. }) => {
-                    if matches!(mode, Mode::Command | Mode::SearchFwd | Mode::SearchBwd) {
-                        match (code, modifiers) {
-                            (KeyCode::Esc, _) => { mode = Mode::Normal; cmdline.clear(); }
-                            ,(KeyCode::Enter, _) => {
-                                if mode == Mode::SearchFwd || mode == Mode::SearchBwd {
-                                    // build regex from cmdline and jump
-                                    let raw = cmdline.clone();
-                                    let pat = raw.as_str();
-                                    let mut case_insensitive = false;
-                                    // support \c (ignore case) and \C (match case)
-                                    if pat.contains("\\c") { case_insensitive = true; }
-                                    if pat.contains("\\C") { case_insensitive = false; }
-                                    let mut pat_clean = pat.replace("\\c", "");
-                                    pat_clean = pat_clean.replace("\\C", "");
-                                    let mut builder = RegexBuilder::new(&pat_clean);
-                                    if case_insensitive { builder.case_insensitive(true); }
-                                    match builder.build() {
-                                        Ok(re) => {
-                                            search.regex = Some(re);
-                                            search.pattern = pat_clean;
-                                            search.case_insensitive = case_insensitive;
-                                            search.last_dir = if mode == Mode::SearchBwd { -1 } else { 1 };
-                                            // jump to first match
-                                            if let Some((ny, nx)) = find_next(&lines, cy, cx, &search, search.last_dir) { cy = ny; cx = nx; }
-                                        }
-                                        Err(e) => { status = Some(format!("regex error: {}", e)); }
-                                    }
-                                    cmdline.clear(); mode = Mode::Normal;
-                                    continue;
+        }).map_err(std::io::Error::other)?;
+
+        // input: `:argdo` のコマンドはキー入力の代わりに一つずつ実行し、エラーで止める
+        if status.as_deref().is_some_and(|s| s.starts_with('E') && s[1..].starts_with(|c: char| c.is_ascii_digit())) { pending_cmds.clear(); }
+        let queued = pending_cmds.pop_front();
+        // キーを待つのは次のタイマーの期限まで。キーが来たらすぐ処理する
+        let wait = next_timer.map_or(250, |ms| ms.clamp(1, 250));
+        if queued.is_some() || event::poll(Duration::from_millis(wait)).map_err(std::io::Error::other)? {
+            let input = match queued {
+                Some(c) => { mode = Mode::Command; cmdline = c; Event::Key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)) }
+                None => event::read().map_err(std::io::Error::other)?,
+            };
+            if let Event::Key(KeyEvent { code, modifiers, .. }) = input {
+                if matches!(mode, Mode::Command | Mode::SearchFwd | Mode::SearchBwd | Mode::Key) {
+                    match (code, modifiers) {
+                        (KeyCode::Esc, _) => { mode = Mode::Normal; cmdline.clear(); key_prompt = None; }
+                        ,(KeyCode::Enter, _) if mode == Mode::Key => {
+                            let key = std::mem::take(&mut cmdline);
+                            mode = Mode::Normal;
+                            match key_prompt.take() {
+                                // 空のキーは暗号化をやめる
+                                Some(KeyPrompt::Set) if !key.is_empty() => { key_prompt = Some(KeyPrompt::Confirm(key)); mode = Mode::Key; }
+                                Some(KeyPrompt::Confirm(first)) if first != key => { status = Some("Keys don't match!".into()); }
+                                Some(KeyPrompt::Set) | Some(KeyPrompt::Confirm(_)) => {
+                                    let bi = views[cur_view].buf;
+                                    file_opts.entry(bi).or_default().key = key;
+                                    match bi.and_then(|bi| buffers.get_mut(bi)) { Some(b) => b.modified = true, None => modified = true }
                                 }
-                                let cmd = cmdline.trim();
-                                if cmd == "q" || cmd == ":q" { if modified { status = Some("No write since last change (:q! to quit)".into()); } else { break; } }
-                                else if cmd == "q!" || cmd == ":q!" { break; }
-                                else if cmd.starts_with("wq") || cmd.starts_with(":wq") {
-                                    if let Some(ref p) = filename { let _ = save_file(p, &lines); break; } else { status = Some("No file name".into()); }
+                                Some(KeyPrompt::Read(p, bi)) => {
+                                    let mut o = file_opts.remove(&bi).unwrap_or_default(); o.key = key;
+                                    let (new_lines, o, msg) = open_file(&p, &FileArgs::default(), &o);
+                                    let target = match bi.and_then(|bi| buffers.get_mut(bi)) { Some(b) => &mut b.lines, None => &mut lines };
+                                    *target = if new_lines.is_empty() { vec![String::new()] } else { new_lines };
+                                    undo_trees.insert(bi, undo_read(&p, target, undofile, &o.key));
+                                    file_opts.insert(bi, o);
+                                    status = msg;
                                 }
-                                else if cmd.starts_with("w") || cmd.starts_with(":w") {
-                                    let parts: Vec<&str> = cmd.trim_start_matches(':').split_whitespace().collect();
-                                    let active_bi = views[cur_view].buf;
-                                    if let Some(bi) = active_bi {
-                                        if let Some(b) = buffers.get_mut(bi) {
-                                            if parts.len() >= 2 { b.filename = Some(PathBuf::from(parts[1])); }
-                                            if let Some(ref p) = b.filename { match save_file(p, &b.lines) { Ok(_) => { b.modified = false; status = Some("written".into()); }, Err(_) => status = Some("write error".into()) } } else { status = Some("No file name".into()); }
-                                        }
-                                    } else {
-                                        if parts.len() >= 2 { filename = Some(PathBuf::from(parts[1])); }
-                                        if let Some(ref p) = filename { match save_file(p, &lines) { Ok(_) => { modified = false; status = Some("written".into()); }, Err(_) => status = Some("write error".into()) } } else { status = Some("No file name".into()); }
+                                None => {}
+                            }
+                        }
+                        ,(KeyCode::Enter, _) => {
+                            if mode == Mode::SearchFwd || mode == Mode::SearchBwd {
+                                // build regex from cmdline and jump
+                                let raw = cmdline.clone();
+                                let pat = raw.as_str();
+                                let mut case_insensitive = false;
+                                // support \c (ignore case) and \C (match case)
+                                if pat.contains("\\c") { case_insensitive = true; }
+                                if pat.contains("\\C") { case_insensitive = false; }
+                                let mut pat_clean = pat.replace("\\c", "");
+                                pat_clean = pat_clean.replace("\\C", "");
+                                let mut builder = RegexBuilder::new(&pat_clean);
+                                if case_insensitive { builder.case_insensitive(true); }
+                                match builder.build() {
+                                    Ok(re) => {
+                                        search.regex = Some(re);
+                                        search.pattern = pat_clean;
+                                        search.case_insensitive = case_insensitive;
+                                        search.last_dir = if mode == Mode::SearchBwd { -1 } else { 1 };
+                                        // jump to first match
+                                        if let Some((ny, nx)) = find_next(&lines, cy, cx, &search, search.last_dir) { cy = ny; cx = nx; }
                                     }
+                                    Err(e) => { status = Some(format!("regex error: {}", e)); }
                                 }
-                                else if cmd.starts_with(":badd ") || cmd.starts_with("badd ") {
-                                    let parts: Vec<&str> = cmd.split_whitespace().collect();
-                                    if parts.len() >= 2 {
-                                        let p = PathBuf::from(parts[1]);
-                                        let mut l = open_file(&p);
-                                        if l.is_empty(){ l.push(String::new()); }
-                                        buffers.push(Buffer{ lines: l, filename: Some(p), modified: false });
-                                        status = Some(format!("badd: #{}", buffers.len()-1));
-                                    } else { status = Some("badd: missing file".into()); }
-                                }
-                                else if cmd == ":bn" || cmd == "bn" {
-                                    if buffers.is_empty() { status = Some("no buffers".into()); } else { let v=&mut views[cur_view]; let idx=v.buf.unwrap_or(usize::MAX); v.buf=Some(if idx==usize::MAX {0} else {(idx+1)%buffers.len()}); cx=0; cy=0; scroll=0; }
-                                }
-                                else if cmd == ":bp" || cmd == "bp" {
-                                    if buffers.is_empty() { status = Some("no buffers".into()); } else { let v=&mut views[cur_view]; let idx=v.buf.unwrap_or(0); v.buf=Some((idx+buffers.len()-1)%buffers.len()); cx=0; cy=0; scroll=0; }
-                                }
-                                else if cmd.starts_with(":buffer ") || cmd.starts_with("buffer ") || cmd.starts_with(":b ") || cmd.starts_with("b ") {
-                                    let parts: Vec<&str> = cmd.split_whitespace().collect(); if parts.len()>=2 { if let Ok(n)=parts[1].parse::<usize>() { if n<buffers.len() { views[cur_view].buf=Some(n); cx=0; cy=0; scroll=0; } else { status=Some("buffer: out of range".into()); } } }
-                                }
-                                else if cmd == ":buffers" || cmd == "buffers" || cmd == ":ls" || cmd == "ls" {
-                                    // 記録: 呼び出し元のビュー
-                                    last_normal_view = cur_view;
-                                    views.push(View { kind: ViewKind::BuffersList, cx: 1, cy: 1, scroll: 0, buf: None });
-                                    cur_view = views.len() - 1;
-                                }
-                                else if cmd == "&" || cmd == ":&" || cmd == "&&" || cmd == ":&&" {
-                                    if last_pat.is_empty() {
-                                        status = Some("no previous substitute".into());
-                                    } else {
-                                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ let len=b.lines.len(); let range= if cmd.ends_with("&&") {(1,len)} else {(cy+1,cy+1)}; match substitute_lines(&mut b.lines, range, &last_pat, &last_repl, &last_flags){ Ok(n)=>{ if n>0 { b.modified=true; } status=Some(format!("substitutions: {}", n)); }, Err(e)=> status=Some(e) } } else { let len=lines.len(); let range= if cmd.ends_with("&&") {(1,len)} else {(cy+1,cy+1)}; match substitute_lines(&mut lines, range, &last_pat, &last_repl, &last_flags){ Ok(n)=>{ if n>0 { modified=true; } status=Some(format!("substitutions: {}", n)); }, Err(e)=> status=Some(e) } } }
-                                        else { let len=lines.len(); let range= if cmd.ends_with("&&") {(1,len)} else {(cy+1,cy+1)}; match substitute_lines(&mut lines, range, &last_pat, &last_repl, &last_flags){ Ok(n)=>{ if n>0 { modified=true; } status=Some(format!("substitutions: {}", n)); }, Err(e)=> status=Some(e) } }
+                                cmdline.clear(); mode = Mode::Normal;
+                                continue;
+                            }
+                            let cmd = cmdline.trim();
+                            // 引数リストのコマンド（:args, :next など）。引数を開くのは `:e` と同じ
+                            let buf_name = view_buffer_name(views[cur_view].buf, &buffers, &filename);
+                            let mut arg_cmd = rust_arglist::execute(cmd, &mut arglist, &mut views[cur_view].args, &buf_name, &cwd);
+                            let arg_edit = match &arg_cmd { Some(Ok(ArgAction::Edit { idx, name, bang })) => Some((*idx, PathBuf::from(name), *bang)), _ => None };
+                            // バッファリストのコマンド（:ls, :badd, :bdelete, :bwipeout, :bunload）
+                            let mut buf_cmd = if arg_cmd.is_none() { rust_buffer::execute(cmd, &mut buflist) } else { None };
+                            if cmd == "q" || cmd == ":q" { if modified { status = Some("No write since last change (:q! to quit)".into()); } else { break; } }
+                            else if cmd == "q!" || cmd == ":q!" { break; }
+                            else if cmd.starts_with("wq") || cmd.starts_with(":wq") {
+                                if let Some(ref p) = filename { let o = file_opts.entry(None).or_default(); if save_file(p, &lines, o, &FileArgs::default()).is_ok() { undo_written(undo_trees.entry(None).or_default(), p, &lines, undofile, &o.key); } break; } else { status = Some("No file name".into()); }
+                            }
+                            else if cmd.starts_with("w") || cmd.starts_with(":w") {
+                                // `:w[!] [++ff=dos] [>>] [file]`, `:w !cmd`
+                                let rest = cmd.trim_start_matches(':');
+                                let rest = rest.strip_prefix("write").or_else(|| rest.strip_prefix('w')).unwrap_or(rest);
+                                let (bang, rest) = match rest.strip_prefix('!') { Some(r) => (true, r), None => (false, rest) };
+                                match WriteCmd::parse(rest.trim()) {
+                                    Err(e) => status = Some(e),
+                                    Ok(wcmd) if wcmd.target == WriteTarget::Buffer && buf_nrs.get(&views[cur_view].buf).and_then(|nr| buflist.get(*nr)).is_some_and(|b| !b.buftype.can_write()) => {
+                                        status = Some("E382: Cannot write, 'buftype' option is set".into());
                                     }
-                                }
-                                else if cmd.starts_with('s') || cmd.starts_with(":s") || cmd.contains('s') && cmd.find('s').unwrap_or(usize::MAX) < 6 {
-                                    if let Some((range_str, pat, repl_raw, flags)) = parse_substitute(cmd) {
-                                        let repl = convert_repl_to_rust(&repl_raw, &last_repl);
-                                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ let (start,end)= if let Some(rs)=range_str { parse_range(&rs, b.lines.len(), cy).unwrap_or((cy+1,cy+1)) } else { (cy+1,cy+1) }; match substitute_lines(&mut b.lines, (start,end), &pat, &repl, &flags){ Ok(n)=>{ if n>0 { b.modified=true; } status=Some(format!("substitutions: {}", n)); last_pat=pat; last_repl=repl_raw; last_flags=flags; }, Err(e)=> status=Some(e) } } else { let (start,end)= if let Some(rs)=range_str { parse_range(&rs, lines.len(), cy).unwrap_or((cy+1,cy+1)) } else { (cy+1,cy+1) }; match substitute_lines(&mut lines, (start,end), &pat, &repl, &flags){ Ok(n)=>{ if n>0 { modified=true; } status=Some(format!("substitutions: {}", n)); last_pat=pat; last_repl=repl_raw; last_flags=flags; }, Err(e)=> status=Some(e) } } }
-                                        else { let (start,end)= if let Some(rs)=range_str { parse_range(&rs, lines.len(), cy).unwrap_or((cy+1,cy+1)) } else { (cy+1,cy+1) }; match substitute_lines(&mut lines, (start,end), &pat, &repl, &flags){ Ok(n)=>{ if n>0 { modified=true; } status=Some(format!("substitutions: {}", n)); last_pat=pat; last_repl=repl_raw; last_flags=flags; }, Err(e)=> status=Some(e) } }
+                                    Ok(wcmd) => {
+                                        let active_bi = views[cur_view].buf;
+                                        let (b_lines, b_name, b_modified, key) = match active_bi.and_then(|bi| buffers.get_mut(bi)) {
+                                            Some(b) => (&b.lines, &mut b.filename, &mut b.modified, active_bi),
+                                            None => (&lines, &mut filename, &mut modified, None),
+                                        };
+                                        match write_cmd(b_name.as_deref(), b_lines, file_opts.entry(key).or_default(), &wcmd, bang) {
+                                            Err(e) => status = Some(e),
+                                            Ok(w) => {
+                                                status = Some(w.messages.join(" "));
+                                                if w.reset_modified {
+                                                    if let (WriteTarget::File(f), None) = (&wcmd.target, &b_name) { *b_name = Some(PathBuf::from(f)); }
+                                                    *b_modified = false;
+                                                    let crypt_key = &file_opts.entry(key).or_default().key;
+                                                    if let Some(ref p) = b_name { if let Some(msg) = undo_written(undo_trees.entry(key).or_default(), p, b_lines, undofile, crypt_key) { status = Some(msg); } }
+                                                }
+                                            }
+                                        }
                                     }
                                 }
-                                else if cmd.starts_with("e!") || cmd.starts_with(":e!") {
-                                    let parts: Vec<&str> = cmd.trim_start_matches(':').split_whitespace().collect();
-                                    if parts.len() >= 2 {
-                                        let p = PathBuf::from(parts[1]);
-                                        let new_lines = open_file(&p);
-                                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ b.lines = if new_lines.is_empty(){ vec![String::new()] } else { new_lines }; b.filename=Some(p); b.modified=false; } }
-                                        else { lines = if new_lines.is_empty() { vec![String::new()] } else { new_lines }; filename=Some(p); modified=false; }
-                                        cx = 0; cy = 0; scroll = 0; status = Some("reloaded".into());
+                            }
+                            else if let Some(result) = buf_cmd.take() {
+                                match result {
+                                    Ok(BufAction::Message(ls)) => {
+                                        // 一覧ビュー。記録: 呼び出し元のビュー
+                                        ls_lines = ls;
+                                        last_normal_view = cur_view;
+                                        views.push(View { kind: ViewKind::BuffersList, cx: 1, cy: 1, scroll: 0, buf: None, args: WindowArgs::default() });
+                                        cur_view = views.len() - 1;
                                     }
-                                }
-                                else if cmd.starts_with("e ") || cmd.starts_with(":e ") {
-                                    if modified { status = Some("No write since last change (:e! to force)".into()); }
-                                    else {
-                                        let p = PathBuf::from(cmd.split_whitespace().nth(1).unwrap_or(""));
-                                        if !p.as_os_str().is_empty() {
-                                            let new_lines = open_file(&p);
-                                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ b.lines = if new_lines.is_empty(){ vec![String::new()] } else { new_lines }; b.filename=Some(p); b.modified=false; } }
-                                            else { lines = if new_lines.is_empty() { vec![String::new()] } else { new_lines }; filename=Some(p); modified=false; }
-                                            cx = 0; cy = 0; scroll = 0; status = Some("edited".into());
+                                    Ok(BufAction::Added(nr)) => {
+                                        // :badd はファイルを読まずにスロットだけ作る
+                                        if slot_of(&buf_nrs, nr).is_none() {
+                                            let name = buflist.get(nr).map(|b| PathBuf::from(&b.name));
+                                            buffers.push(Buffer { lines: vec![String::new()], filename: name, modified: false });
+                                            buf_nrs.insert(Some(buffers.len() - 1), nr);
+                                        }
+                                    }
+                                    Ok(BufAction::Removed { nrs, msg, .. }) => {
+                                        // 消したバッファを表示しているウィンドウは代わりのバッファ（なければ新しい空のバッファ）を表示する
+                                        status = msg;
+                                        for nr in nrs {
+                                            let slot = slot_of(&buf_nrs, nr);
+                                            let wins: Vec<usize> = (0..views.len()).filter(|&w| views[w].kind == ViewKind::Normal && Some(views[w].buf) == slot).collect();
+                                            if wins.is_empty() { continue; }
+                                            let to = match buflist.replacement(nr) {
+                                                Some(to) => to,
+                                                None => { buffers.push(Buffer { lines: vec![String::new()], filename: None, modified: false }); let to = buflist.add("", true); buf_nrs.insert(Some(buffers.len() - 1), to); to }
+                                            };
+                                            buf_switches.extend(wins.into_iter().map(|w| (w, to, true)));
                                         }
                                     }
+                                    Ok(BufAction::None) => {}
+                                    Err(e) => status = Some(e),
                                 }
-                                else if cmd.starts_with("set ") || cmd.starts_with(":set ") {
-                                    if let Some(pos) = cmd.find("ts=") { if let Ok(n) = cmd[pos+3..].trim().parse::<usize>() { tabstop = n.max(1); status = Some(format!("tabstop={}", tabstop)); } }
+                            }
+                            else if cmd == ":bn" || cmd == "bn" || cmd == ":bp" || cmd == "bp" {
+                                // 次・前のリストされたバッファ
+                                let listed: Vec<usize> = buflist.iter().filter(|b| b.listed).map(|b| b.nr).collect();
+                                let cur = buflist.current();
+                                let next = if cmd.ends_with('n') { listed.iter().find(|&&n| n > cur).or(listed.first()) } else { listed.iter().rev().find(|&&n| n < cur).or(listed.last()) };
+                                match next { Some(&nr) => if nr != cur { buf_switches.push((cur_view, nr, false)); }, None => status = Some("E85: There is no listed buffer".into()) }
+                            }
+                            else if cmd.starts_with(":buffer ") || cmd.starts_with("buffer ") || cmd.starts_with(":b ") || cmd.starts_with("b ") {
+                                // バッファ番号か名前で切り替える
+                                match buflist.lookup(cmd.split_once(' ').map_or("", |(_, a)| a).trim()) { Ok(nr) => buf_switches.push((cur_view, nr, false)), Err(e) => status = Some(e) }
+                            }
+                            else if let Some(result) = arg_cmd.take().filter(|_| arg_edit.is_none()) {
+                                match result {
+                                    Ok(ArgAction::Message(msg)) => status = Some(msg),
+                                    Ok(ArgAction::ArgDo { cmd: c, bang }) => {
+                                        status = None;
+                                        for i in 0..views[cur_view].args.list(&arglist).len() {
+                                            pending_cmds.push_back(format!("{}argument{}", i + 1, if bang { "!" } else { "" }));
+                                            pending_cmds.extend(c.split('|').map(|c| c.trim().to_string()));
+                                        }
+                                    }
+                                    Ok(_) => {}
+                                    Err(e) => status = Some(e),
                                 }
-                                else if cmd == ":split" || cmd == "split" || cmd == ":sp" || cmd == "sp" {
-                                    // add a new horizontal view (clone current)
-                                    let k = views[cur_view].kind;
-                                    views[cur_view] = View { kind: k, cx, cy, scroll, buf: views[cur_view].buf };
-                                    views.push(View { kind: ViewKind::Normal, cx, cy, scroll, buf: views[cur_view].buf });
-                                    cur_view = views.len() - 1;
-                                    layout = SplitLayout::Horizontal;
-                                    status = Some("split".into());
+                            }
+                            else if cmd == "&" || cmd == ":&" || cmd == "&&" || cmd == ":&&" {
+                                if last_pat.is_empty() {
+                                    status = Some("no previous substitute".into());
+                                } else {
+                                    if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ let len=b.lines.len(); let range= if cmd.ends_with("&&") {(1,len)} else {(cy+1,cy+1)}; match substitute_lines(&mut b.lines, range, &last_pat, &last_repl, &last_flags){ Ok(n)=>{ if n>0 { b.modified=true; } status=Some(format!("substitutions: {}", n)); }, Err(e)=> status=Some(e) } } else { let len=lines.len(); let range= if cmd.ends_with("&&") {(1,len)} else {(cy+1,cy+1)}; match substitute_lines(&mut lines, range, &last_pat, &last_repl, &last_flags){ Ok(n)=>{ if n>0 { modified=true; } status=Some(format!("substitutions: {}", n)); }, Err(e)=> status=Some(e) } } }
+                                    else { let len=lines.len(); let range= if cmd.ends_with("&&") {(1,len)} else {(cy+1,cy+1)}; match substitute_lines(&mut lines, range, &last_pat, &last_repl, &last_flags){ Ok(n)=>{ if n>0 { modified=true; } status=Some(format!("substitutions: {}", n)); }, Err(e)=> status=Some(e) } }
                                 }
-                                else if cmd == ":vsplit" || cmd == "vsplit" || cmd == ":vsp" || cmd == "vsp" {
-                                    let k = views[cur_view].kind;
-                                    views[cur_view] = View { kind: k, cx, cy, scroll, buf: views[cur_view].buf };
-                                    views.push(View { kind: ViewKind::Normal, cx, cy, scroll, buf: views[cur_view].buf });
-                                    cur_view = views.len() - 1;
-                                    layout = SplitLayout::Vertical;
-                                    status = Some("vsplit".into());
+                            }
+                            else if arg_edit.is_none() && (cmd.starts_with('s') || cmd.starts_with(":s") || cmd.contains('s') && cmd.find('s').unwrap_or(usize::MAX) < 6) {
+                                if let Some((range_str, pat, repl_raw, flags)) = parse_substitute(cmd) {
+                                    let repl = convert_repl_to_rust(&repl_raw, &last_repl);
+                                    if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ let (start,end)= if let Some(rs)=range_str { parse_range(&rs, b.lines.len(), cy).unwrap_or((cy+1,cy+1)) } else { (cy+1,cy+1) }; match substitute_lines(&mut b.lines, (start,end), &pat, &repl, &flags){ Ok(n)=>{ if n>0 { b.modified=true; } status=Some(format!("substitutions: {}", n)); last_pat=pat; last_repl=repl_raw; last_flags=flags; }, Err(e)=> status=Some(e) } } else { let (start,end)= if let Some(rs)=range_str { parse_range(&rs, lines.len(), cy).unwrap_or((cy+1,cy+1)) } else { (cy+1,cy+1) }; match substitute_lines(&mut lines, (start,end), &pat, &repl, &flags){ Ok(n)=>{ if n>0 { modified=true; } status=Some(format!("substitutions: {}", n)); last_pat=pat; last_repl=repl_raw; last_flags=flags; }, Err(e)=> status=Some(e) } } }
+                                    else { let (start,end)= if let Some(rs)=range_str { parse_range(&rs, lines.len(), cy).unwrap_or((cy+1,cy+1)) } else { (cy+1,cy+1) }; match substitute_lines(&mut lines, (start,end), &pat, &repl, &flags){ Ok(n)=>{ if n>0 { modified=true; } status=Some(format!("substitutions: {}", n)); last_pat=pat; last_repl=repl_raw; last_flags=flags; }, Err(e)=> status=Some(e) } }
                                 }
-                                else if cmd == ":only" || cmd == "only" {
-                                    if views.len() > 1 { let keep = views[cur_view].clone(); views.clear(); views.push(keep); cur_view = 0; status = Some("only".into()); }
+                            }
+                            else if arg_edit.is_some() || cmd.starts_with("e!") || cmd.starts_with(":e!") || cmd.starts_with("e ") || cmd.starts_with(":e ") {
+                                // `:e[!] [++enc=utf-8] [++ff=dos] [file]`: without a file the current one is read again
+                                let bang = arg_edit.as_ref().map_or(cmd.trim_start_matches(':').starts_with("e!"), |a| a.2);
+                                let cmd = if arg_edit.is_some() { "e" } else { cmd };
+                                let bi = views[cur_view].buf;
+                                let cur_name = match bi.and_then(|bi| buffers.get(bi)) { Some(b) => b.filename.clone(), None => filename.clone() };
+                                let cur_modified = match bi.and_then(|bi| buffers.get(bi)) { Some(b) => b.modified, None => modified };
+                                match FileArgs::parse(cmd.trim_start_matches(':').trim_start_matches("e!").trim_start_matches('e')) {
+                                    Err(e) => status = Some(e),
+                                    Ok((fargs, name)) => 'edit: {
+                                        let target = arg_edit.as_ref().map(|a| a.1.clone()).or_else(|| name.split_whitespace().next().map(PathBuf::from));
+                                        let same = target.is_none() || target == cur_name;
+                                        if same && cur_modified && !bang { status = Some("No write since last change (:e! to force)".into()); break 'edit; }
+                                        let Some(p) = target.or(cur_name.clone()) else { status = Some("E32: No file name".into()); break 'edit };
+                                        // 別のファイルは別のバッファで開く。変更のない空の無名バッファはそのまま使う
+                                        let empty = cur_name.is_none() && !cur_modified && with_active_ro(&buffers, bi, &lines, |l| l.len() == 1 && l[0].is_empty());
+                                        let mut bi = bi;
+                                        if !same && !empty {
+                                            let pname = p.display().to_string();
+                                            let found = buflist.iter().find(|b| b.name == pname).map(|b| b.nr).filter(|&nr| slot_of(&buf_nrs, nr).is_some());
+                                            let nr = match found {
+                                                Some(nr) => nr,
+                                                None => { buffers.push(Buffer { lines: vec![String::new()], filename: Some(p.clone()), modified: false }); let nr = buflist.add(&pname, true); buf_nrs.insert(Some(buffers.len() - 1), nr); nr }
+                                            };
+                                            let to = slot_of(&buf_nrs, nr).unwrap_or(bi);
+                                            match switch_buffer(&mut buflist, &buf_nrs, &mut views, cur_view, to, hidden, bang) {
+                                                Err(e) => { status = Some(e); break 'edit; }
+                                                Ok(false) => {
+                                                    // 読み込み済みの隠れバッファはそのまま表示する
+                                                    if let Some((idx, ..)) = arg_edit { views[cur_view].args.idx = idx; }
+                                                    let len = with_active_ro(&buffers, to, &lines, |l| l.len());
+                                                    cy = (buflist.get(nr).map_or(1, |b| b.lnum).max(1) - 1).min(len.saturating_sub(1)); cx = 0; scroll = cy;
+                                                    status = Some(format!("\"{}\"", p.display()));
+                                                    break 'edit;
+                                                }
+                                                Ok(true) => bi = to,
+                                            }
+                                        }
+                                        if let Some((idx, ..)) = arg_edit { views[cur_view].args.idx = idx; }
+                                        let (new_lines, o, msg) = open_file(&p, &fargs, file_opts.entry(bi).or_default());
+                                        if needs_key(&p, &o) { key_prompt = Some(KeyPrompt::Read(p.clone(), bi)); }
+                                        let crypt_key = o.key.clone();
+                                        file_opts.insert(bi, o);
+                                        if let Some(bi)=bi { if let Some(b)=buffers.get_mut(bi){ b.lines = if new_lines.is_empty(){ vec![String::new()] } else { new_lines }; undo_trees.insert(Some(bi), undo_read(&p, &b.lines, undofile, &crypt_key)); b.filename=Some(p.clone()); b.modified=false; } }
+                                        else { lines = if new_lines.is_empty() { vec![String::new()] } else { new_lines }; undo_trees.insert(None, undo_read(&p, &lines, undofile, &crypt_key)); filename=Some(p.clone()); modified=false; }
+                                        cx = 0; cy = 0; scroll = 0; status = Some(msg.unwrap_or_else(|| format!("\"{}\" [New]", p.display())));
+                                    }
                                 }
-                                else if cmd == ":close" || cmd == "close" {
-                                    if views.len() > 1 { views.remove(cur_view); cur_view = 0; let v = views[cur_view].clone(); cx = v.cx; cy = v.cy; scroll = v.scroll; status = Some("closed".into()); } else { status = Some("cannot close last window".into()); }
+                            }
+                            else if cmd.starts_with("set ") || cmd.starts_with(":set ") {
+                                if let Some(pos) = cmd.find("ts=") { if let Ok(n) = cmd[pos+3..].trim().parse::<usize>() { tabstop = n.max(1); status = Some(format!("tabstop={}", tabstop)); } }
+                                let arg = cmd.trim_start_matches(':').trim_start_matches("set").trim();
+                                if arg == "undofile" || arg == "udf" {
+                                    // Like Vim, read the undo file of an unchanged buffer right away
+                                    let bi = views[cur_view].buf;
+                                    undofile = true;
+                                    let unchanged = undo_trees.get(&bi).is_none_or(|t| t.is_empty());
+                                    let crypt_key = &file_opts.entry(bi).or_default().key;
+                                    if let Some(b) = bi.and_then(|bi| buffers.get(bi)) { if let (false, true, Some(p)) = (b.modified, unchanged, b.filename.as_ref()) { undo_trees.insert(bi, undo_read(p, &b.lines, true, crypt_key)); } }
+                                    else if let (false, true, Some(p)) = (modified, unchanged, filename.as_ref()) { undo_trees.insert(None, undo_read(p, &lines, true, crypt_key)); }
+                                    status = Some("undofile".into());
                                 }
-                                else if cmd == ":wincmd w" || cmd == "wincmd w" {
-                                    if !views.is_empty() { let k = views[cur_view].kind; let b = views[cur_view].buf; views[cur_view] = View { kind: k, cx, cy, scroll, buf: b }; cur_view = (cur_view + 1) % views.len(); let v = views[cur_view].clone(); cx = v.cx; cy = v.cy; scroll = v.scroll; }
+                                else if arg == "noundofile" || arg == "noudf" { undofile = false; status = Some("noundofile".into()); }
+                                else if arg == "hidden" || arg == "hid" || arg == "nohidden" || arg == "nohid" { hidden = !arg.starts_with("no"); status = Some(arg.to_string()); }
+                                else if let Some(result) = buf_nrs.get(&views[cur_view].buf).and_then(|&nr| buflist.set_option(nr, arg)) {
+                                    status = Some(match result { Ok(()) => arg.to_string(), Err(e) => e });
                                 }
-                                else if cmd == "help" || cmd == ":help" {
-                                    last_normal_view = cur_view;
-                                    views.push(View { kind: ViewKind::Help, cx: 0, cy: 0, scroll: 0, buf: None });
-                                    cur_view = views.len() - 1;
+                                else if let Some(result) = file_opts.entry(views[cur_view].buf).or_default().set(arg) {
+                                    status = Some(match result { Ok(msg) if msg.is_empty() => arg.to_string(), Ok(msg) => msg.trim_start().to_string(), Err(e) => e });
                                 }
-                                cmdline.clear(); mode = Mode::Normal;
                             }
-                            ,(KeyCode::Backspace, _) => { cmdline.pop(); }
-                            ,(KeyCode::Char(c), KeyModifiers::NONE) => { cmdline.push(c); }
-                            ,(KeyCode::Char('c'), KeyModifiers::CONTROL) => { mode = Mode::Normal; cmdline.clear(); }
-                            ,_ => {}
+                            else if let Some((name, arg)) = undo_command(cmd) {
+                                let bi = views[cur_view].buf;
+                                let tree = undo_trees.entry(bi).or_default();
+                                let target = match bi.and_then(|bi| buffers.get_mut(bi)) { Some(b) => { b.modified = true; &mut b.lines } None => { modified = true; &mut lines } };
+                                status = Some(if name == "undo" {
+                                    if arg.is_empty() { undo_step(tree, target, &mut cx, &mut cy, |t, text| t.undo(text, 1)) }
+                                    else if let Ok(n) = arg.parse::<i64>() { undo_step(tree, target, &mut cx, &mut cy, |t, text| t.undo_time(text, n, StepUnit::Changes, true)) }
+                                    else { format!("E474: Invalid argument: {}", arg) }
+                                } else if name == "redo" { undo_step(tree, target, &mut cx, &mut cy, |t, text| t.redo(text, 1)) }
+                                else if let Some((n, unit)) = parse_step(arg) {
+                                    let step = if name == "earlier" { -n } else { n };
+                                    undo_step(tree, target, &mut cx, &mut cy, |t, text| t.undo_time(text, step, unit, false))
+                                } else { format!("E475: Invalid argument: {}", arg) });
+                            }
+                            else if cmd == ":split" || cmd == "split" || cmd == ":sp" || cmd == "sp" {
+                                // add a new horizontal view (clone current)
+                                let k = views[cur_view].kind;
+                                views[cur_view] = View { kind: k, cx, cy, scroll, buf: views[cur_view].buf, args: std::mem::take(&mut views[cur_view].args) };
+                                views.push(View { kind: ViewKind::Normal, cx, cy, scroll, buf: views[cur_view].buf, args: views[cur_view].args.clone() });
+                                cur_view = views.len() - 1;
+                                layout = SplitLayout::Horizontal;
+                                status = Some("split".into());
+                            }
+                            else if cmd == ":vsplit" || cmd == "vsplit" || cmd == ":vsp" || cmd == "vsp" {
+                                let k = views[cur_view].kind;
+                                views[cur_view] = View { kind: k, cx, cy, scroll, buf: views[cur_view].buf, args: std::mem::take(&mut views[cur_view].args) };
+                                views.push(View { kind: ViewKind::Normal, cx, cy, scroll, buf: views[cur_view].buf, args: views[cur_view].args.clone() });
+                                cur_view = views.len() - 1;
+                                layout = SplitLayout::Vertical;
+                                status = Some("vsplit".into());
+                            }
+                            else if cmd == ":only" || cmd == "only" {
+                                if views.len() > 1 { let keep = views[cur_view].clone(); views.clear(); views.push(keep); cur_view = 0; status = Some("only".into()); }
+                            }
+                            else if cmd == ":close" || cmd == "close" {
+                                if views.len() > 1 { views.remove(cur_view); cur_view = 0; let v = views[cur_view].clone(); cx = v.cx; cy = v.cy; scroll = v.scroll; status = Some("closed".into()); } else { status = Some("cannot close last window".into()); }
+                            }
+                            else if cmd == ":wincmd w" || cmd == "wincmd w" {
+                                if !views.is_empty() { let k = views[cur_view].kind; let b = views[cur_view].buf; views[cur_view] = View { kind: k, cx, cy, scroll, buf: b, args: std::mem::take(&mut views[cur_view].args) }; cur_view = (cur_view + 1) % views.len(); let v = views[cur_view].clone(); cx = v.cx; cy = v.cy; scroll = v.scroll; }
+                            }
+                            else if let Some((name, bang, arg)) = session_command(cmd) {
+                                views[cur_view].cx = cx; views[cur_view].cy = cy; views[cur_view].scroll = scroll;
+                                let v = views[cur_view].clone();
+                                let buf_name = view_buffer_name(v.buf, &buffers, &filename);
+                                // 画面サイズはウィンドウの大きさの割合に使う
+                                let size = terminal.size().unwrap_or_default();
+                                let area = split_rect(layout, Rect::new(0, 0, size.width, size.height.saturating_sub(2)), views.len())[cur_view];
+                                status = Some(if name == "mksession" {
+                                    let session = session_of(&views, cur_view, layout, &buffers, &filename, &arglist, size);
+                                    let path = if arg.is_empty() { "Session.vim" } else { arg };
+                                    match write_script(Path::new(path), &mksession(&session, &SessionOptions::session()), bang) { Ok(()) => format!("\"{}\" written", path), Err(e) => e }
+                                } else {
+                                    let win = session_window(&v, buf_name.clone(), area);
+                                    match view_file(&buf_name, arg) {
+                                        Err(e) => e,
+                                        Ok(path) if name == "mkview" => {
+                                            let script = mkview(&win, &SessionOptions::view());
+                                            let written = match path.parent() { Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir).map_err(|_| format!("E739: Cannot create directory: {}", dir.display())), _ => Ok(()) };
+                                            match written.and_then(|()| write_script(&path, &script, bang || arg.len() <= 1)) { Ok(()) => format!("\"{}\" written", path.display()), Err(e) => e }
+                                        }
+                                        Ok(path) => match std::fs::read_to_string(&path) {
+                                            Err(_) => format!("E484: Can't open file {}", path.display()),
+                                            Ok(script) => { let mut win = win; match win.load_view(&script) {
+                                                Ok(()) => { let len = with_active_ro(&buffers, v.buf, &lines, |l| l.len()); cy = (win.lnum.max(1) - 1).min(len.saturating_sub(1)); cx = win.col; scroll = (win.topline.max(1) - 1).min(cy); format!("\"{}\"", path.display()) }
+                                                Err(e) => e.to_string() } }
+                                        },
+                                    }
+                                });
+                            }
+                            else if cmd == "X" || cmd == ":X" { key_prompt = Some(KeyPrompt::Set); }
+                            else if cmd == "help" || cmd == ":help" {
+                                last_normal_view = cur_view;
+                                views.push(View { kind: ViewKind::Help, cx: 0, cy: 0, scroll: 0, buf: None, args: WindowArgs::default() });
+                                cur_view = views.len() - 1;
+                            }
+                            else if !cmd.is_empty() { let _ = ev.do_cmdline(cmd); }
+                            cmdline.clear(); mode = if key_prompt.is_some() { Mode::Key } else { Mode::Normal };
                         }
-                        continue;
+                        ,(KeyCode::Backspace, _) => { cmdline.pop(); }
+                        ,(KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => { cmdline.push(c); }
+                        ,(KeyCode::Char('c'), KeyModifiers::CONTROL) => { mode = Mode::Normal; cmdline.clear(); }
+                        ,_ => {}
                     }
+                    continue;
+                }
 
-                    // Normalモードの数値カウントを先に処理
-                    if matches!(mode, Mode::Normal) {
-                        if let KeyCode::Char(ch) = code {
-                            if ch.is_ascii_digit() {
-                                if ch == '0' {
-                                    if count.is_some() {
-                                        let v = count.get_or_insert(0);
-                                        *v = v.saturating_mul(10);
-                                        continue;
-                                    }
-                                } else {
+                // Normalモードの数値カウントを先に処理
+                if matches!(mode, Mode::Normal) {
+                    if let KeyCode::Char(ch) = code {
+                        // CTRL-6 は CTRL-^
+                        if ch.is_ascii_digit() && !modifiers.contains(KeyModifiers::CONTROL) {
+                            if ch == '0' {
+                                if count.is_some() {
                                     let v = count.get_or_insert(0);
-                                    *v = v.saturating_mul(10).saturating_add((ch as u8 - b'0') as usize);
+                                    *v = v.saturating_mul(10);
                                     continue;
                                 }
+                            } else {
+                                let v = count.get_or_insert(0);
+                                *v = v.saturating_mul(10).saturating_add((ch as u8 - b'0') as usize);
+                                continue;
                             }
                         }
                     }
+                }
 
-                    // まず 2キーオペレーター処理（dd/yy/cc）を先に判定
-                    if matches!(mode, Mode::Normal) {
-                        if let Some('d') = pending_op {
-                            match code {
-                                KeyCode::Char('d') => {
-                                    // dd: 現在行を削除（ヤンクはLinewiseに入れる）
-                                    let n = count.take().unwrap_or(1);
-                                    if let Some(bi) = views[cur_view].buf {
-                                        if let Some(b) = buffers.get_mut(bi) {
-                                            undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy });
-                                            let mut taken: Vec<String> = Vec::new();
-                                            for _ in 0..n { if cy < b.lines.len() { taken.push(b.lines.remove(cy)); } }
-                                            if taken.is_empty() { taken.push(String::new()); }
-                                            clipboard = Some(Register::Linewise(taken));
-                                            if cy >= b.lines.len() { if b.lines.is_empty() { b.lines.push(String::new()); } cy = b.lines.len().saturating_sub(1); }
-                                            cx = cx.min(b.lines[cy].len());
-                                            b.modified = true;
-                                        }
-                                    } else {
-                                        undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy });
+                // まず 2キーオペレーター処理（dd/yy/cc）を先に判定
+                if matches!(mode, Mode::Normal) {
+                    if let Some('d') = pending_op {
+                        match code {
+                            KeyCode::Char('d') => {
+                                // dd: 現在行を削除（ヤンクはLinewiseに入れる）
+                                let n = count.take().unwrap_or(1);
+                                if let Some(bi) = views[cur_view].buf {
+                                    if let Some(b) = buffers.get_mut(bi) {
+                                        undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy });
                                         let mut taken: Vec<String> = Vec::new();
-                                        for _ in 0..n { if cy < lines.len() { taken.push(lines.remove(cy)); } }
+                                        for _ in 0..n { if cy < b.lines.len() { taken.push(b.lines.remove(cy)); } }
                                         if taken.is_empty() { taken.push(String::new()); }
                                         clipboard = Some(Register::Linewise(taken));
-                                        if cy >= lines.len() { if lines.is_empty() { lines.push(String::new()); } cy = lines.len().saturating_sub(1); }
-                                        cx = cx.min(lines[cy].len());
-                                        modified = true;
+                                        if cy >= b.lines.len() { if b.lines.is_empty() { b.lines.push(String::new()); } cy = b.lines.len().saturating_sub(1); }
+                                        cx = cx.min(b.lines[cy].len());
+                                        b.modified = true;
                                     }
-                                    pending_op = None; op_anchor=None;
-                                    continue;
+                                } else {
+                                    undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy });
+                                    let mut taken: Vec<String> = Vec::new();
+                                    for _ in 0..n { if cy < lines.len() { taken.push(lines.remove(cy)); } }
+                                    if taken.is_empty() { taken.push(String::new()); }
+                                    clipboard = Some(Register::Linewise(taken));
+                                    if cy >= lines.len() { if lines.is_empty() { lines.push(String::new()); } cy = lines.len().saturating_sub(1); }
+                                    cx = cx.min(lines[cy].len());
+                                    modified = true;
                                 }
-                                KeyCode::Char('w') | KeyCode::Char('e') | KeyCode::Char('b') | KeyCode::Char('$') => {
-                                    if let Some((ax, ay)) = op_anchor.take() {
-                                        let n = count.take().unwrap_or(1);
-                                        let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
-                                        let (tx, ty, include_target) = match code {
-                                            KeyCode::Char('w') => { let (tx,ty)=motion_w(src, ax, ay, n); (tx,ty,false) }
-                                            ,KeyCode::Char('e') => { let (tx,ty)=motion_e(src, ax, ay, n); (tx,ty,true) }
-                                            ,KeyCode::Char('b') => { let (tx,ty)=motion_b(src, ax, ay, n); (tx,ty,true) }
-                                            ,_ => { let (tx,ty) = (src[ay].len(), ay); (tx,ty,true) }
-                                        };
-                                        // 決定範囲（charwise）
-                                        let (mut sx, mut sy, mut ex, mut ey) = if (ty < ay) || (ty == ay && tx <= ax) {
-                                            // 後方方向
-                                            let end = prev_char_pos(src, ax, ay).unwrap_or((ax, ay));
-                                            (tx, ty, end.0, end.1)
-                                        } else {
-                                            // 前方方向
-                                            let end = if include_target { (tx, ty) } else { prev_char_pos(src, tx, ty).unwrap_or((ax, ay)) };
-                                            (ax, ay, end.0, end.1)
-                                        };
-                                        // 正規化: 範囲が無効なら何もしない
-                                        if sy > ey || (sy == ey && sx > ex) { pending_op=None; continue; }
-                                        // 実行
-                                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){
-                                            undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy });
-                                            clipboard = Some(yank_selection(&b.lines, sx, sy, ex, ey, true));
-                                            delete_selection(&mut b.lines, sx, sy, ex, ey, true);
-                                            b.modified = true;
-                                        } }
-                                        else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); clipboard = Some(yank_selection(&lines, sx, sy, ex, ey, true)); delete_selection(&mut lines, sx, sy, ex, ey, true); modified = true; }
-                                        cx = sx; cy = sy; pending_op=None; continue;
-                                    }
+                                pending_op = None; op_anchor=None;
+                                continue;
+                            }
+                            KeyCode::Char('w') | KeyCode::Char('e') | KeyCode::Char('b') | KeyCode::Char('$') => {
+                                if let Some((ax, ay)) = op_anchor.take() {
+                                    let n = count.take().unwrap_or(1);
+                                    let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
+                                    let (tx, ty, include_target) = match code {
+                                        KeyCode::Char('w') => { let (tx,ty)=motion_w(src, ax, ay, n); (tx,ty,false) }
+                                        ,KeyCode::Char('e') => { let (tx,ty)=motion_e(src, ax, ay, n); (tx,ty,true) }
+                                        ,KeyCode::Char('b') => { let (tx,ty)=motion_b(src, ax, ay, n); (tx,ty,true) }
+                                        ,_ => { let (tx,ty) = (src[ay].len(), ay); (tx,ty,true) }
+                                    };
+                                    // 決定範囲（charwise）
+                                    let (mut sx, mut sy, mut ex, mut ey) = if (ty < ay) || (ty == ay && tx <= ax) {
+                                        // 後方方向
+                                        let end = prev_char_pos(src, ax, ay).unwrap_or((ax, ay));
+                                        (tx, ty, end.0, end.1)
+                                    } else {
+                                        // 前方方向
+                                        let end = if include_target { (tx, ty) } else { prev_char_pos(src, tx, ty).unwrap_or((ax, ay)) };
+                                        (ax, ay, end.0, end.1)
+                                    };
+                                    // 正規化: 範囲が無効なら何もしない
+                                    if sy > ey || (sy == ey && sx > ex) { pending_op=None; continue; }
+                                    // 実行
+                                    if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){
+                                        undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy });
+                                        clipboard = Some(yank_selection(&b.lines, sx, sy, ex, ey, true));
+                                        delete_selection(&mut b.lines, sx, sy, ex, ey, true);
+                                        b.modified = true;
+                                    } }
+                                    else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); clipboard = Some(yank_selection(&lines, sx, sy, ex, ey, true)); delete_selection(&mut lines, sx, sy, ex, ey, true); modified = true; }
+                                    cx = sx; cy = sy; pending_op=None; continue;
                                 }
-                                _ => { pending_op = None; }
                             }
-                        } else if let Some('y') = pending_op {
-                            match code {
-                                KeyCode::Char('y') => {
-                                    // yy: 行ヤンク（変更なし）
+                            _ => { pending_op = None; }
+                        }
+                    } else if let Some('y') = pending_op {
+                        match code {
+                            KeyCode::Char('y') => {
+                                // yy: 行ヤンク（変更なし）
+                                let n = count.take().unwrap_or(1);
+                                if let Some(bi) = views[cur_view].buf { if let Some(b) = buffers.get(bi) { let mut v=Vec::new(); for i in 0..n { let li=cy.saturating_add(i); if li < b.lines.len() { v.push(b.lines[li].clone()); } } if v.is_empty(){ v.push(String::new()); } clipboard = Some(Register::Linewise(v)); } }
+                                else { let mut v=Vec::new(); for i in 0..n { let li=cy.saturating_add(i); if li < lines.len() { v.push(lines[li].clone()); } } if v.is_empty(){ v.push(String::new()); } clipboard = Some(Register::Linewise(v)); }
+                                pending_op = None; op_anchor=None; continue;
+                            }
+                            KeyCode::Char('w') | KeyCode::Char('e') | KeyCode::Char('b') | KeyCode::Char('$') => {
+                                if let Some((ax, ay)) = op_anchor.take() {
                                     let n = count.take().unwrap_or(1);
-                                    if let Some(bi) = views[cur_view].buf { if let Some(b) = buffers.get(bi) { let mut v=Vec::new(); for i in 0..n { let li=cy.saturating_add(i); if li < b.lines.len() { v.push(b.lines[li].clone()); } } if v.is_empty(){ v.push(String::new()); } clipboard = Some(Register::Linewise(v)); } }
-                                    else { let mut v=Vec::new(); for i in 0..n { let li=cy.saturating_add(i); if li < lines.len() { v.push(lines[li].clone()); } } if v.is_empty(){ v.push(String::new()); } clipboard = Some(Register::Linewise(v)); }
-                                    pending_op = None; op_anchor=None; continue;
+                                    let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
+                                    let (tx, ty, include_target) = match code {
+                                        KeyCode::Char('w') => { let (tx,ty)=motion_w(src, ax, ay, n); (tx,ty,false) }
+                                        ,KeyCode::Char('e') => { let (tx,ty)=motion_e(src, ax, ay, n); (tx,ty,true) }
+                                        ,KeyCode::Char('b') => { let (tx,ty)=motion_b(src, ax, ay, n); (tx,ty,true) }
+                                        ,_ => { let (tx,ty) = (src[ay].len(), ay); (tx,ty,true) }
+                                    };
+                                    let (mut sx, mut sy, mut ex, mut ey) = if (ty < ay) || (ty == ay && tx <= ax) {
+                                        let end = prev_char_pos(src, ax, ay).unwrap_or((ax, ay));
+                                        (tx, ty, end.0, end.1)
+                                    } else {
+                                        let end = if include_target { (tx, ty) } else { prev_char_pos(src, tx, ty).unwrap_or((ax, ay)) };
+                                        (ax, ay, end.0, end.1)
+                                    };
+                                    if sy > ey || (sy == ey && sx > ex) { pending_op=None; continue; }
+                                    let reg = yank_selection(src, sx, sy, ex, ey, true);
+                                    clipboard = Some(reg);
+                                    pending_op=None; continue;
                                 }
-                                KeyCode::Char('w') | KeyCode::Char('e') | KeyCode::Char('b') | KeyCode::Char('$') => {
-                                    if let Some((ax, ay)) = op_anchor.take() {
-                                        let n = count.take().unwrap_or(1);
-                                        let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
-                                        let (tx, ty, include_target) = match code {
-                                            KeyCode::Char('w') => { let (tx,ty)=motion_w(src, ax, ay, n); (tx,ty,false) }
-                                            ,KeyCode::Char('e') => { let (tx,ty)=motion_e(src, ax, ay, n); (tx,ty,true) }
-                                            ,KeyCode::Char('b') => { let (tx,ty)=motion_b(src, ax, ay, n); (tx,ty,true) }
-                                            ,_ => { let (tx,ty) = (src[ay].len(), ay); (tx,ty,true) }
-                                        };
-                                        let (mut sx, mut sy, mut ex, mut ey) = if (ty < ay) || (ty == ay && tx <= ax) {
-                                            let end = prev_char_pos(src, ax, ay).unwrap_or((ax, ay));
-                                            (tx, ty, end.0, end.1)
-                                        } else {
-                                            let end = if include_target { (tx, ty) } else { prev_char_pos(src, tx, ty).unwrap_or((ax, ay)) };
-                                            (ax, ay, end.0, end.1)
-                                        };
-                                        if sy > ey || (sy == ey && sx > ex) { pending_op=None; continue; }
-                                        let reg = yank_selection(src, sx, sy, ex, ey, true);
-                                        clipboard = Some(reg);
-                                        pending_op=None; continue;
-                                    }
+                            }
+                            _ => { pending_op = None; }
+                        }
+                    } else if let Some('c') = pending_op {
+                        match code {
+                            KeyCode::Char('c') => {
+                                // cc: 行変更（空行にしてInsert, 削除前をヤンク）
+                                let n = count.take().unwrap_or(1);
+                                if let Some(bi) = views[cur_view].buf { if let Some(b) = buffers.get_mut(bi) {
+                                    undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy });
+                                    let mut taken: Vec<String> = Vec::new();
+                                    taken.push(std::mem::replace(&mut b.lines[cy], String::new()));
+                                    for _ in 1..n { if cy + 1 < b.lines.len() { taken.push(b.lines.remove(cy + 1)); } }
+                                    clipboard = Some(Register::Linewise(taken));
+                                    b.modified = true;
+                                } }
+                                else {
+                                    undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy });
+                                    let mut taken: Vec<String> = Vec::new();
+                                    taken.push(std::mem::replace(&mut lines[cy], String::new()));
+                                    for _ in 1..n { if cy + 1 < lines.len() { taken.push(lines.remove(cy + 1)); } }
+                                    clipboard = Some(Register::Linewise(taken));
+                                    modified = true;
                                 }
-                                _ => { pending_op = None; }
+                                cx = 0; mode = Mode::Insert; pending_op = None; visual_anchor=None; op_anchor=None; continue;
                             }
-                        } else if let Some('c') = pending_op {
-                            match code {
-                                KeyCode::Char('c') => {
-                                    // cc: 行変更（空行にしてInsert, 削除前をヤンク）
+                            KeyCode::Char('w') | KeyCode::Char('e') | KeyCode::Char('b') | KeyCode::Char('$') => {
+                                if let Some((ax, ay)) = op_anchor.take() {
                                     let n = count.take().unwrap_or(1);
-                                    if let Some(bi) = views[cur_view].buf { if let Some(b) = buffers.get_mut(bi) {
+                                    let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
+                                    // Vimのcwはce相当
+                                    let (tx, ty, include_target) = match code {
+                                        KeyCode::Char('w') => { let (tx,ty)=motion_e(src, ax, ay, n); (tx,ty,true) }
+                                        ,KeyCode::Char('e') => { let (tx,ty)=motion_e(src, ax, ay, n); (tx,ty,true) }
+                                        ,KeyCode::Char('b') => { let (tx,ty)=motion_b(src, ax, ay, n); (tx,ty,true) }
+                                        ,_ => { let (tx,ty) = (src[ay].len(), ay); (tx,ty,true) }
+                                    };
+                                    let (mut sx, mut sy, mut ex, mut ey) = if (ty < ay) || (ty == ay && tx <= ax) {
+                                        let end = prev_char_pos(src, ax, ay).unwrap_or((ax, ay));
+                                        (tx, ty, end.0, end.1)
+                                    } else {
+                                        let end = if include_target { (tx, ty) } else { prev_char_pos(src, tx, ty).unwrap_or((ax, ay)) };
+                                        (ax, ay, end.0, end.1)
+                                    };
+                                    if sy > ey || (sy == ey && sx > ex) { pending_op=None; continue; }
+                                    if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){
                                         undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy });
-                                        let mut taken: Vec<String> = Vec::new();
-                                        taken.push(std::mem::replace(&mut b.lines[cy], String::new()));
-                                        for _ in 1..n { if cy + 1 < b.lines.len() { taken.push(b.lines.remove(cy + 1)); } }
-                                        clipboard = Some(Register::Linewise(taken));
+                                        clipboard = Some(yank_selection(&b.lines, sx, sy, ex, ey, true));
+                                        delete_selection(&mut b.lines, sx, sy, ex, ey, true);
                                         b.modified = true;
                                     } }
-                                    else {
-                                        undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy });
-                                        let mut taken: Vec<String> = Vec::new();
-                                        taken.push(std::mem::replace(&mut lines[cy], String::new()));
-                                        for _ in 1..n { if cy + 1 < lines.len() { taken.push(lines.remove(cy + 1)); } }
-                                        clipboard = Some(Register::Linewise(taken));
-                                        modified = true;
-                                    }
-                                    cx = 0; mode = Mode::Insert; pending_op = None; visual_anchor=None; op_anchor=None; continue;
+                                    else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); clipboard = Some(yank_selection(&lines, sx, sy, ex, ey, true)); delete_selection(&mut lines, sx, sy, ex, ey, true); modified = true; }
+                                    cx = sx; cy = sy; mode = Mode::Insert; pending_op=None; continue;
                                 }
-                                KeyCode::Char('w') | KeyCode::Char('e') | KeyCode::Char('b') | KeyCode::Char('$') => {
-                                    if let Some((ax, ay)) = op_anchor.take() {
-                                        let n = count.take().unwrap_or(1);
-                                        let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
-                                        // Vimのcwはce相当
-                                        let (tx, ty, include_target) = match code {
-                                            KeyCode::Char('w') => { let (tx,ty)=motion_e(src, ax, ay, n); (tx,ty,true) }
-                                            ,KeyCode::Char('e') => { let (tx,ty)=motion_e(src, ax, ay, n); (tx,ty,true) }
-                                            ,KeyCode::Char('b') => { let (tx,ty)=motion_b(src, ax, ay, n); (tx,ty,true) }
-                                            ,_ => { let (tx,ty) = (src[ay].len(), ay); (tx,ty,true) }
-                                        };
-                                        let (mut sx, mut sy, mut ex, mut ey) = if (ty < ay) || (ty == ay && tx <= ax) {
-                                            let end = prev_char_pos(src, ax, ay).unwrap_or((ax, ay));
-                                            (tx, ty, end.0, end.1)
-                                        } else {
-                                            let end = if include_target { (tx, ty) } else { prev_char_pos(src, tx, ty).unwrap_or((ax, ay)) };
-                                            (ax, ay, end.0, end.1)
-                                        };
-                                        if sy > ey || (sy == ey && sx > ex) { pending_op=None; continue; }
-                                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){
-                                            undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy });
-                                            clipboard = Some(yank_selection(&b.lines, sx, sy, ex, ey, true));
-                                            delete_selection(&mut b.lines, sx, sy, ex, ey, true);
-                                            b.modified = true;
-                                        } }
-                                        else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); clipboard = Some(yank_selection(&lines, sx, sy, ex, ey, true)); delete_selection(&mut lines, sx, sy, ex, ey, true); modified = true; }
-                                        cx = sx; cy = sy; mode = Mode::Insert; pending_op=None; continue;
-                                    }
-                                }
-                                _ => { pending_op = None; }
                             }
+                            _ => { pending_op = None; }
+                        }
+                    } else if let Some('g') = pending_op {
+                        pending_op = None;
+                        match code {
+                            // gg: 先頭行へ
+                            KeyCode::Char('g') => { cy = 0; cx = 0; continue; }
+                            // g- / g+: 変更を時系列で前後に辿る（カウント対応）
+                            KeyCode::Char('-') | KeyCode::Char('+') => {
+                                let n = count.take().unwrap_or(1) as i64;
+                                let step = if code == KeyCode::Char('-') { -n } else { n };
+                                let bi = views[cur_view].buf;
+                                let tree = undo_trees.entry(bi).or_default();
+                                let target = match bi.and_then(|bi| buffers.get_mut(bi)) { Some(b) => { b.modified = true; &mut b.lines } None => { modified = true; &mut lines } };
+                                status = Some(undo_step(tree, target, &mut cx, &mut cy, |t, text| t.undo_time(text, step, StepUnit::Changes, false)));
+                                continue;
+                            }
+                            _ => {}
                         }
                     }
+                }
 
-                    // Normal/Insert modes
-                    match (code, modifiers, mode) {
-                        (KeyCode::Char(':'), _, Mode::Normal) => { mode = Mode::Command; cmdline.clear(); }
-                        ,(KeyCode::Char('/'), _, Mode::Normal) => { mode = Mode::SearchFwd; cmdline.clear(); }
-                        ,(KeyCode::Char('?'), _, Mode::Normal) => { mode = Mode::SearchBwd; cmdline.clear(); }
-                        ,(KeyCode::Char('v'), _, Mode::Normal) => { count=None; if matches!(mode, Mode::VisualChar) { mode = Mode::Normal; visual_anchor=None; } else { mode = Mode::VisualChar; visual_anchor = Some((cx, cy)); } }
-                        ,(KeyCode::Char('V'), _, Mode::Normal) => { count=None; if matches!(mode, Mode::VisualLine) { mode = Mode::Normal; visual_anchor=None; } else { mode = Mode::VisualLine; visual_anchor = Some((cx, cy)); } }
-                        ,(KeyCode::Char('i'), _, Mode::Normal) => { count=None; insert_record.clear(); mode = Mode::Insert; }
-                        ,(KeyCode::Char('a'), _, Mode::Normal) => { count=None; let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; if cx < src[cy].len() { cx += 1; } insert_record.clear(); mode = Mode::Insert; }
-                        ,(KeyCode::Char('A'), _, Mode::Normal) => { count=None; let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; cx = src[cy].len(); insert_record.clear(); mode = Mode::Insert; }
-                        ,(KeyCode::Char('I'), _, Mode::Normal) => { count=None; let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; let line = &src[cy]; let first_nb = line.chars().position(|ch| ch != ' ' && ch != '\t').unwrap_or(0); cx = first_nb; insert_record.clear(); mode = Mode::Insert; }
-                        ,(KeyCode::Char('o'), _, Mode::Normal) => {
-                            count=None; if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); b.lines.insert(cy+1, String::new()); cy += 1; cx = 0; b.modified = true; } }
-                            else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); lines.insert(cy+1, String::new()); cy += 1; cx = 0; modified = true; }
-                            insert_record.clear(); mode = Mode::Insert;
-                        }
-                        ,(KeyCode::Char('O'), _, Mode::Normal) => {
-                            count=None; if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); b.lines.insert(cy, String::new()); cx = 0; b.modified = true; } }
-                            else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); lines.insert(cy, String::new()); cx = 0; modified = true; }
-                            insert_record.clear(); mode = Mode::Insert;
-                        }
-                        ,(KeyCode::Esc, _, Mode::Insert) => { mode = Mode::Normal; last_insert = insert_record.clone(); insert_record.clear(); }
-                        ,(KeyCode::Char('h'), _, Mode::Normal) | (KeyCode::Left, _, _) => {
-                            let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
-                            let n = count.take().unwrap_or(1);
-                            for _ in 0..n { if cx>0 { cx-=1; } else if cy>0 { cy-=1; cx = src[cy].len(); } }
-                        }
-                        ,(KeyCode::Char('l'), _, Mode::Normal) | (KeyCode::Right, _, _) => {
-                            let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
-                            let n = count.take().unwrap_or(1);
-                            for _ in 0..n { if cx < src[cy].len() { cx+=1; } else if cy+1 < src.len() { cy+=1; cx=0; } }
-                        }
-                        ,(KeyCode::Char('k'), _, Mode::Normal) | (KeyCode::Up, _, _) => {
-                            let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
-                            let n = count.take().unwrap_or(1);
-                            for _ in 0..n { if cy>0 { cy-=1; cx = cx.min(src[cy].len()); } }
+                // Normal/Insert modes
+                match (code, modifiers, mode) {
+                    (KeyCode::Char(':'), _, Mode::Normal) => { mode = Mode::Command; cmdline.clear(); }
+                    ,(KeyCode::Char('^' | '6'), m, Mode::Normal) if m.contains(KeyModifiers::CONTROL) => {
+                        // CTRL-^: 代替バッファ（カウントがあればその番号のバッファ）を編集する
+                        match count.take().or_else(|| buflist.alternate()) { Some(nr) => buf_switches.push((cur_view, nr, false)), None => status = Some("E23: No alternate file".into()) }
+                    }
+                    ,(KeyCode::Char('/'), _, Mode::Normal) => { mode = Mode::SearchFwd; cmdline.clear(); }
+                    ,(KeyCode::Char('?'), _, Mode::Normal) => { mode = Mode::SearchBwd; cmdline.clear(); }
+                    ,(KeyCode::Char('v'), _, Mode::Normal) => { count=None; if matches!(mode, Mode::VisualChar) { mode = Mode::Normal; visual_anchor=None; } else { mode = Mode::VisualChar; visual_anchor = Some((cx, cy)); } }
+                    ,(KeyCode::Char('V'), _, Mode::Normal) => { count=None; if matches!(mode, Mode::VisualLine) { mode = Mode::Normal; visual_anchor=None; } else { mode = Mode::VisualLine; visual_anchor = Some((cx, cy)); } }
+                    ,(KeyCode::Char('i'), _, Mode::Normal) => { count=None; undo_snap = Some(UndoSnap { buf: views[cur_view].buf, lines: with_active_ro(&buffers, views[cur_view].buf, &lines, |ls| ls.clone()), cx, cy }); insert_record.clear(); mode = Mode::Insert; }
+                    ,(KeyCode::Char('a'), _, Mode::Normal) => { count=None; undo_snap = Some(UndoSnap { buf: views[cur_view].buf, lines: with_active_ro(&buffers, views[cur_view].buf, &lines, |ls| ls.clone()), cx, cy }); let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; if cx < src[cy].len() { cx += 1; } insert_record.clear(); mode = Mode::Insert; }
+                    ,(KeyCode::Char('A'), _, Mode::Normal) => { count=None; let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; cx = src[cy].len(); insert_record.clear(); mode = Mode::Insert; }
+                    ,(KeyCode::Char('I'), _, Mode::Normal) => { count=None; let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; let line = &src[cy]; let first_nb = line.chars().position(|ch| ch != ' ' && ch != '\t').unwrap_or(0); cx = first_nb; insert_record.clear(); mode = Mode::Insert; }
+                    ,(KeyCode::Char('o'), _, Mode::Normal) => {
+                        count=None; if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); b.lines.insert(cy+1, String::new()); cy += 1; cx = 0; b.modified = true; } }
+                        else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); lines.insert(cy+1, String::new()); cy += 1; cx = 0; modified = true; }
+                        insert_record.clear(); mode = Mode::Insert;
+                    }
+                    ,(KeyCode::Char('O'), _, Mode::Normal) => {
+                        count=None; if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); b.lines.insert(cy, String::new()); cx = 0; b.modified = true; } }
+                        else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); lines.insert(cy, String::new()); cx = 0; modified = true; }
+                        insert_record.clear(); mode = Mode::Insert;
+                    }
+                    ,(KeyCode::Esc, _, Mode::Insert) => { mode = Mode::Normal; last_insert = insert_record.clone(); insert_record.clear(); }
+                    ,(KeyCode::Char('h'), _, Mode::Normal) | (KeyCode::Left, _, _) => {
+                        let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
+                        let n = count.take().unwrap_or(1);
+                        for _ in 0..n { if cx>0 { cx-=1; } else if cy>0 { cy-=1; cx = src[cy].len(); } }
+                    }
+                    ,(KeyCode::Char('l'), _, Mode::Normal) | (KeyCode::Right, _, _) => {
+                        let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
+                        let n = count.take().unwrap_or(1);
+                        for _ in 0..n { if cx < src[cy].len() { cx+=1; } else if cy+1 < src.len() { cy+=1; cx=0; } }
+                    }
+                    ,(KeyCode::Char('k'), _, Mode::Normal) | (KeyCode::Up, _, _) => {
+                        let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
+                        let n = count.take().unwrap_or(1);
+                        for _ in 0..n { if cy>0 { cy-=1; cx = cx.min(src[cy].len()); } }
+                    }
+                    ,(KeyCode::Char('j'), _, Mode::Normal) | (KeyCode::Down, _, _) => {
+                        let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
+                        let n = count.take().unwrap_or(1);
+                        for _ in 0..n { if cy+1 < src.len() { cy+=1; cx = cx.min(src[cy].len()); } }
+                    }
+                    ,(KeyCode::Home, _, _) | (KeyCode::Char('0'), _, Mode::Normal) => { cx = 0; }
+                    ,(KeyCode::End, _, _) | (KeyCode::Char('$'), _, Mode::Normal) => { let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; cx = src[cy].len(); }
+                    ,(KeyCode::Char('G'), _, Mode::Normal) => {
+                        let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
+                        if let Some(nv) = count.take() { cy = nv.saturating_sub(1).min(src.len().saturating_sub(1)); cx = 0; }
+                        else { cy = src.len().saturating_sub(1); cx = 0; }
+                    }
+                    ,(KeyCode::Char('g'), _, Mode::Normal) => { pending_op = Some('g'); }
+                    ,(KeyCode::Char('x'), _, Mode::Normal) => {
+                        // カーソル位置の1文字削除（カウント対応）
+                        let n = count.take().unwrap_or(1);
+                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ let len=b.lines[cy].len(); let to_del = n.min(len.saturating_sub(cx)); if to_del>0 { let snapshot=b.lines.clone(); undo_snap = Some(UndoSnap { buf: Some(bi), lines: snapshot, cx, cy }); for _ in 0..to_del { b.lines[cy].remove(cx); } b.modified=true; } } }
+                        else { let len=lines[cy].len(); let to_del = n.min(len.saturating_sub(cx)); if to_del>0 { let snapshot=lines.clone(); undo_snap = Some(UndoSnap { buf: None, lines: snapshot, cx, cy }); for _ in 0..to_del { lines[cy].remove(cx); } modified=true; } }
+                    }
+                    ,(KeyCode::Char('J'), _, Mode::Normal) => {
+                        // 次の行と結合（カウント対応）
+                        let times = count.take().unwrap_or(1);
+                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ if cy+1 < b.lines.len() { undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); for _ in 0..times { if cy+1 < b.lines.len() { let next=b.lines.remove(cy+1); b.lines[cy].push_str(&next); } } b.modified=true; } } }
+                        else { if cy+1 < lines.len() { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); for _ in 0..times { if cy+1 < lines.len() { let next=lines.remove(cy+1); lines[cy].push_str(&next); } } modified=true; } }
+                    }
+                    ,(KeyCode::Char('y'), _, Mode::Normal) => { pending_op = Some('y'); op_anchor = Some((cx, cy)); }
+                    ,(KeyCode::Char('c'), _, Mode::Normal) => { pending_op = Some('c'); op_anchor = Some((cx, cy)); }
+                    ,(KeyCode::Char('d'), _, Mode::Normal) => { pending_op = Some('d'); op_anchor = Some((cx, cy)); }
+                    ,(KeyCode::Char('d'), _, m) if matches!(m, Mode::VisualChar | Mode::VisualLine) => {
+                        if let Some((ax, ay)) = visual_anchor {
+                            let (sx, sy, ex, ey) = ordered_region(ax, ay, cx, cy);
+                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ delete_selection(&mut b.lines, sx, sy, ex, ey, matches!(m, Mode::VisualChar)); b.modified=true; } }
+                            else { delete_selection(&mut lines, sx, sy, ex, ey, matches!(m, Mode::VisualChar)); modified = true; }
+                            mode = Mode::Normal; visual_anchor=None; cx = sx; cy = sy;
                         }
-                        ,(KeyCode::Char('j'), _, Mode::Normal) | (KeyCode::Down, _, _) => {
+                    }
+                    ,(KeyCode::Char('y'), _, m) if matches!(m, Mode::VisualChar | Mode::VisualLine) => {
+                        if let Some((ax, ay)) = visual_anchor {
+                            let (sx, sy, ex, ey) = ordered_region(ax, ay, cx, cy);
                             let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
-                            let n = count.take().unwrap_or(1);
-                            for _ in 0..n { if cy+1 < src.len() { cy+=1; cx = cx.min(src[cy].len()); } }
+                            clipboard = Some(yank_selection(src, sx, sy, ex, ey, matches!(m, Mode::VisualChar)));
+                            mode = Mode::Normal; visual_anchor=None;
                         }
-                        ,(KeyCode::Home, _, _) | (KeyCode::Char('0'), _, Mode::Normal) => { cx = 0; }
-                        ,(KeyCode::End, _, _) | (KeyCode::Char('$'), _, Mode::Normal) => { let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; cx = src[cy].len(); }
-                        ,(KeyCode::Char('G'), _, Mode::Normal) => {
+                    }
+                    ,(KeyCode::Char('c'), _, m) if matches!(m, Mode::VisualChar | Mode::VisualLine) => {
+                        if let Some((ax, ay)) = visual_anchor {
+                            let (sx, sy, ex, ey) = ordered_region(ax, ay, cx, cy);
                             let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
-                            if let Some(nv) = count.take() { cy = nv.saturating_sub(1).min(src.len().saturating_sub(1)); cx = 0; }
-                            else { cy = src.len().saturating_sub(1); cx = 0; }
-                        }
-                        ,(KeyCode::Char('g'), _, Mode::Normal) => { cy = 0; cx = 0; }
-                        ,(KeyCode::Char('x'), _, Mode::Normal) => {
-                            // カーソル位置の1文字削除（カウント対応）
-                            let n = count.take().unwrap_or(1);
-                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ let len=b.lines[cy].len(); let to_del = n.min(len.saturating_sub(cx)); if to_del>0 { let snapshot=b.lines.clone(); undo_snap = Some(UndoSnap { buf: Some(bi), lines: snapshot, cx, cy }); for _ in 0..to_del { b.lines[cy].remove(cx); } b.modified=true; } } }
-                            else { let len=lines[cy].len(); let to_del = n.min(len.saturating_sub(cx)); if to_del>0 { let snapshot=lines.clone(); undo_snap = Some(UndoSnap { buf: None, lines: snapshot, cx, cy }); for _ in 0..to_del { lines[cy].remove(cx); } modified=true; } }
-                        }
-                        ,(KeyCode::Char('J'), _, Mode::Normal) => {
-                            // 次の行と結合（カウント対応）
-                            let times = count.take().unwrap_or(1);
-                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ if cy+1 < b.lines.len() { undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); for _ in 0..times { if cy+1 < b.lines.len() { let next=b.lines.remove(cy+1); b.lines[cy].push_str(&next); } } b.modified=true; } } }
-                            else { if cy+1 < lines.len() { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); for _ in 0..times { if cy+1 < lines.len() { let next=lines.remove(cy+1); lines[cy].push_str(&next); } } modified=true; } }
-                        }
-                        ,(KeyCode::Char('y'), _, Mode::Normal) => { pending_op = Some('y'); op_anchor = Some((cx, cy)); }
-                        ,(KeyCode::Char('c'), _, Mode::Normal) => { pending_op = Some('c'); op_anchor = Some((cx, cy)); }
-                        ,(KeyCode::Char('d'), _, Mode::Normal) => { pending_op = Some('d'); op_anchor = Some((cx, cy)); }
-                        ,(KeyCode::Char('d'), _, m) if matches!(m, Mode::VisualChar | Mode::VisualLine) => {
-                            if let Some((ax, ay)) = visual_anchor {
-                                let (sx, sy, ex, ey) = ordered_region(ax, ay, cx, cy);
-                                if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ delete_selection(&mut b.lines, sx, sy, ex, ey, matches!(m, Mode::VisualChar)); b.modified=true; } }
-                                else { delete_selection(&mut lines, sx, sy, ex, ey, matches!(m, Mode::VisualChar)); modified = true; }
-                                mode = Mode::Normal; visual_anchor=None; cx = sx; cy = sy;
-                            }
-                        }
-                        ,(KeyCode::Char('y'), _, m) if matches!(m, Mode::VisualChar | Mode::VisualLine) => {
-                            if let Some((ax, ay)) = visual_anchor {
-                                let (sx, sy, ex, ey) = ordered_region(ax, ay, cx, cy);
-                                let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
-                                clipboard = Some(yank_selection(src, sx, sy, ex, ey, matches!(m, Mode::VisualChar)));
-                                mode = Mode::Normal; visual_anchor=None;
-                            }
-                        }
-                        ,(KeyCode::Char('c'), _, m) if matches!(m, Mode::VisualChar | Mode::VisualLine) => {
-                            if let Some((ax, ay)) = visual_anchor {
-                                let (sx, sy, ex, ey) = ordered_region(ax, ay, cx, cy);
-                                let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines };
-                                clipboard = Some(yank_selection(src, sx, sy, ex, ey, matches!(m, Mode::VisualChar)));
-                                if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ delete_selection(&mut b.lines, sx, sy, ex, ey, matches!(m, Mode::VisualChar)); b.modified=true; } }
-                                else { delete_selection(&mut lines, sx, sy, ex, ey, matches!(m, Mode::VisualChar)); modified=true; }
-                                mode = Mode::Insert; visual_anchor=None; cx = sx; cy = sy;
-                            }
-                        }
-                        ,(KeyCode::Char('p'), _, Mode::Normal) => {
-                            let times = count.take().unwrap_or(1);
-                            if let Some(orig) = clipboard.clone() {
-                                if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); for _ in 0..times { let reg = orig.clone(); match reg { Register::Charwise(s)=>{ for ch in s.chars(){ b.lines[cy].insert(cx, ch); cx+=1; } }, Register::Linewise(mut ls)=>{ let insert_at=cy+1; for (i,l) in ls.drain(..).enumerate(){ b.lines.insert(insert_at+i, l);} cy=insert_at; cx=0; } } } b.modified=true; } }
-                                else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); for _ in 0..times { let reg = orig.clone(); match reg { Register::Charwise(s)=>{ for ch in s.chars(){ lines[cy].insert(cx, ch); cx+=1; } }, Register::Linewise(mut ls)=>{ let insert_at=cy+1; for (i,l) in ls.drain(..).enumerate(){ lines.insert(insert_at+i, l);} cy=insert_at; cx=0; } } } modified=true; }
-                            }
-                        }
-                        ,(KeyCode::Char('P'), _, Mode::Normal) => {
-                            let times = count.take().unwrap_or(1);
-                            if let Some(orig) = clipboard.clone() {
-                                if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); for _ in 0..times { let reg = orig.clone(); match reg { Register::Charwise(s)=>{ let mut off=0; for ch in s.chars(){ b.lines[cy].insert(cx.saturating_sub(off+1), ch); off+=1; } }, Register::Linewise(mut ls)=>{ let insert_at=cy; for (i,l) in ls.drain(..).enumerate(){ b.lines.insert(insert_at+i, l);} cy=insert_at; cx=0; } } } b.modified=true; } }
-                                else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); for _ in 0..times { let reg = orig.clone(); match reg { Register::Charwise(s)=>{ let mut off=0; for ch in s.chars(){ lines[cy].insert(cx.saturating_sub(off+1), ch); off+=1; } }, Register::Linewise(mut ls)=>{ let insert_at=cy; for (i,l) in ls.drain(..).enumerate(){ lines.insert(insert_at+i, l);} cy=insert_at; cx=0; } } } modified=true; }
-                            }
-                        }
-                        ,(KeyCode::Char('D'), _, Mode::Normal) => {
-                            // 行末まで削除（d$ 相当）
-                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ if cx < b.lines[cy].len() { undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); b.lines[cy].replace_range(cx.., ""); b.modified = true; } } }
-                            else { if cx < lines[cy].len() { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); lines[cy].replace_range(cx.., ""); modified = true; } }
+                            clipboard = Some(yank_selection(src, sx, sy, ex, ey, matches!(m, Mode::VisualChar)));
+                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ delete_selection(&mut b.lines, sx, sy, ex, ey, matches!(m, Mode::VisualChar)); b.modified=true; } }
+                            else { delete_selection(&mut lines, sx, sy, ex, ey, matches!(m, Mode::VisualChar)); modified=true; }
+                            mode = Mode::Insert; visual_anchor=None; cx = sx; cy = sy;
                         }
-                        ,(KeyCode::Char('Y'), _, Mode::Normal) => {
-                            // 行ヤンク（yy 相当、カウント対応）
-                            let n = count.take().unwrap_or(1);
-                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get(bi){ let mut v=Vec::new(); for i in 0..n { let li = cy.saturating_add(i); if li < b.lines.len() { v.push(b.lines[li].clone()); } } if v.is_empty(){ v.push(String::new()); } clipboard = Some(Register::Linewise(v)); } }
-                            else { let mut v=Vec::new(); for i in 0..n { let li = cy.saturating_add(i); if li < lines.len() { v.push(lines[li].clone()); } } if v.is_empty(){ v.push(String::new()); } clipboard = Some(Register::Linewise(v)); }
-                        }
-                        ,(KeyCode::Char('u'), _, Mode::Normal) => {
-                            if let Some(snap) = undo_snap.clone() {
-                                if let Some(bi) = snap.buf { if let Some(b) = buffers.get_mut(bi) { b.lines = snap.lines; cx = snap.cx; cy = snap.cy; b.modified = true; } }
-                                else { lines = snap.lines; cx = snap.cx; cy = snap.cy; modified = true; }
-                            }
-                        }
-                        ,(KeyCode::Enter, _, Mode::Insert) => {
-                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ let cur = b.lines[cy].clone(); let (l,r)=cur.split_at(cx); b.lines[cy]=l.to_string(); b.lines.insert(cy+1, r.to_string()); cy+=1; cx=0; b.modified=true; } }
-                            else { let cur = lines[cy].clone(); let (l,r)=cur.split_at(cx); lines[cy]=l.to_string(); lines.insert(cy+1, r.to_string()); cy+=1; cx=0; modified=true; }
-                            insert_record.push('\n');
-                        }
-                        ,(KeyCode::Enter, _, Mode::Normal) => {
-                            // If buffers list is active, select and switch
-                            if !views.is_empty() && matches!(views[cur_view].kind, ViewKind::BuffersList) {
-                                let sel_row = views[cur_view].cy.saturating_sub(1); // row 0 is header
-                                if sel_row < buffers.len() {
-                                    // 割当: 呼び出し元のビューに選択バッファを割当
-                                    let caller = last_normal_view.min(views.len().saturating_sub(1));
-                                    if caller < views.len() { views[caller].buf = Some(sel_row); }
-                                    // 一覧ビューを閉じて呼出元へ戻る
-                                    let list_idx = cur_view;
-                                    views.remove(list_idx);
-                                    cur_view = caller.min(views.len().saturating_sub(1));
-                                    status = Some("buffer switched".into());
-                                }
-                            }
-                        }
-                        ,(KeyCode::Char('q'), _, Mode::Normal) => {
-                            if !views.is_empty() && matches!(views[cur_view].kind, ViewKind::BuffersList) {
-                                views.remove(cur_view); cur_view = 0;
-                            }
+                    }
+                    ,(KeyCode::Char('p'), _, Mode::Normal) => {
+                        let times = count.take().unwrap_or(1);
+                        if let Some(orig) = clipboard.clone() {
+                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); for _ in 0..times { let reg = orig.clone(); match reg { Register::Charwise(s)=>{ for ch in s.chars(){ b.lines[cy].insert(cx, ch); cx+=1; } }, Register::Linewise(mut ls)=>{ let insert_at=cy+1; for (i,l) in ls.drain(..).enumerate(){ b.lines.insert(insert_at+i, l);} cy=insert_at; cx=0; } } } b.modified=true; } }
+                            else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); for _ in 0..times { let reg = orig.clone(); match reg { Register::Charwise(s)=>{ for ch in s.chars(){ lines[cy].insert(cx, ch); cx+=1; } }, Register::Linewise(mut ls)=>{ let insert_at=cy+1; for (i,l) in ls.drain(..).enumerate(){ lines.insert(insert_at+i, l);} cy=insert_at; cx=0; } } } modified=true; }
                         }
-                        ,(KeyCode::Backspace, _, Mode::Insert) => {
-                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ if cx>0 { b.lines[cy].remove(cx-1); cx-=1; b.modified=true; if !insert_record.is_empty() { insert_record.pop(); } } else if cy>0 { let prev_len=b.lines[cy-1].len(); let line=b.lines.remove(cy); b.lines[cy-1].push_str(&line); cy-=1; cx=prev_len; b.modified=true; } } }
-                            else { if cx>0 { lines[cy].remove(cx-1); cx-=1; modified=true; if !insert_record.is_empty() { insert_record.pop(); } } else if cy>0 { let prev_len=lines[cy-1].len(); let line=lines.remove(cy); lines[cy-1].push_str(&line); cy-=1; cx=prev_len; modified=true; } }
+                    }
+                    ,(KeyCode::Char('P'), _, Mode::Normal) => {
+                        let times = count.take().unwrap_or(1);
+                        if let Some(orig) = clipboard.clone() {
+                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); for _ in 0..times { let reg = orig.clone(); match reg { Register::Charwise(s)=>{ let mut off=0; for ch in s.chars(){ b.lines[cy].insert(cx.saturating_sub(off+1), ch); off+=1; } }, Register::Linewise(mut ls)=>{ let insert_at=cy; for (i,l) in ls.drain(..).enumerate(){ b.lines.insert(insert_at+i, l);} cy=insert_at; cx=0; } } } b.modified=true; } }
+                            else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); for _ in 0..times { let reg = orig.clone(); match reg { Register::Charwise(s)=>{ let mut off=0; for ch in s.chars(){ lines[cy].insert(cx.saturating_sub(off+1), ch); off+=1; } }, Register::Linewise(mut ls)=>{ let insert_at=cy; for (i,l) in ls.drain(..).enumerate(){ lines.insert(insert_at+i, l);} cy=insert_at; cx=0; } } } modified=true; }
                         }
-                        ,(KeyCode::Char(c), KeyModifiers::NONE, Mode::Insert) => {
-                            if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ if c=='\t' { let spaces = " ".repeat(tabstop); for ch in spaces.chars(){ b.lines[cy].insert(cx, ch); cx+=1; insert_record.push(' ');} } else { b.lines[cy].insert(cx, c); cx+=1; insert_record.push(c); } b.modified=true; } }
-                            else { if c=='\t' { let spaces = " ".repeat(tabstop); for ch in spaces.chars(){ lines[cy].insert(cx, ch); cx+=1; insert_record.push(' ');} } else { lines[cy].insert(cx, c); cx+=1; insert_record.push(c); } modified=true; }
+                    }
+                    ,(KeyCode::Char('D'), _, Mode::Normal) => {
+                        // 行末まで削除（d$ 相当）
+                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ if cx < b.lines[cy].len() { undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); b.lines[cy].replace_range(cx.., ""); b.modified = true; } } }
+                        else { if cx < lines[cy].len() { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); lines[cy].replace_range(cx.., ""); modified = true; } }
+                    }
+                    ,(KeyCode::Char('Y'), _, Mode::Normal) => {
+                        // 行ヤンク（yy 相当、カウント対応）
+                        let n = count.take().unwrap_or(1);
+                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get(bi){ let mut v=Vec::new(); for i in 0..n { let li = cy.saturating_add(i); if li < b.lines.len() { v.push(b.lines[li].clone()); } } if v.is_empty(){ v.push(String::new()); } clipboard = Some(Register::Linewise(v)); } }
+                        else { let mut v=Vec::new(); for i in 0..n { let li = cy.saturating_add(i); if li < lines.len() { v.push(lines[li].clone()); } } if v.is_empty(){ v.push(String::new()); } clipboard = Some(Register::Linewise(v)); }
+                    }
+                    ,(KeyCode::Char('u'), _, Mode::Normal) | (KeyCode::Char('r'), KeyModifiers::CONTROL, Mode::Normal) => {
+                        // u / CTRL-R: undo ツリーを辿る（カウント対応）
+                        let n = count.take().unwrap_or(1);
+                        let bi = views[cur_view].buf;
+                        let tree = undo_trees.entry(bi).or_default();
+                        let target = match bi.and_then(|bi| buffers.get_mut(bi)) { Some(b) => { b.modified = true; &mut b.lines } None => { modified = true; &mut lines } };
+                        status = Some(if code == KeyCode::Char('u') { undo_step(tree, target, &mut cx, &mut cy, |t, text| t.undo(text, n)) } else { undo_step(tree, target, &mut cx, &mut cy, |t, text| t.redo(text, n)) });
+                    }
+                    ,(KeyCode::Enter, _, Mode::Insert) => {
+                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ let cur = b.lines[cy].clone(); let (l,r)=cur.split_at(cx); b.lines[cy]=l.to_string(); b.lines.insert(cy+1, r.to_string()); cy+=1; cx=0; b.modified=true; } }
+                        else { let cur = lines[cy].clone(); let (l,r)=cur.split_at(cx); lines[cy]=l.to_string(); lines.insert(cy+1, r.to_string()); cy+=1; cx=0; modified=true; }
+                        insert_record.push('\n');
+                    }
+                    ,(KeyCode::Enter, _, Mode::Normal) if !views.is_empty() && matches!(views[cur_view].kind, ViewKind::BuffersList) => {
+                        // If buffers list is active, select and switch
+                        let sel_row = views[cur_view].cy.saturating_sub(1); // row 0 is header
+                        // 行の先頭はバッファ番号
+                        if let Some(nr) = ls_lines.get(sel_row).and_then(|l| l.get(..4)).and_then(|n| n.trim().parse::<usize>().ok()) {
+                            // 割当: 呼び出し元のビューに選択バッファを割当
+                            let caller = last_normal_view.min(views.len().saturating_sub(1));
+                            if caller < views.len() { buf_switches.push((caller, nr, false)); }
+                            // 一覧ビューを閉じて呼出元へ戻る
+                            let list_idx = cur_view;
+                            views.remove(list_idx);
+                            cur_view = caller.min(views.len().saturating_sub(1));
+                            status = None;
                         }
-                        ,(KeyCode::Char('.'), _, Mode::Normal) => {
-                            if !last_insert.is_empty() {
-                                if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); for ch in last_insert.chars(){ if ch=='\n' { let cur=b.lines[cy].clone(); let (l,r)=cur.split_at(cx); b.lines[cy]=l.to_string(); b.lines.insert(cy+1, r.to_string()); cy+=1; cx=0; } else { b.lines[cy].insert(cx, ch); cx+=1; } } b.modified=true; } }
-                                else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); for ch in last_insert.chars(){ if ch=='\n' { let cur=lines[cy].clone(); let (l,r)=cur.split_at(cx); lines[cy]=l.to_string(); lines.insert(cy+1, r.to_string()); cy+=1; cx=0; } else { lines[cy].insert(cx, ch); cx+=1; } } modified=true; }
-                            }
+                    }
+                    ,(KeyCode::Char('q'), _, Mode::Normal) => {
+                        if !views.is_empty() && matches!(views[cur_view].kind, ViewKind::BuffersList) {
+                            views.remove(cur_view); cur_view = 0;
                         }
-                        ,(KeyCode::Char('s'), KeyModifiers::CONTROL, _) => {
-                            // save active buffer or global
-                            let active_bi = views[cur_view].buf;
-                            if let Some(bi) = active_bi {
-                                if let Some(b) = buffers.get_mut(bi) {
-                                    if let Some(ref p) = b.filename { if save_file(p, &b.lines).is_ok() { b.modified = false; status = Some("written".into()); } else { status = Some("write error".into()); } }
-                                    else { status = Some("No file name".into()); }
-                                }
-                            } else {
-                                if let Some(ref p) = filename { if save_file(p, &lines).is_ok() { modified = false; status = Some("written".into()); } else { status = Some("write error".into()); } } else { status = Some("No file name".into()); }
+                    }
+                    ,(KeyCode::Backspace, _, Mode::Insert) => {
+                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ if cx>0 { b.lines[cy].remove(cx-1); cx-=1; b.modified=true; if !insert_record.is_empty() { insert_record.pop(); } } else if cy>0 { let prev_len=b.lines[cy-1].len(); let line=b.lines.remove(cy); b.lines[cy-1].push_str(&line); cy-=1; cx=prev_len; b.modified=true; } } }
+                        else { if cx>0 { lines[cy].remove(cx-1); cx-=1; modified=true; if !insert_record.is_empty() { insert_record.pop(); } } else if cy>0 { let prev_len=lines[cy-1].len(); let line=lines.remove(cy); lines[cy-1].push_str(&line); cy-=1; cx=prev_len; modified=true; } }
+                    }
+                    ,(KeyCode::Char(c), KeyModifiers::NONE, Mode::Insert) => {
+                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ if c=='\t' { let spaces = " ".repeat(tabstop); for ch in spaces.chars(){ b.lines[cy].insert(cx, ch); cx+=1; insert_record.push(' ');} } else { b.lines[cy].insert(cx, c); cx+=1; insert_record.push(c); } b.modified=true; } }
+                        else { if c=='\t' { let spaces = " ".repeat(tabstop); for ch in spaces.chars(){ lines[cy].insert(cx, ch); cx+=1; insert_record.push(' ');} } else { lines[cy].insert(cx, c); cx+=1; insert_record.push(c); } modified=true; }
+                    }
+                    ,(KeyCode::Char('.'), _, Mode::Normal) if !last_insert.is_empty() => {
+                        if let Some(bi)=views[cur_view].buf { if let Some(b)=buffers.get_mut(bi){ undo_snap = Some(UndoSnap { buf: Some(bi), lines: b.lines.clone(), cx, cy }); for ch in last_insert.chars(){ if ch=='\n' { let cur=b.lines[cy].clone(); let (l,r)=cur.split_at(cx); b.lines[cy]=l.to_string(); b.lines.insert(cy+1, r.to_string()); cy+=1; cx=0; } else { b.lines[cy].insert(cx, ch); cx+=1; } } b.modified=true; } }
+                        else { undo_snap = Some(UndoSnap { buf: None, lines: lines.clone(), cx, cy }); for ch in last_insert.chars(){ if ch=='\n' { let cur=lines[cy].clone(); let (l,r)=cur.split_at(cx); lines[cy]=l.to_string(); lines.insert(cy+1, r.to_string()); cy+=1; cx=0; } else { lines[cy].insert(cx, ch); cx+=1; } } modified=true; }
+                    }
+                    ,(KeyCode::Char('s'), KeyModifiers::CONTROL, _) => {
+                        // save active buffer or global
+                        let active_bi = views[cur_view].buf;
+                        if let Some(bi) = active_bi {
+                            if let Some(b) = buffers.get_mut(bi) {
+                                if let Some(ref p) = b.filename { let o = file_opts.entry(Some(bi)).or_default(); match save_file(p, &b.lines, o, &FileArgs::default()) { Ok(msg) => { b.modified = false; status = Some(undo_written(undo_trees.entry(Some(bi)).or_default(), p, &b.lines, undofile, &o.key).unwrap_or(msg)); } Err(e) => status = Some(e) } }
+                                else { status = Some("No file name".into()); }
                             }
+                        } else {
+                            if let Some(ref p) = filename { let o = file_opts.entry(None).or_default(); match save_file(p, &lines, o, &FileArgs::default()) { Ok(msg) => { modified = false; status = Some(undo_written(undo_trees.entry(None).or_default(), p, &lines, undofile, &o.key).unwrap_or(msg)); } Err(e) => status = Some(e) } } else { status = Some("No file name".into()); }
                         }
-                        ,(KeyCode::Char('n'), _, Mode::Normal) => { if let Some(_) = &search.regex { let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; if let Some((ny,nx))=find_next(src, cy, cx, &search, search.last_dir){ cy=ny; cx=nx; } } }
-                        ,(KeyCode::Char('N'), _, Mode::Normal) => { if let Some(_) = &search.regex { let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; if let Some((ny,nx))=find_next(src, cy, cx, &search, -search.last_dir){ cy=ny; cx=nx; } } }
-                        ,(KeyCode::Char('w'), KeyModifiers::CONTROL, Mode::Normal) => { if !views.is_empty() { let k = views[cur_view].kind; let b = views[cur_view].buf; views[cur_view] = View { kind: k, cx, cy, scroll, buf: b }; cur_view = (cur_view + 1) % views.len(); let v = views[cur_view].clone(); cx = v.cx; cy = v.cy; scroll = v.scroll; } }
-                        ,(KeyCode::Char('w'), KeyModifiers::NONE, Mode::Normal) => { let n=count.take().unwrap_or(1); let src= if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; let (nx,ny)=motion_w(src,cx,cy,n); cx=nx; cy=ny; }
-                        ,(KeyCode::Char('e'), KeyModifiers::NONE, Mode::Normal) => { let n=count.take().unwrap_or(1); let src= if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; let (nx,ny)=motion_e(src,cx,cy,n); cx=nx; cy=ny; }
-                        ,(KeyCode::Char('b'), KeyModifiers::NONE, Mode::Normal) => { let n=count.take().unwrap_or(1); let src= if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; let (nx,ny)=motion_b(src,cx,cy,n); cx=nx; cy=ny; }
-                        ,(KeyCode::Char('q'), KeyModifiers::CONTROL, _) => { if modified { status = Some("No write since last change (:q! to quit)".into()); } else { break; } }
-                        ,_ => {}
                     }
+                    ,(KeyCode::Char('n'), _, Mode::Normal) => { if let Some(_) = &search.regex { let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; if let Some((ny,nx))=find_next(src, cy, cx, &search, search.last_dir){ cy=ny; cx=nx; } } }
+                    ,(KeyCode::Char('N'), _, Mode::Normal) => { if let Some(_) = &search.regex { let src = if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; if let Some((ny,nx))=find_next(src, cy, cx, &search, -search.last_dir){ cy=ny; cx=nx; } } }
+                    ,(KeyCode::Char('w'), KeyModifiers::CONTROL, Mode::Normal) if !views.is_empty() => { let k = views[cur_view].kind; let b = views[cur_view].buf; views[cur_view] = View { kind: k, cx, cy, scroll, buf: b, args: std::mem::take(&mut views[cur_view].args) }; cur_view = (cur_view + 1) % views.len(); let v = views[cur_view].clone(); cx = v.cx; cy = v.cy; scroll = v.scroll; }
+                    ,(KeyCode::Char('w'), KeyModifiers::NONE, Mode::Normal) => { let n=count.take().unwrap_or(1); let src= if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; let (nx,ny)=motion_w(src,cx,cy,n); cx=nx; cy=ny; }
+                    ,(KeyCode::Char('e'), KeyModifiers::NONE, Mode::Normal) => { let n=count.take().unwrap_or(1); let src= if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; let (nx,ny)=motion_e(src,cx,cy,n); cx=nx; cy=ny; }
+                    ,(KeyCode::Char('b'), KeyModifiers::NONE, Mode::Normal) => { let n=count.take().unwrap_or(1); let src= if let Some(bi)=views[cur_view].buf { buffers.get(bi).map(|b| &b.lines).unwrap_or(&lines) } else { &lines }; let (nx,ny)=motion_b(src,cx,cy,n); cx=nx; cy=ny; }
+                    ,(KeyCode::Char('q'), KeyModifiers::CONTROL, _) => { if modified { status = Some("No write since last change (:q! to quit)".into()); } else { break; } }
+                    ,_ => {}
                 }
-                _ => {}
             }
         }
     }
 
     // restore terminal
-    terminal::disable_raw_mode().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    terminal::disable_raw_mode().map_err(std::io::Error::other)?;
     let mut stdout = std::io::stdout();
-    execute!(stdout, crossterm::cursor::Show, crossterm::terminal::LeaveAlternateScreen).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    execute!(stdout, crossterm::cursor::Show, crossterm::terminal::LeaveAlternateScreen).map_err(std::io::Error::other)?;
     Ok(())
 }
 
@@ -0,0 +1,154 @@
+//! The argument list in scripts: `:args`, `:next`, `:argdo` and the other
+//! commands of rust_arglist, and the builtins argc(), argidx() and argv().
+//!
+//! Editing an argument reads the file into the one buffer of the evaluator.
+//! The evaluator has one window, its argument list is global unless
+//! `:arglocal` was used.
+
+use std::path::{Path, PathBuf};
+
+use rust_arglist::{ArgAction, ArgList, WindowArgs};
+use rust_autocmd::Event;
+use rust_fileio::{read_file, ReadOptions};
+
+use crate::ex::{expand_home, parse_script};
+use crate::{Evaluator, Value};
+
+impl Evaluator {
+    /// The global argument list, an embedder fills it with the file
+    /// arguments it was started with.
+    pub fn arglist(&self) -> &ArgList {
+        &self.arglist
+    }
+
+    pub fn arglist_mut(&mut self) -> &mut ArgList {
+        &mut self.arglist
+    }
+
+    /// The argument list of the window and the index of its argument.
+    pub fn window_args(&self) -> &WindowArgs {
+        &self.win_args
+    }
+
+    /// Execute argument list command `text`.
+    pub(crate) fn ex_arglist(&mut self, lnum: usize, text: &str) -> Result<(), ()> {
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let name = self.buffer.name().to_string();
+        // Work on copies, the lists only change when the buffer can be left
+        // for the argument the command edits.
+        let (mut arglist, mut win_args) = (self.arglist.clone(), self.win_args.clone());
+        let result = rust_arglist::execute(text, &mut arglist, &mut win_args, &name, &cwd);
+        if let Some(Ok(ArgAction::Edit { bang, .. })) = result {
+            if !self.can_abandon(bang) {
+                return self.emsg("E37: No write since last change (add ! to override)".to_string());
+            }
+        }
+        (self.arglist, self.win_args) = (arglist, win_args);
+        match result {
+            None | Some(Ok(ArgAction::None)) => Ok(()),
+            Some(Ok(ArgAction::Message(msg))) => {
+                self.message(msg);
+                Ok(())
+            }
+            Some(Ok(ArgAction::Edit { idx, name, bang })) => self.edit_arg(idx, &name, bang),
+            Some(Ok(ArgAction::ArgDo { cmd, bang })) => self.argdo(lnum, &cmd, bang),
+            Some(Err(msg)) => self.emsg(msg),
+        }
+    }
+
+    /// `:argdo`: edit each argument and execute `cmd` in it.  Stops at the
+    /// first error.
+    fn argdo(&mut self, lnum: usize, cmd: &str, bang: bool) -> Result<(), ()> {
+        let stmts = match parse_script(&[(lnum, cmd.to_string())]) {
+            Ok(stmts) => stmts,
+            Err(msg) => return self.emsg(msg),
+        };
+        let mut idx = 0;
+        while let Some(name) = self.win_args.list(&self.arglist).get(idx).map(str::to_string) {
+            self.edit_arg(idx, &name, bang)?;
+            self.exec_stmts(&stmts)?;
+            idx += 1;
+        }
+        Ok(())
+    }
+
+    /// Edit argument `idx`, file `name`.  A changed buffer is only left
+    /// with `bang` or when 'hidden' or 'bufhidden' keep it.
+    fn edit_arg(&mut self, idx: usize, name: &str, bang: bool) -> Result<(), ()> {
+        if !self.can_abandon(bang) {
+            return self.emsg("E37: No write since last change (add ! to override)".to_string());
+        }
+        self.win_args.idx = idx;
+        self.edit_file(name)
+    }
+
+    /// Read file `name` into its buffer, like `:edit!`, the buffer becomes
+    /// the current one.  A file that does not exist gives an empty buffer.
+    pub(crate) fn edit_file(&mut self, name: &str) -> Result<(), ()> {
+        let path = expand_home(name);
+        let _ = self.apply_autocmds_group(Event::BufReadPre, name, false, None);
+        let (lines, msg) = if Path::new(&path).exists() {
+            let opts = ReadOptions {
+                fileencodings: self.string_option("fileencodings"),
+                fileformats: self.string_option("fileformats"),
+                binary: self.bool_option("binary"),
+                key: self.crypt_key().map(str::to_string),
+                ..ReadOptions::default()
+            };
+            let result = match read_file(&path, &opts) {
+                Ok(result) => result,
+                Err(_) => return self.emsg(format!("E484: Can't open file {}", name)),
+            };
+            let _ = self.set_option("fileformat", Value::Str(result.fileformat.name().to_string()));
+            let _ = self.set_option("fileencoding", Value::Str(result.fileencoding.clone()));
+            let _ = self.set_option("bomb", Value::Number(result.bomb as i64));
+            let _ = self.set_option("endofline", Value::Number(result.eol as i64));
+            let lines = result.lines.iter().map(|l| String::from_utf8_lossy(l).into_owned()).collect();
+            (lines, result.message(name))
+        } else {
+            (Vec::new(), format!("\"{}\" [New]", name))
+        };
+        let nr = self.buffer_for_file(name);
+        self.enter_buffer(nr);
+        let lnum = self.buflist.get(nr).map_or(1, |buf| buf.lnum);
+        self.buffer.set_name(name);
+        self.buffer.set_lines(lines);
+        self.buffer.set_cursor(lnum, 1);
+        let _ = self.set_option("modified", Value::Number(0));
+        self.message(msg);
+        let _ = self.apply_autocmds_group(Event::BufReadPost, name, false, None);
+        Ok(())
+    }
+
+    /// The argument list of the window `winid` argument refers to: -1 for
+    /// the global list, absent or zero for the current window.
+    fn arglist_of(&mut self, winid: Option<&Value>) -> Result<Option<&ArgList>, ()> {
+        match winid.map(|val| self.tv_number(val)).transpose()? {
+            Some(-1) => Ok(Some(&self.arglist)),
+            None | Some(0) => Ok(Some(self.win_args.list(&self.arglist))),
+            Some(_) => Ok(None),
+        }
+    }
+}
+
+/// argc([{winid}]): the number of files in the argument list, -1 for an
+/// unknown window.
+pub(crate) fn f_argc(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Number(ev.arglist_of(args.first())?.map_or(-1, |list| list.len() as i64)))
+}
+
+/// argidx(): the index of the current file in the argument list.
+pub(crate) fn f_argidx(ev: &mut Evaluator, _args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Number(ev.win_args.idx as i64))
+}
+
+/// argv([{nr} [, {winid}]]): file {nr} of the argument list, an empty
+/// String when there is none.  Without {nr} or with -1 a List of all files.
+pub(crate) fn f_argv(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let nr = args.first().map(|val| ev.tv_number(val)).transpose()?;
+    let names = ev.arglist_of(args.get(1))?.map(|list| list.names().to_vec()).unwrap_or_default();
+    Ok(match nr {
+        None | Some(-1) => Value::new_list(names.into_iter().map(Value::Str).collect()),
+        Some(nr) => Value::Str(usize::try_from(nr).ok().and_then(|i| names.get(i).cloned()).unwrap_or_default()),
+    })
+}
@@ -0,0 +1,167 @@
+//! Autocommands: `:autocmd`, `:augroup`, `:doautocmd` and `:doautoall`, and
+//! expand() for `<amatch>`, `<afile>` and `<abuf>`.
+//!
+//! [`AutoCmds`] keeps the autocommands and picks the ones to run, the
+//! evaluator executes them.  An autocommand is executed in the script it
+//! was defined in, not in the scope of a function that triggered it.
+
+use rust_autocmd::{AutoCmdRun, AutoCmds, Event};
+
+use crate::ex::{expand_home, parse_script, split_lines};
+use crate::{Evaluator, Value};
+
+impl Evaluator {
+    pub fn autocmds(&self) -> &AutoCmds {
+        &self.autocmds
+    }
+
+    /// exists("#group#event#pattern") without the first "#": any of the
+    /// three parts may be left out, "##event" checks that the event is
+    /// supported.
+    pub(crate) fn autocmd_exists(&self, arg: &str) -> bool {
+        if let Some(event) = arg.strip_prefix('#') {
+            return Event::from_name(event).is_some();
+        }
+        let (group, arg) = match arg.split_once('#') {
+            Some((name, rest)) => match self.autocmds.group_id(name) {
+                Some(id) => (Some(id), rest),
+                None => (None, arg),
+            },
+            None => match self.autocmds.group_id(arg) {
+                Some(_) => return true,
+                None => (None, arg),
+            },
+        };
+        let (event, pattern) = match arg.split_once('#') {
+            Some((event, pattern)) => (event, Some(pattern)),
+            None => (arg, None),
+        };
+        let Some(event) = Event::from_name(event) else {
+            return false;
+        };
+        let pattern = pattern.map(|pat| match pat {
+            "<buffer>" => format!("<buffer={}>", self.curbuf()),
+            _ => pat.to_string(),
+        });
+        self.autocmds.has_event(event, group, pattern.as_deref())
+    }
+
+    /// Execute the autocommands for `event` and file name `fname`, like
+    /// Vim's apply_autocmds().  Errors are reported.  Returns whether an
+    /// autocommand was executed, Err when there was an error.
+    pub fn apply_autocmds(&mut self, event: Event, fname: &str) -> Result<bool, ()> {
+        let mut done = false;
+        self.toplevel(|ev| {
+            done = ev.apply_autocmds_group(event, fname, false, None)?;
+            Ok(())
+        })?;
+        Ok(done)
+    }
+
+    pub(crate) fn apply_autocmds_group(
+        &mut self,
+        event: Event,
+        fname: &str,
+        force: bool,
+        group: Option<usize>,
+    ) -> Result<bool, ()> {
+        let runs = match self.autocmds.apply(event, fname, self.curbuf(), force, group) {
+            Ok(runs) => runs,
+            Err(msg) => return self.emsg(msg),
+        };
+        for run in &runs {
+            let state = self.autocmds.enter(run);
+            self.autocmd_runs.push(run.clone());
+            let result = self.exec_autocmd(run);
+            self.autocmd_runs.pop();
+            self.autocmds.leave(state);
+            result?;
+        }
+        Ok(!runs.is_empty())
+    }
+
+    fn exec_autocmd(&mut self, run: &AutoCmdRun) -> Result<(), ()> {
+        let stmts = match parse_script(&split_lines(&run.cmd)) {
+            Ok(stmts) => stmts,
+            Err(msg) => return self.emsg(msg),
+        };
+        let saved_sid = std::mem::replace(&mut self.sid, run.sid);
+        let saved_pos = self.enter_sourcing(format!("{} Autocommands for \"{}\"", run.event, run.pattern));
+        let saved_frames = std::mem::take(&mut self.frames);
+        let result = self.exec_stmts(&stmts);
+        self.frames = saved_frames;
+        self.leave_sourcing(saved_pos);
+        self.sid = saved_sid;
+        result.map(|_| ())
+    }
+
+    /// `:autocmd`
+    pub(crate) fn ex_autocmd(&mut self, arg: &str, bang: bool) -> Result<(), ()> {
+        match self.autocmds.do_autocmd(arg, bang, self.curbuf(), self.sid) {
+            Ok(lines) => {
+                for line in lines {
+                    self.message(line);
+                }
+                Ok(())
+            }
+            Err(msg) => self.emsg(msg),
+        }
+    }
+
+    /// `:augroup`
+    pub(crate) fn ex_augroup(&mut self, arg: &str, bang: bool) -> Result<(), ()> {
+        match self.autocmds.do_augroup(arg, bang) {
+            Ok(lines) => {
+                for line in lines {
+                    self.message(line);
+                }
+                Ok(())
+            }
+            Err(msg) => self.emsg(msg),
+        }
+    }
+
+    /// `:doautocmd`, or `:doautoall` when `doall` is set.  Without a file
+    /// name the name of the buffer is used.  `:doautoall` does not say so
+    /// when no autocommand matched.
+    pub(crate) fn ex_doautocmd(&mut self, arg: &str, doall: bool) -> Result<(), ()> {
+        let (group, events, fname) = match self.autocmds.do_args(arg) {
+            Ok(args) => args,
+            Err(msg) => return self.emsg(msg),
+        };
+        let fname = if fname.is_empty() { self.buffer.name().to_string() } else { fname.to_string() };
+        let mut done = false;
+        for event in events {
+            done |= self.apply_autocmds_group(event, &fname, true, group)?;
+        }
+        if !done && !doall {
+            self.message(format!("No matching autocommands: {}", arg.trim()));
+        }
+        Ok(())
+    }
+}
+
+/// expand({string}): `<amatch>`, `<afile>` and `<abuf>` of the autocommand
+/// being executed, "%" for the buffer name and a leading "~/" for the home
+/// directory.  Other text is returned unchanged.
+pub(crate) fn f_expand(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let text = ev.tv_string(&args[0])?;
+    let run = ev.autocmd_runs.last();
+    let expanded = match text.as_str() {
+        "<amatch>" => match run {
+            Some(run) => run.amatch.clone(),
+            None => return ev.emsg("E497: No autocommand match name to substitute for \"<amatch>\"".to_string()),
+        },
+        "<afile>" => match run {
+            Some(run) => run.afile.clone(),
+            None => return ev.emsg("E495: No autocommand file name to substitute for \"<afile>\"".to_string()),
+        },
+        "<abuf>" => match run {
+            Some(run) => run.abuf.to_string(),
+            None => return ev.emsg("E496: No autocommand buffer number to substitute for \"<abuf>\"".to_string()),
+        },
+        "%" => ev.buffer.name().to_string(),
+        _ => expand_home(&text).to_string_lossy().into_owned(),
+    };
+    Ok(Value::Str(expanded))
+}
@@ -0,0 +1,345 @@
+//! The text the evaluator works on, the builtins that access it:
+//! getline(), setline(), append(), line() and col(), and `:recover`.
+//!
+//! The evaluator has one buffer.  An embedder fills it with
+//! [`Evaluator::buffer_mut`] and reads the result back with
+//! [`Evaluator::buffer`].  Line and column numbers are one based, as in Vim.
+//! Changes made by setline() and append() can be undone, see undo.rs.
+
+use std::collections::HashMap;
+
+use rust_undo::{UndoBuffer, UndoTree};
+
+use crate::ex::expand_home;
+use crate::{Evaluator, Value};
+
+/// The lines of a buffer, the cursor and the marks set in it.
+#[derive(Debug, Clone)]
+pub struct Buffer {
+    /// The file name, empty for a buffer without a name.
+    name: String,
+    /// Never empty: an empty buffer has one empty line.
+    lines: Vec<String>,
+    cursor: (usize, usize),
+    marks: HashMap<char, (usize, usize)>,
+    pub(crate) undo: UndoTree,
+}
+
+impl Buffer {
+    pub fn new() -> Self {
+        Buffer {
+            name: String::new(),
+            lines: vec![String::new()],
+            cursor: (1, 1),
+            marks: HashMap::new(),
+            undo: UndoTree::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Replace all lines, like when reading a file.  The undo information
+    /// is cleared.  The cursor is moved to the last line when it was below
+    /// it.
+    pub fn set_lines(&mut self, lines: Vec<String>) {
+        self.lines = if lines.is_empty() { vec![String::new()] } else { lines };
+        self.undo.clear();
+        let (lnum, col) = self.cursor;
+        self.set_cursor(lnum, col);
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The cursor position: line and byte column.
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    /// Move the cursor, the position is adjusted to be inside the text.
+    pub fn set_cursor(&mut self, lnum: usize, col: usize) {
+        self.cursor = (lnum.clamp(1, self.lines.len()), col.max(1));
+    }
+
+    pub fn mark(&self, name: char) -> Option<(usize, usize)> {
+        self.marks.get(&name).copied()
+    }
+
+    pub fn set_mark(&mut self, name: char, lnum: usize, col: usize) {
+        self.marks.insert(name, (lnum, col));
+    }
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The buffer as undo sees it, with the value of 'modified'.
+pub(crate) struct UndoText<'a> {
+    buffer: &'a mut Buffer,
+    pub(crate) modified: bool,
+}
+
+impl UndoBuffer for UndoText<'_> {
+    fn line_count(&self) -> usize {
+        self.buffer.lines.len()
+    }
+
+    fn line(&self, lnum: usize) -> &str {
+        &self.buffer.lines[lnum - 1]
+    }
+
+    fn replace_lines(&mut self, top: usize, count: usize, lines: Vec<String>) -> Vec<String> {
+        self.buffer.lines.splice(top..top + count, lines).collect()
+    }
+
+    fn modified(&self) -> bool {
+        self.modified
+    }
+
+    fn set_modified(&mut self, modified: bool) {
+        self.modified = modified;
+    }
+
+    fn cursor(&self) -> (usize, usize) {
+        self.buffer.cursor
+    }
+
+    fn set_cursor(&mut self, lnum: usize, col: usize) {
+        self.buffer.set_cursor(lnum, col);
+    }
+}
+
+impl Evaluator {
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut Buffer {
+        &mut self.buffer
+    }
+
+    /// Call `f` with the undo tree and the text of the buffer.  'undolevels'
+    /// is applied first and 'modified' is set to what `f` leaves.
+    pub(crate) fn with_undo<R>(&mut self, f: impl FnOnce(&mut UndoTree, &mut UndoText) -> R) -> R {
+        let modified = matches!(self.get_option("modified"), Some(Value::Number(n)) if n != 0);
+        let undolevels = match self.get_option("undolevels") {
+            Some(Value::Number(n)) => n,
+            _ => 1000,
+        };
+        let mut undo = std::mem::take(&mut self.buffer.undo);
+        undo.set_undolevels(undolevels);
+        let mut text = UndoText { buffer: &mut self.buffer, modified };
+        let result = f(&mut undo, &mut text);
+        let modified = text.modified;
+        self.buffer.undo = undo;
+        let _ = self.set_option("modified", Value::Number(modified as i64));
+        result
+    }
+
+    /// Save lines `top + 1` to `bot - 1` for undo before changing them, like
+    /// Vim's u_save().  Sets 'modified'.
+    fn u_save(&mut self, top: usize, bot: usize) {
+        self.with_undo(|undo, text| {
+            undo.save(text, top, bot);
+            text.modified = true;
+        });
+    }
+
+    /// Execute `:recover[!] [file]`: replace the text of the buffer with
+    /// what is in the swap file of `file`, or the buffer name.  The first
+    /// swap file found in 'directory' is used.  A swap file name can also
+    /// be given.
+    pub(crate) fn ex_recover(&mut self, arg: &str, bang: bool) -> Result<(), ()> {
+        if !bang && matches!(self.get_option("modified"), Some(Value::Number(n)) if n != 0) {
+            return self.emsg("E37: No write since last change (add ! to override)".to_string());
+        }
+        let name = if arg.is_empty() { self.buffer.name().to_string() } else { arg.to_string() };
+        if name.is_empty() {
+            return self.emsg("E32: No file name".to_string());
+        }
+        let path = expand_home(&name);
+        let is_swap = path.extension().is_some_and(|ext| rust_memline::is_swap_ext(&ext.to_string_lossy()));
+        let swap = if is_swap {
+            Some(path)
+        } else {
+            let dirs = self.get_option("directory").map(|dirs| dirs.to_string()).unwrap_or_default();
+            rust_memline::find_swap_files(&path, &dirs).into_iter().next()
+        };
+        let Some(swap) = swap else {
+            return self.emsg(format!("E305: No swap file found for {}", name));
+        };
+        let recovered = match rust_memline::recover(&swap, self.crypt_key()) {
+            Ok(recovered) => recovered,
+            Err(msg) => return self.emsg(msg),
+        };
+        if !recovered.fname.as_os_str().is_empty() {
+            self.buffer.set_name(&recovered.fname.to_string_lossy());
+        }
+        self.buffer.set_lines(recovered.lines);
+        let _ = self.set_option("modified", Value::Number(recovered.changed as i64));
+        for msg in recovered.messages {
+            self.message(msg);
+        }
+        Ok(())
+    }
+
+    /// The position `expr` refers to, like Vim's var2fpos(): ".", "$", "'x",
+    /// "w0" or "w$".  The whole buffer counts as visible.  None for an
+    /// unknown position or a mark that is not set.
+    fn position(&self, expr: &str) -> Option<(usize, usize)> {
+        let buf = &self.buffer;
+        let last = buf.line_count();
+        match expr {
+            "." | "v" => Some(buf.cursor()),
+            "$" => Some((last, buf.lines[last - 1].len() + 1)),
+            "w0" => Some((1, 1)),
+            "w$" => Some((last, 1)),
+            _ => {
+                let mut chars = expr.chars();
+                match (chars.next(), chars.next(), chars.next()) {
+                    (Some('\''), Some(name), None) => buf.mark(name),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// A line number argument: a Number, or a String for line().
+    fn tv_lnum(&mut self, val: &Value) -> Result<usize, ()> {
+        if let Value::Str(s) = val {
+            if s.parse::<i64>().is_err() {
+                return Ok(self.position(s).map_or(0, |(lnum, _)| lnum));
+            }
+        }
+        Ok(self.tv_number(val)?.max(0) as usize)
+    }
+
+    /// The text argument of setline() and append(): a String or a List of
+    /// Strings.
+    fn tv_lines(&mut self, val: &Value) -> Result<Vec<String>, ()> {
+        match val {
+            Value::List(list) => {
+                let items = list.borrow().clone();
+                items.iter().map(|item| self.tv_string(item)).collect()
+            }
+            val => Ok(vec![self.tv_string(val)?]),
+        }
+    }
+}
+
+/// getline({lnum} [, {end}]): the text of line {lnum}, or a List of the
+/// lines {lnum} to {end}.
+pub(crate) fn f_getline(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let first = ev.tv_lnum(&args[0])?;
+    let lines = ev.buffer.lines();
+    let Some(end) = args.get(1) else {
+        let line = first.checked_sub(1).and_then(|i| lines.get(i)).cloned();
+        return Ok(Value::Str(line.unwrap_or_default()));
+    };
+    let end = ev.tv_lnum(end)?;
+    let lines = ev.buffer.lines();
+    let first = first.max(1);
+    let end = end.min(lines.len());
+    let items = if first > end {
+        Vec::new()
+    } else {
+        lines[first - 1..end].iter().map(|line| Value::Str(line.clone())).collect()
+    };
+    Ok(Value::new_list(items))
+}
+
+/// setline({lnum}, {text}): replace line {lnum}, and the ones below it for
+/// a List.  Lines are added after the last one.  Returns 1 when {lnum} is
+/// invalid.
+pub(crate) fn f_setline(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let lnum = ev.tv_lnum(&args[0])?;
+    let text = ev.tv_lines(&args[1])?;
+    let len = ev.buffer.lines.len();
+    if lnum == 0 || lnum > len + 1 {
+        return Ok(Value::Number(1));
+    }
+    if !text.is_empty() {
+        ev.u_save(lnum - 1, (lnum - 1 + text.len()).min(len) + 1);
+    }
+    let lines = &mut ev.buffer.lines;
+    for (i, line) in text.into_iter().enumerate() {
+        match lines.get_mut(lnum - 1 + i) {
+            Some(old) => *old = line,
+            None => lines.push(line),
+        }
+    }
+    Ok(Value::Number(0))
+}
+
+/// append({lnum}, {text}): insert lines below line {lnum}, zero inserts at
+/// the start.  Returns 1 when {lnum} is invalid.
+pub(crate) fn f_append(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let lnum = ev.tv_lnum(&args[0])?;
+    let text = ev.tv_lines(&args[1])?;
+    if lnum > ev.buffer.lines.len() {
+        return Ok(Value::Number(1));
+    }
+    if !text.is_empty() {
+        ev.u_save(lnum, lnum + 1);
+    }
+    let buf = &mut ev.buffer;
+    let added = text.len();
+    buf.lines.splice(lnum..lnum, text);
+    // Keep the cursor on the same text.
+    if buf.cursor.0 > lnum {
+        buf.cursor.0 += added;
+    }
+    Ok(Value::Number(0))
+}
+
+/// line({expr} [, {winid}]): the line number of a position, zero when it is
+/// invalid.
+pub(crate) fn f_line(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let expr = ev.tv_string(&args[0])?;
+    Ok(Value::Number(ev.position(&expr).map_or(0, |(lnum, _)| lnum as i64)))
+}
+
+/// col({expr} [, {winid}]): the byte column of a position, one more than
+/// the length of the line for "$".  {expr} may also be a List [lnum, col]
+/// where col can be "$".
+pub(crate) fn f_col(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let col = match &args[0] {
+        Value::List(list) => {
+            let items = list.borrow().clone();
+            let [lnum, col] = items.as_slice() else { return Ok(Value::Number(0)) };
+            let lnum = ev.tv_number(lnum)?;
+            let len = usize::try_from(lnum - 1).ok().and_then(|i| ev.buffer.lines.get(i)).map(String::len);
+            match (len, col) {
+                (None, _) => 0,
+                (Some(len), Value::Str(s)) if s == "$" => len + 1,
+                (Some(len), col) => {
+                    let col = ev.tv_number(col)?;
+                    if col < 1 || col as usize > len + 1 {
+                        0
+                    } else {
+                        col as usize
+                    }
+                }
+            }
+        }
+        expr => {
+            let expr = ev.tv_string(expr)?;
+            ev.position(&expr).map_or(0, |(_, col)| col)
+        }
+    };
+    Ok(Value::Number(col as i64))
+}
@@ -0,0 +1,277 @@
+//! The buffer list in scripts: `:ls`, `:badd`, `:bdelete`, `:bwipeout` and
+//! `:bunload` of rust_buffer, the options 'buftype', 'bufhidden' and
+//! 'buflisted', and the builtins bufadd(), bufexists(), buflisted(),
+//! bufloaded(), bufname(), bufnr() and getbufinfo().
+//!
+//! Only the text of the current buffer is in memory.  The evaluator has one
+//! window, a buffer it leaves is unloaded, or deleted or wiped out when
+//! 'bufhidden' says so, and editing it again reads the file.
+
+use std::collections::BTreeMap;
+
+use rust_buffer::{Buf, BufAction, BufType, BufferList};
+
+use crate::{Evaluator, Value};
+
+/// The ID of the one window, for getbufinfo().
+const WINID: i64 = 1000;
+
+/// The list with the first buffer, empty and without a name.
+pub(crate) fn new_buflist() -> BufferList {
+    let mut list = BufferList::new();
+    let nr = list.add("", true);
+    list.enter(nr);
+    if let Some(buf) = list.get_mut(nr) {
+        buf.windows = 1;
+    }
+    list
+}
+
+impl Evaluator {
+    pub fn buflist(&self) -> &BufferList {
+        &self.buflist
+    }
+
+    /// The number of the current buffer, for `<abuf>` and `<buffer>`.
+    pub(crate) fn curbuf(&self) -> usize {
+        self.buflist.current()
+    }
+
+    /// The 'buftype' of the current buffer.
+    pub(crate) fn buftype(&self) -> BufType {
+        self.buflist.get(self.curbuf()).map_or(BufType::Normal, |buf| buf.buftype)
+    }
+
+    /// Copy the name, 'modified', 'modifiable', 'readonly' and the cursor
+    /// line of the current buffer to its entry in the list.
+    fn sync_curbuf(&mut self) {
+        let changed = self.bool_option("modified");
+        let modifiable = self.bool_option("modifiable");
+        let readonly = self.bool_option("readonly");
+        let (lnum, _) = self.buffer.cursor();
+        let cur = self.curbuf();
+        let name = self.buffer.name().to_string();
+        if let Some(buf) = self.buflist.get_mut(cur) {
+            buf.name = name;
+            buf.changed = changed;
+            buf.modifiable = modifiable;
+            buf.readonly = readonly;
+            buf.lnum = lnum;
+        }
+    }
+
+    /// The value of 'buftype', 'bufhidden' or 'buflisted', which are kept in
+    /// the list.  None for another option.
+    pub(crate) fn buf_option(&self, name: &str) -> Option<Value> {
+        let buf = self.buflist.get(self.curbuf())?;
+        Some(match name {
+            "buftype" => Value::Str(buf.buftype.name().to_string()),
+            "bufhidden" => Value::Str(buf.bufhidden.name().to_string()),
+            "buflisted" => Value::Number(buf.listed as i64),
+            _ => return None,
+        })
+    }
+
+    /// Set 'buftype', 'bufhidden' or 'buflisted' of the current buffer.
+    /// None for another option.
+    pub(crate) fn set_buf_option(&mut self, name: &str, val: &Value) -> Option<Result<(), String>> {
+        let arg = match val {
+            Value::Number(0) => format!("no{}", name),
+            Value::Number(_) => name.to_string(),
+            val => format!("{}={}", name, val),
+        };
+        let cur = self.curbuf();
+        self.buflist.set_option(cur, &arg)
+    }
+
+    /// The buffer to read file `name` into: the one with that name, or the
+    /// current buffer when it is empty and has no name, or a new one.
+    pub(crate) fn buffer_for_file(&mut self, name: &str) -> usize {
+        let cur = self.curbuf();
+        let empty = self.buffer.name().is_empty() && !self.bool_option("modified") && self.buffer.lines() == [""];
+        if empty && self.buflist.iter().all(|buf| buf.name != name) {
+            if let Some(buf) = self.buflist.get_mut(cur) {
+                buf.name = name.to_string();
+                buf.listed = true;
+                return cur;
+            }
+        }
+        self.buflist.add(name, true)
+    }
+
+    /// Whether the current buffer can be left for another one: it has no
+    /// changes, `force` is set, or 'hidden' or 'bufhidden' keep it.
+    pub(crate) fn can_abandon(&mut self, force: bool) -> bool {
+        self.sync_curbuf();
+        let hidden = self.bool_option("hidden");
+        self.buflist.clone().abandon(self.curbuf(), 0, hidden, force).is_ok()
+    }
+
+    /// Make buffer `nr` the current buffer, the caller loads its text.  The
+    /// text of the buffer that is left is not kept, its changes are lost.
+    pub(crate) fn enter_buffer(&mut self, nr: usize) {
+        self.sync_curbuf();
+        let cur = self.curbuf();
+        if nr != cur {
+            let _ = self.buflist.abandon(cur, 0, false, true);
+            let _ = self.buflist.unload(cur, true);
+        }
+        self.buflist.enter(nr);
+        if let Some(buf) = self.buflist.get_mut(nr) {
+            buf.windows = 1;
+        }
+    }
+
+    /// Edit buffer `nr`: read its file, or make it empty when it has no name.
+    fn load_buffer(&mut self, nr: usize) -> Result<(), ()> {
+        let name = self.buflist.get(nr).map(|buf| buf.name.clone()).unwrap_or_default();
+        if !name.is_empty() {
+            return self.edit_file(&name);
+        }
+        self.enter_buffer(nr);
+        self.buffer.set_name("");
+        self.buffer.set_lines(Vec::new());
+        self.buffer.set_cursor(1, 1);
+        let _ = self.set_option("modified", Value::Number(0));
+        Ok(())
+    }
+
+    /// Execute buffer list command `text`.  When the current buffer is
+    /// removed another listed buffer is edited, or a new empty one.
+    pub(crate) fn ex_buflist(&mut self, text: &str) -> Result<(), ()> {
+        self.sync_curbuf();
+        match rust_buffer::execute(text, &mut self.buflist) {
+            None | Some(Ok(BufAction::None | BufAction::Added(_))) => Ok(()),
+            Some(Ok(BufAction::Message(lines))) => {
+                for line in lines {
+                    self.message(line);
+                }
+                Ok(())
+            }
+            Some(Ok(BufAction::Removed { nrs, msg, .. })) => {
+                let cur = self.curbuf();
+                if nrs.contains(&cur) {
+                    // The changes were discarded already.
+                    let _ = self.set_option("modified", Value::Number(0));
+                    let nr = self.buflist.replacement(cur).unwrap_or_else(|| self.buflist.add("", true));
+                    self.load_buffer(nr)?;
+                }
+                if let Some(msg) = msg {
+                    self.message(msg);
+                }
+                Ok(())
+            }
+            Some(Err(msg)) => self.emsg(msg),
+        }
+    }
+
+    /// The buffer `{buf}` refers to: a Number, or a String for
+    /// [`BufferList::lookup`].  None when there is no such buffer.
+    fn tv_buf(&mut self, val: &Value) -> Result<Option<usize>, ()> {
+        self.sync_curbuf();
+        let nr = match val {
+            Value::Number(nr) => usize::try_from(*nr).ok(),
+            val => {
+                let name = self.tv_string(val)?;
+                self.buflist.lookup(&name).ok()
+            }
+        };
+        Ok(nr.filter(|&nr| self.buflist.get(nr).is_some()))
+    }
+
+    /// The entry of the buffer `{buf}` refers to, the current buffer
+    /// without an argument.
+    fn arg_buf(&mut self, arg: Option<&Value>) -> Result<Option<&Buf>, ()> {
+        let nr = match arg {
+            Some(val) => self.tv_buf(val)?,
+            None => {
+                self.sync_curbuf();
+                Some(self.curbuf())
+            }
+        };
+        Ok(nr.and_then(|nr| self.buflist.get(nr)))
+    }
+
+    fn buf_info(&self, buf: &Buf) -> Value {
+        let current = buf.nr == self.curbuf();
+        let mut dict = BTreeMap::new();
+        dict.insert("bufnr".to_string(), Value::Number(buf.nr as i64));
+        dict.insert("changed".to_string(), Value::Number(buf.changed as i64));
+        dict.insert("hidden".to_string(), Value::Number(buf.hidden() as i64));
+        dict.insert("lastused".to_string(), Value::Number(buf.lastused as i64));
+        let linecount = if current { self.buffer.line_count() } else { 0 };
+        dict.insert("linecount".to_string(), Value::Number(linecount as i64));
+        dict.insert("listed".to_string(), Value::Number(buf.listed as i64));
+        dict.insert("lnum".to_string(), Value::Number(buf.lnum as i64));
+        dict.insert("loaded".to_string(), Value::Number(buf.loaded as i64));
+        dict.insert("name".to_string(), Value::Str(buf.name.clone()));
+        let windows = if current { vec![Value::Number(WINID)] } else { Vec::new() };
+        dict.insert("windows".to_string(), Value::new_list(windows));
+        Value::new_dict(dict)
+    }
+}
+
+/// bufadd({name}): add a buffer for file {name}, not loaded and not listed.
+/// Returns the number of the buffer, an existing one for {name}.
+pub(crate) fn f_bufadd(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let name = ev.tv_string(&args[0])?;
+    Ok(Value::Number(ev.buflist.add(&name, false) as i64))
+}
+
+/// bufexists({buf}): whether buffer {buf} exists, listed or not.
+pub(crate) fn f_bufexists(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Number(ev.tv_buf(&args[0])?.is_some() as i64))
+}
+
+/// buflisted({buf}): whether buffer {buf} is listed.
+pub(crate) fn f_buflisted(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Number(ev.arg_buf(args.first())?.is_some_and(|buf| buf.listed) as i64))
+}
+
+/// bufloaded({buf}): whether buffer {buf} is loaded.
+pub(crate) fn f_bufloaded(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Number(ev.arg_buf(args.first())?.is_some_and(|buf| buf.loaded) as i64))
+}
+
+/// bufname([{buf}]): the name of buffer {buf}, empty when there is no such
+/// buffer or it has no name.
+pub(crate) fn f_bufname(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Str(ev.arg_buf(args.first())?.map(|buf| buf.name.clone()).unwrap_or_default()))
+}
+
+/// bufnr([{buf} [, {create}]]): the number of buffer {buf}, -1 when there
+/// is none.  With {create} an unlisted buffer is added for the name.
+pub(crate) fn f_bufnr(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    if let Some(buf) = ev.arg_buf(args.first())? {
+        return Ok(Value::Number(buf.nr as i64));
+    }
+    let create = args.get(1).map(|val| ev.tv_number(val)).transpose()?.unwrap_or(0) != 0;
+    match args.first() {
+        Some(Value::Str(name)) if create => Ok(Value::Number(ev.buflist.add(name, false) as i64)),
+        _ => Ok(Value::Number(-1)),
+    }
+}
+
+/// getbufinfo([{buf}]) or getbufinfo([{dict}]): a List with a Dictionary
+/// for buffer {buf}, or for all buffers.  {dict} selects buffers with the
+/// "buflisted", "bufloaded" and "bufmodified" items.
+pub(crate) fn f_getbufinfo(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let nrs: Vec<usize> = match args.first() {
+        None | Some(Value::Dict(_)) => {
+            let flag = |name: &str| match args.first() {
+                Some(Value::Dict(dict)) => dict.borrow().get(name).is_some_and(|val| val.to_string() != "0"),
+                _ => false,
+            };
+            let (listed, loaded, modified) = (flag("buflisted"), flag("bufloaded"), flag("bufmodified"));
+            ev.sync_curbuf();
+            ev.buflist
+                .iter()
+                .filter(|buf| (!listed || buf.listed) && (!loaded || buf.loaded) && (!modified || buf.changed))
+                .map(|buf| buf.nr)
+                .collect()
+        }
+        Some(val) => ev.tv_buf(val)?.into_iter().collect(),
+    };
+    let items = nrs.iter().filter_map(|&nr| ev.buflist.get(nr)).map(|buf| ev.buf_info(buf)).collect();
+    Ok(Value::new_list(items))
+}
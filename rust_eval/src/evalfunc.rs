@@ -0,0 +1,532 @@
+//! The table of builtin functions, and the builtins that have no module of
+//! their own: types, numbers, floats, JSON, exists() and has().
+//!
+//! Like Vim's global_functions[] the table gives the number of arguments a
+//! function accepts, a call with too few or too many fails before the
+//! function is called.  The conversions a builtin uses for its arguments are
+//! here too; they give the same errors as Vim's tv_get_number() and
+//! tv_get_string().
+
+use crate::{arglist, autocmd, buffer, buflist, func, gc, listfunc, mapping, strfunc, timer, undo, usercmd};
+use crate::{BuiltinFn, Evaluator, Value};
+
+/// Vim's MAX_FUNC_ARGS.
+const MAX_FUNC_ARGS: usize = 20;
+
+/// An entry in the table of builtin functions.
+pub(crate) struct FuncInfo {
+    pub(crate) name: &'static str,
+    pub(crate) min_argc: usize,
+    pub(crate) max_argc: usize,
+    pub(crate) func: BuiltinFn,
+}
+
+const fn f(name: &'static str, min_argc: usize, max_argc: usize, func: BuiltinFn) -> FuncInfo {
+    FuncInfo { name, min_argc, max_argc, func }
+}
+
+/// All builtin functions, sorted by name.
+pub(crate) static FUNCTIONS: &[FuncInfo] = &[
+    f("abs", 1, 1, f_abs),
+    f("add", 2, 2, listfunc::f_add),
+    f("and", 2, 2, f_and),
+    f("append", 2, 2, buffer::f_append),
+    f("argc", 0, 1, arglist::f_argc),
+    f("argidx", 0, 0, arglist::f_argidx),
+    f("argv", 0, 2, arglist::f_argv),
+    f("bufadd", 1, 1, buflist::f_bufadd),
+    f("bufexists", 1, 1, buflist::f_bufexists),
+    f("buflisted", 1, 1, buflist::f_buflisted),
+    f("bufloaded", 1, 1, buflist::f_bufloaded),
+    f("bufname", 0, 1, buflist::f_bufname),
+    f("bufnr", 0, 2, buflist::f_bufnr),
+    f("call", 2, 3, func::f_call),
+    f("ceil", 1, 1, f_ceil),
+    f("col", 1, 2, buffer::f_col),
+    f("concat", 0, MAX_FUNC_ARGS, strfunc::f_concat),
+    f("copy", 1, 1, listfunc::f_copy),
+    f("count", 2, 4, listfunc::f_count),
+    f("deepcopy", 1, 2, listfunc::f_deepcopy),
+    f("empty", 1, 1, listfunc::f_empty),
+    f("escape", 2, 2, strfunc::f_escape),
+    f("exists", 1, 1, f_exists),
+    f("exp", 1, 1, f_exp),
+    f("expand", 1, 3, autocmd::f_expand),
+    f("extend", 2, 3, listfunc::f_extend),
+    f("filter", 2, 2, listfunc::f_filter),
+    f("flatten", 1, 2, listfunc::f_flatten),
+    f("float2nr", 1, 1, f_float2nr),
+    f("floor", 1, 1, f_floor),
+    f("fmod", 2, 2, f_fmod),
+    f("funcref", 1, 3, func::f_funcref),
+    f("function", 1, 3, func::f_function),
+    f("garbagecollect", 0, 1, gc::f_garbagecollect),
+    f("get", 2, 3, listfunc::f_get),
+    f("getbufinfo", 0, 1, buflist::f_getbufinfo),
+    f("getcompletion", 2, 3, usercmd::f_getcompletion),
+    f("getline", 1, 2, buffer::f_getline),
+    f("has", 1, 2, f_has),
+    f("has_key", 2, 2, listfunc::f_has_key),
+    f("index", 2, 4, listfunc::f_index),
+    f("insert", 2, 3, listfunc::f_insert),
+    f("isinf", 1, 1, f_isinf),
+    f("isnan", 1, 1, f_isnan),
+    f("items", 1, 1, listfunc::f_items),
+    f("join", 1, 2, strfunc::f_join),
+    f("json_decode", 1, 1, f_json_decode),
+    f("json_encode", 1, 1, f_json_encode),
+    f("keys", 1, 1, listfunc::f_keys),
+    f("len", 1, 1, listfunc::f_len),
+    f("line", 1, 2, buffer::f_line),
+    f("log", 1, 1, f_log),
+    f("log10", 1, 1, f_log10),
+    f("map", 2, 2, listfunc::f_map),
+    f("maparg", 1, 4, mapping::f_maparg),
+    f("mapset", 1, 3, mapping::f_mapset),
+    f("match", 2, 4, strfunc::f_match),
+    f("matchend", 2, 4, strfunc::f_matchend),
+    f("matchstr", 2, 4, strfunc::f_matchstr),
+    f("max", 1, 1, listfunc::f_max),
+    f("min", 1, 1, listfunc::f_min),
+    f("or", 2, 2, f_or),
+    f("pow", 2, 2, f_pow),
+    f("printf", 1, MAX_FUNC_ARGS - 1, strfunc::f_printf),
+    f("range", 1, 3, listfunc::f_range),
+    f("reduce", 2, 3, listfunc::f_reduce),
+    f("remove", 2, 3, listfunc::f_remove),
+    f("repeat", 2, 2, strfunc::f_repeat),
+    f("reverse", 1, 1, listfunc::f_reverse),
+    f("round", 1, 1, f_round),
+    f("setline", 2, 2, buffer::f_setline),
+    f("sort", 1, 3, listfunc::f_sort),
+    f("split", 1, 3, strfunc::f_split),
+    f("sqrt", 1, 1, f_sqrt),
+    f("str2float", 1, 2, f_str2float),
+    f("str2nr", 1, 3, f_str2nr),
+    f("strcharpart", 2, 4, strfunc::f_strcharpart),
+    f("strchars", 1, 2, strfunc::f_strchars),
+    f("stridx", 2, 3, strfunc::f_stridx),
+    f("string", 1, 1, strfunc::f_string),
+    f("strlen", 1, 1, strfunc::f_strlen),
+    f("strpart", 2, 4, strfunc::f_strpart),
+    f("submatch", 1, 2, strfunc::f_submatch),
+    f("substitute", 4, 4, strfunc::f_substitute),
+    f("test_garbagecollect_now", 0, 0, gc::f_test_garbagecollect_now),
+    f("test_settime", 1, 1, undo::f_test_settime),
+    f("timer_info", 0, 1, timer::f_timer_info),
+    f("timer_pause", 2, 2, timer::f_timer_pause),
+    f("timer_start", 2, 3, timer::f_timer_start),
+    f("timer_stop", 1, 1, timer::f_timer_stop),
+    f("timer_stopall", 0, 0, timer::f_timer_stopall),
+    f("tolower", 1, 1, strfunc::f_tolower),
+    f("toupper", 1, 1, strfunc::f_toupper),
+    f("trim", 1, 3, strfunc::f_trim),
+    f("trunc", 1, 1, f_trunc),
+    f("type", 1, 1, f_type),
+    f("typename", 1, 1, f_typename),
+    f("undotree", 0, 1, undo::f_undotree),
+    f("uniq", 1, 3, listfunc::f_uniq),
+    f("values", 1, 1, listfunc::f_values),
+    f("xor", 2, 2, f_xor),
+];
+
+/// Parse the number at the start of `s` like Vim's vim_str2nr().  With a
+/// `base` of zero "0x", "0b" and "0o" or a leading zero select the base,
+/// otherwise only the prefix of that base is accepted.  A number that does
+/// not fit is clipped.
+pub(crate) fn str2nr(s: &str, base: u32) -> i64 {
+    let (neg, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let bytes = digits.as_bytes();
+    let prefixed = |c: u8, digit_base: u32| {
+        bytes.len() > 2
+            && bytes[0] == b'0'
+            && bytes[1].eq_ignore_ascii_case(&c)
+            && (bytes[2] as char).is_digit(digit_base)
+    };
+    let (radix, digits) = if (base == 0 || base == 16) && prefixed(b'x', 16) {
+        (16, &digits[2..])
+    } else if (base == 0 || base == 2) && prefixed(b'b', 2) {
+        (2, &digits[2..])
+    } else if (base == 0 || base == 8) && prefixed(b'o', 8) {
+        (8, &digits[2..])
+    } else if base == 0 && bytes.first() == Some(&b'0') && {
+        // A leading zero is octal when no 8 or 9 follows.
+        let end = bytes.iter().position(|c| !c.is_ascii_digit()).unwrap_or(bytes.len());
+        bytes[..end].iter().all(|c| (b'0'..=b'7').contains(c))
+    } {
+        (8, digits)
+    } else if base == 0 {
+        (10, digits)
+    } else {
+        (base, digits)
+    };
+    let mut n: i64 = 0;
+    for c in digits.chars() {
+        let Some(d) = c.to_digit(radix) else { break };
+        n = match n.checked_mul(radix as i64).and_then(|n| n.checked_add(d as i64)) {
+            Some(n) => n,
+            None => return if neg { i64::MIN } else { i64::MAX },
+        };
+    }
+    if neg {
+        -n
+    } else {
+        n
+    }
+}
+
+impl Evaluator {
+    /// The value as a Number, like tv_get_number(): a String is converted,
+    /// a Float, List, etc. is an error.
+    pub(crate) fn tv_number(&mut self, val: &Value) -> Result<i64, ()> {
+        match val {
+            Value::Number(n) => Ok(*n),
+            Value::Str(s) => Ok(str2nr(s, 0)),
+            Value::Bool(b) => Ok(*b as i64),
+            Value::Special(_) => Ok(0),
+            Value::Float(_) => self.emsg("E805: Using a Float as a Number".to_string()),
+            Value::List(_) => self.emsg("E745: Using a List as a Number".to_string()),
+            Value::Dict(_) => self.emsg("E728: Using a Dictionary as a Number".to_string()),
+            Value::Func(_) => self.emsg("E703: Using a Funcref as a Number".to_string()),
+            Value::Blob(_) => self.emsg("E974: Using a Blob as a Number".to_string()),
+            Value::Tuple(_) => self.emsg("E1520: Using a Tuple as a Number".to_string()),
+            Value::Job(_) => self.emsg("E910: Using a Job as a Number".to_string()),
+            Value::Channel(_) => self.emsg("E913: Using a Channel as a Number".to_string()),
+            Value::Object(_) => self.emsg("E1391: Using an Object as a Number".to_string()),
+            Value::Class(_) => self.emsg("E1403: Using a Class as a Number".to_string()),
+        }
+    }
+
+    /// The value as a String, like tv_get_string(): a Number is converted,
+    /// a List, Float, etc. is an error.
+    pub(crate) fn tv_string(&mut self, val: &Value) -> Result<String, ()> {
+        match val {
+            Value::Str(s) => Ok(s.clone()),
+            Value::Number(_) | Value::Bool(_) | Value::Special(_) => Ok(val.to_string()),
+            Value::Float(_) => self.emsg("E806: Using a Float as a String".to_string()),
+            Value::List(_) => self.emsg("E730: Using a List as a String".to_string()),
+            Value::Dict(_) => self.emsg("E731: Using a Dictionary as a String".to_string()),
+            Value::Func(_) => self.emsg("E729: Using a Funcref as a String".to_string()),
+            Value::Blob(_) => self.emsg("E976: Using a Blob as a String".to_string()),
+            Value::Tuple(_) => self.emsg("E1523: Using a Tuple as a String".to_string()),
+            _ => self.emsg(format!("E908: Using an invalid value as a String: {}", val.to_quoted())),
+        }
+    }
+
+    /// The value of a Float or Number argument.
+    pub(crate) fn tv_float(&mut self, val: &Value) -> Result<f64, ()> {
+        match val {
+            Value::Number(n) => Ok(*n as f64),
+            Value::Float(f) => Ok(*f),
+            _ => self.emsg("E808: Number or Float required".to_string()),
+        }
+    }
+
+    /// Call builtin function `info` after checking the number of arguments.
+    pub(crate) fn call_builtin(&mut self, info: &FuncInfo, args: &[Value]) -> Result<Value, ()> {
+        if args.len() < info.min_argc {
+            return self.emsg(format!("E119: Not enough arguments for function: {}", info.name));
+        }
+        if args.len() > info.max_argc {
+            return self.emsg(format!("E118: Too many arguments for function: {}", info.name));
+        }
+        (info.func)(self, args)
+    }
+}
+
+fn f_type(_ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Number(args[0].type_nr()))
+}
+
+/// The type of the items of a container: their common type or "any".
+fn items_type(items: &[Value]) -> String {
+    let types: Vec<String> = items.iter().map(typename).collect();
+    match types.first() {
+        Some(first) if types.iter().all(|t| t == first) => first.clone(),
+        Some(_) => "any".to_string(),
+        None => "any".to_string(),
+    }
+}
+
+fn typename(val: &Value) -> String {
+    match val {
+        Value::Number(_) => "number".to_string(),
+        Value::Float(_) => "float".to_string(),
+        Value::Str(_) => "string".to_string(),
+        Value::Bool(_) => "bool".to_string(),
+        Value::Special(rust_core::Special::None) => "none".to_string(),
+        Value::Special(_) => "special".to_string(),
+        Value::Blob(_) => "blob".to_string(),
+        Value::List(l) => format!("list<{}>", items_type(&l.borrow())),
+        Value::Dict(d) => {
+            let items: Vec<Value> = d.borrow().values().cloned().collect();
+            format!("dict<{}>", items_type(&items))
+        }
+        Value::Tuple(t) => format!("tuple<{}>", t.iter().map(typename).collect::<Vec<_>>().join(", ")),
+        Value::Func(_) => "func".to_string(),
+        Value::Job(_) => "job".to_string(),
+        Value::Channel(_) => "channel".to_string(),
+        Value::Class(c) => format!("class<{}>", c.name),
+        Value::Object(o) => format!("object<{}>", o.class.name),
+    }
+}
+
+fn f_typename(_ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Str(typename(&args[0])))
+}
+
+/// exists({expr}): "&option", "$ENV", "*func", ":cmd", "#group#event" or a
+/// variable name, possibly with an index: "l[1]", "d.key".  For ":cmd" the
+/// result is 2 for a full name, 1 for an abbreviation and 3 when several
+/// user commands match.
+fn f_exists(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let name = ev.tv_string(&args[0])?;
+    let result = match name.chars().next() {
+        Some('&' | '+') => {
+            let opt = &name[1..];
+            let opt = opt.strip_prefix("l:").or_else(|| opt.strip_prefix("g:")).unwrap_or(opt);
+            ev.get_option(opt).is_some() as i64
+        }
+        Some('$') => std::env::var_os(&name[1..]).is_some() as i64,
+        Some('*') => {
+            let fname = &name[1..];
+            (ev.funcs.contains_key(fname) || ev.find_func(&ev.func_name(fname)).is_some()) as i64
+        }
+        Some(':') => ev.command_exists(&name[1..]),
+        Some('#') => ev.autocmd_exists(&name[1..]) as i64,
+        _ => ev.var_exists(&name) as i64,
+    };
+    Ok(Value::Number(result))
+}
+
+/// Features for has(), with whether they are supported.  The unsupported
+/// ones are only known to has() with {check}.
+const FEATURES: &[(&str, bool)] = &[
+    ("autocmd", true),
+    ("clipboard", false),
+    ("cmdline_compl", true),
+    ("cryptv", true),
+    ("eval", true),
+    ("float", true),
+    ("gui", false),
+    ("gui_running", false),
+    ("lambda", true),
+    ("mac", cfg!(target_os = "macos")),
+    ("num64", true),
+    ("python3", false),
+    ("sodium", true),
+    ("terminal", false),
+    ("timers", true),
+    ("unix", cfg!(unix)),
+    ("user_commands", true),
+    ("vim_starting", false),
+    ("win32", cfg!(windows)),
+];
+
+/// The version has() compares "patch-9.1.0" with, like v:version.
+const VERSION: (u32, u32, u32) = (9, 1, 0);
+
+/// has({feature} [, {check}]): whether {feature} is supported, with {check}
+/// whether it is a known feature.  "patch-X.Y.Z" is supported up to
+/// [`VERSION`], no patches of the current version are included.
+fn f_has(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let name = ev.tv_string(&args[0])?.to_ascii_lowercase();
+    let check = ev.opt_number(args, 1, 0)? != 0;
+    let supported = if let Some(version) = name.strip_prefix("patch-") {
+        let nrs: Vec<Option<u32>> = version.split('.').map(|nr| nr.parse().ok()).collect();
+        match nrs[..] {
+            [Some(major), Some(minor), Some(patch)] => (major, minor, patch) <= VERSION,
+            _ => false,
+        }
+    } else {
+        FEATURES.iter().any(|&(feature, supported)| feature == name && (supported || check))
+    };
+    Ok(Value::Number(supported as i64))
+}
+
+/// str2nr({string} [, {base} [, {quoted}]]): leading white space is
+/// skipped, with {quoted} single quotes between digits are ignored.
+fn f_str2nr(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let base = match args.get(1) {
+        Some(base) => ev.tv_number(base)?,
+        None => 10,
+    };
+    if ![2, 8, 10, 16].contains(&base) {
+        return ev.emsg("E474: Invalid argument".to_string());
+    }
+    let mut s = ev.tv_string(&args[0])?.trim_start().to_string();
+    if let Some(quoted) = args.get(2) {
+        if ev.tv_number(quoted)? != 0 {
+            s = s.replace('\'', "");
+        }
+    }
+    let (neg, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(&s)),
+    };
+    let n = str2nr(digits, base as u32);
+    Ok(Value::Number(if neg { n.wrapping_neg() } else { n }))
+}
+
+/// str2float({string} [, {quoted}]): the Float at the start of {string}.
+fn f_str2float(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let mut s = ev.tv_string(&args[0])?.trim_start().to_string();
+    if let Some(quoted) = args.get(1) {
+        if ev.tv_number(quoted)? != 0 {
+            s = s.replace('\'', "");
+        }
+    }
+    let (neg, rest) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(&s)),
+    };
+    let lower = rest.to_ascii_lowercase();
+    let f = if lower.starts_with("inf") {
+        f64::INFINITY
+    } else if lower.starts_with("nan") {
+        f64::NAN
+    } else {
+        // The longest prefix that is a valid float: digits, a fraction and
+        // an exponent.
+        let bytes = rest.as_bytes();
+        let mut end = 0;
+        while end < bytes.len() && bytes[end].is_ascii_digit() {
+            end += 1;
+        }
+        if bytes.get(end) == Some(&b'.') {
+            end += 1;
+            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                end += 1;
+            }
+        }
+        if matches!(bytes.get(end), Some(b'e' | b'E')) {
+            let mut exp = end + 1;
+            if matches!(bytes.get(exp), Some(b'+' | b'-')) {
+                exp += 1;
+            }
+            if bytes.get(exp).is_some_and(u8::is_ascii_digit) {
+                end = exp;
+                while end < bytes.len() && bytes[end].is_ascii_digit() {
+                    end += 1;
+                }
+            }
+        }
+        rest[..end].parse().unwrap_or(0.0)
+    };
+    Ok(Value::Float(if neg { -f } else { f }))
+}
+
+fn f_float2nr(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let f = ev.tv_float(&args[0])?;
+    // Out of range values are clipped, NaN becomes zero.
+    Ok(Value::Number(f as i64))
+}
+
+fn f_abs(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    match &args[0] {
+        Value::Float(f) => Ok(Value::Float(f.abs())),
+        val => {
+            let n = ev.tv_number(val)?;
+            Ok(Value::Number(if n == i64::MIN { i64::MAX } else { n.abs() }))
+        }
+    }
+}
+
+/// A function of one Float: ceil(), sqrt() and friends.
+fn float_func(ev: &mut Evaluator, args: &[Value], func: fn(f64) -> f64) -> Result<Value, ()> {
+    let f = ev.tv_float(&args[0])?;
+    Ok(Value::Float(func(f)))
+}
+
+fn f_ceil(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    float_func(ev, args, f64::ceil)
+}
+
+fn f_floor(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    float_func(ev, args, f64::floor)
+}
+
+fn f_round(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    float_func(ev, args, f64::round)
+}
+
+fn f_trunc(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    float_func(ev, args, f64::trunc)
+}
+
+fn f_sqrt(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    float_func(ev, args, f64::sqrt)
+}
+
+fn f_exp(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    float_func(ev, args, f64::exp)
+}
+
+fn f_log(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    float_func(ev, args, f64::ln)
+}
+
+fn f_log10(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    float_func(ev, args, f64::log10)
+}
+
+fn f_pow(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let x = ev.tv_float(&args[0])?;
+    let y = ev.tv_float(&args[1])?;
+    Ok(Value::Float(x.powf(y)))
+}
+
+fn f_fmod(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let x = ev.tv_float(&args[0])?;
+    let y = ev.tv_float(&args[1])?;
+    Ok(Value::Float(x % y))
+}
+
+/// isinf(): 1 for positive and -1 for negative infinity.
+fn f_isinf(_ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Number(match args[0] {
+        Value::Float(f) if f.is_infinite() => f.signum() as i64,
+        _ => 0,
+    }))
+}
+
+fn f_isnan(_ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Number(matches!(args[0], Value::Float(f) if f.is_nan()) as i64))
+}
+
+/// A bitwise operation on two Numbers.
+fn bitwise(ev: &mut Evaluator, args: &[Value], op: fn(i64, i64) -> i64) -> Result<Value, ()> {
+    let a = ev.tv_number(&args[0])?;
+    let b = ev.tv_number(&args[1])?;
+    Ok(Value::Number(op(a, b)))
+}
+
+fn f_and(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    bitwise(ev, args, |a, b| a & b)
+}
+
+fn f_or(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    bitwise(ev, args, |a, b| a | b)
+}
+
+fn f_xor(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    bitwise(ev, args, |a, b| a ^ b)
+}
+
+fn f_json_encode(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    match args[0].to_json() {
+        Ok(json) => Ok(Value::Str(json)),
+        Err(msg) => ev.emsg(msg),
+    }
+}
+
+fn f_json_decode(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let text = ev.tv_string(&args[0])?;
+    match Value::from_json(&text) {
+        Ok(val) => Ok(val),
+        Err(msg) => ev.emsg(msg),
+    }
+}
@@ -0,0 +1,1405 @@
+//! Executing legacy Vim script: the Ex commands of a sourced file or of a
+//! `:function` body, with `:if`, `:while`, `:for` and `:try` blocks.
+//!
+//! A script is split into commands and parsed into a tree of statements
+//! first; expressions are parsed when a command is executed, like Vim does.
+//! Errors are handled like in Vim: inside `:try` they turn into an exception
+//! "Vim(cmd):E123: ...", otherwise the message is reported and execution
+//! continues with the next command, unless the function was defined with
+//! the "abort" attribute.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use rust_scriptfile::read_script;
+
+use crate::func::truthy;
+use crate::{eval, parse_expr, skip_ws, Evaluator, Expr, ListRef, Tokenizer, Value};
+
+/// Handler of an Ex command added with [`Evaluator::add_ex_command`].
+pub type ExCmdFn = fn(&mut Evaluator, &ExArg) -> Result<(), ()>;
+
+/// The arguments of an Ex command.
+pub struct ExArg<'a> {
+    pub bang: bool,
+    /// The range before the command name, e.g. "1,3".
+    pub range: &'a str,
+    pub arg: &'a str,
+}
+
+/// The builtin commands, with the length of their shortest abbreviation.
+const COMMANDS: &[(&str, usize)] = &[
+    ("let", 3),
+    ("unlet", 3),
+    ("lockvar", 5),
+    ("unlockvar", 4),
+    ("if", 2),
+    ("elseif", 5),
+    ("else", 2),
+    ("endif", 2),
+    ("while", 2),
+    ("endwhile", 4),
+    ("for", 3),
+    ("endfor", 5),
+    ("break", 4),
+    ("continue", 3),
+    ("try", 3),
+    ("catch", 3),
+    ("finally", 4),
+    ("endtry", 4),
+    ("throw", 2),
+    ("function", 2),
+    ("endfunction", 4),
+    ("delfunction", 4),
+    ("return", 4),
+    ("call", 3),
+    ("execute", 3),
+    ("source", 2),
+    ("finish", 4),
+    ("echo", 2),
+    ("echon", 5),
+    ("echomsg", 5),
+    ("echoerr", 5),
+    ("autocmd", 2),
+    ("augroup", 3),
+    ("doautocmd", 2),
+    ("doautoall", 7),
+    ("command", 3),
+    ("delcommand", 4),
+    ("comclear", 4),
+    ("recover", 3),
+    ("undo", 1),
+    ("undojoin", 5),
+    ("redo", 3),
+    ("earlier", 2),
+    ("later", 3),
+    ("write", 1),
+    ("wundo", 2),
+    ("rundo", 4),
+    ("args", 2),
+    ("argadd", 4),
+    ("argdelete", 4),
+    ("argdo", 5),
+    ("argedit", 4),
+    ("argglobal", 4),
+    ("arglocal", 4),
+    ("argument", 4),
+    ("next", 1),
+    ("Next", 1),
+    ("previous", 4),
+    ("first", 3),
+    ("rewind", 3),
+    ("last", 2),
+    ("ls", 2),
+    ("buffers", 7),
+    ("files", 5),
+    ("badd", 3),
+    ("bdelete", 2),
+    ("bwipeout", 2),
+    ("bunload", 3),
+];
+
+/// Commands that see a "|" as part of their argument.
+const BAR_IN_ARG: &[(&str, usize)] =
+    &[("normal", 4), ("global", 1), ("vglobal", 1), ("autocmd", 2), ("argdo", 5), ("command", 3)];
+
+/// The full names of the builtin commands.
+pub(crate) fn command_names() -> impl Iterator<Item = &'static str> {
+    COMMANDS.iter().map(|(name, _)| *name)
+}
+
+fn full_name(word: &str, table: &[(&'static str, usize)]) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(name, min)| word.len() >= *min && name.starts_with(word))
+        .map(|(name, _)| *name)
+}
+
+/// One Ex command and the line it is in.
+#[derive(Debug, Clone)]
+pub(crate) struct Cmd {
+    pub(crate) lnum: usize,
+    /// The full name for a builtin command, as typed otherwise.
+    pub(crate) name: String,
+    pub(crate) bang: bool,
+    pub(crate) range: String,
+    pub(crate) arg: String,
+    /// The whole command, for a line that is an expression.
+    text: String,
+}
+
+fn parse_cmd(lnum: usize, text: &str) -> Cmd {
+    let text = text.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+    let range_len = text
+        .find(|c: char| !(c.is_ascii_digit() || ",.$%".contains(c)))
+        .unwrap_or(text.len());
+    let (range, rest) = text.split_at(range_len);
+    // The name of a user command may contain digits.
+    let user = rest.starts_with(|c: char| c.is_ascii_uppercase());
+    let name_len = rest
+        .find(|c: char| !(c.is_ascii_alphabetic() || (user && c.is_ascii_digit())))
+        .unwrap_or(rest.len());
+    let (word, rest) = rest.split_at(name_len);
+    let (bang, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    Cmd {
+        lnum,
+        name: full_name(word, COMMANDS).map_or_else(|| word.to_string(), str::to_string),
+        bang,
+        range: range.to_string(),
+        arg: rest.trim().to_string(),
+        text: text.to_string(),
+    }
+}
+
+/// Split a line at "|" command separators.  A "|" inside a string or in
+/// "||" does not separate commands.  The command after the modifiers
+/// decides: a user command splits its own argument when it has "-bar".
+fn split_bar(line: &str) -> Vec<&str> {
+    let mut first = parse_cmd(0, line);
+    while rust_usercmd::modifier(&first.name).is_some() {
+        first = parse_cmd(0, &first.arg);
+    }
+    if full_name(&first.name, BAR_IN_ARG).is_some() || first.name.starts_with(|c: char| c.is_ascii_uppercase()) {
+        return vec![line];
+    }
+    split_at_bars(line)
+}
+
+pub(crate) fn split_at_bars(line: &str) -> Vec<&str> {
+    let bytes = line.as_bytes();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        match quote {
+            Some(b'"') if c == b'\\' => i += 1,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == b'"' || c == b'\'' => quote = Some(c),
+            None if c == b'|' => {
+                if bytes.get(i + 1) == Some(&b'|') {
+                    i += 1;
+                } else {
+                    parts.push(&line[start..i]);
+                    start = i + 1;
+                }
+            }
+            None => {}
+        }
+        i += 1;
+    }
+    parts.push(&line[start..]);
+    parts
+}
+
+/// Number the lines of a script, starting at one.
+pub(crate) fn split_lines(text: &str) -> Vec<(usize, String)> {
+    text.lines().enumerate().map(|(i, l)| (i + 1, l.to_string())).collect()
+}
+
+/// Join continuation lines, starting with a backslash, to the line before
+/// them and drop `"\ ` comments between them.
+fn join_continuation(lines: &[(usize, String)]) -> Vec<(usize, String)> {
+    let mut joined: Vec<(usize, String)> = Vec::new();
+    for (lnum, line) in lines {
+        let trimmed = line.trim_start();
+        if let (Some(rest), Some(last)) = (trimmed.strip_prefix('\\'), joined.last_mut()) {
+            last.1.push_str(rest);
+        } else if !trimmed.starts_with("\"\\ ") {
+            joined.push((*lnum, line.clone()));
+        }
+    }
+    joined
+}
+
+#[derive(Debug)]
+pub(crate) enum Stmt {
+    Cmd(Cmd),
+    /// `:if` and `:elseif` conditions with their commands, and the `:else`
+    /// commands.
+    If(Vec<(Cmd, Vec<Stmt>)>, Option<Vec<Stmt>>),
+    While(Cmd, Vec<Stmt>),
+    For(Cmd, Vec<Stmt>),
+    Try {
+        cmd: Cmd,
+        body: Vec<Stmt>,
+        catches: Vec<(Cmd, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    },
+    /// A `:function` definition with the lines of its body.
+    Function(Cmd, Vec<(usize, String)>),
+}
+
+impl Stmt {
+    fn cmd(&self) -> &Cmd {
+        match self {
+            Stmt::Cmd(cmd) | Stmt::While(cmd, _) | Stmt::For(cmd, _) | Stmt::Function(cmd, _) => cmd,
+            Stmt::Try { cmd, .. } => cmd,
+            Stmt::If(branches, _) => &branches[0].0,
+        }
+    }
+}
+
+enum Item {
+    Cmd(Cmd),
+    Function(Cmd, Vec<(usize, String)>),
+}
+
+fn is_func_def(cmd: &Cmd) -> bool {
+    cmd.name == "function" && cmd.arg.contains('(')
+}
+
+struct Parser<'a> {
+    lines: &'a [(usize, String)],
+    pos: usize,
+    /// Commands that followed a "|" on the current line.
+    pending: VecDeque<Cmd>,
+    /// Number of enclosing `:while` and `:for` loops.
+    loops: usize,
+}
+
+impl Parser<'_> {
+    fn next_item(&mut self) -> Result<Option<Item>, String> {
+        if let Some(cmd) = self.pending.pop_front() {
+            return Ok(Some(Item::Cmd(cmd)));
+        }
+        let lines = self.lines;
+        while let Some((lnum, line)) = lines.get(self.pos) {
+            self.pos += 1;
+            let mut parts = split_bar(line)
+                .into_iter()
+                .map(str::trim)
+                .filter(|p| !p.is_empty() && !p.starts_with('"'));
+            let Some(first) = parts.next() else { continue };
+            let cmd = parse_cmd(*lnum, first);
+            if is_func_def(&cmd) {
+                let body = self.function_body()?;
+                return Ok(Some(Item::Function(cmd, body)));
+            }
+            self.pending.push_back(cmd);
+            self.pending.extend(parts.map(|p| parse_cmd(*lnum, p)));
+            return Ok(self.pending.pop_front().map(Item::Cmd));
+        }
+        Ok(None)
+    }
+
+    /// The lines up to the matching `:endfunction`.
+    fn function_body(&mut self) -> Result<Vec<(usize, String)>, String> {
+        let lines = self.lines;
+        let mut depth = 1;
+        let mut body = Vec::new();
+        while let Some((lnum, line)) = lines.get(self.pos) {
+            self.pos += 1;
+            let cmd = parse_cmd(*lnum, line.trim());
+            if is_func_def(&cmd) {
+                depth += 1;
+            } else if cmd.name == "endfunction" {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(body);
+                }
+            }
+            body.push((*lnum, line.clone()));
+        }
+        Err("E126: Missing :endfunction".to_string())
+    }
+
+    /// Parse statements until one of the commands in `ends`, which is
+    /// returned with them.
+    fn block(&mut self, ends: &[&str]) -> Result<(Vec<Stmt>, Option<Cmd>), String> {
+        let mut stmts = Vec::new();
+        while let Some(item) = self.next_item()? {
+            let cmd = match item {
+                Item::Function(cmd, body) => {
+                    stmts.push(Stmt::Function(cmd, body));
+                    continue;
+                }
+                Item::Cmd(cmd) => cmd,
+            };
+            if ends.contains(&cmd.name.as_str()) {
+                return Ok((stmts, Some(cmd)));
+            }
+            let stmt = match cmd.name.as_str() {
+                "if" => self.if_block(cmd)?,
+                "while" | "for" => {
+                    let end = if cmd.name == "while" { "endwhile" } else { "endfor" };
+                    self.loops += 1;
+                    let (body, found) = self.block(&[end])?;
+                    self.loops -= 1;
+                    if found.is_none() {
+                        return Err(format!("E170: Missing :{}", end));
+                    }
+                    if cmd.name == "while" {
+                        Stmt::While(cmd, body)
+                    } else {
+                        Stmt::For(cmd, body)
+                    }
+                }
+                "try" => self.try_block(cmd)?,
+                "break" if self.loops == 0 => return Err("E587: :break without :while or :for".to_string()),
+                "continue" if self.loops == 0 => {
+                    return Err("E586: :continue without :while or :for".to_string())
+                }
+                "elseif" => return Err("E582: :elseif without :if".to_string()),
+                "else" => return Err("E581: :else without :if".to_string()),
+                "endif" => return Err("E580: :endif without :if".to_string()),
+                "endwhile" => return Err("E588: :endwhile without :while".to_string()),
+                "endfor" => return Err("E588: :endfor without :for".to_string()),
+                "catch" => return Err("E603: :catch without :try".to_string()),
+                "finally" => return Err("E606: :finally without :try".to_string()),
+                "endtry" => return Err("E602: :endtry without :try".to_string()),
+                "endfunction" => return Err("E193: :endfunction not inside a function".to_string()),
+                _ => Stmt::Cmd(cmd),
+            };
+            stmts.push(stmt);
+        }
+        Ok((stmts, None))
+    }
+
+    fn if_block(&mut self, cmd: Cmd) -> Result<Stmt, String> {
+        const ENDS: &[&str] = &["elseif", "else", "endif"];
+        let mut branches = Vec::new();
+        let mut cond = cmd;
+        loop {
+            let (body, end) = self.block(ENDS)?;
+            branches.push((cond, body));
+            let end = end.ok_or("E171: Missing :endif")?;
+            match end.name.as_str() {
+                "elseif" => cond = end,
+                "else" => {
+                    let (body, end) = self.block(ENDS)?;
+                    return match end.as_ref().map(|c| c.name.as_str()) {
+                        Some("endif") => Ok(Stmt::If(branches, Some(body))),
+                        Some("else") => Err("E583: Multiple :else".to_string()),
+                        Some(_) => Err("E584: :elseif after :else".to_string()),
+                        None => Err("E171: Missing :endif".to_string()),
+                    };
+                }
+                _ => return Ok(Stmt::If(branches, None)),
+            }
+        }
+    }
+
+    fn try_block(&mut self, cmd: Cmd) -> Result<Stmt, String> {
+        const ENDS: &[&str] = &["catch", "finally", "endtry"];
+        let (body, mut end) = self.block(ENDS)?;
+        let mut catches = Vec::new();
+        let mut finally = None;
+        loop {
+            let clause = end.ok_or("E600: Missing :endtry")?;
+            match clause.name.as_str() {
+                "catch" if finally.is_some() => return Err("E604: :catch after :finally".to_string()),
+                "finally" if finally.is_some() => return Err("E607: Multiple :finally".to_string()),
+                "catch" => {
+                    let (stmts, next) = self.block(ENDS)?;
+                    catches.push((clause, stmts));
+                    end = next;
+                }
+                "finally" => {
+                    let (stmts, next) = self.block(ENDS)?;
+                    finally = Some(stmts);
+                    end = next;
+                }
+                _ => return Ok(Stmt::Try { cmd, body, catches, finally }),
+            }
+        }
+    }
+}
+
+/// Parse script lines into statements.
+pub(crate) fn parse_script(lines: &[(usize, String)]) -> Result<Vec<Stmt>, String> {
+    let lines = join_continuation(lines);
+    let mut parser = Parser { lines: &lines, pos: 0, pending: VecDeque::new(), loops: 0 };
+    parser.block(&[]).map(|(stmts, _)| stmts)
+}
+
+/// How execution continues after a command.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+    Finish,
+}
+
+/// A thrown exception, caught with `:catch`.
+#[derive(Debug, Clone)]
+pub(crate) struct Exception {
+    value: String,
+    throwpoint: String,
+}
+
+/// A variable with indexes that can be assigned to: "name", "d.key" or
+/// "l[idx]".
+struct Lval {
+    name: String,
+    path: Vec<Expr>,
+    /// `[first : last]` after the path, either may be omitted.
+    slice: Option<(Option<Expr>, Option<Expr>)>,
+}
+
+/// The target of `:let` and `:for`, a variable or a list of variables.
+enum LetTarget {
+    Single(Lval),
+    /// `[a, b; rest]`
+    List(Vec<Lval>, Option<Lval>),
+}
+
+fn parse_lval(tokens: &mut Tokenizer) -> Option<Lval> {
+    skip_ws(&mut tokens.iter);
+    // An option or register cannot have an index.
+    match tokens.iter.peek() {
+        Some('&') => {
+            tokens.iter.next();
+            let mut name = String::from("&");
+            for prefix in ["l:", "g:"] {
+                if tokens.skip_token(prefix) {
+                    name.push_str(prefix);
+                }
+            }
+            while let Some(c) = tokens.iter.peek().copied().filter(char::is_ascii_alphabetic) {
+                name.push(c);
+                tokens.iter.next();
+            }
+            return Some(Lval { name, path: Vec::new(), slice: None });
+        }
+        Some('@') => {
+            tokens.iter.next();
+            let reg = tokens.iter.next()?;
+            return Some(Lval { name: format!("@{}", reg), path: Vec::new(), slice: None });
+        }
+        _ => {}
+    }
+    let env = tokens.skip_token("$");
+    let name = tokens.parse_identifier()?;
+    let name = if env { format!("${}", name) } else { name };
+    let mut path = Vec::new();
+    let mut slice = None;
+    loop {
+        match tokens.iter.peek() {
+            // A slice can only be the last index.
+            _ if slice.is_some() => break,
+            Some('[') => {
+                tokens.iter.next();
+                let first = if tokens.peek_non_ws() == Some(':') { None } else { Some(parse_expr(tokens).ok()?) };
+                if tokens.skip_token(":") {
+                    let last = if tokens.peek_non_ws() == Some(']') { None } else { Some(parse_expr(tokens).ok()?) };
+                    slice = Some((first, last));
+                } else {
+                    path.push(first?);
+                }
+                if !tokens.skip_token("]") {
+                    return None;
+                }
+            }
+            Some('.') => {
+                let mut probe = tokens.iter.clone();
+                probe.next();
+                if !probe.peek().is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    break;
+                }
+                tokens.iter = probe;
+                let mut key = String::new();
+                while let Some(&c) = tokens.iter.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    key.push(c);
+                    tokens.iter.next();
+                }
+                path.push(Expr::Str(key));
+            }
+            _ => break,
+        }
+    }
+    Some(Lval { name, path, slice })
+}
+
+fn parse_let_target(tokens: &mut Tokenizer) -> Option<LetTarget> {
+    if !tokens.skip_token("[") {
+        return parse_lval(tokens).map(LetTarget::Single);
+    }
+    let mut names = Vec::new();
+    loop {
+        names.push(parse_lval(tokens)?);
+        if tokens.skip_token("]") {
+            return Some(LetTarget::List(names, None));
+        }
+        if tokens.skip_token(";") {
+            let rest = parse_lval(tokens)?;
+            return tokens.skip_token("]").then_some(LetTarget::List(names, Some(rest)));
+        }
+        if !tokens.skip_token(",") {
+            return None;
+        }
+    }
+}
+
+/// The operator of `:let`: None for "=", otherwise the operator of "+=",
+/// "-=", "*=", "/=", "%=", ".=" and "..=".
+fn parse_let_op(tokens: &mut Tokenizer) -> Option<Option<char>> {
+    for (token, op) in [("..=", '.'), (".=", '.'), ("+=", '+'), ("-=", '-'), ("*=", '*'), ("/=", '/'), ("%=", '%')] {
+        if tokens.skip_token(token) {
+            return Some(Some(op));
+        }
+    }
+    if tokens.skip_token("=") && tokens.iter.peek() != Some(&'=') {
+        return Some(None);
+    }
+    None
+}
+
+/// Match an exception against the pattern of `:catch /pat/`.  Without a
+/// pattern every exception is caught.
+fn catch_matches(arg: &str, value: &str) -> bool {
+    let mut chars = arg.chars();
+    let Some(delim) = chars.next().filter(|&c| c != '"') else { return true };
+    let rest = chars.as_str();
+    let pat = rest.split(delim).next().unwrap_or(rest);
+    rust_regexp::search(pat.as_bytes(), value.as_bytes(), false).is_some()
+}
+
+impl Evaluator {
+    /// Give error `msg`.  The first error of a command is kept, it is
+    /// reported or turned into an exception when the command fails.
+    pub(crate) fn emsg<T>(&mut self, msg: String) -> Result<T, ()> {
+        if self.last_error.is_none() {
+            self.last_error = Some(msg);
+        }
+        Err(())
+    }
+
+    fn report_error(&mut self, msg: String) {
+        self.scopes.vim.insert("errmsg".to_string(), Value::Str(msg.clone()));
+        self.output.push(msg);
+        self.did_emsg += 1;
+    }
+
+    pub(crate) fn message(&mut self, msg: String) {
+        self.output.push(msg);
+    }
+
+    /// Deal with a failed command `cmd`.  Returns Err when execution of the
+    /// commands is to be aborted: for an exception, an error inside `:try`
+    /// or an error in a function with the "abort" attribute.
+    pub(crate) fn handle_error(&mut self, cmd: &str) -> Result<(), ()> {
+        if self.exception.is_some() {
+            return Err(());
+        }
+        if let Some(msg) = self.last_error.take() {
+            if self.try_depth > 0 {
+                self.throw(format!("Vim({}):{}", cmd, msg));
+                return Err(());
+            }
+            self.report_error(msg);
+        }
+        match self.frames.last() {
+            Some(frame) if frame.borrow().abort => Err(()),
+            _ => Ok(()),
+        }
+    }
+
+    fn throw(&mut self, value: String) {
+        let throwpoint = if self.sourcing_name.is_empty() {
+            String::new()
+        } else {
+            format!("{}, line {}", self.sourcing_name, self.sourcing_lnum)
+        };
+        self.exception = Some(crate::ex::Exception { value, throwpoint });
+    }
+
+    /// Start executing the lines of script or function `name`.  Returns
+    /// what is to be passed to [`Evaluator::leave_sourcing`].
+    pub(crate) fn enter_sourcing(&mut self, name: String) -> (String, usize) {
+        let saved = (std::mem::replace(&mut self.sourcing_name, name), self.sourcing_lnum);
+        self.sourcing_lnum = 0;
+        saved
+    }
+
+    pub(crate) fn leave_sourcing(&mut self, saved: (String, usize)) {
+        (self.sourcing_name, self.sourcing_lnum) = saved;
+    }
+
+    fn parse_cmd_expr(&mut self, text: &str) -> Result<Expr, ()> {
+        let mut tokens = Tokenizer::new(text);
+        let Ok(expr) = parse_expr(&mut tokens) else {
+            return self.emsg(format!("E15: Invalid expression: \"{}\"", text.trim()));
+        };
+        match tokens.peek_non_ws() {
+            None | Some('"') => Ok(expr),
+            Some(_) => self.emsg(format!("E488: Trailing characters: {}", tokens.rest().trim())),
+        }
+    }
+
+    fn eval_parsed(&mut self, expr: &Expr, text: &str) -> Result<Value, ()> {
+        let before = self.did_emsg;
+        let result = eval(expr, self);
+        if result.is_err() && self.exception.is_none() && self.did_emsg == before {
+            return self.emsg(format!("E15: Invalid expression: \"{}\"", text.trim()));
+        }
+        result
+    }
+
+    /// Evaluate the expression argument of a command.  A `"` after the
+    /// expression starts a comment.
+    pub(crate) fn eval_cmd_expr(&mut self, text: &str) -> Result<Value, ()> {
+        let expr = self.parse_cmd_expr(text)?;
+        self.eval_parsed(&expr, text)
+    }
+
+    /// Evaluate the white space separated expressions of `:echo` and
+    /// `:execute`.
+    fn eval_cmd_exprs(&mut self, text: &str) -> Result<Vec<Value>, ()> {
+        let mut tokens = Tokenizer::new(text);
+        let mut values = Vec::new();
+        while tokens.peek_non_ws().is_some() {
+            let Ok(expr) = parse_expr(&mut tokens) else {
+                return self.emsg(format!("E15: Invalid expression: \"{}\"", text.trim()));
+            };
+            values.push(self.eval_parsed(&expr, text)?);
+        }
+        Ok(values)
+    }
+
+    pub(crate) fn is_true(&mut self, val: &Value) -> Result<bool, ()> {
+        match val {
+            Value::List(_) => self.emsg("E745: Using a List as a Number".to_string()),
+            Value::Dict(_) => self.emsg("E728: Using a Dictionary as a Number".to_string()),
+            Value::Func(_) => self.emsg("E703: Using a Funcref as a Number".to_string()),
+            _ => truthy(val),
+        }
+    }
+
+    pub(crate) fn exec_stmts(&mut self, stmts: &[Stmt]) -> Result<Flow, ()> {
+        for stmt in stmts {
+            let cmd = stmt.cmd();
+            self.sourcing_lnum = cmd.lnum;
+            self.last_error = None;
+            match self.exec_stmt(stmt) {
+                Ok(Flow::Normal) => {}
+                Ok(flow) => return Ok(flow),
+                Err(()) => self.handle_error(&cmd.name)?,
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_stmt(&mut self, stmt: &Stmt) -> Result<Flow, ()> {
+        match stmt {
+            Stmt::Cmd(cmd) => self.exec_cmd(cmd),
+            Stmt::If(branches, else_body) => {
+                for (cond, body) in branches {
+                    self.sourcing_lnum = cond.lnum;
+                    let val = self.eval_cmd_expr(&cond.arg)?;
+                    if self.is_true(&val)? {
+                        return self.exec_stmts(body);
+                    }
+                }
+                match else_body {
+                    Some(body) => self.exec_stmts(body),
+                    None => Ok(Flow::Normal),
+                }
+            }
+            Stmt::While(cmd, body) => loop {
+                self.sourcing_lnum = cmd.lnum;
+                let val = self.eval_cmd_expr(&cmd.arg)?;
+                if !self.is_true(&val)? {
+                    return Ok(Flow::Normal);
+                }
+                match self.exec_stmts(body)? {
+                    Flow::Normal | Flow::Continue => {}
+                    Flow::Break => return Ok(Flow::Normal),
+                    flow => return Ok(flow),
+                }
+            },
+            Stmt::For(cmd, body) => self.exec_for(cmd, body),
+            Stmt::Try { body, catches, finally, .. } => self.exec_try(body, catches, finally.as_deref()),
+            Stmt::Function(cmd, body) => self.define_function(cmd, body).map(|()| Flow::Normal),
+        }
+    }
+
+    fn exec_cmd(&mut self, cmd: &Cmd) -> Result<Flow, ()> {
+        let arg = cmd.arg.as_str();
+        match cmd.name.as_str() {
+            "let" => self.ex_let(arg)?,
+            "unlet" => self.ex_unlet(arg, cmd.bang)?,
+            "lockvar" => self.ex_lockvar(arg, cmd.bang, true)?,
+            "unlockvar" => self.ex_lockvar(arg, cmd.bang, false)?,
+            "call" => self.ex_call(&cmd.range, arg)?,
+            "execute" => {
+                let text = self.eval_cmd_exprs(arg)?.iter().map(Value::to_string).collect::<Vec<_>>().join(" ");
+                let lines: Vec<(usize, String)> = text.lines().map(|l| (cmd.lnum, l.to_string())).collect();
+                let stmts = match parse_script(&lines) {
+                    Ok(stmts) => stmts,
+                    Err(msg) => return self.emsg(msg),
+                };
+                return self.exec_stmts(&stmts);
+            }
+            "echo" | "echon" | "echomsg" | "echoerr" => {
+                let msg = self.eval_cmd_exprs(arg)?.iter().map(Value::to_string).collect::<Vec<_>>().join(" ");
+                if cmd.name == "echoerr" {
+                    return self.emsg(msg);
+                }
+                self.message(msg);
+            }
+            "throw" => {
+                if arg.is_empty() {
+                    return self.emsg("E471: Argument required".to_string());
+                }
+                let val = self.eval_cmd_expr(arg)?.to_string();
+                if val.starts_with("Vim") {
+                    return self.emsg("E608: Cannot :throw exceptions with 'Vim' prefix".to_string());
+                }
+                self.throw(val);
+                return Err(());
+            }
+            "return" => {
+                if !self.frames.last().is_some_and(|f| f.borrow().legacy) {
+                    return self.emsg("E133: :return not inside a function".to_string());
+                }
+                let val = if arg.is_empty() || arg.starts_with('"') { Value::Number(0) } else { self.eval_cmd_expr(arg)? };
+                return Ok(Flow::Return(val));
+            }
+            "break" => return Ok(Flow::Break),
+            "continue" => return Ok(Flow::Continue),
+            "finish" => return Ok(Flow::Finish),
+            "source" => {
+                if arg.is_empty() {
+                    return self.emsg("E471: Argument required".to_string());
+                }
+                self.source(&expand_home(arg))?;
+            }
+            "function" => {
+                let lines = self.function_list();
+                let name = self.func_name(arg);
+                let prefix = format!("function {}(", name);
+                let lines: Vec<String> = lines.into_iter().filter(|l| arg.is_empty() || l.starts_with(&prefix)).collect();
+                if lines.is_empty() && !arg.is_empty() {
+                    return self.emsg(format!("E123: Undefined function: {}", arg));
+                }
+                for line in lines {
+                    self.message(line);
+                }
+            }
+            "delfunction" => self.delete_function(arg, cmd.bang)?,
+            "autocmd" => self.ex_autocmd(arg, cmd.bang)?,
+            "augroup" => self.ex_augroup(arg, cmd.bang)?,
+            "doautocmd" | "doautoall" => self.ex_doautocmd(arg, cmd.name == "doautoall")?,
+            name if rust_map::map_command(name, cmd.bang).is_some() => self.ex_map(name, cmd.bang, arg)?,
+            name if rust_map::abbr_command(name).is_some() => self.ex_abbr(name, arg)?,
+            "command" => self.ex_command(arg, cmd.bang)?,
+            "delcommand" => self.ex_delcommand(arg)?,
+            "comclear" => self.usercmds.clear(self.curbuf()),
+            "recover" => self.ex_recover(arg, cmd.bang)?,
+            "undo" => self.ex_undo(arg)?,
+            "undojoin" => self.ex_undojoin()?,
+            "redo" => self.ex_redo()?,
+            "earlier" | "later" => self.ex_later(arg, cmd.name == "earlier")?,
+            "write" => self.ex_write(&cmd.range, arg, cmd.bang)?,
+            "wundo" => self.ex_wundo(arg, cmd.bang)?,
+            "rundo" => self.ex_rundo(arg)?,
+            name if rust_arglist::command_name(name) == Some(name) => self.ex_arglist(cmd.lnum, &cmd.text)?,
+            name if rust_buffer::command_name(name) == Some(name) => self.ex_buflist(&cmd.text)?,
+            name if rust_usercmd::modifier(name).is_some() => {
+                let full = rust_usercmd::modifier(name).unwrap_or_default();
+                self.cmdmods.push(format!("{}{}{}", cmd.range, full, if cmd.bang { "!" } else { "" }));
+                let result = self.exec_cmd(&parse_cmd(cmd.lnum, arg));
+                self.cmdmods.pop();
+                return result;
+            }
+            _ => {
+                let handler = self
+                    .ex_commands
+                    .iter()
+                    .find(|(name, min, _)| cmd.name.len() >= *min && name.starts_with(cmd.name.as_str()))
+                    .map(|(_, _, func)| *func);
+                if let Some(func) = handler.filter(|_| !cmd.name.is_empty()) {
+                    func(self, &ExArg { bang: cmd.bang, range: &cmd.range, arg })?;
+                } else if cmd.name.starts_with(|c: char| c.is_ascii_uppercase()) {
+                    if let Some(flow) = self.exec_user_cmd(cmd)? {
+                        return Ok(flow);
+                    }
+                    return self.emsg(format!("E492: Not an editor command: {}", cmd.text));
+                } else if self.expr_lines {
+                    let val = self.eval_cmd_expr(&cmd.text)?;
+                    self.last_value = Some(val);
+                } else if !(cmd.name.is_empty() && arg.is_empty()) {
+                    return self.emsg(format!("E492: Not an editor command: {}", cmd.text));
+                }
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_for(&mut self, cmd: &Cmd, body: &[Stmt]) -> Result<Flow, ()> {
+        let Some((target, expr)) = cmd.arg.split_once(" in ") else {
+            return self.emsg("E690: Missing \"in\" after :for".to_string());
+        };
+        let Some(target) = parse_let_target(&mut Tokenizer::new(target)) else {
+            return self.emsg(format!("E475: Invalid argument: {}", cmd.arg));
+        };
+        let val = self.eval_cmd_expr(expr)?;
+        let chars: Vec<Value> = match &val {
+            Value::List(_) => Vec::new(),
+            Value::Str(s) => s.chars().map(|c| Value::Str(c.to_string())).collect(),
+            _ => return self.emsg("E1098: String, List or Blob required".to_string()),
+        };
+        let mut idx = 0;
+        loop {
+            // Items added to the list in the loop are also used.
+            let item = match &val {
+                Value::List(list) => list.borrow().get(idx).cloned(),
+                _ => chars.get(idx).cloned(),
+            };
+            let Some(item) = item else { return Ok(Flow::Normal) };
+            idx += 1;
+            self.assign_target(&target, None, item)?;
+            match self.exec_stmts(body)? {
+                Flow::Normal | Flow::Continue => {}
+                Flow::Break => return Ok(Flow::Normal),
+                flow => return Ok(flow),
+            }
+        }
+    }
+
+    fn exec_try(
+        &mut self,
+        body: &[Stmt],
+        catches: &[(Cmd, Vec<Stmt>)],
+        finally: Option<&[Stmt]>,
+    ) -> Result<Flow, ()> {
+        self.try_depth += 1;
+        let mut result = self.exec_stmts(body);
+        self.try_depth -= 1;
+        if result.is_err() {
+            if let Some(exc) = self.exception.take() {
+                match catches.iter().find(|(cmd, _)| catch_matches(&cmd.arg, &exc.value)) {
+                    Some((cmd, clause)) => {
+                        self.sourcing_lnum = cmd.lnum;
+                        let vim = &mut self.scopes.vim;
+                        let saved = (
+                            vim.insert("exception".to_string(), Value::Str(exc.value)),
+                            vim.insert("throwpoint".to_string(), Value::Str(exc.throwpoint)),
+                        );
+                        result = self.exec_stmts(clause);
+                        let vim = &mut self.scopes.vim;
+                        for (name, val) in [("exception", saved.0), ("throwpoint", saved.1)] {
+                            vim.insert(name.to_string(), val.unwrap_or(Value::Str(String::new())));
+                        }
+                    }
+                    None => self.exception = Some(exc),
+                }
+            }
+        }
+        if let Some(finally) = finally {
+            // An exception that was not caught is thrown again after the
+            // finally clause, unless that clause ends in another way.
+            let pending = self.exception.take();
+            match self.exec_stmts(finally) {
+                Ok(Flow::Normal) => self.exception = pending,
+                other => result = other,
+            }
+        }
+        result
+    }
+
+    fn ex_let(&mut self, arg: &str) -> Result<(), ()> {
+        let mut tokens = Tokenizer::new(arg);
+        let target = parse_let_target(&mut tokens);
+        let op = parse_let_op(&mut tokens);
+        let (Some(target), Some(op)) = (target, op) else {
+            return self.emsg(format!("E475: Invalid argument: {}", arg));
+        };
+        let val = self.eval_cmd_expr(&tokens.rest())?;
+        if self.expr_lines {
+            self.last_value = Some(val.clone());
+        }
+        self.assign_target(&target, op, val)
+    }
+
+    fn ex_unlet(&mut self, arg: &str, bang: bool) -> Result<(), ()> {
+        let mut tokens = Tokenizer::new(arg);
+        while tokens.peek_non_ws().is_some_and(|c| c != '"') {
+            let Some(lval) = parse_lval(&mut tokens) else {
+                return self.emsg(format!("E475: Invalid argument: {}", arg));
+            };
+            if lval.slice.is_some() {
+                let (list, first, last) = self.resolve_slice(&lval)?;
+                self.check_value_lock(list.lock(), &lval.name)?;
+                let len = list.borrow().len();
+                list.borrow_mut().drain(first.min(len)..last.map_or(len, |last| (last + 1).min(len)));
+                continue;
+            }
+            if lval.path.is_empty() {
+                if !bang || self.lookup_var(&lval.name).is_some() {
+                    self.unlet_var(&lval.name)?;
+                }
+                continue;
+            }
+            let (container, key) = self.resolve_lval(&lval)?;
+            match &container {
+                Value::List(list) => {
+                    self.check_value_lock(list.lock(), &lval.name)?;
+                    let len = list.borrow().len();
+                    let idx = self.list_index(len, &key)?;
+                    list.borrow_mut().remove(idx);
+                }
+                Value::Dict(dict) => {
+                    self.check_value_lock(dict.lock(), &lval.name)?;
+                    let key = key.to_string();
+                    if dict.borrow_mut().remove(&key).is_none() && !bang {
+                        return self.emsg(format!("E716: Key not present in Dictionary: \"{}\"", key));
+                    }
+                }
+                _ => return self.emsg("E689: Can only index a List, Dictionary or Blob".to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute `:lockvar[!] [depth] {name} ...` or `:unlockvar`.  The
+    /// variable is locked and the Lists and Dictionaries in its value as
+    /// deep as [depth] says, two levels by default and all of them with "!".
+    /// For an item only its value is locked.
+    fn ex_lockvar(&mut self, arg: &str, bang: bool, lock: bool) -> Result<(), ()> {
+        let arg = arg.trim_start();
+        let digits = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+        let depth = match arg[..digits].parse() {
+            _ if bang => -1,
+            Ok(depth) => depth,
+            Err(_) => 2,
+        };
+        let mut tokens = Tokenizer::new(&arg[digits..]);
+        if tokens.peek_non_ws().is_none_or(|c| c == '"') {
+            return self.emsg("E471: Argument required".to_string());
+        }
+        while tokens.peek_non_ws().is_some_and(|c| c != '"') {
+            let Some(lval) = parse_lval(&mut tokens) else {
+                return self.emsg(format!("E475: Invalid argument: {}", arg));
+            };
+            if lval.slice.is_some() {
+                let (list, first, last) = self.resolve_slice(&lval)?;
+                let items = list.borrow().clone();
+                let last = last.map_or(items.len(), |last| (last + 1).min(items.len()));
+                for item in items.get(first..last).unwrap_or_default() {
+                    item.lock_items(depth, lock);
+                }
+                continue;
+            }
+            let val = if lval.path.is_empty() {
+                let Some(val) = self.lookup_var(&lval.name) else {
+                    return self.emsg(format!("E108: No such variable: \"{}\"", lval.name));
+                };
+                self.lock_var(&lval.name, lock);
+                val
+            } else {
+                let (container, key) = self.resolve_lval(&lval)?;
+                match &container {
+                    Value::List(list) => {
+                        let len = list.borrow().len();
+                        let idx = self.list_index(len, &key)?;
+                        let item = list.borrow()[idx].clone();
+                        item
+                    }
+                    Value::Dict(dict) => {
+                        let item = dict.borrow().get(&key.to_string()).cloned();
+                        match item {
+                            Some(item) => item,
+                            None => return self.emsg(format!("E716: Key not present in Dictionary: \"{}\"", key)),
+                        }
+                    }
+                    _ => return self.emsg("E689: Can only index a List, Dictionary or Blob".to_string()),
+                }
+            };
+            val.lock_items(depth, lock);
+        }
+        Ok(())
+    }
+
+    fn ex_call(&mut self, range: &str, arg: &str) -> Result<(), ()> {
+        let expr = self.parse_cmd_expr(arg)?;
+        let name = match &expr {
+            Expr::Call(name, _) => Some(name),
+            Expr::CallValue(..) | Expr::Method(..) => None,
+            _ => return self.emsg(format!("E129: Function name required: {}", arg)),
+        };
+        let range = match self.parse_range(range)? {
+            Some((first, last, _)) if first > last => return self.emsg(format!("E16: Invalid range: {}", range)),
+            range => range.map(|(first, last, _)| (first, last)),
+        };
+        let has_range = name.is_some_and(|n| self.find_func(&self.func_name(n)).is_some_and(|f| f.range));
+        // A function without the "range" attribute is called for each line.
+        let calls: Vec<Option<(i64, i64)>> = match range {
+            Some((first, last)) if !has_range => (first..=last).map(|lnum| Some((lnum, lnum))).collect(),
+            _ => vec![range],
+        };
+        for call_range in calls {
+            self.call_range = call_range;
+            let result = self.eval_parsed(&expr, arg);
+            self.call_range = None;
+            result?;
+        }
+        Ok(())
+    }
+
+    /// The lines of `range`, e.g. "3", "1,$" or "%", and the number of
+    /// addresses given.  None when there is no range.
+    pub(crate) fn parse_range(&mut self, range: &str) -> Result<Option<(i64, i64, usize)>, ()> {
+        let lastline = self.buffer.line_count() as i64;
+        if range.is_empty() {
+            return Ok(None);
+        } else if range == "%" {
+            return Ok(Some((1, lastline, 2)));
+        }
+        let mut lnums = Vec::new();
+        for addr in range.split(',') {
+            lnums.push(match addr {
+                "" | "." => self.buffer.cursor().0 as i64,
+                "$" => lastline,
+                _ => match addr.parse() {
+                    Ok(lnum) => lnum,
+                    Err(_) => return self.emsg(format!("E16: Invalid range: {}", range)),
+                },
+            });
+        }
+        match lnums[..] {
+            [lnum] => Ok(Some((lnum, lnum, 1))),
+            [first, last] => Ok(Some((first, last, 2))),
+            _ => self.emsg(format!("E16: Invalid range: {}", range)),
+        }
+    }
+
+    fn assign_target(&mut self, target: &LetTarget, op: Option<char>, val: Value) -> Result<(), ()> {
+        let (names, rest) = match target {
+            LetTarget::Single(lval) => return self.assign_lval(lval, op, val),
+            LetTarget::List(names, rest) => (names, rest),
+        };
+        let Value::List(list) = &val else {
+            return self.emsg("E714: List required".to_string());
+        };
+        let items = list.borrow().clone();
+        if items.len() < names.len() {
+            return self.emsg("E688: More targets than List items".to_string());
+        }
+        if rest.is_none() && items.len() > names.len() {
+            return self.emsg("E687: Less targets than List items".to_string());
+        }
+        for (lval, item) in names.iter().zip(&items) {
+            self.assign_lval(lval, op, item.clone())?;
+        }
+        match rest {
+            Some(lval) => self.assign_lval(lval, op, Value::new_list(items[names.len()..].to_vec())),
+            None => Ok(()),
+        }
+    }
+
+    fn assign_lval(&mut self, lval: &Lval, op: Option<char>, val: Value) -> Result<(), ()> {
+        if lval.slice.is_some() {
+            return self.assign_slice(lval, op, val);
+        }
+        if lval.path.is_empty() {
+            let val = match op {
+                Some(op) => {
+                    let Some(cur) = self.lookup_var(&lval.name) else {
+                        return self.emsg(format!("E121: Undefined variable: {}", lval.name));
+                    };
+                    let_op(self, op, cur, val)?
+                }
+                None => val,
+            };
+            return self.assign_var(&lval.name, val);
+        }
+        let (container, key) = self.resolve_lval(lval)?;
+        match &container {
+            Value::List(list) => {
+                self.check_value_lock(list.items_lock(), &lval.name)?;
+                let len = list.borrow().len();
+                let idx = self.list_index(len, &key)?;
+                let val = match op {
+                    Some(op) => {
+                        let cur = list.borrow()[idx].clone();
+                        let_op(self, op, cur, val)?
+                    }
+                    None => val,
+                };
+                list.borrow_mut()[idx] = val;
+            }
+            Value::Dict(dict) => {
+                let key = key.to_string();
+                // Adding an item needs the Dictionary unlocked, changing one
+                // needs its items unlocked.
+                let exists = dict.borrow().contains_key(&key);
+                self.check_value_lock(if exists { dict.items_lock() } else { dict.lock() }, &lval.name)?;
+                let val = match op {
+                    Some(op) => {
+                        let cur = dict.borrow().get(&key).cloned();
+                        let Some(cur) = cur else {
+                            return self.emsg(format!("E716: Key not present in Dictionary: \"{}\"", key));
+                        };
+                        let_op(self, op, cur, val)?
+                    }
+                    None => val,
+                };
+                dict.borrow_mut().insert(key, val);
+            }
+            _ => return self.emsg("E689: Can only index a List, Dictionary or Blob".to_string()),
+        }
+        Ok(())
+    }
+
+    /// `:let l[first : last] = [...]`: the items of the value replace the
+    /// items of the slice, which must be as many.  Without `last` the value
+    /// replaces the items up to the end of the List and may add items.
+    fn assign_slice(&mut self, lval: &Lval, op: Option<char>, val: Value) -> Result<(), ()> {
+        let (list, first, last) = self.resolve_slice(lval)?;
+        let Value::List(src) = &val else {
+            return self.emsg("E709: [:] requires a List or Blob value".to_string());
+        };
+        let items = src.borrow().clone();
+        let len = list.borrow().len();
+        let end = first + items.len();
+        // The end of the slice, without `last` the end of the List.
+        let slice_end = last.map_or(len, |last| last + 1);
+        if last.is_some() && end > slice_end {
+            return self.emsg("E710: List value has more items than targets".to_string());
+        }
+        if end < slice_end {
+            return self.emsg("E711: List value does not have enough items".to_string());
+        }
+        self.check_value_lock(list.items_lock(), &lval.name)?;
+        if end > len {
+            self.check_value_lock(list.lock(), &lval.name)?;
+        }
+        for (idx, item) in (first..).zip(items) {
+            let item = match op {
+                Some(op) => {
+                    let cur = list.borrow().get(idx).cloned().unwrap_or(Value::Number(0));
+                    let_op(self, op, cur, item)?
+                }
+                None => item,
+            };
+            let mut dest = list.borrow_mut();
+            if idx < dest.len() {
+                dest[idx] = item;
+            } else {
+                dest.push(item);
+            }
+        }
+        Ok(())
+    }
+
+    /// The List an `lval` with a slice refers to and the indexes of the
+    /// slice.  `first` may be the length of the List, `last` past its end.
+    fn resolve_slice(&mut self, lval: &Lval) -> Result<(ListRef, usize, Option<usize>), ()> {
+        let (first, last) = lval.slice.as_ref().expect("lval without slice");
+        let target = if lval.path.is_empty() {
+            match self.lookup_var(&lval.name) {
+                Some(val) => val,
+                None => return self.emsg(format!("E121: Undefined variable: {}", lval.name)),
+            }
+        } else {
+            let (container, key) = self.resolve_lval(lval)?;
+            self.index_value(&container, &key)?
+        };
+        let Value::List(list) = target else {
+            return self.emsg("E689: Can only index a List, Dictionary or Blob".to_string());
+        };
+        let len = list.borrow().len() as i64;
+        let n1 = match first {
+            Some(expr) => {
+                let val = self.eval_parsed(expr, &lval.name)?;
+                self.tv_number(&val)?
+            }
+            None => 0,
+        };
+        let idx1 = if n1 < 0 { len + n1 } else { n1 };
+        if !(0..=len).contains(&idx1) {
+            return self.emsg(format!("E684: List index out of range: {}", n1));
+        }
+        let idx2 = match last {
+            Some(expr) => {
+                let val = self.eval_parsed(expr, &lval.name)?;
+                let n2 = self.tv_number(&val)?;
+                let idx2 = if n2 < 0 { len + n2 } else { n2 };
+                if idx2 < idx1 {
+                    return self.emsg(format!("E684: List index out of range: {}", n2));
+                }
+                Some(idx2 as usize)
+            }
+            None => None,
+        };
+        Ok((list, idx1 as usize, idx2))
+    }
+
+    /// The container holding the item `lval` refers to, and the key of the
+    /// item in it.
+    fn resolve_lval(&mut self, lval: &Lval) -> Result<(Value, Value), ()> {
+        let Some(mut container) = self.lookup_var(&lval.name) else {
+            return self.emsg(format!("E121: Undefined variable: {}", lval.name));
+        };
+        let mut keys = Vec::new();
+        for expr in &lval.path {
+            keys.push(self.eval_parsed(expr, &lval.name)?);
+        }
+        let key = keys.pop().expect("lval without index");
+        for key in keys {
+            container = match &container {
+                Value::List(list) => {
+                    let len = list.borrow().len();
+                    let idx = self.list_index(len, &key)?;
+                    let item = list.borrow()[idx].clone();
+                    item
+                }
+                Value::Dict(dict) => {
+                    let item = dict.borrow().get(&key.to_string()).cloned();
+                    match item {
+                        Some(item) => item,
+                        None => return self.emsg(format!("E716: Key not present in Dictionary: \"{}\"", key)),
+                    }
+                }
+                _ => return self.emsg("E689: Can only index a List, Dictionary or Blob".to_string()),
+            };
+        }
+        Ok((container, key))
+    }
+
+    pub(crate) fn list_index(&mut self, len: usize, key: &Value) -> Result<usize, ()> {
+        let n = key.as_number()?;
+        let idx = if n < 0 { len as i64 + n } else { n };
+        match usize::try_from(idx) {
+            Ok(idx) if idx < len => Ok(idx),
+            _ => self.emsg(format!("E684: List index out of range: {}", n)),
+        }
+    }
+
+    /// Source the script `path`: execute its lines with its own "s:"
+    /// variables.  The same script keeps its script ID when sourced again.
+    pub(crate) fn source(&mut self, path: &Path) -> Result<(), ()> {
+        let Ok(text) = read_script(path) else {
+            return self.emsg(format!("E484: Can't open file {}", path.display()));
+        };
+        let canon = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let sid = match self.scripts.iter().position(|p| *p == canon) {
+            Some(idx) => idx + 1,
+            None => {
+                self.scripts.push(canon);
+                self.scripts.len()
+            }
+        };
+        let stmts = match parse_script(&split_lines(&text)) {
+            Ok(stmts) => stmts,
+            Err(msg) => return self.emsg(msg),
+        };
+        let saved_sid = std::mem::replace(&mut self.sid, sid);
+        let saved_pos = self.enter_sourcing(path.display().to_string());
+        // The script does not run in the scope of a function sourcing it.
+        let saved_frames = std::mem::take(&mut self.frames);
+        let result = self.exec_stmts(&stmts);
+        self.frames = saved_frames;
+        self.leave_sourcing(saved_pos);
+        self.sid = saved_sid;
+        result.map(|_| ())
+    }
+
+    /// Run `f` as a top level command: an error is reported and so is an
+    /// exception that was not caught.  Returns Err when an error was given.
+    pub(crate) fn toplevel(&mut self, f: impl FnOnce(&mut Self) -> Result<(), ()>) -> Result<(), ()> {
+        let before = self.did_emsg;
+        self.last_error = None;
+        if f(self).is_err() {
+            if let Some(exc) = self.exception.take() {
+                self.report_error(format!("E605: Exception not caught: {}", exc.value));
+            } else if let Some(msg) = self.last_error.take() {
+                self.report_error(msg);
+            }
+        }
+        self.may_garbage_collect();
+        self.u_sync();
+        if self.did_emsg == before {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Execute the Ex commands in `text`, one or more lines.  Errors are
+    /// reported in the output and execution continues after them, like
+    /// when sourcing a script.  Returns Err when there was an error.
+    pub fn do_cmdline(&mut self, text: &str) -> Result<(), ()> {
+        self.toplevel(|ev| {
+            let stmts = match parse_script(&split_lines(text)) {
+                Ok(stmts) => stmts,
+                Err(msg) => return ev.emsg(msg),
+            };
+            ev.exec_stmts(&stmts).map(|_| ())
+        })
+    }
+
+    /// Execute `:source {path}`.
+    pub fn source_file(&mut self, path: &Path) -> Result<(), ()> {
+        self.toplevel(|ev| ev.source(path))
+    }
+
+    /// exists(":name"): 2 for the full name of a command, 1 for an
+    /// abbreviation, 3 when it is ambiguous between user commands and zero
+    /// when there is no such command.
+    pub(crate) fn command_exists(&self, name: &str) -> i64 {
+        if name.is_empty() {
+            return 0;
+        }
+        let mut commands = COMMANDS
+            .iter()
+            .map(|&(cmd, min)| (cmd, min))
+            .chain(self.ex_commands.iter().map(|(cmd, min, _)| (cmd.as_str(), *min)));
+        if commands.clone().any(|(cmd, _)| cmd == name) {
+            return 2;
+        }
+        if commands.any(|(cmd, min)| name.len() >= min && cmd.starts_with(name)) {
+            return 1;
+        }
+        match self.usercmds.find(name, self.curbuf()) {
+            Ok(Some(cmd)) if cmd.name == name => 2,
+            Ok(Some(_)) => 1,
+            Ok(None) => 0,
+            Err(_) => 3,
+        }
+    }
+
+    /// Add Ex command `name`, which can be abbreviated to `min_len`
+    /// characters.
+    pub fn add_ex_command(&mut self, name: &str, min_len: usize, func: ExCmdFn) {
+        self.ex_commands.push((name.to_string(), min_len, func));
+    }
+
+    /// Output of `:echo` and error messages.
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
+}
+
+/// Apply the operator of `:let var op= val`.  `+=` on a List adds the items
+/// in place.
+fn let_op(ev: &mut Evaluator, op: char, cur: Value, val: Value) -> Result<Value, ()> {
+    match (op, &cur, &val) {
+        ('+', Value::List(list), Value::List(add)) => {
+            let items = add.borrow().clone();
+            list.borrow_mut().extend(items);
+            Ok(cur)
+        }
+        ('.', ..) => Ok(Value::Str(format!("{}{}", cur, val))),
+        _ => ev.arith(op, cur, val),
+    }
+}
+
+pub(crate) fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,611 @@
+//! User functions, lambdas and calling funcref/partial values.
+//!
+//! A lambda is turned into a user function named "<lambda>N" when the lambda
+//! expression is evaluated, like Vim does.  The function keeps the scope it
+//! was created in, so that it works as a closure: the variables of that scope
+//! are shared by reference, not copied.  Functions defined with `:function`
+//! have a body of Ex commands, executed by the code in ex.rs.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::rc::Rc;
+
+use rust_core::gc::{self, Trace, Tracer};
+
+use crate::ex::{parse_script, Cmd, Flow, Stmt};
+use crate::{eval, DictRef, Evaluator, Expr, Partial, Value};
+
+/// Maximum depth of nested function calls, Vim's 'maxfuncdepth'.
+const MAX_FUNC_DEPTH: usize = 100;
+
+/// A parsed lambda expression.
+#[derive(Debug)]
+pub(crate) struct Lambda {
+    params: Vec<String>,
+    body: Rc<Expr>,
+    /// Legacy lambdas ignore extra arguments, so that `{-> expr}` can be
+    /// used as a callback; Vim9 lambdas do not.
+    varargs: bool,
+}
+
+impl Lambda {
+    pub(crate) fn new(params: Vec<String>, body: Expr, varargs: bool) -> Self {
+        Lambda { params, body: Rc::new(body), varargs }
+    }
+}
+
+pub(crate) enum FuncBody {
+    /// The expression of a lambda.
+    Expr(Rc<Expr>),
+    /// The commands of a `:function`.
+    Lines(Rc<Vec<Stmt>>),
+}
+
+/// A user defined function.
+pub(crate) struct UserFunc {
+    name: String,
+    params: Vec<String>,
+    /// The default value expression of each optional argument.
+    defaults: Vec<Option<String>>,
+    /// For a lambda extra arguments are ignored, for a `:function` they are
+    /// available as a:000.
+    varargs: bool,
+    body: FuncBody,
+    /// The scope the function was defined in, for closures.
+    closure: Option<Rc<SharedFrame>>,
+    /// The script the function was defined in, for "s:" names.
+    sid: usize,
+    pub(crate) range: bool,
+    abort: bool,
+    dict: bool,
+}
+
+/// The local variables of a function call.
+#[derive(Default)]
+pub(crate) struct Frame {
+    pub(crate) vars: HashMap<String, Value>,
+    /// The "a:" variables of a `:function`.
+    args: HashMap<String, Value>,
+    outer: Option<Rc<SharedFrame>>,
+    /// A `:function` call, where a name without scope is always local.
+    pub(crate) legacy: bool,
+    pub(crate) abort: bool,
+    /// Local variables locked with `:lockvar`.
+    pub(crate) locked: HashSet<String>,
+}
+
+impl Frame {
+    /// Find `name` in this scope or one of the scopes it is nested in.
+    pub(crate) fn lookup(&self, name: &str) -> Option<Value> {
+        if let Some(val) = self.vars.get(name) {
+            return Some(val.clone());
+        }
+        self.outer.as_ref().and_then(|outer| outer.borrow().lookup(name))
+    }
+
+    pub(crate) fn lookup_arg(&self, name: &str) -> Option<Value> {
+        if let Some(val) = self.args.get(name) {
+            return Some(val.clone());
+        }
+        self.outer.as_ref().and_then(|outer| outer.borrow().lookup_arg(name))
+    }
+
+    /// Set local variable `name`.  When a closure assigns to a variable of
+    /// an enclosing function, that variable is changed.
+    pub(crate) fn assign(&mut self, name: &str, val: Value) {
+        if !self.vars.contains_key(name) {
+            if let Some(outer) = &self.outer {
+                if outer.borrow().lookup(name).is_some() {
+                    outer.borrow_mut().assign(name, val);
+                    return;
+                }
+            }
+        }
+        self.vars.insert(name.to_string(), val);
+    }
+}
+
+/// A frame that closures can refer to.  It takes part in cycle collection:
+/// a closure stored in a variable of the scope it was defined in refers to
+/// itself.
+#[derive(Default)]
+pub(crate) struct SharedFrame(RefCell<Frame>);
+
+impl SharedFrame {
+    pub(crate) fn new(frame: Frame) -> Rc<Self> {
+        let rc = Rc::new(SharedFrame(RefCell::new(frame)));
+        gc::track(&rc);
+        rc
+    }
+}
+
+impl Deref for SharedFrame {
+    type Target = RefCell<Frame>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Trace for SharedFrame {
+    fn trace(&self, tracer: &mut Tracer) {
+        let frame = self.borrow();
+        for val in frame.vars.values().chain(frame.args.values()) {
+            val.trace(tracer);
+        }
+        if let Some(outer) = &frame.outer {
+            tracer.visit(outer);
+        }
+    }
+
+    fn clear(&self) {
+        let frame = std::mem::take(&mut *self.borrow_mut());
+        drop(frame);
+    }
+}
+
+impl Trace for UserFunc {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(closure) = &self.closure {
+            tracer.visit(closure);
+        }
+    }
+}
+
+/// The header of a `:function` definition.
+struct FuncHeader {
+    name: String,
+    params: Vec<String>,
+    defaults: Vec<Option<String>>,
+    varargs: bool,
+    range: bool,
+    abort: bool,
+    dict: bool,
+    closure: bool,
+}
+
+/// Parse "Name(a, b = 1, ...) range abort dict closure".
+fn parse_func_header(arg: &str) -> Result<FuncHeader, String> {
+    let open = arg.find('(').ok_or_else(|| format!("E124: Missing '(': {}", arg))?;
+    let name = arg[..open].trim().to_string();
+    let mut depth = 0;
+    let mut close = None;
+    for (i, c) in arg[open..].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(open + i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let close = close.ok_or_else(|| format!("E473: Missing ')': {}", arg))?;
+    let mut header = FuncHeader {
+        name,
+        params: Vec::new(),
+        defaults: Vec::new(),
+        varargs: false,
+        range: false,
+        abort: false,
+        dict: false,
+        closure: false,
+    };
+    let mut depth = 0;
+    let mut start = open + 1;
+    let mut params = Vec::new();
+    for (i, c) in arg[open + 1..close].char_indices() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                params.push(&arg[start..open + 1 + i]);
+                start = open + 2 + i;
+            }
+            _ => {}
+        }
+    }
+    params.push(&arg[start..close]);
+    let params: Vec<&str> = params.into_iter().map(str::trim).collect();
+    let count = params.len();
+    for (i, param) in params.into_iter().enumerate() {
+        if param.is_empty() && count == 1 {
+            break;
+        }
+        if param == "..." {
+            if i + 1 != count {
+                return Err(format!("E125: Illegal argument: {}", param));
+            }
+            header.varargs = true;
+            continue;
+        }
+        let (pname, default) = match param.split_once('=') {
+            Some((n, d)) => (n.trim(), Some(d.trim().to_string())),
+            None => (param, None),
+        };
+        let valid = pname.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && pname.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid || header.params.iter().any(|p| p == pname) {
+            return Err(format!("E125: Illegal argument: {}", pname));
+        }
+        if default.is_none() && header.defaults.iter().any(Option::is_some) {
+            return Err("E989: Non-default argument follows default argument".to_string());
+        }
+        header.params.push(pname.to_string());
+        header.defaults.push(default);
+    }
+    for attr in arg[close + 1..].split_whitespace() {
+        match attr {
+            "range" => header.range = true,
+            "abort" => header.abort = true,
+            "dict" => header.dict = true,
+            "closure" => header.closure = true,
+            _ if attr.starts_with('"') => break,
+            _ => return Err(format!("E488: Trailing characters: {}", attr)),
+        }
+    }
+    Ok(header)
+}
+
+impl Evaluator {
+    /// The name under which function `name` is stored: "s:Name" and
+    /// "<SID>Name" become "<SNR>{sid}_Name", "g:Name" becomes "Name".
+    pub(crate) fn func_name(&self, name: &str) -> String {
+        if let Some(short) = name.strip_prefix("s:").or_else(|| name.strip_prefix("<SID>")) {
+            return format!("<SNR>{}_{}", self.sid, short);
+        }
+        name.strip_prefix("g:").unwrap_or(name).to_string()
+    }
+
+    /// Create the function for an evaluated lambda expression.
+    pub(crate) fn make_lambda(&mut self, lambda: &Lambda) -> Value {
+        self.lambda_count += 1;
+        let name = format!("<lambda>{}", self.lambda_count);
+        let func = Rc::new(UserFunc {
+            name: name.clone(),
+            params: lambda.params.clone(),
+            defaults: vec![None; lambda.params.len()],
+            varargs: lambda.varargs,
+            body: FuncBody::Expr(lambda.body.clone()),
+            closure: self.frames.last().cloned(),
+            sid: self.sid,
+            range: false,
+            abort: false,
+            dict: false,
+        });
+        gc::track(&func);
+        // The lambda lives as long as a funcref refers to it.
+        self.lambdas.insert(name.clone(), Rc::downgrade(&func));
+        let mut pt = Partial::new(&name);
+        pt.func = Some(func);
+        Value::new_func(pt)
+    }
+
+    /// Execute `:function[!] {header}` with the lines of the body.
+    pub(crate) fn define_function(&mut self, cmd: &Cmd, body: &[(usize, String)]) -> Result<(), ()> {
+        let header = match parse_func_header(&cmd.arg) {
+            Ok(header) => header,
+            Err(msg) => return self.emsg(msg),
+        };
+        let stmts = match parse_script(body) {
+            Ok(stmts) => stmts,
+            Err(msg) => return self.emsg(msg),
+        };
+        let closure = if header.closure {
+            match self.frames.last() {
+                Some(frame) => Some(frame.clone()),
+                None => return self.emsg("E932: Closure function should not be at top level".to_string()),
+            }
+        } else {
+            None
+        };
+        // "dict.name" defines a numbered function stored in the dictionary.
+        let dict_target = match header.name.split_once('.') {
+            Some((var, key)) => match self.lookup_var(var) {
+                Some(Value::Dict(dict)) => Some((dict, key.to_string())),
+                _ => return self.emsg(format!("E1203: Dot can only be used on a dictionary: {}", header.name)),
+            },
+            None => None,
+        };
+        let name = match &dict_target {
+            Some(_) => {
+                self.lambda_count += 1;
+                self.lambda_count.to_string()
+            }
+            None => self.func_name(&header.name),
+        };
+        let first = name.chars().next().unwrap_or(' ');
+        if dict_target.is_none() && !name.starts_with("<SNR>") && !name.contains('#') && !first.is_ascii_uppercase() {
+            return self.emsg(format!("E128: Function name must start with a capital or \"s:\": {}", header.name));
+        }
+        if dict_target.is_none() && !cmd.bang && self.ufuncs.contains_key(&name) {
+            return self.emsg(format!("E122: Function {} already exists, add ! to replace it", name));
+        }
+        let func = Rc::new(UserFunc {
+            name: name.clone(),
+            params: header.params,
+            defaults: header.defaults,
+            varargs: header.varargs,
+            body: FuncBody::Lines(Rc::new(stmts)),
+            closure,
+            sid: self.sid,
+            range: header.range,
+            abort: header.abort,
+            dict: header.dict,
+        });
+        gc::track(&func);
+        self.ufuncs.insert(name.clone(), func.clone());
+        if let Some((dict, key)) = dict_target {
+            let mut pt = Partial::new(&name);
+            pt.func = Some(func);
+            dict.borrow_mut().insert(key, Value::new_func(pt));
+        }
+        Ok(())
+    }
+
+    /// Execute `:delfunction[!] {name}`.
+    pub(crate) fn delete_function(&mut self, name: &str, bang: bool) -> Result<(), ()> {
+        let stored = self.func_name(name.trim());
+        if self.ufuncs.remove(&stored).is_none() && !bang {
+            return self.emsg(format!("E130: Unknown function: {}", name.trim()));
+        }
+        Ok(())
+    }
+
+    /// Builtin and global user functions, with "(" after the name and ")"
+    /// too when the function has no arguments.
+    pub(crate) fn function_names(&self) -> Vec<String> {
+        let builtin = self.funcs.values().map(|info| {
+            format!("{}({}", info.name, if info.max_argc == 0 { ")" } else { "" })
+        });
+        // Not script-local functions, lambdas and numbered functions.
+        let user = self
+            .ufuncs
+            .iter()
+            .filter(|(name, _)| !name.starts_with('<') && !name.starts_with(|c: char| c.is_ascii_digit()))
+            .map(|(name, func)| format!("{}({}", name, if func.params.is_empty() && !func.varargs { ")" } else { "" }));
+        builtin.chain(user).collect()
+    }
+
+    /// The lines listed by `:function` without arguments.
+    pub(crate) fn function_list(&self) -> Vec<String> {
+        let mut names: Vec<&String> = self
+            .ufuncs
+            .keys()
+            .filter(|n| !n.starts_with("<lambda>") && !n.starts_with(|c: char| c.is_ascii_digit()))
+            .collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let func = &self.ufuncs[name];
+                let mut params = func.params.clone();
+                if func.varargs {
+                    params.push("...".to_string());
+                }
+                let mut line = format!("function {}({})", name, params.join(", "));
+                for (set, attr) in [(func.range, "range"), (func.abort, "abort"), (func.dict, "dict")] {
+                    if set {
+                        line.push(' ');
+                        line.push_str(attr);
+                    }
+                }
+                line
+            })
+            .collect()
+    }
+
+    /// The user function or lambda stored as `name`.
+    pub(crate) fn find_func(&self, name: &str) -> Option<Rc<UserFunc>> {
+        match self.ufuncs.get(name) {
+            Some(func) => Some(func.clone()),
+            None => self.lambdas.get(name).and_then(|func| func.upgrade()),
+        }
+    }
+
+    /// Whether `name` is a user or builtin function.
+    pub fn function_exists(&self, name: &str) -> bool {
+        self.find_func(&self.func_name(name)).is_some() || self.funcs.contains_key(name)
+    }
+
+    /// Call a funcref value, or a function given by name as a string.
+    pub fn call_value(&mut self, func: &Value, args: &[Value]) -> Result<Value, ()> {
+        match func {
+            Value::Func(pt) => self.call_partial(pt, args),
+            Value::Str(name) => self.call_function(name, args),
+            _ => Err(()),
+        }
+    }
+
+    pub(crate) fn call_partial(&mut self, pt: &Partial, args: &[Value]) -> Result<Value, ()> {
+        let mut all = pt.args.clone();
+        all.extend_from_slice(args);
+        if let Some(func) = &pt.func {
+            let func = (func.clone() as Rc<dyn std::any::Any>).downcast::<UserFunc>().map_err(|_| ())?;
+            return self.call_user(&func, &all, pt.dict.clone());
+        }
+        self.call_by_name(&pt.name, &all, pt.dict.clone())
+    }
+
+    pub(crate) fn call_by_name(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        dict: Option<DictRef>,
+    ) -> Result<Value, ()> {
+        if let Some(func) = self.find_func(&self.func_name(name)) {
+            return self.call_user(&func, args, dict);
+        }
+        match self.funcs.get(name) {
+            Some(info) => self.call_builtin(info, args),
+            None => self.emsg(format!("E117: Unknown function: {}", name)),
+        }
+    }
+
+    fn call_user(
+        &mut self,
+        func: &Rc<UserFunc>,
+        args: &[Value],
+        dict: Option<DictRef>,
+    ) -> Result<Value, ()> {
+        let nparams = func.params.len();
+        let required = func.defaults.iter().filter(|d| d.is_none()).count();
+        if args.len() < required {
+            return self.emsg(format!("E119: Not enough arguments for function: {}", func.name));
+        }
+        if args.len() > nparams && !func.varargs {
+            return self.emsg(format!("E118: Too many arguments for function: {}", func.name));
+        }
+        if self.frames.len() >= MAX_FUNC_DEPTH {
+            return self.emsg(format!(
+                "E132: Function call depth is higher than 'maxfuncdepth': {}",
+                func.name
+            ));
+        }
+        let stmts = match &func.body {
+            FuncBody::Expr(body) => {
+                let mut frame = Frame {
+                    vars: func.params.iter().cloned().zip(args.iter().cloned()).collect(),
+                    outer: func.closure.clone(),
+                    ..Frame::default()
+                };
+                if let Some(dict) = dict {
+                    frame.vars.insert("self".to_string(), Value::Dict(dict));
+                }
+                self.frames.push(SharedFrame::new(frame));
+                let saved_sid = std::mem::replace(&mut self.sid, func.sid);
+                let result = eval(body, self);
+                self.sid = saved_sid;
+                self.frames.pop();
+                return result;
+            }
+            FuncBody::Lines(stmts) => stmts.clone(),
+        };
+        if func.dict && dict.is_none() {
+            return self.emsg(format!("E725: Calling dict function without Dictionary: {}", func.name));
+        }
+        let (first, last) = self.call_range.take().unwrap_or((1, 1));
+        let mut frame = Frame {
+            outer: func.closure.clone(),
+            legacy: true,
+            abort: func.abort,
+            ..Frame::default()
+        };
+        let extra: Vec<Value> = args.iter().skip(nparams).cloned().collect();
+        for (i, val) in extra.iter().enumerate() {
+            frame.args.insert((i + 1).to_string(), val.clone());
+        }
+        frame.args.insert("0".to_string(), Value::Number(extra.len() as i64));
+        frame.args.insert("000".to_string(), Value::new_list(extra));
+        frame.args.insert("firstline".to_string(), Value::Number(first));
+        frame.args.insert("lastline".to_string(), Value::Number(last));
+        for (param, val) in func.params.iter().zip(args) {
+            frame.args.insert(param.clone(), val.clone());
+        }
+        if let Some(dict) = dict {
+            frame.vars.insert("self".to_string(), Value::Dict(dict));
+        }
+        let frame = SharedFrame::new(frame);
+        self.frames.push(frame.clone());
+        let saved_sid = std::mem::replace(&mut self.sid, func.sid);
+        let saved_pos = self.enter_sourcing(format!("function {}", func.name));
+        let mut result = Ok(Flow::Normal);
+        // Optional arguments are evaluated in the function, so that they can
+        // use the arguments before them.
+        for (param, default) in func.params.iter().zip(&func.defaults).skip(args.len()) {
+            let Some(expr) = default else { continue };
+            match self.eval_cmd_expr(expr) {
+                Ok(val) => {
+                    frame.borrow_mut().args.insert(param.clone(), val);
+                }
+                Err(()) => {
+                    if self.handle_error("function").is_err() {
+                        result = Err(());
+                        break;
+                    }
+                }
+            }
+        }
+        if matches!(result, Ok(Flow::Normal)) {
+            result = self.exec_stmts(&stmts);
+        }
+        self.leave_sourcing(saved_pos);
+        self.sid = saved_sid;
+        self.frames.pop();
+        match result? {
+            Flow::Return(val) => Ok(val),
+            _ => Ok(Value::Number(0)),
+        }
+    }
+}
+
+/// Common part of function() and funcref(): `func` is a name or funcref,
+/// optionally followed by a list of arguments and a dict to bind.
+fn make_partial(ev: &Evaluator, args: &[Value], by_ref: bool) -> Result<Value, ()> {
+    let mut pt = match args.first() {
+        Some(Value::Str(name)) => {
+            if !ev.function_exists(name) {
+                return Err(());
+            }
+            let name = ev.func_name(name);
+            let mut pt = Partial::new(&name);
+            if by_ref {
+                pt.func = ev.find_func(&name).map(|f| f as Rc<dyn Trace>);
+            }
+            pt
+        }
+        Some(Value::Func(pt)) => (**pt).clone(),
+        _ => return Err(()),
+    };
+    for arg in args.iter().skip(1) {
+        match arg {
+            Value::List(list) if pt.dict.is_none() => pt.args.extend(list.borrow().iter().cloned()),
+            Value::Dict(dict) => pt.dict = Some(dict.clone()),
+            _ => return Err(()),
+        }
+    }
+    if args.len() > 3 {
+        return Err(());
+    }
+    Ok(Value::new_func(pt))
+}
+
+pub(crate) fn f_function(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    make_partial(ev, args, false)
+}
+
+pub(crate) fn f_funcref(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    make_partial(ev, args, true)
+}
+
+/// call({func}, {arglist} [, {dict}])
+pub(crate) fn f_call(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let list = match args.get(1) {
+        Some(Value::List(list)) => list.borrow().clone(),
+        _ => return Err(()),
+    };
+    match (args.first(), args.get(2)) {
+        (Some(func), None) => ev.call_value(func, &list),
+        (Some(func), Some(Value::Dict(dict))) => {
+            let mut pt = match func {
+                Value::Func(pt) => (**pt).clone(),
+                Value::Str(name) => Partial::new(name),
+                _ => return Err(()),
+            };
+            pt.dict = Some(dict.clone());
+            ev.call_partial(&pt, &list)
+        }
+        _ => Err(()),
+    }
+}
+
+pub(crate) fn truthy(val: &Value) -> Result<bool, ()> {
+    match val {
+        Value::Str(s) => Ok(s.trim().parse::<i64>().unwrap_or(0) != 0),
+        _ => Ok(val.as_number()? != 0),
+    }
+}
@@ -0,0 +1,67 @@
+//! garbagecollect() and test_garbagecollect_now(): freeing values that only
+//! a reference cycle keeps alive, such as a list that contains itself or a
+//! closure stored in the scope it was defined in.
+//!
+//! The roots are the variables of all scopes, the frames of the functions
+//! being executed, the user functions with their closures and the timer
+//! callbacks.  Values kept by other code are added with `gc::add_root()`.
+
+use rust_core::gc::{self, Tracer};
+
+use crate::{Evaluator, Value};
+
+impl Evaluator {
+    fn trace_roots(&self, tracer: &mut Tracer) {
+        let scopes = &self.scopes;
+        let vars = self
+            .vars
+            .values()
+            .chain(scopes.buffer.values())
+            .chain(scopes.window.values())
+            .chain(scopes.tab.values())
+            .chain(scopes.vim.values())
+            .chain(scopes.script.values().flat_map(|vars| vars.values()))
+            .chain(self.last_value.iter())
+            .chain(self.timer_callbacks.values().map(|timer| &timer.callback));
+        for val in vars {
+            val.trace(tracer);
+        }
+        for frame in &self.frames {
+            tracer.visit(frame);
+        }
+        for func in self.ufuncs.values() {
+            tracer.visit(func);
+        }
+    }
+
+    /// Free the values that only cycles keep alive, right away.  Returns the
+    /// number of containers, partials and function scopes freed.
+    pub fn garbage_collect(&mut self) -> usize {
+        self.want_gc = false;
+        let freed = gc::collect(|tracer| self.trace_roots(tracer));
+        self.lambdas.retain(|_, func| func.strong_count() > 0);
+        freed
+    }
+
+    /// Collect garbage when garbagecollect() asked for it or many values
+    /// were created since the last time.  Called after a top level command,
+    /// not while a function is executing.
+    pub(crate) fn may_garbage_collect(&mut self) {
+        if self.frames.is_empty() && (self.want_gc || gc::should_collect()) {
+            self.garbage_collect();
+        }
+    }
+}
+
+/// garbagecollect([{atexit}]): collect after the current command, when no
+/// function is executing.
+pub(crate) fn f_garbagecollect(ev: &mut Evaluator, _args: &[Value]) -> Result<Value, ()> {
+    ev.want_gc = true;
+    Ok(Value::Number(0))
+}
+
+/// test_garbagecollect_now(): collect right away, for tests.
+pub(crate) fn f_test_garbagecollect_now(ev: &mut Evaluator, _args: &[Value]) -> Result<Value, ()> {
+    ev.garbage_collect();
+    Ok(Value::Number(0))
+}
@@ -1,115 +1,271 @@
-use once_cell::sync::Lazy;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::iter::Peekable;
 use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
 use std::str::Chars;
-use std::sync::Mutex;
-pub use rust_core::{typval_T, ValUnion, Vartype, Value, to_typval, from_typval, tv_free};
+use rust_regexp::VimRegex;
+pub use rust_core::{typval_T, ValUnion, Vartype, Value, Partial, DictRef, ListRef, to_typval, from_typval, tv_free};
+
+mod arglist;
+mod autocmd;
+mod buflist;
+mod buffer;
+mod evalfunc;
+mod ex;
+mod func;
+mod gc;
+mod listfunc;
+mod mapping;
+mod options;
+mod strfunc;
+mod timer;
+mod undo;
+mod usercmd;
+mod vars;
+mod write;
+
+pub use buffer::Buffer;
+pub use rust_autocmd::Event;
+pub use ex::{ExArg, ExCmdFn};
+use evalfunc::FuncInfo;
+use ex::Exception;
+use func::{Lambda, SharedFrame, UserFunc};
+use rust_arglist::{ArgList, WindowArgs};
+use rust_buffer::BufferList;
+use rust_autocmd::{AutoCmdRun, AutoCmds};
+use rust_map::MapTable;
+use rust_time::TimerQueue;
+use rust_usercmd::UserCmds;
+use timer::TimerCallback;
+use vars::Scopes;
 
 #[derive(Debug, Clone)]
 enum Expr {
     Number(i64),
     Float(f64),
     Str(String),
+    Blob(Vec<u8>),
     Var(String),
+    List(Vec<Expr>),
+    /// `{key: val}`, the keys are expressions.
+    Dict(Vec<(Expr, Expr)>),
+    Lambda(Rc<Lambda>),
+    /// `$"text {expr}"`: the parts are concatenated.
+    Interp(Vec<Expr>),
     Call(String, Vec<Expr>),
+    CallValue(Box<Expr>, Vec<Expr>),
+    /// `base->name(args)` or `base->{lambda}(args)`: base is passed as the
+    /// first argument.
+    Method(Box<Expr>, Box<Expr>, Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    /// `expr[first : last]`, either may be omitted.
+    Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>),
+    /// `dict.key`
+    Member(Box<Expr>, String),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Plus(Box<Expr>),
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
+    Mod(Box<Expr>, Box<Expr>),
     Concat(Box<Expr>, Box<Expr>),
+    Compare(CmpOp, MatchCase, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `expr ?? default`
+    Falsy(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CmpOp {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Match,
+    NoMatch,
+    Is,
+    IsNot,
+}
+
+/// How a comparison treats case: "==" follows 'ignorecase', "==#" matches
+/// case and "==?" ignores it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MatchCase {
+    Option,
+    Match,
+    Ignore,
 }
 
+/// A builtin function.  It gets the evaluator so that it can call back into
+/// funcrefs passed as arguments.
+pub type BuiltinFn = fn(&mut Evaluator, &[Value]) -> Result<Value, ()>;
+
 pub struct Evaluator {
     vars: HashMap<String, Value>,
-    funcs: HashMap<String, fn(&[Value]) -> Result<Value, ()>>,
+    funcs: HashMap<&'static str, &'static FuncInfo>,
+    ufuncs: HashMap<String, Rc<UserFunc>>,
+    /// Lambdas by their "<lambda>N" name.  A lambda is freed when no funcref
+    /// refers to it any longer.
+    lambdas: HashMap<String, Weak<UserFunc>>,
+    frames: Vec<Rc<SharedFrame>>,
+    lambda_count: usize,
+    scopes: Scopes,
+    /// ID of the script being executed, zero when not sourcing.
+    sid: usize,
+    /// Sourced scripts, the script ID is the index plus one.
+    scripts: Vec<PathBuf>,
+    /// The range of `:call`, passed to the called function.
+    call_range: Option<(i64, i64)>,
+    /// The exception being thrown.
+    exception: Option<Exception>,
+    /// The first error of the command being executed.
+    last_error: Option<String>,
+    /// Number of errors reported.
+    did_emsg: usize,
+    /// Number of active `:try` commands.
+    try_depth: usize,
+    /// Script or function being executed and the line in it, for
+    /// v:throwpoint.
+    sourcing_name: String,
+    sourcing_lnum: usize,
+    ex_commands: Vec<(String, usize, ExCmdFn)>,
+    output: Vec<String>,
+    /// Value of the last expression line or `:let` for eval_script().
+    last_value: Option<Value>,
+    /// Whether a line that is not a command is evaluated as an expression.
+    expr_lines: bool,
+    /// garbagecollect() was called: collect after the current command.
+    want_gc: bool,
+    buffer: Buffer,
+    /// Option values by full name.
+    options: HashMap<&'static str, Value>,
+    registers: HashMap<char, String>,
+    /// The match and submatches of the `\=` replacement being evaluated,
+    /// for submatch().
+    submatches: Option<Vec<String>>,
+    timers: TimerQueue,
+    timer_callbacks: HashMap<u64, TimerCallback>,
+    /// A timer callback is being invoked.
+    timer_busy: bool,
+    autocmds: AutoCmds,
+    /// The autocommands being executed, the innermost last.
+    autocmd_runs: Vec<AutoCmdRun>,
+    maps: MapTable,
+    abbrs: MapTable,
+    usercmds: UserCmds,
+    /// The command modifiers before the command being executed, for
+    /// `<mods>`.
+    cmdmods: Vec<String>,
+    arglist: ArgList,
+    /// The argument list of the window and its current argument.
+    win_args: WindowArgs,
+    buflist: BufferList,
 }
 
 impl Evaluator {
     pub fn new() -> Self {
-        let mut funcs: HashMap<String, fn(&[Value]) -> Result<Value, ()>> = HashMap::new();
-        fn add_func(args: &[Value]) -> Result<Value, ()> {
-            let a = args
-                .get(0)
-                .map(|v| v.as_float())
-                .transpose()?
-                .unwrap_or(0.0);
-            let b = args
-                .get(1)
-                .map(|v| v.as_float())
-                .transpose()?
-                .unwrap_or(0.0);
-            let res = a + b;
-            if args.iter().any(|v| matches!(v, Value::Float(_))) || res.fract() != 0.0 {
-                Ok(Value::Float(res))
-            } else {
-                Ok(Value::Number(res as i64))
-            }
+        Evaluator {
+            vars: HashMap::new(),
+            funcs: evalfunc::FUNCTIONS.iter().map(|info| (info.name, info)).collect(),
+            ufuncs: HashMap::new(),
+            lambdas: HashMap::new(),
+            frames: Vec::new(),
+            lambda_count: 0,
+            scopes: Scopes::new(),
+            sid: 0,
+            scripts: Vec::new(),
+            call_range: None,
+            exception: None,
+            last_error: None,
+            did_emsg: 0,
+            try_depth: 0,
+            sourcing_name: String::new(),
+            sourcing_lnum: 0,
+            ex_commands: Vec::new(),
+            output: Vec::new(),
+            last_value: None,
+            expr_lines: false,
+            want_gc: false,
+            buffer: Buffer::new(),
+            options: options::default_options().into_iter().collect(),
+            registers: HashMap::new(),
+            submatches: None,
+            timers: TimerQueue::new(),
+            timer_callbacks: HashMap::new(),
+            timer_busy: false,
+            autocmds: AutoCmds::new(),
+            autocmd_runs: Vec::new(),
+            maps: MapTable::new(),
+            abbrs: MapTable::new_abbr(),
+            usercmds: UserCmds::new(),
+            cmdmods: Vec::new(),
+            arglist: ArgList::new(),
+            win_args: WindowArgs::default(),
+            buflist: buflist::new_buflist(),
         }
-        fn concat_func(args: &[Value]) -> Result<Value, ()> {
-            let mut s = String::new();
-            for v in args {
-                s.push_str(&v.to_string());
-            }
-            Ok(Value::Str(s))
-        }
-        funcs.insert("add".to_string(), add_func);
-        funcs.insert("concat".to_string(), concat_func);
-        Evaluator { vars: HashMap::new(), funcs }
     }
 
+    /// Set a global variable; a "g:" prefix is optional.
     pub fn set_var(&mut self, name: &str, val: Value) {
+        let name = name.strip_prefix("g:").unwrap_or(name);
         self.vars.insert(name.to_string(), val);
     }
 
     pub fn get_var(&self, name: &str) -> Option<Value> {
+        let name = name.strip_prefix("g:").unwrap_or(name);
         self.vars.get(name).cloned()
     }
 
-    pub fn call_function(&self, name: &str, args: &[Value]) -> Result<Value, ()> {
-        match self.funcs.get(name) {
-            Some(f) => f(args),
-            None => Err(()),
+    /// Call `name`, which may be a variable holding a funcref, a user
+    /// function or a builtin function.
+    pub fn call_function(&mut self, name: &str, args: &[Value]) -> Result<Value, ()> {
+        if let Some(Value::Func(pt)) = self.lookup_var(name) {
+            return self.call_partial(&pt, args);
         }
+        self.call_by_name(name, args, None)
     }
 
-    pub fn eval_expr(&self, expr: &str) -> Result<Value, ()> {
+    pub fn eval_expr(&mut self, expr: &str) -> Result<Value, ()> {
+        self.last_error = None;
         let mut tokens = Tokenizer::new(expr);
-        let ast = parse_concat(&mut tokens)?;
+        let ast = parse_expr(&mut tokens)?;
         if tokens.next_non_ws().is_some() {
             return Err(());
         }
         eval(&ast, self)
     }
 
+    /// Execute script lines, where a line may also be an expression.
+    /// Returns the value of the last expression or `:let`.
     pub fn eval_script(&mut self, script: &str) -> Result<Option<Value>, ()> {
-        let mut last = None;
-        for line in script.lines() {
-            let line = line.trim();
-            if line.is_empty() {
-                continue;
-            }
-            if let Some(rest) = line.strip_prefix("let ") {
-                if let Some((name, expr)) = rest.split_once('=') {
-                    let name = name.trim();
-                    let expr = expr.trim();
-                    let val = self.eval_expr(expr)?;
-                    self.set_var(name, val.clone());
-                    last = Some(val);
-                } else {
-                    return Err(());
-                }
-            } else {
-                last = Some(self.eval_expr(line)?);
-            }
-        }
-        Ok(last)
+        self.last_value = None;
+        let saved = std::mem::replace(&mut self.expr_lines, true);
+        let result = self.do_cmdline(script);
+        self.expr_lines = saved;
+        result.map(|()| self.last_value.take())
     }
 }
 
-static GLOBAL_EVAL: Lazy<Mutex<Evaluator>> = Lazy::new(|| Mutex::new(Evaluator::new()));
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+thread_local! {
+    static GLOBAL_EVAL: RefCell<Evaluator> = RefCell::new(Evaluator::new());
+}
+
+#[derive(Clone)]
 struct Tokenizer<'a> {
     iter: Peekable<Chars<'a>>,
 }
@@ -139,30 +295,111 @@ impl<'a> Tokenizer<'a> {
         self.iter.peek().copied()
     }
 
+    /// The input that was not parsed yet.
+    fn rest(&self) -> String {
+        self.iter.clone().collect()
+    }
+
+    /// Take up to `max` digits in `radix`.  None when there is none.
+    fn parse_digits(&mut self, radix: u32, max: usize) -> Option<u64> {
+        let mut n: Option<u64> = None;
+        for _ in 0..max {
+            let Some(d) = self.iter.peek().and_then(|c| c.to_digit(radix)) else { break };
+            self.iter.next();
+            n = Some(n.unwrap_or(0).saturating_mul(radix as u64).saturating_add(d as u64));
+        }
+        n
+    }
+
+    /// A Number, Float or Blob literal: "12", "0x1F", "0b101", "017",
+    /// "0o17", "1.5", "1.5e-3" or "0z00FF.AB".  A Number that does not fit
+    /// is the largest Number.
     fn parse_number(&mut self) -> Option<Expr> {
+        if !self.iter.peek()?.is_ascii_digit() {
+            return None;
+        }
+        let mut probe = self.iter.clone();
+        if probe.next() == Some('0') {
+            let radix = match probe.next() {
+                Some('x' | 'X') => 16,
+                Some('b' | 'B') => 2,
+                Some('o' | 'O') => 8,
+                Some('z' | 'Z') => {
+                    let mut tokens = Tokenizer { iter: probe };
+                    let bytes = tokens.parse_blob()?;
+                    self.iter = tokens.iter;
+                    return Some(Expr::Blob(bytes));
+                }
+                _ => 0,
+            };
+            if radix != 0 && probe.peek().is_some_and(|c| c.is_digit(radix)) {
+                self.iter = probe;
+                let n = self.parse_digits(radix, usize::MAX)?;
+                return Some(Expr::Number(n.min(i64::MAX as u64) as i64));
+            }
+        }
         let mut s = String::new();
-        let mut has_dot = false;
-        while let Some(&c) = self.iter.peek() {
-            if c.is_ascii_digit() {
-                s.push(c);
-                self.iter.next();
-            } else if c == '.' && !has_dot {
-                has_dot = true;
+        while let Some(c) = self.iter.peek().copied().filter(char::is_ascii_digit) {
+            s.push(c);
+            self.iter.next();
+        }
+        // A Float needs digits after the dot, "1.x" is a concatenation.
+        let mut probe = self.iter.clone();
+        if probe.next() == Some('.') && probe.peek().is_some_and(char::is_ascii_digit) {
+            s.push('.');
+            while let Some(c) = probe.peek().copied().filter(char::is_ascii_digit) {
                 s.push(c);
-                self.iter.next();
-            } else {
-                break;
+                probe.next();
             }
+            let mut exp = probe.clone();
+            if matches!(exp.next(), Some('e' | 'E')) {
+                let mut e = String::from("e");
+                if let Some(sign @ ('+' | '-')) = exp.peek().copied() {
+                    e.push(sign);
+                    exp.next();
+                }
+                if exp.peek().is_some_and(char::is_ascii_digit) {
+                    while let Some(c) = exp.peek().copied().filter(char::is_ascii_digit) {
+                        e.push(c);
+                        exp.next();
+                    }
+                    s.push_str(&e);
+                    probe = exp;
+                }
+            }
+            self.iter = probe;
+            return s.parse().ok().map(Expr::Float);
         }
-        if s.is_empty() {
-            None
-        } else if has_dot {
-            s.parse::<f64>().ok().map(Expr::Float)
+        // A leading zero makes an octal number, unless there is an 8 or 9.
+        let n = if s.len() > 1 && s.starts_with('0') && s.bytes().all(|b| b < b'8') {
+            u64::from_str_radix(&s, 8).unwrap_or(u64::MAX)
         } else {
-            s.parse::<i64>().ok().map(Expr::Number)
+            s.parse().unwrap_or(u64::MAX)
+        };
+        Some(Expr::Number(n.min(i64::MAX as u64) as i64))
+    }
+
+    /// The bytes of a Blob literal after the "0z": pairs of hex digits,
+    /// optionally separated by dots.
+    fn parse_blob(&mut self) -> Option<Vec<u8>> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.iter.peek() {
+                Some(c) if c.is_ascii_hexdigit() => {
+                    let hi = self.iter.next()?.to_digit(16)?;
+                    // The digits come in pairs.
+                    let lo = self.iter.next().and_then(|c| c.to_digit(16))?;
+                    bytes.push((hi * 16 + lo) as u8);
+                }
+                Some('.') if !bytes.is_empty() => {
+                    self.iter.next();
+                }
+                _ => return Some(bytes),
+            }
         }
     }
 
+    /// A "string", in which a backslash starts an escape sequence.
     fn parse_string(&mut self) -> Option<String> {
         if self.peek_non_ws() != Some('"') {
             return None;
@@ -170,18 +407,97 @@ impl<'a> Tokenizer<'a> {
         self.next_non_ws();
         let mut s = String::new();
         while let Some(c) = self.iter.next() {
-            if c == '"' {
-                return Some(s);
+            match c {
+                '"' => return Some(s),
+                '\\' => self.parse_escape(&mut s)?,
+                c => s.push(c),
+            }
+        }
+        None
+    }
+
+    /// Add the character the escape sequence after a backslash stands for:
+    /// "\n", "\t", "\x41", "\u20AC", "\101", etc.  A backslash before
+    /// another character is dropped.
+    fn parse_escape(&mut self, s: &mut String) -> Option<()> {
+        let c = self.iter.next()?;
+        let code = match c {
+            'e' => 0x1b,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'n' => 0x0a,
+            'r' => 0x0d,
+            't' => 0x09,
+            'x' | 'X' | 'u' | 'U' => {
+                let max = match c {
+                    'x' | 'X' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                match self.parse_digits(16, max) {
+                    Some(n) => n,
+                    // Not followed by a hex digit: the character itself.
+                    None => c as u64,
+                }
+            }
+            '0'..='7' => {
+                // Up to three octal digits.
+                let mut n = c as u64 - '0' as u64;
+                for _ in 0..2 {
+                    let Some(d) = self.iter.peek().and_then(|c| c.to_digit(8)) else { break };
+                    self.iter.next();
+                    n = n * 8 + d as u64;
+                }
+                n
+            }
+            c => c as u64,
+        };
+        s.push(char::from_u32(code as u32).unwrap_or(char::REPLACEMENT_CHARACTER));
+        Some(())
+    }
+
+    /// A 'literal' string, in which '' stands for a single quote.
+    fn parse_literal_string(&mut self) -> Option<String> {
+        if self.peek_non_ws() != Some('\'') {
+            return None;
+        }
+        self.next_non_ws();
+        let mut s = String::new();
+        while let Some(c) = self.iter.next() {
+            if c == '\'' {
+                if self.iter.peek() != Some(&'\'') {
+                    return Some(s);
+                }
+                self.iter.next();
             }
             s.push(c);
         }
         None
     }
 
+    /// Consume `token` if the input continues with it after white space.
+    fn skip_token(&mut self, token: &str) -> bool {
+        skip_ws(&mut self.iter);
+        let mut probe = self.iter.clone();
+        for expected in token.chars() {
+            if probe.next() != Some(expected) {
+                return false;
+            }
+        }
+        self.iter = probe;
+        true
+    }
+
     fn parse_identifier(&mut self) -> Option<String> {
         let mut s = String::new();
+        // A script-local function: "<SID>Name" or "<SNR>12_Name".
+        for prefix in ["<SID>", "<SNR>"] {
+            if self.skip_token(prefix) {
+                s.push_str(prefix);
+            }
+        }
         if let Some(&c) = self.iter.peek() {
-            if c.is_ascii_alphabetic() || c == '_' {
+            if c.is_ascii_alphabetic() || c == '_' || (!s.is_empty() && c.is_ascii_digit()) {
                 s.push(c);
                 self.iter.next();
             } else {
@@ -190,8 +506,20 @@ impl<'a> Tokenizer<'a> {
         } else {
             return None;
         }
+        // A scope prefix such as "a:" or "g:".  Arguments are also numbered,
+        // as in "a:1".
+        let mut probe = self.iter.clone();
+        if "gbwtslav".contains(s.as_str()) && probe.next() == Some(':') {
+            if let Some(c) = probe.next() {
+                if c.is_ascii_alphabetic() || c == '_' || (s == "a" && c.is_ascii_digit()) {
+                    s.push(':');
+                    self.iter.next();
+                }
+            }
+        }
+        // "#" separates the parts of an autoload name.
         while let Some(&c) = self.iter.peek() {
-            if c.is_ascii_alphanumeric() || c == '_' {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '#' {
                 s.push(c);
                 self.iter.next();
             } else {
@@ -202,22 +530,306 @@ impl<'a> Tokenizer<'a> {
     }
 }
 
-fn parse_primary(tokens: &mut Tokenizer) -> Result<Expr, ()> {
+/// Parse the argument names of a lambda up to `end`, e.g. "x, y" for
+/// `{x, y -> ...}`.  With `typed` a Vim9 type may follow each name.
+fn parse_lambda_params(tokens: &mut Tokenizer, end: &str, typed: bool) -> Option<Vec<String>> {
+    let mut params = Vec::new();
+    if tokens.skip_token(end) {
+        return Some(params);
+    }
+    loop {
+        skip_ws(&mut tokens.iter);
+        params.push(tokens.parse_identifier()?);
+        if typed && tokens.skip_token(":") {
+            skip_type(tokens);
+        }
+        if tokens.skip_token(end) {
+            return Some(params);
+        }
+        if !tokens.skip_token(",") {
+            return None;
+        }
+    }
+}
+
+/// Skip a Vim9 type such as "list<number>" or "func(any): bool".
+fn skip_type(tokens: &mut Tokenizer) {
+    skip_ws(&mut tokens.iter);
+    let mut depth = 0;
+    while let Some(&c) = tokens.iter.peek() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' if depth > 0 => depth -= 1,
+            ':' if depth > 0 => {}
+            c if depth == 0 && !(c.is_ascii_alphanumeric() || c == '_') => break,
+            _ => {}
+        }
+        tokens.iter.next();
+        if depth > 0 {
+            skip_ws(&mut tokens.iter);
+        }
+    }
+}
+
+/// Parse a legacy lambda `{args -> expr}` after the "{".
+fn parse_legacy_lambda(tokens: &mut Tokenizer) -> Result<Expr, ()> {
+    let params = parse_lambda_params(tokens, "->", false).ok_or(())?;
+    let body = parse_expr(tokens)?;
+    if tokens.next_non_ws() != Some('}') {
+        return Err(());
+    }
+    Ok(Expr::Lambda(Rc::new(Lambda::new(params, body, true))))
+}
+
+/// Try parsing a Vim9 lambda `(args) => expr` after the "(".  Returns None
+/// without consuming anything when this is not a lambda.
+fn parse_vim9_lambda(tokens: &mut Tokenizer) -> Option<Result<Expr, ()>> {
+    let mut probe = tokens.clone();
+    let params = parse_lambda_params(&mut probe, ")", true)?;
+    if probe.skip_token(":") {
+        skip_type(&mut probe);
+    }
+    if !probe.skip_token("=>") {
+        return None;
+    }
+    *tokens = probe;
+    Some(parse_expr(tokens).map(|body| Expr::Lambda(Rc::new(Lambda::new(params, body, false)))))
+}
+
+/// Parse a Dictionary after the "{".  With `literal` the keys are names
+/// instead of expressions, as in `#{name: val}`.
+fn parse_dict(tokens: &mut Tokenizer, literal: bool) -> Result<Expr, ()> {
+    let mut items = Vec::new();
+    while !tokens.skip_token("}") {
+        let key = if literal {
+            skip_ws(&mut tokens.iter);
+            let mut key = String::new();
+            while let Some(c) = tokens.iter.peek().copied().filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-') {
+                key.push(c);
+                tokens.iter.next();
+            }
+            if key.is_empty() {
+                return Err(());
+            }
+            Expr::Str(key)
+        } else {
+            parse_expr(tokens)?
+        };
+        if !tokens.skip_token(":") {
+            return Err(());
+        }
+        items.push((key, parse_expr(tokens)?));
+        if !tokens.skip_token(",") && tokens.peek_non_ws() != Some('}') {
+            return Err(());
+        }
+    }
+    Ok(Expr::Dict(items))
+}
+
+/// Parse an interpolated string after the "$": `$"text {expr}"` or
+/// `$'text {expr}'`.  "{{" and "}}" stand for a literal brace.
+fn parse_interpolated(tokens: &mut Tokenizer) -> Result<Expr, ()> {
+    let quote = tokens.iter.next().ok_or(())?;
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    loop {
+        match tokens.iter.next().ok_or(())? {
+            '\'' if quote == '\'' && tokens.iter.peek() == Some(&'\'') => {
+                tokens.iter.next();
+                text.push('\'');
+            }
+            c if c == quote => break,
+            '\\' if quote == '"' => tokens.parse_escape(&mut text).ok_or(())?,
+            c @ ('{' | '}') if tokens.iter.peek() == Some(&c) => {
+                tokens.iter.next();
+                text.push(c);
+            }
+            '{' => {
+                if !text.is_empty() {
+                    parts.push(Expr::Str(std::mem::take(&mut text)));
+                }
+                parts.push(parse_expr(tokens)?);
+                if tokens.next_non_ws() != Some('}') {
+                    return Err(());
+                }
+            }
+            '}' => return Err(()),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(Expr::Str(text));
+    }
+    Ok(Expr::Interp(parts))
+}
+
+fn parse_args(tokens: &mut Tokenizer) -> Result<Vec<Expr>, ()> {
+    let mut args = Vec::new();
+    if tokens.peek_non_ws() != Some(')') {
+        loop {
+            let arg = parse_expr(tokens)?;
+            args.push(arg);
+            match tokens.peek_non_ws() {
+                Some(',') => { tokens.next_non_ws(); }
+                Some(')') => break,
+                _ => return Err(()),
+            }
+        }
+    }
+    if tokens.next_non_ws() != Some(')') {
+        return Err(());
+    }
+    Ok(args)
+}
+
+/// Parse `[idx]` or `[first : last]` after the "[".
+fn parse_index(tokens: &mut Tokenizer, base: Expr) -> Result<Expr, ()> {
+    let first = if tokens.peek_non_ws() == Some(':') { None } else { Some(Box::new(parse_expr(tokens)?)) };
+    if tokens.skip_token(":") {
+        let last = if tokens.peek_non_ws() == Some(']') { None } else { Some(Box::new(parse_expr(tokens)?)) };
+        if !tokens.skip_token("]") {
+            return Err(());
+        }
+        return Ok(Expr::Slice(Box::new(base), first, last));
+    }
+    match first {
+        Some(idx) if tokens.skip_token("]") => Ok(Expr::Index(Box::new(base), idx)),
+        _ => Err(()),
+    }
+}
+
+/// Parse what follows "->": `name(args)` or `{lambda}(args)`.
+fn parse_method(tokens: &mut Tokenizer, base: Expr) -> Result<Expr, ()> {
+    skip_ws(&mut tokens.iter);
+    let func = if tokens.iter.peek() == Some(&'{') {
+        tokens.iter.next();
+        parse_legacy_lambda(tokens)?
+    } else {
+        Expr::Var(tokens.parse_identifier().ok_or(())?)
+    };
+    if tokens.iter.next() != Some('(') {
+        return Err(());
+    }
+    let args = parse_args(tokens)?;
+    Ok(Expr::Method(Box::new(base), Box::new(func), args))
+}
+
+/// Parse the subscripts after an atom: `[idx]`, `[first : last]`, `.key`,
+/// `(args)` and `->method(args)`.  Only "->" may come after white space.
+fn parse_subscripts(tokens: &mut Tokenizer, mut node: Expr) -> Result<Expr, ()> {
+    loop {
+        match tokens.iter.peek() {
+            Some('(') => {
+                tokens.iter.next();
+                node = Expr::CallValue(Box::new(node), parse_args(tokens)?);
+            }
+            Some('[') => {
+                tokens.iter.next();
+                node = parse_index(tokens, node)?;
+            }
+            // "1.5" and "'str'.x" are not a member.
+            Some('.') if !matches!(node, Expr::Number(_) | Expr::Float(_) | Expr::Str(_) | Expr::Interp(_)) => {
+                let mut probe = tokens.iter.clone();
+                probe.next();
+                if !probe.peek().is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    break;
+                }
+                tokens.iter = probe;
+                let mut key = String::new();
+                while let Some(c) = tokens.iter.peek().copied().filter(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    key.push(c);
+                    tokens.iter.next();
+                }
+                node = Expr::Member(Box::new(node), key);
+            }
+            _ => {
+                if !tokens.skip_token("->") {
+                    break;
+                }
+                node = parse_method(tokens, node)?;
+            }
+        }
+    }
+    Ok(node)
+}
+
+fn parse_atom(tokens: &mut Tokenizer) -> Result<Expr, ()> {
     if let Some(c) = tokens.peek_non_ws() {
         if c == '(' {
             tokens.next_non_ws();
-            let expr = parse_concat(tokens)?;
+            if let Some(lambda) = parse_vim9_lambda(tokens) {
+                return lambda;
+            }
+            let expr = parse_expr(tokens)?;
             if tokens.next_non_ws() != Some(')') {
                 return Err(());
             }
             return Ok(expr);
         }
-        if c == '"' {
-            if let Some(s) = tokens.parse_string() {
-                return Ok(Expr::Str(s));
-            } else {
+        if c == '{' {
+            tokens.next_non_ws();
+            if parse_lambda_params(&mut tokens.clone(), "->", false).is_some() {
+                return parse_legacy_lambda(tokens);
+            }
+            return parse_dict(tokens, false);
+        }
+        if c == '#' {
+            tokens.next_non_ws();
+            if tokens.iter.next() != Some('{') {
                 return Err(());
             }
+            return parse_dict(tokens, true);
+        }
+        if c == '[' {
+            tokens.next_non_ws();
+            let mut items = Vec::new();
+            while !tokens.skip_token("]") {
+                items.push(parse_expr(tokens)?);
+                if !tokens.skip_token(",") && tokens.peek_non_ws() != Some(']') {
+                    return Err(());
+                }
+            }
+            return Ok(Expr::List(items));
+        }
+        if c == '"' {
+            return tokens.parse_string().map(Expr::Str).ok_or(());
+        }
+        if c == '\'' {
+            return tokens.parse_literal_string().map(Expr::Str).ok_or(());
+        }
+        if c == '$' {
+            tokens.next_non_ws();
+            if matches!(tokens.iter.peek(), Some('"' | '\'')) {
+                return parse_interpolated(tokens);
+            }
+            // An environment variable, "$HOME".
+            let mut name = String::from("$");
+            while let Some(c) = tokens.iter.peek().copied().filter(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                name.push(c);
+                tokens.iter.next();
+            }
+            return if name.len() > 1 { Ok(Expr::Var(name)) } else { Err(()) };
+        }
+        if c == '&' {
+            // An option, "&tw", "&l:tw" or "&g:tw".
+            tokens.next_non_ws();
+            let mut name = String::from("&");
+            for prefix in ["l:", "g:"] {
+                if tokens.skip_token(prefix) {
+                    name.push_str(prefix);
+                }
+            }
+            while let Some(c) = tokens.iter.peek().copied().filter(char::is_ascii_alphabetic) {
+                name.push(c);
+                tokens.iter.next();
+            }
+            return Ok(Expr::Var(name));
+        }
+        if c == '@' {
+            // A register, "@a".
+            tokens.next_non_ws();
+            let reg = tokens.iter.next().filter(|c| !c.is_whitespace()).ok_or(())?;
+            return Ok(Expr::Var(format!("@{}", reg)));
         }
     }
     if let Some(num) = tokens.parse_number() {
@@ -226,21 +838,7 @@ fn parse_primary(tokens: &mut Tokenizer) -> Result<Expr, ()> {
     if let Some(id) = tokens.parse_identifier() {
         if tokens.peek_non_ws() == Some('(') {
             tokens.next_non_ws();
-            let mut args = Vec::new();
-            if tokens.peek_non_ws() != Some(')') {
-                loop {
-                    let arg = parse_concat(tokens)?;
-                    args.push(arg);
-                    match tokens.peek_non_ws() {
-                        Some(',') => { tokens.next_non_ws(); }
-                        Some(')') => break,
-                        _ => return Err(()),
-                    }
-                }
-            }
-            if tokens.next_non_ws() != Some(')') {
-                return Err(());
-            }
+            let args = parse_args(tokens)?;
             return Ok(Expr::Call(id, args));
         } else {
             return Ok(Expr::Var(id));
@@ -249,116 +847,570 @@ fn parse_primary(tokens: &mut Tokenizer) -> Result<Expr, ()> {
     Err(())
 }
 
+/// Parse an atom with its subscripts and the unary operators "!", "-" and
+/// "+" before it.  The operators apply to the result of subscripts, except
+/// that for a number they apply first: "-1->abs()" is 1.
+fn parse_unary(tokens: &mut Tokenizer) -> Result<Expr, ()> {
+    let mut ops = Vec::new();
+    while let Some(op @ ('!' | '-' | '+')) = tokens.peek_non_ws() {
+        tokens.next_non_ws();
+        ops.push(op);
+    }
+    let apply = |ops: &[char], node: Expr| {
+        ops.iter().rev().fold(node, |node, op| match op {
+            '!' => Expr::Not(Box::new(node)),
+            '-' => Expr::Neg(Box::new(node)),
+            _ => Expr::Plus(Box::new(node)),
+        })
+    };
+    if tokens.peek_non_ws().is_some_and(|c| c.is_ascii_digit()) {
+        let num = tokens.parse_number().ok_or(())?;
+        return parse_subscripts(tokens, apply(&ops, num));
+    }
+    let atom = parse_atom(tokens)?;
+    Ok(apply(&ops, parse_subscripts(tokens, atom)?))
+}
+
 fn parse_mul_div(tokens: &mut Tokenizer) -> Result<Expr, ()> {
-    let mut node = parse_primary(tokens)?;
+    let mut node = parse_unary(tokens)?;
     loop {
         let op = match tokens.peek_non_ws() {
             Some('*') => '*',
             Some('/') => '/',
+            Some('%') => '%',
             _ => break,
         };
         tokens.next_non_ws();
-        let rhs = parse_primary(tokens)?;
+        let rhs = parse_unary(tokens)?;
         node = match op {
             '*' => Expr::Mul(Box::new(node), Box::new(rhs)),
             '/' => Expr::Div(Box::new(node), Box::new(rhs)),
+            '%' => Expr::Mod(Box::new(node), Box::new(rhs)),
             _ => unreachable!(),
         };
     }
     Ok(node)
 }
 
+/// "+", "-", "." and ".." have the same precedence.
 fn parse_add_sub(tokens: &mut Tokenizer) -> Result<Expr, ()> {
     let mut node = parse_mul_div(tokens)?;
     loop {
         let op = match tokens.peek_non_ws() {
             Some('+') => '+',
             Some('-') => '-',
+            Some('.') => '.',
             _ => break,
         };
         tokens.next_non_ws();
+        if op == '.' {
+            // Both "." and ".." concatenate.
+            tokens.skip_token(".");
+        }
         let rhs = parse_mul_div(tokens)?;
         node = match op {
             '+' => Expr::Add(Box::new(node), Box::new(rhs)),
             '-' => Expr::Sub(Box::new(node), Box::new(rhs)),
-            _ => unreachable!(),
+            _ => Expr::Concat(Box::new(node), Box::new(rhs)),
         };
     }
     Ok(node)
 }
 
-fn parse_concat(tokens: &mut Tokenizer) -> Result<Expr, ()> {
-    let mut node = parse_add_sub(tokens)?;
-    loop {
-        match tokens.peek_non_ws() {
-            Some('.') => {
-                tokens.next_non_ws();
-                let rhs = parse_add_sub(tokens)?;
-                node = Expr::Concat(Box::new(node), Box::new(rhs));
-            }
-            _ => break,
+/// Parse a comparison operator with the "#" or "?" that may follow it.
+fn parse_cmp_op(tokens: &mut Tokenizer) -> Option<(CmpOp, MatchCase)> {
+    const OPS: [(&str, CmpOp); 8] = [
+        ("==", CmpOp::Equal),
+        ("!=", CmpOp::NotEqual),
+        (">=", CmpOp::GreaterEqual),
+        ("<=", CmpOp::LessEqual),
+        ("=~", CmpOp::Match),
+        ("!~", CmpOp::NoMatch),
+        (">", CmpOp::Greater),
+        ("<", CmpOp::Less),
+    ];
+    let mut op = OPS.iter().find(|(token, _)| tokens.skip_token(token)).map(|&(_, op)| op);
+    if op.is_none() {
+        // "is" and "isnot" must not be the start of a name.
+        let mut probe = tokens.iter.clone();
+        let mut word = String::new();
+        while let Some(c) = probe.peek().copied().filter(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            word.push(c);
+            probe.next();
+        }
+        op = match word.as_str() {
+            "is" => Some(CmpOp::Is),
+            "isnot" => Some(CmpOp::IsNot),
+            _ => None,
+        };
+        if op.is_some() {
+            tokens.iter = probe;
         }
     }
+    let op = op?;
+    let case = match tokens.iter.peek() {
+        Some('#') => MatchCase::Match,
+        Some('?') => MatchCase::Ignore,
+        _ => return Some((op, MatchCase::Option)),
+    };
+    tokens.iter.next();
+    Some((op, case))
+}
+
+fn parse_compare(tokens: &mut Tokenizer) -> Result<Expr, ()> {
+    let node = parse_add_sub(tokens)?;
+    let Some((op, case)) = parse_cmp_op(tokens) else {
+        return Ok(node);
+    };
+    let rhs = parse_add_sub(tokens)?;
+    Ok(Expr::Compare(op, case, Box::new(node), Box::new(rhs)))
+}
+
+fn parse_and(tokens: &mut Tokenizer) -> Result<Expr, ()> {
+    let mut node = parse_compare(tokens)?;
+    while tokens.skip_token("&&") {
+        node = Expr::And(Box::new(node), Box::new(parse_compare(tokens)?));
+    }
+    Ok(node)
+}
+
+fn parse_or(tokens: &mut Tokenizer) -> Result<Expr, ()> {
+    let mut node = parse_and(tokens)?;
+    while tokens.skip_token("||") {
+        node = Expr::Or(Box::new(node), Box::new(parse_and(tokens)?));
+    }
     Ok(node)
 }
 
-fn eval(expr: &Expr, ctx: &Evaluator) -> Result<Value, ()> {
+/// Parse a whole expression, including `cond ? a : b` and `a ?? b`.
+fn parse_expr(tokens: &mut Tokenizer) -> Result<Expr, ()> {
+    let node = parse_or(tokens)?;
+    if tokens.skip_token("??") {
+        return Ok(Expr::Falsy(Box::new(node), Box::new(parse_expr(tokens)?)));
+    }
+    if !tokens.skip_token("?") {
+        return Ok(node);
+    }
+    let then = parse_expr(tokens)?;
+    if !tokens.skip_token(":") {
+        return Err(());
+    }
+    let otherwise = parse_expr(tokens)?;
+    Ok(Expr::Ternary(Box::new(node), Box::new(then), Box::new(otherwise)))
+}
+
+fn eval_args(args: &[Expr], ctx: &mut Evaluator) -> Result<Vec<Value>, ()> {
+    args.iter().map(|e| eval(e, ctx)).collect()
+}
+
+impl Evaluator {
+    /// Compare two values for `op`.  Lists, Dictionaries, Blobs and Funcrefs
+    /// can only be checked for being equal.
+    fn compare(&mut self, op: CmpOp, case: MatchCase, a: &Value, b: &Value) -> Result<bool, ()> {
+        use std::cmp::Ordering;
+        let ic = match case {
+            MatchCase::Option => self.ignorecase(),
+            MatchCase::Match => false,
+            MatchCase::Ignore => true,
+        };
+        match op {
+            CmpOp::Is | CmpOp::IsNot => {
+                let same = match (a, b) {
+                    (Value::List(x), Value::List(y)) => Rc::ptr_eq(x, y),
+                    (Value::Dict(x), Value::Dict(y)) => Rc::ptr_eq(x, y),
+                    (Value::Blob(x), Value::Blob(y)) => Rc::ptr_eq(x, y),
+                    (Value::Tuple(x), Value::Tuple(y)) => Rc::ptr_eq(x, y),
+                    _ => a.type_nr() == b.type_nr() && a.equal(b, ic),
+                };
+                return Ok(same == (op == CmpOp::Is));
+            }
+            CmpOp::Match | CmpOp::NoMatch => {
+                let text = self.tv_string(a)?;
+                let pat = self.tv_string(b)?;
+                let re = match VimRegex::new(&pat, ic) {
+                    Ok(re) => re,
+                    Err(msg) => return self.emsg(msg),
+                };
+                return Ok(re.is_match(&text) == (op == CmpOp::Match));
+            }
+            _ => {}
+        }
+        let equality = matches!(op, CmpOp::Equal | CmpOp::NotEqual);
+        let kind = match (a, b) {
+            (Value::List(_), _) | (_, Value::List(_)) => Some(("List", "E691", "E692")),
+            (Value::Dict(_), _) | (_, Value::Dict(_)) => Some(("Dictionary", "E735", "E736")),
+            (Value::Blob(_), _) | (_, Value::Blob(_)) => Some(("Blob", "E977", "E978")),
+            _ => None,
+        };
+        if let Some((name, e_type, e_op)) = kind {
+            if a.type_nr() != b.type_nr() {
+                return self.emsg(format!("{}: Can only compare {} with {}", e_type, name, name));
+            }
+            if !equality {
+                return self.emsg(format!("{}: Invalid operation for {}", e_op, name));
+            }
+            return Ok(a.equal(b, ic) == (op == CmpOp::Equal));
+        }
+        let ord = match (a, b) {
+            (Value::Func(x), Value::Func(y)) if equality => {
+                let equal = x.name == y.name && x.args == y.args;
+                return Ok(equal == (op == CmpOp::Equal));
+            }
+            (Value::Func(_), _) | (_, Value::Func(_)) => {
+                if !equality {
+                    return self.emsg("E694: Invalid operation for Funcrefs".to_string());
+                }
+                return Ok(op == CmpOp::NotEqual);
+            }
+            (Value::Str(x), Value::Str(y)) if ic => x.to_lowercase().cmp(&y.to_lowercase()),
+            (Value::Str(x), Value::Str(y)) => x.cmp(y),
+            (Value::Float(_), _) | (_, Value::Float(_)) => {
+                let x = self.tv_float(a)?;
+                let y = self.tv_float(b)?;
+                x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+            }
+            (Value::Number(_) | Value::Str(_) | Value::Bool(_) | Value::Special(_), _) => {
+                self.tv_number(a)?.cmp(&self.tv_number(b)?)
+            }
+            _ if equality => return Ok(a.equal(b, ic) == (op == CmpOp::Equal)),
+            _ => return Err(()),
+        };
+        Ok(match op {
+            CmpOp::Equal => ord == Ordering::Equal,
+            CmpOp::NotEqual => ord != Ordering::Equal,
+            CmpOp::Greater => ord == Ordering::Greater,
+            CmpOp::GreaterEqual => ord != Ordering::Less,
+            CmpOp::Less => ord == Ordering::Less,
+            _ => ord != Ordering::Greater,
+        })
+    }
+
+    /// Apply arithmetic operator `op` to two values: '+', '-', '*', '/' or
+    /// '%'.  Adding two Lists or two Blobs concatenates them.  A String is
+    /// converted to a Number.  Dividing a Number by zero gives the largest
+    /// or smallest Number, like Vim.
+    pub(crate) fn arith(&mut self, op: char, a: Value, b: Value) -> Result<Value, ()> {
+        match (&a, &b) {
+            (Value::List(x), Value::List(y)) if op == '+' => {
+                let mut items = x.borrow().clone();
+                items.extend(y.borrow().iter().cloned());
+                Ok(Value::new_list(items))
+            }
+            (Value::Blob(x), Value::Blob(y)) if op == '+' => {
+                let mut bytes = x.borrow().clone();
+                bytes.extend_from_slice(&y.borrow());
+                Ok(Value::new_blob(bytes))
+            }
+            (Value::Float(_), _) | (_, Value::Float(_)) => {
+                if op == '%' {
+                    return self.emsg("E804: Cannot use '%' with Float".to_string());
+                }
+                let x = self.arith_float(&a)?;
+                let y = self.arith_float(&b)?;
+                Ok(Value::Float(match op {
+                    '+' => x + y,
+                    '-' => x - y,
+                    '*' => x * y,
+                    _ => x / y,
+                }))
+            }
+            _ => {
+                let x = self.tv_number(&a)?;
+                let y = self.tv_number(&b)?;
+                Ok(Value::Number(match op {
+                    '+' => x.wrapping_add(y),
+                    '-' => x.wrapping_sub(y),
+                    '*' => x.wrapping_mul(y),
+                    '/' if y == 0 => match x {
+                        0 => i64::MIN,
+                        x if x > 0 => i64::MAX,
+                        _ => -i64::MAX,
+                    },
+                    '/' => x.wrapping_div(y),
+                    _ if y == 0 => 0,
+                    _ => x.wrapping_rem(y),
+                }))
+            }
+        }
+    }
+
+    fn arith_float(&mut self, val: &Value) -> Result<f64, ()> {
+        match val {
+            Value::Float(f) => Ok(*f),
+            val => Ok(self.tv_number(val)? as f64),
+        }
+    }
+
+    /// `base[idx]`.  A Number is indexed like a String.
+    fn index_value(&mut self, base: &Value, idx: &Value) -> Result<Value, ()> {
+        match base {
+            Value::Str(_) | Value::Number(_) => {
+                let s = self.tv_string(base)?;
+                let n = self.tv_number(idx)?;
+                let byte = usize::try_from(n).ok().and_then(|i| s.as_bytes().get(i));
+                Ok(Value::Str(byte.map_or(String::new(), |b| String::from_utf8_lossy(&[*b]).into_owned())))
+            }
+            Value::List(list) => {
+                let len = list.borrow().len();
+                let n = self.tv_number(idx)?;
+                let i = self.list_index(len, &Value::Number(n))?;
+                let item = list.borrow()[i].clone();
+                Ok(item)
+            }
+            Value::Tuple(tuple) => {
+                let n = self.tv_number(idx)?;
+                let i = if n < 0 { tuple.len() as i64 + n } else { n };
+                match usize::try_from(i).ok().and_then(|i| tuple.get(i)) {
+                    Some(item) => Ok(item.clone()),
+                    None => self.emsg(format!("E1519: Tuple index out of range: {}", n)),
+                }
+            }
+            Value::Blob(blob) => {
+                let n = self.tv_number(idx)?;
+                let len = blob.borrow().len() as i64;
+                let i = if n < 0 { len + n } else { n };
+                match usize::try_from(i).ok().and_then(|i| blob.borrow().get(i).copied()) {
+                    Some(byte) => Ok(Value::Number(byte as i64)),
+                    None => self.emsg(format!("E979: Blob index out of range: {}", n)),
+                }
+            }
+            Value::Dict(dict) => {
+                let key = self.tv_string(idx)?;
+                let item = dict.borrow().get(&key).cloned();
+                match item {
+                    Some(item) => Ok(item),
+                    None => self.emsg(format!("E716: Key not present in Dictionary: \"{}\"", key)),
+                }
+            }
+            _ => self.index_error(base),
+        }
+    }
+
+    /// `base[first : last]`, the last item is included.  Out of range
+    /// indexes give an empty result.
+    fn slice_value(&mut self, base: &Value, first: Option<&Value>, last: Option<&Value>) -> Result<Value, ()> {
+        if let Value::Dict(_) = base {
+            return self.emsg("E719: Cannot slice a Dictionary".to_string());
+        }
+        let first = match first {
+            Some(val) => self.tv_number(val)?,
+            None => 0,
+        };
+        let last = match last {
+            Some(val) => self.tv_number(val)?,
+            None => -1,
+        };
+        let range = |len: usize| {
+            let len = len as i64;
+            let first = if first < 0 { (len + first).max(0) } else { first };
+            let last = if last < 0 { len + last } else { last.min(len - 1) };
+            if first > last {
+                0..0
+            } else {
+                first as usize..last as usize + 1
+            }
+        };
+        match base {
+            Value::Str(_) | Value::Number(_) => {
+                let s = self.tv_string(base)?;
+                let bytes = s.as_bytes();
+                Ok(Value::Str(String::from_utf8_lossy(&bytes[range(bytes.len())]).into_owned()))
+            }
+            Value::List(list) => {
+                let items = list.borrow();
+                Ok(Value::new_list(items[range(items.len())].to_vec()))
+            }
+            Value::Tuple(tuple) => Ok(Value::new_tuple(tuple[range(tuple.len())].to_vec())),
+            Value::Blob(blob) => {
+                let bytes = blob.borrow();
+                Ok(Value::new_blob(bytes[range(bytes.len())].to_vec()))
+            }
+            _ => self.index_error(base),
+        }
+    }
+
+    fn index_error<T>(&mut self, base: &Value) -> Result<T, ()> {
+        match base {
+            Value::Float(_) => self.emsg("E806: Using a Float as a String".to_string()),
+            Value::Func(_) => self.emsg("E695: Cannot index a Funcref".to_string()),
+            Value::Bool(_) | Value::Special(_) => self.emsg("E909: Cannot index a special variable".to_string()),
+            _ => self.emsg("E689: Can only index a List, Dictionary or Blob".to_string()),
+        }
+    }
+
+    /// `base.key`: a Dictionary item, or for other values concatenating
+    /// with the variable `key`, as in legacy Vim script.
+    fn member_value(&mut self, base: &Value, key: &str) -> Result<Value, ()> {
+        match base {
+            Value::Dict(_) => self.index_value(base, &Value::Str(key.to_string())),
+            Value::Object(obj) => match obj.member(key) {
+                Some(val) => Ok(val),
+                None => self.emsg(format!("E1326: Variable \"{}\" not found in object", key)),
+            },
+            _ => {
+                let rhs = if key.bytes().all(|b| b.is_ascii_digit()) {
+                    Value::Str(key.to_string())
+                } else {
+                    eval(&Expr::Var(key.to_string()), self)?
+                };
+                Ok(Value::Str(base.to_string() + &rhs.to_string()))
+            }
+        }
+    }
+
+    /// The function for `expr(args)`.  A Funcref taken from a Dictionary is
+    /// bound to it, so that it is called with "self".
+    fn eval_callee(&mut self, expr: &Expr) -> Result<Value, ()> {
+        let (base, func) = match expr {
+            Expr::Member(base, key) => {
+                let base = eval(base, self)?;
+                let func = self.member_value(&base, key)?;
+                (base, func)
+            }
+            Expr::Index(base, idx) => {
+                let base = eval(base, self)?;
+                let idx = eval(idx, self)?;
+                let func = self.index_value(&base, &idx)?;
+                (base, func)
+            }
+            expr => return eval(expr, self),
+        };
+        match (base, func) {
+            (Value::Dict(dict), Value::Func(pt)) if pt.dict.is_none() => {
+                let mut pt = (*pt).clone();
+                pt.dict = Some(dict);
+                Ok(Value::new_func(pt))
+            }
+            (_, func) => Ok(func),
+        }
+    }
+}
+
+fn eval(expr: &Expr, ctx: &mut Evaluator) -> Result<Value, ()> {
     match expr {
         Expr::Number(n) => Ok(Value::Number(*n)),
         Expr::Float(f) => Ok(Value::Float(*f)),
         Expr::Str(s) => Ok(Value::Str(s.clone())),
-        Expr::Var(name) => Ok(ctx.get_var(name).unwrap_or(Value::Number(0))),
+        Expr::Blob(bytes) => Ok(Value::new_blob(bytes.clone())),
+        Expr::Var(name) => match ctx.lookup_var(name) {
+            Some(val) => Ok(val),
+            None if name.starts_with('&') => ctx.emsg(format!("E113: Unknown option: {}", &name[1..])),
+            None => ctx.emsg(format!("E121: Undefined variable: {}", name)),
+        },
+        Expr::List(items) => Ok(Value::new_list(eval_args(items, ctx)?)),
+        Expr::Dict(items) => {
+            let mut dict = std::collections::BTreeMap::new();
+            for (key, val) in items {
+                let key = eval(key, ctx)?;
+                let key = ctx.tv_string(&key)?;
+                let val = eval(val, ctx)?;
+                if dict.insert(key.clone(), val).is_some() {
+                    return ctx.emsg(format!("E721: Duplicate key in Dictionary: \"{}\"", key));
+                }
+            }
+            Ok(Value::new_dict(dict))
+        }
+        Expr::Lambda(lambda) => Ok(ctx.make_lambda(lambda)),
+        Expr::Interp(parts) => {
+            let mut s = String::new();
+            for part in parts {
+                s.push_str(&eval(part, ctx)?.to_string());
+            }
+            Ok(Value::Str(s))
+        }
         Expr::Call(name, args) => {
-            let vals = args
-                .iter()
-                .map(|e| eval(e, ctx))
-                .collect::<Result<Vec<_>, _>>()?;
+            let vals = eval_args(args, ctx)?;
             ctx.call_function(name, &vals)
         }
-        Expr::Add(a, b) => {
-            let a = eval(a, ctx)?;
-            let b = eval(b, ctx)?;
-            match (a, b) {
-                (Value::Number(n1), Value::Number(n2)) => Ok(Value::Number(n1 + n2)),
-                (Value::Float(f1), Value::Float(f2)) => Ok(Value::Float(f1 + f2)),
-                (Value::Float(f1), Value::Number(n2)) => Ok(Value::Float(f1 + n2 as f64)),
-                (Value::Number(n1), Value::Float(f2)) => Ok(Value::Float(n1 as f64 + f2)),
-                _ => Err(()),
+        Expr::CallValue(func, args) => {
+            let func = ctx.eval_callee(func)?;
+            let vals = eval_args(args, ctx)?;
+            ctx.call_value(&func, &vals)
+        }
+        Expr::Method(base, func, args) => {
+            let mut vals = vec![eval(base, ctx)?];
+            vals.extend(eval_args(args, ctx)?);
+            match &**func {
+                Expr::Var(name) => ctx.call_function(name, &vals),
+                func => {
+                    let func = eval(func, ctx)?;
+                    ctx.call_value(&func, &vals)
+                }
             }
         }
-        Expr::Sub(a, b) => {
+        Expr::Index(base, idx) => {
+            let base = eval(base, ctx)?;
+            let idx = eval(idx, ctx)?;
+            ctx.index_value(&base, &idx)
+        }
+        Expr::Slice(base, first, last) => {
+            let base = eval(base, ctx)?;
+            let first = first.as_ref().map(|e| eval(e, ctx)).transpose()?;
+            let last = last.as_ref().map(|e| eval(e, ctx)).transpose()?;
+            ctx.slice_value(&base, first.as_ref(), last.as_ref())
+        }
+        Expr::Member(base, key) => {
+            let base = eval(base, ctx)?;
+            ctx.member_value(&base, key)
+        }
+        Expr::Not(a) => {
+            let val = eval(a, ctx)?;
+            let b = match val {
+                Value::Float(f) => f != 0.0,
+                val => ctx.is_true(&val)?,
+            };
+            Ok(Value::Number(!b as i64))
+        }
+        Expr::Neg(a) => match eval(a, ctx)? {
+            Value::Float(f) => Ok(Value::Float(-f)),
+            val => Ok(Value::Number(ctx.tv_number(&val)?.wrapping_neg())),
+        },
+        Expr::Plus(a) => match eval(a, ctx)? {
+            val @ Value::Float(_) => Ok(val),
+            val => Ok(Value::Number(ctx.tv_number(&val)?)),
+        },
+        Expr::Compare(op, case, a, b) => {
             let a = eval(a, ctx)?;
             let b = eval(b, ctx)?;
-            match (a, b) {
-                (Value::Number(n1), Value::Number(n2)) => Ok(Value::Number(n1 - n2)),
-                (Value::Float(f1), Value::Float(f2)) => Ok(Value::Float(f1 - f2)),
-                (Value::Float(f1), Value::Number(n2)) => Ok(Value::Float(f1 - n2 as f64)),
-                (Value::Number(n1), Value::Float(f2)) => Ok(Value::Float(n1 as f64 - f2)),
-                _ => Err(()),
-            }
+            Ok(Value::Number(ctx.compare(*op, *case, &a, &b)? as i64))
         }
-        Expr::Mul(a, b) => {
+        Expr::And(a, b) => {
             let a = eval(a, ctx)?;
-            let b = eval(b, ctx)?;
-            match (a, b) {
-                (Value::Number(n1), Value::Number(n2)) => Ok(Value::Number(n1 * n2)),
-                (Value::Float(f1), Value::Float(f2)) => Ok(Value::Float(f1 * f2)),
-                (Value::Float(f1), Value::Number(n2)) => Ok(Value::Float(f1 * n2 as f64)),
-                (Value::Number(n1), Value::Float(f2)) => Ok(Value::Float(n1 as f64 * f2)),
-                _ => Err(()),
-            }
+            let result = ctx.is_true(&a)? && {
+                let b = eval(b, ctx)?;
+                ctx.is_true(&b)?
+            };
+            Ok(Value::Number(result as i64))
         }
-        Expr::Div(a, b) => {
+        Expr::Or(a, b) => {
             let a = eval(a, ctx)?;
-            let b = eval(b, ctx)?;
-            match (a, b) {
-                (Value::Number(n1), Value::Number(n2)) => Ok(Value::Number(n1 / n2)),
-                (Value::Float(f1), Value::Float(f2)) => Ok(Value::Float(f1 / f2)),
-                (Value::Float(f1), Value::Number(n2)) => Ok(Value::Float(f1 / n2 as f64)),
-                (Value::Number(n1), Value::Float(f2)) => Ok(Value::Float(n1 as f64 / f2)),
-                _ => Err(()),
+            let result = ctx.is_true(&a)? || {
+                let b = eval(b, ctx)?;
+                ctx.is_true(&b)?
+            };
+            Ok(Value::Number(result as i64))
+        }
+        Expr::Ternary(cond, then, otherwise) => {
+            let cond = eval(cond, ctx)?;
+            if ctx.is_true(&cond)? {
+                eval(then, ctx)
+            } else {
+                eval(otherwise, ctx)
+            }
+        }
+        Expr::Falsy(a, b) => {
+            let val = eval(a, ctx)?;
+            if listfunc::is_empty(&val) {
+                eval(b, ctx)
+            } else {
+                Ok(val)
             }
         }
+        Expr::Add(a, b) => binary('+', a, b, ctx),
+        Expr::Sub(a, b) => binary('-', a, b, ctx),
+        Expr::Mul(a, b) => binary('*', a, b, ctx),
+        Expr::Div(a, b) => binary('/', a, b, ctx),
+        Expr::Mod(a, b) => binary('%', a, b, ctx),
         Expr::Concat(a, b) => {
             let left = eval(a, ctx)?.to_string();
             let right = eval(b, ctx)?.to_string();
@@ -367,6 +1419,11 @@ fn eval(expr: &Expr, ctx: &Evaluator) -> Result<Value, ()> {
     }
 }
 
+fn binary(op: char, a: &Expr, b: &Expr, ctx: &mut Evaluator) -> Result<Value, ()> {
+    let a = eval(a, ctx)?;
+    let b = eval(b, ctx)?;
+    ctx.arith(op, a, b)
+}
 
 #[no_mangle]
 pub extern "C" fn eval_expr_rs(expr: *const c_char, out: *mut typval_T) -> bool {
@@ -378,8 +1435,8 @@ pub extern "C" fn eval_expr_rs(expr: *const c_char, out: *mut typval_T) -> bool
         Ok(s) => s,
         Err(_) => return false,
     };
-    let eval = GLOBAL_EVAL.lock().unwrap();
-    match eval.eval_expr(expr_str) {
+    let result = GLOBAL_EVAL.with(|eval| eval.borrow_mut().eval_expr(expr_str));
+    match result {
         Ok(val) => {
             unsafe { to_typval(val, out); }
             true
@@ -406,8 +1463,8 @@ pub extern "C" fn eval_to_bool_rs(expr: *const c_char, error: *mut bool) -> bool
             return false;
         }
     };
-    let eval = GLOBAL_EVAL.lock().unwrap();
-    match eval.eval_expr(expr_str) {
+    let result = GLOBAL_EVAL.with(|eval| eval.borrow_mut().eval_expr(expr_str));
+    match result {
         Ok(val) => match val.as_float() {
             Ok(n) => {
                 if !error.is_null() {
@@ -441,8 +1498,8 @@ pub extern "C" fn eval_variable_rs(name: *const c_char, out: *mut typval_T) -> b
         Ok(s) => s,
         Err(_) => return false,
     };
-    let eval = GLOBAL_EVAL.lock().unwrap();
-    match eval.get_var(name_str) {
+    let result = GLOBAL_EVAL.with(|eval| eval.borrow().get_var(name_str));
+    match result {
         Some(val) => {
             unsafe { to_typval(val, out); }
             true
@@ -462,9 +1519,8 @@ pub extern "C" fn set_variable_rs(name: *const c_char, val: *const typval_T) ->
         Err(_) => return false,
     };
     let value = unsafe { from_typval(val) };
-    let mut eval = GLOBAL_EVAL.lock().unwrap();
     if let Some(v) = value {
-        eval.set_var(name_str, v);
+        GLOBAL_EVAL.with(|eval| eval.borrow_mut().set_var(name_str, v));
         true
     } else {
         false
@@ -494,8 +1550,8 @@ pub extern "C" fn call_function_rs(name: *const c_char, args: *const typval_T, a
             return false;
         }
     }
-    let eval = GLOBAL_EVAL.lock().unwrap();
-    match eval.call_function(name_str, &vals) {
+    let result = GLOBAL_EVAL.with(|eval| eval.borrow_mut().call_function(name_str, &vals));
+    match result {
         Ok(v) => {
             unsafe { to_typval(v, out); }
             true
@@ -514,8 +1570,8 @@ pub extern "C" fn eval_script_rs(script: *const c_char, out: *mut typval_T) -> b
         Ok(s) => s,
         Err(_) => return false,
     };
-    let mut eval = GLOBAL_EVAL.lock().unwrap();
-    match eval.eval_script(script_str) {
+    let result = GLOBAL_EVAL.with(|eval| eval.borrow_mut().eval_script(script_str));
+    match result {
         Ok(Some(val)) => {
             if !out.is_null() {
                 unsafe { to_typval(val, out); }
@@ -527,6 +1583,20 @@ pub extern "C" fn eval_script_rs(script: *const c_char, out: *mut typval_T) -> b
     }
 }
 
+/// Execute `:source {path}`.  Returns false when an error was given.
+#[no_mangle]
+pub extern "C" fn source_rs(path: *const c_char) -> bool {
+    if path.is_null() {
+        return false;
+    }
+    let c_str = unsafe { CStr::from_ptr(path) };
+    let path_str = match c_str.to_str() {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    GLOBAL_EVAL.with(|eval| eval.borrow_mut().source_file(Path::new(path_str))).is_ok()
+}
+
 // Minimal C-ABI replacement for Vim's eval_to_string().
 // For now this returns a copy of the input expression as a newly allocated C string.
 #[no_mangle]
@@ -0,0 +1,668 @@
+//! Builtin functions on Lists and Dictionaries.
+//!
+//! Functions that change a List or Dictionary in place check its lock
+//! first, like Vim's value_check_lock().  map() and filter() accept a
+//! Funcref, called with the key and the item, or a String that is evaluated
+//! with v:key and v:val set.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use rust_core::{DictRef, VarLock};
+
+use crate::{Evaluator, Value};
+
+impl Evaluator {
+    /// Give E741 when `lock` does not allow changing the argument of
+    /// function `name`.
+    pub(crate) fn check_lock(&mut self, lock: VarLock, name: &str) -> Result<(), ()> {
+        match lock.check(&format!("{}() argument", name)) {
+            Ok(()) => Ok(()),
+            Err(msg) => self.emsg(msg),
+        }
+    }
+
+    /// Evaluate the {expr2} of map() or filter() for one item.
+    fn eval_item(&mut self, expr: &Value, key: Value, val: Value) -> Result<Value, ()> {
+        if let Value::Func(_) = expr {
+            return self.call_value(expr, &[key, val]);
+        }
+        let text = self.tv_string(expr)?;
+        let saved_key = self.scopes.vim.insert("key".to_string(), key);
+        let saved_val = self.scopes.vim.insert("val".to_string(), val);
+        let result = self.eval_cmd_expr(&text);
+        for (name, saved) in [("key", saved_key), ("val", saved_val)] {
+            match saved {
+                Some(saved) => self.scopes.vim.insert(name.to_string(), saved),
+                None => self.scopes.vim.remove(name),
+            };
+        }
+        result
+    }
+}
+
+/// The index `idx` of a sequence of `len` items, counting from the end when
+/// negative.  None when out of range.
+fn list_index(idx: i64, len: usize) -> Option<usize> {
+    let idx = if idx < 0 { idx + len as i64 } else { idx };
+    usize::try_from(idx).ok().filter(|&i| i < len)
+}
+
+fn list_required<T>(ev: &mut Evaluator) -> Result<T, ()> {
+    ev.emsg("E714: List required".to_string())
+}
+
+fn dict_required<T>(ev: &mut Evaluator) -> Result<T, ()> {
+    ev.emsg("E715: Dictionary required".to_string())
+}
+
+fn list_or_dict_required<T>(ev: &mut Evaluator, name: &str) -> Result<T, ()> {
+    ev.emsg(format!("E712: Argument of {}() must be a List or Dictionary", name))
+}
+
+/// add({object}, {expr}): append to a List or Blob.  For other values the
+/// arguments are added as numbers, which scripts written for the first
+/// version of this evaluator still use.
+pub(crate) fn f_add(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    match &args[0] {
+        Value::List(list) => {
+            ev.check_lock(list.lock(), "add")?;
+            list.borrow_mut().push(args[1].clone());
+        }
+        Value::Blob(blob) => {
+            ev.check_lock(blob.lock(), "add")?;
+            let byte = ev.tv_number(&args[1])?;
+            blob.borrow_mut().push(byte as u8);
+        }
+        a => {
+            let (a, b) = (a.as_float()?, args[1].as_float()?);
+            let sum = a + b;
+            return Ok(if args.iter().any(|v| matches!(v, Value::Float(_))) || sum.fract() != 0.0 {
+                Value::Float(sum)
+            } else {
+                Value::Number(sum as i64)
+            });
+        }
+    }
+    Ok(args[0].clone())
+}
+
+/// insert({object}, {item} [, {idx}]): insert before {idx}, at the start by
+/// default.
+pub(crate) fn f_insert(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let Value::List(list) = &args[0] else { return list_required(ev) };
+    ev.check_lock(list.lock(), "insert")?;
+    let idx = ev.opt_number(args, 2, 0)?;
+    let len = list.borrow().len();
+    let pos = if idx == len as i64 { Some(len) } else { list_index(idx, len) };
+    let Some(pos) = pos else {
+        return ev.emsg(format!("E684: List index out of range: {}", idx));
+    };
+    list.borrow_mut().insert(pos, args[1].clone());
+    Ok(args[0].clone())
+}
+
+/// remove({list}, {idx} [, {end}]) and remove({dict}, {key}): the removed
+/// item, or a List of the items from {idx} to {end}.
+pub(crate) fn f_remove(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    match &args[0] {
+        Value::List(list) => {
+            ev.check_lock(list.lock(), "remove")?;
+            let len = list.borrow().len();
+            let idx = ev.tv_number(&args[1])?;
+            let Some(first) = list_index(idx, len) else {
+                return ev.emsg(format!("E684: List index out of range: {}", idx));
+            };
+            let Some(end) = args.get(2) else {
+                return Ok(list.borrow_mut().remove(first));
+            };
+            let end = ev.tv_number(end)?;
+            let last = match list_index(end, len) {
+                Some(last) if last >= first => last,
+                Some(_) => return ev.emsg("E16: Invalid range".to_string()),
+                None => return ev.emsg(format!("E684: List index out of range: {}", end)),
+            };
+            let removed: Vec<Value> = list.borrow_mut().drain(first..=last).collect();
+            Ok(Value::new_list(removed))
+        }
+        Value::Dict(dict) => {
+            if args.len() > 2 {
+                return ev.emsg("E118: Too many arguments for function: remove".to_string());
+            }
+            ev.check_lock(dict.lock(), "remove")?;
+            let key = ev.tv_string(&args[1])?;
+            let removed = dict.borrow_mut().remove(&key);
+            match removed {
+                Some(val) => Ok(val),
+                None => ev.emsg(format!("E716: Key not present in Dictionary: \"{}\"", key)),
+            }
+        }
+        _ => ev.emsg("E896: Argument of remove() must be a List, Dictionary or Blob".to_string()),
+    }
+}
+
+pub(crate) fn f_copy(_ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(args[0].copy())
+}
+
+pub(crate) fn f_deepcopy(_ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(args[0].deep_copy())
+}
+
+/// empty({expr}): true for a zero Number, an empty String, List, etc.
+pub(crate) fn f_empty(_ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Number(is_empty(&args[0]) as i64))
+}
+
+/// Whether a value is empty, also used for the falsy operator "??".
+pub(crate) fn is_empty(val: &Value) -> bool {
+    match val {
+        Value::Number(n) => *n == 0,
+        Value::Float(f) => *f == 0.0,
+        Value::Str(s) => s.is_empty(),
+        Value::Bool(b) => !b,
+        Value::Special(_) => true,
+        Value::List(l) => l.borrow().is_empty(),
+        Value::Dict(d) => d.borrow().is_empty(),
+        Value::Blob(b) => b.borrow().is_empty(),
+        Value::Tuple(t) => t.is_empty(),
+        Value::Func(pt) => pt.name.is_empty(),
+        Value::Job(job) => job.status.get() != rust_core::JobStatus::Run,
+        Value::Channel(ch) => !ch.open.get(),
+        Value::Object(_) | Value::Class(_) => false,
+    }
+}
+
+/// count({comp}, {expr} [, {ic} [, {start}]]): how often {expr} is in a
+/// List or Dictionary, or how often a String appears in a String.
+pub(crate) fn f_count(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let ic = ev.opt_number(args, 2, 0)? != 0;
+    let n = match &args[0] {
+        Value::Str(s) => {
+            let needle = ev.tv_string(&args[1])?;
+            if needle.is_empty() {
+                0
+            } else if ic {
+                s.to_lowercase().matches(&needle.to_lowercase()).count()
+            } else {
+                s.matches(&needle).count()
+            }
+        }
+        Value::List(list) => {
+            let items = list.borrow();
+            let start = match args.get(3) {
+                Some(start) => {
+                    let idx = ev.tv_number(start)?;
+                    match list_index(idx, items.len()) {
+                        Some(start) => start,
+                        None => return ev.emsg(format!("E684: List index out of range: {}", idx)),
+                    }
+                }
+                None => 0,
+            };
+            items[start..].iter().filter(|item| item.equal(&args[1], ic)).count()
+        }
+        Value::Dict(dict) => {
+            if args.len() > 3 {
+                return ev.emsg("E474: Invalid argument".to_string());
+            }
+            dict.borrow().values().filter(|item| item.equal(&args[1], ic)).count()
+        }
+        _ => return ev.emsg("E706: Argument of count() must be a List, Dictionary or String".to_string()),
+    };
+    Ok(Value::Number(n as i64))
+}
+
+/// index({object}, {expr} [, {start} [, {ic}]]): index of the first item
+/// equal to {expr}, -1 when there is none.
+pub(crate) fn f_index(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let start = ev.opt_number(args, 2, 0)?;
+    let ic = ev.opt_number(args, 3, 0)? != 0;
+    match &args[0] {
+        Value::List(list) => {
+            let items = list.borrow();
+            let start = if start < 0 { (start + items.len() as i64).max(0) } else { start } as usize;
+            let found = items.iter().enumerate().skip(start).find(|(_, item)| item.equal(&args[1], ic));
+            Ok(Value::Number(found.map_or(-1, |(idx, _)| idx as i64)))
+        }
+        Value::Blob(blob) => {
+            let byte = ev.tv_number(&args[1])?;
+            let bytes = blob.borrow();
+            let start = if start < 0 { (start + bytes.len() as i64).max(0) } else { start } as usize;
+            let found = bytes.iter().enumerate().skip(start).find(|(_, &b)| b as i64 == byte);
+            Ok(Value::Number(found.map_or(-1, |(idx, _)| idx as i64)))
+        }
+        _ => ev.emsg("E897: List or Blob required".to_string()),
+    }
+}
+
+pub(crate) fn f_has_key(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let Value::Dict(dict) = &args[0] else { return dict_required(ev) };
+    let key = ev.tv_string(&args[1])?;
+    Ok(Value::Number(dict.borrow().contains_key(&key) as i64))
+}
+
+/// get({list}, {idx} [, {default}]) and get({dict}, {key} [, {default}])
+pub(crate) fn f_get(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let default = args.get(2).cloned().unwrap_or(Value::Number(0));
+    let found = match &args[0] {
+        Value::List(list) => {
+            let idx = ev.tv_number(&args[1])?;
+            let list = list.borrow();
+            list_index(idx, list.len()).map(|i| list[i].clone())
+        }
+        Value::Blob(blob) => {
+            let idx = ev.tv_number(&args[1])?;
+            let bytes = blob.borrow();
+            list_index(idx, bytes.len()).map(|i| Value::Number(bytes[i] as i64))
+        }
+        Value::Dict(dict) => {
+            let key = ev.tv_string(&args[1])?;
+            dict.borrow().get(&key).cloned()
+        }
+        _ => return ev.emsg("E896: Argument of get() must be a List, Dictionary or Blob".to_string()),
+    };
+    Ok(found.unwrap_or(default))
+}
+
+pub(crate) fn f_len(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let len = match &args[0] {
+        Value::List(list) => list.borrow().len(),
+        Value::Dict(dict) => dict.borrow().len(),
+        Value::Blob(blob) => blob.borrow().len(),
+        Value::Tuple(tuple) => tuple.len(),
+        Value::Str(s) => s.len(),
+        Value::Number(n) => n.to_string().len(),
+        _ => return ev.emsg("E701: Invalid type for len()".to_string()),
+    };
+    Ok(Value::Number(len as i64))
+}
+
+/// keys(), values() and items() of a Dictionary.
+fn dict_list(ev: &mut Evaluator, dict: &Value, item: fn(&String, &Value) -> Value) -> Result<Value, ()> {
+    let Value::Dict(dict) = dict else { return dict_required(ev) };
+    let items = dict.borrow().iter().map(|(k, v)| item(k, v)).collect();
+    Ok(Value::new_list(items))
+}
+
+pub(crate) fn f_keys(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    dict_list(ev, &args[0], |k, _| Value::Str(k.clone()))
+}
+
+pub(crate) fn f_values(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    dict_list(ev, &args[0], |_, v| v.clone())
+}
+
+/// items({dict}): a List of [key, value] pairs.  For a List the pairs are
+/// [index, item].
+pub(crate) fn f_items(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    if let Value::List(list) = &args[0] {
+        let items = list
+            .borrow()
+            .iter()
+            .enumerate()
+            .map(|(i, v)| Value::new_list(vec![Value::Number(i as i64), v.clone()]))
+            .collect();
+        return Ok(Value::new_list(items));
+    }
+    dict_list(ev, &args[0], |k, v| Value::new_list(vec![Value::Str(k.clone()), v.clone()]))
+}
+
+/// map({expr1}, {expr2}): replace each item with the result of {expr2}.
+/// The List or Dictionary is changed in place.
+pub(crate) fn f_map(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let [container, func] = args else { return Err(()) };
+    match container {
+        Value::List(list) => {
+            ev.check_lock(list.lock(), "map")?;
+            ev.check_lock(list.items_lock(), "map")?;
+            let len = list.borrow().len();
+            for idx in 0..len {
+                // Do not keep the list borrowed, {expr2} may look at it.
+                let Some(item) = list.borrow().get(idx).cloned() else { break };
+                let new = ev.eval_item(func, Value::Number(idx as i64), item)?;
+                if let Some(slot) = list.borrow_mut().get_mut(idx) {
+                    *slot = new;
+                }
+            }
+        }
+        Value::Dict(dict) => {
+            ev.check_lock(dict.lock(), "map")?;
+            ev.check_lock(dict.items_lock(), "map")?;
+            let keys: Vec<String> = dict.borrow().keys().cloned().collect();
+            for key in keys {
+                let Some(item) = dict.borrow().get(&key).cloned() else { continue };
+                let new = ev.eval_item(func, Value::Str(key.clone()), item)?;
+                dict.borrow_mut().insert(key, new);
+            }
+        }
+        _ => return list_or_dict_required(ev, "map"),
+    }
+    Ok(container.clone())
+}
+
+/// filter({expr1}, {expr2}): remove the items for which {expr2} is false.
+pub(crate) fn f_filter(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let [container, func] = args else { return Err(()) };
+    match container {
+        Value::List(list) => {
+            ev.check_lock(list.lock(), "filter")?;
+            let items = list.borrow().clone();
+            let mut kept = Vec::new();
+            for (idx, item) in items.into_iter().enumerate() {
+                let keep = ev.eval_item(func, Value::Number(idx as i64), item.clone())?;
+                if ev.is_true(&keep)? {
+                    kept.push(item);
+                }
+            }
+            *list.borrow_mut() = kept;
+        }
+        Value::Dict(dict) => {
+            ev.check_lock(dict.lock(), "filter")?;
+            let items = dict.borrow().clone();
+            for (key, item) in items {
+                let keep = ev.eval_item(func, Value::Str(key.clone()), item)?;
+                if !ev.is_true(&keep)? {
+                    dict.borrow_mut().remove(&key);
+                }
+            }
+        }
+        _ => return list_or_dict_required(ev, "filter"),
+    }
+    Ok(container.clone())
+}
+
+/// How sort() and uniq() compare items.
+enum Compare {
+    /// By the string representation, optionally ignoring case.
+    Str(bool),
+    /// Numbers; other items count as zero.
+    Number,
+    /// Like Number, but Strings are converted.
+    NumberStr,
+    Float,
+    /// A function returning a negative, zero or positive Number.
+    Func(Value, Option<DictRef>),
+}
+
+impl Compare {
+    /// The {how} and {dict} arguments of sort() and uniq().
+    fn new(ev: &mut Evaluator, args: &[Value]) -> Result<Compare, ()> {
+        let dict = match args.get(2) {
+            Some(Value::Dict(dict)) => Some(dict.clone()),
+            Some(_) => return dict_required(ev),
+            None => None,
+        };
+        Ok(match args.get(1) {
+            None => Compare::Str(false),
+            Some(func @ Value::Func(_)) => Compare::Func(func.clone(), dict),
+            Some(Value::Str(how)) => match how.as_str() {
+                "" | "l" => Compare::Str(false),
+                "i" | "1" => Compare::Str(true),
+                "n" => Compare::Number,
+                "N" => Compare::NumberStr,
+                "f" => Compare::Float,
+                name => Compare::Func(Value::Str(name.to_string()), dict),
+            },
+            Some(how) => match ev.tv_number(how)? {
+                0 => Compare::Str(false),
+                1 => Compare::Str(true),
+                _ => return ev.emsg("E474: Invalid argument".to_string()),
+            },
+        })
+    }
+
+    fn cmp(&self, ev: &mut Evaluator, a: &Value, b: &Value) -> Result<Ordering, ()> {
+        let string = |v: &Value, ic: bool| {
+            let s = v.to_string();
+            if ic {
+                s.to_lowercase()
+            } else {
+                s
+            }
+        };
+        let number = |v: &Value| match v {
+            Value::Number(n) => *n as f64,
+            Value::Float(f) => *f,
+            _ => 0.0,
+        };
+        Ok(match self {
+            Compare::Str(ic) => string(a, *ic).cmp(&string(b, *ic)),
+            Compare::Number | Compare::Float => number(a).partial_cmp(&number(b)).unwrap_or(Ordering::Equal),
+            Compare::NumberStr => ev.tv_number(a)?.cmp(&ev.tv_number(b)?),
+            Compare::Func(func, dict) => {
+                let args = [a.clone(), b.clone()];
+                let result = match (func, dict) {
+                    (Value::Func(pt), Some(dict)) => {
+                        let mut pt = (**pt).clone();
+                        pt.dict = Some(dict.clone());
+                        ev.call_partial(&pt, &args)?
+                    }
+                    (Value::Str(name), Some(dict)) => ev.call_by_name(name, &args, Some(dict.clone()))?,
+                    _ => ev.call_value(func, &args)?,
+                };
+                ev.tv_number(&result)?.cmp(&0)
+            }
+        })
+    }
+}
+
+/// sort({list} [, {how} [, {dict}]]): sort in place.  {how} is "i" to
+/// ignore case, "n", "N" or "f" for numeric sorting, or a function.  The
+/// sort is stable.
+pub(crate) fn f_sort(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let Value::List(list) = &args[0] else { return list_required(ev) };
+    ev.check_lock(list.lock(), "sort")?;
+    let how = Compare::new(ev, args)?;
+    let mut items = list.borrow().clone();
+    let mut failed = false;
+    items.sort_by(|a, b| {
+        if failed {
+            return Ordering::Equal;
+        }
+        how.cmp(ev, a, b).unwrap_or_else(|()| {
+            failed = true;
+            Ordering::Equal
+        })
+    });
+    if failed {
+        return ev.emsg("E702: Sort compare function failed".to_string());
+    }
+    *list.borrow_mut() = items;
+    Ok(args[0].clone())
+}
+
+/// uniq({list} [, {how} [, {dict}]]): remove repeated adjacent items.
+pub(crate) fn f_uniq(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let Value::List(list) = &args[0] else { return list_required(ev) };
+    ev.check_lock(list.lock(), "uniq")?;
+    let how = Compare::new(ev, args)?;
+    let items = list.borrow().clone();
+    let mut kept: Vec<Value> = Vec::with_capacity(items.len());
+    for item in items {
+        let repeated = match kept.last() {
+            Some(last) => how.cmp(ev, last, &item).or_else(|()| {
+                ev.emsg::<Ordering>("E882: Uniq compare function failed".to_string())
+            })? == Ordering::Equal,
+            None => false,
+        };
+        if !repeated {
+            kept.push(item);
+        }
+    }
+    *list.borrow_mut() = kept;
+    Ok(args[0].clone())
+}
+
+/// reverse({object}): reverse a List or Blob in place, or return a String
+/// with the characters reversed.
+pub(crate) fn f_reverse(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    match &args[0] {
+        Value::List(list) => {
+            ev.check_lock(list.lock(), "reverse")?;
+            list.borrow_mut().reverse();
+        }
+        Value::Blob(blob) => {
+            ev.check_lock(blob.lock(), "reverse")?;
+            blob.borrow_mut().reverse();
+        }
+        Value::Str(s) => return Ok(Value::Str(s.chars().rev().collect())),
+        _ => {}
+    }
+    Ok(args[0].clone())
+}
+
+/// reduce({object}, {func} [, {initial}]): call {func} with the accumulated
+/// value and each item of a List, Blob or String.
+pub(crate) fn f_reduce(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let items: Vec<Value> = match &args[0] {
+        Value::List(list) => list.borrow().clone(),
+        Value::Tuple(tuple) => tuple.to_vec(),
+        Value::Blob(blob) => blob.borrow().iter().map(|&b| Value::Number(b as i64)).collect(),
+        Value::Str(s) => s.chars().map(|c| Value::Str(c.to_string())).collect(),
+        _ => return ev.emsg("E1098: String, List or Blob required".to_string()),
+    };
+    let mut items = items.into_iter();
+    let mut acc = match args.get(2) {
+        Some(initial) => initial.clone(),
+        None => match items.next() {
+            Some(first) => first,
+            None => {
+                let kind = match &args[0] {
+                    Value::Blob(_) => "Blob",
+                    Value::Str(_) => "String",
+                    _ => "List",
+                };
+                return ev.emsg(format!("E998: Reduce of an empty {} with no initial value", kind));
+            }
+        },
+    };
+    for item in items {
+        acc = ev.call_value(&args[1], &[acc, item])?;
+    }
+    Ok(acc)
+}
+
+fn flatten_into(items: Vec<Value>, depth: i64, out: &mut Vec<Value>) {
+    for item in items {
+        match item {
+            Value::List(list) if depth > 0 => flatten_into(list.borrow().clone(), depth - 1, out),
+            item => out.push(item),
+        }
+    }
+}
+
+/// flatten({list} [, {maxdepth}]): replace nested Lists by their items, in
+/// place.
+pub(crate) fn f_flatten(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let Value::List(list) = &args[0] else { return list_required(ev) };
+    let depth = ev.opt_number(args, 1, i64::MAX)?;
+    if depth < 0 {
+        return ev.emsg("E900: maxdepth must be non-negative number".to_string());
+    }
+    ev.check_lock(list.lock(), "flatten")?;
+    let items = list.borrow().clone();
+    let mut flat = Vec::with_capacity(items.len());
+    flatten_into(items, depth, &mut flat);
+    *list.borrow_mut() = flat;
+    Ok(args[0].clone())
+}
+
+/// extend({expr1}, {expr2} [, {expr3}]): add the items of {expr2} to
+/// {expr1}.  For Lists {expr3} is the index to insert at, for Dictionaries
+/// what to do with existing keys: "keep", "force" or "error".
+pub(crate) fn f_extend(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    match (&args[0], &args[1]) {
+        (Value::List(l1), Value::List(l2)) => {
+            ev.check_lock(l1.lock(), "extend")?;
+            let len = l1.borrow().len();
+            let pos = match args.get(2) {
+                Some(idx) => {
+                    let idx = ev.tv_number(idx)?;
+                    let pos = if idx == len as i64 { Some(len) } else { list_index(idx, len) };
+                    match pos {
+                        Some(pos) => pos,
+                        None => return ev.emsg(format!("E684: List index out of range: {}", idx)),
+                    }
+                }
+                None => len,
+            };
+            // Copy first, {expr2} may be the same List.
+            let new = l2.borrow().clone();
+            l1.borrow_mut().splice(pos..pos, new);
+        }
+        (Value::Dict(d1), Value::Dict(d2)) => {
+            ev.check_lock(d1.lock(), "extend")?;
+            let action = match args.get(2) {
+                Some(action) => ev.tv_string(action)?,
+                None => "force".to_string(),
+            };
+            if !["keep", "force", "error"].contains(&action.as_str()) {
+                return ev.emsg(format!("E475: Invalid argument: {}", action));
+            }
+            let new: BTreeMap<String, Value> = d2.borrow().clone();
+            for (key, val) in new {
+                let exists = d1.borrow().contains_key(&key);
+                match action.as_str() {
+                    "error" if exists => return ev.emsg(format!("E737: Key already exists: {}", key)),
+                    "keep" if exists => {}
+                    _ => {
+                        d1.borrow_mut().insert(key, val);
+                    }
+                }
+            }
+        }
+        _ => return list_or_dict_required(ev, "extend"),
+    }
+    Ok(args[0].clone())
+}
+
+/// range({expr} [, {max} [, {stride}]]): a List of Numbers.
+pub(crate) fn f_range(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let first = ev.tv_number(&args[0])?;
+    let (start, end) = match args.get(1) {
+        Some(max) => (first, ev.tv_number(max)?),
+        None => (0, first - 1),
+    };
+    let stride = ev.opt_number(args, 2, 1)?;
+    if stride == 0 {
+        return ev.emsg("E726: Stride is zero".to_string());
+    }
+    if (stride > 0 && end + 1 < start) || (stride < 0 && end - 1 > start) {
+        return ev.emsg("E727: Start past end".to_string());
+    }
+    let mut items = Vec::new();
+    let mut n = start;
+    while (stride > 0 && n <= end) || (stride < 0 && n >= end) {
+        items.push(Value::Number(n));
+        n += stride;
+    }
+    Ok(Value::new_list(items))
+}
+
+/// Common part of max() and min(): `pick` tells whether the new item
+/// replaces the one found so far.
+fn extreme(ev: &mut Evaluator, args: &[Value], name: &str, pick: fn(i64, i64) -> bool) -> Result<Value, ()> {
+    let items: Vec<Value> = match &args[0] {
+        Value::List(list) => list.borrow().clone(),
+        Value::Dict(dict) => dict.borrow().values().cloned().collect(),
+        Value::Tuple(tuple) => tuple.to_vec(),
+        _ => return list_or_dict_required(ev, name),
+    };
+    let mut best: Option<i64> = None;
+    for item in &items {
+        let n = ev.tv_number(item)?;
+        if best.is_none_or(|best| pick(n, best)) {
+            best = Some(n);
+        }
+    }
+    Ok(Value::Number(best.unwrap_or(0)))
+}
+
+pub(crate) fn f_max(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    extreme(ev, args, "max", |n, best| n > best)
+}
+
+pub(crate) fn f_min(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    extreme(ev, args, "min", |n, best| n < best)
+}
@@ -0,0 +1,227 @@
+//! Key mappings and abbreviations in scripts: `:map`, `:abbreviate` and the
+//! other map commands, maparg() and mapset().
+//!
+//! The mappings and the abbreviations are each kept in a [`MapTable`].  The
+//! evaluator is the [`MapHost`] that rust_getchar's vgetc() uses to expand
+//! them: it evaluates `<expr>` mappings and abbreviations and executes
+//! `<Cmd>` mappings, in the script the mapping was defined in.
+//! "g:mapleader" and "g:maplocalleader" are used for `<Leader>` and
+//! `<LocalLeader>` when a mapping is defined.
+
+use std::collections::BTreeMap;
+
+use rust_map::{abbr_command, map_command, parse_keys, MapCmd, MapContext, MapHost, MapTable, Mapping, Mode};
+
+use crate::{Evaluator, Value};
+
+impl Evaluator {
+    pub fn maps(&self) -> &MapTable {
+        &self.maps
+    }
+
+    pub fn maps_mut(&mut self) -> &mut MapTable {
+        &mut self.maps
+    }
+
+    pub fn abbrs(&self) -> &MapTable {
+        &self.abbrs
+    }
+
+    /// "g:mapleader" and "g:maplocalleader", a backslash when not set.
+    fn leaders(&self) -> (String, String) {
+        let leader = |name| match self.get_var(name) {
+            Some(Value::Str(s)) => s,
+            _ => "\\".to_string(),
+        };
+        (leader("mapleader"), leader("maplocalleader"))
+    }
+
+    /// Keys for `text` in `<>` notation, with the leaders of the user.
+    fn map_keys(&self, text: &str) -> Vec<u32> {
+        let (leader, localleader) = self.leaders();
+        parse_keys(text, &leader, &localleader)
+    }
+
+    /// Execute map command `name`, e.g. `:nnoremap`.
+    pub(crate) fn ex_map(&mut self, name: &str, bang: bool, arg: &str) -> Result<(), ()> {
+        let Some((cmd, mode)) = map_command(name, bang) else {
+            return self.emsg(format!("E492: Not an editor command: {}", name));
+        };
+        self.do_map(false, cmd, mode, arg)
+    }
+
+    /// Execute abbreviation command `name`, e.g. `:iabbrev`.
+    pub(crate) fn ex_abbr(&mut self, name: &str, arg: &str) -> Result<(), ()> {
+        let Some((cmd, mode)) = abbr_command(name) else {
+            return self.emsg(format!("E492: Not an editor command: {}", name));
+        };
+        self.do_map(true, cmd, mode, arg)
+    }
+
+    fn do_map(&mut self, abbr: bool, cmd: MapCmd, mode: Mode, arg: &str) -> Result<(), ()> {
+        let (leader, localleader) = self.leaders();
+        let ctx = MapContext { curbuf: self.curbuf(), sid: self.sid, leader: &leader, localleader: &localleader };
+        let table = if abbr { &mut self.abbrs } else { &mut self.maps };
+        match table.do_map(cmd, mode, arg, &ctx) {
+            Ok(lines) => {
+                for line in lines {
+                    self.message(line);
+                }
+                Ok(())
+            }
+            Err(msg) => self.emsg(msg),
+        }
+    }
+}
+
+impl MapHost for Evaluator {
+    fn maps(&self) -> &MapTable {
+        &self.maps
+    }
+
+    fn abbrs(&self) -> &MapTable {
+        &self.abbrs
+    }
+
+    /// 'timeoutlen' when 'timeout' is set.
+    fn timeoutlen(&self) -> Option<u64> {
+        if !matches!(self.get_option("timeout"), Some(Value::Number(n)) if n != 0) {
+            return None;
+        }
+        match self.get_option("timeoutlen") {
+            Some(Value::Number(n)) => Some(n.max(0) as u64),
+            _ => None,
+        }
+    }
+
+    fn eval_map_expr(&mut self, map: &Mapping) -> String {
+        let saved_sid = std::mem::replace(&mut self.sid, map.sid);
+        let mut result = String::new();
+        let _ = self.toplevel(|ev| {
+            let val = ev.eval_cmd_expr(&map.rhs)?;
+            result = ev.tv_string(&val)?;
+            Ok(())
+        });
+        self.sid = saved_sid;
+        result
+    }
+
+    fn exec_map_cmd(&mut self, map: &Mapping, cmd: &str) {
+        let saved_sid = std::mem::replace(&mut self.sid, map.sid);
+        let _ = self.do_cmdline(cmd);
+        self.sid = saved_sid;
+    }
+}
+
+fn map_dict(map: &Mapping, abbr: bool) -> Value {
+    let mut dict = BTreeMap::new();
+    let lhs = map.lhs_string();
+    let raw: String = map.lhs.iter().filter_map(|&key| char::from_u32(key)).collect();
+    dict.insert("lhs".to_string(), Value::Str(lhs));
+    dict.insert("lhsraw".to_string(), Value::Str(raw));
+    dict.insert("rhs".to_string(), Value::Str(map.rhs.clone()));
+    dict.insert("noremap".to_string(), Value::Number(map.noremap as i64));
+    dict.insert("script".to_string(), Value::Number(map.script as i64));
+    dict.insert("expr".to_string(), Value::Number(map.expr as i64));
+    dict.insert("silent".to_string(), Value::Number(map.silent as i64));
+    dict.insert("nowait".to_string(), Value::Number(map.nowait as i64));
+    dict.insert("buffer".to_string(), Value::Number(map.buffer.is_some() as i64));
+    dict.insert("sid".to_string(), Value::Number(map.sid as i64));
+    dict.insert("lnum".to_string(), Value::Number(0));
+    dict.insert("scriptversion".to_string(), Value::Number(1));
+    dict.insert("mode".to_string(), Value::Str(map.mode.to_chars()));
+    dict.insert("mode_bits".to_string(), Value::Number(map.mode.bits() as i64));
+    dict.insert("abbr".to_string(), Value::Number(abbr as i64));
+    Value::new_dict(dict)
+}
+
+/// maparg({name} [, {mode} [, {abbr} [, {dict}]]]): the right-hand side of
+/// the mapping of {name} in {mode}, or a Dictionary describing it.
+pub(crate) fn f_maparg(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let name = ev.tv_string(&args[0])?;
+    let mode = match args.get(1) {
+        Some(mode) => ev.tv_string(mode)?,
+        None => String::new(),
+    };
+    let abbr = match args.get(2) {
+        Some(abbr) => ev.is_true(abbr)?,
+        None => false,
+    };
+    let want_dict = match args.get(3) {
+        Some(dict) => ev.is_true(dict)?,
+        None => false,
+    };
+    let lhs = ev.map_keys(&name);
+    let mode = Mode::from_chars(&mode).unwrap_or(Mode::NVO);
+    let table = if abbr { &ev.abbrs } else { &ev.maps };
+    Ok(match table.get(&lhs, mode, ev.curbuf()) {
+        Some(map) if want_dict => map_dict(map, abbr),
+        Some(map) if map.rhs_keys.is_empty() && !map.expr => Value::Str(String::new()),
+        Some(map) => Value::Str(map.rhs_string()),
+        None if want_dict => Value::new_dict(BTreeMap::new()),
+        None => Value::Str(String::new()),
+    })
+}
+
+/// mapset({mode}, {abbr}, {dict}) or mapset({dict}): restore a mapping
+/// from a Dictionary returned by maparg().
+pub(crate) fn f_mapset(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let (mode, abbr, dict) = match args {
+        [Value::Dict(dict)] => (None, None, dict.clone()),
+        [mode, abbr, Value::Dict(dict)] => (Some(ev.tv_string(mode)?), Some(ev.is_true(abbr)?), dict.clone()),
+        [_] | [_, _, _] => return ev.emsg("E1206: Dictionary required for argument 1".to_string()),
+        _ => return ev.emsg("E119: Not enough arguments for function: mapset".to_string()),
+    };
+    let get = |key: &str| dict.borrow().get(key).cloned();
+    let (Some(lhs), Some(rhs)) = (get("lhs"), get("rhs")) else {
+        return ev.emsg("E460: Entries missing in mapset() dict argument".to_string());
+    };
+    let flag = |ev: &mut Evaluator, key: &str| match get(key) {
+        Some(val) => ev.is_true(&val),
+        None => Ok(false),
+    };
+    let noremap = flag(ev, "noremap")?;
+    let script = flag(ev, "script")?;
+    let expr = flag(ev, "expr")?;
+    let silent = flag(ev, "silent")?;
+    let nowait = flag(ev, "nowait")?;
+    let buffer = flag(ev, "buffer")?;
+    let abbr = match abbr {
+        Some(abbr) => abbr,
+        None => flag(ev, "abbr")?,
+    };
+    let sid = match get("sid") {
+        Some(sid) => ev.tv_number(&sid)?.max(0) as usize,
+        None => ev.sid,
+    };
+    let mode = match mode {
+        Some(mode) => mode,
+        None => match get("mode") {
+            Some(mode) => ev.tv_string(&mode)?,
+            None => String::new(),
+        },
+    };
+    let Some(mode) = Mode::from_chars(&mode) else {
+        return ev.emsg(format!("E475: Invalid argument: {}", mode));
+    };
+    let lhs = ev.tv_string(&lhs)?;
+    let lhs = ev.map_keys(&lhs);
+    let rhs = ev.tv_string(&rhs)?;
+    let rhs_keys = if expr || rhs.eq_ignore_ascii_case("<Nop>") { Vec::new() } else { ev.map_keys(&rhs) };
+    let curbuf = ev.curbuf();
+    let table = if abbr { &mut ev.abbrs } else { &mut ev.maps };
+    table.add(Mapping {
+        lhs,
+        rhs,
+        rhs_keys,
+        mode,
+        noremap,
+        script,
+        expr,
+        silent,
+        nowait,
+        buffer: buffer.then_some(curbuf),
+        sid,
+    });
+    Ok(Value::Number(0))
+}
@@ -0,0 +1,174 @@
+//! Options as scripts see them: `&name` in an expression and `:let &name`.
+//!
+//! Only the options that scripts commonly read are known.  An option has
+//! one value, "&l:name" and "&g:name" refer to the same one.
+
+use crate::{Evaluator, Value};
+
+#[derive(Debug, Clone, Copy)]
+enum OptDefault {
+    Bool(bool),
+    Number(i64),
+    Str(&'static str),
+}
+
+struct OptionDef {
+    name: &'static str,
+    short: &'static str,
+    default: OptDefault,
+}
+
+const fn opt(name: &'static str, short: &'static str, default: OptDefault) -> OptionDef {
+    OptionDef { name, short, default }
+}
+
+use OptDefault::{Bool, Number, Str};
+
+static OPTIONS: &[OptionDef] = &[
+    opt("autoindent", "ai", Bool(false)),
+    opt("background", "bg", Str("light")),
+    opt("backup", "bk", Bool(false)),
+    opt("backupcopy", "bkc", Str("auto")),
+    opt("backupdir", "bdir", Str(".,~/tmp,~/")),
+    opt("backupext", "bex", Str("~")),
+    opt("binary", "bin", Bool(false)),
+    opt("bomb", "bomb", Bool(false)),
+    opt("bufhidden", "bh", Str("")),
+    opt("buflisted", "bl", Bool(true)),
+    opt("buftype", "bt", Str("")),
+    opt("clipboard", "cb", Str("")),
+    opt("compatible", "cp", Bool(false)),
+    opt("cpoptions", "cpo", Str("aABceFs")),
+    opt("cryptmethod", "cm", Str("xchacha20scrypt")),
+    opt("directory", "dir", Str(".,~/tmp,/var/tmp,/tmp")),
+    opt("encoding", "enc", Str("utf-8")),
+    opt("endofline", "eol", Bool(true)),
+    opt("expandtab", "et", Bool(false)),
+    opt("fileencoding", "fenc", Str("")),
+    opt("fileencodings", "fencs", Str("ucs-bom,utf-8,default,latin1")),
+    opt("fileformat", "ff", Str("unix")),
+    opt("fileformats", "ffs", Str("unix,dos")),
+    opt("filetype", "ft", Str("")),
+    opt("fixendofline", "fixeol", Bool(true)),
+    opt("hidden", "hid", Bool(false)),
+    opt("history", "hi", Number(50)),
+    opt("hlsearch", "hls", Bool(false)),
+    opt("ignorecase", "ic", Bool(false)),
+    opt("incsearch", "is", Bool(false)),
+    opt("iskeyword", "isk", Str("@,48-57,_,192-255")),
+    opt("key", "key", Str("")),
+    opt("laststatus", "ls", Number(1)),
+    opt("list", "list", Bool(false)),
+    opt("magic", "magic", Bool(true)),
+    opt("modifiable", "ma", Bool(true)),
+    opt("modified", "mod", Bool(false)),
+    opt("number", "nu", Bool(false)),
+    opt("readonly", "ro", Bool(false)),
+    opt("relativenumber", "rnu", Bool(false)),
+    opt("runtimepath", "rtp", Str("")),
+    opt("scrolloff", "so", Number(0)),
+    opt("shell", "sh", Str("sh")),
+    opt("shellcmdflag", "shcf", Str("-c")),
+    opt("shiftwidth", "sw", Number(8)),
+    opt("smartcase", "scs", Bool(false)),
+    opt("softtabstop", "sts", Number(0)),
+    opt("swapfile", "swf", Bool(true)),
+    opt("tabstop", "ts", Number(8)),
+    opt("textwidth", "tw", Number(0)),
+    opt("timeout", "to", Bool(true)),
+    opt("timeoutlen", "tm", Number(1000)),
+    opt("undodir", "udir", Str(".")),
+    opt("undofile", "udf", Bool(false)),
+    opt("undolevels", "ul", Number(1000)),
+    opt("updatecount", "uc", Number(200)),
+    opt("updatetime", "ut", Number(4000)),
+    opt("virtualedit", "ve", Str("")),
+    opt("wrap", "wrap", Bool(true)),
+    opt("wrapscan", "ws", Bool(true)),
+    opt("writebackup", "wb", Bool(true)),
+];
+
+fn find_option(name: &str) -> Option<&'static OptionDef> {
+    OPTIONS.iter().find(|def| def.name == name || def.short == name)
+}
+
+/// The value every option starts with, by full name.
+pub(crate) fn default_options() -> Vec<(&'static str, Value)> {
+    OPTIONS
+        .iter()
+        .map(|def| {
+            let val = match def.default {
+                Bool(b) => Value::Number(b as i64),
+                Number(n) => Value::Number(n),
+                Str(s) => Value::Str(s.to_string()),
+            };
+            (def.name, val)
+        })
+        .collect()
+}
+
+/// The full names of the options.
+pub(crate) fn option_names() -> impl Iterator<Item = &'static str> {
+    OPTIONS.iter().map(|def| def.name)
+}
+
+/// Whether `name` is a String option.
+pub(crate) fn is_string_option(name: &str) -> bool {
+    find_option(name).is_some_and(|def| matches!(def.default, Str(_)))
+}
+
+impl Evaluator {
+    /// The value of option `name`, which may be the short name.  A boolean
+    /// option is a Number, zero or one.  None for an unknown option.  Like
+    /// Vim 'key' is "*****" when set, the key itself cannot be obtained.
+    pub fn get_option(&self, name: &str) -> Option<Value> {
+        let def = find_option(name)?;
+        if def.name == "key" && self.crypt_key().is_some() {
+            return Some(Value::Str("*****".to_string()));
+        }
+        if let Some(val) = self.buf_option(def.name) {
+            return Some(val);
+        }
+        self.options.get(def.name).cloned()
+    }
+
+    /// Set option `name` like `:let &name = val`.  A Number or String value
+    /// is converted to the type of the option.
+    pub fn set_option(&mut self, name: &str, val: Value) -> Result<(), String> {
+        let Some(def) = find_option(name) else {
+            return Err(format!("E355: Unknown option: {}", name));
+        };
+        let val = match (def.default, val) {
+            (Bool(_), Value::Number(n)) => Value::Number((n != 0) as i64),
+            (Bool(_), Value::Bool(b)) => Value::Number(b as i64),
+            (Number(_), val @ Value::Number(_)) => val,
+            (Number(_), Value::Bool(b)) => Value::Number(b as i64),
+            (Bool(_) | Number(_), Value::Str(s)) => match s.trim().parse::<i64>() {
+                Ok(n) if matches!(def.default, Bool(_)) => Value::Number((n != 0) as i64),
+                Ok(n) => Value::Number(n),
+                Err(_) => return Err(format!("E521: Number required after =: {}={}", def.name, s)),
+            },
+            (Str(_), val @ Value::Str(_)) => val,
+            (Str(_), val @ (Value::Number(_) | Value::Bool(_))) => Value::Str(val.to_string()),
+            _ => return Err(format!("E474: Invalid argument: {}", def.name)),
+        };
+        if let Some(result) = self.set_buf_option(def.name, &val) {
+            return result;
+        }
+        self.options.insert(def.name, val);
+        Ok(())
+    }
+
+    /// The passphrase in 'key', None when it is empty.
+    pub(crate) fn crypt_key(&self) -> Option<&str> {
+        match self.options.get("key") {
+            Some(Value::Str(key)) if !key.is_empty() => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Whether 'ignorecase' is set.
+    pub(crate) fn ignorecase(&self) -> bool {
+        matches!(self.options.get("ignorecase"), Some(Value::Number(n)) if *n != 0)
+    }
+}
@@ -0,0 +1,642 @@
+//! Builtin functions on Strings: matching with Vim patterns, substitute(),
+//! split() and printf(), and the simpler ones like strpart() and trim().
+//!
+//! Offsets are in bytes unless the function is about characters, as in Vim.
+
+use rust_regexp::{VimMatch, VimRegex};
+
+use crate::{Evaluator, Value};
+
+impl Evaluator {
+    /// The text a `\=` replacement inserts: the items of a List are joined
+    /// with line breaks, like `:echo` shows them.
+    fn sub_string(&mut self, val: &Value) -> Result<String, ()> {
+        match val {
+            Value::List(list) => Ok(list.borrow().iter().map(Value::to_string).collect::<Vec<_>>().join("\n")),
+            _ => self.tv_string(val),
+        }
+    }
+
+    /// Compile a pattern argument.  With `ic` case is ignored, unless the
+    /// pattern has "\C".
+    pub(crate) fn regex(&mut self, pat: &str, ic: bool) -> Result<VimRegex, ()> {
+        match VimRegex::new(pat, ic) {
+            Ok(re) => Ok(re),
+            Err(msg) => self.emsg(msg),
+        }
+    }
+
+    /// The optional Number argument `idx`, `default` when it is absent.
+    pub(crate) fn opt_number(&mut self, args: &[Value], idx: usize, default: i64) -> Result<i64, ()> {
+        match args.get(idx) {
+            Some(val) => self.tv_number(val),
+            None => Ok(default),
+        }
+    }
+}
+
+/// The byte length of the character at `idx`, one at the end of the text so
+/// that an empty match always advances.
+fn char_len_at(text: &str, idx: usize) -> usize {
+    text[idx..].chars().next().map_or(1, char::len_utf8)
+}
+
+/// concat({expr}, ...): the arguments converted to Strings and joined.
+pub(crate) fn f_concat(_ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Str(args.iter().map(Value::to_string).collect()))
+}
+
+pub(crate) fn f_string(_ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Str(args[0].to_quoted()))
+}
+
+pub(crate) fn f_strlen(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let s = ev.tv_string(&args[0])?;
+    Ok(Value::Number(s.len() as i64))
+}
+
+pub(crate) fn f_strchars(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let s = ev.tv_string(&args[0])?;
+    Ok(Value::Number(s.chars().count() as i64))
+}
+
+/// Clip the part of a sequence of `total` units starting at `start` with
+/// length `len` to what exists.  Returns the start and end.
+fn clip_part(total: i64, start: i64, len: Option<i64>) -> (usize, usize) {
+    let mut len = len.unwrap_or(total);
+    let mut start = start;
+    if start < 0 {
+        // A negative start shortens the part, like Vim.
+        len += start;
+        start = 0;
+    }
+    let start = start.min(total);
+    let end = (start + len.max(0)).min(total);
+    (start as usize, end as usize)
+}
+
+/// strpart({src}, {start} [, {len} [, {chars}]]): byte offsets, unless
+/// {chars} is true.
+pub(crate) fn f_strpart(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let s = ev.tv_string(&args[0])?;
+    let start = ev.tv_number(&args[1])?;
+    let len = args.get(2).map(|len| ev.tv_number(len)).transpose()?;
+    if ev.opt_number(args, 3, 0)? != 0 {
+        let chars: Vec<char> = s.chars().collect();
+        let (start, end) = clip_part(chars.len() as i64, start, len);
+        return Ok(Value::Str(chars[start..end].iter().collect()));
+    }
+    let (start, end) = clip_part(s.len() as i64, start, len);
+    Ok(Value::Str(String::from_utf8_lossy(&s.as_bytes()[start..end]).into_owned()))
+}
+
+/// strcharpart({src}, {start} [, {len} [, {skipcc}]]): character offsets.
+pub(crate) fn f_strcharpart(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let s = ev.tv_string(&args[0])?;
+    let start = ev.tv_number(&args[1])?;
+    let len = args.get(2).map(|len| ev.tv_number(len)).transpose()?;
+    let chars: Vec<char> = s.chars().collect();
+    let (start, end) = clip_part(chars.len() as i64, start, len);
+    Ok(Value::Str(chars[start..end].iter().collect()))
+}
+
+/// stridx({haystack}, {needle} [, {start}]): byte index or -1.
+pub(crate) fn f_stridx(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let haystack = ev.tv_string(&args[0])?;
+    let needle = ev.tv_string(&args[1])?;
+    let start = ev.opt_number(args, 2, 0)?.max(0) as usize;
+    if start > haystack.len() {
+        return Ok(Value::Number(-1));
+    }
+    if needle.is_empty() {
+        return Ok(Value::Number(start as i64));
+    }
+    let found = haystack.as_bytes()[start..].windows(needle.len()).position(|w| w == needle.as_bytes());
+    Ok(Value::Number(found.map_or(-1, |i| (start + i) as i64)))
+}
+
+pub(crate) fn f_tolower(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Str(ev.tv_string(&args[0])?.to_lowercase()))
+}
+
+pub(crate) fn f_toupper(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    Ok(Value::Str(ev.tv_string(&args[0])?.to_uppercase()))
+}
+
+/// trim({text} [, {mask} [, {dir}]]): remove the characters in {mask},
+/// white space by default, at the start ({dir} 1), end (2) or both (0).
+pub(crate) fn f_trim(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let text = ev.tv_string(&args[0])?;
+    let mask = match args.get(1) {
+        Some(mask) => Some(ev.tv_string(mask)?),
+        None => None,
+    };
+    let dir = ev.opt_number(args, 2, 0)?;
+    if !(0..=2).contains(&dir) {
+        return ev.emsg(format!("E475: Invalid argument: {}", dir));
+    }
+    let trimmed = |c: char| match &mask {
+        Some(mask) => mask.contains(c),
+        None => c <= ' ' || c == '\u{a0}' || c == '\u{3000}',
+    };
+    let mut s = text.as_str();
+    if dir != 2 {
+        s = s.trim_start_matches(trimmed);
+    }
+    if dir != 1 {
+        s = s.trim_end_matches(trimmed);
+    }
+    Ok(Value::Str(s.to_string()))
+}
+
+/// repeat({expr}, {count}): a String or List repeated.
+pub(crate) fn f_repeat(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let count = ev.tv_number(&args[1])?.max(0) as usize;
+    match &args[0] {
+        Value::List(list) => {
+            let items = list.borrow();
+            Ok(Value::new_list((0..count).flat_map(|_| items.iter().cloned()).collect()))
+        }
+        val => Ok(Value::Str(ev.tv_string(val)?.repeat(count))),
+    }
+}
+
+/// escape({string}, {chars}): a backslash before each of {chars}.
+pub(crate) fn f_escape(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let s = ev.tv_string(&args[0])?;
+    let chars = ev.tv_string(&args[1])?;
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if chars.contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    Ok(Value::Str(out))
+}
+
+/// split({string} [, {pattern} [, {keepempty}]]): the pieces between
+/// matches of {pattern}, white space by default.  Empty pieces at the start
+/// and end are dropped unless {keepempty} is true.
+pub(crate) fn f_split(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let text = ev.tv_string(&args[0])?;
+    let pat = match args.get(1) {
+        Some(pat) => ev.tv_string(pat)?,
+        None => String::new(),
+    };
+    let keepempty = ev.opt_number(args, 2, 0)? != 0;
+    // 'ignorecase' is not used.
+    let re = ev.regex(if pat.is_empty() { "[\\x01- ]\\+" } else { &pat }, false)?;
+    let mut items = Vec::new();
+    let mut pos = 0;
+    // Offset from `pos` where matching starts, to get past an empty match.
+    let mut col = 0;
+    while pos < text.len() || keepempty {
+        let found = if pos < text.len() { re.find_at(&text, pos + col) } else { None };
+        let end = found.as_ref().map_or(text.len(), |m| m.start);
+        let nonempty_match = found.as_ref().is_some_and(|m| end < m.end);
+        if keepempty || end > pos || (!items.is_empty() && pos < text.len() && nonempty_match) {
+            items.push(Value::Str(text[pos..end].to_string()));
+        }
+        let Some(m) = found else { break };
+        col = if m.end > pos { 0 } else { char_len_at(&text, m.end) };
+        pos = m.end;
+    }
+    Ok(Value::new_list(items))
+}
+
+/// join({list} [, {sep}]): the items as Strings with {sep}, a space by
+/// default, in between.
+pub(crate) fn f_join(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let Value::List(list) = &args[0] else {
+        return ev.emsg("E714: List required".to_string());
+    };
+    let sep = match args.get(1) {
+        Some(sep) => ev.tv_string(sep)?,
+        None => " ".to_string(),
+    };
+    let items: Vec<String> = list.borrow().iter().map(Value::to_string).collect();
+    Ok(Value::Str(items.join(&sep)))
+}
+
+/// Case change for the replacement text of substitute(), set by "\u",
+/// "\U" and friends.
+#[derive(Clone, Copy, PartialEq)]
+enum Case {
+    Keep,
+    Upper,
+    Lower,
+}
+
+/// Expand the special items in the replacement `sub` for match `m`, like
+/// vim_regsub(): "&" and "\0" to "\9" insert submatches, "\u" and "\l"
+/// change the case of one character, "\U" and "\L" of everything up to
+/// "\E" or "\e".
+fn regsub(sub: &str, text: &str, m: &VimMatch) -> String {
+    let mut out = String::new();
+    let mut one = Case::Keep;
+    let mut all = Case::Keep;
+    let push = |out: &mut String, s: &str, one: &mut Case, all: Case| {
+        for c in s.chars() {
+            let case = if *one != Case::Keep { std::mem::replace(one, Case::Keep) } else { all };
+            match case {
+                Case::Upper => out.extend(c.to_uppercase()),
+                Case::Lower => out.extend(c.to_lowercase()),
+                Case::Keep => out.push(c),
+            }
+        }
+    };
+    let mut chars = sub.chars();
+    while let Some(c) = chars.next() {
+        if c == '&' {
+            push(&mut out, m.group(text, 0), &mut one, all);
+            continue;
+        }
+        if c != '\\' {
+            push(&mut out, c.encode_utf8(&mut [0; 4]), &mut one, all);
+            continue;
+        }
+        match chars.next() {
+            Some(d @ '0'..='9') => push(&mut out, m.group(text, d as usize - '0' as usize), &mut one, all),
+            Some('u') => one = Case::Upper,
+            Some('l') => one = Case::Lower,
+            Some('U') => all = Case::Upper,
+            Some('L') => all = Case::Lower,
+            Some('e' | 'E') => {
+                one = Case::Keep;
+                all = Case::Keep;
+            }
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some(c) => push(&mut out, c.encode_utf8(&mut [0; 4]), &mut one, all),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// substitute({string}, {pat}, {sub}, {flags}): replace the first match of
+/// {pat}, or all matches with "g" in {flags}.  {sub} may be a Funcref, it
+/// is called with the List of the match and its submatches.  A {sub} that
+/// starts with "\=" is an expression, submatch() gives the submatches.
+pub(crate) fn f_substitute(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let text = ev.tv_string(&args[0])?;
+    let pat = ev.tv_string(&args[1])?;
+    let flags = ev.tv_string(&args[3])?;
+    let sub = match &args[2] {
+        Value::Func(_) => None,
+        val => Some(ev.tv_string(val)?),
+    };
+    let re = ev.regex(&pat, ev.ignorecase())?;
+    let global = flags.contains('g');
+    let mut out = String::new();
+    let mut pos = 0;
+    let mut search = 0;
+    while let Some(m) = re.find_at(&text, search) {
+        out.push_str(&text[pos..m.start]);
+        match &sub {
+            Some(sub) if sub.starts_with("\\=") => {
+                let groups = (0..10).map(|nr| m.group(&text, nr).to_string()).collect();
+                let saved = ev.submatches.replace(groups);
+                let result = ev.eval_cmd_expr(&sub[2..]);
+                ev.submatches = saved;
+                out.push_str(&ev.sub_string(&result?)?);
+            }
+            Some(sub) => out.push_str(&regsub(sub, &text, &m)),
+            None => {
+                let groups = (0..10).map(|nr| Value::Str(m.group(&text, nr).to_string())).collect();
+                let result = ev.call_value(&args[2], &[Value::new_list(groups)])?;
+                out.push_str(&ev.tv_string(&result)?);
+            }
+        }
+        pos = m.end;
+        search = m.end;
+        if m.end == m.start && m.end < text.len() {
+            // An empty match: copy one character to get past it.
+            let len = char_len_at(&text, m.end);
+            out.push_str(&text[m.end..m.end + len]);
+            pos += len;
+            search += len;
+        }
+        if pos >= text.len() || !global {
+            break;
+        }
+    }
+    out.push_str(&text[pos.min(text.len())..]);
+    Ok(Value::Str(out))
+}
+
+/// submatch({nr} [, {list}]): submatch {nr} of the `\=` replacement being
+/// evaluated, with {list} as a List of lines.  Empty outside of one.
+pub(crate) fn f_submatch(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let nr = ev.tv_number(&args[0])?;
+    if !(0..=9).contains(&nr) {
+        return ev.emsg(format!("E935: Invalid submatch number: {}", nr));
+    }
+    let list = ev.opt_number(args, 1, 0)? != 0;
+    let text = ev.submatches.as_ref().map(|groups| groups[nr as usize].clone());
+    Ok(match (text, list) {
+        (Some(text), true) => Value::new_list(text.split('\n').map(|line| Value::Str(line.to_string())).collect()),
+        (None, true) => Value::new_list(Vec::new()),
+        (text, false) => Value::Str(text.unwrap_or_default()),
+    })
+}
+
+/// What match(), matchend() and matchstr() return.
+#[derive(Clone, Copy, PartialEq)]
+enum MatchKind {
+    Start,
+    End,
+    Str,
+}
+
+/// Common part of match(), matchend() and matchstr(): find the {count}'th
+/// match of {pat} in a String, or in the items of a List.
+fn find_match(ev: &mut Evaluator, args: &[Value], kind: MatchKind) -> Result<Value, ()> {
+    let pat = ev.tv_string(&args[1])?;
+    let re = ev.regex(&pat, ev.ignorecase())?;
+    let start = args.get(2).map(|start| ev.tv_number(start)).transpose()?;
+    let mut count = ev.opt_number(args, 3, 1)?.max(1);
+    let not_found = || match kind {
+        MatchKind::Str => Value::Str(String::new()),
+        _ => Value::Number(-1),
+    };
+    if let Value::List(list) = &args[0] {
+        let items = list.borrow().clone();
+        let first = start.unwrap_or(0);
+        let first = if first < 0 { (items.len() as i64 + first).max(0) } else { first } as usize;
+        for (idx, item) in items.iter().enumerate().skip(first) {
+            let text = match item {
+                Value::Str(s) => s.clone(),
+                item => item.to_string(),
+            };
+            let Some(m) = re.find_at(&text, 0) else { continue };
+            count -= 1;
+            if count == 0 {
+                return Ok(match kind {
+                    MatchKind::Start => Value::Number(idx as i64),
+                    MatchKind::End => Value::Number(m.end as i64),
+                    MatchKind::Str => Value::Str(text),
+                });
+            }
+        }
+        return Ok(not_found());
+    }
+    let text = ev.tv_string(&args[0])?;
+    let mut pos = start.unwrap_or(0).max(0) as usize;
+    if pos > text.len() {
+        return Ok(not_found());
+    }
+    while !text.is_char_boundary(pos) {
+        pos += 1;
+    }
+    loop {
+        let Some(m) = re.find_at(&text, pos) else { return Ok(not_found()) };
+        count -= 1;
+        if count == 0 {
+            return Ok(match kind {
+                MatchKind::Start => Value::Number(m.start as i64),
+                MatchKind::End => Value::Number(m.end as i64),
+                MatchKind::Str => Value::Str(text[m.start..m.end].to_string()),
+            });
+        }
+        // The next match may start one character after this one.
+        if m.start >= text.len() {
+            return Ok(not_found());
+        }
+        pos = m.start + char_len_at(&text, m.start);
+    }
+}
+
+/// match({expr}, {pat} [, {start} [, {count}]]): byte index of the match.
+pub(crate) fn f_match(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    find_match(ev, args, MatchKind::Start)
+}
+
+pub(crate) fn f_matchend(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    find_match(ev, args, MatchKind::End)
+}
+
+pub(crate) fn f_matchstr(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    find_match(ev, args, MatchKind::Str)
+}
+
+/// The flags, width and precision of a printf() conversion.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pad `body` to the width.  `sign` and `prefix` go before zero padding.
+    fn pad(&self, sign: &str, prefix: &str, body: &str) -> String {
+        let len = sign.len() + prefix.len() + body.chars().count();
+        let fill = self.width.saturating_sub(len);
+        if self.left {
+            format!("{}{}{}{}", sign, prefix, body, " ".repeat(fill))
+        } else if self.zero {
+            format!("{}{}{}{}", sign, prefix, "0".repeat(fill), body)
+        } else {
+            format!("{}{}{}{}", " ".repeat(fill), sign, prefix, body)
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+}
+
+/// A Float in the "%e" format of C: one digit before the point and an
+/// exponent of at least two digits.
+fn format_exp(f: f64, precision: usize, upper: bool) -> String {
+    let s = format!("{:.*e}", precision, f);
+    let (mantissa, exp) = s.split_once('e').unwrap_or((&s, "0"));
+    let exp: i32 = exp.parse().unwrap_or(0);
+    let e = if upper { 'E' } else { 'e' };
+    format!("{}{}{}{:02}", mantissa, e, if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+/// A Float in the "%g" format of C: "%e" for very small or large numbers,
+/// "%f" otherwise, without trailing zeros unless `alt`.
+fn format_general(f: f64, precision: usize, upper: bool, alt: bool) -> String {
+    let precision = precision.max(1);
+    if f == 0.0 {
+        return if alt { format!("{:.*}", precision - 1, 0.0) } else { "0".to_string() };
+    }
+    let exp = format!("{:.*e}", precision - 1, f)
+        .split_once('e')
+        .and_then(|(_, e)| e.parse::<i32>().ok())
+        .unwrap_or(0);
+    let strip = |s: String| {
+        if alt || !s.contains('.') {
+            s
+        } else {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        }
+    };
+    if exp < -4 || exp >= precision as i32 {
+        let s = format_exp(f, precision - 1, upper);
+        let (mantissa, exp) = s.split_at(s.find(['e', 'E']).unwrap_or(s.len()));
+        format!("{}{}", strip(mantissa.to_string()), exp)
+    } else {
+        strip(format!("{:.*}", (precision as i32 - 1 - exp).max(0) as usize, f))
+    }
+}
+
+/// printf({fmt}, {expr1} ...): format like C's printf().  Supported are
+/// "%%", "%c", "%d", "%i", "%o", "%x", "%X", "%b", "%B", "%s", "%S", "%f",
+/// "%F", "%e", "%E", "%g" and "%G" with the flags "-+ #0", a width and a
+/// precision, where "*" takes the value from the arguments.
+pub(crate) fn f_printf(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let fmt = ev.tv_string(&args[0])?;
+    let mut rest = args[1..].iter();
+    let mut next_arg = |ev: &mut Evaluator| match rest.next() {
+        Some(arg) => Ok(arg.clone()),
+        None => ev.emsg("E766: Insufficient arguments for printf()".to_string()),
+    };
+    let mut out = String::new();
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut spec = Spec::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alt = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        let mut number = |chars: &mut std::iter::Peekable<std::str::Chars>, ev: &mut Evaluator| {
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                let arg = next_arg(ev)?;
+                return ev.tv_number(&arg).map(Some);
+            }
+            let mut n = None;
+            while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                n = Some(n.unwrap_or(0) * 10 + d as i64);
+                chars.next();
+            }
+            Ok(n)
+        };
+        if let Some(width) = number(&mut chars, ev)? {
+            if width < 0 {
+                spec.left = true;
+            }
+            spec.width = width.unsigned_abs() as usize;
+        }
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(number(&mut chars, ev)?.unwrap_or(0).max(0) as usize);
+        }
+        // Length modifiers make no difference, all Numbers are 64 bits.
+        while matches!(chars.peek(), Some('h' | 'l' | 'L' | 'q' | 'j' | 'z' | 't')) {
+            chars.next();
+        }
+        let Some(conv) = chars.next() else {
+            out.push('%');
+            break;
+        };
+        match conv {
+            '%' => out.push('%'),
+            'c' => {
+                let arg = next_arg(ev)?;
+                let n = ev.tv_number(&arg)?;
+                let c = char::from_u32(n as u32).unwrap_or('\u{fffd}');
+                out.push_str(&spec.pad("", "", &c.to_string()));
+            }
+            's' | 'S' => {
+                let arg = next_arg(ev)?;
+                let mut s = match arg {
+                    Value::Str(s) => s,
+                    arg => arg.to_string(),
+                };
+                if let Some(precision) = spec.precision {
+                    s = s.chars().take(precision).collect();
+                }
+                spec.zero = false;
+                out.push_str(&spec.pad("", "", &s));
+            }
+            'd' | 'i' | 'u' | 'o' | 'x' | 'X' | 'b' | 'B' => {
+                let arg = next_arg(ev)?;
+                let n = ev.tv_number(&arg)?;
+                let (negative, mut body, prefix) = match conv {
+                    'd' | 'i' | 'u' => (n < 0, n.unsigned_abs().to_string(), ""),
+                    // Other bases show the bits of a negative Number.
+                    'o' => (false, format!("{:o}", n), if spec.alt && n != 0 { "0" } else { "" }),
+                    'x' => (false, format!("{:x}", n), if spec.alt && n != 0 { "0x" } else { "" }),
+                    'X' => (false, format!("{:X}", n), if spec.alt && n != 0 { "0X" } else { "" }),
+                    'b' => (false, format!("{:b}", n), if spec.alt && n != 0 { "0b" } else { "" }),
+                    _ => (false, format!("{:b}", n), if spec.alt && n != 0 { "0B" } else { "" }),
+                };
+                if let Some(precision) = spec.precision {
+                    spec.zero = false;
+                    if precision == 0 && n == 0 {
+                        body.clear();
+                    } else if body.len() < precision {
+                        body = format!("{}{}", "0".repeat(precision - body.len()), body);
+                    }
+                }
+                let sign = if matches!(conv, 'd' | 'i') { spec.sign(negative) } else { "" };
+                out.push_str(&spec.pad(sign, prefix, &body));
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let arg = next_arg(ev)?;
+                let f = match arg {
+                    Value::Float(f) => f,
+                    Value::Number(n) => n as f64,
+                    _ => return ev.emsg("E807: Expected Float argument for printf()".to_string()),
+                };
+                let precision = spec.precision.unwrap_or(6);
+                let upper = conv.is_ascii_uppercase();
+                let body = if f.is_nan() {
+                    "nan".to_string()
+                } else if f.is_infinite() {
+                    "inf".to_string()
+                } else {
+                    match conv {
+                        'f' | 'F' => format!("{:.*}", precision, f.abs()),
+                        'e' | 'E' => format_exp(f.abs(), precision, upper),
+                        _ => format_general(f.abs(), precision, upper, spec.alt),
+                    }
+                };
+                let body = if upper { body.to_uppercase() } else { body };
+                if !f.is_finite() {
+                    spec.zero = false;
+                }
+                let negative = f.is_sign_negative() && !f.is_nan();
+                out.push_str(&spec.pad(spec.sign(negative), "", &body));
+            }
+            other => {
+                out.push('%');
+                out.push(other);
+            }
+        }
+    }
+    if rest.next().is_some() {
+        return ev.emsg("E767: Too many arguments for printf()".to_string());
+    }
+    Ok(Value::Str(out))
+}
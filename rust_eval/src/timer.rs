@@ -0,0 +1,156 @@
+//! Timers: timer_start(), timer_stop(), timer_stopall(), timer_pause() and
+//! timer_info().
+//!
+//! A [`TimerQueue`] does the timing, the evaluator keeps the callback of
+//! each timer.  Callbacks are invoked by [`Evaluator::run_timers`].  The
+//! evaluator is an [`IdleHandler`], so that the input loop runs timers while
+//! waiting for a key and never in the middle of a command.  Tests switch the
+//! queue to a virtual clock with [`Evaluator::timers_mut`].
+
+use std::collections::BTreeMap;
+
+use rust_input::IdleHandler;
+use rust_time::TimerQueue;
+
+use crate::{func, Evaluator, Value};
+
+/// A timer is stopped when its callback failed this many times.
+const MAX_TIMER_ERRORS: usize = 3;
+
+/// What a timer does when it fires.
+pub(crate) struct TimerCallback {
+    pub(crate) callback: Value,
+    errors: usize,
+}
+
+impl Evaluator {
+    pub fn timers(&self) -> &TimerQueue {
+        &self.timers
+    }
+
+    pub fn timers_mut(&mut self) -> &mut TimerQueue {
+        &mut self.timers
+    }
+
+    /// Invoke the callbacks of the timers that are due.  An error in a
+    /// callback is reported like for a command.  Returns the milliseconds
+    /// until the next timer is due.  Does nothing when called from a timer
+    /// callback.
+    pub fn run_timers(&mut self) -> Option<u64> {
+        if self.timer_busy {
+            return self.timers.next_due();
+        }
+        self.timer_busy = true;
+        for id in self.timers.take_due() {
+            let Some(callback) = self.timer_callbacks.get(&id).map(|t| t.callback.clone()) else {
+                continue;
+            };
+            let failed = self.toplevel(|ev| ev.call_value(&callback, &[Value::Number(id as i64)]).map(|_| ())).is_err();
+            let entry = self.timer_callbacks.get_mut(&id);
+            if let Some(entry) = entry.filter(|_| failed) {
+                entry.errors += 1;
+                if entry.errors >= MAX_TIMER_ERRORS {
+                    self.timers.stop(id);
+                }
+            }
+            if self.timers.get(id).is_none() {
+                self.timer_callbacks.remove(&id);
+            }
+        }
+        self.timer_busy = false;
+        self.timers.next_due()
+    }
+
+    /// Timer `id` argument, which must be a Number.
+    fn timer_id(&mut self, val: &Value) -> Result<u64, ()> {
+        match val {
+            Value::Number(n) => Ok((*n).max(0) as u64),
+            _ => self.emsg("E39: Number expected".to_string()),
+        }
+    }
+
+    fn timer_info_dict(&self, id: u64) -> Option<Value> {
+        let timer = self.timers.get(id)?;
+        let callback = self.timer_callbacks.get(&id)?.callback.clone();
+        let mut dict = BTreeMap::new();
+        dict.insert("id".to_string(), Value::Number(id as i64));
+        dict.insert("time".to_string(), Value::Number(timer.time as i64));
+        dict.insert("remaining".to_string(), Value::Number(self.timers.remaining(timer)));
+        // The number of times the timer still fires.
+        let repeat = if timer.repeat < 0 { -1 } else { timer.repeat + 1 };
+        dict.insert("repeat".to_string(), Value::Number(repeat));
+        dict.insert("callback".to_string(), callback);
+        dict.insert("paused".to_string(), Value::Number(timer.paused as i64));
+        Some(Value::new_dict(dict))
+    }
+}
+
+impl IdleHandler for Evaluator {
+    fn run_due(&mut self) -> Option<u64> {
+        self.run_timers()
+    }
+
+    fn sleep(&mut self, ms: u64) {
+        self.timers.sleep(ms);
+    }
+}
+
+/// timer_start({time}, {callback} [, {options}]): call {callback} with the
+/// timer ID after {time} milliseconds.  The "repeat" option gives how many
+/// times, -1 for forever.
+pub(crate) fn f_timer_start(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let time = ev.tv_number(&args[0])?.max(0) as u64;
+    let callback = match &args[1] {
+        Value::Func(_) => args[1].clone(),
+        Value::Str(name) if !name.is_empty() => func::f_function(ev, &args[1..2])?,
+        _ => return ev.emsg("E921: Invalid callback argument".to_string()),
+    };
+    let repeat = match args.get(2) {
+        None => 1,
+        Some(Value::Dict(dict)) => {
+            let repeat = dict.borrow().get("repeat").cloned();
+            match repeat {
+                Some(repeat) => ev.tv_number(&repeat)?,
+                None => 1,
+            }
+        }
+        Some(_) => return ev.emsg("E715: Dictionary required".to_string()),
+    };
+    // The queue counts the times after the first one.
+    let id = ev.timers.start(time, if repeat < 0 { -1 } else { (repeat - 1).max(0) });
+    ev.timer_callbacks.insert(id, TimerCallback { callback, errors: 0 });
+    Ok(Value::Number(id as i64))
+}
+
+/// timer_stop({id})
+pub(crate) fn f_timer_stop(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let id = ev.timer_id(&args[0])?;
+    ev.timers.stop(id);
+    ev.timer_callbacks.remove(&id);
+    Ok(Value::Number(0))
+}
+
+/// timer_stopall()
+pub(crate) fn f_timer_stopall(ev: &mut Evaluator, _args: &[Value]) -> Result<Value, ()> {
+    ev.timers.stop_all();
+    ev.timer_callbacks.clear();
+    Ok(Value::Number(0))
+}
+
+/// timer_pause({id}, {paused})
+pub(crate) fn f_timer_pause(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let id = ev.timer_id(&args[0])?;
+    let paused = ev.is_true(&args[1])?;
+    ev.timers.pause(id, paused);
+    Ok(Value::Number(0))
+}
+
+/// timer_info([{id}]): a List with a Dictionary for timer {id}, or for all
+/// timers.  Empty when there is no such timer.
+pub(crate) fn f_timer_info(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let ids: Vec<u64> = match args.first() {
+        Some(id) => vec![ev.timer_id(id)?],
+        None => ev.timers.timers().map(|timer| timer.id).collect(),
+    };
+    Ok(Value::new_list(ids.into_iter().filter_map(|id| ev.timer_info_dict(id)).collect()))
+}
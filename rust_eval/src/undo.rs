@@ -0,0 +1,172 @@
+//! Undo in scripts: `:undo`, `:redo`, `:earlier`, `:later`, `:undojoin`,
+//! `:wundo` and `:rundo`, and the undotree() builtin.
+//!
+//! The undo tree is rust_undo's [`UndoTree`], kept with the buffer.  The
+//! changes made by one command line form one undo block: the tree is synced
+//! when the command line is done.
+//!
+//! [`UndoTree`]: rust_undo::UndoTree
+
+use std::collections::BTreeMap;
+
+use rust_undo::{parse_step, read_undo_file, write_undo_file, StepUnit, TreeEntry, UndoFileError};
+
+use crate::ex::expand_home;
+use crate::{Evaluator, Value};
+
+impl Evaluator {
+    /// Show the message of undo or redo, or give the error.
+    fn undo_done(&mut self, result: Result<String, String>) -> Result<(), ()> {
+        match result {
+            Ok(msg) => {
+                self.message(msg);
+                Ok(())
+            }
+            Err(msg) => self.emsg(msg),
+        }
+    }
+
+    /// End the undo block, the next change starts a new one.
+    pub(crate) fn u_sync(&mut self) {
+        if !self.buffer.undo.synced() {
+            self.with_undo(|undo, text| undo.sync(text));
+        }
+    }
+
+    /// Execute `:undo [N]`: undo one change, or go to the state after change
+    /// N.
+    pub(crate) fn ex_undo(&mut self, arg: &str) -> Result<(), ()> {
+        if arg.is_empty() {
+            let result = self.with_undo(|undo, text| undo.undo(text, 1));
+            return self.undo_done(result);
+        }
+        let Ok(seq) = arg.parse::<i64>() else {
+            return self.emsg(format!("E474: Invalid argument: {}", arg));
+        };
+        let result = self.with_undo(|undo, text| undo.undo_time(text, seq, StepUnit::Changes, true));
+        self.undo_done(result)
+    }
+
+    pub(crate) fn ex_redo(&mut self) -> Result<(), ()> {
+        let result = self.with_undo(|undo, text| undo.redo(text, 1));
+        self.undo_done(result)
+    }
+
+    /// Execute `:earlier {N}` or `:later {N}`, N optionally followed by "s",
+    /// "m", "h", "d" or "f".
+    pub(crate) fn ex_later(&mut self, arg: &str, earlier: bool) -> Result<(), ()> {
+        let Some((count, unit)) = parse_step(arg) else {
+            return self.emsg(format!("E475: Invalid argument: {}", arg));
+        };
+        let step = if earlier { -count } else { count };
+        let result = self.with_undo(|undo, text| undo.undo_time(text, step, unit, false));
+        self.undo_done(result)
+    }
+
+    pub(crate) fn ex_undojoin(&mut self) -> Result<(), ()> {
+        let undolevels = matches!(self.get_option("undolevels"), Some(Value::Number(n)) if n >= 0);
+        if !undolevels {
+            return Ok(());
+        }
+        match self.buffer.undo.undojoin() {
+            Ok(()) => Ok(()),
+            Err(msg) => self.emsg(msg),
+        }
+    }
+
+    fn undo_file_error(&mut self, err: UndoFileError) -> Result<(), ()> {
+        match err {
+            UndoFileError::Error(msg) => self.emsg(msg),
+            UndoFileError::Message(msg) => {
+                self.message(msg);
+                Ok(())
+            }
+        }
+    }
+
+    /// Execute `:wundo[!] {file}`: write the undo tree to {file}.  Nothing
+    /// is written when there is nothing to undo.
+    pub(crate) fn ex_wundo(&mut self, arg: &str, bang: bool) -> Result<(), ()> {
+        if arg.is_empty() {
+            return self.emsg("E471: Argument required".to_string());
+        }
+        if self.buffer.undo.is_empty() {
+            return Ok(());
+        }
+        self.u_sync();
+        let path = expand_home(arg);
+        let key = self.crypt_key().map(str::to_string);
+        let result = self.with_undo(|undo, text| write_undo_file(undo, text, &path, bang, key.as_deref()));
+        result.or_else(|err| self.undo_file_error(err))
+    }
+
+    /// Execute `:rundo {file}`: read the undo tree from {file}.  It is only
+    /// used when the text is what it was written for.
+    pub(crate) fn ex_rundo(&mut self, arg: &str) -> Result<(), ()> {
+        if arg.is_empty() {
+            return self.emsg("E471: Argument required".to_string());
+        }
+        let path = expand_home(arg);
+        let key = self.crypt_key().map(str::to_string);
+        let result = self.with_undo(|undo, text| {
+            let mut tree = read_undo_file(text, &path, key.as_deref())?;
+            tree.set_time_for_testing(undo.time_for_testing());
+            *undo = tree;
+            Ok(())
+        });
+        match result {
+            Ok(()) => {
+                self.message(format!("Finished reading undo file {}", path.display()));
+                Ok(())
+            }
+            Err(err) => self.undo_file_error(err),
+        }
+    }
+}
+
+fn tree_entries(entries: Vec<TreeEntry>) -> Value {
+    let items = entries
+        .into_iter()
+        .map(|entry| {
+            let mut dict = BTreeMap::new();
+            dict.insert("seq".to_string(), Value::Number(entry.seq));
+            dict.insert("time".to_string(), Value::Number(entry.time));
+            if entry.newhead {
+                dict.insert("newhead".to_string(), Value::Number(1));
+            }
+            if entry.curhead {
+                dict.insert("curhead".to_string(), Value::Number(1));
+            }
+            if entry.save > 0 {
+                dict.insert("save".to_string(), Value::Number(entry.save));
+            }
+            if !entry.alt.is_empty() {
+                dict.insert("alt".to_string(), tree_entries(entry.alt));
+            }
+            Value::new_dict(dict)
+        })
+        .collect();
+    Value::new_list(items)
+}
+
+/// undotree([{buf}]): the state of the undo tree as a Dictionary.
+pub(crate) fn f_undotree(ev: &mut Evaluator, _args: &[Value]) -> Result<Value, ()> {
+    let undo = &ev.buffer.undo;
+    let mut dict = BTreeMap::new();
+    dict.insert("synced".to_string(), Value::Number(undo.synced() as i64));
+    dict.insert("seq_last".to_string(), Value::Number(undo.seq_last()));
+    dict.insert("seq_cur".to_string(), Value::Number(undo.seq_cur()));
+    dict.insert("time_cur".to_string(), Value::Number(undo.time_cur()));
+    dict.insert("save_last".to_string(), Value::Number(undo.save_last()));
+    dict.insert("save_cur".to_string(), Value::Number(undo.save_cur()));
+    dict.insert("entries".to_string(), tree_entries(undo.entries()));
+    Ok(Value::new_dict(dict))
+}
+
+/// test_settime({expr}): use {expr} as the current time for undo, zero to
+/// use the clock again.
+pub(crate) fn f_test_settime(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let time = ev.tv_number(&args[0])?;
+    ev.buffer.undo.set_time_for_testing((time != 0).then_some(time));
+    Ok(Value::Number(0))
+}
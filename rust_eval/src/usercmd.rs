@@ -0,0 +1,252 @@
+//! User-defined commands in scripts: `:command`, `:delcommand`,
+//! `:comclear`, executing a user command and completing command-line
+//! arguments, also for getcompletion().
+//!
+//! The commands are kept in rust_usercmd's [`UserCmds`].  The replacement
+//! text of a command is executed in the script the command was defined in,
+//! so that "s:" and `<SID>` refer to its functions.  `-complete=custom`
+//! and `-complete=customlist` functions are called in that script too.
+//!
+//! [`UserCmds`]: rust_usercmd::UserCmds
+
+use std::path::PathBuf;
+
+use rust_autocmd::ALL_EVENTS;
+use rust_usercmd::{modifier, mods_string, Complete, COMPLETE_NAMES};
+
+use crate::ex::{command_names, expand_home, parse_script, split_at_bars, split_lines, Cmd, Flow};
+use crate::options::option_names;
+use crate::{Evaluator, Value};
+
+/// The kind of completion for the arguments of builtin commands.
+const BUILTIN_COMPLETE: &[(&str, &str)] = &[
+    ("augroup", "augroup"),
+    ("call", "function"),
+    ("delcommand", "user"),
+    ("delfunction", "function"),
+    ("doautoall", "event"),
+    ("doautocmd", "event"),
+    ("function", "function"),
+    ("lockvar", "var"),
+    ("rundo", "file"),
+    ("source", "file"),
+    ("unlet", "var"),
+    ("unlockvar", "var"),
+    ("write", "file"),
+    ("wundo", "file"),
+];
+
+impl Evaluator {
+    /// Execute `:command`.
+    pub(crate) fn ex_command(&mut self, arg: &str, bang: bool) -> Result<(), ()> {
+        match self.usercmds.do_command(arg, bang, self.curbuf(), self.sid) {
+            Ok(lines) => {
+                for line in lines {
+                    self.message(line);
+                }
+                Ok(())
+            }
+            Err(msg) => self.emsg(msg),
+        }
+    }
+
+    /// Execute `:delcommand`.
+    pub(crate) fn ex_delcommand(&mut self, arg: &str) -> Result<(), ()> {
+        self.usercmds.do_delcommand(arg, self.curbuf()).or_else(|msg| self.emsg(msg))
+    }
+
+    /// Execute user command `cmd`.  Returns None when there is no such
+    /// command.
+    pub(crate) fn exec_user_cmd(&mut self, cmd: &Cmd) -> Result<Option<Flow>, ()> {
+        let ucmd = match self.usercmds.find(&cmd.name, self.curbuf()) {
+            Ok(Some(ucmd)) => ucmd.clone(),
+            Ok(None) => return Ok(None),
+            Err(msg) => return self.emsg(msg),
+        };
+        let range = match self.parse_range(&cmd.range)? {
+            Some((first, last, _)) if first > last => return self.emsg("E493: Backwards range given".to_string()),
+            range => range,
+        };
+        // With -bar the arguments end at a "|", the rest is the next command.
+        let (args, next) = match split_at_bars(&cmd.arg)[..] {
+            [args, _, ..] if ucmd.attrs.bar => (args.trim_end(), Some(&cmd.arg[args.len() + 1..])),
+            _ => (cmd.arg.as_str(), None),
+        };
+        let mods = mods_string(&self.cmdmods);
+        let lines = (self.buffer.cursor().0 as i64, self.buffer.line_count() as i64);
+        let text = match ucmd.invocation(cmd.bang, range, lines, args, &mods) {
+            Ok(inv) => ucmd.replace(&inv),
+            Err(msg) => return self.emsg(msg),
+        };
+        let stmts = match parse_script(&split_lines(&text)) {
+            Ok(stmts) => stmts,
+            Err(msg) => return self.emsg(msg),
+        };
+        let saved_mods = std::mem::take(&mut self.cmdmods);
+        let saved_sid = std::mem::replace(&mut self.sid, ucmd.sid);
+        let sourcing = (!ucmd.attrs.keepscript).then(|| self.enter_sourcing(ucmd.name.clone()));
+        let result = self.exec_stmts(&stmts);
+        if let Some(saved) = sourcing {
+            self.leave_sourcing(saved);
+        }
+        self.sid = saved_sid;
+        self.cmdmods = saved_mods;
+        match (result?, next) {
+            (Flow::Normal, Some(next)) => {
+                let stmts = match parse_script(&[(cmd.lnum, next.to_string())]) {
+                    Ok(stmts) => stmts,
+                    Err(msg) => return self.emsg(msg),
+                };
+                self.exec_stmts(&stmts).map(Some)
+            }
+            (flow, _) => Ok(Some(flow)),
+        }
+    }
+
+    /// The completions for command line `line` with the cursor at its end:
+    /// command names while the name is being typed, otherwise the arguments
+    /// of the command as its `-complete` says.
+    pub fn cmdline_completions(&mut self, line: &str) -> Result<Vec<String>, ()> {
+        let text = line.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+        let text = text.trim_start_matches(|c: char| c.is_ascii_digit() || ",.$%".contains(c));
+        let name_len = text.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(text.len());
+        let (name, rest) = text.split_at(name_len);
+        if rest.is_empty() {
+            return Ok(self.complete_kind("command", name));
+        }
+        let arg = rest.strip_prefix('!').unwrap_or(rest).trim_start();
+        if modifier(name).is_some() {
+            return self.cmdline_completions(arg);
+        }
+        let lead = arg.rsplit([' ', '\t']).next().unwrap_or("");
+        let (complete, sid) = if name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            match self.usercmds.find(name, self.curbuf()) {
+                Ok(Some(ucmd)) if ucmd.attrs.nargs.allows_args() => (ucmd.attrs.complete.clone(), ucmd.sid),
+                _ => (None, 0),
+            }
+        } else {
+            let full = command_names().find(|full| full.starts_with(name)).unwrap_or(name);
+            let kind = BUILTIN_COMPLETE.iter().find(|(cmd, _)| *cmd == full).map(|&(_, kind)| Complete::Kind(kind));
+            (kind, self.sid)
+        };
+        match complete {
+            Some(complete) => self.complete(&complete, lead, line, sid),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The completions for `lead` with `complete`, in command line `line`.
+    /// Custom completion functions are called in script `sid`.
+    fn complete(&mut self, complete: &Complete, lead: &str, line: &str, sid: usize) -> Result<Vec<String>, ()> {
+        let func = match complete {
+            Complete::Kind(kind) => return Ok(self.complete_kind(kind, lead)),
+            Complete::Custom(func) | Complete::CustomList(func) => func,
+        };
+        let args = [Value::Str(lead.to_string()), Value::Str(line.to_string()), Value::Number(line.len() as i64)];
+        let saved_sid = std::mem::replace(&mut self.sid, sid);
+        let result = self.call_function(func, &args);
+        self.sid = saved_sid;
+        Ok(match (complete, result?) {
+            // Not filtered, the function is expected to do that.
+            (Complete::CustomList(_), Value::List(list)) => list.borrow().iter().map(Value::to_string).collect(),
+            (Complete::CustomList(_), _) => Vec::new(),
+            (_, val) => val.to_string().lines().filter(|m| m.starts_with(lead)).map(str::to_string).collect(),
+        })
+    }
+
+    /// The completions for `lead` of a kind in [`COMPLETE_NAMES`], sorted.
+    /// Kinds that need an editor this evaluator does not have give none.
+    fn complete_kind(&self, kind: &str, lead: &str) -> Vec<String> {
+        let mut names: Vec<String> = match kind {
+            "augroup" => self.autocmds.group_names().map(str::to_string).collect(),
+            "buffer" => [self.buffer.name()].into_iter().filter(|n| !n.is_empty()).map(str::to_string).collect(),
+            "command" => {
+                let builtin = command_names().map(str::to_string);
+                let embedder = self.ex_commands.iter().map(|(name, _, _)| name.clone());
+                builtin.chain(embedder).chain(self.user_command_names()).collect()
+            }
+            "dir" => return complete_files(lead, true),
+            "environment" => std::env::vars().map(|(name, _)| name).collect(),
+            "event" => {
+                // Event names are matched ignoring case.
+                let lower = lead.to_lowercase();
+                return ALL_EVENTS
+                    .iter()
+                    .map(|event| event.name().to_string())
+                    .filter(|name| name.to_lowercase().starts_with(&lower))
+                    .collect();
+            }
+            "file" => return complete_files(lead, false),
+            "function" => self.function_names(),
+            "mapping" => {
+                let curbuf = self.curbuf();
+                self.maps.maps().filter(|m| m.buffer.is_none_or(|buf| buf == curbuf)).map(|m| m.lhs_string()).collect()
+            }
+            "option" => option_names().map(str::to_string).collect(),
+            "user" => self.user_command_names(),
+            "var" => {
+                let prefix = if lead.starts_with("g:") { "g:" } else { "" };
+                self.vars.keys().map(|name| format!("{}{}", prefix, name)).collect()
+            }
+            _ => Vec::new(),
+        };
+        names.retain(|name| name.starts_with(lead));
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn user_command_names(&self) -> Vec<String> {
+        self.usercmds.commands(self.curbuf()).map(|cmd| cmd.name.clone()).collect()
+    }
+}
+
+/// The files, or only the directories, whose path starts with `lead`.  A
+/// directory ends in "/".  Hidden files are only matched by a leading ".".
+fn complete_files(lead: &str, dirs_only: bool) -> Vec<String> {
+    let (dir, base) = match lead.rfind('/') {
+        Some(i) => lead.split_at(i + 1),
+        None => ("", lead),
+    };
+    let path = if dir.is_empty() { PathBuf::from(".") } else { expand_home(dir) };
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(base) || (name.starts_with('.') && !base.starts_with('.')) {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            if dirs_only && !is_dir {
+                return None;
+            }
+            Some(format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }))
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// getcompletion({pat}, {type} [, {filtered}])
+pub(crate) fn f_getcompletion(ev: &mut Evaluator, args: &[Value]) -> Result<Value, ()> {
+    let pat = ev.tv_string(&args[0])?;
+    let kind = ev.tv_string(&args[1])?;
+    let matches = if kind == "cmdline" {
+        ev.cmdline_completions(&pat)?
+    } else {
+        let complete = match kind.split_once(',') {
+            Some(("custom", func)) => Complete::Custom(func.to_string()),
+            Some(("customlist", func)) => Complete::CustomList(func.to_string()),
+            _ => match COMPLETE_NAMES.iter().find(|&&name| name == kind) {
+                Some(name) => Complete::Kind(name),
+                None => return ev.emsg(format!("E475: Invalid argument: {}", kind)),
+            },
+        };
+        let sid = ev.sid;
+        ev.complete(&complete, &pat, &pat, sid)?
+    };
+    Ok(Value::new_list(matches.into_iter().map(Value::Str).collect()))
+}
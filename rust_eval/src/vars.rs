@@ -0,0 +1,335 @@
+//! Variable scopes of legacy Vim script: g:, b:, w:, t:, s:, l:, a: and v:,
+//! plus environment variables, options and registers.
+//!
+//! A name without a scope is a local variable inside a `:function` and a
+//! global variable elsewhere.  Lambdas also see global variables without the
+//! "g:" prefix.
+
+use std::collections::{HashMap, HashSet};
+
+use rust_core::{Special, VarLock};
+
+use crate::{eval, parse_expr, Evaluator, Expr, Tokenizer, Value};
+
+/// The variables of the scopes that are not kept in a function frame.
+#[derive(Default)]
+pub(crate) struct Scopes {
+    pub(crate) buffer: HashMap<String, Value>,
+    pub(crate) window: HashMap<String, Value>,
+    pub(crate) tab: HashMap<String, Value>,
+    pub(crate) vim: HashMap<String, Value>,
+    /// Script-local variables, by script ID.
+    pub(crate) script: HashMap<usize, HashMap<String, Value>>,
+    /// Variables locked with `:lockvar`, by [`Evaluator::lock_key`].
+    pub(crate) locked: HashSet<String>,
+}
+
+impl Scopes {
+    pub(crate) fn new() -> Self {
+        let mut scopes = Scopes::default();
+        for (name, val) in [
+            ("version", Value::Number(901)),
+            ("true", Value::Bool(true)),
+            ("false", Value::Bool(false)),
+            ("null", Value::Special(Special::Null)),
+            ("none", Value::Special(Special::None)),
+            ("exception", Value::Str(String::new())),
+            ("throwpoint", Value::Str(String::new())),
+            ("errmsg", Value::Str(String::new())),
+        ] {
+            scopes.vim.insert(name.to_string(), val);
+        }
+        scopes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Scope {
+    Global,
+    Buffer,
+    Window,
+    Tab,
+    Script,
+    Local,
+    Arg,
+    Vim,
+    Env,
+    /// "&name", "&l:name" or "&g:name".
+    Option,
+    /// "@r"
+    Register,
+    /// No scope given.
+    Implicit,
+}
+
+/// Split the scope prefix off `name`.
+pub(crate) fn split_scope(name: &str) -> (Scope, &str) {
+    if let Some(env) = name.strip_prefix('$') {
+        return (Scope::Env, env);
+    }
+    if let Some(opt) = name.strip_prefix('&') {
+        let opt = opt.strip_prefix("l:").or_else(|| opt.strip_prefix("g:")).unwrap_or(opt);
+        return (Scope::Option, opt);
+    }
+    if let Some(reg) = name.strip_prefix('@') {
+        return (Scope::Register, reg);
+    }
+    let scope = match name.as_bytes() {
+        [c, b':', ..] => match c {
+            b'g' => Scope::Global,
+            b'b' => Scope::Buffer,
+            b'w' => Scope::Window,
+            b't' => Scope::Tab,
+            b's' => Scope::Script,
+            b'l' => Scope::Local,
+            b'a' => Scope::Arg,
+            b'v' => Scope::Vim,
+            _ => return (Scope::Implicit, name),
+        },
+        _ => return (Scope::Implicit, name),
+    };
+    (scope, &name[2..])
+}
+
+/// v: variables that cannot be changed.
+const READONLY_VIM_VARS: &[&str] = &["version", "true", "false", "null", "none"];
+
+/// Registers that can only be read.
+const READONLY_REGISTERS: &str = ":.%#";
+
+fn valid_register(name: char) -> bool {
+    name.is_ascii_alphanumeric() || "\"@-*+/_=".contains(name) || READONLY_REGISTERS.contains(name)
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '#')
+}
+
+impl Evaluator {
+    /// Look up a variable by its name as written in the script.
+    pub(crate) fn lookup_var(&self, name: &str) -> Option<Value> {
+        let (scope, short) = split_scope(name);
+        match scope {
+            Scope::Global => self.vars.get(short).cloned(),
+            Scope::Buffer => self.scopes.buffer.get(short).cloned(),
+            Scope::Window => self.scopes.window.get(short).cloned(),
+            Scope::Tab => self.scopes.tab.get(short).cloned(),
+            Scope::Vim => self.scopes.vim.get(short).cloned(),
+            Scope::Script => self.scopes.script.get(&self.sid).and_then(|vars| vars.get(short)).cloned(),
+            Scope::Env => std::env::var(short).ok().map(Value::Str),
+            Scope::Option => self.get_option(short),
+            Scope::Register => {
+                let name = short.chars().next()?;
+                Some(Value::Str(self.register(name)))
+            }
+            Scope::Arg => {
+                let frame = self.frames.last()?.borrow();
+                frame.lookup_arg(short).or_else(|| frame.lookup(short))
+            }
+            Scope::Local => self.frames.last()?.borrow().lookup(short),
+            Scope::Implicit => {
+                if let Some(frame) = self.frames.last() {
+                    let frame = frame.borrow();
+                    if let Some(val) = frame.lookup(short) {
+                        return Some(val);
+                    }
+                    if frame.legacy {
+                        return None;
+                    }
+                }
+                self.vars.get(short).cloned()
+            }
+        }
+    }
+
+    /// exists("name"): whether the variable, or the item of it like "l[1]"
+    /// or "d.key", exists.  Errors are not reported.
+    pub(crate) fn var_exists(&mut self, name: &str) -> bool {
+        fn is_var(expr: &Expr) -> bool {
+            match expr {
+                Expr::Var(_) => true,
+                Expr::Index(base, _) | Expr::Member(base, _) => is_var(base),
+                _ => false,
+            }
+        }
+        let mut tokens = Tokenizer::new(name);
+        let Ok(expr) = parse_expr(&mut tokens) else {
+            return false;
+        };
+        if tokens.peek_non_ws().is_some() || !is_var(&expr) {
+            return false;
+        }
+        let saved = self.last_error.take();
+        let found = eval(&expr, self).is_ok();
+        self.last_error = saved;
+        found
+    }
+
+    /// Assign to a variable, as with `:let name = val`.
+    pub(crate) fn assign_var(&mut self, name: &str, val: Value) -> Result<(), ()> {
+        let (scope, short) = split_scope(name);
+        match scope {
+            Scope::Option => {
+                let val = if crate::options::is_string_option(short) {
+                    Value::Str(self.tv_string(&val)?)
+                } else {
+                    Value::Number(self.tv_number(&val)?)
+                };
+                return self.set_option(short, val).or_else(|msg| self.emsg(msg));
+            }
+            Scope::Register => {
+                let text = self.tv_string(&val)?;
+                let mut chars = short.chars();
+                return match (chars.next(), chars.next()) {
+                    (Some(reg), None) if valid_register(reg) && !READONLY_REGISTERS.contains(reg) => {
+                        self.set_register(reg, &text);
+                        Ok(())
+                    }
+                    _ => self.emsg(format!("E354: Invalid register name: '{}'", short)),
+                };
+            }
+            _ => {}
+        }
+        if !valid_name(short) || (scope == Scope::Local && self.frames.is_empty()) {
+            return self.emsg(format!("E461: Illegal variable name: {}", name));
+        }
+        if scope == Scope::Vim && READONLY_VIM_VARS.contains(&short) {
+            return self.emsg(format!("E46: Cannot change read-only variable \"{}\"", name));
+        }
+        if self.var_locked(name) {
+            return self.emsg(format!("E741: Value is locked: {}", name));
+        }
+        let map = match scope {
+            Scope::Global => &mut self.vars,
+            Scope::Buffer => &mut self.scopes.buffer,
+            Scope::Window => &mut self.scopes.window,
+            Scope::Tab => &mut self.scopes.tab,
+            Scope::Vim => &mut self.scopes.vim,
+            Scope::Script => self.scopes.script.entry(self.sid).or_default(),
+            Scope::Env => {
+                std::env::set_var(short, val.to_string());
+                return Ok(());
+            }
+            Scope::Arg => return self.emsg(format!("E46: Cannot change read-only variable \"{}\"", name)),
+            Scope::Option | Scope::Register => unreachable!("handled above"),
+            Scope::Local | Scope::Implicit => match self.frames.last() {
+                Some(frame) => {
+                    frame.borrow_mut().assign(short, val);
+                    return Ok(());
+                }
+                None => &mut self.vars,
+            },
+        };
+        map.insert(short.to_string(), val);
+        Ok(())
+    }
+
+    /// Remove a variable, as with `:unlet name`.
+    pub(crate) fn unlet_var(&mut self, name: &str) -> Result<(), ()> {
+        let (scope, short) = split_scope(name);
+        if self.var_locked(name) {
+            return self.emsg(format!("E741: Value is locked: {}", name));
+        }
+        let removed = match scope {
+            Scope::Global => self.vars.remove(short).is_some(),
+            Scope::Buffer => self.scopes.buffer.remove(short).is_some(),
+            Scope::Window => self.scopes.window.remove(short).is_some(),
+            Scope::Tab => self.scopes.tab.remove(short).is_some(),
+            Scope::Vim => return self.emsg(format!("E46: Cannot change read-only variable \"{}\"", name)),
+            Scope::Script => self
+                .scopes
+                .script
+                .get_mut(&self.sid)
+                .is_some_and(|vars| vars.remove(short).is_some()),
+            Scope::Env => {
+                let found = std::env::var_os(short).is_some();
+                std::env::remove_var(short);
+                found
+            }
+            Scope::Arg | Scope::Option | Scope::Register => {
+                return self.emsg(format!("E795: Cannot delete variable {}", name))
+            }
+            Scope::Local | Scope::Implicit => match self.frames.last() {
+                Some(frame) => frame.borrow_mut().vars.remove(short).is_some(),
+                None => self.vars.remove(short).is_some(),
+            },
+        };
+        if removed {
+            Ok(())
+        } else {
+            self.emsg(format!("E108: No such variable: \"{}\"", name))
+        }
+    }
+
+    /// The key of variable `name` in [`Scopes::locked`].  None for a
+    /// variable of a function, the frame keeps its lock.
+    fn lock_key(&self, name: &str) -> Option<String> {
+        let (scope, short) = split_scope(name);
+        Some(match scope {
+            Scope::Global => format!("g:{}", short),
+            Scope::Script => format!("s{}:{}", self.sid, short),
+            Scope::Local | Scope::Arg => return None,
+            Scope::Implicit => match self.frames.last() {
+                Some(frame) if frame.borrow().legacy || frame.borrow().vars.contains_key(short) => return None,
+                _ => format!("g:{}", short),
+            },
+            _ => name.to_string(),
+        })
+    }
+
+    /// Whether variable `name` was locked with `:lockvar`.
+    fn var_locked(&self, name: &str) -> bool {
+        match self.lock_key(name) {
+            Some(key) => self.scopes.locked.contains(&key),
+            None => self.frames.last().is_some_and(|frame| frame.borrow().locked.contains(split_scope(name).1)),
+        }
+    }
+
+    /// Lock or unlock variable `name`, not its value.
+    pub(crate) fn lock_var(&mut self, name: &str, lock: bool) {
+        let update = |locked: &mut HashSet<String>, key: String| {
+            if lock {
+                locked.insert(key);
+            } else {
+                locked.remove(&key);
+            }
+        };
+        match self.lock_key(name) {
+            Some(key) => update(&mut self.scopes.locked, key),
+            None => {
+                if let Some(frame) = self.frames.last() {
+                    update(&mut frame.borrow_mut().locked, split_scope(name).1.to_string());
+                }
+            }
+        }
+    }
+
+    /// Give E741 when `lock` does not allow changing the value of `name`.
+    pub(crate) fn check_value_lock(&mut self, lock: VarLock, name: &str) -> Result<(), ()> {
+        lock.check(name).or_else(|msg| self.emsg(msg))
+    }
+
+    /// The text of register `name`; empty when it was not set.  An upper
+    /// case name refers to the lower case register.
+    pub fn register(&self, name: char) -> String {
+        let name = if name == '@' { '"' } else { name.to_ascii_lowercase() };
+        self.registers.get(&name).cloned().unwrap_or_default()
+    }
+
+    /// Set register `name`, an upper case name appends to the lower case
+    /// register.  Writing to the "_" register does nothing.
+    pub fn set_register(&mut self, name: char, text: &str) {
+        let reg = if name == '@' { '"' } else { name.to_ascii_lowercase() };
+        if reg == '_' {
+            return;
+        }
+        let contents = self.registers.entry(reg).or_default();
+        if name.is_ascii_uppercase() {
+            contents.push_str(text);
+        } else {
+            *contents = text.to_string();
+        }
+    }
+}
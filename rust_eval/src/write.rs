@@ -0,0 +1,121 @@
+//! `:write` in scripts: the buffer is written with rust_bufwrite, the
+//! autocommands for writing are executed by the evaluator.
+//!
+//! The options that say how the file is written ('fileformat', 'backup',
+//! 'backupcopy', 'key', etc.) are the values of the options in options.rs.
+
+use rust_autocmd::Event;
+use rust_bufwrite::{do_write, BackupCopy, FileOptions, WriteBuffer, WriteCmd, WriteSettings, WriteTarget};
+use rust_crypt::CryptMethod;
+use rust_fileio::FileFormat;
+
+use crate::{Evaluator, Value};
+
+/// The buffer of the evaluator as `:write` sees it.
+struct EvalBuffer<'a>(&'a mut Evaluator);
+
+impl WriteBuffer for EvalBuffer<'_> {
+    fn name(&self) -> &str {
+        self.0.buffer.name()
+    }
+
+    fn line_count(&self) -> usize {
+        self.0.buffer.line_count()
+    }
+
+    fn line(&self, lnum: usize) -> &str {
+        &self.0.buffer.lines()[lnum - 1]
+    }
+
+    fn apply_autocmds(&mut self, event: Event, fname: &str) -> bool {
+        // An error in an autocommand has been reported, it still counts as
+        // executed.
+        self.0.apply_autocmds_group(event, fname, false, None).unwrap_or(true)
+    }
+}
+
+impl Evaluator {
+    pub(crate) fn bool_option(&self, name: &str) -> bool {
+        matches!(self.get_option(name), Some(Value::Number(n)) if n != 0)
+    }
+
+    pub(crate) fn string_option(&self, name: &str) -> String {
+        self.get_option(name).map(|val| val.to_string()).unwrap_or_default()
+    }
+
+    /// The buffer options for writing.
+    fn file_options(&mut self) -> Result<FileOptions, ()> {
+        let cryptmethod = self.string_option("cryptmethod");
+        let Some(cryptmethod) = CryptMethod::from_name(&cryptmethod) else {
+            return self.emsg(format!("E474: Invalid argument: cryptmethod={}", cryptmethod));
+        };
+        Ok(FileOptions {
+            fileformat: FileFormat::from_name(&self.string_option("fileformat")).unwrap_or_default(),
+            fileencoding: self.string_option("fileencoding"),
+            bomb: self.bool_option("bomb"),
+            endofline: self.bool_option("endofline"),
+            fixendofline: self.bool_option("fixendofline"),
+            binary: self.bool_option("binary"),
+            cryptmethod,
+            key: self.crypt_key().unwrap_or_default().to_string(),
+        })
+    }
+
+    fn write_settings(&mut self) -> Result<WriteSettings, ()> {
+        let backupcopy = self.string_option("backupcopy");
+        let Some(backupcopy) = BackupCopy::parse(&backupcopy) else {
+            return self.emsg(format!("E474: Invalid argument: backupcopy={}", backupcopy));
+        };
+        // Without an extension the backup would be the file itself.
+        let backupext = self.string_option("backupext");
+        if backupext.is_empty() {
+            return self.emsg("E589: 'backupext' and 'patchmode' are equal".to_string());
+        }
+        Ok(WriteSettings {
+            backup: self.bool_option("backup"),
+            writebackup: self.bool_option("writebackup"),
+            backupcopy,
+            backupdir: self.string_option("backupdir"),
+            backupext,
+            shell: self.string_option("shell"),
+            shellcmdflag: self.string_option("shellcmdflag"),
+        })
+    }
+
+    /// Execute `:[range]write[!] [++opt] [>>] [file]` or `:[range]write
+    /// !cmd`.  Writing the whole buffer to its file resets 'modified'; a
+    /// buffer without a name gets the name of the file it is written to.
+    pub(crate) fn ex_write(&mut self, range: &str, arg: &str, bang: bool) -> Result<(), ()> {
+        let cmd = match WriteCmd::parse(arg) {
+            Ok(cmd) => cmd,
+            Err(msg) => return self.emsg(msg),
+        };
+        if cmd.target == WriteTarget::Buffer && !self.buftype().can_write() {
+            return self.emsg("E382: Cannot write, 'buftype' option is set".to_string());
+        }
+        let range = match self.parse_range(range)? {
+            Some((first, last, _)) if first < 1 || last < first || last > self.buffer.line_count() as i64 => {
+                return self.emsg("E16: Invalid range".to_string());
+            }
+            Some((first, last, _)) => Some((first as usize, last as usize)),
+            None => None,
+        };
+        let opts = self.file_options()?;
+        let settings = self.write_settings()?;
+        let written = match do_write(&mut EvalBuffer(self), range, &cmd, bang, &opts, &settings) {
+            Ok(written) => written,
+            Err(msg) => return self.emsg(msg),
+        };
+        for msg in written.messages {
+            self.message(msg);
+        }
+        if written.reset_modified {
+            if let (WriteTarget::File(name), true) = (&cmd.target, self.buffer.name().is_empty()) {
+                self.buffer.set_name(name);
+            }
+            let _ = self.set_option("modified", Value::Number(0));
+            self.buffer.undo.set_saved();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs;
+
+use rust_eval::Evaluator;
+
+mod common;
+use common::{echo, output};
+
+#[test]
+fn edit_arguments() {
+    let dir = tempfile::tempdir().unwrap();
+    let name = |file: &str| dir.path().join(file).display().to_string();
+    fs::write(name("a.txt"), "one\ntwo\n").unwrap();
+    fs::write(name("b.txt"), "three\r\n").unwrap();
+    let mut ev = Evaluator::new();
+    ev.arglist_mut().insert(0, vec![name("a.txt"), name("b.txt"), name("c.txt")]);
+    assert_eq!(echo(&mut ev, "argc()"), "3");
+
+    assert_eq!(output(&mut ev, "first"), [format!("\"{}\" 2L, 8B", name("a.txt"))]);
+    assert_eq!(ev.buffer().lines(), ["one", "two"]);
+    ev.do_cmdline("call setline(1, 'ONE')").unwrap();
+    assert_eq!(output(&mut ev, "next"), ["E37: No write since last change (add ! to override)"]);
+    assert_eq!(output(&mut ev, "next!"), [format!("\"{}\" [dos] 1L, 7B", name("b.txt"))]);
+    assert_eq!((echo(&mut ev, "&fileformat"), echo(&mut ev, "argidx()")), ("dos".to_string(), "1".to_string()));
+    assert_eq!(output(&mut ev, "n"), [format!("\"{}\" [New]", name("c.txt"))]);
+    assert_eq!(output(&mut ev, "n"), ["E165: Cannot go beyond last file"]);
+    assert_eq!(output(&mut ev, "args").len(), 1);
+
+    ev.do_cmdline("argdelete *c.txt").unwrap();
+    assert_eq!(echo(&mut ev, "argv()"), format!("['{}', '{}']", name("a.txt"), name("b.txt")));
+    assert_eq!(echo(&mut ev, "argv(5) .. argc(3)"), "-1");
+    ev.do_cmdline("let g:seen = []").unwrap();
+    ev.do_cmdline("argdo call add(g:seen, getline(1)) | let g:last = argidx()").unwrap();
+    assert_eq!(echo(&mut ev, "g:seen"), "['one', 'three']");
+    assert_eq!(echo(&mut ev, "g:last"), "1");
+
+    ev.do_cmdline("arglocal x.txt").unwrap();
+    assert_eq!((echo(&mut ev, "argc()"), echo(&mut ev, "argc(-1)")), ("1".to_string(), "2".to_string()));
+    assert_eq!(echo(&mut ev, "argv(0, -1)"), name("a.txt"));
+}
+
+#[test]
+fn leave_changed_buffer() {
+    let dir = tempfile::tempdir().unwrap();
+    let name = |file: &str| dir.path().join(file).display().to_string();
+    fs::write(name("a.txt"), "one\n").unwrap();
+    fs::write(name("b.txt"), "two\n").unwrap();
+    let mut ev = Evaluator::new();
+    ev.arglist_mut().insert(0, vec![name("a.txt"), name("b.txt")]);
+    ev.do_cmdline("first").unwrap();
+    ev.do_cmdline("call setline(1, 'changed')").unwrap();
+
+    // The list is only changed when the buffer can be left.
+    let arglocal = format!("arglocal {}", name("c.txt"));
+    assert_eq!(output(&mut ev, &arglocal), ["E37: No write since last change (add ! to override)"]);
+    assert_eq!((echo(&mut ev, "argc()"), echo(&mut ev, "argidx()")), ("2".to_string(), "0".to_string()));
+    assert_eq!(echo(&mut ev, "argc(-1)"), "2");
+
+    ev.do_cmdline("let &hidden = 1").unwrap();
+    ev.do_cmdline("next").unwrap();
+    assert_eq!((echo(&mut ev, "argidx()"), echo(&mut ev, "getline(1)")), ("1".to_string(), "two".to_string()));
+}
@@ -0,0 +1,79 @@
+use rust_eval::{Evaluator, Event};
+
+mod common;
+use common::{error, eval};
+
+#[test]
+fn events_and_patterns() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("let g:log = []").unwrap();
+    ev.do_cmdline("autocmd BufReadPost *.txt call add(g:log, 'txt ' . expand('<afile>'))").unwrap();
+    ev.do_cmdline("au BufRead *.{c,h} call add(g:log, 'c') | call add(g:log, expand('<amatch>'))").unwrap();
+    ev.do_cmdline("au FileType vim ++once call add(g:log, 'ft ' . expand('<abuf>'))").unwrap();
+
+    assert_eq!(ev.apply_autocmds(Event::BufReadPost, "/tmp/notes.txt"), Ok(true));
+    assert_eq!(ev.apply_autocmds(Event::BufReadPost, "dir/main.h"), Ok(true));
+    assert_eq!(ev.apply_autocmds(Event::BufReadPost, "notes.txt.orig"), Ok(false));
+    ev.do_cmdline("doautocmd FileType vim\ndoautocmd FileType vim").unwrap();
+    assert_eq!(eval(&mut ev, "g:log"), "['txt /tmp/notes.txt', 'c', 'dir/main.h', 'ft 1']");
+    assert_eq!(ev.output().last().unwrap(), "No matching autocommands: FileType vim");
+
+    ev.buffer_mut().set_name("other.txt");
+    ev.do_cmdline("let g:log = []\ndoautoall BufRead\nautocmd! BufRead *.txt\ndoautocmd BufRead").unwrap();
+    assert_eq!(eval(&mut ev, "g:log"), "['txt other.txt']");
+
+    assert_eq!(error(&mut ev, "autocmd NoSuchEvent * echo"), "E216: No such group or event: NoSuchEvent * echo");
+    assert_eq!(error(&mut ev, "doautocmd *"), "E217: Can't execute autocommands for ALL events");
+    assert_eq!(error(&mut ev, "echo expand('<amatch>')"), "E497: No autocommand match name to substitute for \"<amatch>\"");
+}
+
+#[test]
+fn groups_and_listing() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("let g:n = 0").unwrap();
+    ev.do_cmdline("augroup counting\nautocmd!\nautocmd User Count let g:n += 1\naugroup END").unwrap();
+    // Sourcing the group again does not define the autocommand twice.
+    ev.do_cmdline("augroup counting\nautocmd!\nautocmd User Count let g:n += 1\naugroup END").unwrap();
+    ev.do_cmdline("autocmd User Count let g:n += 10\nautocmd TextChanged <buffer> let g:n += 100").unwrap();
+    ev.do_cmdline("doautocmd User Count").unwrap();
+    assert_eq!(eval(&mut ev, "g:n"), "11");
+    ev.do_cmdline("doautocmd counting User Count").unwrap();
+    assert_eq!(eval(&mut ev, "g:n"), "12");
+    assert_eq!(ev.apply_autocmds(Event::TextChanged, ""), Ok(true));
+    assert_eq!(eval(&mut ev, "g:n"), "112");
+
+    let before = ev.output().len();
+    ev.do_cmdline("augroup\nautocmd counting User").unwrap();
+    assert_eq!(ev.output()[before..], ["counting", "--- Autocommands ---", "counting  User", "    Count     let g:n += 1"]);
+
+    ev.do_cmdline("augroup! counting\ndoautocmd User Count").unwrap();
+    assert_eq!(eval(&mut ev, "g:n"), "122");
+    assert_eq!(error(&mut ev, "augroup! counting"), "E367: No such group: \"counting\"");
+}
+
+#[test]
+fn nesting() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("let g:log = []").unwrap();
+    ev.do_cmdline("autocmd User Outer call add(g:log, 'outer') | doautocmd User Inner").unwrap();
+    ev.do_cmdline("autocmd User Inner call add(g:log, 'inner')").unwrap();
+    ev.do_cmdline("autocmd BufEnter * call add(g:log, 'enter')").unwrap();
+    ev.do_cmdline("autocmd BufLeave * call add(g:log, 'leave') | call Apply()").unwrap();
+    ev.do_cmdline("autocmd BufLeave * ++nested call add(g:log, 'nested') | call Trigger()").unwrap();
+    ev.do_cmdline("function Trigger()\ncall Apply()\nendfunction").unwrap();
+    ev.add_ex_command("Enter", 5, |ev, _| ev.apply_autocmds(Event::BufEnter, "x").map(|_| ()));
+    ev.do_cmdline("function Apply()\nEnter\nendfunction").unwrap();
+
+    // ":doautocmd" always applies autocommands.
+    ev.do_cmdline("doautocmd User Outer").unwrap();
+    assert_eq!(eval(&mut ev, "g:log"), "['outer', 'inner']");
+
+    // Other events only trigger autocommands from a nested one.
+    ev.do_cmdline("let g:log = []").unwrap();
+    ev.apply_autocmds(Event::BufLeave, "x").unwrap();
+    assert_eq!(eval(&mut ev, "g:log"), "['leave', 'nested', 'enter']");
+
+    ev.do_cmdline("autocmd User Loop ++nested doautocmd User Loop").unwrap();
+    assert_eq!(error(&mut ev, "doautocmd User Loop"), "E218: Autocommand nesting too deep");
+    assert!(!ev.autocmds().is_busy());
+}
@@ -0,0 +1,53 @@
+use std::fs;
+
+use rust_eval::Evaluator;
+
+mod common;
+use common::{echo, output};
+
+#[test]
+fn buffer_list() {
+    let dir = tempfile::tempdir().unwrap();
+    let name = |file: &str| dir.path().join(file).display().to_string();
+    fs::write(name("a.txt"), "one\ntwo\n").unwrap();
+    fs::write(name("b.txt"), "three\n").unwrap();
+    let mut ev = Evaluator::new();
+    assert_eq!((echo(&mut ev, "bufnr()"), echo(&mut ev, "bufname()")), ("1".to_string(), String::new()));
+
+    // The empty first buffer is reused for the first file.
+    ev.do_cmdline(&format!("args {} {}", name("a.txt"), name("b.txt"))).unwrap();
+    assert_eq!(echo(&mut ev, "bufnr('%')"), "1");
+    ev.buffer_mut().set_cursor(2, 1);
+    ev.do_cmdline("next").unwrap();
+    assert_eq!(echo(&mut ev, "[bufnr(), bufnr('#'), bufnr('a.txt'), bufnr('nothing')]"), "[2, 1, 1, -1]");
+    assert_eq!(echo(&mut ev, "[bufloaded(1), bufloaded(2), buflisted('b.txt'), bufexists(3)]"), "[0, 1, 1, 0]");
+    assert_eq!(echo(&mut ev, "bufnr('new.txt', 1)"), "3");
+    assert_eq!(echo(&mut ev, "[bufexists(3), buflisted(3), bufadd('new.txt'), bufname(3)]"), "[1, 0, 3, 'new.txt']");
+    let ls = output(&mut ev, "ls");
+    assert_eq!(ls.len(), 2);
+    assert!(ls[0].starts_with("  1 #    \"") && ls[0].ends_with(" line 2"), "{}", ls[0]);
+    assert!(ls[1].starts_with("  2 %a   \""), "{}", ls[1]);
+    assert_eq!(output(&mut ev, "ls! u").len(), 1);
+
+    let info = "map(getbufinfo({'buflisted': 1}), '[v:val.bufnr, v:val.loaded, v:val.lnum, v:val.windows]')";
+    assert_eq!(echo(&mut ev, info), "[[1, 0, 2, []], [2, 1, 1, [1000]]]");
+    assert_eq!(echo(&mut ev, "getbufinfo(9)"), "[]");
+
+    ev.do_cmdline("call setline(1, 'THREE')").unwrap();
+    assert_eq!(output(&mut ev, "bdelete"), ["E89: No write since last change for buffer 2 (add ! to override)"]);
+    ev.do_cmdline("bdelete!").unwrap();
+    assert_eq!(echo(&mut ev, "[bufnr(), buflisted(2), line('.')]"), "[1, 0, 2]");
+    assert_eq!(ev.buffer().lines(), ["one", "two"]);
+    assert_eq!(output(&mut ev, "bwipeout 2 3"), ["2 buffers wiped out"]);
+    assert_eq!(output(&mut ev, "bunload"), ["E90: Cannot unload last buffer"]);
+
+    ev.do_cmdline("let &buftype = 'nofile' | let &bufhidden = 'wipe'").unwrap();
+    assert_eq!(echo(&mut ev, "[&buftype, &bufhidden, &buflisted]"), "['nofile', 'wipe', 1]");
+    assert_eq!(output(&mut ev, "let &bt = 'bogus'"), ["E474: Invalid argument: buftype=bogus"]);
+    assert_eq!(output(&mut ev, "write"), ["E382: Cannot write, 'buftype' option is set"]);
+    ev.do_cmdline(&format!("badd {}", name("b.txt"))).unwrap();
+    // 'bufhidden' "wipe" turns the delete into a wipe out.
+    ev.do_cmdline("bdelete").unwrap();
+    assert_eq!((echo(&mut ev, "bufnr()"), echo(&mut ev, "bufexists(1)")), ("4".to_string(), "0".to_string()));
+    assert_eq!(ev.buffer().lines(), ["three"]);
+}
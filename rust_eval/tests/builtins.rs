@@ -0,0 +1,175 @@
+use rust_eval::{Evaluator, Value};
+
+mod common;
+use common::{error, eval};
+
+#[test]
+fn argument_count_is_checked() {
+    let mut ev = Evaluator::new();
+    assert_eq!(error(&mut ev, "echo strlen()"), "E119: Not enough arguments for function: strlen");
+    assert_eq!(error(&mut ev, "echo tolower('a', 'b')"), "E118: Too many arguments for function: tolower");
+    assert_eq!(error(&mut ev, "echo nosuchfunc()"), "E117: Unknown function: nosuchfunc");
+    assert_eq!(error(&mut ev, "echo strlen([])"), "E730: Using a List as a String");
+    assert_eq!(error(&mut ev, "echo and(1.5, 1)"), "E805: Using a Float as a Number");
+}
+
+#[test]
+fn string_functions() {
+    let mut ev = Evaluator::new();
+    assert_eq!(eval(&mut ev, "substitute('aaa', 'a', 'b', '')"), "baa");
+    assert_eq!(eval(&mut ev, "substitute('aaa', 'a', 'b', 'g')"), "bbb");
+    assert_eq!(eval(&mut ev, r"substitute('foo bar', '\(\w\+\) \(\w\+\)', '\2 \u\1', '')"), "bar Foo");
+    assert_eq!(eval(&mut ev, r"substitute('abc', '\zs', '-', 'g')"), "-a-b-c");
+    assert_eq!(eval(&mut ev, "substitute('abc', 'b', {m -> toupper(get(m, 0))}, '')"), "aBc");
+    assert_eq!(eval(&mut ev, r"substitute('abc', 'b', '\=toupper(submatch(0))', '')"), "aBc");
+    assert_eq!(eval(&mut ev, r"substitute('a1b2', '\(\a\)\(\d\)', '\=submatch(2) . submatch(1)', 'g')"), "1a2b");
+    assert_eq!(eval(&mut ev, r"substitute('ab', 'b', '\=[1, submatch(0, 1)]', '')"), "a1\n['b']");
+    assert_eq!(eval(&mut ev, "[submatch(0), submatch(1, 1)]"), "['', []]");
+    assert_eq!(error(&mut ev, "echo submatch(10)"), "E935: Invalid submatch number: 10");
+    assert_eq!(eval(&mut ev, "matchstr('testing', 'ing')"), "ing");
+    assert_eq!(eval(&mut ev, "match('testing', 'ing')"), "4");
+    assert_eq!(eval(&mut ev, "matchend('testing', 'ing')"), "7");
+    assert_eq!(eval(&mut ev, "match('testing', 't', 0, 2)"), "3");
+    assert_eq!(eval(&mut ev, "match(['a', 'b', 'c'], 'c')"), "2");
+    assert_eq!(eval(&mut ev, "split('  a b  c ')"), "['a', 'b', 'c']");
+    assert_eq!(eval(&mut ev, "split(',a,b,,c,', ',')"), "['a', 'b', '', 'c']");
+    assert_eq!(eval(&mut ev, "split(',a,,b', ',', 1)"), "['', 'a', '', 'b']");
+    assert_eq!(eval(&mut ev, r"split('abc', '\zs')"), "['a', 'b', 'c']");
+    assert_eq!(eval(&mut ev, "join([1, 'a', [2]], '-')"), "1-a-[2]");
+    assert_eq!(eval(&mut ev, "trim('  x  ')"), "x");
+    assert_eq!(eval(&mut ev, "trim('xxaxx', 'x', 1)"), "axx");
+    assert_eq!(eval(&mut ev, "tolower('ABC') . toupper('def')"), "abcDEF");
+    assert_eq!(eval(&mut ev, "strcharpart('äbc', 1, 1)"), "b");
+    assert_eq!(eval(&mut ev, "strpart('abcdef', 0 - 2, 4)"), "ab");
+    assert_eq!(eval(&mut ev, "strlen('ä') . strchars('ä')"), "21");
+    assert_eq!(eval(&mut ev, "stridx('an apple', 'p')"), "4");
+    assert_eq!(eval(&mut ev, r"escape('a.b', '.')"), r"a\.b");
+    assert_eq!(eval(&mut ev, "string([1, 'a'])"), "[1, 'a']");
+}
+
+#[test]
+fn printf_formats() {
+    let mut ev = Evaluator::new();
+    assert_eq!(eval(&mut ev, "printf('%d-%s', 12, 'x')"), "12-x");
+    assert_eq!(eval(&mut ev, "printf('%5d|%-5d|%05d', 1, 2, 0 - 3)"), "    1|2    |-0003");
+    assert_eq!(eval(&mut ev, "printf('%x %X %#o %b', 255, 255, 8, 5)"), "ff FF 010 101");
+    assert_eq!(eval(&mut ev, "printf('%.2f %e %g', 3.14159, 1234.5, 0.0001)"), "3.14 1.234500e+03 0.0001");
+    assert_eq!(eval(&mut ev, "printf('%*s|%.2s', 4, 'ab', 'xyz')"), "  ab|xy");
+    assert_eq!(eval(&mut ev, "printf('%c%%', 65)"), "A%");
+    assert_eq!(error(&mut ev, "echo printf('%d %d', 1)"), "E766: Insufficient arguments for printf()");
+    assert_eq!(error(&mut ev, "echo printf('%d', 1, 2)"), "E767: Too many arguments for printf()");
+}
+
+#[test]
+fn list_and_dict_functions() {
+    let mut ev = Evaluator::new();
+    assert_eq!(eval(&mut ev, "map([1, 2], 'v:val * 2')"), "[2, 4]");
+    assert_eq!(eval(&mut ev, "filter(['a', 'b'], 'v:key == 1')"), "['b']");
+    assert_eq!(eval(&mut ev, "sort(['b', 'A', 'c'], 'i')"), "['A', 'b', 'c']");
+    assert_eq!(eval(&mut ev, "sort([3, 1.5, 2], 'f')"), "[1.5, 2, 3]");
+    assert_eq!(eval(&mut ev, "uniq([1, 1, 2, 1])"), "[1, 2, 1]");
+    assert_eq!(eval(&mut ev, "reduce([1, 2, 3], {acc, v -> acc + v})"), "6");
+    assert_eq!(eval(&mut ev, "reduce([], {acc, v -> acc + v}, 10)"), "10");
+    assert_eq!(eval(&mut ev, "flatten([1, [2, [3]]])"), "[1, 2, 3]");
+    assert_eq!(eval(&mut ev, "flatten([1, [2, [3]]], 1)"), "[1, 2, [3]]");
+    assert_eq!(eval(&mut ev, "extend([1, 2], [3], 1)"), "[1, 3, 2]");
+    assert_eq!(eval(&mut ev, "add([1], 2)"), "[1, 2]");
+    assert_eq!(eval(&mut ev, "insert([1], 0)"), "[0, 1]");
+    assert_eq!(eval(&mut ev, "remove([1, 2, 3], 0, 1)"), "[1, 2]");
+    assert_eq!(eval(&mut ev, "index([1, 2, 3], 3)"), "2");
+    assert_eq!(eval(&mut ev, "count([1, 2, 1], 1)"), "2");
+    assert_eq!(eval(&mut ev, "range(3)"), "[0, 1, 2]");
+    assert_eq!(eval(&mut ev, "range(5, 1, 0 - 2)"), "[5, 3, 1]");
+    assert_eq!(eval(&mut ev, "reverse([1, 2])"), "[2, 1]");
+    assert_eq!(eval(&mut ev, "max([3, 9, 2]) . min([3, 9, 2])"), "92");
+    assert_eq!(eval(&mut ev, "empty([]) . empty('x')"), "10");
+
+    ev.set_var("d", Value::from_json(r#"{"a": 1, "b": 2}"#).unwrap());
+    assert_eq!(eval(&mut ev, "keys(d)"), "['a', 'b']");
+    assert_eq!(eval(&mut ev, "items(d)"), "[['a', 1], ['b', 2]]");
+    assert_eq!(eval(&mut ev, "has_key(d, 'a')"), "1");
+    assert_eq!(eval(&mut ev, "extend(d, json_decode('{\"a\": 5}'), 'keep')"), "{'a': 1, 'b': 2}");
+
+    assert_eq!(error(&mut ev, "echo reduce([], {a, b -> a})"), "E998: Reduce of an empty List with no initial value");
+    assert_eq!(error(&mut ev, "echo flatten([], 0 - 1)"), "E900: maxdepth must be non-negative number");
+    assert_eq!(error(&mut ev, "echo extend(d, d, 'error')"), "E737: Key already exists: a");
+    assert_eq!(error(&mut ev, "echo range(1, 2, 0)"), "E726: Stride is zero");
+    assert_eq!(error(&mut ev, "echo remove([], 0)"), "E684: List index out of range: 0");
+    assert_eq!(error(&mut ev, "echo keys([])"), "E715: Dictionary required");
+
+    ev.set_var("l", Value::new_list(vec![Value::Number(1)]));
+    ev.get_var("l").unwrap().lock_items(1, true);
+    assert_eq!(error(&mut ev, "call add(l, 2)"), "E741: Value is locked: add() argument");
+}
+
+#[test]
+fn type_and_number_functions() {
+    let mut ev = Evaluator::new();
+    assert_eq!(eval(&mut ev, "type(1) . type('') . type([]) . type(1.0)"), "0135");
+    assert_eq!(eval(&mut ev, "typename([1, 2])"), "list<number>");
+    assert_eq!(eval(&mut ev, "str2nr('  0x1f', 16)"), "31");
+    assert_eq!(eval(&mut ev, "str2nr('-12abc')"), "-12");
+    assert_eq!(eval(&mut ev, "str2float('1.5e2x')"), "150.0");
+    assert_eq!(eval(&mut ev, "float2nr(3.9) . abs(0 - 2)"), "32");
+    assert_eq!(eval(&mut ev, "floor(2.5) . ceil(2.5) . round(2.5)"), "2.03.03.0");
+    assert_eq!(eval(&mut ev, "pow(2, 10)"), "1024.0");
+    assert_eq!(eval(&mut ev, "and(12, 10) . or(12, 10) . xor(12, 10)"), "8146");
+    assert_eq!(eval(&mut ev, "json_encode([1, 'a', json_decode('{\"k\": null}')])"), r#"[1,"a",{"k":null}]"#);
+    assert_eq!(error(&mut ev, "echo json_decode('[1,')"), "E491: JSON decode error at '[1,'");
+}
+
+#[test]
+fn special_values() {
+    let mut ev = Evaluator::new();
+    assert_eq!(eval(&mut ev, "[type(v:true), type(v:false), type(v:null), type(v:none)]"), "[6, 6, 7, 7]");
+    assert_eq!(eval(&mut ev, "json_encode([v:true, v:false, v:null, v:none])"), "[true,false,null,null]");
+    let strings = "['v:true', 'v:false', 'v:null', 'v:none']";
+    assert_eq!(eval(&mut ev, "map([v:true, v:false, v:null, v:none], 'string(v:val)')"), strings);
+    assert_eq!(eval(&mut ev, "json_decode('[true, null]') == [v:true, v:null]"), "1");
+    assert_eq!(eval(&mut ev, "[v:true + 1, v:false ? 'y' : 'n', typename(v:none)]"), "[2, 'n', 'none']");
+    assert_eq!(error(&mut ev, "let v:true = 0"), "E46: Cannot change read-only variable \"v:true\"");
+}
+
+#[test]
+fn exists_and_has() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("let g:list = [1]\nlet g:dict = {'key': 1}\nfunction Func()\nendfunction").unwrap();
+    ev.do_cmdline("command Cmd echo\ncommand Cmd2 echo\naugroup Group\nautocmd BufRead *.c echo\naugroup END").unwrap();
+    let exists = |ev: &mut Evaluator, names: &str| eval(ev, &format!("map({}, 'exists(v:val)')", names));
+    let vars = "['g:list', 'list[0]', 'list[1]', 'dict.key', 'dict.nope', 'nope', 'Func()', 'v:true']";
+    assert_eq!(exists(&mut ev, vars), "[1, 1, 0, 1, 0, 0, 0, 1]");
+    assert_eq!(exists(&mut ev, "['&ts', '&l:ts', '+nope', '$PATH', '$NO_SUCH_VAR']"), "[1, 1, 0, 1, 0]");
+    assert_eq!(exists(&mut ev, "['*strlen', '*Func', '*Nope']"), "[1, 1, 0]");
+    assert_eq!(exists(&mut ev, "[':echo', ':ec', ':Cmd', ':Cmd2', ':Cm', ':Nope']"), "[2, 1, 2, 2, 3, 0]");
+    let autocmds = "['#Group', '#BufRead', '#Group#BufRead', '#Group#BufRead#*.c', '#BufRead#*.h', '#BufNewFile']";
+    assert_eq!(exists(&mut ev, autocmds), "[1, 1, 1, 1, 0, 0]");
+    assert_eq!(exists(&mut ev, "['##BufNewFile', '##Nope']"), "[1, 0]");
+    // Errors while looking up a variable are not reported.
+    assert_eq!(error(&mut ev, "echo exists('list[x]')"), "0");
+
+    let has = |ev: &mut Evaluator, names: &str| eval(ev, &format!("map({}, 'has(v:val)')", names));
+    assert_eq!(has(&mut ev, "['eval', 'TIMERS', 'gui_running', 'nope']"), "[1, 1, 0, 0]");
+    assert_eq!(eval(&mut ev, "[has('gui_running', 1), has('nope', 1)]"), "[1, 0]");
+    assert_eq!(has(&mut ev, "['patch-8.2.1', 'patch-9.1.0', 'patch-9.1.1', 'patch-9.1']"), "[1, 1, 0, 0]");
+}
+
+#[test]
+fn buffer_functions() {
+    let mut ev = Evaluator::new();
+    ev.buffer_mut().set_lines(vec!["one".to_string(), "two".to_string()]);
+    ev.buffer_mut().set_cursor(2, 2);
+    assert_eq!(eval(&mut ev, "getline(1)"), "one");
+    assert_eq!(eval(&mut ev, "getline(1, '$')"), "['one', 'two']");
+    assert_eq!(eval(&mut ev, "getline(5)"), "");
+    assert_eq!(eval(&mut ev, "line('.') . line('$') . col('.') . col('$')"), "2224");
+    assert_eq!(eval(&mut ev, "col([1, '$'])"), "4");
+
+    assert_eq!(eval(&mut ev, "setline(2, ['TWO', 'three'])"), "0");
+    assert_eq!(eval(&mut ev, "append(0, 'zero')"), "0");
+    assert_eq!(eval(&mut ev, "append(9, 'x')"), "1");
+    assert_eq!(ev.buffer().lines(), ["zero", "one", "TWO", "three"]);
+    assert_eq!(eval(&mut ev, "line('.')"), "3");
+
+    ev.buffer_mut().set_mark('a', 4, 1);
+    assert_eq!(eval(&mut ev, "line(\"'a\") . line(\"'b\")"), "40");
+}
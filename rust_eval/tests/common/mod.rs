@@ -0,0 +1,27 @@
+//! Helpers shared by the tests of the evaluator.  Each test uses some of
+//! them.
+#![allow(dead_code)]
+
+use rust_eval::Evaluator;
+
+/// The value of `expr` as a string, panics when it fails.
+pub fn eval(ev: &mut Evaluator, expr: &str) -> String {
+    ev.eval_expr(expr).unwrap_or_else(|()| panic!("{}", expr)).to_string()
+}
+
+/// The messages `cmd` gives.
+pub fn output(ev: &mut Evaluator, cmd: &str) -> Vec<String> {
+    let before = ev.output().len();
+    let _ = ev.do_cmdline(cmd);
+    ev.output()[before..].to_vec()
+}
+
+/// The error message `cmd` gives.
+pub fn error(ev: &mut Evaluator, cmd: &str) -> String {
+    output(ev, cmd).into_iter().next().unwrap_or_default()
+}
+
+/// What `:echo {expr}` shows.
+pub fn echo(ev: &mut Evaluator, expr: &str) -> String {
+    output(ev, &format!("echo {}", expr)).join("\n")
+}
@@ -0,0 +1,113 @@
+use rust_eval::{Evaluator, Value};
+
+mod common;
+use common::{error, eval};
+
+#[test]
+fn precedence_and_logic() {
+    let mut ev = Evaluator::new();
+    assert_eq!(eval(&mut ev, "1 + 2 * 3 - 4 / 2"), "5");
+    assert_eq!(eval(&mut ev, "7 % 3 . 'x'"), "1x");
+    assert_eq!(eval(&mut ev, "'a' . 1 + 2"), "2");
+    assert_eq!(eval(&mut ev, "-2 * -3"), "6");
+    assert_eq!(eval(&mut ev, "!0 . !5 . !''"), "101");
+    assert_eq!(eval(&mut ev, "1 < 2 && 2 < 1 || 3"), "1");
+    assert_eq!(eval(&mut ev, "0 && undefined"), "0");
+    assert_eq!(eval(&mut ev, "1 || undefined"), "1");
+    assert_eq!(eval(&mut ev, "1 ? 'a' : 0 ? 'b' : 'c'"), "a");
+    assert_eq!(eval(&mut ev, "0 ? 'a' : 0 ? 'b' : 'c'"), "c");
+    assert_eq!(eval(&mut ev, "'' ?? 'default'"), "default");
+    assert_eq!(eval(&mut ev, "[1] ?? 'default'"), "[1]");
+    assert_eq!(eval(&mut ev, "'3' + 4"), "7");
+    assert_eq!(eval(&mut ev, "1.5 + 1"), "2.5");
+    assert_eq!(eval(&mut ev, "0x1F + 0b11 + 017 + 0o10"), "57");
+    assert_eq!(eval(&mut ev, "1.5e2"), "150.0");
+    assert_eq!(eval(&mut ev, "-1->abs()"), "1");
+    assert_eq!(error(&mut ev, "echo 1.5 % 2"), "E804: Cannot use '%' with Float");
+}
+
+#[test]
+fn comparisons() {
+    let mut ev = Evaluator::new();
+    assert_eq!(eval(&mut ev, "'abc' ==# 'ABC'"), "0");
+    assert_eq!(eval(&mut ev, "'abc' ==? 'ABC'"), "1");
+    assert_eq!(eval(&mut ev, "'abc' == 'ABC'"), "0");
+    ev.do_cmdline("let &ic = 1").unwrap();
+    assert_eq!(eval(&mut ev, "'abc' == 'ABC'"), "1");
+    assert_eq!(eval(&mut ev, "'abc' =~ 'B'"), "1");
+    assert_eq!(eval(&mut ev, "'abc' =~# 'B'"), "0");
+    assert_eq!(eval(&mut ev, "'abc' !~ '^x'"), "1");
+    assert_eq!(eval(&mut ev, "('10' < '9') . (10 > 9)"), "11");
+    assert_eq!(eval(&mut ev, "'10' == 10"), "1");
+    assert_eq!(eval(&mut ev, "'10' is 10"), "0");
+
+    ev.set_var("l", Value::new_list(vec![Value::Number(1)]));
+    assert_eq!(eval(&mut ev, "l is l"), "1");
+    assert_eq!(eval(&mut ev, "l is copy(l)"), "0");
+    assert_eq!(eval(&mut ev, "l == copy(l)"), "1");
+    assert_eq!(eval(&mut ev, "l isnot copy(l)"), "1");
+    assert_eq!(error(&mut ev, "echo l == 1"), "E691: Can only compare List with List");
+    assert_eq!(error(&mut ev, "echo l < l"), "E692: Invalid operation for List");
+}
+
+#[test]
+fn literals() {
+    let mut ev = Evaluator::new();
+    assert_eq!(eval(&mut ev, r#""a\tb\n" == "a\x09b\u000a""#), "1");
+    assert_eq!(eval(&mut ev, r#""\101\"\\""#), r#"A"\"#);
+    assert_eq!(eval(&mut ev, "{'a': 1, 'b': [2]}"), "{'a': 1, 'b': [2]}");
+    assert_eq!(eval(&mut ev, "#{one: 1, two-x: 2}"), "{'one': 1, 'two-x': 2}");
+    assert_eq!(eval(&mut ev, "{}"), "{}");
+    assert_eq!(eval(&mut ev, "{x -> x + 1}(1)"), "2");
+    assert_eq!(eval(&mut ev, "0z00FF.10"), "0z00FF10");
+    assert_eq!(eval(&mut ev, "0z01 + 0z02"), "0z0102");
+    ev.set_var("n", Value::Number(3));
+    assert_eq!(eval(&mut ev, r#"$"n={n} list={[n, 'x']} {{}}""#), "n=3 list=[3, 'x'] {}");
+    assert_eq!(eval(&mut ev, "$'it''s {n + 1}'"), "it's 4");
+    assert_eq!(error(&mut ev, "echo {'a': 1, 'a': 2}"), "E721: Duplicate key in Dictionary: \"a\"");
+}
+
+#[test]
+fn indexing_and_methods() {
+    let mut ev = Evaluator::new();
+    ev.set_var("l", Value::from_json("[1, 2, 3, 4]").unwrap());
+    ev.set_var("d", Value::from_json(r#"{"key": {"sub": [5]}}"#).unwrap());
+    assert_eq!(eval(&mut ev, "l[0] . l[-1]"), "14");
+    assert_eq!(eval(&mut ev, "l[1:2]"), "[2, 3]");
+    assert_eq!(eval(&mut ev, "l[:1]"), "[1, 2]");
+    assert_eq!(eval(&mut ev, "l[-2:]"), "[3, 4]");
+    assert_eq!(eval(&mut ev, "l[3:1]"), "[]");
+    assert_eq!(eval(&mut ev, "'abcdef'[1:3] . 'xyz'[0]"), "bcdx");
+    assert_eq!(eval(&mut ev, "'abc'[5] . 'abc'[1:9]"), "bc");
+    assert_eq!(eval(&mut ev, "d.key.sub[0]"), "5");
+    assert_eq!(eval(&mut ev, "d['key']['sub']"), "[5]");
+    assert_eq!(eval(&mut ev, "0z0102[1]"), "2");
+    assert_eq!(eval(&mut ev, "l->len()"), "4");
+    assert_eq!(eval(&mut ev, "l->copy()->map('v:val * 2')->join(',')"), "2,4,6,8");
+    assert_eq!(eval(&mut ev, "'x'->{s -> s . s}()"), "xx");
+    assert_eq!(eval(&mut ev, "[[1, 2]][0][1]"), "2");
+    assert_eq!(error(&mut ev, "echo l[9]"), "E684: List index out of range: 9");
+    assert_eq!(error(&mut ev, "echo d.nokey"), "E716: Key not present in Dictionary: \"nokey\"");
+    assert_eq!(error(&mut ev, "echo d[1:2]"), "E719: Cannot slice a Dictionary");
+
+    ev.do_cmdline("let g:obj = {'n': 2}\nfunction g:obj.twice() dict\nreturn self.n * 2\nendfunction").unwrap();
+    assert_eq!(eval(&mut ev, "g:obj.twice()"), "4");
+}
+
+#[test]
+fn environment_options_and_registers() {
+    let mut ev = Evaluator::new();
+    std::env::set_var("RUST_EVAL_TEST", "value");
+    assert_eq!(eval(&mut ev, "$RUST_EVAL_TEST"), "value");
+    assert_eq!(eval(&mut ev, "&tw . &textwidth . &l:ts"), "008");
+    ev.do_cmdline("let &tw = 78\nlet &sw += 2\nlet &ft = 'vim'").unwrap();
+    assert_eq!(eval(&mut ev, "&textwidth . &g:shiftwidth . &filetype"), "7810vim");
+    assert_eq!(ev.get_option("tw"), Some(Value::Number(78)));
+    assert_eq!(error(&mut ev, "echo &nosuch"), "E113: Unknown option: nosuch");
+
+    ev.do_cmdline("let @a = 'one'\nlet @A = 'two'").unwrap();
+    assert_eq!(eval(&mut ev, "@a"), "onetwo");
+    assert_eq!(eval(&mut ev, "@b"), "");
+    assert_eq!(ev.register('a'), "onetwo");
+    assert_eq!(error(&mut ev, "let @: = 'x'"), "E354: Invalid register name: ':'");
+}
@@ -0,0 +1,83 @@
+use rust_core::gc;
+use rust_eval::Evaluator;
+
+fn output(ev: &Evaluator) -> Vec<String> {
+    ev.output().to_vec()
+}
+
+#[test]
+fn list_cycle() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("let g:l = [1]\nlet g:l += [g:l]\ncall test_garbagecollect_now()\necho g:l").unwrap();
+    assert_eq!(output(&ev), ["[1, [...]]"]);
+    assert_eq!(gc::tracked(), 1);
+
+    // A value held outside of the evaluator is kept.
+    let held = ev.get_var("l").unwrap();
+    ev.do_cmdline("unlet g:l\ncall test_garbagecollect_now()").unwrap();
+    assert_eq!(held.to_string(), "[1, [...]]");
+
+    drop(held);
+    assert_eq!(gc::tracked(), 1);
+    assert_eq!(ev.garbage_collect(), 1);
+    assert_eq!(gc::tracked(), 0);
+}
+
+#[test]
+fn closure_cycle() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline(
+        r#"
+        function Counter()
+          let n = 0
+          let Inc = {-> n + 1}
+          return Inc
+        endfunction
+        function Leak()
+          let x = 5
+          let Get = {-> x}
+          return Get()
+        endfunction
+        let g:Inc = Counter()
+        let g:r = Leak()
+        "#,
+    )
+    .unwrap();
+    // The scope of Leak() contains a lambda that refers to the scope.
+    let before = gc::tracked();
+    ev.do_cmdline("call test_garbagecollect_now()").unwrap();
+    assert!(gc::tracked() < before);
+    assert!(ev.function_exists("<lambda>1"));
+    assert!(!ev.function_exists("<lambda>2"));
+    ev.do_cmdline("echo g:Inc() g:r").unwrap();
+    assert_eq!(output(&ev), ["1 5"]);
+}
+
+#[test]
+fn garbagecollect_waits_for_command() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline(
+        r#"
+        function Collect()
+          let l = []
+          let l += [l]
+          call garbagecollect()
+          return 1
+        endfunction
+        "#,
+    )
+    .unwrap();
+    let before = gc::tracked();
+    ev.do_cmdline("call Collect()").unwrap();
+    assert_eq!(gc::tracked(), before);
+}
+
+#[test]
+fn threshold() {
+    let mut ev = Evaluator::new();
+    gc::set_threshold(10);
+    for _ in 0..50 {
+        ev.do_cmdline("let c = []\nlet c += [c]").unwrap();
+    }
+    assert!(gc::tracked() <= 10, "{}", gc::tracked());
+}
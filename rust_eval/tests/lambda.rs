@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+
+use rust_eval::{call_function_rs, set_variable_rs, typval_T, Evaluator, ValUnion, Value, Vartype};
+
+mod common;
+use common::eval;
+
+#[test]
+fn legacy_and_vim9_lambdas() {
+    let mut ev = Evaluator::new();
+    assert_eq!(ev.eval_expr("{x -> x * 2}(21)").unwrap(), Value::Number(42));
+    assert_eq!(ev.eval_expr("{-> 7}()").unwrap(), Value::Number(7));
+    ev.eval_script("let F = (x) => x * 2\nlet G = (a: number, b: number): number => a + b").unwrap();
+    assert_eq!(ev.eval_expr("F(4)").unwrap(), Value::Number(8));
+    assert_eq!(ev.eval_expr("G(1, 2)").unwrap(), Value::Number(3));
+    assert!(eval(&mut ev, "F").starts_with("<lambda>"));
+    // Legacy lambdas ignore extra arguments, Vim9 lambdas do not.
+    assert_eq!(ev.eval_expr("{x -> x}(1, 2)").unwrap(), Value::Number(1));
+    assert!(ev.eval_expr("F(1, 2)").is_err());
+    assert!(ev.eval_expr("G(1)").is_err());
+}
+
+#[test]
+fn closures_keep_their_scope() {
+    let mut ev = Evaluator::new();
+    ev.eval_script("let Adder = {n -> {x -> x + n}}\nlet Add5 = Adder(5)\nlet Add7 = Adder(7)").unwrap();
+    assert_eq!(ev.eval_expr("Add5(1)").unwrap(), Value::Number(6));
+    assert_eq!(ev.eval_expr("Add7(1)").unwrap(), Value::Number(8));
+
+    // Global variables are looked up when the lambda is called.
+    ev.eval_script("let n = 1\nlet N = {-> n}\nlet n = 2").unwrap();
+    assert_eq!(ev.eval_expr("N()").unwrap(), Value::Number(2));
+
+    // A lambda referring to itself runs into 'maxfuncdepth'.
+    ev.eval_script("let R = {x -> R(x)}").unwrap();
+    assert!(ev.eval_expr("R(1)").is_err());
+}
+
+#[test]
+fn function_partials_and_call() {
+    let mut ev = Evaluator::new();
+    ev.eval_script("let P = function('add', [10])").unwrap();
+    assert_eq!(ev.eval_expr("P(5)").unwrap(), Value::Number(15));
+    assert_eq!(eval(&mut ev, "P"), "function('add', [10])");
+    assert_eq!(ev.eval_expr("call(P, [1])").unwrap(), Value::Number(11));
+    assert_eq!(ev.eval_expr("call('add', [1, 2])").unwrap(), Value::Number(3));
+    assert_eq!(ev.eval_expr("function(P, [1])()").unwrap(), Value::Number(11));
+    assert!(ev.eval_expr("function('NoSuchFunc')").is_err());
+    assert!(ev.eval_expr("funcref('NoSuchFunc')").is_err());
+
+    let mut d = BTreeMap::new();
+    d.insert("name".to_string(), Value::Str("vim".to_string()));
+    ev.set_var("d", Value::new_dict(d));
+    ev.eval_script("let Name = function({-> get(self, 'name')}, d)").unwrap();
+    assert_eq!(eval(&mut ev, "Name()"), "vim");
+    assert_eq!(eval(&mut ev, "call({-> get(self, 'name', 'none')}, [], d)"), "vim");
+
+    ev.eval_script("let L = {x -> x + 1}\nlet R = funcref(L)").unwrap();
+    assert_eq!(ev.eval_expr("R(1)").unwrap(), Value::Number(2));
+    assert_eq!(ev.eval_expr("R == L").unwrap(), Value::Number(1));
+}
+
+#[test]
+fn map_filter_and_sort_call_funcrefs() {
+    let mut ev = Evaluator::new();
+    assert_eq!(eval(&mut ev, "map([1, 2, 3], {i, v -> v * 10})"), "[10, 20, 30]");
+    assert_eq!(eval(&mut ev, "map(['a', 'b'], (i, v) => v . i)"), "['a0', 'b1']");
+    assert_eq!(eval(&mut ev, "filter([1, 2, 3, 4], {_, v -> v > 2})"), "[3, 4]");
+    assert_eq!(eval(&mut ev, "sort([3, 1, 2], {a, b -> a - b})"), "[1, 2, 3]");
+    assert_eq!(eval(&mut ev, "sort([3, 1, 2], {a, b -> b - a})"), "[3, 2, 1]");
+    assert_eq!(eval(&mut ev, "sort([10, 9, 100], 'n')"), "[9, 10, 100]");
+    assert_eq!(eval(&mut ev, "sort(['b', 'c', 'a'])"), "['a', 'b', 'c']");
+
+    // map() changes the list in place, a closure sees the change.
+    ev.eval_script("let l = [1, 2]\nlet Len = {-> len(l)}\nlet m = map(l, {i, v -> [v]})").unwrap();
+    assert_eq!(eval(&mut ev, "l"), "[[1], [2]]");
+    assert_eq!(ev.eval_expr("m == l").unwrap(), Value::Number(1));
+    assert_eq!(ev.eval_expr("filter(l, {-> 0})").unwrap().to_string(), "[]");
+    assert_eq!(ev.eval_expr("Len()").unwrap(), Value::Number(0));
+
+    // Errors in the callback are passed on.
+    assert!(ev.eval_expr("map([1], (v) => v)").is_err());
+    assert!(ev.eval_expr("sort([1, 2], {a, b -> [a]})").is_err());
+}
+
+#[test]
+fn funcref_from_typval() {
+    let name = CString::new("Fn").unwrap();
+    let add = CString::new("add").unwrap();
+    let val = typval_T { v_type: Vartype::VAR_FUNC, v_lock: 0, vval: ValUnion { v_string: add.as_ptr() as *mut _ } };
+    assert!(set_variable_rs(name.as_ptr(), &val));
+    let args = [
+        typval_T { v_type: Vartype::VAR_NUMBER, v_lock: 0, vval: ValUnion { v_number: 2 } },
+        typval_T { v_type: Vartype::VAR_NUMBER, v_lock: 0, vval: ValUnion { v_number: 3 } },
+    ];
+    let mut out = typval_T { v_type: Vartype::VAR_UNKNOWN, v_lock: 0, vval: ValUnion { v_number: 0 } };
+    assert!(call_function_rs(name.as_ptr(), args.as_ptr(), args.len(), &mut out));
+    unsafe { assert_eq!(out.vval.v_number, 5); }
+
+    // A NULL partial, as returned by test_null_partial(), cannot be called.
+    let null = typval_T { v_type: Vartype::VAR_PARTIAL, v_lock: 0, vval: ValUnion { v_partial: std::ptr::null_mut() } };
+    assert!(set_variable_rs(name.as_ptr(), &null));
+    assert!(!call_function_rs(name.as_ptr(), args.as_ptr(), args.len(), &mut out));
+}
@@ -0,0 +1,218 @@
+use std::io::Write;
+
+use rust_eval::{Evaluator, Value};
+
+mod common;
+use common::{error, eval};
+
+#[test]
+fn control_flow_and_functions() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline(
+        r#"
+        function! Fact(n) abort
+          if a:n <= 1
+            return 1
+          endif
+          return a:n * Fact(a:n - 1)
+        endfunction
+        function Sum(...)
+          let total = 0
+          for x in a:000
+            let total += x
+          endfor
+          return total
+        endfunction
+        let g:fact = Fact(5)
+        let g:sum = Sum(1, 2, 3)
+        let i = 0
+        let evens = []
+        while i < 10
+          let i += 1
+          if i % 2 | continue | endif
+          if i > 6 | break | endif
+          let evens += [i]
+        endwhile
+        let [a, b; rest] = [1, 2, 3, 4]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(ev.get_var("fact"), Some(Value::Number(120)));
+    assert_eq!(ev.get_var("sum"), Some(Value::Number(6)));
+    assert_eq!(eval(&mut ev, "evens"), "[2, 4, 6]");
+    assert_eq!(eval(&mut ev, "a . b"), "12");
+    assert_eq!(eval(&mut ev, "rest"), "[3, 4]");
+
+    // Local variables are not visible outside the function.
+    ev.do_cmdline("function! SetLocal()\n  let x = 5\nendfunction\ncall SetLocal()").unwrap();
+    assert_eq!(ev.get_var("x"), None);
+    assert!(ev.do_cmdline("function Fact(n)\nendfunction").is_err());
+    assert!(ev.output().last().unwrap().starts_with("E122:"));
+
+    // A function without "range" is called for each line.
+    ev.do_cmdline(
+        "let g:calls = []\nfunction Range() range\n  let g:calls += [[a:firstline, a:lastline]]\nendfunction\n\
+         function Line()\n  let g:calls += [a:firstline]\nendfunction\n2,4call Range()\n2,3call Line()",
+    )
+    .unwrap();
+    assert_eq!(eval(&mut ev, "g:calls"), "[[2, 4], 2, 3]");
+}
+
+#[test]
+fn try_catch_finally() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline(
+        r#"
+        let log = []
+        try
+          throw "oops"
+        catch /^oo/
+          let log += [v:exception]
+        finally
+          let log += ["finally"]
+        endtry
+        try
+          let x = undefined_var
+        catch /E121/
+          let log += [v:exception]
+        endtry
+        try
+          try
+            throw "inner"
+          finally
+            let log += ["inner finally"]
+          endtry
+        catch
+          let log += ["outer " . v:exception]
+        endtry
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        eval(&mut ev, "log"),
+        "['oops', 'finally', 'Vim(let):E121: Undefined variable: undefined_var', 'inner finally', 'outer inner']"
+    );
+
+    // An uncaught exception and errors are reported, execution continues
+    // after an error.
+    assert!(ev.do_cmdline("throw 'lost'").is_err());
+    assert_eq!(ev.output().last().unwrap(), "E605: Exception not caught: lost");
+    assert!(ev.do_cmdline("let y = nosuch\nlet z = 3").is_err());
+    assert_eq!(ev.get_var("z"), Some(Value::Number(3)));
+    assert_eq!(eval(&mut ev, "v:errmsg"), "E121: Undefined variable: nosuch");
+}
+
+#[test]
+fn abort_stops_function() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline(
+        r#"
+        let g:reached = []
+        function Abort() abort
+          let x = nosuch
+          let g:reached += ['abort']
+        endfunction
+        function NoAbort()
+          let x = nosuch
+          let g:reached += ['noabort']
+        endfunction
+        "#,
+    )
+    .unwrap();
+    assert!(ev.do_cmdline("call Abort()\ncall NoAbort()").is_err());
+    assert_eq!(eval(&mut ev, "g:reached"), "['noabort']");
+}
+
+#[test]
+fn parse_errors() {
+    let mut ev = Evaluator::new();
+    for (script, error) in [
+        ("if 1\necho 1", "E171:"),
+        ("while 1", "E170:"),
+        ("endif", "E580:"),
+        ("try\necho 1", "E600:"),
+        ("break", "E587:"),
+        ("function F()\necho 1", "E126:"),
+        ("if 1\nelse\nelse\nendif", "E583:"),
+        ("notacommand", "E492:"),
+        ("let x = 1 2", "E488:"),
+    ] {
+        assert!(ev.do_cmdline(script).is_err(), "{}", script);
+        assert!(ev.output().last().unwrap().starts_with(error), "{}: {:?}", script, ev.output().last());
+    }
+}
+
+#[test]
+fn scopes_and_sourcing() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(
+        file,
+        "let s:count = 0\nfunction! s:Inc()\n  let s:count += 1\n  return s:count\nendfunction\n\
+         let g:Inc = function('s:Inc')\nlet b:buf = 1\nlet g:lines = [1,\n      \\ 2,\n      \\ 3]\nfinish\nlet g:after = 1"
+    )
+    .unwrap();
+    let mut ev = Evaluator::new();
+    ev.source_file(file.path()).unwrap();
+    assert_eq!(eval(&mut ev, "g:lines"), "[1, 2, 3]");
+    assert_eq!(ev.get_var("after"), None);
+    assert_eq!(eval(&mut ev, "g:Inc()"), "1");
+    assert_eq!(eval(&mut ev, "g:Inc()"), "2");
+    assert_eq!(eval(&mut ev, "b:buf"), "1");
+    // Script variables are not visible outside the script.
+    assert!(ev.eval_expr("s:count").is_err());
+    assert!(ev.source_file(std::path::Path::new("/nonexistent/file.vim")).is_err());
+    assert!(ev.output().last().unwrap().starts_with("E484:"));
+}
+
+#[test]
+fn slice_assignment() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("let l = [1, 2, 3, 4]").unwrap();
+    ev.do_cmdline("let l[1:2] = [8, 9]").unwrap();
+    assert_eq!(eval(&mut ev, "l"), "[1, 8, 9, 4]");
+    ev.do_cmdline("let l[-2:] = [5, 6, 7]").unwrap();
+    assert_eq!(eval(&mut ev, "l"), "[1, 8, 5, 6, 7]");
+    ev.do_cmdline("let l[:1] += [1, 1] | let d = {'k': [0, 0]} | let d.k[1:] = ['x']").unwrap();
+    assert_eq!(eval(&mut ev, "[l, d]"), "[[2, 9, 5, 6, 7], {'k': [0, 'x']}]");
+    ev.do_cmdline("unlet l[1:3]").unwrap();
+    assert_eq!(eval(&mut ev, "l"), "[2, 7]");
+
+    assert_eq!(error(&mut ev, "let l[0:1] = 3"), "E709: [:] requires a List or Blob value");
+    assert_eq!(error(&mut ev, "let l[0:0] = [1, 2]"), "E710: List value has more items than targets");
+    assert_eq!(error(&mut ev, "let l[0:1] = [1]"), "E711: List value does not have enough items");
+    assert_eq!(error(&mut ev, "let l[0:] = [1]"), "E711: List value does not have enough items");
+    assert_eq!(error(&mut ev, "let l[3:] = [1]"), "E684: List index out of range: 3");
+    assert_eq!(eval(&mut ev, "l"), "[2, 7]");
+}
+
+#[test]
+fn lock_variables() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("let l = [1, [2]] | let d = {'a': 1} | let n = 5").unwrap();
+    ev.do_cmdline("lockvar l d n").unwrap();
+    assert_eq!(error(&mut ev, "let n = 6"), "E741: Value is locked: n");
+    assert_eq!(error(&mut ev, "unlet n"), "E741: Value is locked: n");
+    assert_eq!(error(&mut ev, "let l[0] = 9"), "E741: Value is locked: l");
+    assert_eq!(error(&mut ev, "call add(l, 3)"), "E741: Value is locked: add() argument");
+    assert_eq!(error(&mut ev, "let d.b = 2"), "E741: Value is locked: d");
+    // With the default depth of two the items of the List in the List can
+    // still be changed.
+    assert_eq!(error(&mut ev, "call add(l[1], 3)"), "E741: Value is locked: add() argument");
+    ev.do_cmdline("let l[1][0] = 3").unwrap();
+    assert_eq!(eval(&mut ev, "l"), "[1, [3]]");
+
+    ev.do_cmdline("unlockvar l n").unwrap();
+    ev.do_cmdline("let l[0] = 9 | let n = 6").unwrap();
+    assert_eq!(eval(&mut ev, "[l, n]"), "[[9, [3]], 6]");
+    ev.do_cmdline("lockvar 1 l").unwrap();
+    ev.do_cmdline("let l[0] = 8").unwrap();
+    assert_eq!(error(&mut ev, "unlet l[0]"), "E741: Value is locked: l");
+    ev.do_cmdline("lockvar! d.a").unwrap();
+    assert_eq!(error(&mut ev, "lockvar nothing"), "E108: No such variable: \"nothing\"");
+    assert_eq!(error(&mut ev, "unlockvar"), "E471: Argument required");
+
+    // A local variable is locked in its function only.
+    ev.do_cmdline("function F()\n  let x = 1\n  lockvar x\n  let x = 2\nendfunction").unwrap();
+    assert_eq!(error(&mut ev, "call F()"), "E741: Value is locked: x");
+    ev.do_cmdline("let x = 3").unwrap();
+}
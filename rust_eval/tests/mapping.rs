@@ -0,0 +1,106 @@
+use rust_edit::LineInput;
+use rust_eval::Evaluator;
+use rust_input::{rs_input_context_free, rs_input_context_new, rs_input_feed};
+use rust_map::{keys_to_string, parse_keys, Mode, TypeBuf};
+
+mod common;
+use common::{eval, output};
+
+/// Type `typed` and get the keys in Normal mode with mappings expanded.
+fn type_keys(ev: &mut Evaluator, typed: &str) -> String {
+    let ctx = rs_input_context_new();
+    for key in parse_keys(typed, "", "") {
+        rs_input_feed(ctx, key);
+    }
+    let input = unsafe { &mut *ctx };
+    let mut typebuf = TypeBuf::new();
+    let mut keys = Vec::new();
+    while let Some(key) = rust_getchar::vgetc(input, &mut typebuf, Mode::NORMAL, 1, ev).unwrap() {
+        keys.push(key);
+    }
+    rs_input_context_free(ctx);
+    keys_to_string(&keys)
+}
+
+#[test]
+fn define_and_list() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("let g:mapleader = ','\nnnoremap <Leader>w :write<CR>\nmap <silent> Q gq").unwrap();
+    ev.do_cmdline("inoremap <buffer> jk <Esc>").unwrap();
+    assert_eq!(output(&mut ev, "nmap"), ["n  ,w          * :write<CR>", "   Q             gq"]);
+    assert_eq!(output(&mut ev, "imap"), ["i  jk          *@<Esc>"]);
+    assert_eq!(output(&mut ev, "nunmap Q\nomap"), ["ov Q             gq"]);
+    assert_eq!(output(&mut ev, "nunmap xx"), ["E31: No such mapping"]);
+    ev.do_cmdline("nnoremap <Space>e :echo 1<CR>").unwrap();
+    assert_eq!(output(&mut ev, "nmap <Space>e"), ["n  <Space>e    * :echo 1<CR>"]);
+    assert_eq!(output(&mut ev, "imapclear <buffer>\nimap"), ["No mapping found"]);
+}
+
+#[test]
+fn maparg_and_mapset() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("nnoremap <expr> <F5> 'x' . 'y'\nxmap <C-a> <Plug>(inc)").unwrap();
+    assert_eq!(eval(&mut ev, "maparg('<F5>', 'n')"), "'x' . 'y'");
+    assert_eq!(eval(&mut ev, "maparg('<c-a>', 'x')"), "<Plug>(inc)");
+    assert_eq!(eval(&mut ev, "maparg('<c-a>', 'n')"), "");
+    assert_eq!(eval(&mut ev, "maparg('<F5>', 'n', 0, 1)->filter({k -> k =~ '^\\(lhs\\|expr\\|noremap\\|mode\\)$'})"),
+        "{'expr': 1, 'lhs': '<F5>', 'mode': 'n', 'noremap': 1}");
+
+    ev.do_cmdline("let saved = maparg('<F5>', 'n', 0, 1)\nnunmap <F5>").unwrap();
+    assert_eq!(eval(&mut ev, "maparg('<F5>', 'n')"), "");
+    ev.do_cmdline("call mapset('n', 0, saved)").unwrap();
+    assert_eq!(eval(&mut ev, "maparg('<F5>', 'n', 0, 1).expr"), "1");
+    assert_eq!(output(&mut ev, "call mapset({'lhs': 'x'})"), ["E460: Entries missing in mapset() dict argument"]);
+}
+
+#[test]
+fn expand_typed_keys() {
+    let mut ev = Evaluator::new();
+    ev.timers_mut().use_virtual_clock();
+    ev.do_cmdline("let g:n = 0\nnnoremap <expr> e repeat('l', 3)\nnmap c <Cmd>let g:n += 1<CR>x").unwrap();
+    ev.do_cmdline("nmap g <Left>\nnmap gg <Right>").unwrap();
+    assert_eq!(type_keys(&mut ev, "ecc"), "lllxx");
+    assert_eq!(eval(&mut ev, "g:n"), "2");
+
+    // "gg" is complete, a single "g" waits for 'timeoutlen'.
+    assert_eq!(type_keys(&mut ev, "gg"), "<Right>");
+    assert_eq!(ev.timers().now(), 0);
+    assert_eq!(type_keys(&mut ev, "g"), "<Left>");
+    assert_eq!(ev.timers().now(), 1000);
+    ev.do_cmdline("let &timeout = 0").unwrap();
+    assert_eq!(type_keys(&mut ev, "g"), "<Left>");
+    assert_eq!(ev.timers().now(), 1000);
+}
+
+#[test]
+fn abbreviations() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("iabbrev teh the\ncnoreabbrev <expr> W getcmdtype() == ':' ? 'w' : 'W'\niabbrev <buffer> #i #include").unwrap();
+    assert_eq!(output(&mut ev, "abbreviate"), ["i  #i           @#include", "i  teh           the", "c  W           * getcmdtype() == ':' ? 'w' : 'W'"]);
+    assert_eq!(output(&mut ev, "iabbrev a#b x"), ["E474: Invalid argument"]);
+    assert_eq!(output(&mut ev, "cunabbrev teh"), ["E24: No such abbreviation"]);
+    assert_eq!(eval(&mut ev, "maparg('teh', 'i', 1)"), "the");
+    assert_eq!(eval(&mut ev, "maparg('teh', 'i')"), "");
+    assert_eq!(eval(&mut ev, "maparg('teh', 'i', 1, 1).abbr"), "1");
+
+    // Typed in Insert mode.
+    let ctx = rs_input_context_new();
+    for key in parse_keys("teh #i<Esc>", "", "") {
+        rs_input_feed(ctx, key);
+    }
+    let input = unsafe { &mut *ctx };
+    let mut typebuf = TypeBuf::new();
+    let mut line = LineInput::insert("", 0);
+    while let Some(key) = rust_getchar::vgetc(input, &mut typebuf, Mode::INSERT, 1, &mut ev).unwrap() {
+        if line.key(key, &mut typebuf, 1, &mut ev).is_some() {
+            break;
+        }
+    }
+    rs_input_context_free(ctx);
+    assert_eq!(line.text(), "the #include");
+
+    ev.do_cmdline("let saved = maparg('teh', 'i', 1, 1)\niabclear").unwrap();
+    assert_eq!(output(&mut ev, "iabbrev"), ["i  #i           @#include"]);
+    ev.do_cmdline("call mapset('i', 1, saved)").unwrap();
+    assert_eq!(eval(&mut ev, "maparg('teh', 'i', 1)"), "the");
+}
@@ -0,0 +1,47 @@
+use rust_eval::Evaluator;
+use rust_memline::{open_swap, MemBuffer, SwapOpen, SwapOptions};
+
+mod common;
+use common::output;
+
+#[test]
+fn recover_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let fname = dir.path().join("todo.txt");
+    std::fs::write(&fname, "one\n").unwrap();
+
+    // A buffer with a swap file that is left behind.
+    let mut buf = MemBuffer::new();
+    buf.ml_append(0, "one");
+    let Ok(SwapOpen::Created(swap)) = open_swap(&fname, ".", SwapOptions::default(), &mut |_| unreachable!()) else {
+        panic!("no swap file");
+    };
+    buf.set_swap(*swap).unwrap();
+    buf.ml_append(1, "two");
+    buf.ml_sync_all().unwrap();
+
+    let mut ev = Evaluator::new();
+    let swap = dir.path().join(".todo.txt.swp");
+    assert_eq!(output(&mut ev, "recover"), ["E32: No file name"]);
+    let other = dir.path().join("other.txt");
+    assert_eq!(output(&mut ev, &format!("recover {}", other.display())), [format!(
+        "E305: No swap file found for {}",
+        other.display()
+    )]);
+    ev.buffer_mut().set_name(&fname.to_string_lossy());
+    ev.do_cmdline("let &modified = 1").unwrap();
+    assert_eq!(output(&mut ev, "recover"), ["E37: No write since last change (add ! to override)"]);
+    assert_eq!(output(&mut ev, "recover!")[..3], [
+        format!("Using swap file \"{}\"", swap.display()),
+        format!("Original file \"{}\"", fname.display()),
+        "Recovery completed. You should check if everything is OK.".to_string(),
+    ]);
+    assert_eq!(ev.buffer().lines(), ["one", "two"]);
+    assert_eq!(ev.get_option("modified").unwrap().to_string(), "1");
+
+    // Recovering from a swap file by its name.
+    ev.buffer_mut().set_lines(Vec::new());
+    ev.do_cmdline(&format!("recover! {}", swap.display())).unwrap();
+    assert_eq!(ev.buffer().lines(), ["one", "two"]);
+    buf.close_swap(true).unwrap();
+}
@@ -0,0 +1,84 @@
+use rust_eval::{Evaluator, Value};
+use rust_input::{rs_input_context_free, rs_input_context_new};
+
+mod common;
+use common::eval;
+
+fn new_evaluator() -> Evaluator {
+    let mut ev = Evaluator::new();
+    ev.timers_mut().use_virtual_clock();
+    ev.do_cmdline("let g:fired = []\nfunction Fire(id)\ncall add(g:fired, a:id)\nendfunction").unwrap();
+    ev
+}
+
+#[test]
+fn timers_fire_when_due() {
+    let mut ev = new_evaluator();
+    let once = eval(&mut ev, "timer_start(100, 'Fire')");
+    let thrice = eval(&mut ev, "timer_start(40, function('Fire'), {'repeat': 3})");
+    assert_eq!((once.as_str(), thrice.as_str()), ("1", "2"));
+    assert_eq!(ev.run_timers(), Some(40));
+    assert_eq!(eval(&mut ev, "g:fired"), "[]");
+
+    ev.timers_mut().advance(40);
+    assert_eq!(ev.run_timers(), Some(40));
+    assert_eq!(eval(&mut ev, "g:fired"), "[2]");
+    assert_eq!(eval(&mut ev, "timer_info(2)[0].repeat . timer_info(1)[0].remaining"), "260");
+
+    for _ in 0..4 {
+        ev.timers_mut().advance(40);
+        ev.run_timers();
+    }
+    assert_eq!(eval(&mut ev, "g:fired"), "[2, 2, 1, 2]");
+    assert_eq!(ev.run_timers(), None);
+    assert_eq!(eval(&mut ev, "timer_info()"), "[]");
+}
+
+#[test]
+fn stop_and_pause() {
+    let mut ev = new_evaluator();
+    ev.do_cmdline("let t1 = timer_start(10, {id -> add(g:fired, 'lambda')}, {'repeat': -1})").unwrap();
+    ev.do_cmdline("let t2 = timer_start(10, 'Fire')").unwrap();
+    ev.do_cmdline("call timer_pause(t2, 1)").unwrap();
+    assert_eq!(eval(&mut ev, "timer_info(t2)[0].paused"), "1");
+    ev.timers_mut().advance(10);
+    ev.run_timers();
+    ev.timers_mut().advance(10);
+    ev.run_timers();
+    assert_eq!(eval(&mut ev, "g:fired"), "['lambda', 'lambda']");
+    ev.do_cmdline("call timer_stop(t1)\ncall timer_pause(t2, 0)").unwrap();
+    ev.run_timers();
+    assert_eq!(eval(&mut ev, "g:fired"), "['lambda', 'lambda', 2]");
+
+    ev.do_cmdline("call timer_start(10, 'Fire')\ncall timer_start(20, 'Fire')\ncall timer_stopall()").unwrap();
+    assert_eq!(eval(&mut ev, "timer_info()"), "[]");
+    assert!(ev.do_cmdline("call timer_start(10, 0)").is_err());
+    assert_eq!(ev.output().last().unwrap(), "E921: Invalid callback argument");
+}
+
+#[test]
+fn failing_callback_is_stopped() {
+    let mut ev = new_evaluator();
+    let id = eval(&mut ev, "timer_start(10, {-> undefined}, {'repeat': -1})");
+    for _ in 0..5 {
+        ev.timers_mut().advance(10);
+        ev.run_timers();
+    }
+    let errors = ev.output().iter().filter(|line| line.starts_with("E121")).count();
+    assert_eq!(errors, 3);
+    assert_eq!(eval(&mut ev, &format!("timer_info({})", id)), "[]");
+}
+
+#[test]
+fn input_wait_runs_timers() {
+    let mut ev = new_evaluator();
+    ev.do_cmdline("call timer_start(500, 'Fire')").unwrap();
+    let ctx = rs_input_context_new();
+    let input = unsafe { &mut *ctx };
+    assert_eq!(rust_getchar::getchar_wait(input, Some(100), &mut ev), None);
+    assert_eq!(ev.get_var("fired"), Some(Value::new_list(Vec::new())));
+    assert_eq!(rust_getchar::getchar_wait(input, None, &mut ev), None);
+    assert_eq!(eval(&mut ev, "g:fired"), "[1]");
+    assert_eq!(ev.timers().now(), 500);
+    rs_input_context_free(ctx);
+}
@@ -0,0 +1,109 @@
+use rust_eval::Evaluator;
+
+mod common;
+use common::{echo, output};
+
+#[test]
+fn undo_redo_and_branches() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("call test_settime(1000)").unwrap();
+    ev.do_cmdline("call setline(1, 'one')").unwrap();
+    ev.do_cmdline("call append(1, ['two', 'three'])").unwrap();
+    assert_eq!(echo(&mut ev, "&modified"), "1");
+    ev.do_cmdline("let &modified = 0").unwrap();
+    ev.do_cmdline("call setline(2, 'TWO')").unwrap();
+    assert_eq!(ev.buffer().lines(), ["one", "TWO", "three"]);
+
+    assert_eq!(output(&mut ev, "undo"), ["1 change; before #3  0 seconds ago"]);
+    assert_eq!(ev.buffer().lines(), ["one", "two", "three"]);
+    assert_eq!(echo(&mut ev, "&modified"), "0");
+    assert_eq!(output(&mut ev, "u"), ["2 fewer lines; before #2  0 seconds ago"]);
+    assert_eq!(output(&mut ev, "redo"), ["2 more lines; after #2  0 seconds ago"]);
+
+    // A change after undo starts a branch, `:undo N` goes to any state.
+    ev.do_cmdline("call setline(3, 'THREE')").unwrap();
+    assert_eq!(echo(&mut ev, "undotree().seq_cur"), "4");
+    ev.do_cmdline("undo 3").unwrap();
+    assert_eq!(ev.buffer().lines(), ["one", "TWO", "three"]);
+    ev.do_cmdline("undo 0").unwrap();
+    assert_eq!(ev.buffer().lines(), [""]);
+    assert_eq!(output(&mut ev, "undo 9"), ["E830: Undo number 9 not found"]);
+    ev.do_cmdline("later 4").unwrap();
+    assert_eq!(ev.buffer().lines(), ["one", "two", "THREE"]);
+    assert_eq!(output(&mut ev, "later"), ["Already at newest change"]);
+    assert_eq!(output(&mut ev, "earlier 1x"), ["E475: Invalid argument: 1x"]);
+
+    let tree = echo(&mut ev, "undotree()");
+    assert!(tree.contains("'seq_last': 4"), "{}", tree);
+    assert!(tree.contains("'alt': [{'seq': 3, 'time': 1000}]"), "{}", tree);
+    assert_eq!(echo(&mut ev, "undotree().entries->map({_, e -> e.seq})"), "[1, 2, 4]");
+}
+
+#[test]
+fn undojoin_and_time() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("call test_settime(100)").unwrap();
+    ev.do_cmdline("call setline(1, 'a')").unwrap();
+    ev.do_cmdline("call test_settime(200)").unwrap();
+    ev.do_cmdline("call setline(1, 'b')").unwrap();
+    ev.do_cmdline("undojoin | call append(1, 'c')").unwrap();
+    assert_eq!(echo(&mut ev, "len(undotree().entries)"), "2");
+    ev.do_cmdline("call test_settime(250)").unwrap();
+    assert_eq!(output(&mut ev, "undo"), ["1 line less; before #2  50 seconds ago"]);
+    assert_eq!(ev.buffer().lines(), ["a"]);
+    assert_eq!(output(&mut ev, "undojoin"), ["E790: Undojoin is not allowed after undo"]);
+    ev.do_cmdline("later 1m").unwrap();
+    assert_eq!(ev.buffer().lines(), ["b", "c"]);
+    ev.do_cmdline("earlier 30s").unwrap();
+    assert_eq!(ev.buffer().lines(), ["a"]);
+    ev.do_cmdline("earlier 1f").unwrap();
+    assert_eq!(ev.buffer().lines(), [""]);
+
+    // Replacing the text forgets the undo information.
+    ev.buffer_mut().set_lines(vec!["x".to_string()]);
+    assert_eq!(echo(&mut ev, "undotree().entries"), "[]");
+    assert_eq!(output(&mut ev, "undo"), ["Already at oldest change"]);
+}
+
+#[test]
+fn undo_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("undo");
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("call setline(1, 'one')").unwrap();
+    ev.do_cmdline("call setline(1, 'two')").unwrap();
+    assert_eq!(output(&mut ev, "wundo"), ["E471: Argument required"]);
+    ev.do_cmdline(&format!("wundo {}", file.display())).unwrap();
+
+    let mut other = Evaluator::new();
+    other.buffer_mut().set_lines(vec!["two".to_string()]);
+    assert_eq!(output(&mut other, &format!("rundo {}", file.display())), [format!(
+        "Finished reading undo file {}",
+        file.display()
+    )]);
+    other.do_cmdline("undo").unwrap();
+    assert_eq!(other.buffer().lines(), ["one"]);
+    other.do_cmdline("undo").unwrap();
+    assert_eq!(other.buffer().lines(), [""]);
+
+    // The undo file is for other text now.
+    assert_eq!(output(&mut other, &format!("rundo {}", file.display())), [
+        "File contents changed, cannot use undo info"
+    ]);
+    let text = dir.path().join("text");
+    std::fs::write(&text, "text\n").unwrap();
+    assert_eq!(output(&mut ev, &format!("wundo {}", text.display())), [format!(
+        "Will not overwrite, this is not an undo file: {}",
+        text.display()
+    )]);
+    assert_eq!(output(&mut ev, &format!("rundo {}", text.display())), [format!(
+        "E823: Not an undo file: {}",
+        text.display()
+    )]);
+    ev.do_cmdline(&format!("wundo! {}", text.display())).unwrap();
+    assert_eq!(output(&mut ev, &format!("rundo {}", dir.path().join("none").display())), [format!(
+        "E822: Cannot open undo file for reading: {}",
+        dir.path().join("none").display()
+    )]);
+    assert_eq!(echo(&mut ev, "[&undofile, &udir]"), "[0, '.']");
+}
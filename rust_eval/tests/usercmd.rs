@@ -0,0 +1,106 @@
+use rust_eval::Evaluator;
+
+mod common;
+use common::{eval, output};
+
+#[test]
+fn define_and_execute() {
+    let mut ev = Evaluator::new();
+    ev.buffer_mut().set_lines((1..=5).map(|n| n.to_string()).collect());
+    ev.do_cmdline("command -nargs=* -range=% -bang Show echo <line1> <line2> '<bang>' <q-args>").unwrap();
+    ev.do_cmdline("command -nargs=* Args let g:args = [<f-args>]").unwrap();
+    ev.do_cmdline("command -count=3 -register Count let g:count = [<count>, '<reg>']").unwrap();
+    assert_eq!(output(&mut ev, "Show a  b"), ["1 5  a  b"]);
+    assert_eq!(output(&mut ev, "2,3Show! x|y"), ["2 3 ! x|y"]);
+    ev.do_cmdline("Args one two\\ words").unwrap();
+    assert_eq!(eval(&mut ev, "g:args"), "['one', 'two words']");
+    ev.do_cmdline("Count").unwrap();
+    assert_eq!(eval(&mut ev, "g:count"), "[3, '']");
+    ev.do_cmdline("Count a 7").unwrap();
+    assert_eq!(eval(&mut ev, "g:count"), "[7, 'a']");
+
+    // Errors in the arguments and abbreviated names.
+    assert_eq!(output(&mut ev, "Arg!"), ["E477: No ! allowed"]);
+    assert_eq!(output(&mut ev, "Sh 1"), ["1 5  1"]);
+    ev.do_cmdline("command Shout echo 'loud'").unwrap();
+    assert_eq!(output(&mut ev, "Sh"), ["E464: Ambiguous use of user-defined command"]);
+    assert_eq!(output(&mut ev, "Shout x"), ["E488: Trailing characters: x"]);
+    assert_eq!(output(&mut ev, "Nope"), ["E492: Not an editor command: Nope"]);
+    assert_eq!(output(&mut ev, "command Shout echo"), ["E174: Command already exists: add ! to replace it: Shout"]);
+    assert_eq!(output(&mut ev, "command shout echo"), ["E183: User defined commands must start with an uppercase letter"]);
+}
+
+#[test]
+fn bar_mods_and_script_context() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("command -nargs=1 -bar Echo echo <args>\ncommand -nargs=1 NoBar echo <q-args>").unwrap();
+    assert_eq!(output(&mut ev, "Echo 1 | echo 2"), ["1", "2"]);
+    assert_eq!(output(&mut ev, "NoBar 1 | echo 2"), ["1 | echo 2"]);
+    ev.do_cmdline("command Mods echo '<mods>'").unwrap();
+    assert_eq!(output(&mut ev, "vert silent! Mods"), ["silent! vertical"]);
+
+    // The replacement text keeps its bars, also with -bar.
+    ev.do_cmdline("let g:log = []").unwrap();
+    ev.do_cmdline("command! -bar B call add(g:log, 'b') | call add(g:log, 'after')").unwrap();
+    assert_eq!(eval(&mut ev, "g:log"), "[]");
+    ev.do_cmdline("B | call add(g:log, 'next')").unwrap();
+    assert_eq!(eval(&mut ev, "g:log"), "['b', 'after', 'next']");
+    assert_eq!(output(&mut ev, "command -range -count Both echo"), ["E177: Count cannot be specified twice"]);
+
+    // The command runs in the script that defined it.
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("cmd.vim");
+    std::fs::write(&script, "function s:Hello(name)\n  echo 'hello ' . a:name\nendfunction\ncommand -nargs=1 Hello call s:Hello(<q-args>)\n").unwrap();
+    ev.do_cmdline(&format!("source {}", script.display())).unwrap();
+    assert_eq!(output(&mut ev, "Hello you"), ["hello you"]);
+}
+
+#[test]
+fn list_and_delete() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("command -nargs=? -complete=file Edit echo <q-args>\ncommand! -buffer -bar Local echo").unwrap();
+    assert_eq!(output(&mut ev, "command"), [
+        "    Name              Args Address Complete    Definition",
+        "  b|Local             0                        echo",
+        "    Edit              ?            file        echo <q-args>",
+    ]);
+    assert_eq!(output(&mut ev, "command E"), [
+        "    Name              Args Address Complete    Definition",
+        "    Edit              ?            file        echo <q-args>",
+    ]);
+    assert_eq!(output(&mut ev, "delcommand Local"), ["E184: No such user-defined command: Local"]);
+    ev.do_cmdline("delcommand -buffer Local\ncomclear").unwrap();
+    assert_eq!(output(&mut ev, "command"), ["No user-defined commands found"]);
+}
+
+#[test]
+fn completion() {
+    let mut ev = Evaluator::new();
+    ev.do_cmdline(concat!(
+        "function ListColors(lead, line, pos)\n",
+        "  return filter(['red', 'green', 'blue'], {_, c -> c =~ '^' . a:lead})\n",
+        "endfunction\n",
+        "function Sizes(lead, line, pos)\n",
+        "  return join(['small', 'medium', 'large'], \"\\n\")\n",
+        "endfunction\n",
+        "command -nargs=1 -complete=customlist,ListColors Color echo <q-args>\n",
+        "command -nargs=* -complete=custom,Sizes Size echo <q-args>\n",
+        "augroup MyGroup\n",
+        "augroup END",
+    ))
+    .unwrap();
+    let complete = |ev: &mut Evaluator, line: &str| ev.cmdline_completions(line).unwrap();
+    assert_eq!(complete(&mut ev, "Col"), ["Color"]);
+    assert_eq!(complete(&mut ev, "Color "), ["red", "green", "blue"]);
+    assert_eq!(complete(&mut ev, "Color gr"), ["green"]);
+    assert_eq!(complete(&mut ev, "silent Size m"), ["medium"]);
+    assert_eq!(complete(&mut ev, "augroup My"), ["MyGroup"]);
+    assert_eq!(complete(&mut ev, "call ListC"), ["ListColors("]);
+    assert_eq!(complete(&mut ev, "delcommand S"), ["Size"]);
+
+    assert_eq!(eval(&mut ev, "getcompletion('Color b', 'cmdline')"), "['blue']");
+    assert_eq!(eval(&mut ev, "getcompletion('bufwi', 'event')"), "['BufWinEnter', 'BufWinLeave', 'BufWipeout']");
+    assert_eq!(eval(&mut ev, "getcompletion('L', 'customlist,ListColors')"), "[]");
+    assert_eq!(eval(&mut ev, "getcompletion('tim', 'option')"), "['timeout', 'timeoutlen']");
+    assert_eq!(output(&mut ev, "echo getcompletion('', 'colors')"), ["E475: Invalid argument: colors"]);
+}
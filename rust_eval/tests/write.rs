@@ -0,0 +1,106 @@
+use std::fs;
+
+use rust_eval::Evaluator;
+
+mod common;
+use common::{echo, output};
+
+#[test]
+fn write_and_autocmds() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.txt");
+    let name = path.display().to_string();
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("call setline(1, ['one', 'two', 'three'])").unwrap();
+    assert_eq!(output(&mut ev, "write"), ["E32: No file name"]);
+
+    // A buffer without a name gets the name of the file it is written to.
+    ev.do_cmdline("autocmd BufWritePre * call setline(1, 'ONE')").unwrap();
+    ev.do_cmdline("autocmd BufWritePost * let g:post = expand('<afile>')").unwrap();
+    assert_eq!(output(&mut ev, &format!("w {}", name)), [format!("\"{}\" [New] 3L, 14B written", name)]);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "ONE\ntwo\nthree\n");
+    assert_eq!(ev.buffer().name(), name);
+    assert_eq!(echo(&mut ev, "&modified"), "0");
+    assert_eq!(echo(&mut ev, "g:post"), name);
+
+    // Some lines, to another file and appended.
+    let part = dir.path().join("part").display().to_string();
+    assert_eq!(output(&mut ev, "2,3w"), ["E140: Use ! to write partial buffer"]);
+    output(&mut ev, &format!("2,3w {}", part));
+    assert_eq!(output(&mut ev, &format!("1w >> {}", part)), [format!("\"{}\" 1L, 4B appended", part)]);
+    assert_eq!(fs::read_to_string(&part).unwrap(), "two\nthree\nONE\n");
+    assert_eq!(output(&mut ev, &format!("w {}", part)), ["E13: File exists (add ! to override)"]);
+    assert_eq!(output(&mut ev, "5,6w"), ["E16: Invalid range"]);
+
+    // A BufWriteCmd autocommand writes instead.
+    ev.do_cmdline("autocmd! BufWritePre").unwrap();
+    ev.do_cmdline("autocmd BufWriteCmd * let g:cmd = 1 | let &modified = 0").unwrap();
+    ev.do_cmdline("call setline(1, 'one')").unwrap();
+    assert_eq!(output(&mut ev, "w"), Vec::<String>::new());
+    assert_eq!(echo(&mut ev, "g:cmd"), "1");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "ONE\ntwo\nthree\n");
+}
+
+#[test]
+fn write_options_and_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.txt");
+    let name = path.display().to_string();
+    fs::write(&path, "old\n").unwrap();
+    let mut ev = Evaluator::new();
+    ev.buffer_mut().set_name(&name);
+    ev.do_cmdline("call setline(1, ['a', 'b'])").unwrap();
+    ev.do_cmdline("let &backup = 1 | let &backupdir = '.' | let &backupext = '.bak'").unwrap();
+    ev.do_cmdline("let &fileformat = 'dos' | let &endofline = 0 | let &fixendofline = 0").unwrap();
+    assert_eq!(output(&mut ev, "w"), [format!("\"{}\" [noeol][dos] 2L, 4B written", name)]);
+    assert_eq!(fs::read(&path).unwrap(), b"a\r\nb");
+    assert_eq!(fs::read_to_string(dir.path().join("file.txt.bak")).unwrap(), "old\n");
+
+    ev.do_cmdline("let &backupcopy = 'sometimes'").unwrap();
+    assert_eq!(output(&mut ev, "w"), ["E474: Invalid argument: backupcopy=sometimes"]);
+    ev.do_cmdline("let &backupcopy = 'yes'").unwrap();
+    ev.do_cmdline("let &backupext = ''").unwrap();
+    assert_eq!(output(&mut ev, "w"), ["E589: 'backupext' and 'patchmode' are equal"]);
+    assert_eq!(fs::read(&path).unwrap(), b"a\r\nb");
+    ev.do_cmdline("let &backupext = '.bak'").unwrap();
+
+    // The lines are the input of the command.
+    assert_eq!(output(&mut ev, "w !cat"), ["a", "b"]);
+    assert_eq!(output(&mut ev, "2w !cat; exit 3"), ["b", "shell returned 3"]);
+}
+
+#[test]
+fn encrypted_write_and_undo_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("secret.txt");
+    let name = path.display().to_string();
+    let mut ev = Evaluator::new();
+    ev.do_cmdline("call setline(1, 'secret text')").unwrap();
+    ev.do_cmdline("let &key = 'passphrase'").unwrap();
+    assert_eq!(echo(&mut ev, "&key"), "*****");
+    assert_eq!(echo(&mut ev, "&cm"), "xchacha20scrypt");
+    let written = output(&mut ev, &format!("w {}", name));
+    assert!(written[0].starts_with(&format!("\"{}\" [xchacha20scrypt][New] 1L, ", name)));
+    let data = fs::read(&path).unwrap();
+    assert!(data.starts_with(b"VimCrypt~s1!") && !data.windows(6).any(|w| w == b"secret"));
+    let opts = rust_fileio::ReadOptions { key: Some("passphrase".into()), ..Default::default() };
+    assert_eq!(rust_fileio::read_file(&path, &opts).unwrap().lines, [b"secret text"]);
+
+    let undo = dir.path().join("undo").display().to_string();
+    ev.do_cmdline("call setline(1, 'changed')").unwrap();
+    ev.do_cmdline(&format!("wundo {}", undo)).unwrap();
+    ev.do_cmdline("let &key = ''").unwrap();
+    assert_eq!(
+        output(&mut ev, &format!("rundo {}", undo)),
+        [format!("E832: Non-encrypted file has encrypted undo file: {}", undo)]
+    );
+    ev.do_cmdline("let &key = 'passphrase'").unwrap();
+    assert_eq!(output(&mut ev, &format!("rundo {}", undo)), [format!("Finished reading undo file {}", undo)]);
+
+    // "zip" can only be read, the file is written with "xchacha20scrypt".
+    ev.do_cmdline("let &cm = 'zip'").unwrap();
+    assert!(output(&mut ev, "w!")[0].starts_with(&format!("\"{}\" [xchacha20scrypt] 1L, ", name)));
+    assert!(fs::read(&path).unwrap().starts_with(b"VimCrypt~s1!"));
+    ev.do_cmdline("let &cm = 'blowfish'").unwrap();
+    assert_eq!(output(&mut ev, "w!"), ["E474: Invalid argument: cryptmethod=blowfish"]);
+}
@@ -1,38 +1,41 @@
 use once_cell::sync::Lazy;
-use rust_typval::TypVal;
+use rust_typval::Value;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::sync::Mutex;
 
-static VIM_VARS: Lazy<Mutex<HashMap<i32, TypVal>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+thread_local! {
+    /// Values hold `Rc` references, they stay with the thread that runs
+    /// scripts.
+    static VIM_VARS: RefCell<HashMap<i32, Value>> = RefCell::new(HashMap::new());
+}
 
 static WINDOWS: Lazy<Mutex<Vec<i32>>> = Lazy::new(|| Mutex::new(Vec::new()));
 
 /// Store a numeric Vim variable identified by `idx`.
 pub fn set_vim_var_nr(idx: i32, val: i64) {
-    let mut vars = VIM_VARS.lock().unwrap();
-    vars.insert(idx, TypVal::Number(val));
+    VIM_VARS.with(|vars| vars.borrow_mut().insert(idx, Value::Number(val)));
 }
 
 /// Retrieve a previously stored numeric Vim variable.
 pub fn get_vim_var_nr(idx: i32) -> Option<i64> {
-    match VIM_VARS.lock().unwrap().get(&idx) {
-        Some(TypVal::Number(n)) => Some(*n),
+    VIM_VARS.with(|vars| match vars.borrow().get(&idx) {
+        Some(Value::Number(n)) => Some(*n),
         _ => None,
-    }
+    })
 }
 
 /// Store a string Vim variable identified by `idx`.
 pub fn set_vim_var_str(idx: i32, val: &str) {
-    let mut vars = VIM_VARS.lock().unwrap();
-    vars.insert(idx, TypVal::String(val.to_string()));
+    VIM_VARS.with(|vars| vars.borrow_mut().insert(idx, Value::Str(val.to_string())));
 }
 
 /// Retrieve a previously stored string Vim variable.
 pub fn get_vim_var_str(idx: i32) -> Option<String> {
-    match VIM_VARS.lock().unwrap().get(&idx) {
-        Some(TypVal::String(s)) => Some(s.clone()),
+    VIM_VARS.with(|vars| match vars.borrow().get(&idx) {
+        Some(Value::Str(s)) => Some(s.clone()),
         _ => None,
-    }
+    })
 }
 
 /// Create a new window and return its id.  The first window gets id 1.
@@ -0,0 +1,309 @@
+//! Character encodings of files: names as 'fileencodings' uses them and
+//! conversion between them and the internal UTF-8.
+//!
+//! latin1, cp1252, UTF-8 and UTF-16 are handled here, the multibyte
+//! encodings (euc-jp, sjis, gb18030, ...) with encoding_rs.  Unlike the
+//! WHATWG tables cp1252 has five undefined bytes, so that detection can fall
+//! back to latin1 for text that is not cp1252.
+
+use encoding_rs::{DecoderResult, EncoderResult, Encoding};
+
+/// The canonical name of encoding `name`, like Vim's enc_canonize():
+/// lower case, "_" replaced with "-" and aliases resolved.
+pub fn canonical_name(name: &str) -> String {
+    let name = name.to_ascii_lowercase().replace('_', "-");
+    let canonical = match name.as_str() {
+        "utf8" => "utf-8",
+        "ansi" | "iso-8859-1" | "iso8859-1" | "latin-1" | "l1" => "latin1",
+        "windows-1252" | "win-1252" => "cp1252",
+        "utf-16be" | "utf16" | "ucs-2" | "ucs2" | "unicode" => "utf-16",
+        "utf16le" | "ucs-2le" | "ucs2le" => "utf-16le",
+        "shift-jis" | "ms-kanji" | "cp932" => "sjis",
+        "eucjp" => "euc-jp",
+        "gb-18030" => "gb18030",
+        _ => return name,
+    };
+    canonical.to_string()
+}
+
+/// Whether `name`, a canonical name, is the internal encoding: text in it is
+/// not converted.
+pub fn is_utf8(name: &str) -> bool {
+    name.is_empty() || name == "utf-8"
+}
+
+/// Whether `name` is a Unicode encoding that can start with a BOM.
+pub fn is_unicode(name: &str) -> bool {
+    matches!(name, "utf-8" | "utf-16" | "utf-16le")
+}
+
+/// The byte order mark of Unicode encoding `name`.
+pub fn bom(name: &str) -> &'static [u8] {
+    match name {
+        "utf-8" => b"\xef\xbb\xbf",
+        "utf-16" => b"\xfe\xff",
+        "utf-16le" => b"\xff\xfe",
+        _ => b"",
+    }
+}
+
+/// The encoding of the byte order mark `data` starts with and its length,
+/// for "ucs-bom" in 'fileencodings'.
+pub fn detect_bom(data: &[u8]) -> Option<(&'static str, usize)> {
+    ["utf-8", "utf-16le", "utf-16"]
+        .into_iter()
+        .find(|name| data.starts_with(bom(name)))
+        .map(|name| (name, bom(name).len()))
+}
+
+/// The characters of bytes 0x80 to 0x9f in cp1252, None where undefined.
+const CP1252_HIGH: [Option<char>; 32] = [
+    Some('\u{20ac}'), None, Some('\u{201a}'), Some('\u{0192}'),
+    Some('\u{201e}'), Some('\u{2026}'), Some('\u{2020}'), Some('\u{2021}'),
+    Some('\u{02c6}'), Some('\u{2030}'), Some('\u{0160}'), Some('\u{2039}'),
+    Some('\u{0152}'), None, Some('\u{017d}'), None,
+    None, Some('\u{2018}'), Some('\u{2019}'), Some('\u{201c}'),
+    Some('\u{201d}'), Some('\u{2022}'), Some('\u{2013}'), Some('\u{2014}'),
+    Some('\u{02dc}'), Some('\u{2122}'), Some('\u{0161}'), Some('\u{203a}'),
+    Some('\u{0153}'), None, Some('\u{017e}'), Some('\u{0178}'),
+];
+
+fn cp1252_char(byte: u8) -> Option<char> {
+    match byte {
+        0x80..=0x9f => CP1252_HIGH[(byte - 0x80) as usize],
+        _ => Some(byte as char),
+    }
+}
+
+fn push_char(out: &mut Vec<u8>, c: char) {
+    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// The encoding_rs encoding for `name`, None for the ones handled here.
+fn rs_encoding(name: &str) -> Option<&'static Encoding> {
+    match name {
+        "utf-8" | "latin1" | "cp1252" => None,
+        "utf-16" => Some(encoding_rs::UTF_16BE),
+        "utf-16le" => Some(encoding_rs::UTF_16LE),
+        _ => Encoding::for_label(name.as_bytes()),
+    }
+}
+
+/// Whether text can be converted from and to encoding `name`.
+pub fn is_supported(name: &str) -> bool {
+    matches!(name, "utf-8" | "latin1" | "cp1252") || rs_encoding(name).is_some()
+}
+
+/// A streaming conversion of one encoding to UTF-8.
+pub enum Decoder {
+    /// Checks that the text is valid UTF-8.  Holds the bytes of a
+    /// character that continues in the next buffer.
+    Utf8(Vec<u8>),
+    Latin1,
+    Cp1252,
+    Rs(encoding_rs::Decoder),
+}
+
+impl Decoder {
+    /// A decoder for encoding `name`, which must be canonical.  None for an
+    /// unknown encoding.
+    pub fn new(name: &str) -> Option<Decoder> {
+        match name {
+            "utf-8" => Some(Decoder::Utf8(Vec::new())),
+            "latin1" => Some(Decoder::Latin1),
+            "cp1252" => Some(Decoder::Cp1252),
+            _ => rs_encoding(name).map(|enc| Decoder::Rs(enc.new_decoder_without_bom_handling())),
+        }
+    }
+
+    /// Convert `src` to UTF-8 and append it to `out`.  `last` is set for the
+    /// end of the file.  Stops after an illegal byte sequence.  Returns the
+    /// number of bytes of `src` used and the illegal bytes, if any.
+    pub fn decode(&mut self, src: &[u8], out: &mut Vec<u8>, last: bool) -> (usize, Option<Vec<u8>>) {
+        match self {
+            Decoder::Utf8(pending) => {
+                let before = pending.len();
+                let mut data = std::mem::take(pending);
+                data.extend_from_slice(src);
+                match std::str::from_utf8(&data) {
+                    Ok(_) => {
+                        out.extend_from_slice(&data);
+                        (src.len(), None)
+                    }
+                    Err(err) => {
+                        let valid = err.valid_up_to();
+                        out.extend_from_slice(&data[..valid]);
+                        match err.error_len() {
+                            Some(len) => {
+                                let bad = data[valid..valid + len].to_vec();
+                                ((valid + len).saturating_sub(before), Some(bad))
+                            }
+                            None if last => (src.len(), Some(data[valid..].to_vec())),
+                            None => {
+                                *pending = data[valid..].to_vec();
+                                (src.len(), None)
+                            }
+                        }
+                    }
+                }
+            }
+            Decoder::Latin1 => {
+                for &byte in src {
+                    push_char(out, byte as char);
+                }
+                (src.len(), None)
+            }
+            Decoder::Cp1252 => {
+                for (i, &byte) in src.iter().enumerate() {
+                    match cp1252_char(byte) {
+                        Some(c) => push_char(out, c),
+                        None => return (i + 1, Some(vec![byte])),
+                    }
+                }
+                (src.len(), None)
+            }
+            Decoder::Rs(decoder) => {
+                let mut read = 0;
+                loop {
+                    let max = decoder.max_utf8_buffer_length_without_replacement(src.len() - read);
+                    let max = max.unwrap_or(4096).max(16);
+                    let mut buf = vec![0u8; max];
+                    let (result, n, written) = decoder.decode_to_utf8_without_replacement(&src[read..], &mut buf, last);
+                    out.extend_from_slice(&buf[..written]);
+                    read += n;
+                    match result {
+                        DecoderResult::InputEmpty => return (read, None),
+                        DecoderResult::OutputFull => continue,
+                        DecoderResult::Malformed(bad, extra) => {
+                            let end = read.saturating_sub(extra as usize);
+                            let start = end.saturating_sub(bad as usize);
+                            return (read, Some(src[start..end].to_vec()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Convert `text`, UTF-8, to encoding `name` and append it to `out`.
+/// Returns the first character that cannot be converted as an error.
+/// Bytes that are not valid UTF-8 are written unchanged.
+pub fn encode(name: &str, text: &[u8], out: &mut Vec<u8>) -> Result<(), char> {
+    let mut rest = text;
+    while !rest.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(s) => (s, &b""[..]),
+            Err(err) => {
+                let (valid, invalid) = rest.split_at(err.valid_up_to());
+                let len = err.error_len().unwrap_or(invalid.len());
+                (std::str::from_utf8(valid).unwrap_or_default(), &invalid[..len])
+            }
+        };
+        encode_str(name, valid, out)?;
+        out.extend_from_slice(invalid);
+        rest = &rest[valid.len() + invalid.len()..];
+    }
+    Ok(())
+}
+
+fn encode_str(name: &str, text: &str, out: &mut Vec<u8>) -> Result<(), char> {
+    match name {
+        "" | "utf-8" => out.extend_from_slice(text.as_bytes()),
+        "latin1" => {
+            for c in text.chars() {
+                out.push(u8::try_from(c as u32).map_err(|_| c)?);
+            }
+        }
+        "cp1252" => {
+            for c in text.chars() {
+                let byte = match c as u32 {
+                    n @ (0..=0x7f | 0xa0..=0xff) => n as u8,
+                    _ => CP1252_HIGH.iter().position(|&h| h == Some(c)).map(|i| 0x80 + i as u8).ok_or(c)?,
+                };
+                out.push(byte);
+            }
+        }
+        // encoding_rs only decodes UTF-16.
+        "utf-16" | "utf-16le" => {
+            for unit in text.encode_utf16() {
+                let bytes = if name == "utf-16" { unit.to_be_bytes() } else { unit.to_le_bytes() };
+                out.extend_from_slice(&bytes);
+            }
+        }
+        _ => {
+            let Some(enc) = rs_encoding(name) else {
+                out.extend_from_slice(text.as_bytes());
+                return Ok(());
+            };
+            let mut encoder = enc.new_encoder();
+            let mut read = 0;
+            loop {
+                let max = encoder.max_buffer_length_from_utf8_without_replacement(text.len() - read);
+                let max = max.unwrap_or(4096).max(16);
+                let mut buf = vec![0u8; max];
+                let (result, n, written) = encoder.encode_from_utf8_without_replacement(&text[read..], &mut buf, true);
+                out.extend_from_slice(&buf[..written]);
+                read += n;
+                match result {
+                    EncoderResult::InputEmpty => break,
+                    EncoderResult::OutputFull => continue,
+                    EncoderResult::Unmappable(c) => return Err(c),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(name: &str, data: &[u8]) -> (String, Vec<Vec<u8>>) {
+        let mut decoder = Decoder::new(name).unwrap();
+        let mut out = Vec::new();
+        let mut bad = Vec::new();
+        // Feed one byte at a time to exercise characters split over buffers.
+        for (i, chunk) in data.chunks(1).enumerate() {
+            let mut rest = chunk;
+            loop {
+                let (n, illegal) = decoder.decode(rest, &mut out, i + 1 == data.len());
+                bad.extend(illegal);
+                rest = &rest[n..];
+                if rest.is_empty() {
+                    break;
+                }
+            }
+        }
+        (String::from_utf8(out).unwrap(), bad)
+    }
+
+    #[test]
+    fn decode_and_encode() {
+        assert_eq!(decode_all("utf-8", "añ€".as_bytes()), ("añ€".to_string(), vec![]));
+        let bad = vec![b"\xff".to_vec(), b"\xe2\x82".to_vec()];
+        assert_eq!(decode_all("utf-8", b"a\xffb\xe2\x82"), ("ab".to_string(), bad));
+        assert_eq!(decode_all("latin1", b"caf\xe9\x81").0, "café\u{81}");
+        assert_eq!(decode_all("cp1252", b"\x80\x81").0, "€");
+        assert_eq!(decode_all("cp1252", b"\x80\x81").1, [b"\x81".to_vec()]);
+        assert_eq!(decode_all("utf-16le", b"a\x00\xac\x20").0, "a€");
+        assert_eq!(decode_all("utf-16", b"\x00a\x20\xac").0, "a€");
+        assert_eq!(decode_all("euc-jp", b"\xa4\xa2").0, "あ");
+        assert_eq!(decode_all("sjis", b"\x82\xa0").0, "あ");
+        assert_eq!(decode_all("gb18030", b"\xc4\xe3").0, "你");
+
+        let mut out = Vec::new();
+        encode("cp1252", "€é".as_bytes(), &mut out).unwrap();
+        encode("utf-16le", "a".as_bytes(), &mut out).unwrap();
+        encode("sjis", "あ".as_bytes(), &mut out).unwrap();
+        encode("latin1", b"\xff", &mut out).unwrap();
+        assert_eq!(out, b"\x80\xe9a\x00\x82\xa0\xff");
+        assert_eq!(encode("latin1", "€".as_bytes(), &mut out), Err('€'));
+
+        assert_eq!(canonical_name("UTF8"), "utf-8");
+        assert_eq!(canonical_name("Shift_JIS"), "sjis");
+        assert_eq!(detect_bom(b"\xff\xfea"), Some(("utf-16le", 2)));
+        assert!(!is_supported("foo"));
+    }
+}
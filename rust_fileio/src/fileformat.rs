@@ -0,0 +1,163 @@
+//! 'fileformat': the line breaks of a file, and their detection with
+//! 'fileformats'.
+
+/// The line break of a file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FileFormat {
+    /// NL
+    #[default]
+    Unix,
+    /// CR NL
+    Dos,
+    /// CR
+    Mac,
+}
+
+impl FileFormat {
+    /// The format for a 'fileformat' value.
+    pub fn from_name(name: &str) -> Option<FileFormat> {
+        match name {
+            "unix" => Some(FileFormat::Unix),
+            "dos" => Some(FileFormat::Dos),
+            "mac" => Some(FileFormat::Mac),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "unix",
+            FileFormat::Dos => "dos",
+            FileFormat::Mac => "mac",
+        }
+    }
+
+    /// The bytes that end a line.
+    pub fn line_break(self) -> &'static [u8] {
+        match self {
+            FileFormat::Unix => b"\n",
+            FileFormat::Dos => b"\r\n",
+            FileFormat::Mac => b"\r",
+        }
+    }
+
+    /// The indicator in file messages, "[dos]" or "[mac]".  Empty for the
+    /// native format.
+    pub fn indicator(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "",
+            FileFormat::Dos => "[dos]",
+            FileFormat::Mac => "[mac]",
+        }
+    }
+}
+
+/// Pick the format of text split at NL into `lines`, `eol` telling whether
+/// the last one ended in a NL, from the comma separated `fileformats`.  Like
+/// Vim: dos when every line ends in CR NL, unix when some do not, mac when
+/// there are more lone CRs than NLs.  Without any line break the first of
+/// `fileformats` is used.
+pub fn detect(lines: &[Vec<u8>], eol: bool, fileformats: &str) -> FileFormat {
+    let formats: Vec<FileFormat> = fileformats.split(',').filter_map(FileFormat::from_name).collect();
+    let Some(&first) = formats.first() else {
+        return FileFormat::Unix;
+    };
+    let try_dos = formats.contains(&FileFormat::Dos);
+    let try_unix = formats.contains(&FileFormat::Unix);
+    let try_mac = formats.contains(&FileFormat::Mac);
+
+    let nl_lines = if eol { lines.len() } else { lines.len().saturating_sub(1) };
+    let terminated = &lines[..nl_lines];
+    let cr_lines = terminated.iter().filter(|l| l.ends_with(b"\r")).count();
+    let lone_crs: usize = lines
+        .iter()
+        .enumerate()
+        .map(|(i, l)| l.iter().filter(|&&b| b == b'\r').count() - usize::from(i < nl_lines && l.ends_with(b"\r")))
+        .sum();
+
+    if nl_lines == 0 {
+        return if try_mac && lone_crs > 0 { FileFormat::Mac } else { first };
+    }
+    if try_dos && cr_lines == nl_lines {
+        FileFormat::Dos
+    } else if try_mac && lone_crs > nl_lines {
+        FileFormat::Mac
+    } else if try_unix {
+        FileFormat::Unix
+    } else if try_dos {
+        FileFormat::Dos
+    } else {
+        first
+    }
+}
+
+/// Turn `lines`, split at NL, into the lines of a file in format `ff`.
+/// Returns whether the last line ended in a line break and whether a dos
+/// line lacked its CR.
+pub fn apply(lines: &mut Vec<Vec<u8>>, eol: bool, ff: FileFormat) -> (bool, bool) {
+    match ff {
+        FileFormat::Unix => (eol, false),
+        FileFormat::Dos => {
+            let nl_lines = if eol { lines.len() } else { lines.len().saturating_sub(1) };
+            let mut cr_missing = false;
+            for line in &mut lines[..nl_lines] {
+                if line.ends_with(b"\r") {
+                    line.pop();
+                } else {
+                    cr_missing = true;
+                }
+            }
+            (eol, cr_missing)
+        }
+        FileFormat::Mac => {
+            // A NL is a character in a line of a mac file.
+            let mut text = lines.join(&b'\n');
+            if eol {
+                text.push(b'\n');
+            }
+            let eol = text.ends_with(b"\r");
+            if eol {
+                text.pop();
+            }
+            *lines = if text.is_empty() && !eol {
+                Vec::new()
+            } else {
+                text.split(|&b| b == b'\r').map(|l| l.to_vec()).collect()
+            };
+            (eol, false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(text: &str) -> (Vec<Vec<u8>>, bool) {
+        let eol = text.ends_with('\n');
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        (text.split('\n').map(|l| l.as_bytes().to_vec()).collect(), eol)
+    }
+
+    #[test]
+    fn detect_and_apply() {
+        let check = |text: &str, ffs: &str| {
+            let (lines, eol) = split(text);
+            detect(&lines, eol, ffs)
+        };
+        assert_eq!(check("a\r\nb\r\n", "unix,dos"), FileFormat::Dos);
+        assert_eq!(check("a\r\nb\n", "unix,dos"), FileFormat::Unix);
+        assert_eq!(check("a\r\nb\n", "dos"), FileFormat::Dos);
+        assert_eq!(check("a\rb\r", "unix,dos,mac"), FileFormat::Mac);
+        assert_eq!(check("a\rb\r", "unix,dos"), FileFormat::Unix);
+        assert_eq!(check("a", "dos,unix"), FileFormat::Dos);
+        assert_eq!(check("a\rb\rc\nd", "unix,mac"), FileFormat::Mac);
+
+        let (mut lines, eol) = split("a\r\nb\nc\r");
+        assert_eq!(apply(&mut lines, eol, FileFormat::Dos), (false, true));
+        assert_eq!(lines, [b"a".to_vec(), b"b".to_vec(), b"c\r".to_vec()]);
+        let (mut lines, eol) = split("a\rb\nc\r");
+        assert_eq!(apply(&mut lines, eol, FileFormat::Mac), (true, false));
+        assert_eq!(lines, [b"a".to_vec(), b"b\nc".to_vec()]);
+    }
+}
@@ -4,9 +4,21 @@ use std::fs;
 use std::os::raw::{c_char, c_int, c_void};
 use std::path::PathBuf;
 
-// Maximum number of bytes we are willing to read or write in one go.  This
-// prevents passing a ridiculously large length from C and accidentally
-// allocating excessive memory or overflowing usize calculations.
+mod encoding;
+mod fileformat;
+mod textfile;
+
+pub use encoding::canonical_name;
+pub use fileformat::FileFormat;
+pub use textfile::{
+    check_write, read_file, write_file, write_lines, BadChar, FileArgs, ReadOptions, ReadResult, WriteOptions,
+    WriteResult, DEFAULT_FILEENCODINGS, DEFAULT_FILEFORMATS,
+};
+
+// Maximum number of bytes we are willing to write in one go.  This prevents
+// passing a ridiculously large length from C and accidentally allocating
+// excessive memory or overflowing usize calculations.  Files are read in
+// chunks and have no limit.
 const MAX_IO_SIZE: usize = 10 * 1024 * 1024; // 10MB
 
 use rust_path::normalize_path;
@@ -22,7 +34,8 @@ fn c_path_to_normalized(ptr: *const c_char) -> Option<PathBuf> {
         .or_else(|| Some(PathBuf::from(path_str)))
 }
 
-/// Read the file at `fname`.
+/// Read the file at `fname`, detecting its encoding with the default
+/// 'fileencodings'.
 /// Unused parameters mirror the original C API.
 #[no_mangle]
 pub extern "C" fn readfile(
@@ -39,12 +52,7 @@ pub extern "C" fn readfile(
         None => return -1,
     };
 
-    match fs::metadata(&norm) {
-        Ok(meta) if meta.len() as usize <= MAX_IO_SIZE => (),
-        _ => return -1,
-    }
-
-    match fs::read(&norm) {
+    match read_file(&norm, &ReadOptions::default()) {
         Ok(_) => 0,
         Err(_) => -1,
     }
@@ -223,7 +231,7 @@ mod tests {
     }
 
     #[test]
-    fn read_too_large() {
+    fn read_large() {
         use tempfile::tempdir;
         let dir = tempdir().unwrap();
         let file_path = dir.path().join("huge.bin");
@@ -243,7 +251,7 @@ mod tests {
                 std::ptr::null_mut(),
                 0
             ),
-            -1
+            0
         );
     }
 
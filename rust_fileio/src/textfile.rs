@@ -0,0 +1,717 @@
+//! Reading and writing text files with 'fileencodings' detection and
+//! conversion to and from the internal UTF-8.
+//!
+//! Files are read in chunks, there is no limit on their size.  Each encoding
+//! in 'fileencodings' is tried in turn, the first one the whole file converts
+//! with is used.  When none fits the bytes are kept as they are and the first
+//! line that is not valid UTF-8 is reported as an illegal byte.  The line
+//! breaks are detected with 'fileformats' once the text is converted.
+//!
+//! An encrypted file is decrypted with 'key' before it is converted, the
+//! text is encrypted after conversion when 'key' is set.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use rust_crypt::{CryptError, CryptMethod};
+
+use crate::encoding::{self, Decoder};
+use crate::fileformat::{self, FileFormat};
+
+/// The bytes read from the file at a time.
+const READ_SIZE: usize = 64 * 1024;
+
+/// The default of 'fileencodings' when 'encoding' is utf-8.
+pub const DEFAULT_FILEENCODINGS: &str = "ucs-bom,utf-8,default,latin1";
+
+/// The default of 'fileformats' on Unix.
+pub const DEFAULT_FILEFORMATS: &str = "unix,dos";
+
+/// What to do with a byte that cannot be converted, `++bad=`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BadChar {
+    /// Replace it with this character.
+    Replace(char),
+    /// Keep the byte unconverted.
+    Keep,
+    /// Leave it out.
+    Drop,
+}
+
+/// The `++enc=`, `++bad=`, `++ff=` and `++[no]bin` arguments of a command
+/// that reads or writes a file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileArgs {
+    pub enc: Option<String>,
+    pub bad: Option<BadChar>,
+    pub ff: Option<FileFormat>,
+    pub bin: Option<bool>,
+}
+
+impl FileArgs {
+    /// Parse the `++` arguments at the start of `arg`.  Returns them and the
+    /// rest of `arg`, or "E474" for an unknown or invalid argument.
+    pub fn parse(arg: &str) -> Result<(FileArgs, &str), String> {
+        let mut args = FileArgs::default();
+        let mut rest = arg.trim_start();
+        while let Some(opt) = rest.strip_prefix("++") {
+            let end = opt.find(char::is_whitespace).unwrap_or(opt.len());
+            let (name, value) = match &opt[..end] {
+                "bin" | "binary" => ("bin", ""),
+                "nobin" | "nobinary" => ("nobin", ""),
+                arg => arg.split_once('=').ok_or_else(|| "E474: Invalid argument".to_string())?,
+            };
+            match name {
+                "bin" | "nobin" => args.bin = Some(name == "bin"),
+                "ff" | "fileformat" => {
+                    args.ff = Some(FileFormat::from_name(value).ok_or_else(|| "E474: Invalid argument".to_string())?)
+                }
+                "enc" | "encoding" if !value.is_empty() => args.enc = Some(encoding::canonical_name(value)),
+                "bad" => {
+                    args.bad = Some(match value {
+                        "keep" => BadChar::Keep,
+                        "drop" => BadChar::Drop,
+                        _ => {
+                            let mut chars = value.chars();
+                            match (chars.next(), chars.next()) {
+                                (Some(c), None) => BadChar::Replace(c),
+                                _ => return Err("E474: Invalid argument".to_string()),
+                            }
+                        }
+                    })
+                }
+                _ => return Err("E474: Invalid argument".to_string()),
+            }
+            rest = opt[end..].trim_start();
+        }
+        Ok((args, rest))
+    }
+}
+
+/// How to read a file.
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// 'fileencodings': the encodings to try, separated by commas.
+    pub fileencodings: String,
+    /// `++enc=`: use this encoding instead of detecting it.
+    pub enc: Option<String>,
+    /// `++bad=`: the default keeps illegal UTF-8 and replaces what cannot be
+    /// converted with "?".
+    pub bad: Option<BadChar>,
+    /// 'fileformats': the formats to detect, separated by commas.  Empty to
+    /// use unix.
+    pub fileformats: String,
+    /// `++ff=`: use this format instead of detecting it.
+    pub ff: Option<FileFormat>,
+    /// 'binary' or `++bin`: read the bytes as they are, only NL breaks lines.
+    pub binary: bool,
+    /// 'key': the passphrase for an encrypted file.  Reading one fails
+    /// without it.
+    pub key: Option<String>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            fileencodings: DEFAULT_FILEENCODINGS.to_string(),
+            enc: None,
+            bad: None,
+            fileformats: DEFAULT_FILEFORMATS.to_string(),
+            ff: None,
+            binary: false,
+            key: None,
+        }
+    }
+}
+
+impl ReadOptions {
+    /// Options with the `++` arguments of `args`.
+    pub fn with_args(mut self, args: &FileArgs) -> Self {
+        if args.enc.is_some() {
+            self.enc = args.enc.clone();
+        }
+        if args.bad.is_some() {
+            self.bad = args.bad;
+        }
+        if args.ff.is_some() {
+            self.ff = args.ff;
+        }
+        if let Some(bin) = args.bin {
+            self.binary = bin;
+        }
+        self
+    }
+}
+
+/// A file that was read.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReadResult {
+    /// The lines, in UTF-8 unless the conversion failed.
+    pub lines: Vec<Vec<u8>>,
+    /// The encoding the file was read with, for 'fileencoding'.  Empty when
+    /// the text was not converted.
+    pub fileencoding: String,
+    /// The file started with a byte order mark, for 'bomb'.
+    pub bomb: bool,
+    /// The last line ended in a line break, for 'eol'.
+    pub eol: bool,
+    /// The line breaks, for 'fileformat'.
+    pub fileformat: FileFormat,
+    /// Reading as dos found a line without a CR.
+    pub cr_missing: bool,
+    /// The first line with bytes that are not valid UTF-8.
+    pub illegal_byte: Option<usize>,
+    /// The first line with bytes that could not be converted.
+    pub conv_error: Option<usize>,
+    pub converted: bool,
+    /// `++enc=` named an encoding that cannot be converted.
+    pub not_converted: bool,
+    /// The size of the file.
+    pub bytes: u64,
+    /// The file was encrypted with this method, for 'cryptmethod'.
+    pub cryptmethod: Option<CryptMethod>,
+}
+
+impl ReadResult {
+    /// The message for reading file `fname`, like
+    /// `"fname" [noeol][converted][dos] 3L, 20B`.
+    pub fn message(&self, fname: &str) -> String {
+        let mut msg = format!("\"{}\" ", fname);
+        if !self.eol && !self.lines.is_empty() {
+            msg.push_str("[noeol]");
+        }
+        if self.cr_missing {
+            msg.push_str("[CR missing]");
+        }
+        if self.not_converted {
+            msg.push_str("[NOT converted]");
+        } else if self.converted {
+            msg.push_str("[converted]");
+        }
+        if let Some(method) = self.cryptmethod {
+            msg.push_str(&crypt_indicator(method));
+        }
+        if let Some(lnum) = self.conv_error {
+            msg.push_str(&format!("[CONVERSION ERROR in line {}]", lnum));
+        } else if let Some(lnum) = self.illegal_byte {
+            msg.push_str(&format!("[ILLEGAL BYTE in line {}]", lnum));
+        }
+        msg.push_str(self.fileformat.indicator());
+        if !msg.ends_with(' ') {
+            msg.push(' ');
+        }
+        msg.push_str(&format!("{}L, {}B", self.lines.len(), self.bytes));
+        msg
+    }
+}
+
+/// The indicator in file messages for an encrypted file: "[crypted]" for
+/// zip, otherwise the method, like Vim.
+fn crypt_indicator(method: CryptMethod) -> String {
+    match method {
+        CryptMethod::Zip => "[crypted]".to_string(),
+        method => format!("[{}]", method.name()),
+    }
+}
+
+/// Collects the converted text into lines.
+struct Lines {
+    lines: Vec<Vec<u8>>,
+    partial: Vec<u8>,
+}
+
+impl Lines {
+    /// The number of the line that text is added to.
+    fn lnum(&self) -> usize {
+        self.lines.len() + 1
+    }
+
+    fn add(&mut self, text: &[u8]) {
+        let mut parts = text.split(|&b| b == b'\n');
+        if let Some(first) = parts.next() {
+            self.partial.extend_from_slice(first);
+        }
+        for part in parts {
+            self.lines.push(std::mem::replace(&mut self.partial, part.to_vec()));
+        }
+    }
+}
+
+/// The outcome of reading the file with one encoding.
+enum Attempt {
+    Done(Lines),
+    /// Strict reading found bytes that do not convert.
+    Failed,
+}
+
+/// Read `file` from the start converting from `enc`.  When `strict` is set
+/// the first byte that does not convert fails, otherwise it is handled as
+/// `bad` says and its line stored in `error_line`.
+fn read_with(
+    file: &mut (impl Read + Seek),
+    skip: usize,
+    mut decoder: Decoder,
+    strict: bool,
+    bad: BadChar,
+    error_line: &mut Option<usize>,
+) -> io::Result<Attempt> {
+    file.seek(SeekFrom::Start(skip as u64))?;
+    let mut lines = Lines { lines: Vec::new(), partial: Vec::new() };
+    let mut buf = vec![0u8; READ_SIZE];
+    let mut out = Vec::with_capacity(READ_SIZE);
+    let mut eof = false;
+    while !eof {
+        let n = file.read(&mut buf)?;
+        eof = n == 0;
+        let mut rest = &buf[..n];
+        loop {
+            out.clear();
+            let (used, illegal) = decoder.decode(rest, &mut out, eof);
+            lines.add(&out);
+            rest = &rest[used..];
+            if let Some(bytes) = illegal {
+                if strict {
+                    return Ok(Attempt::Failed);
+                }
+                error_line.get_or_insert(lines.lnum());
+                match bad {
+                    BadChar::Replace(c) => lines.add(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    BadChar::Keep => lines.add(&bytes),
+                    BadChar::Drop => (),
+                }
+            }
+            if rest.is_empty() {
+                break;
+            }
+        }
+    }
+    Ok(Attempt::Done(lines))
+}
+
+/// Read the text file `path` with the encoding and format detection and
+/// conversion of `opts`.  An encrypted file is decrypted with the key of
+/// `opts`, failing without one.
+pub fn read_file(path: &Path, opts: &ReadOptions) -> io::Result<ReadResult> {
+    let mut file = File::open(path)?;
+    let bytes = file.metadata()?.len();
+    let mut head = [0u8; rust_crypt::MAGIC_LEN];
+    let head_len = file.read(&mut head)?;
+    if !rust_crypt::is_encrypted(&head[..head_len]) {
+        return Ok(ReadResult { bytes, ..read_text(&mut file, opts)? });
+    }
+    let Some(key) = opts.key.as_deref().filter(|key| !key.is_empty()) else {
+        return Err(io::Error::other(format!("Need encryption key for \"{}\"", path.display())));
+    };
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut data)?;
+    let (method, text) =
+        rust_crypt::decrypt(key, &data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    let result = read_text(&mut Cursor::new(text), opts)?;
+    Ok(ReadResult { bytes, cryptmethod: Some(method), ..result })
+}
+
+/// Read the text in `file`, see [`read_file`].
+fn read_text(file: &mut (impl Read + Seek), opts: &ReadOptions) -> io::Result<ReadResult> {
+    let mut head = [0u8; 3];
+    file.seek(SeekFrom::Start(0))?;
+    let head_len = file.read(&mut head)?;
+    let head = &head[..head_len];
+
+    let forced = opts.enc.as_ref().map(|enc| encoding::canonical_name(enc));
+    let candidates: Vec<String> = match &forced {
+        _ if opts.binary => Vec::new(),
+        Some(enc) => vec![enc.clone()],
+        None => opts.fileencodings.split(',').filter(|s| !s.is_empty()).map(encoding::canonical_name).collect(),
+    };
+
+    let mut result = ReadResult::default();
+    let mut found = None;
+    for name in candidates {
+        let (name, skip) = match name.as_str() {
+            "ucs-bom" => match encoding::detect_bom(head) {
+                Some((name, len)) => (name.to_string(), len),
+                None => continue,
+            },
+            "default" => ("utf-8".to_string(), 0),
+            _ => (name, 0),
+        };
+        let Some(decoder) = Decoder::new(&name) else {
+            result.not_converted = forced.is_some();
+            continue;
+        };
+        let bad = opts.bad.unwrap_or(if name == "utf-8" { BadChar::Keep } else { BadChar::Replace('?') });
+        let mut error_line = None;
+        if let Attempt::Done(lines) = read_with(file, skip, decoder, forced.is_none(), bad, &mut error_line)? {
+            if name == "utf-8" {
+                result.illegal_byte = error_line;
+            } else {
+                result.conv_error = error_line;
+            }
+            result.converted = !encoding::is_utf8(&name);
+            result.bomb = skip > 0;
+            result.fileencoding = name;
+            found = Some(lines);
+            break;
+        }
+    }
+    let mut lines = match found {
+        Some(lines) => lines,
+        None => {
+            let mut error_line = None;
+            let decoder = Decoder::Utf8(Vec::new());
+            let Attempt::Done(lines) = read_with(file, 0, decoder, false, BadChar::Keep, &mut error_line)? else {
+                unreachable!("reading with BadChar::Keep cannot fail");
+            };
+            if !opts.binary {
+                result.illegal_byte = error_line;
+            }
+            lines
+        }
+    };
+
+    let eol = lines.partial.is_empty();
+    if !eol {
+        lines.lines.push(lines.partial);
+    }
+    result.lines = lines.lines;
+    result.fileformat = match opts.ff {
+        _ if opts.binary => FileFormat::Unix,
+        Some(ff) => ff,
+        None => fileformat::detect(&result.lines, eol, &opts.fileformats),
+    };
+    (result.eol, result.cr_missing) = fileformat::apply(&mut result.lines, eol, result.fileformat);
+    Ok(result)
+}
+
+/// How to write a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// 'fileencoding', empty to write the text as it is.
+    pub fileencoding: String,
+    /// 'bomb': start with a byte order mark, for a Unicode encoding.
+    pub bomb: bool,
+    /// End the last line with a line break.
+    pub eol: bool,
+    /// The line breaks.
+    pub fileformat: FileFormat,
+    /// 'key': encrypt the file with this passphrase, empty for plain text.
+    pub key: String,
+    pub cryptmethod: CryptMethod,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            fileencoding: String::new(),
+            bomb: false,
+            eol: true,
+            fileformat: FileFormat::Unix,
+            key: String::new(),
+            cryptmethod: CryptMethod::default(),
+        }
+    }
+}
+
+/// A file that was written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteResult {
+    pub lines: usize,
+    pub bytes: u64,
+    /// The file did not exist before.
+    pub new: bool,
+    /// The lines were added to the end of the file.
+    pub appended: bool,
+    pub converted: bool,
+    pub eol: bool,
+    pub fileformat: FileFormat,
+    pub cryptmethod: Option<CryptMethod>,
+}
+
+impl WriteResult {
+    /// The result of writing `lines` lines, `bytes` bytes, as `opts` say.
+    pub fn new(lines: usize, bytes: u64, opts: &WriteOptions) -> Self {
+        WriteResult {
+            lines,
+            bytes,
+            new: false,
+            appended: false,
+            converted: !encoding::is_utf8(&encoding::canonical_name(&opts.fileencoding)),
+            eol: opts.eol,
+            fileformat: opts.fileformat,
+            cryptmethod: (!opts.key.is_empty()).then_some(opts.cryptmethod),
+        }
+    }
+
+    /// The message for writing file `fname`, like
+    /// `"fname" [New][dos] 3L, 20B written`.
+    pub fn message(&self, fname: &str) -> String {
+        let mut msg = format!("\"{}\" ", fname);
+        if self.converted {
+            msg.push_str("[converted]");
+        }
+        if let Some(method) = self.cryptmethod {
+            msg.push_str(&crypt_indicator(method));
+        }
+        if self.new {
+            msg.push_str("[New]");
+        }
+        if !self.eol && self.lines > 0 {
+            msg.push_str("[noeol]");
+        }
+        msg.push_str(self.fileformat.indicator());
+        if !msg.ends_with(' ') {
+            msg.push(' ');
+        }
+        let done = if self.appended { "appended" } else { "written" };
+        msg.push_str(&format!("{}L, {}B {}", self.lines, self.bytes, done));
+        msg
+    }
+}
+
+/// Convert line `line` to `enc` and append it, with line break `brk` if
+/// there is one, to `out`.  Gives E513 when it does not convert.
+fn encode_line(enc: &str, line: &[u8], lnum: usize, brk: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+    encoding::encode(enc, line, out).map_err(|_| {
+        format!("E513: Write error, conversion failed in line {} (make 'fenc' empty to override)", lnum)
+    })?;
+    encoding::encode(enc, brk, out).map_err(|_| "E513: Write error, conversion failed".to_string())
+}
+
+/// The encoding to write with, E213 when text cannot be converted to it.
+fn write_encoding(opts: &WriteOptions) -> Result<String, String> {
+    let enc = encoding::canonical_name(&opts.fileencoding);
+    if !enc.is_empty() && !encoding::is_supported(&enc) {
+        return Err("E213: Cannot convert (add ! to write without conversion)".to_string());
+    }
+    Ok(enc)
+}
+
+/// The line break after line `i` of `count` lines.
+fn line_break(opts: &WriteOptions, i: usize, count: usize) -> &'static [u8] {
+    if opts.eol || i + 1 < count {
+        opts.fileformat.line_break()
+    } else {
+        b""
+    }
+}
+
+/// Check that `lines` can be written as `opts` say, before a file is
+/// touched: gives E213 or E513 when they cannot be converted, an error
+/// when 'cryptmethod' can only be read.
+pub fn check_write<L: AsRef<[u8]>>(lines: &[L], opts: &WriteOptions) -> Result<(), String> {
+    if !opts.key.is_empty() && opts.cryptmethod == CryptMethod::Zip {
+        return Err(CryptError::ReadOnly(opts.cryptmethod).to_string());
+    }
+    let enc = write_encoding(opts)?;
+    let mut scratch = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        scratch.clear();
+        encode_line(&enc, line.as_ref(), i + 1, line_break(opts, i, lines.len()), &mut scratch)?;
+    }
+    Ok(())
+}
+
+/// Write `lines`, UTF-8, to `writer` converted as `opts` say, after a byte
+/// order mark with 'bomb'.  With 'key' the text is encrypted as a whole.
+/// Returns the number of bytes written.
+pub fn write_lines<W: Write, L: AsRef<[u8]>>(writer: &mut W, lines: &[L], opts: &WriteOptions) -> Result<u64, String> {
+    let write_error = |_| "E514: Write error (file system full?)".to_string();
+    if !opts.key.is_empty() {
+        let mut text = Vec::new();
+        write_lines(&mut text, lines, &WriteOptions { key: String::new(), ..opts.clone() })?;
+        let data = rust_crypt::encrypt(opts.cryptmethod, &opts.key, &text).map_err(|err| err.to_string())?;
+        writer.write_all(&data).and_then(|_| writer.flush()).map_err(write_error)?;
+        return Ok(data.len() as u64);
+    }
+    let enc = write_encoding(opts)?;
+    let mut writer = BufWriter::new(writer);
+    let mut bytes = 0;
+    if opts.bomb && encoding::is_unicode(&enc) {
+        writer.write_all(encoding::bom(&enc)).map_err(write_error)?;
+        bytes += encoding::bom(&enc).len() as u64;
+    }
+    let mut scratch = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        scratch.clear();
+        encode_line(&enc, line.as_ref(), i + 1, line_break(opts, i, lines.len()), &mut scratch)?;
+        writer.write_all(&scratch).map_err(write_error)?;
+        bytes += scratch.len() as u64;
+    }
+    writer.flush().map_err(write_error)?;
+    Ok(bytes)
+}
+
+/// Write `lines`, UTF-8, to the file `path` converted to the encoding of
+/// `opts`.  Every line is converted before the file is touched, so that a
+/// conversion error does not leave it half written.
+pub fn write_file<L: AsRef<[u8]>>(path: &Path, lines: &[L], opts: &WriteOptions) -> Result<WriteResult, String> {
+    check_write(lines, opts)?;
+    let new = !path.exists();
+    let mut file = File::create(path).map_err(|_| format!("E212: Can't open file for writing: {}", path.display()))?;
+    let bytes = write_lines(&mut file, lines, opts)?;
+    Ok(WriteResult { new, ..WriteResult::new(lines.len(), bytes, opts) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(data: &[u8], opts: &ReadOptions) -> ReadResult {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text");
+        std::fs::write(&path, data).unwrap();
+        read_file(&path, opts).unwrap()
+    }
+
+    fn text(result: &ReadResult) -> Vec<String> {
+        result.lines.iter().map(|l| String::from_utf8_lossy(l).into_owned()).collect()
+    }
+
+    #[test]
+    fn detect_encoding() {
+        let utf8 = read("añ\n€\n".as_bytes(), &ReadOptions::default());
+        assert_eq!(text(&utf8), ["añ", "€"]);
+        assert_eq!((utf8.fileencoding.as_str(), utf8.converted), ("utf-8", false));
+        assert_eq!(utf8.message("x"), "\"x\" 2L, 8B");
+
+        let bom = read(b"\xef\xbb\xbfa", &ReadOptions::default());
+        assert_eq!((text(&bom), bom.bomb, bom.eol), (vec!["a".to_string()], true, false));
+        assert_eq!(bom.message("x"), "\"x\" [noeol] 1L, 4B");
+        let utf16 = read(b"\xff\xfea\x00\n\x00", &ReadOptions::default());
+        assert_eq!((text(&utf16), utf16.fileencoding.as_str()), (vec!["a".to_string()], "utf-16le"));
+
+        let latin1 = read(b"caf\xe9\n", &ReadOptions::default());
+        assert_eq!((text(&latin1), latin1.fileencoding.as_str()), (vec!["café".to_string()], "latin1"));
+        assert_eq!(latin1.message("x"), "\"x\" [converted] 1L, 5B");
+
+        let fencs = |s: &str| ReadOptions { fileencodings: s.to_string(), ..ReadOptions::default() };
+        let cp1252 = read(b"\x80\x81\n\x80\n", &fencs("cp1252,latin1"));
+        assert_eq!(cp1252.fileencoding, "latin1");
+        assert_eq!(text(&read(b"\x80\n", &fencs("cp1252,latin1"))), ["€"]);
+        assert_eq!(text(&read(b"\xa4\xa2\n", &fencs("utf-8,euc-jp"))), ["あ"]);
+        assert_eq!(text(&read(b"\x82\xa0\n", &fencs("utf-8,sjis"))), ["あ"]);
+        assert_eq!(text(&read(b"\xc4\xe3\n", &fencs("utf-8,gb18030"))), ["你"]);
+
+        // Nothing fits: the bytes are kept and reported.
+        let none = read(b"ok\nbad\xff\n", &fencs("utf-8"));
+        assert_eq!((none.fileencoding.as_str(), none.illegal_byte), ("", Some(2)));
+        assert_eq!(none.lines[1], b"bad\xff");
+        assert_eq!(none.message("x"), "\"x\" [ILLEGAL BYTE in line 2] 2L, 8B");
+    }
+
+    #[test]
+    fn file_args() {
+        let (args, rest) = FileArgs::parse("++enc=Latin1 ++bad=drop file").unwrap();
+        assert_eq!(args, FileArgs { enc: Some("latin1".into()), bad: Some(BadChar::Drop), ..FileArgs::default() });
+        assert_eq!(rest, "file");
+        assert_eq!(FileArgs::parse("++bad=X").unwrap().0.bad, Some(BadChar::Replace('X')));
+        assert_eq!(FileArgs::parse("++bad=xy"), Err("E474: Invalid argument".to_string()));
+        assert_eq!(FileArgs::parse("++foo=x"), Err("E474: Invalid argument".to_string()));
+
+        let opts = |arg| ReadOptions::default().with_args(&FileArgs::parse(arg).unwrap().0);
+        let utf8 = read(b"a\xffb\n\xfe\n", &opts("++enc=utf-8"));
+        assert_eq!((utf8.lines[0].as_slice(), utf8.illegal_byte), (&b"a\xffb"[..], Some(1)));
+        assert_eq!(text(&read(b"a\xffb\n", &opts("++enc=utf-8 ++bad=drop"))), ["ab"]);
+        let cp1252 = read(b"a\nb\x81\n", &opts("++enc=cp1252"));
+        assert_eq!((text(&cp1252), cp1252.conv_error), (vec!["a".into(), "b?".into()], Some(2)));
+        assert_eq!(cp1252.message("x"), "\"x\" [converted][CONVERSION ERROR in line 2] 2L, 5B");
+        let foo = read(b"a\n", &opts("++enc=foo"));
+        assert_eq!(foo.message("x"), "\"x\" [NOT converted] 1L, 2B");
+    }
+
+    #[test]
+    fn write_converted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text");
+        let opts = WriteOptions { fileencoding: "utf-16le".into(), bomb: true, ..WriteOptions::default() };
+        let written = write_file(&path, &["a", "€"], &opts).unwrap();
+        assert_eq!(written.message("x"), "\"x\" [converted][New] 2L, 10B written");
+        assert_eq!(std::fs::read(&path).unwrap(), b"\xff\xfea\x00\n\x00\xac\x20\n\x00");
+        let back = read_file(&path, &ReadOptions::default()).unwrap();
+        assert_eq!((text(&back), back.bomb), (vec!["a".into(), "€".into()], true));
+
+        let opts = WriteOptions { fileencoding: "latin1".into(), eol: false, ..WriteOptions::default() };
+        assert_eq!(
+            write_file(&path, &["é", "€"], &opts),
+            Err("E513: Write error, conversion failed in line 2 (make 'fenc' empty to override)".to_string())
+        );
+        assert_eq!(std::fs::read(&path).unwrap().len(), 10);
+        let written = write_file(&path, &["é"], &opts).unwrap();
+        assert_eq!(written.message("x"), "\"x\" [converted][noeol] 1L, 1B written");
+        assert_eq!(std::fs::read(&path).unwrap(), b"\xe9");
+        let opts = WriteOptions { fileencoding: "foo".into(), ..WriteOptions::default() };
+        assert!(write_file(&path, &["a"], &opts).unwrap_err().starts_with("E213:"));
+    }
+
+    #[test]
+    fn file_formats() {
+        let dos = read(b"a\r\nb\r\n", &ReadOptions::default());
+        assert_eq!((text(&dos), dos.fileformat, dos.eol), (vec!["a".into(), "b".into()], FileFormat::Dos, true));
+        assert_eq!(dos.message("x"), "\"x\" [dos] 2L, 6B");
+        let mixed = read(b"a\r\nb\n", &ReadOptions::default());
+        assert_eq!((text(&mixed), mixed.fileformat), (vec!["a\r".into(), "b".into()], FileFormat::Unix));
+        let opts = |arg| ReadOptions::default().with_args(&FileArgs::parse(arg).unwrap().0);
+        let forced = read(b"a\r\nb\nc", &opts("++ff=dos"));
+        assert_eq!(text(&forced), ["a", "b", "c"]);
+        assert_eq!(forced.message("x"), "\"x\" [noeol][CR missing][dos] 3L, 6B");
+        let mac = read(b"a\rb", &ReadOptions { fileformats: "unix,mac".into(), ..ReadOptions::default() });
+        assert_eq!((text(&mac), mac.eol), (vec!["a".into(), "b".into()], false));
+        assert_eq!(mac.message("x"), "\"x\" [noeol][mac] 2L, 3B");
+        let bin = read(b"a\r\n\xe9\n", &opts("++bin"));
+        assert_eq!((bin.lines[1].as_slice(), bin.fileformat, bin.illegal_byte), (&b"\xe9"[..], FileFormat::Unix, None));
+        assert_eq!(FileArgs::parse("++ff=foo"), Err("E474: Invalid argument".to_string()));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text");
+        let opts = WriteOptions { fileformat: FileFormat::Dos, ..WriteOptions::default() };
+        let written = write_file(&path, &["a", "b"], &opts).unwrap();
+        assert_eq!(written.message("x"), "\"x\" [New][dos] 2L, 6B written");
+        assert_eq!(std::fs::read(&path).unwrap(), b"a\r\nb\r\n");
+        let opts = WriteOptions {
+            fileformat: FileFormat::Mac,
+            fileencoding: "utf-16".into(),
+            eol: false,
+            bomb: false,
+            ..WriteOptions::default()
+        };
+        write_file(&path, &["a", "b"], &opts).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"\x00a\x00\r\x00b");
+    }
+
+    #[test]
+    fn encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text");
+        let opts = WriteOptions { key: "secret".into(), fileformat: FileFormat::Dos, ..WriteOptions::default() };
+        let written = write_file(&path, &["a", "é"], &opts).unwrap();
+        assert_eq!(written.message("x"), "\"x\" [xchacha20scrypt][New][dos] 2L, 84B written");
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.starts_with(b"VimCrypt~s1!") && !data.windows(2).any(|w| w == b"\r\n"));
+
+        let err = read_file(&path, &ReadOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), format!("Need encryption key for \"{}\"", path.display()));
+        let wrong = ReadOptions { key: Some("wrong".into()), ..ReadOptions::default() };
+        assert_eq!(read_file(&path, &wrong).unwrap_err().to_string(), "E1200: Decryption failed!");
+        let back = read(&data, &ReadOptions { key: Some("secret".into()), ..ReadOptions::default() });
+        assert_eq!((text(&back), back.fileformat), (vec!["a".into(), "é".into()], FileFormat::Dos));
+        assert_eq!(back.cryptmethod, Some(CryptMethod::XChaCha20Scrypt));
+        assert_eq!(back.message("x"), "\"x\" [xchacha20scrypt][dos] 2L, 84B");
+
+        let zip = WriteOptions { cryptmethod: CryptMethod::Zip, ..opts };
+        assert!(write_file(&path, &["a"], &zip).unwrap_err().contains("cryptmethod=zip can only be used for reading"));
+    }
+
+    #[test]
+    fn large_file_is_streamed() {
+        let mut data = Vec::new();
+        for _ in 0..200_000 {
+            data.extend_from_slice("line é\n".as_bytes());
+        }
+        data.extend_from_slice(b"\xe9\n");
+        let result = read(&data, &ReadOptions::default());
+        assert_eq!((result.lines.len(), result.fileencoding.as_str()), (200_001, "latin1"));
+        assert_eq!(result.lines[0], "line Ã©".as_bytes());
+    }
+}
@@ -1,34 +1,200 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+//! A cycle collector for reference counted objects.
+//!
+//! Objects are owned through `Rc` as usual and freed when the last reference
+//! goes away.  The heap only keeps a weak reference to each object, so that
+//! it can find the ones that are kept alive by nothing but a reference cycle.
+//!
+//! A collection marks everything reachable from the roots the caller passes
+//! in.  Objects that are referenced from outside the traced graph, e.g. by a
+//! value on the Rust stack or by code that registers no roots, are found by
+//! trial deletion: when an object has more strong references than traced
+//! objects refer to it, something else holds it, and it is marked too.  The
+//! objects left unmarked are only reachable from each other; they are
+//! cleared, which breaks the cycles and lets reference counting free them.
 
-/// A very small garbage-collected heap using Rust ownership.
-///
-/// Values allocated through [`GcHeap::alloc`] are reference counted and
-/// automatically cleaned up when the heap is dropped.
-pub struct GcHeap<T> {
-    objects: RefCell<Vec<Rc<T>>>,
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+/// Number of objects allocated after which [`GcHeap::should_collect`]
+/// returns true.
+pub const DEFAULT_THRESHOLD: usize = 10000;
+
+/// An object that can refer to other objects on the heap.
+pub trait Trace: Any {
+    /// Pass every object this one refers to to [`Tracer::visit`].
+    fn trace(&self, tracer: &mut Tracer);
+
+    /// Drop the references to other objects.  Called for an object that is
+    /// only reachable through a cycle, it does not need to leave the object
+    /// in a useful state.
+    fn clear(&self) {}
+}
+
+fn addr<T: ?Sized>(obj: &Rc<T>) -> *const () {
+    Rc::as_ptr(obj) as *const ()
 }
 
-impl<T> GcHeap<T> {
+/// Walks the object graph, see [`Trace::trace`].
+pub struct Tracer {
+    marked: HashSet<*const ()>,
+    pending: Vec<Rc<dyn Trace>>,
+    /// When counting references instead of marking: how often each object
+    /// was visited.
+    counts: Option<HashMap<*const (), usize>>,
+}
+
+impl Tracer {
+    fn marking() -> Self {
+        Tracer { marked: HashSet::new(), pending: Vec::new(), counts: None }
+    }
+
+    fn counting() -> Self {
+        Tracer { counts: Some(HashMap::new()), ..Tracer::marking() }
+    }
+
+    /// Whether `ptr` has to be traced further.
+    fn reach(&mut self, ptr: *const ()) -> bool {
+        match &mut self.counts {
+            Some(counts) => {
+                *counts.entry(ptr).or_default() += 1;
+                false
+            }
+            None => self.marked.insert(ptr),
+        }
+    }
+
+    /// Mark `obj` as reachable.
+    pub fn visit<T: Trace>(&mut self, obj: &Rc<T>) {
+        if self.reach(addr(obj)) {
+            self.pending.push(obj.clone());
+        }
+    }
+
+    /// Like [`Tracer::visit`] for an object of unknown type.
+    pub fn visit_dyn(&mut self, obj: &Rc<dyn Trace>) {
+        if self.reach(addr(obj)) {
+            self.pending.push(obj.clone());
+        }
+    }
+
+    /// Trace the objects marked so far, until everything reachable from them
+    /// is marked.
+    fn run(&mut self) {
+        while let Some(obj) = self.pending.pop() {
+            obj.trace(self);
+        }
+    }
+}
+
+/// The objects that take part in cycle collection.
+pub struct GcHeap {
+    objects: RefCell<Vec<Weak<dyn Trace>>>,
+    /// Objects allocated since the last collection.
+    allocated: Cell<usize>,
+    threshold: Cell<usize>,
+}
+
+impl GcHeap {
     /// Create a new empty heap.
     pub fn new() -> Self {
-        Self { objects: RefCell::new(Vec::new()) }
+        Self { objects: RefCell::new(Vec::new()), allocated: Cell::new(0), threshold: Cell::new(DEFAULT_THRESHOLD) }
     }
 
     /// Allocate a value on the heap and return a [`Gc`] handle.
-    pub fn alloc(&self, value: T) -> Gc<T> {
+    pub fn alloc<T: Trace>(&self, value: T) -> Gc<T> {
         let rc = Rc::new(value);
-        self.objects.borrow_mut().push(rc.clone());
+        self.track(&rc);
         Gc { inner: rc }
     }
+
+    /// Add an object that was allocated elsewhere.
+    pub fn track<T: Trace>(&self, obj: &Rc<T>) {
+        let weak: Weak<dyn Trace> = Rc::downgrade(obj) as Weak<dyn Trace>;
+        self.objects.borrow_mut().push(weak);
+        self.allocated.set(self.allocated.get() + 1);
+    }
+
+    /// Number of objects that are still alive.
+    pub fn len(&self) -> usize {
+        self.objects.borrow().iter().filter(|w| w.strong_count() > 0).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether enough objects were allocated since the last collection to
+    /// make another one worthwhile.
+    pub fn should_collect(&self) -> bool {
+        self.allocated.get() >= self.threshold.get()
+    }
+
+    pub fn set_threshold(&self, threshold: usize) {
+        self.threshold.set(threshold);
+    }
+
+    /// Free the objects that are only kept alive by cycles.  `roots` passes
+    /// the objects that are in use to [`Tracer::visit`].  Returns the number
+    /// of objects freed.
+    pub fn collect(&self, roots: impl FnOnce(&mut Tracer)) -> usize {
+        self.allocated.set(0);
+        let objects: Vec<Rc<dyn Trace>> = {
+            let mut weak = self.objects.borrow_mut();
+            weak.retain(|w| w.strong_count() > 0);
+            weak.iter().filter_map(Weak::upgrade).collect()
+        };
+
+        // Count the references between tracked objects, before marking adds
+        // references of its own.  One reference of each object is in
+        // `objects`.
+        let mut counter = Tracer::counting();
+        for obj in &objects {
+            obj.trace(&mut counter);
+        }
+        let internal = counter.counts.take().unwrap_or_default();
+        let external: Vec<&Rc<dyn Trace>> = objects
+            .iter()
+            .filter(|obj| Rc::strong_count(obj) - 1 > internal.get(&addr(obj)).copied().unwrap_or(0))
+            .collect();
+
+        let mut tracer = Tracer::marking();
+        roots(&mut tracer);
+        for obj in external {
+            tracer.visit_dyn(obj);
+        }
+        tracer.run();
+
+        let garbage: Vec<Rc<dyn Trace>> =
+            objects.into_iter().filter(|obj| !tracer.marked.contains(&addr(obj))).collect();
+        drop(tracer);
+        // All garbage is kept alive by `garbage` until every object has been
+        // cleared, clear() does not have to deal with freed objects.
+        for obj in &garbage {
+            obj.clear();
+        }
+        garbage.len()
+    }
+}
+
+impl Default for GcHeap {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Handle to a value allocated on a [`GcHeap`].
-#[derive(Clone)]
 pub struct Gc<T> {
     inner: Rc<T>,
 }
 
+impl<T> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        Gc { inner: self.inner.clone() }
+    }
+}
+
 impl<T> std::ops::Deref for Gc<T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
@@ -40,22 +206,82 @@ impl<T> std::ops::Deref for Gc<T> {
 mod tests {
     use super::*;
 
-    #[test]
-    fn alloc_and_drop() {
-        struct DropTracker<'a>(&'a RefCell<bool>);
-        impl<'a> Drop for DropTracker<'a> {
-            fn drop(&mut self) {
-                *self.0.borrow_mut() = true;
+    struct Node {
+        next: RefCell<Option<Gc<Node>>>,
+        dropped: Rc<Cell<bool>>,
+    }
+
+    impl Trace for Node {
+        fn trace(&self, tracer: &mut Tracer) {
+            if let Some(next) = &*self.next.borrow() {
+                tracer.visit(&next.inner);
             }
         }
 
-        let dropped = RefCell::new(false);
+        fn clear(&self) {
+            let next = self.next.borrow_mut().take();
+            drop(next);
+        }
+    }
+
+    impl Drop for Node {
+        fn drop(&mut self) {
+            self.dropped.set(true);
+        }
+    }
+
+    fn node(heap: &GcHeap, dropped: &Rc<Cell<bool>>) -> Gc<Node> {
+        heap.alloc(Node { next: RefCell::new(None), dropped: dropped.clone() })
+    }
+
+    #[test]
+    fn alloc_and_drop() {
+        let dropped = Rc::new(Cell::new(false));
         {
             let heap = GcHeap::new();
-            let _v = heap.alloc(DropTracker(&dropped));
-            assert_eq!(*dropped.borrow(), false);
+            let _v = node(&heap, &dropped);
+            assert!(!dropped.get());
         }
         // Heap dropped, value should be dropped as well.
-        assert_eq!(*dropped.borrow(), true);
+        assert!(dropped.get());
+    }
+
+    #[test]
+    fn collects_cycles_only() {
+        let heap = GcHeap::new();
+        let (a_dropped, b_dropped) = (Rc::new(Cell::new(false)), Rc::new(Cell::new(false)));
+        let a = node(&heap, &a_dropped);
+        let b = node(&heap, &b_dropped);
+        *a.next.borrow_mut() = Some(b.clone());
+        *b.next.borrow_mut() = Some(a.clone());
+        drop(b);
+
+        // `a` is still held here, which keeps the whole cycle.
+        assert_eq!(heap.collect(|_| {}), 0);
+        assert_eq!(heap.len(), 2);
+
+        // Held by a root only.
+        let root = a.inner.clone();
+        drop(a);
+        assert_eq!(heap.collect(|tracer| tracer.visit(&root)), 0);
+
+        drop(root);
+        assert!(!a_dropped.get());
+        assert_eq!(heap.collect(|_| {}), 2);
+        assert!(a_dropped.get() && b_dropped.get());
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn threshold() {
+        let heap = GcHeap::new();
+        heap.set_threshold(2);
+        let dropped = Rc::new(Cell::new(false));
+        let _a = node(&heap, &dropped);
+        assert!(!heap.should_collect());
+        let _b = node(&heap, &dropped);
+        assert!(heap.should_collect());
+        heap.collect(|_| {});
+        assert!(!heap.should_collect());
     }
 }
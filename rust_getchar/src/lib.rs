@@ -1,5 +1,9 @@
 use std::os::raw::{c_int, c_uint};
 use rust_input::{InputContext, rs_input_get, rs_input_unget, rs_input_avail};
+use rust_map::{KeySource, MapTable, Mapping, Mode, TypeBuf};
+
+pub use rust_input::IdleHandler;
+pub use rust_map::MapHost;
 
 #[no_mangle]
 pub extern "C" fn rs_getchar(ctx: *mut InputContext) -> c_int {
@@ -11,6 +15,61 @@ pub extern "C" fn rs_getchar_avail(ctx: *mut InputContext) -> c_int {
     rs_input_avail(ctx)
 }
 
+/// Get a typed key like rs_getchar(), but wait for it at most `timeout`
+/// milliseconds.  Meanwhile `idle` fires timers, so that their callbacks run
+/// while the user is not typing.
+pub fn getchar_wait(ctx: &mut InputContext, timeout: Option<u64>, idle: &mut dyn IdleHandler) -> Option<u32> {
+    ctx.wait_key(timeout, idle)
+}
+
+/// Typed keys for [`TypeBuf::vgetc`]: keys from the input context, timers
+/// run while waiting.
+struct Typed<'a, H> {
+    ctx: &'a mut InputContext,
+    host: &'a mut H,
+}
+
+impl<H: MapHost + IdleHandler> MapHost for Typed<'_, H> {
+    fn maps(&self) -> &MapTable {
+        self.host.maps()
+    }
+
+    fn abbrs(&self) -> &MapTable {
+        self.host.abbrs()
+    }
+
+    fn timeoutlen(&self) -> Option<u64> {
+        self.host.timeoutlen()
+    }
+
+    fn eval_map_expr(&mut self, map: &Mapping) -> String {
+        self.host.eval_map_expr(map)
+    }
+
+    fn exec_map_cmd(&mut self, map: &Mapping, cmd: &str) {
+        self.host.exec_map_cmd(map, cmd)
+    }
+}
+
+impl<H: MapHost + IdleHandler> KeySource for Typed<'_, H> {
+    fn get_key(&mut self, timeout: Option<u64>) -> Option<u32> {
+        self.ctx.wait_key(timeout, self.host)
+    }
+}
+
+/// Get a key with mappings for `mode` expanded, like Vim's vgetc().  When
+/// the typed keys are the start of a longer mapping, the next key is waited
+/// for as long as `host` says.  Returns None when no key was typed.
+pub fn vgetc<H: MapHost + IdleHandler>(
+    ctx: &mut InputContext,
+    typebuf: &mut TypeBuf,
+    mode: Mode,
+    buf: usize,
+    host: &mut H,
+) -> Result<Option<u32>, String> {
+    typebuf.vgetc(mode, buf, &mut Typed { ctx, host })
+}
+
 #[no_mangle]
 pub extern "C" fn rs_ungetchar(ctx: *mut InputContext, key: c_uint) {
     rs_input_unget(ctx, key)
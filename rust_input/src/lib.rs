@@ -8,6 +8,24 @@ pub struct InputContext {
     record: Vec<u32>,
 }
 
+/// Work done while waiting for a key, such as firing timers.
+pub trait IdleHandler {
+    /// Do the work that is due.  Returns the milliseconds until more work
+    /// is due, None when nothing is pending.
+    fn run_due(&mut self) -> Option<u64>;
+
+    /// Wait `ms` milliseconds.
+    fn sleep(&mut self, ms: u64);
+
+    /// Wait at most `ms` milliseconds for a typed key and return it as soon
+    /// as there is one.  Without a source of keys of its own the handler
+    /// only sleeps: keys then come from the input context.
+    fn poll_key(&mut self, ms: u64) -> Option<u32> {
+        self.sleep(ms);
+        None
+    }
+}
+
 impl InputContext {
     fn new() -> Self {
         Self {
@@ -16,6 +34,38 @@ impl InputContext {
             record: Vec::new(),
         }
     }
+
+    /// Get the next key, waiting at most `timeout` milliseconds, or as long
+    /// as `idle` has work pending for None.  The idle work is only done
+    /// while no key is available, never in between the keys of a mapping or
+    /// while typeahead is pending.  Keys are polled for until the next work
+    /// is due, a key typed meanwhile is returned right away.  Returns None
+    /// when no key arrived.
+    pub fn wait_key(&mut self, timeout: Option<u64>, idle: &mut dyn IdleHandler) -> Option<u32> {
+        let mut waited = 0;
+        loop {
+            if let Some(key) = self.input.pop_front() {
+                return Some(key);
+            }
+            let next = idle.run_due();
+            if !self.input.is_empty() {
+                continue;
+            }
+            let left = timeout.map(|t| t.saturating_sub(waited));
+            let wait = match (next, left) {
+                (_, Some(0)) | (None, None) => return None,
+                (Some(next), Some(left)) => next.min(left),
+                (Some(ms), None) | (None, Some(ms)) => ms,
+            };
+            // Always make progress, also when work is due right away.
+            let wait = wait.max(1);
+            if let Some(key) = idle.poll_key(wait) {
+                self.record.push(key);
+                return Some(key);
+            }
+            waited += wait;
+        }
+    }
 }
 
 #[no_mangle]
@@ -197,6 +247,84 @@ mod tests {
         }
     }
 
+    /// Fires a key after 30 ms of virtual time.
+    struct KeyTimer {
+        now: u64,
+        fired: bool,
+    }
+
+    impl IdleHandler for KeyTimer {
+        fn run_due(&mut self) -> Option<u64> {
+            if self.fired {
+                return None;
+            }
+            if self.now >= 30 {
+                self.fired = true;
+                return None;
+            }
+            Some(30 - self.now)
+        }
+
+        fn sleep(&mut self, ms: u64) {
+            self.now += ms;
+        }
+    }
+
+    #[test]
+    fn wait_runs_idle_work() {
+        let mut ctx = InputContext::new();
+        let mut idle = KeyTimer { now: 0, fired: false };
+        assert_eq!(ctx.wait_key(Some(10), &mut idle), None);
+        assert_eq!(idle.now, 10);
+        assert_eq!(ctx.wait_key(None, &mut idle), None);
+        assert!(idle.fired);
+        assert_eq!(idle.now, 30);
+        ctx.input.push_back('k' as u32);
+        assert_eq!(ctx.wait_key(None, &mut idle), Some('k' as u32));
+    }
+
+    /// A timer due at 30 ms and a key typed at 12 ms, in virtual time.
+    struct TypedKey {
+        now: u64,
+        fired: bool,
+        key_at: u64,
+    }
+
+    impl IdleHandler for TypedKey {
+        fn run_due(&mut self) -> Option<u64> {
+            if self.now >= 30 {
+                self.fired = true;
+            }
+            Some(30u64.saturating_sub(self.now)).filter(|_| !self.fired)
+        }
+
+        fn sleep(&mut self, ms: u64) {
+            self.now += ms;
+        }
+
+        fn poll_key(&mut self, ms: u64) -> Option<u32> {
+            if self.now + ms < self.key_at {
+                self.now += ms;
+                return None;
+            }
+            self.now = self.now.max(self.key_at);
+            Some('k' as u32)
+        }
+    }
+
+    #[test]
+    fn key_before_timer() {
+        let mut ctx = InputContext::new();
+        let mut idle = TypedKey { now: 0, fired: false, key_at: 12 };
+        // The key is not held back until the timer fires.
+        assert_eq!(ctx.wait_key(None, &mut idle), Some('k' as u32));
+        assert_eq!((idle.now, idle.fired), (12, false));
+        assert_eq!(ctx.record, ['k' as u32]);
+        idle.key_at = 100;
+        assert_eq!(ctx.wait_key(Some(50), &mut idle), None);
+        assert_eq!((idle.now, idle.fired), (62, true));
+    }
+
     #[test]
     fn unget_and_avail() {
         let ctx = rs_input_context_new();
@@ -2,6 +2,8 @@ use std::ffi::CStr;
 use std::os::raw::{c_char, c_int};
 use once_cell::sync::OnceCell;
 use serde::Deserialize;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 use tokio::runtime::Runtime;
 
@@ -36,6 +38,28 @@ pub fn run_job(config: JobConfig) -> Result<i32, JobError> {
     Ok(status.code().unwrap_or_default())
 }
 
+/// Run the job with `input` on its stdin, like `:w !cmd`.  Returns the exit
+/// code and what it wrote to stdout and stderr.
+pub fn run_job_input(config: JobConfig, input: &[u8]) -> Result<(i32, Vec<u8>), JobError> {
+    let rt = RUNTIME.get_or_init(|| Runtime::new().unwrap());
+    let output = rt.block_on(async {
+        let mut child = Command::new(&config.cmd)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A command that does not read its input closes the pipe early.
+            let _ = stdin.write_all(input).await;
+        }
+        child.wait_with_output().await
+    })?;
+    let mut text = output.stdout;
+    text.extend_from_slice(&output.stderr);
+    Ok((output.status.code().unwrap_or_default(), text))
+}
+
 #[no_mangle]
 pub extern "C" fn job_start(config_json: *const c_char, exit_code: *mut c_int) -> bool {
     if config_json.is_null() || exit_code.is_null() {
@@ -68,4 +92,11 @@ mod tests {
         let code = run_job(cfg).expect("run true");
         assert_eq!(code, 0);
     }
+
+    #[test]
+    fn run_with_input() {
+        let cfg = JobConfig { cmd: "sh".into(), args: vec!["-c".into(), "tr a-z A-Z; exit 3".into()] };
+        let (code, output) = run_job_input(cfg, b"abc\n").expect("run tr");
+        assert_eq!((code, output.as_slice()), (3, &b"ABC\n"[..]));
+    }
 }
@@ -0,0 +1,126 @@
+//! Abbreviations: `:abbreviate` and friends, and finding the abbreviation
+//! before the cursor.
+//!
+//! Abbreviations are kept in their own [`MapTable`].  There are three kinds,
+//! depending on the characters of the left-hand side:
+//! - full-id: only keyword characters, "foo"
+//! - end-id: ends in a keyword character, the others are not, "#i"
+//! - non-id: ends in a non-keyword character, "def#"
+//!
+//! The kind decides where the text before the cursor that is compared with
+//! the abbreviations starts.
+
+use crate::{MapCmd, MapTable, Mapping, Mode};
+
+/// The abbreviation commands: name, shortest abbreviation, what it does and
+/// the mode characters.
+const ABBR_COMMANDS: &[(&str, usize, MapCmd, &str)] = &[
+    ("abbreviate", 2, MapCmd::Map, "!"),
+    ("iabbrev", 2, MapCmd::Map, "i"),
+    ("cabbrev", 2, MapCmd::Map, "c"),
+    ("noreabbrev", 5, MapCmd::Noremap, "!"),
+    ("inoreabbrev", 6, MapCmd::Noremap, "i"),
+    ("cnoreabbrev", 6, MapCmd::Noremap, "c"),
+    ("unabbreviate", 3, MapCmd::Unmap, "!"),
+    ("iunabbrev", 4, MapCmd::Unmap, "i"),
+    ("cunabbrev", 4, MapCmd::Unmap, "c"),
+    ("abclear", 4, MapCmd::Clear, "!"),
+    ("iabclear", 4, MapCmd::Clear, "i"),
+    ("cabclear", 4, MapCmd::Clear, "c"),
+];
+
+/// What Ex command `name` does when it is an abbreviation command, and for
+/// which modes.
+pub fn abbr_command(name: &str) -> Option<(MapCmd, Mode)> {
+    let &(_, _, cmd, chars) =
+        ABBR_COMMANDS.iter().find(|(full, min, _, _)| name.len() >= *min && full.starts_with(name))?;
+    Mode::from_chars(chars).map(|mode| (cmd, mode))
+}
+
+/// Whether `c` is a keyword character, for the default 'iskeyword'.
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_word_key(key: u32) -> bool {
+    char::from_u32(key).is_some_and(is_word_char)
+}
+
+/// Whether `lhs` can be an abbreviation: when it ends in a keyword
+/// character the others must all be keyword characters or all not, and
+/// there is no white space.
+pub(crate) fn valid_lhs(lhs: &[u32]) -> bool {
+    let Some((&last, rest)) = lhs.split_last() else {
+        return false;
+    };
+    if lhs.iter().any(|&key| key == ' ' as u32 || key == '\t' as u32) {
+        return false;
+    }
+    !is_word_key(last) || rest.iter().all(|&key| is_word_key(key) == is_word_key(rest[0]))
+}
+
+impl MapTable {
+    /// The abbreviation at the end of `before`, the text before the cursor,
+    /// for `mode` with buffer `buf` current, and its length in characters.
+    /// It does not start before byte `mincol`.  A buffer-local abbreviation
+    /// is used before a global one.
+    pub fn find_abbr(&self, before: &str, mincol: usize, mode: Mode, buf: usize) -> Option<(&Mapping, usize)> {
+        let mincol = before[..mincol.min(before.len())].chars().count();
+        let before: Vec<char> = before.chars().collect();
+        if before.len() <= mincol {
+            return None;
+        }
+        // After a keyword character the text goes back as long as the
+        // characters are of the same kind as the one before it, otherwise
+        // up to white space.
+        let last_is_word = is_word_char(before[before.len() - 1]);
+        let is_id = before.len() < 2 || is_word_char(before[before.len() - 2]);
+        let mut start = before.len() - 1;
+        while start > mincol {
+            let c = before[start - 1];
+            if c.is_whitespace() || (last_is_word && is_word_char(c) != is_id) {
+                break;
+            }
+            start -= 1;
+        }
+        let text = &before[start..];
+        self.applicable(mode, buf)
+            .find(|m| m.lhs.len() == text.len() && m.lhs.iter().zip(text).all(|(&key, &c)| key == c as u32))
+            .map(|m| (m, text.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MapContext;
+
+    #[test]
+    fn kinds_and_matching() {
+        assert_eq!(abbr_command("ab"), Some((MapCmd::Map, Mode::INSERT | Mode::CMDLINE)));
+        assert_eq!(abbr_command("inorea"), Some((MapCmd::Noremap, Mode::INSERT)));
+        assert_eq!(abbr_command("cuna"), Some((MapCmd::Unmap, Mode::CMDLINE)));
+        assert_eq!(abbr_command("a"), None);
+
+        let ctx = MapContext { curbuf: 1, sid: 0, leader: "\\", localleader: "" };
+        let mut table = MapTable::new_abbr();
+        for arg in ["foo FOO", "#i #include", "def# DEFINE", "<buffer> foo LOCAL"] {
+            table.do_map(MapCmd::Map, Mode::INSERT, arg, &ctx).unwrap();
+        }
+        assert_eq!(table.do_map(MapCmd::Map, Mode::INSERT, "a#b x", &ctx).unwrap_err(), "E474: Invalid argument");
+        assert_eq!(table.do_map(MapCmd::Unmap, Mode::INSERT, "bar", &ctx).unwrap_err(), "E24: No such abbreviation");
+        assert_eq!(table.do_map(MapCmd::Map, Mode::CMDLINE, "", &ctx).unwrap(), ["No abbreviation found"]);
+
+        let find = |line: &str, mincol: usize, buf: usize| {
+            table.find_abbr(line, mincol, Mode::INSERT, buf).map(|(m, len)| (m.rhs.as_str(), len))
+        };
+        assert_eq!(find("a foo", 0, 2), Some(("FOO", 3)));
+        assert_eq!(find("a foo", 0, 1), Some(("LOCAL", 3)));
+        assert_eq!(find("xfoo", 0, 2), None);
+        assert_eq!(find("xfoo", 1, 2), Some(("FOO", 3)));
+        assert_eq!(find("a#i", 0, 2), Some(("#include", 2)));
+        assert_eq!(find("(def#", 0, 2), None);
+        assert_eq!(find(" def#", 0, 2), Some(("DEFINE", 4)));
+        assert_eq!(find("foo", 3, 2), None);
+    }
+}
@@ -0,0 +1,239 @@
+//! Key codes and the `<>` notation used in mappings: `<C-x>`, `<M-a>`,
+//! `<F5>`, `<CR>`, etc.
+//!
+//! A key is a `u32`: a character, or a special key from [`K_SPECIAL`] up,
+//! with modifier bits on top.  CTRL with a letter gives the control
+//! character, like Vim does, other modifiers are kept as bits.
+
+/// Modifier bits.
+pub const MOD_SHIFT: u32 = 1 << 24;
+pub const MOD_CTRL: u32 = 1 << 25;
+pub const MOD_ALT: u32 = 1 << 26;
+pub const MOD_CMD: u32 = 1 << 27;
+const MOD_MASK: u32 = MOD_SHIFT | MOD_CTRL | MOD_ALT | MOD_CMD;
+
+/// The first special key, above all characters.
+pub const K_SPECIAL: u32 = 0x20_0000;
+/// `<F1>`, the other function keys follow.
+pub const K_F1: u32 = K_SPECIAL + 0x100;
+/// `<Cmd>`: the text up to `<CR>` is executed as a command.
+pub const K_CMD: u32 = K_SPECIAL;
+pub const K_PLUG: u32 = K_SPECIAL + 1;
+pub const K_IGNORE: u32 = K_SPECIAL + 2;
+
+pub const BS: u32 = 0x08;
+pub const NL: u32 = 0x0a;
+pub const CAR: u32 = 0x0d;
+pub const CTRL_V: u32 = 0x16;
+pub const ESC: u32 = 0x1b;
+/// CTRL-], expands an abbreviation without inserting a character.
+pub const CTRL_RSB: u32 = 0x1d;
+
+/// Special keys that are not characters, in the order of their codes.
+const SPECIAL_KEYS: &[&str] = &[
+    "Cmd", "Plug", "Ignore", "Up", "Down", "Left", "Right", "Home", "End", "PageUp", "PageDown", "Insert", "Del",
+    "Help", "Undo", "CursorHold", "LeftMouse", "MiddleMouse", "RightMouse", "ScrollWheelUp", "ScrollWheelDown",
+    "kEnter", "kPlus", "kMinus", "kMultiply", "kDivide",
+];
+
+/// Names of characters.
+const CHAR_NAMES: &[(&str, u32)] = &[
+    ("Nul", 0),
+    ("BS", 8),
+    ("Backspace", 8),
+    ("Tab", 9),
+    ("NL", 10),
+    ("NewLine", 10),
+    ("LineFeed", 10),
+    ("LF", 10),
+    ("FF", 12),
+    ("CR", CAR),
+    ("Return", CAR),
+    ("Enter", CAR),
+    ("Esc", ESC),
+    ("Space", b' ' as u32),
+    ("lt", b'<' as u32),
+    ("Bslash", b'\\' as u32),
+    ("Bar", b'|' as u32),
+];
+
+fn key_by_name(name: &str) -> Option<u32> {
+    if let Some(&(_, key)) = CHAR_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        return Some(key);
+    }
+    if let Some(idx) = SPECIAL_KEYS.iter().position(|n| n.eq_ignore_ascii_case(name)) {
+        return Some(K_SPECIAL + idx as u32);
+    }
+    let num = name.strip_prefix(['F', 'f'])?.parse::<u32>().ok()?;
+    (1..=37).contains(&num).then(|| K_F1 + num - 1)
+}
+
+/// Apply modifiers to `key`.  CTRL with a letter or one of "@[\]^_?" gives
+/// the control character, SHIFT with a letter the upper case letter.
+fn add_modifiers(mut key: u32, mut mods: u32) -> u32 {
+    if mods & MOD_SHIFT != 0 {
+        if let Some(c) = char::from_u32(key).filter(char::is_ascii_alphabetic) {
+            key = c.to_ascii_uppercase() as u32;
+            mods &= !MOD_SHIFT;
+        }
+    }
+    if mods & MOD_CTRL != 0 {
+        match char::from_u32(key) {
+            Some('?') => {
+                key = 0x7f;
+                mods &= !MOD_CTRL;
+            }
+            Some(c) if c.is_ascii_alphabetic() || "@[\\]^_".contains(c) => {
+                key = c.to_ascii_uppercase() as u32 & 0x1f;
+                mods &= !MOD_CTRL;
+            }
+            _ => {}
+        }
+    }
+    key | mods
+}
+
+/// Parse `<...>` at the start of `text`.  Returns the key and the length
+/// of the notation; None when it is not a known key.
+fn parse_notation(text: &str) -> Option<(u32, usize)> {
+    let end = text.find('>')?;
+    let inner = &text[1..end];
+    let mut mods = 0;
+    let mut name = inner;
+    // "<C-->" is CTRL and "-": the last part may be a single "-".
+    while name.len() > 2 && name.as_bytes()[1] == b'-' {
+        mods |= match name.as_bytes()[0].to_ascii_uppercase() {
+            b'S' => MOD_SHIFT,
+            b'C' => MOD_CTRL,
+            b'M' | b'A' => MOD_ALT,
+            b'D' => MOD_CMD,
+            _ => return None,
+        };
+        name = &name[2..];
+    }
+    let mut chars = name.chars();
+    let key = match (chars.next(), chars.next()) {
+        (Some(c), None) if mods != 0 => c as u32,
+        _ => key_by_name(name)?,
+    };
+    Some((add_modifiers(key, mods), end + 1))
+}
+
+/// Translate `text` with `<>` notation to keys.  `<Leader>` and
+/// `<LocalLeader>` are replaced with `leader` and `localleader`.  Text that
+/// is not a known key name is used literally.  CTRL-V makes the next
+/// character literal.
+pub fn parse_keys(text: &str, leader: &str, localleader: &str) -> Vec<u32> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let lower = rest.to_ascii_lowercase();
+            if lower.starts_with("<leader>") {
+                keys.extend(leader.chars().map(|c| c as u32));
+                rest = &rest[8..];
+                continue;
+            }
+            if lower.starts_with("<localleader>") {
+                keys.extend(localleader.chars().map(|c| c as u32));
+                rest = &rest[13..];
+                continue;
+            }
+            if let Some((key, len)) = parse_notation(rest) {
+                keys.push(key);
+                rest = &rest[len..];
+                continue;
+            }
+        } else if c == '\x16' {
+            if let Some(next) = rest[1..].chars().next() {
+                keys.push(next as u32);
+                rest = &rest[1 + next.len_utf8()..];
+                continue;
+            }
+        }
+        keys.push(c as u32);
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+/// Characters that `:map` shows by name.
+const SHOWN_NAMES: &[(u32, &str)] = &[(0, "Nul"), (8, "BS"), (9, "Tab"), (10, "NL"), (CAR, "CR"), (ESC, "Esc"), (0x20, "Space")];
+
+/// The `<>` notation for `key`, like `:map` shows it.
+pub fn key_to_string(key: u32) -> String {
+    let mods = key & MOD_MASK;
+    let base = key & !MOD_MASK;
+    let name = if base >= K_F1 {
+        format!("F{}", base - K_F1 + 1)
+    } else if base >= K_SPECIAL {
+        SPECIAL_KEYS.get((base - K_SPECIAL) as usize).unwrap_or(&"Ignore").to_string()
+    } else if let Some(&(_, name)) = SHOWN_NAMES.iter().find(|&&(k, _)| k == base) {
+        name.to_string()
+    } else if base < 0x20 || base == 0x7f {
+        // A control character: "<C-X>", "<C-?>".
+        format!("C-{}", char::from_u32(base ^ 0x40).unwrap_or('?'))
+    } else {
+        let c = char::from_u32(base).unwrap_or('?').to_string();
+        if mods == 0 {
+            return c;
+        }
+        c
+    };
+    format!("<{}{}>", modifier_prefix(mods), name)
+}
+
+fn modifier_prefix(mods: u32) -> String {
+    let mut prefix = String::new();
+    for (bit, name) in [(MOD_SHIFT, "S-"), (MOD_CTRL, "C-"), (MOD_ALT, "M-"), (MOD_CMD, "D-")] {
+        if mods & bit != 0 {
+            prefix.push_str(name);
+        }
+    }
+    prefix
+}
+
+/// The `<>` notation for `keys`.
+pub fn keys_to_string(keys: &[u32]) -> String {
+    keys.iter().map(|&key| key_to_string(key)).collect()
+}
+
+/// The `<>` notation for the right-hand side `keys` of a mapping, like
+/// `:map` shows it: a space is shown as is, only at the start and the end
+/// it is "<Space>".
+pub fn rhs_to_string(keys: &[u32]) -> String {
+    let last = keys.len().saturating_sub(1);
+    keys.iter()
+        .enumerate()
+        .map(|(i, &key)| if key == 0x20 && i != 0 && i != last { " ".to_string() } else { key_to_string(key) })
+        .collect()
+}
+
+/// The keys for the characters of `text`, as typed.  Used for the result
+/// of an `<expr>` mapping, which is not translated.
+pub fn chars_to_keys(text: &str) -> Vec<u32> {
+    text.chars().map(|c| c as u32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notation() {
+        assert_eq!(parse_keys("<C-x>a", "\\", ""), [0x18, 'a' as u32]);
+        assert_eq!(parse_keys("<c-[><Esc><CR><lt>", "\\", ""), [ESC, ESC, CAR, '<' as u32]);
+        assert_eq!(parse_keys("<M-a><S-F5><F12><C-Left>", "\\", ""), [
+            'a' as u32 | MOD_ALT,
+            (K_F1 + 4) | MOD_SHIFT,
+            K_F1 + 11,
+            (K_SPECIAL + 5) | MOD_CTRL
+        ]);
+        assert_eq!(parse_keys("<Leader>w<LocalLeader>", ",", "_"), [',' as u32, 'w' as u32, '_' as u32]);
+        assert_eq!(parse_keys("<nokey>", "", ""), parse_keys("<", "", "").into_iter().chain("nokey>".chars().map(|c| c as u32)).collect::<Vec<_>>());
+        assert_eq!(parse_keys("<S-a><C-->", "", ""), ['A' as u32, '-' as u32 | MOD_CTRL]);
+
+        assert_eq!(keys_to_string(&parse_keys("<C-x><Esc>a b<F5><M-x><C-Left>", "", "")), "<C-X><Esc>a<Space>b<F5><M-x><C-Left>");
+        assert_eq!(rhs_to_string(&parse_keys(" :echo 1<CR> ", "", "")), "<Space>:echo 1<CR><Space>");
+    }
+}
@@ -1,24 +1,509 @@
+//! Key mappings: `:map` and friends, and the lookup done when keys are
+//! typed.
+//!
+//! [`MapTable`] keeps the mappings for all modes.  A mapping is found by
+//! the keys it starts with, buffer-local mappings first.  Expanding
+//! mappings in typed keys is done by [`TypeBuf`], which asks a
+//! [`KeySource`] for more keys when the typed ones are a prefix of a
+//! longer mapping.  Abbreviations are kept in a separate [`MapTable`].
+
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
 use std::ffi::{CStr, CString};
+use std::ops::BitOr;
 use std::os::raw::c_char;
 use std::sync::Mutex;
 
-static MAPS: Lazy<Mutex<HashMap<String, String>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
+mod abbr;
+pub mod keys;
+mod typebuf;
+
+pub use abbr::{abbr_command, is_word_char};
+pub use keys::{key_to_string, keys_to_string, parse_keys};
+pub use typebuf::{KeySource, MapHost, TypeBuf, MAX_MAPDEPTH};
+
+/// The modes a mapping applies in, a combination of bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Mode(u32);
+
+impl Mode {
+    pub const NORMAL: Mode = Mode(0x01);
+    /// Visual mode, "x" in map commands.
+    pub const VISUAL: Mode = Mode(0x02);
+    pub const SELECT: Mode = Mode(0x04);
+    pub const OP_PENDING: Mode = Mode(0x08);
+    pub const INSERT: Mode = Mode(0x10);
+    pub const CMDLINE: Mode = Mode(0x20);
+    pub const TERMINAL: Mode = Mode(0x40);
+    /// Language mappings, `:lmap`.
+    pub const LANGMAP: Mode = Mode(0x80);
+    /// The modes of `:map`.
+    pub const NVO: Mode = Mode(0x0f);
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u32) -> Mode {
+        Mode(bits & 0xff)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn intersects(self, other: Mode) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn contains(self, other: Mode) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn without(self, other: Mode) -> Mode {
+        Mode(self.0 & !other.0)
+    }
+
+    /// The modes for mode characters as used in map command names and by
+    /// maparg(): "n", "v", "x", "s", "o", "i", "c", "t", "l" and "!".  An
+    /// empty string is the modes of `:map`.
+    pub fn from_chars(chars: &str) -> Option<Mode> {
+        if chars.is_empty() || chars == " " {
+            return Some(Mode::NVO);
+        }
+        let mut mode = Mode::default();
+        for c in chars.chars() {
+            mode = mode
+                | match c {
+                    'n' => Mode::NORMAL,
+                    'v' => Mode::VISUAL | Mode::SELECT,
+                    'x' => Mode::VISUAL,
+                    's' => Mode::SELECT,
+                    'o' => Mode::OP_PENDING,
+                    'i' => Mode::INSERT,
+                    'c' => Mode::CMDLINE,
+                    't' => Mode::TERMINAL,
+                    'l' => Mode::LANGMAP,
+                    '!' => Mode::INSERT | Mode::CMDLINE,
+                    _ => return None,
+                };
+        }
+        Some(mode)
+    }
+
+    /// The mode characters `:map` shows, like Vim's map_mode_to_chars().
+    pub fn to_chars(self) -> String {
+        if self.contains(Mode::INSERT | Mode::CMDLINE) {
+            return "!".to_string();
+        }
+        if self.intersects(Mode::INSERT) {
+            return "i".to_string();
+        }
+        if self.intersects(Mode::LANGMAP) {
+            return "l".to_string();
+        }
+        if self.intersects(Mode::CMDLINE) {
+            return "c".to_string();
+        }
+        if self.contains(Mode::NVO) {
+            return " ".to_string();
+        }
+        let mut chars = String::new();
+        for (mode, c) in [(Mode::NORMAL, 'n'), (Mode::OP_PENDING, 'o'), (Mode::TERMINAL, 't')] {
+            if self.intersects(mode) {
+                chars.push(c);
+            }
+        }
+        if self.contains(Mode::VISUAL | Mode::SELECT) {
+            chars.push('v');
+        } else {
+            for (mode, c) in [(Mode::VISUAL, 'x'), (Mode::SELECT, 's')] {
+                if self.intersects(mode) {
+                    chars.push(c);
+                }
+            }
+        }
+        chars
+    }
+}
+
+impl BitOr for Mode {
+    type Output = Mode;
+
+    fn bitor(self, other: Mode) -> Mode {
+        Mode(self.0 | other.0)
+    }
+}
+
+/// A key mapping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub lhs: Vec<u32>,
+    /// The right-hand side as given, the expression for `<expr>`.
+    pub rhs: String,
+    /// The keys the mapping is replaced with, empty for `<Nop>` and
+    /// `<expr>`.
+    pub rhs_keys: Vec<u32>,
+    pub mode: Mode,
+    /// The keys of the right-hand side are not mapped again.
+    pub noremap: bool,
+    /// Only script-local mappings apply to the right-hand side.
+    pub script: bool,
+    pub expr: bool,
+    pub silent: bool,
+    /// Used without waiting for a longer mapping.
+    pub nowait: bool,
+    /// The buffer of a buffer-local mapping.
+    pub buffer: Option<usize>,
+    /// The script the mapping was defined in.
+    pub sid: usize,
+}
+
+impl Mapping {
+    /// The left-hand side in `<>` notation.
+    pub fn lhs_string(&self) -> String {
+        keys_to_string(&self.lhs)
+    }
 
+    /// The right-hand side as `:map` shows it.
+    pub fn rhs_string(&self) -> String {
+        if self.expr {
+            self.rhs.clone()
+        } else if self.rhs_keys.is_empty() {
+            "<Nop>".to_string()
+        } else {
+            keys::rhs_to_string(&self.rhs_keys)
+        }
+    }
+
+    /// Whether the mapping executes a command with `<Cmd>`.
+    pub fn is_cmd(&self) -> bool {
+        !self.expr && self.rhs_keys.first() == Some(&keys::K_CMD)
+    }
+}
+
+/// What a map command does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapCmd {
+    Map,
+    Noremap,
+    Unmap,
+    Clear,
+}
+
+/// The map commands: name, shortest abbreviation, what it does and the
+/// mode characters.
+const MAP_COMMANDS: &[(&str, usize, MapCmd, &str)] = &[
+    ("map", 3, MapCmd::Map, ""),
+    ("nmap", 2, MapCmd::Map, "n"),
+    ("vmap", 2, MapCmd::Map, "v"),
+    ("xmap", 2, MapCmd::Map, "x"),
+    ("smap", 4, MapCmd::Map, "s"),
+    ("omap", 2, MapCmd::Map, "o"),
+    ("imap", 2, MapCmd::Map, "i"),
+    ("lmap", 2, MapCmd::Map, "l"),
+    ("cmap", 2, MapCmd::Map, "c"),
+    ("tmap", 3, MapCmd::Map, "t"),
+    ("noremap", 2, MapCmd::Noremap, ""),
+    ("nnoremap", 2, MapCmd::Noremap, "n"),
+    ("vnoremap", 2, MapCmd::Noremap, "v"),
+    ("xnoremap", 2, MapCmd::Noremap, "x"),
+    ("snoremap", 4, MapCmd::Noremap, "s"),
+    ("onoremap", 3, MapCmd::Noremap, "o"),
+    ("inoremap", 3, MapCmd::Noremap, "i"),
+    ("lnoremap", 2, MapCmd::Noremap, "l"),
+    ("cnoremap", 3, MapCmd::Noremap, "c"),
+    ("tnoremap", 3, MapCmd::Noremap, "t"),
+    ("unmap", 3, MapCmd::Unmap, ""),
+    ("nunmap", 3, MapCmd::Unmap, "n"),
+    ("vunmap", 2, MapCmd::Unmap, "v"),
+    ("xunmap", 2, MapCmd::Unmap, "x"),
+    ("sunmap", 4, MapCmd::Unmap, "s"),
+    ("ounmap", 2, MapCmd::Unmap, "o"),
+    ("iunmap", 2, MapCmd::Unmap, "i"),
+    ("lunmap", 2, MapCmd::Unmap, "l"),
+    ("cunmap", 2, MapCmd::Unmap, "c"),
+    ("tunmap", 5, MapCmd::Unmap, "t"),
+    ("mapclear", 4, MapCmd::Clear, ""),
+    ("nmapclear", 5, MapCmd::Clear, "n"),
+    ("vmapclear", 5, MapCmd::Clear, "v"),
+    ("xmapclear", 5, MapCmd::Clear, "x"),
+    ("smapclear", 5, MapCmd::Clear, "s"),
+    ("omapclear", 5, MapCmd::Clear, "o"),
+    ("imapclear", 5, MapCmd::Clear, "i"),
+    ("lmapclear", 5, MapCmd::Clear, "l"),
+    ("cmapclear", 5, MapCmd::Clear, "c"),
+    ("tmapclear", 5, MapCmd::Clear, "t"),
+];
+
+/// What Ex command `name` does when it is a map command, and for which
+/// modes.  With `bang` `:map!` and friends apply to Insert and Command-line
+/// mode.
+pub fn map_command(name: &str, bang: bool) -> Option<(MapCmd, Mode)> {
+    let &(_, _, cmd, chars) =
+        MAP_COMMANDS.iter().find(|(full, min, _, _)| name.len() >= *min && full.starts_with(name))?;
+    if bang && chars.is_empty() {
+        return Some((cmd, Mode::INSERT | Mode::CMDLINE));
+    }
+    Mode::from_chars(chars).map(|mode| (cmd, mode))
+}
+
+/// The state a map command depends on.
+#[derive(Debug, Clone, Copy)]
+pub struct MapContext<'a> {
+    /// The buffer number used for `<buffer>`.
+    pub curbuf: usize,
+    /// The script being sourced.
+    pub sid: usize,
+    /// The value of "mapleader" and "maplocalleader".
+    pub leader: &'a str,
+    pub localleader: &'a str,
+}
+
+/// The result of looking up typed keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lookup<'a> {
+    /// No mapping starts with the keys.
+    None,
+    /// A mapping matches, the keys may be longer than its left-hand side.
+    Full(&'a Mapping),
+    /// The keys are the start of a longer mapping, more keys are needed to
+    /// decide.  Holds the mapping to use when no more keys are typed.
+    Partial(Option<&'a Mapping>),
+}
+
+/// Split off the left-hand side of a map command, which ends at white
+/// space that is not preceded by CTRL-V.
+fn split_lhs(arg: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (i, c) in arg.char_indices() {
+        if c.is_whitespace() && !escaped {
+            return (&arg[..i], arg[i..].trim_start());
+        }
+        escaped = c == '\x16' && !escaped;
+    }
+    (arg, "")
+}
+
+/// All mappings, or all abbreviations.
+#[derive(Debug, Default)]
+pub struct MapTable {
+    maps: Vec<Mapping>,
+    abbr: bool,
+}
+
+impl MapTable {
+    pub fn new() -> Self {
+        MapTable { maps: Vec::new(), abbr: false }
+    }
+
+    /// A table for abbreviations.
+    pub fn new_abbr() -> Self {
+        MapTable { maps: Vec::new(), abbr: true }
+    }
+
+    pub fn is_abbr(&self) -> bool {
+        self.abbr
+    }
+
+    pub fn maps(&self) -> impl Iterator<Item = &Mapping> {
+        self.maps.iter()
+    }
+
+    /// Execute map command `cmd` for `mode` with argument `arg`:
+    /// `[<buffer>] [<nowait>] [<silent>] [<script>] [<expr>] [<unique>]
+    /// {lhs} {rhs}`.  Returns the lines of a listing.
+    pub fn do_map(&mut self, cmd: MapCmd, mode: Mode, arg: &str, ctx: &MapContext) -> Result<Vec<String>, String> {
+        let mut arg = arg.trim_start();
+        let mut map = Mapping {
+            lhs: Vec::new(),
+            rhs: String::new(),
+            rhs_keys: Vec::new(),
+            mode,
+            noremap: cmd == MapCmd::Noremap,
+            script: false,
+            expr: false,
+            silent: false,
+            nowait: false,
+            buffer: None,
+            sid: ctx.sid,
+        };
+        let mut unique = false;
+        'flags: loop {
+            for name in ["<buffer>", "<nowait>", "<silent>", "<special>", "<script>", "<expr>", "<unique>"] {
+                let Some(rest) = arg.strip_prefix(name) else { continue };
+                match name {
+                    "<buffer>" => map.buffer = Some(ctx.curbuf),
+                    "<nowait>" => map.nowait = true,
+                    "<silent>" => map.silent = true,
+                    "<script>" => map.script = true,
+                    "<expr>" => map.expr = true,
+                    "<unique>" => unique = true,
+                    _ => {}
+                }
+                arg = rest.trim_start();
+                continue 'flags;
+            }
+            break;
+        }
+        let (lhs, rhs) = split_lhs(arg);
+        map.lhs = parse_keys(lhs, ctx.leader, ctx.localleader);
+
+        match cmd {
+            MapCmd::Clear => {
+                if !lhs.is_empty() {
+                    return Err(format!("E474: Invalid argument: {}", arg));
+                }
+                self.clear(mode, map.buffer);
+            }
+            MapCmd::Unmap => {
+                if lhs.is_empty() {
+                    return Err("E474: Invalid argument".to_string());
+                }
+                if !self.remove(&map.lhs, mode, map.buffer) {
+                    return Err(if self.abbr { "E24: No such abbreviation" } else { "E31: No such mapping" }.to_string());
+                }
+            }
+            MapCmd::Map | MapCmd::Noremap if rhs.is_empty() => {
+                return Ok(self.list(mode, map.buffer, &map.lhs));
+            }
+            MapCmd::Map | MapCmd::Noremap => {
+                if self.abbr && !abbr::valid_lhs(&map.lhs) {
+                    return Err("E474: Invalid argument".to_string());
+                }
+                if unique && self.maps.iter().any(|m| m.lhs == map.lhs && m.buffer == map.buffer && m.mode.intersects(mode)) {
+                    if self.abbr {
+                        return Err(format!("E226: Abbreviation already exists for {}", lhs));
+                    }
+                    return Err(format!("E227: Mapping already exists for {}", lhs));
+                }
+                map.rhs = rhs.to_string();
+                if !map.expr && !rhs.eq_ignore_ascii_case("<Nop>") {
+                    map.rhs_keys = parse_keys(rhs, ctx.leader, ctx.localleader);
+                }
+                self.add(map);
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    /// Add `map`, replacing a mapping with the same left-hand side in the
+    /// modes of `map`.
+    pub fn add(&mut self, map: Mapping) {
+        self.remove(&map.lhs, map.mode, map.buffer);
+        self.maps.push(map);
+    }
+
+    /// Remove the mapping of `lhs` for `mode`, global or for `buffer`.  A
+    /// mapping that also applies in other modes is kept for them.  Returns
+    /// false when there was no such mapping.
+    pub fn remove(&mut self, lhs: &[u32], mode: Mode, buffer: Option<usize>) -> bool {
+        let mut found = false;
+        for map in self.maps.iter_mut().filter(|m| m.lhs == lhs && m.buffer == buffer && m.mode.intersects(mode)) {
+            map.mode = map.mode.without(mode);
+            found = true;
+        }
+        self.maps.retain(|m| !m.mode.is_empty());
+        found
+    }
+
+    /// Remove all mappings for `mode`, only those local to `buffer` when
+    /// given.
+    pub fn clear(&mut self, mode: Mode, buffer: Option<usize>) {
+        for map in self.maps.iter_mut().filter(|m| buffer.is_none() || m.buffer == buffer) {
+            if buffer.is_some() || map.buffer.is_none() {
+                map.mode = map.mode.without(mode);
+            }
+        }
+        self.maps.retain(|m| !m.mode.is_empty());
+    }
+
+    /// Remove the mappings local to buffer `buf`, when it is wiped out.
+    pub fn buf_deleted(&mut self, buf: usize) {
+        self.maps.retain(|m| m.buffer != Some(buf));
+    }
+
+    /// The mappings that apply in `mode` with buffer `buf` current, the
+    /// buffer-local ones first.
+    fn applicable(&self, mode: Mode, buf: usize) -> impl Iterator<Item = &Mapping> {
+        let local = self.maps.iter().filter(move |m| m.buffer == Some(buf));
+        let global = self.maps.iter().filter(|m| m.buffer.is_none());
+        local.chain(global).filter(move |m| m.mode.intersects(mode))
+    }
+
+    /// The mapping of exactly `lhs` in `mode`, a buffer-local one first.
+    pub fn get(&self, lhs: &[u32], mode: Mode, buf: usize) -> Option<&Mapping> {
+        self.applicable(mode, buf).find(|m| m.lhs == lhs)
+    }
+
+    /// Find the mapping to use for typed `keys` in `mode`.  The longest
+    /// mapping that matches is used, a buffer-local one before a global one.
+    /// A matching `<nowait>` mapping is used right away.
+    pub fn lookup(&self, mode: Mode, buf: usize, keys: &[u32]) -> Lookup<'_> {
+        let mut full: Option<&Mapping> = None;
+        let mut partial = false;
+        for map in self.applicable(mode, buf) {
+            if map.lhs.is_empty() {
+                continue;
+            }
+            if keys.starts_with(&map.lhs) {
+                if map.nowait {
+                    return Lookup::Full(map);
+                }
+                if full.is_none_or(|f| map.lhs.len() > f.lhs.len()) {
+                    full = Some(map);
+                }
+            } else if map.lhs.starts_with(keys) {
+                partial = true;
+            }
+        }
+        match full {
+            _ if partial => Lookup::Partial(full),
+            Some(map) => Lookup::Full(map),
+            None => Lookup::None,
+        }
+    }
+
+    /// The `:map` or `:abbreviate` listing for `mode`: the mappings that start with `prefix`,
+    /// only those local to `buffer` when given.
+    pub fn list(&self, mode: Mode, buffer: Option<usize>, prefix: &[u32]) -> Vec<String> {
+        let local = self.maps.iter().filter(|m| m.buffer.is_some() && (buffer.is_none() || m.buffer == buffer));
+        let global = self.maps.iter().filter(|m| m.buffer.is_none() && buffer.is_none());
+        let lines: Vec<String> = local
+            .chain(global)
+            .filter(|m| m.mode.intersects(mode) && m.lhs.starts_with(prefix))
+            .map(|m| {
+                let noremap = if m.script { '&' } else if m.noremap { '*' } else { ' ' };
+                let local = if m.buffer.is_some() { '@' } else { ' ' };
+                format!("{:<3}{:<11} {}{}{}", m.mode.to_chars(), m.lhs_string(), noremap, local, m.rhs_string())
+            })
+            .collect();
+        if lines.is_empty() {
+            let msg = if self.abbr { "No abbreviation found" } else { "No mapping found" };
+            return vec![msg.to_string()];
+        }
+        lines
+    }
+}
+
+static MAPS: Lazy<Mutex<MapTable>> = Lazy::new(|| Mutex::new(MapTable::new()));
+
+/// Define a mapping like `:map {lhs} {rhs}`.
 #[no_mangle]
 pub extern "C" fn rs_map_add(lhs: *const c_char, rhs: *const c_char) {
     let lhs = unsafe { CStr::from_ptr(lhs) }.to_string_lossy().into_owned();
     let rhs = unsafe { CStr::from_ptr(rhs) }.to_string_lossy().into_owned();
-    MAPS.lock().unwrap().insert(lhs, rhs);
+    let ctx = MapContext { curbuf: 0, sid: 0, leader: "\\", localleader: "" };
+    let _ = MAPS.lock().unwrap().do_map(MapCmd::Map, Mode::NVO, &format!("{} {}", lhs, rhs), &ctx);
 }
 
+/// The right-hand side of the Normal mode mapping of `lhs`, NULL when there
+/// is none.  The caller frees the result.
 #[no_mangle]
 pub extern "C" fn rs_map_lookup(lhs: *const c_char) -> *const c_char {
     let lhs = unsafe { CStr::from_ptr(lhs) }.to_str().unwrap_or("");
-    if let Some(rhs) = MAPS.lock().unwrap().get(lhs) {
-        let cstr = CString::new(rhs.clone()).unwrap();
+    let lhs = parse_keys(lhs, "\\", "");
+    if let Some(map) = MAPS.lock().unwrap().get(&lhs, Mode::NORMAL, 0) {
+        let cstr = CString::new(map.rhs.clone()).unwrap();
         cstr.into_raw()
     } else {
         std::ptr::null()
@@ -29,6 +514,17 @@ pub extern "C" fn rs_map_lookup(lhs: *const c_char) -> *const c_char {
 mod tests {
     use super::*;
 
+    const CTX: MapContext = MapContext { curbuf: 1, sid: 0, leader: ",", localleader: "" };
+
+    fn map(table: &mut MapTable, cmd: &str, bang: bool, arg: &str) -> Result<Vec<String>, String> {
+        let (cmd, mode) = map_command(cmd, bang).unwrap();
+        table.do_map(cmd, mode, arg, &CTX)
+    }
+
+    fn keys(text: &str) -> Vec<u32> {
+        parse_keys(text, ",", "")
+    }
+
     #[test]
     fn add_and_lookup() {
         rs_map_add(b"jj\0".as_ptr() as *const c_char, b"<Esc>\0".as_ptr() as *const c_char);
@@ -36,4 +532,53 @@ mod tests {
         let cstr = unsafe { CStr::from_ptr(ptr) };
         assert_eq!(cstr.to_str().unwrap(), "<Esc>");
     }
+
+    #[test]
+    fn commands_and_modes() {
+        assert_eq!(map_command("nno", false), Some((MapCmd::Noremap, Mode::NORMAL)));
+        assert_eq!(map_command("map", true), Some((MapCmd::Map, Mode::INSERT | Mode::CMDLINE)));
+        assert_eq!(map_command("vu", false), Some((MapCmd::Unmap, Mode::VISUAL | Mode::SELECT)));
+        assert_eq!(map_command("nmapc", false), Some((MapCmd::Clear, Mode::NORMAL)));
+        assert_eq!(map_command("norm", false), None);
+        assert_eq!((Mode::NORMAL | Mode::VISUAL).to_chars(), "nx");
+        assert_eq!(Mode::NVO.to_chars(), " ");
+
+        let mut table = MapTable::new();
+        map(&mut table, "map", false, "<Leader>w :write<CR>").unwrap();
+        map(&mut table, "nnoremap", false, "<silent> <F5> <Cmd>echo<CR>").unwrap();
+        map(&mut table, "imap", false, "<buffer> jj <Esc>").unwrap();
+        assert_eq!(
+            map(&mut table, "map", false, "").unwrap(),
+            ["   ,w            :write<CR>", "n  <F5>        * <Cmd>echo<CR>"]
+        );
+        assert_eq!(map(&mut table, "imap", false, "").unwrap(), ["i  jj           @<Esc>"]);
+        assert_eq!(map(&mut table, "map", false, "x").unwrap(), ["No mapping found"]);
+
+        // Unmapping in one mode keeps the mapping for the others.
+        map(&mut table, "ounmap", false, ",w").unwrap();
+        assert_eq!(table.get(&keys(",w"), Mode::NORMAL, 1).unwrap().mode.to_chars(), "nv");
+        assert!(table.get(&keys(",w"), Mode::OP_PENDING, 1).is_none());
+        assert_eq!(map(&mut table, "unmap", false, "nosuch").unwrap_err(), "E31: No such mapping");
+        assert_eq!(map(&mut table, "map", false, "<unique> ,w x").unwrap_err(), "E227: Mapping already exists for ,w");
+        map(&mut table, "mapclear", false, "").unwrap();
+        assert!(table.get(&keys(",w"), Mode::NORMAL, 1).is_none());
+        assert!(table.get(&keys("jj"), Mode::INSERT, 1).is_some());
+        map(&mut table, "imapclear", false, "<buffer>").unwrap();
+        assert_eq!(table.maps().count(), 0);
+    }
+
+    #[test]
+    fn lookup_prefers_longest_and_local() {
+        let mut table = MapTable::new();
+        map(&mut table, "nmap", false, "a A").unwrap();
+        map(&mut table, "nmap", false, "ab AB").unwrap();
+        map(&mut table, "nmap", false, "<buffer><nowait> x X").unwrap();
+        map(&mut table, "nmap", false, "xy XY").unwrap();
+        assert!(matches!(table.lookup(Mode::NORMAL, 1, &keys("a")), Lookup::Partial(Some(m)) if m.rhs == "A"));
+        assert!(matches!(table.lookup(Mode::NORMAL, 1, &keys("abc")), Lookup::Full(m) if m.rhs == "AB"));
+        assert!(matches!(table.lookup(Mode::NORMAL, 1, &keys("ac")), Lookup::Full(m) if m.rhs == "A"));
+        assert!(matches!(table.lookup(Mode::NORMAL, 1, &keys("x")), Lookup::Full(m) if m.rhs == "X"));
+        assert!(matches!(table.lookup(Mode::NORMAL, 2, &keys("x")), Lookup::Partial(None)));
+        assert_eq!(table.lookup(Mode::INSERT, 1, &keys("a")), Lookup::None);
+    }
 }
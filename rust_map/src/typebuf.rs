@@ -0,0 +1,331 @@
+//! The typeahead buffer: typed keys with mappings expanded, like Vim's
+//! vgetorpeek().
+//!
+//! When the typed keys are the start of a mapping, more keys are waited for
+//! up to 'timeoutlen'.  When none come the longest mapping that matches is
+//! used, or the keys are used as they are.  Keys that a mapping inserts
+//! may be mapped again, unless it was defined with `:noremap`.
+//!
+//! An abbreviation is expanded by inserting keys that delete it, followed
+//! by its replacement.  These keys do not trigger an abbreviation again.
+
+use std::collections::VecDeque;
+
+use crate::keys::{chars_to_keys, BS, CAR, CTRL_RSB};
+use crate::{Lookup, MapTable, Mapping, Mode};
+
+/// How many mappings are expanded before keys are typed again, Vim's
+/// 'maxmapdepth'.  More means the mapping is recursive.
+pub const MAX_MAPDEPTH: usize = 1000;
+
+/// What expanding mappings needs from the editor.
+pub trait MapHost {
+    fn maps(&self) -> &MapTable;
+
+    fn abbrs(&self) -> &MapTable;
+
+    /// How many milliseconds to wait for the next key of a mapping, None
+    /// to wait until a key is typed.
+    fn timeoutlen(&self) -> Option<u64>;
+
+    /// Evaluate the expression of an `<expr>` mapping or abbreviation.  An error is reported
+    /// by the host, the result is then empty.
+    fn eval_map_expr(&mut self, map: &Mapping) -> String;
+
+    /// Execute the command of a `<Cmd>` mapping.
+    fn exec_map_cmd(&mut self, map: &Mapping, cmd: &str);
+}
+
+/// Where typed keys come from.
+pub trait KeySource: MapHost {
+    /// The next typed key, waiting at most `timeout` milliseconds, or until
+    /// a key is typed for None.  Returns None when no key was typed.
+    fn get_key(&mut self, timeout: Option<u64>) -> Option<u32>;
+}
+
+/// Keys waiting to be used.
+#[derive(Debug, Default)]
+pub struct TypeBuf {
+    /// The keys and whether they may be mapped.
+    keys: VecDeque<(u32, bool)>,
+    /// Number of mappings expanded since a key was typed.
+    depth: usize,
+    /// Number of keys at the start that result from an abbreviation.
+    no_abbr: usize,
+    /// The key vgetc() returned last resulted from an abbreviation.
+    no_abbr_key: bool,
+}
+
+impl TypeBuf {
+    pub fn new() -> Self {
+        TypeBuf { keys: VecDeque::new(), depth: 0, no_abbr: 0, no_abbr_key: false }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Insert `keys` to be used before the keys that are waiting.
+    pub fn insert(&mut self, keys: &[u32], remap: bool) {
+        for &key in keys.iter().rev() {
+            self.keys.push_front((key, remap));
+        }
+        if self.no_abbr > 0 {
+            self.no_abbr += keys.len();
+        }
+    }
+
+    /// Remove `count` keys from the start.
+    fn remove(&mut self, count: usize) {
+        self.keys.drain(..count);
+        self.no_abbr = self.no_abbr.saturating_sub(count);
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.no_abbr = 0;
+    }
+
+    /// Remove the first key and return it.
+    fn take(&mut self) -> u32 {
+        self.no_abbr_key = self.no_abbr > 0;
+        self.no_abbr = self.no_abbr.saturating_sub(1);
+        self.keys.pop_front().map_or(0, |(key, _)| key)
+    }
+
+    /// Add `keys` after the keys that are waiting, like feedkeys().
+    pub fn feed(&mut self, keys: &[u32], remap: bool) {
+        self.keys.extend(keys.iter().map(|&key| (key, remap)));
+    }
+
+    /// Get the next key for `mode` with buffer `buf` current, with mappings
+    /// expanded.  Returns None when no key was typed.  A recursive mapping
+    /// gives an error and the waiting keys are dropped.
+    pub fn vgetc(&mut self, mode: Mode, buf: usize, src: &mut dyn KeySource) -> Result<Option<u32>, String> {
+        loop {
+            if self.keys.is_empty() {
+                self.depth = 0;
+                match src.get_key(None) {
+                    Some(key) => self.keys.push_back((key, true)),
+                    None => {
+                        self.no_abbr_key = false;
+                        return Ok(None);
+                    }
+                }
+            }
+            if !self.keys[0].1 {
+                return Ok(Some(self.take()));
+            }
+            let keys: Vec<u32> = self.keys.iter().take_while(|(_, remap)| *remap).map(|&(key, _)| key).collect();
+            let map = match src.maps().lookup(mode, buf, &keys) {
+                Lookup::None => None,
+                Lookup::Full(map) => Some(map.clone()),
+                Lookup::Partial(full) => {
+                    let full = full.cloned();
+                    // Only typed keys can complete the mapping.
+                    let typed = if keys.len() == self.keys.len() { src.get_key(src.timeoutlen()) } else { None };
+                    if let Some(key) = typed {
+                        self.keys.push_back((key, true));
+                        continue;
+                    }
+                    full
+                }
+            };
+            match map {
+                Some(map) => self.expand(&map, src)?,
+                None => return Ok(Some(self.take())),
+            }
+        }
+    }
+
+    /// Replace the left-hand side of `map` at the start with its right-hand
+    /// side.
+    fn expand(&mut self, map: &Mapping, src: &mut dyn KeySource) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_MAPDEPTH {
+            self.clear();
+            return Err("E223: Recursive mapping".to_string());
+        }
+        self.remove(map.lhs.len());
+        let remap = !(map.noremap || map.script);
+        if map.is_cmd() {
+            let rest = &map.rhs_keys[1..];
+            let Some(end) = rest.iter().position(|&key| key == CAR) else {
+                self.clear();
+                return Err("E1255: <Cmd> mapping must end with <CR>".to_string());
+            };
+            let cmd: String = rest[..end].iter().filter_map(|&key| char::from_u32(key)).collect();
+            src.exec_map_cmd(map, &cmd);
+            self.insert(&rest[end + 1..], remap);
+            return Ok(());
+        }
+        let rhs = if map.expr { chars_to_keys(&src.eval_map_expr(map)) } else { map.rhs_keys.clone() };
+        self.insert(&rhs, remap);
+        // When the right-hand side starts with the left-hand side its first
+        // key is not mapped again, so that ":map x xyz" works.
+        if remap && rhs.starts_with(&map.lhs) {
+            if let Some(first) = self.keys.front_mut() {
+                first.1 = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand the abbreviation at the end of `before`, the text before the
+    /// cursor, when `key` is typed, like Vim's check_abbr().  It does not
+    /// start before byte `mincol`.  Keys that delete the abbreviation, its replacement and
+    /// `key` are inserted, `key` must not be used now.  CTRL-] expands
+    /// without inserting a key.  Returns false when there is no
+    /// abbreviation, or `key` resulted from one.
+    pub fn check_abbr(
+        &mut self,
+        key: u32,
+        before: &str,
+        mincol: usize,
+        mode: Mode,
+        buf: usize,
+        host: &mut dyn MapHost,
+    ) -> bool {
+        if self.no_abbr_key {
+            return false;
+        }
+        let Some((map, len)) = host.abbrs().find_abbr(before, mincol, mode, buf) else {
+            return false;
+        };
+        let map = map.clone();
+        let typed = if key == CTRL_RSB { 0 } else { 1 };
+        if typed > 0 {
+            self.insert(&[key], false);
+        }
+        let rhs = if map.expr { chars_to_keys(&host.eval_map_expr(&map)) } else { map.rhs_keys.clone() };
+        self.insert(&rhs, !(map.noremap || map.script));
+        self.insert(&vec![BS; len], false);
+        self.no_abbr = len + rhs.len() + typed;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{map_command, parse_keys, MapContext};
+
+    /// Typed keys and the mappings, with a clock that only moves while
+    /// waiting.
+    struct Typed {
+        maps: MapTable,
+        abbrs: MapTable,
+        /// Keys and the time they are typed at.
+        input: VecDeque<(u64, u32)>,
+        now: u64,
+        cmds: Vec<String>,
+    }
+
+    impl Typed {
+        fn new(maps: &[&str], input: &[(u64, &str)]) -> Self {
+            let ctx = MapContext { curbuf: 1, sid: 0, leader: "\\", localleader: "" };
+            let mut table = MapTable::new();
+            for line in maps {
+                let (cmd, arg) = line.split_once(' ').unwrap();
+                let (cmd, mode) = map_command(cmd, false).unwrap();
+                table.do_map(cmd, mode, arg, &ctx).unwrap();
+            }
+            let input = input
+                .iter()
+                .flat_map(|&(time, text)| parse_keys(text, "", "").into_iter().map(move |key| (time, key)))
+                .collect();
+            Typed { maps: table, abbrs: MapTable::new_abbr(), input, now: 0, cmds: Vec::new() }
+        }
+
+        fn get_all(&mut self, mode: Mode) -> Result<String, String> {
+            let mut typebuf = TypeBuf::new();
+            let mut keys = Vec::new();
+            while let Some(key) = typebuf.vgetc(mode, 1, self)? {
+                keys.push(key);
+            }
+            Ok(crate::keys_to_string(&keys))
+        }
+    }
+
+    impl MapHost for Typed {
+        fn maps(&self) -> &MapTable {
+            &self.maps
+        }
+
+        fn abbrs(&self) -> &MapTable {
+            &self.abbrs
+        }
+
+        fn timeoutlen(&self) -> Option<u64> {
+            Some(1000)
+        }
+
+        fn eval_map_expr(&mut self, map: &Mapping) -> String {
+            map.rhs.trim_matches('"').to_uppercase()
+        }
+
+        fn exec_map_cmd(&mut self, _map: &Mapping, cmd: &str) {
+            self.cmds.push(cmd.to_string());
+        }
+    }
+
+    impl KeySource for Typed {
+        fn get_key(&mut self, timeout: Option<u64>) -> Option<u32> {
+            let &(time, key) = self.input.front()?;
+            if timeout.is_some_and(|t| time > self.now + t) {
+                self.now += timeout.unwrap();
+                return None;
+            }
+            self.now = self.now.max(time);
+            self.input.pop_front();
+            Some(key)
+        }
+    }
+
+    #[test]
+    fn ambiguous_prefix_waits_for_timeout() {
+        let maps = ["nmap a <Left>", "nmap ab <Right>", "nmap x y", "nmap y z"];
+        // "b" is typed in time to complete "ab".
+        assert_eq!(Typed::new(&maps, &[(0, "a"), (500, "b")]).get_all(Mode::NORMAL), Ok("<Right>".into()));
+        // "b" comes too late: "a" is used alone.
+        assert_eq!(Typed::new(&maps, &[(0, "a"), (1500, "b")]).get_all(Mode::NORMAL), Ok("<Left>b".into()));
+        assert_eq!(Typed::new(&maps, &[(0, "ac")]).get_all(Mode::NORMAL), Ok("<Left>c".into()));
+        // Recursive expansion.
+        assert_eq!(Typed::new(&maps, &[(0, "x")]).get_all(Mode::NORMAL), Ok("z".into()));
+        assert_eq!(Typed::new(&maps, &[(0, "x")]).get_all(Mode::INSERT), Ok("x".into()));
+    }
+
+    #[test]
+    fn noremap_recursion_expr_and_cmd() {
+        let maps = ["nnoremap x y", "nmap y z", "nmap j jx", "nmap <expr> e \"ab\"", "nmap c <Cmd>let x = 1<CR>y", "nmap r s", "nmap s r"];
+        let mut typed = Typed::new(&maps, &[(0, "xjec")]);
+        assert_eq!(typed.get_all(Mode::NORMAL), Ok("yjyABz".into()));
+        assert_eq!(typed.cmds, ["let x = 1"]);
+        assert_eq!(Typed::new(&maps, &[(0, "r")]).get_all(Mode::NORMAL), Err("E223: Recursive mapping".into()));
+    }
+
+    #[test]
+    fn abbreviation_keys_are_not_expanded_again() {
+        let ctx = MapContext { curbuf: 1, sid: 0, leader: "\\", localleader: "" };
+        let mut typed = Typed::new(&["imap x y"], &[(0, " ")]);
+        typed.abbrs.do_map(crate::MapCmd::Map, Mode::INSERT, "teh the", &ctx).unwrap();
+        typed.abbrs.do_map(crate::MapCmd::Map, Mode::INSERT, "ab abx", &ctx).unwrap();
+        let mut typebuf = TypeBuf::new();
+        let key = typebuf.vgetc(Mode::INSERT, 1, &mut typed).unwrap().unwrap();
+        assert!(typebuf.check_abbr(key, "a teh", 2, Mode::INSERT, 1, &mut typed));
+        let mut keys = Vec::new();
+        while let Some(key) = typebuf.vgetc(Mode::INSERT, 1, &mut typed).unwrap() {
+            assert!(!typebuf.check_abbr(key, "the", 0, Mode::INSERT, 1, &mut typed));
+            keys.push(key);
+        }
+        assert_eq!(crate::keys_to_string(&keys), "<BS><BS><BS>the<Space>");
+
+        // CTRL-] inserts nothing, the replacement is mapped.
+        assert!(typebuf.check_abbr(CTRL_RSB, "ab", 0, Mode::INSERT, 1, &mut typed));
+        let mut keys = Vec::new();
+        while let Some(key) = typebuf.vgetc(Mode::INSERT, 1, &mut typed).unwrap() {
+            keys.push(key);
+        }
+        assert_eq!(crate::keys_to_string(&keys), "<BS><BS>aby");
+    }
+}
@@ -1,52 +1,263 @@
+//! A file of fixed size pages, used in blocks of one or more pages, like
+//! Vim's memfile.c.  The swap file is a memfile.
+//!
+//! Block numbers are page numbers: a block of N pages starting at page 5
+//! occupies pages 5 to 5 + N - 1.  Blocks are kept in memory and written to
+//! the file by [`MemFile::sync`], only the ones that changed.  A freed block
+//! is used again by [`MemFile::new_block`] before the file grows.
 
-#[repr(C)]
-pub struct MemFile {
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The number of a block, the page where it starts.
+pub type BlockNr = u64;
+
+struct Block {
     data: Vec<u8>,
+    /// Changed since it was last written.
+    dirty: bool,
+}
+
+pub struct MemFile {
+    page_size: usize,
+    blocks: BTreeMap<BlockNr, Block>,
+    /// Freed blocks and their number of pages.
+    free: BTreeMap<BlockNr, usize>,
+    /// The first page after the last block.
+    page_count: BlockNr,
+    file: Option<File>,
+    path: Option<PathBuf>,
 }
 
 impl MemFile {
-    pub fn new() -> Self {
-        Self { data: Vec::new() }
+    /// A memfile that is only in memory.
+    pub fn new(page_size: usize) -> Self {
+        MemFile {
+            page_size,
+            blocks: BTreeMap::new(),
+            free: BTreeMap::new(),
+            page_count: 0,
+            file: None,
+            path: None,
+        }
+    }
+
+    /// Create a memfile in file `path`, which must not exist yet.  On Unix
+    /// only the user can read it.
+    pub fn create(path: &Path, page_size: usize) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(path)?;
+        Ok(MemFile { file: Some(file), path: Some(path.to_path_buf()), ..MemFile::new(page_size) })
+    }
+
+    /// Open existing memfile `path` for reading, e.g. to recover from it.
+    /// The page size can be changed once block 0 has been read.
+    pub fn open(path: &Path, page_size: usize) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut mf = MemFile { file: Some(file), path: Some(path.to_path_buf()), ..MemFile::new(page_size) };
+        mf.page_count = len.div_ceil(page_size as u64);
+        Ok(mf)
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Use pages of `page_size` bytes.  The blocks in memory are dropped.
+    pub fn set_page_size(&mut self, page_size: usize) -> io::Result<()> {
+        self.page_size = page_size;
+        self.blocks.clear();
+        self.free.clear();
+        self.page_count = match &self.file {
+            Some(file) => file.metadata()?.len().div_ceil(page_size as u64),
+            None => 0,
+        };
+        Ok(())
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The number of pages in use, including freed ones.
+    pub fn page_count(&self) -> BlockNr {
+        self.page_count
+    }
+
+    /// Allocate a block of `page_count` pages, filled with zeros.  A freed
+    /// block that is big enough is used first, what remains of it stays
+    /// free.
+    pub fn new_block(&mut self, page_count: usize) -> BlockNr {
+        let found = self.free.iter().find(|(_, &count)| count >= page_count).map(|(&nr, &count)| (nr, count));
+        let nr = match found {
+            Some((nr, count)) => {
+                self.free.remove(&nr);
+                if count > page_count {
+                    self.free.insert(nr + page_count as BlockNr, count - page_count);
+                }
+                nr
+            }
+            None => {
+                let nr = self.page_count;
+                self.page_count += page_count as BlockNr;
+                nr
+            }
+        };
+        self.blocks.insert(nr, Block { data: vec![0; page_count * self.page_size], dirty: true });
+        nr
+    }
+
+    /// Allocate block `nr` of `page_count` pages at a fixed place, e.g. block
+    /// zero.  Returns false when it overlaps a block in use.
+    pub fn new_block_at(&mut self, nr: BlockNr, page_count: usize) -> bool {
+        let end = nr + page_count as BlockNr;
+        let overlaps = self
+            .blocks
+            .range(..end)
+            .any(|(&start, block)| start + (block.data.len() / self.page_size) as BlockNr > nr);
+        if overlaps {
+            return false;
+        }
+        self.page_count = self.page_count.max(end);
+        self.blocks.insert(nr, Block { data: vec![0; page_count * self.page_size], dirty: true });
+        true
+    }
+
+    /// Block `nr` of `page_count` pages, read from the file when it is not
+    /// in memory.
+    pub fn get(&mut self, nr: BlockNr, page_count: usize) -> io::Result<&[u8]> {
+        if !self.blocks.contains_key(&nr) {
+            let data = self.read_block(nr, page_count)?;
+            self.blocks.insert(nr, Block { data, dirty: false });
+        }
+        Ok(&self.blocks[&nr].data)
+    }
+
+    /// Block `nr` to change it, when it is in memory.  It is written by the
+    /// next sync.
+    pub fn get_mut(&mut self, nr: BlockNr) -> Option<&mut [u8]> {
+        let block = self.blocks.get_mut(&nr)?;
+        block.dirty = true;
+        Some(&mut block.data)
+    }
+
+    /// Give block `nr` back, its pages can be used for another block.
+    pub fn free_block(&mut self, nr: BlockNr) {
+        if let Some(block) = self.blocks.remove(&nr) {
+            self.free.insert(nr, block.data.len() / self.page_size);
+        }
+    }
+
+    /// Whether a block changed since the last sync.
+    pub fn is_dirty(&self) -> bool {
+        self.blocks.values().any(|block| block.dirty)
+    }
+
+    fn read_block(&mut self, nr: BlockNr, page_count: usize) -> io::Result<Vec<u8>> {
+        let offset = nr * self.page_size as u64;
+        let file = self.file.as_mut().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no file"))?;
+        let mut data = vec![0; page_count * self.page_size];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
     }
-    pub fn write(&mut self, buf: &[u8]) {
-        self.data.extend_from_slice(buf);
+
+    /// Write the changed blocks to the file.  With `fsync` also make sure
+    /// they are on disk, so that they survive a crash of the system.
+    pub fn sync(&mut self, fsync: bool) -> io::Result<()> {
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+        for (&nr, block) in self.blocks.iter_mut().filter(|(_, block)| block.dirty) {
+            file.seek(SeekFrom::Start(nr * self.page_size as u64))?;
+            file.write_all(&block.data)?;
+            block.dirty = false;
+        }
+        file.set_len(self.page_count * self.page_size as u64)?;
+        if fsync {
+            file.sync_all()?;
+        }
+        Ok(())
     }
-    pub fn read(&self) -> &[u8] {
-        &self.data
+
+    /// Close the file, deleting it when `delete` is set.
+    pub fn close(mut self, delete: bool) -> io::Result<()> {
+        self.file = None;
+        match self.path.take() {
+            Some(path) if delete => std::fs::remove_file(path),
+            _ => Ok(()),
+        }
     }
 }
 
 #[no_mangle]
-pub extern "C" fn rs_memfile_new() -> *mut MemFile {
-    Box::into_raw(Box::new(MemFile::new()))
+pub extern "C" fn rs_memfile_new(page_size: usize) -> *mut MemFile {
+    Box::into_raw(Box::new(MemFile::new(page_size)))
 }
 
+/// Allocate a block of `page_count` pages, returns its number.
+///
+/// # Safety
+///
+/// `ptr` must come from [`rs_memfile_new`] and not have been freed.
 #[no_mangle]
-pub extern "C" fn rs_memfile_write(ptr: *mut MemFile, data: *const u8, len: usize) {
-    if ptr.is_null() || data.is_null() {
-        return;
-    }
-    let mf = unsafe { &mut *ptr };
-    let slice = unsafe { std::slice::from_raw_parts(data, len) };
-    mf.write(slice);
+pub unsafe extern "C" fn rs_memfile_new_block(ptr: *mut MemFile, page_count: usize) -> BlockNr {
+    let mf = &mut *ptr;
+    mf.new_block(page_count)
 }
 
+/// The data of block `nr`, to change it.  NULL when there is no such block.
+///
+/// # Safety
+///
+/// `ptr` must be NULL or come from [`rs_memfile_new`] and not have been
+/// freed, `len` must be NULL or valid for writes.  The data is valid until
+/// the memfile is changed.
 #[no_mangle]
-pub extern "C" fn rs_memfile_read(ptr: *const MemFile, len: *mut usize) -> *const u8 {
+pub unsafe extern "C" fn rs_memfile_block(ptr: *mut MemFile, nr: BlockNr, len: *mut usize) -> *mut u8 {
     if ptr.is_null() {
-        return std::ptr::null();
+        return std::ptr::null_mut();
     }
-    let mf = unsafe { &*ptr };
-    if !len.is_null() {
-        unsafe { *len = mf.data.len(); }
+    let mf = &mut *ptr;
+    match mf.get_mut(nr) {
+        Some(data) => {
+            if !len.is_null() {
+                *len = data.len();
+            }
+            data.as_mut_ptr()
+        }
+        None => std::ptr::null_mut(),
     }
-    mf.read().as_ptr()
 }
 
+/// Give block `nr` back.
+///
+/// # Safety
+///
+/// `ptr` must be NULL or come from [`rs_memfile_new`] and not have been
+/// freed.
 #[no_mangle]
-pub extern "C" fn rs_memfile_free(ptr: *mut MemFile) {
+pub unsafe extern "C" fn rs_memfile_free_block(ptr: *mut MemFile, nr: BlockNr) {
     if !ptr.is_null() {
-        unsafe { drop(Box::from_raw(ptr)); }
+        let mf = &mut *ptr;
+        mf.free_block(nr);
+    }
+}
+
+/// # Safety
+///
+/// `ptr` must be NULL or come from [`rs_memfile_new`] and not have been
+/// freed already.
+#[no_mangle]
+pub unsafe extern "C" fn rs_memfile_free(ptr: *mut MemFile) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(ptr));
     }
 }
 
@@ -56,14 +267,50 @@ mod tests {
 
     #[test]
     fn roundtrip() {
-        let mf = rs_memfile_new();
-        let data = b"hello";
-        rs_memfile_write(mf, data.as_ptr(), data.len());
+        let mf = rs_memfile_new(16);
+        let nr = unsafe { rs_memfile_new_block(mf, 2) };
         let mut len: usize = 0;
-        let ptr = rs_memfile_read(mf, &mut len as *mut usize);
-        assert_eq!(len, 5);
-        let slice = unsafe { std::slice::from_raw_parts(ptr, len) };
-        assert_eq!(slice, data);
-        rs_memfile_free(mf);
+        let ptr = unsafe { rs_memfile_block(mf, nr, &mut len as *mut usize) };
+        assert_eq!(len, 32);
+        let data = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+        data[..5].copy_from_slice(b"hello");
+        let ptr = unsafe { rs_memfile_block(mf, nr, &mut len as *mut usize) };
+        let slice = unsafe { std::slice::from_raw_parts(ptr, 5) };
+        assert_eq!(slice, b"hello");
+        assert!(unsafe { rs_memfile_block(mf, 7, &mut len as *mut usize) }.is_null());
+        unsafe { rs_memfile_free(mf) };
+    }
+
+    #[test]
+    fn allocate_sync_and_read() {
+        let dir = std::env::temp_dir().join(format!("memfile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.swp");
+        let _ = std::fs::remove_file(&path);
+
+        let mut mf = MemFile::create(&path, 64).unwrap();
+        assert!(MemFile::create(&path, 64).is_err());
+        assert!(mf.new_block_at(0, 1));
+        let one = mf.new_block(1);
+        let two = mf.new_block(3);
+        assert_eq!((one, two), (1, 2));
+        mf.get_mut(two).unwrap()[..3].copy_from_slice(b"abc");
+        mf.sync(true).unwrap();
+        assert!(!mf.is_dirty());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 5 * 64);
+
+        // A freed block is used again, what is left of it stays free.
+        mf.free_block(two);
+        assert_eq!(mf.new_block(1), 2);
+        assert_eq!(mf.new_block(2), 3);
+        assert_eq!(mf.new_block(1), 5);
+        assert!(!mf.new_block_at(4, 1));
+
+        let mut read = MemFile::open(&path, 64).unwrap();
+        assert_eq!(&read.get(2, 3).unwrap()[..3], b"abc");
+        assert!(read.get(9, 1).is_err());
+        mf.close(true).unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
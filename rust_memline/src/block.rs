@@ -0,0 +1,467 @@
+//! The blocks of a swap file, laid out like Vim does, so that Vim can
+//! recover from our swap files and the other way around.
+//!
+//! Block 0 says which file the swap file is for and who is editing it.
+//! Block 1 is the root of a tree of pointer blocks, the leaves are data
+//! blocks with the text.  Numbers in block 0 are stored with the lowest
+//! byte first, the other blocks use the byte order of the machine, with the
+//! magic numbers in block 0 to check that it matches.
+//!
+//! With 'key' set the text in the data blocks is encrypted, the headers
+//! and the pointer blocks are not.  Block 0 has the salt of the key and the
+//! seed the text was encrypted with, the pointer entries have the sync that
+//! wrote each data block.  Vim cannot recover such a swap file.
+
+use rust_crypt::{CryptKey, NONCE_LEN, SALT_LEN};
+
+/// What Vim versions before 3.0 cannot read, written in block 0.
+pub const SWAP_VERSION: &str = "VIM 9.1";
+
+/// The smallest page size for a swap file: block 0 must fit in one page.
+pub const MIN_SWAP_PAGE_SIZE: usize = 1048;
+
+/// The page size of new swap files.
+pub const SWAP_PAGE_SIZE: usize = 4096;
+
+const B0_UNAME_SIZE: usize = 40;
+const B0_HNAME_SIZE: usize = 40;
+const B0_FNAME_SIZE_ORG: usize = 900;
+/// The room for the file name and 'fileencoding', the last two bytes of
+/// the original file name field are the flags and the dirty byte.
+const B0_FNAME_SIZE_NOCRYPT: usize = 898;
+/// The room for the file name and 'fileencoding' when the swap file is
+/// encrypted: the salt and the seed follow.
+const B0_FNAME_SIZE_CRYPT: usize = B0_FNAME_SIZE_NOCRYPT - SALT_LEN - SEED_LEN;
+/// The second byte of the id of block 0: "b0" for a swap file that is not
+/// encrypted.  Vim uses 'c' to 'f' for its methods.
+const B0_ID_NOCRYPT: u8 = b'0';
+const B0_ID_CRYPT: u8 = b'x';
+
+/// The size of the seed, which changes with the key.
+pub const SEED_LEN: usize = 16;
+
+const B0_VERSION: usize = 2;
+const B0_PAGE_SIZE: usize = 12;
+const B0_MTIME: usize = 16;
+const B0_INO: usize = 20;
+const B0_PID: usize = 24;
+const B0_UNAME: usize = 28;
+const B0_HNAME: usize = B0_UNAME + B0_UNAME_SIZE;
+const B0_FNAME: usize = B0_HNAME + B0_HNAME_SIZE;
+const B0_MAGIC_LONG: usize = B0_FNAME + B0_FNAME_SIZE_ORG;
+const B0_MAGIC_INT: usize = B0_MAGIC_LONG + 8;
+const B0_MAGIC_SHORT: usize = B0_MAGIC_INT + 4;
+const B0_MAGIC_CHAR: usize = B0_MAGIC_SHORT + 2;
+/// The size of block 0, without the rest of its page.
+pub const B0_SIZE: usize = 1024;
+
+const B0_MAGIC_LONG_VALUE: i64 = 0x3031_3233;
+const B0_MAGIC_INT_VALUE: i32 = 0x2021_2223;
+const B0_MAGIC_SHORT_VALUE: i16 = 0x1213;
+const B0_MAGIC_CHAR_VALUE: u8 = 0x55;
+/// The dirty byte when the buffer has changes that were not written.
+const B0_DIRTY: u8 = 0x55;
+
+/// Flags: the swap file is in the directory of the file.
+const B0_SAME_DIR: u8 = 4;
+/// Flags: 'fileencoding' is stored at the end of the file name field.
+const B0_HAS_FENC: u8 = 8;
+
+/// Block 0 of a swap file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Block0 {
+    pub version: String,
+    pub page_size: u32,
+    /// When the file being edited was last modified, in seconds.
+    pub mtime: u32,
+    /// The inode number of the file being edited.
+    pub ino: u32,
+    /// The process editing the file, zero when the file was closed.
+    pub pid: u32,
+    pub uname: String,
+    pub hname: String,
+    /// The file being edited, with the home directory replaced by "~".
+    pub fname: String,
+    /// 'fileencoding' of the buffer, empty when not stored.
+    pub fenc: String,
+    /// The buffer has changes that were not written.
+    pub dirty: bool,
+    /// The swap file is in the directory of the file, `fname` is only used
+    /// for its tail.
+    pub same_dir: bool,
+    /// The text is encrypted.
+    pub crypt: Option<SwapCrypt>,
+}
+
+/// What decrypting the text of a swap file takes besides the passphrase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SwapCrypt {
+    /// The salt the key was derived with, with the default parameters.
+    pub salt: [u8; SALT_LEN],
+    /// Makes the nonce of each data block differ from that of the last sync.
+    pub seed: [u8; SEED_LEN],
+}
+
+/// Why block 0 cannot be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Block0Error {
+    /// It does not start with "b0".
+    NotSwapFile,
+    /// It was written by Vim 3.0, which uses another layout.
+    Version3,
+    /// The magic numbers do not match: another byte order or word size.
+    WrongMagic,
+    /// It was encrypted by Vim.
+    UnknownCrypt,
+}
+
+fn put_str(block: &mut [u8], offset: usize, size: usize, s: &str) {
+    let bytes = s.as_bytes();
+    let len = bytes.len().min(size - 1);
+    block[offset..offset + len].copy_from_slice(&bytes[..len]);
+}
+
+fn get_str(block: &[u8], offset: usize, size: usize) -> String {
+    let field = &block[offset..offset + size];
+    let len = field.iter().position(|&b| b == 0).unwrap_or(size);
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+fn put_u32(block: &mut [u8], offset: usize, n: u32) {
+    block[offset..offset + 4].copy_from_slice(&n.to_le_bytes());
+}
+
+fn get_u32(block: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap())
+}
+
+impl Block0 {
+    /// Block 0 in a page of `page_size` bytes.
+    pub fn encode(&self, page_size: usize) -> Vec<u8> {
+        let mut block = vec![0; page_size.max(B0_SIZE)];
+        block[0] = b'b';
+        block[1] = if self.crypt.is_some() { B0_ID_CRYPT } else { B0_ID_NOCRYPT };
+        put_str(&mut block, B0_VERSION, 10, &self.version);
+        put_u32(&mut block, B0_PAGE_SIZE, self.page_size);
+        put_u32(&mut block, B0_MTIME, self.mtime);
+        put_u32(&mut block, B0_INO, self.ino);
+        put_u32(&mut block, B0_PID, self.pid);
+        put_str(&mut block, B0_UNAME, B0_UNAME_SIZE, &self.uname);
+        put_str(&mut block, B0_HNAME, B0_HNAME_SIZE, &self.hname);
+        let fname_size = if self.crypt.is_some() { B0_FNAME_SIZE_CRYPT } else { B0_FNAME_SIZE_NOCRYPT };
+        put_str(&mut block, B0_FNAME, fname_size, &self.fname);
+        let mut flags = if self.same_dir { B0_SAME_DIR } else { 0 };
+        // 'fileencoding' goes at the end of the file name field, when both
+        // fit with a NUL in between.
+        let fenc = self.fenc.as_bytes();
+        if !fenc.is_empty() && self.fname.len() + 1 + fenc.len() < fname_size {
+            let start = B0_FNAME + fname_size - fenc.len();
+            block[start..start + fenc.len()].copy_from_slice(fenc);
+            flags |= B0_HAS_FENC;
+        }
+        if let Some(crypt) = &self.crypt {
+            let start = B0_FNAME + B0_FNAME_SIZE_CRYPT;
+            block[start..start + SALT_LEN].copy_from_slice(&crypt.salt);
+            block[start + SALT_LEN..start + SALT_LEN + SEED_LEN].copy_from_slice(&crypt.seed);
+        }
+        block[B0_FNAME + B0_FNAME_SIZE_ORG - 2] = flags;
+        block[B0_FNAME + B0_FNAME_SIZE_ORG - 1] = if self.dirty { B0_DIRTY } else { 0 };
+        block[B0_MAGIC_LONG..B0_MAGIC_LONG + 8].copy_from_slice(&B0_MAGIC_LONG_VALUE.to_ne_bytes());
+        block[B0_MAGIC_INT..B0_MAGIC_INT + 4].copy_from_slice(&B0_MAGIC_INT_VALUE.to_ne_bytes());
+        block[B0_MAGIC_SHORT..B0_MAGIC_SHORT + 2].copy_from_slice(&B0_MAGIC_SHORT_VALUE.to_ne_bytes());
+        block[B0_MAGIC_CHAR] = B0_MAGIC_CHAR_VALUE;
+        block
+    }
+
+    /// The host name in block 0 `block`, also when it cannot be decoded
+    /// otherwise.
+    pub fn host_name(block: &[u8]) -> String {
+        match block.len() >= B0_SIZE {
+            true => get_str(block, B0_HNAME, B0_HNAME_SIZE),
+            false => String::new(),
+        }
+    }
+
+    pub fn decode(block: &[u8]) -> Result<Block0, Block0Error> {
+        if block.len() < B0_SIZE || block[0] != b'b' {
+            return Err(Block0Error::NotSwapFile);
+        }
+        let encrypted = match block[1] {
+            B0_ID_NOCRYPT => false,
+            B0_ID_CRYPT => true,
+            b'c'..=b'f' => return Err(Block0Error::UnknownCrypt),
+            _ => return Err(Block0Error::NotSwapFile),
+        };
+        let version = get_str(block, B0_VERSION, 10);
+        if version.starts_with("VIM 3.0") {
+            return Err(Block0Error::Version3);
+        }
+        if block[B0_MAGIC_LONG..B0_MAGIC_LONG + 8] != B0_MAGIC_LONG_VALUE.to_ne_bytes()
+            || block[B0_MAGIC_INT..B0_MAGIC_INT + 4] != B0_MAGIC_INT_VALUE.to_ne_bytes()
+            || block[B0_MAGIC_SHORT..B0_MAGIC_SHORT + 2] != B0_MAGIC_SHORT_VALUE.to_ne_bytes()
+            || block[B0_MAGIC_CHAR] != B0_MAGIC_CHAR_VALUE
+        {
+            return Err(Block0Error::WrongMagic);
+        }
+        let flags = block[B0_FNAME + B0_FNAME_SIZE_ORG - 2];
+        let fname_size = if encrypted { B0_FNAME_SIZE_CRYPT } else { B0_FNAME_SIZE_NOCRYPT };
+        let fenc = if flags & B0_HAS_FENC != 0 {
+            let field = &block[B0_FNAME..B0_FNAME + fname_size];
+            let start = field.iter().rposition(|&b| b == 0).map_or(0, |i| i + 1);
+            String::from_utf8_lossy(&field[start..]).into_owned()
+        } else {
+            String::new()
+        };
+        Ok(Block0 {
+            version,
+            page_size: get_u32(block, B0_PAGE_SIZE),
+            mtime: get_u32(block, B0_MTIME),
+            ino: get_u32(block, B0_INO),
+            pid: get_u32(block, B0_PID),
+            uname: get_str(block, B0_UNAME, B0_UNAME_SIZE),
+            hname: get_str(block, B0_HNAME, B0_HNAME_SIZE),
+            fname: get_str(block, B0_FNAME, fname_size),
+            fenc,
+            dirty: block[B0_FNAME + B0_FNAME_SIZE_ORG - 1] != 0,
+            same_dir: flags & B0_SAME_DIR != 0,
+            crypt: encrypted.then(|| {
+                let start = B0_FNAME + B0_FNAME_SIZE_CRYPT;
+                SwapCrypt {
+                    salt: block[start..start + SALT_LEN].try_into().unwrap(),
+                    seed: block[start + SALT_LEN..start + SALT_LEN + SEED_LEN].try_into().unwrap(),
+                }
+            }),
+        })
+    }
+}
+
+const DATA_ID: u16 = ((b'd' as u16) << 8) | b'a' as u16;
+const PTR_ID: u16 = ((b'p' as u16) << 8) | b't' as u16;
+
+/// Size of the data block header: id, free, txt_start, txt_end and the
+/// line count.
+const DB_HEADER_SIZE: usize = 24;
+/// The bit in an index entry Vim uses to mark a line for `:global`.
+const DB_INDEX_MASK: u32 = 0x7fff_ffff;
+
+/// A data block: the text of a number of lines.  The text is stored from
+/// the end of the block backwards, each line ending in a NUL, the index
+/// after the header has the offset of where each line starts.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DataBlock {
+    pub lines: Vec<String>,
+}
+
+impl DataBlock {
+    /// The bytes a data block needs for `lines`.
+    pub fn size_needed<'a>(lines: impl IntoIterator<Item = &'a str>) -> usize {
+        DB_HEADER_SIZE + lines.into_iter().map(|line| 4 + line.len() + 1).sum::<usize>()
+    }
+
+    /// The block, `size` bytes, at least [`DataBlock::size_needed`].
+    pub fn encode(&self, size: usize) -> Vec<u8> {
+        let mut block = vec![0; size];
+        let mut txt_start = size;
+        for (i, line) in self.lines.iter().enumerate() {
+            txt_start -= line.len() + 1;
+            block[txt_start..txt_start + line.len()].copy_from_slice(line.as_bytes());
+            let index = DB_HEADER_SIZE + 4 * i;
+            block[index..index + 4].copy_from_slice(&(txt_start as u32).to_ne_bytes());
+        }
+        let free = txt_start - (DB_HEADER_SIZE + 4 * self.lines.len());
+        block[..2].copy_from_slice(&DATA_ID.to_ne_bytes());
+        block[4..8].copy_from_slice(&(free as u32).to_ne_bytes());
+        block[8..12].copy_from_slice(&(txt_start as u32).to_ne_bytes());
+        block[12..16].copy_from_slice(&(size as u32).to_ne_bytes());
+        block[16..24].copy_from_slice(&(self.lines.len() as i64).to_ne_bytes());
+        block
+    }
+
+    /// The lines of data block `block`.  None when it is not a data block or
+    /// its header is invalid.
+    pub fn decode(block: &[u8]) -> Option<DataBlock> {
+        let u32_at = |offset: usize| block.get(offset..offset + 4).map(|b| u32::from_ne_bytes(b.try_into().unwrap()));
+        if block.len() < DB_HEADER_SIZE || u16::from_ne_bytes([block[0], block[1]]) != DATA_ID {
+            return None;
+        }
+        let txt_end = u32_at(12)? as usize;
+        let count = i64::from_ne_bytes(block[16..24].try_into().unwrap());
+        if txt_end > block.len() || count < 0 || DB_HEADER_SIZE + 4 * count as usize > txt_end {
+            return None;
+        }
+        let mut lines = Vec::with_capacity(count as usize);
+        let mut end = txt_end;
+        for i in 0..count as usize {
+            let start = (u32_at(DB_HEADER_SIZE + 4 * i)? & DB_INDEX_MASK) as usize;
+            if start > end {
+                return None;
+            }
+            // The text ends in a NUL, text properties may follow it.
+            let text = &block[start..end];
+            let len = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+            lines.push(String::from_utf8_lossy(&text[..len]).into_owned());
+            end = start;
+        }
+        Some(DataBlock { lines })
+    }
+}
+
+/// Encrypt or decrypt the text of data block `block`, block `nr` of the
+/// swap file written by sync `generation`, in place.  The nonce is the
+/// seed, the block number and the generation.  Other blocks are left alone.
+pub fn crypt_data_block(block: &mut [u8], nr: u64, generation: u32, key: &CryptKey, seed: &[u8; SEED_LEN]) {
+    let u32_at = |offset: usize| u32::from_ne_bytes(block[offset..offset + 4].try_into().unwrap()) as usize;
+    if block.len() < DB_HEADER_SIZE || u16::from_ne_bytes([block[0], block[1]]) != DATA_ID {
+        return;
+    }
+    let end = u32_at(12).min(block.len());
+    let start = u32_at(8).min(end);
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..SEED_LEN].copy_from_slice(seed);
+    nonce[SEED_LEN..SEED_LEN + 4].copy_from_slice(&(nr as u32).to_le_bytes());
+    nonce[SEED_LEN + 4..].copy_from_slice(&generation.to_le_bytes());
+    key.xor(&nonce, &mut block[start..end]);
+}
+
+/// An entry of a pointer block: a data block or another pointer block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PtrEntry {
+    pub bnum: i64,
+    /// The number of lines in the block and the blocks below it.
+    pub line_count: i64,
+    /// The number of the first line, used when recovering.
+    pub old_lnum: i64,
+    pub page_count: i32,
+    /// The sync that wrote the data block, part of the nonce when the text
+    /// is encrypted.  Vim leaves these bytes unused.
+    pub generation: u32,
+}
+
+const PB_HEADER_SIZE: usize = 8;
+const PE_SIZE: usize = 32;
+
+/// A pointer block: the blocks below a node of the tree.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PtrBlock {
+    pub entries: Vec<PtrEntry>,
+}
+
+impl PtrBlock {
+    /// How many entries fit in a pointer block of `page_size` bytes.
+    pub fn count_max(page_size: usize) -> usize {
+        (page_size - PB_HEADER_SIZE) / PE_SIZE
+    }
+
+    pub fn encode(&self, page_size: usize) -> Vec<u8> {
+        let mut block = vec![0; page_size];
+        block[..2].copy_from_slice(&PTR_ID.to_ne_bytes());
+        block[2..4].copy_from_slice(&(self.entries.len() as u16).to_ne_bytes());
+        block[4..6].copy_from_slice(&(Self::count_max(page_size) as u16).to_ne_bytes());
+        for (i, pe) in self.entries.iter().enumerate() {
+            let at = PB_HEADER_SIZE + PE_SIZE * i;
+            block[at..at + 8].copy_from_slice(&pe.bnum.to_ne_bytes());
+            block[at + 8..at + 16].copy_from_slice(&pe.line_count.to_ne_bytes());
+            block[at + 16..at + 24].copy_from_slice(&pe.old_lnum.to_ne_bytes());
+            block[at + 24..at + 28].copy_from_slice(&pe.page_count.to_ne_bytes());
+            block[at + 28..at + 32].copy_from_slice(&pe.generation.to_ne_bytes());
+        }
+        block
+    }
+
+    /// The entries of pointer block `block`.  None when it is not a pointer
+    /// block or the count is invalid.
+    pub fn decode(block: &[u8]) -> Option<PtrBlock> {
+        let u16_at = |offset: usize| u16::from_ne_bytes([block[offset], block[offset + 1]]) as usize;
+        if block.len() < PB_HEADER_SIZE || u16_at(0) != PTR_ID as usize {
+            return None;
+        }
+        let count = u16_at(2);
+        if count > u16_at(4) || PB_HEADER_SIZE + PE_SIZE * count > block.len() {
+            return None;
+        }
+        let i64_at = |offset: usize| i64::from_ne_bytes(block[offset..offset + 8].try_into().unwrap());
+        let entries = (0..count)
+            .map(|i| {
+                let at = PB_HEADER_SIZE + PE_SIZE * i;
+                PtrEntry {
+                    bnum: i64_at(at),
+                    line_count: i64_at(at + 8),
+                    old_lnum: i64_at(at + 16),
+                    page_count: i32::from_ne_bytes(block[at + 24..at + 28].try_into().unwrap()),
+                    generation: u32::from_ne_bytes(block[at + 28..at + 32].try_into().unwrap()),
+                }
+            })
+            .collect();
+        Some(PtrBlock { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode() {
+        let b0 = Block0 {
+            version: SWAP_VERSION.to_string(),
+            page_size: 4096,
+            mtime: 1_700_000_000,
+            pid: 42,
+            uname: "user".to_string(),
+            fname: "~/notes.txt".to_string(),
+            fenc: "latin1".to_string(),
+            dirty: true,
+            ..Block0::default()
+        };
+        let block = b0.encode(4096);
+        assert_eq!(&block[..9], b"b0VIM 9.1");
+        assert_eq!(&block[12..16], &[0, 16, 0, 0]);
+        assert_eq!(Block0::decode(&block), Ok(b0));
+        assert_eq!(Block0::decode(&block[..100]), Err(Block0Error::NotSwapFile));
+        let mut wrong = block.clone();
+        wrong[B0_MAGIC_CHAR] = 0;
+        assert_eq!(Block0::decode(&wrong), Err(Block0Error::WrongMagic));
+
+        let db = DataBlock { lines: vec!["one".to_string(), String::new(), "three".to_string()] };
+        let size = DataBlock::size_needed(db.lines.iter().map(String::as_str));
+        assert_eq!(size, 24 + 3 * 4 + 4 + 1 + 6);
+        let block = db.encode(64);
+        assert_eq!(&block[60..64], b"one\0");
+        assert_eq!(DataBlock::decode(&block), Some(db));
+        assert_eq!(DataBlock::decode(&[0; 64]), None);
+
+        let pe = PtrEntry { bnum: 2, line_count: 3, old_lnum: 1, page_count: 1, generation: 4 };
+        let pb = PtrBlock { entries: vec![pe] };
+        assert_eq!(PtrBlock::count_max(4096), 127);
+        assert_eq!(PtrBlock::decode(&pb.encode(4096)), Some(pb));
+        assert_eq!(PtrBlock::decode(&DataBlock::default().encode(64)), None);
+    }
+
+    #[test]
+    fn encrypted() {
+        let crypt = SwapCrypt { salt: [1; SALT_LEN], seed: [2; SEED_LEN] };
+        let b0 = Block0 { fname: "x".repeat(900), fenc: "latin1".to_string(), crypt: Some(crypt), ..Block0::default() };
+        let block = b0.encode(4096);
+        assert_eq!(&block[..2], b"bx");
+        let decoded = Block0::decode(&block).unwrap();
+        assert_eq!((decoded.fname.len(), decoded.fenc.as_str()), (B0_FNAME_SIZE_CRYPT - 1, ""));
+        assert_eq!(decoded.crypt, Some(crypt));
+        let mut vim = block.clone();
+        vim[1] = b'f';
+        assert_eq!(Block0::decode(&vim), Err(Block0Error::UnknownCrypt));
+
+        let key = CryptKey::new("secret", Default::default());
+        let db = DataBlock { lines: vec!["some text".to_string()] };
+        let mut block = db.encode(64);
+        crypt_data_block(&mut block, 2, 1, &key, &crypt.seed);
+        assert!(!block.windows(4).any(|w| w == b"text"));
+        let mut other = block.clone();
+        crypt_data_block(&mut other, 3, 1, &key, &crypt.seed);
+        assert_ne!(DataBlock::decode(&other), Some(db.clone()));
+        let mut later = block.clone();
+        crypt_data_block(&mut later, 2, 2, &key, &crypt.seed);
+        assert_ne!(DataBlock::decode(&later), Some(db.clone()));
+        crypt_data_block(&mut block, 2, 1, &key, &crypt.seed);
+        assert_eq!(DataBlock::decode(&block), Some(db));
+    }
+}
@@ -2,6 +2,18 @@ use ropey::{Rope, RopeSlice};
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
+use std::path::Path;
+
+mod block;
+mod recover;
+mod swap;
+
+pub use block::{Block0, Block0Error, DataBlock, PtrBlock, PtrEntry, SwapCrypt, MIN_SWAP_PAGE_SIZE, SWAP_PAGE_SIZE};
+pub use recover::{recover, Recovered};
+pub use swap::{
+    attention, find_swap_files, is_swap_ext, open_swap, recover_names, swap_names, Attention, LineChange, SwapChoice,
+    SwapFile, SwapOpen, SwapOptions,
+};
 
 // Maximum length of a single line allowed in the memline buffer.  This keeps
 // the C side from accidentally passing an unbounded string and exhausting
@@ -15,6 +27,8 @@ pub struct MemBuffer {
     // Workspace for exposing lines to C as modifiable C strings.
     // Keyed by lnum. Stored with a trailing NUL.
     workspace: HashMap<usize, Vec<u8>>,
+    /// The swap file, synced after 'updatecount' changes.
+    swap: Option<SwapFile>,
 }
 
 impl MemBuffer {
@@ -22,19 +36,109 @@ impl MemBuffer {
         Self {
             lines: Rope::new(),
             workspace: HashMap::new(),
+            swap: None,
         }
     }
 
-    fn line_count(&self) -> usize {
+    pub fn line_count(&self) -> usize {
         self.lines.len_lines().saturating_sub(1)
     }
 
+    /// The text of line `lnum`, None when there is no such line.
+    pub fn ml_get(&self, lnum: usize) -> Option<String> {
+        if lnum == 0 || lnum > self.line_count() {
+            return None;
+        }
+        Some(rope_line(&self.lines, lnum))
+    }
+
+    /// Use `swap` for this buffer and write the text to it.
+    pub fn set_swap(&mut self, swap: SwapFile) -> std::io::Result<()> {
+        self.swap = Some(swap);
+        self.ml_sync_all()
+    }
+
+    pub fn swap(&self) -> Option<&SwapFile> {
+        self.swap.as_ref()
+    }
+
+    /// Stop using the swap file, deleting it when `delete` is set.
+    pub fn close_swap(&mut self, delete: bool) -> std::io::Result<()> {
+        match self.swap.take() {
+            Some(swap) => swap.close(delete),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the text to the swap file.
+    pub fn ml_sync_all(&mut self) -> std::io::Result<()> {
+        let line_count = self.line_count();
+        let Self { lines, swap, .. } = self;
+        match swap.as_mut() {
+            Some(swap) => swap.sync(line_count, |lnum| rope_line(lines, lnum)),
+            None => Ok(()),
+        }
+    }
+
+    /// Sync the swap file when nothing was typed for 'updatetime'.  Returns
+    /// the milliseconds until that is due, None when there is nothing to
+    /// sync.
+    pub fn sync_when_due(&mut self) -> Option<u64> {
+        match self.swap.as_ref()?.sync_due()? {
+            0 => {
+                // When it fails it is tried again after the next change.
+                let _ = self.ml_sync_all();
+                None
+            }
+            ms => Some(ms),
+        }
+    }
+
+    /// Encrypt the text in the swap file with `key`, None or empty to stop
+    /// encrypting it, and write it again.
+    pub fn set_crypt_key(&mut self, key: Option<&str>) -> std::io::Result<()> {
+        match self.swap.as_mut() {
+            Some(swap) => swap.set_key(key),
+            None => return Ok(()),
+        }
+        self.ml_sync_all()
+    }
+
+    /// Store in the swap file whether the buffer has unwritten changes.
+    pub fn set_modified(&mut self, modified: bool) -> std::io::Result<()> {
+        match self.swap.as_mut() {
+            Some(swap) => swap.set_dirty(modified),
+            None => Ok(()),
+        }
+    }
+
+    /// Replace the text with what is recovered from swap file `path`,
+    /// decrypted with `key`.  Returns the messages for the user.
+    pub fn ml_recover(&mut self, path: &Path, key: Option<&str>) -> Result<Vec<String>, String> {
+        let recovered = recover(path, key)?;
+        self.lines = Rope::new();
+        self.workspace.clear();
+        for (i, line) in recovered.lines.iter().enumerate() {
+            self.ml_append(i, line);
+        }
+        Ok(recovered.messages)
+    }
+
+    /// Count `change` for the swap file, sync it at 'updatecount'.
+    fn changed(&mut self, change: LineChange) {
+        if self.swap.as_mut().is_some_and(|swap| swap.changed(change)) {
+            // When it fails it is tried again after the next change.
+            let _ = self.ml_sync_all();
+        }
+    }
+
     pub fn ml_append(&mut self, lnum: usize, line: &str) -> bool {
         if lnum > self.line_count() {
             return false;
         }
         let char_idx = self.lines.line_to_char(lnum);
         self.lines.insert(char_idx, &format!("{}\n", line));
+        self.changed(LineChange::Appended(lnum));
         true
     }
 
@@ -46,6 +150,7 @@ impl MemBuffer {
         let end = self.lines.line_to_char(lnum);
         let removed = self.lines.slice(start..end).to_string();
         self.lines.remove(start..end);
+        self.changed(LineChange::Deleted(lnum));
         Some(removed.trim_end_matches('\n').to_string())
     }
 
@@ -58,10 +163,16 @@ impl MemBuffer {
         let old = self.lines.slice(start..end).to_string();
         self.lines.remove(start..end);
         self.lines.insert(start, &format!("{}\n", line));
+        self.changed(LineChange::Replaced(lnum));
         Some(old.trim_end_matches('\n').to_string())
     }
 }
 
+/// Line `lnum` of `lines`, without the line break.
+fn rope_line(lines: &Rope, lnum: usize) -> String {
+    lines.line(lnum - 1).to_string().trim_end_matches('\n').to_string()
+}
+
 fn rope_slice_to_cstring(slice: RopeSlice) -> CString {
     let mut s = slice.to_string();
     if s.ends_with('\n') {
@@ -171,33 +282,11 @@ pub extern "C" fn ml_line_count(buf: *const MemBuffer) -> usize {
     b.line_count()
 }
 
-/// Representation of the initial swap file block.
-#[repr(C)]
-pub struct Block0 {
-    pub version: u32,
-    pub mtime: u64,
-}
-
-/// Representation of a pointer block in a swap file.
-#[repr(C)]
-pub struct PtrBlock {
-    pub header: u32,
-    pub pointers: [u32; 1],
-}
-
-/// Representation of a data block in a swap file.
-#[repr(C)]
-pub struct DataBlock {
-    pub header: u32,
-    pub data: [u8; 4096],
-}
-
 use memmap2::MmapMut;
 use std::fs::OpenOptions;
-use std::io::Result;
 
 /// Map a file into memory, creating it if needed.
-pub fn map_file(path: &str, size: usize) -> Result<MmapMut> {
+pub fn map_file(path: &str, size: usize) -> std::io::Result<MmapMut> {
     let file = OpenOptions::new()
         .read(true)
         .write(true)
@@ -0,0 +1,179 @@
+//! Recovering the text of a buffer from its swap file, like Vim's
+//! ml_recover().  Blocks that cannot be read or are damaged give lines
+//! starting with "???" in the recovered text, so that the user can find
+//! them.  The text of an encrypted swap file is decrypted with the key
+//! given; with a wrong key it is garbage.
+
+use std::path::{Path, PathBuf};
+
+use rust_crypt::{CryptKey, ScryptParams};
+use rust_memfile::MemFile;
+
+use crate::block::{
+    crypt_data_block, Block0, Block0Error, DataBlock, PtrBlock, PtrEntry, SwapCrypt, B0_SIZE, MIN_SWAP_PAGE_SIZE,
+};
+use crate::swap::{expand_home, file_stat, process_running};
+
+/// The result of recovering from a swap file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recovered {
+    /// The file the swap file is for, empty for a buffer without a name.
+    pub fname: PathBuf,
+    pub lines: Vec<String>,
+    pub block0: Block0,
+    /// The number of damaged or missing blocks.
+    pub errors: usize,
+    /// The text differs from the original file.
+    pub changed: bool,
+    /// What to tell the user, in order.
+    pub messages: Vec<String>,
+}
+
+/// Recover the text from swap file `path`, decrypting it with the
+/// passphrase `key` when it is encrypted.  Errors for a swap file that
+/// cannot be used at all are returned as a message.
+pub fn recover(path: &Path, key: Option<&str>) -> Result<Recovered, String> {
+    let name = path.to_string_lossy();
+    let mut mf = MemFile::open(path, B0_SIZE).map_err(|_| format!("E306: Cannot open {}", name))?;
+    let block = match mf.get(0, 1) {
+        Ok(block) => block,
+        Err(_) => {
+            return Err(format!(
+                "Unable to read block 0 from {}\nMaybe no changes were made or Vim did not update the swap file.",
+                name
+            ))
+        }
+    };
+    let b0 = match Block0::decode(block) {
+        Ok(b0) => b0,
+        Err(Block0Error::Version3) => {
+            return Err(format!("{} cannot be used with this version of Vim.\nUse Vim version 3.0.", name))
+        }
+        Err(Block0Error::NotSwapFile) => return Err(format!("E307: {} does not look like a Vim swap file", name)),
+        Err(Block0Error::UnknownCrypt) => return Err("E821: File is encrypted with unknown method".to_string()),
+        Err(Block0Error::WrongMagic) => {
+            return Err(format!(
+                "{} cannot be used on this computer.\nThe file was created on {},\nor the file has been damaged.",
+                name,
+                Block0::host_name(block)
+            ))
+        }
+    };
+    if (b0.page_size as usize) < MIN_SWAP_PAGE_SIZE {
+        return Err(format!("{} has been damaged (page size is smaller than minimum value).", name));
+    }
+    let _ = mf.set_page_size(b0.page_size as usize);
+    let crypt = match (b0.crypt, key.filter(|key| !key.is_empty())) {
+        (None, _) => None,
+        (Some(_), None) => return Err(format!("Swap file is encrypted: \"{}\"", name)),
+        (Some(crypt), Some(key)) => Some((CryptKey::derive(key, &crypt.salt, ScryptParams::default()), crypt)),
+    };
+
+    let fname = original_name(path, &b0);
+    let mut messages = vec![format!("Using swap file \"{}\"", name)];
+    messages.push(format!("Original file \"{}\"", fname.to_string_lossy()));
+    let (mtime, _) = file_stat(&fname);
+    if b0.mtime != 0 && mtime > b0.mtime {
+        messages.push("E308: Warning: Original file may have been changed".to_string());
+    }
+
+    let root = mf.get(1, 1).ok().and_then(PtrBlock::decode);
+    let Some(root) = root else {
+        return match mf.get(1, 1) {
+            Err(_) => Err(format!("E309: Unable to read block 1 from {}", name)),
+            Ok(_) => Err(format!("E310: Block 1 ID wrong ({} not a .swp file?)", name)),
+        };
+    };
+    let mut recovery = Recovery { mf, crypt, lines: Vec::new(), errors: 0 };
+    recovery.ptr_block(&root, 0);
+    let Recovery { lines, errors, .. } = recovery;
+
+    let original = std::fs::read_to_string(&fname).ok();
+    let changed = !original.is_some_and(|text| text.lines().eq(lines.iter().map(String::as_str)));
+    if errors > 0 {
+        messages.push(">>>>>>>>>>>>>".to_string());
+        messages.push("E312: Errors detected while recovering; look for lines starting with ???".to_string());
+        messages.push("See \":help E312\" for more information.".to_string());
+        messages.push(">>>>>>>>>>>>>".to_string());
+    } else {
+        if !changed {
+            messages.push("Recovery completed. Buffer contents equals file contents.".to_string());
+        } else {
+            messages.push("Recovery completed. You should check if everything is OK.".to_string());
+            messages.push("(You might want to write out this file under another name".to_string());
+            messages.push("and run diff with the original file to check for changes)".to_string());
+        }
+        messages.push("You may want to delete the .swp file now.".to_string());
+        if process_running(&b0) {
+            messages.push(format!("Note: process STILL RUNNING: {}", b0.pid));
+        }
+    }
+    Ok(Recovered { fname, lines, block0: b0, errors, changed, messages })
+}
+
+/// The file swap file `path` is for: in the directory of the swap file when
+/// it was created there, "~" expanded.
+fn original_name(path: &Path, b0: &Block0) -> PathBuf {
+    if b0.fname.is_empty() {
+        return PathBuf::new();
+    }
+    let fname = expand_home(&b0.fname);
+    match (b0.same_dir, fname.file_name()) {
+        (true, Some(tail)) => path.with_file_name(tail),
+        _ => fname,
+    }
+}
+
+/// Maximum depth of the tree, a deeper one must be damaged.
+const MAX_DEPTH: usize = 100;
+
+struct Recovery {
+    mf: MemFile,
+    crypt: Option<(CryptKey, SwapCrypt)>,
+    lines: Vec<String>,
+    errors: usize,
+}
+
+impl Recovery {
+    fn missing(&mut self, text: &str) {
+        self.errors += 1;
+        self.lines.push(text.to_string());
+    }
+
+    fn ptr_block(&mut self, pb: &PtrBlock, depth: usize) {
+        if pb.entries.is_empty() {
+            self.missing("???EMPTY BLOCK");
+        }
+        for pe in &pb.entries {
+            self.entry(pe, depth);
+        }
+    }
+
+    fn entry(&mut self, pe: &PtrEntry, depth: usize) {
+        let page_count = pe.page_count.max(1) as usize;
+        let mut block = match self.mf.get(pe.bnum as u64, page_count) {
+            Ok(block) if pe.bnum > 1 && depth < MAX_DEPTH => block.to_vec(),
+            _ => return self.missing("???MANY LINES MISSING"),
+        };
+        if let Some(pb) = PtrBlock::decode(&block) {
+            if pb.entries.iter().map(|pe| pe.line_count).sum::<i64>() != pe.line_count {
+                self.missing("???LINE COUNT WRONG");
+            }
+            return self.ptr_block(&pb, depth + 1);
+        }
+        if let Some((key, crypt)) = &self.crypt {
+            crypt_data_block(&mut block, pe.bnum as u64, pe.generation, key, &crypt.seed);
+        }
+        let Some(db) = DataBlock::decode(&block) else {
+            return self.missing("???BLOCK MISSING");
+        };
+        let count_wrong = db.lines.len() as i64 != pe.line_count;
+        if count_wrong {
+            self.missing("??? from here until ???END lines may have been inserted/deleted");
+        }
+        self.lines.extend(db.lines);
+        if count_wrong {
+            self.lines.push("???END".to_string());
+        }
+    }
+}
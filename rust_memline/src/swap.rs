@@ -0,0 +1,854 @@
+//! Swap files: the text of a buffer in a [`MemFile`], so that changes can
+//! be recovered after a crash, and what Vim tells the user about them.
+//!
+//! The swap file is written ("synced") after 'updatecount' changes and when
+//! the user did not type anything for 'updatetime' milliseconds.  Block 0
+//! is written when the swap file is created and when the buffer becomes
+//! modified or unmodified.  When the swap file for a file already exists
+//! the user is asked what to do with the ATTENTION message.
+//!
+//! Like Vim's memline only the data blocks with changed lines and the
+//! pointer blocks above them are written.  With a key the text is
+//! encrypted, the nonce of a data block has the sync that wrote it so that no
+//! key stream is used twice.
+
+use std::ffi::CStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use rust_crypt::{CryptKey, ScryptParams};
+use rust_memfile::{BlockNr, MemFile};
+
+use crate::block::{
+    crypt_data_block, Block0, Block0Error, DataBlock, PtrBlock, PtrEntry, SwapCrypt, SWAP_PAGE_SIZE, SWAP_VERSION,
+};
+
+/// When a swap file is synced and how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapOptions {
+    /// 'updatecount': sync after this many changes, zero for never.
+    pub updatecount: usize,
+    /// 'updatetime': sync when nothing was typed for this many
+    /// milliseconds.
+    pub updatetime: u64,
+    /// 'swapsync' is not empty: make sure the swap file is on disk.
+    pub fsync: bool,
+}
+
+impl Default for SwapOptions {
+    fn default() -> Self {
+        SwapOptions { updatecount: 200, updatetime: 4000, fsync: true }
+    }
+}
+
+/// A change to the text of a buffer, for [`SwapFile::changed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineChange {
+    /// A line was inserted below line `lnum`, zero for above the first one.
+    Appended(usize),
+    Deleted(usize),
+    Replaced(usize),
+}
+
+/// A data block of the swap file and the lines of the buffer it holds.
+#[derive(Debug, Clone, Copy)]
+struct DataInfo {
+    nr: BlockNr,
+    page_count: usize,
+    line_count: usize,
+    old_lnum: i64,
+    /// The sync that wrote the block.
+    generation: u32,
+    /// Its lines changed since it was written.
+    dirty: bool,
+}
+
+/// The swap file of a buffer.
+pub struct SwapFile {
+    mf: MemFile,
+    b0: Block0,
+    /// The data blocks in the order of the lines.
+    data: Vec<DataInfo>,
+    /// The pointer blocks between the root and the data blocks, as last
+    /// written.
+    ptrs: Vec<(BlockNr, PtrBlock)>,
+    /// The root, block 1, as last written.
+    root: PtrBlock,
+    /// The number of syncs, part of the nonce of encrypted blocks.
+    generation: u32,
+    /// The number of changes since the last sync.
+    changes: usize,
+    /// When the first change since the last sync was made.
+    changed_at: Option<Instant>,
+    opts: SwapOptions,
+    /// The key the text is encrypted with.
+    key: Option<CryptKey>,
+}
+
+impl SwapFile {
+    /// Create swap file `path` for `fname`, which may not exist yet.  Fails
+    /// when `path` exists.
+    pub fn create(path: &Path, fname: &Path, opts: SwapOptions) -> io::Result<SwapFile> {
+        let mut mf = MemFile::create(path, SWAP_PAGE_SIZE)?;
+        mf.new_block_at(0, 1);
+        mf.new_block_at(1, 1);
+        let (mtime, ino) = file_stat(fname);
+        let b0 = Block0 {
+            version: SWAP_VERSION.to_string(),
+            page_size: SWAP_PAGE_SIZE as u32,
+            mtime,
+            ino,
+            pid: std::process::id(),
+            uname: user_name(current_uid()),
+            hname: host_name(),
+            fname: home_replace(&fname.to_string_lossy()),
+            fenc: String::new(),
+            dirty: false,
+            same_dir: path.parent() == fname.parent(),
+            crypt: None,
+        };
+        let mut swap = SwapFile {
+            mf,
+            b0,
+            data: Vec::new(),
+            ptrs: Vec::new(),
+            root: PtrBlock::default(),
+            generation: 0,
+            changes: 0,
+            changed_at: None,
+            opts,
+            key: None,
+        };
+        swap.sync(0, |_| String::new())?;
+        Ok(swap)
+    }
+
+    pub fn path(&self) -> &Path {
+        self.mf.path().unwrap_or(Path::new(""))
+    }
+
+    pub fn block0(&self) -> &Block0 {
+        &self.b0
+    }
+
+    pub fn options(&self) -> SwapOptions {
+        self.opts
+    }
+
+    pub fn set_options(&mut self, opts: SwapOptions) {
+        self.opts = opts;
+    }
+
+    /// Encrypt the text with the key for `passphrase` from the next sync on,
+    /// None or empty to stop encrypting it.  All data blocks are written
+    /// again.
+    pub fn set_key(&mut self, passphrase: Option<&str>) {
+        self.key = passphrase.filter(|p| !p.is_empty()).map(|p| CryptKey::new(p, ScryptParams::default()));
+        self.b0.crypt = self
+            .key
+            .as_ref()
+            .map(|key| SwapCrypt { salt: *key.salt(), seed: rust_crypt::random_bytes() });
+        self.data.iter_mut().for_each(|info| info.dirty = true);
+    }
+
+    /// Count `change` to the buffer.  Returns true when 'updatecount' has
+    /// been reached and the swap file is to be synced.
+    pub fn changed(&mut self, change: LineChange) -> bool {
+        let (lnum, delta) = match change {
+            LineChange::Appended(lnum) => (lnum.max(1), 1),
+            LineChange::Deleted(lnum) => (lnum, -1),
+            LineChange::Replaced(lnum) => (lnum, 0),
+        };
+        // The block with line `lnum`, the last one for the first line of an
+        // empty buffer.  When the line is not found the counts no longer match
+        // the buffer and the next sync writes all the text.
+        let mut last = 0;
+        let found = self.data.iter().position(|info| {
+            last += info.line_count;
+            lnum <= last
+        });
+        let found = found.or_else(|| if delta > 0 { self.data.len().checked_sub(1) } else { None });
+        if let Some(info) = found.map(|i| &mut self.data[i]) {
+            info.line_count = info.line_count.saturating_add_signed(delta);
+            info.dirty = true;
+        }
+        self.changes += 1;
+        self.changed_at.get_or_insert_with(Instant::now);
+        self.opts.updatecount > 0 && self.changes >= self.opts.updatecount
+    }
+
+    /// The milliseconds until the swap file is to be synced because of
+    /// 'updatetime', zero when that is now.  None when there are no changes
+    /// to sync.
+    pub fn sync_due(&self) -> Option<u64> {
+        let elapsed = self.changed_at?.elapsed().as_millis() as u64;
+        Some(self.opts.updatetime.saturating_sub(elapsed))
+    }
+
+    /// Write the changed lines of the buffer to the swap file.  The buffer
+    /// has `line_count` lines, `line` gets the text of a line.
+    pub fn sync(&mut self, line_count: usize, line: impl Fn(usize) -> String) -> io::Result<()> {
+        self.generation = match self.generation.checked_add(1) {
+            Some(generation) => generation,
+            None => {
+                // The nonces for this seed are used up.
+                if let Some(crypt) = self.b0.crypt.as_mut() {
+                    crypt.seed = rust_crypt::random_bytes();
+                }
+                self.data.iter_mut().for_each(|info| info.dirty = true);
+                0
+            }
+        };
+        self.write_tree(line_count, line);
+        self.write_block0();
+        self.mf.sync(self.opts.fsync)?;
+        self.changes = 0;
+        self.changed_at = None;
+        Ok(())
+    }
+
+    /// Store whether the buffer has changes that were not written, in block
+    /// 0.  It is written right away.
+    pub fn set_dirty(&mut self, dirty: bool) -> io::Result<()> {
+        if self.b0.dirty == dirty {
+            return Ok(());
+        }
+        self.b0.dirty = dirty;
+        self.write_block0();
+        self.mf.sync(self.opts.fsync)
+    }
+
+    /// Close the swap file, deleting it when `delete` is set.
+    pub fn close(self, delete: bool) -> io::Result<()> {
+        self.mf.close(delete)
+    }
+
+    fn write_block0(&mut self) {
+        let block = self.b0.encode(self.mf.page_size());
+        if let Some(data) = self.mf.get_mut(0) {
+            data.copy_from_slice(&block);
+        }
+    }
+
+    /// Write the data blocks with changed lines and the pointer blocks that
+    /// changed because of them, like Vim's memline does.
+    fn write_tree(&mut self, line_count: usize, line: impl Fn(usize) -> String) {
+        self.write_data_blocks(line_count, line);
+        let page_size = self.mf.page_size();
+        let mut entries: Vec<PtrEntry> = self
+            .data
+            .iter()
+            .map(|info| PtrEntry {
+                bnum: info.nr as i64,
+                line_count: info.line_count as i64,
+                old_lnum: info.old_lnum,
+                page_count: info.page_count as i32,
+                generation: info.generation,
+            })
+            .collect();
+
+        // Pointer blocks in between when the root cannot hold all entries.
+        // Those of the last sync are used again, only a changed one is
+        // written.
+        let count_max = PtrBlock::count_max(page_size);
+        let mut used = 0;
+        while entries.len() > count_max {
+            let groups: Vec<Vec<PtrEntry>> = entries.chunks(count_max).map(<[PtrEntry]>::to_vec).collect();
+            entries = Vec::with_capacity(groups.len());
+            for group in groups {
+                let entry = PtrEntry {
+                    line_count: group.iter().map(|pe| pe.line_count).sum(),
+                    old_lnum: group[0].old_lnum,
+                    page_count: 1,
+                    ..PtrEntry::default()
+                };
+                let pb = PtrBlock { entries: group };
+                let nr = match self.ptrs.get(used) {
+                    Some((nr, old)) if *old == pb => *nr,
+                    Some(&(nr, _)) => {
+                        self.put(nr, pb.encode(page_size));
+                        self.ptrs[used].1 = pb;
+                        nr
+                    }
+                    None => {
+                        let nr = self.mf.new_block(1);
+                        self.put(nr, pb.encode(page_size));
+                        self.ptrs.push((nr, pb));
+                        nr
+                    }
+                };
+                used += 1;
+                entries.push(PtrEntry { bnum: nr as i64, ..entry });
+            }
+        }
+        for (nr, _) in self.ptrs.split_off(used) {
+            self.mf.free_block(nr);
+        }
+        let root = PtrBlock { entries };
+        if root != self.root {
+            self.put(1, root.encode(page_size));
+            self.root = root;
+        }
+    }
+
+    /// Write the data blocks with changed lines.  A block that grew is split,
+    /// one without lines is freed.  When the line counts do not match the
+    /// buffer all the text is written.
+    fn write_data_blocks(&mut self, line_count: usize, line: impl Fn(usize) -> String) {
+        if self.data.iter().map(|info| info.line_count).sum::<usize>() != line_count {
+            for info in std::mem::take(&mut self.data) {
+                self.mf.free_block(info.nr);
+            }
+            let nr = self.mf.new_block(1);
+            self.data.push(DataInfo { nr, page_count: 1, line_count, old_lnum: 1, generation: 0, dirty: true });
+        }
+        let page_size = self.mf.page_size();
+        let empty_size = DataBlock::size_needed([]);
+        let mut data = Vec::with_capacity(self.data.len());
+        let mut lnum = 1;
+        for info in std::mem::take(&mut self.data) {
+            if !info.dirty {
+                lnum += info.line_count;
+                data.push(info);
+                continue;
+            }
+            let mut blocks = Vec::new();
+            let mut block = DataBlock::default();
+            let mut size = empty_size;
+            for text in (lnum..lnum + info.line_count).map(&line) {
+                let line_size = DataBlock::size_needed([text.as_str()]) - empty_size;
+                if !block.lines.is_empty() && size + line_size > page_size {
+                    blocks.push((std::mem::take(&mut block), size));
+                    size = empty_size;
+                }
+                block.lines.push(text);
+                size += line_size;
+            }
+            if !block.lines.is_empty() {
+                blocks.push((block, size));
+            }
+            // The first block keeps its number when it still fits.
+            let mut old = Some(info);
+            for (block, size) in blocks {
+                let page_count = size.div_ceil(page_size).max(1);
+                let nr = match old.take() {
+                    Some(old) if old.page_count == page_count => old.nr,
+                    Some(old) => {
+                        self.mf.free_block(old.nr);
+                        self.mf.new_block(page_count)
+                    }
+                    None => self.mf.new_block(page_count),
+                };
+                self.write_data_block(nr, &block, page_count);
+                let line_count = block.lines.len();
+                let generation = self.generation;
+                data.push(DataInfo { nr, page_count, line_count, old_lnum: lnum as i64, generation, dirty: false });
+                lnum += line_count;
+            }
+            if let Some(old) = old {
+                self.mf.free_block(old.nr);
+            }
+        }
+        if data.is_empty() {
+            // An empty buffer still has a data block.
+            let nr = self.mf.new_block(1);
+            self.write_data_block(nr, &DataBlock::default(), 1);
+            let generation = self.generation;
+            data.push(DataInfo { nr, page_count: 1, line_count: 0, old_lnum: 1, generation, dirty: false });
+        }
+        self.data = data;
+    }
+
+    fn write_data_block(&mut self, nr: BlockNr, block: &DataBlock, page_count: usize) {
+        let mut data = block.encode(page_count * self.mf.page_size());
+        if let (Some(key), Some(crypt)) = (&self.key, &self.b0.crypt) {
+            crypt_data_block(&mut data, nr, self.generation, key, &crypt.seed);
+        }
+        self.put(nr, data);
+    }
+
+    fn put(&mut self, nr: BlockNr, data: Vec<u8>) {
+        if let Some(block) = self.mf.get_mut(nr) {
+            block.copy_from_slice(&data);
+        }
+    }
+}
+
+/// The names tried for the swap file of `fname` in directory `dir` of
+/// 'directory', like Vim's makeswapname() and findswapname(): ".foo.swp",
+/// ".foo.swo", down to ".foo.saa".  A "." is the directory of the file and
+/// prepends a dot.  A directory ending in two path separators gives a name
+/// made from the full path, with "%" for each separator.
+pub fn swap_names(fname: &Path, dir: &str) -> impl Iterator<Item = PathBuf> {
+    let tail = fname.file_name().map_or_else(String::new, |t| t.to_string_lossy().into_owned());
+    let base = if dir == "." {
+        let name = if tail.starts_with('.') { tail } else { format!(".{}", tail) };
+        fname.with_file_name(name)
+    } else if dir.ends_with("//") {
+        let full = std::path::absolute(fname).unwrap_or_else(|_| fname.to_path_buf());
+        expand_home(dir).join(full.to_string_lossy().replace('/', "%"))
+    } else if let Some(rel) = dir.strip_prefix("./") {
+        fname.with_file_name(rel).join(tail)
+    } else {
+        expand_home(dir).join(tail)
+    };
+    let base = base.to_string_lossy().into_owned();
+    ('a'..='w').rev().flat_map(move |c2| {
+        let last = if c2 == 'w' { 'p' } else { 'z' };
+        let base = base.clone();
+        ('a'..=last).rev().map(move |c1| PathBuf::from(format!("{}.s{}{}", base, c2, c1)))
+    })
+}
+
+/// The directory of `fname` when `dir` is ".", otherwise `dir` with "~"
+/// expanded.
+fn swap_dir(fname: &Path, dir: &str) -> PathBuf {
+    match dir {
+        "." => fname.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf(),
+        _ => match dir.strip_prefix("./") {
+            Some(rel) => fname.with_file_name(rel),
+            None => expand_home(dir.trim_end_matches('/')),
+        },
+    }
+}
+
+/// The existing swap files for `fname` in the directories of 'directory'
+/// `dirs`.
+pub fn find_swap_files(fname: &Path, dirs: &str) -> Vec<PathBuf> {
+    dirs.split(',')
+        .filter(|dir| !dir.is_empty())
+        .flat_map(|dir| swap_names(fname, dir).take_while(|name| name.exists()))
+        .collect()
+}
+
+/// What the user decides about an existing swap file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapChoice {
+    ReadOnly,
+    Edit,
+    Recover,
+    Delete,
+    Quit,
+    Abort,
+}
+
+impl SwapChoice {
+    /// The choices, "Delete it" only when the process that created the swap
+    /// file is not running.
+    pub fn choices(still_running: bool) -> Vec<SwapChoice> {
+        use SwapChoice::*;
+        [ReadOnly, Edit, Recover, Delete, Quit, Abort]
+            .into_iter()
+            .filter(|&choice| !(still_running && choice == Delete))
+            .collect()
+    }
+
+    /// The button text, "&" before the key.
+    pub fn label(self) -> &'static str {
+        match self {
+            SwapChoice::ReadOnly => "&Open Read-Only",
+            SwapChoice::Edit => "&Edit anyway",
+            SwapChoice::Recover => "&Recover",
+            SwapChoice::Delete => "&Delete it",
+            SwapChoice::Quit => "&Quit",
+            SwapChoice::Abort => "&Abort",
+        }
+    }
+
+    /// The prompt of the dialog in a terminal, the first choice is the
+    /// default: "[O]pen Read-Only, (E)dit anyway, ...: ".
+    pub fn prompt(still_running: bool) -> String {
+        let buttons: Vec<String> = Self::choices(still_running)
+            .iter()
+            .enumerate()
+            .map(|(i, choice)| {
+                let (open, close) = if i == 0 { ('[', ']') } else { ('(', ')') };
+                let label = choice.label().trim_start_matches('&');
+                format!("{}{}{}{}", open, &label[..1], close, &label[1..])
+            })
+            .collect();
+        format!("{}: ", buttons.join(", "))
+    }
+
+    /// The choice for typed `key`, ignoring case.  CR and Esc give the
+    /// default.
+    pub fn from_key(key: char, still_running: bool) -> Option<SwapChoice> {
+        let choices = Self::choices(still_running);
+        if key == '\r' || key == '\n' || key == '\x1b' {
+            return choices.first().copied();
+        }
+        choices.into_iter().find(|choice| choice.label()[1..].starts_with(key.to_ascii_uppercase()))
+    }
+}
+
+/// The ATTENTION message for an existing swap file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attention {
+    pub swap: PathBuf,
+    pub lines: Vec<String>,
+    /// The process that created the swap file is still running.
+    pub still_running: bool,
+}
+
+/// The ATTENTION message for swap file `swap` found when opening `fname`,
+/// like Vim's attention_message().
+pub fn attention(swap: &Path, fname: &Path) -> Attention {
+    let info = swap_info(swap);
+    let mut lines = vec![
+        "E325: ATTENTION".to_string(),
+        format!("Found a swap file by the name \"{}\"", home_replace(&swap.to_string_lossy())),
+    ];
+    lines.extend(info.lines);
+    let fname_str = fname.to_string_lossy();
+    lines.push(format!("While opening file \"{}\"", fname_str));
+    match std::fs::metadata(fname) {
+        Err(_) => lines.push("      CANNOT BE FOUND".to_string()),
+        Ok(_) => {
+            let (mtime, _) = file_stat(fname);
+            lines.push(format!("             dated: {}", ctime(mtime)));
+            if info.mtime != 0 && mtime > info.mtime {
+                lines.push("      NEWER than swap file!".to_string());
+            }
+        }
+    }
+    lines.extend(
+        [
+            "",
+            "(1) Another program may be editing the same file.  If this is the case,",
+            "    be careful not to end up with two different instances of the same",
+            "    file when making changes.  Quit, or continue with caution.",
+            "(2) An edit session for this file crashed.",
+        ]
+        .map(str::to_string),
+    );
+    lines.push(format!("    If this is the case, use \":recover\" or \"vim -r {}\"", fname_str));
+    lines.push("    to recover the changes (see \":help recovery\").".to_string());
+    lines.push(format!("    If you did this already, delete the swap file \"{}\"", swap.to_string_lossy()));
+    lines.push("    to avoid this message.".to_string());
+    Attention { swap: swap.to_path_buf(), lines, still_running: info.still_running }
+}
+
+/// What happened when opening the swap file for a buffer.
+pub enum SwapOpen {
+    /// The swap file was created.
+    Created(Box<SwapFile>),
+    /// The user chose to edit the file read-only, without a swap file.
+    ReadOnly,
+    /// The user chose to recover from this swap file.
+    Recover(PathBuf),
+    Quit,
+    Abort,
+}
+
+/// Create the swap file for `fname` in the first usable directory of
+/// 'directory' `dirs`.  For an existing swap file `ask` gets the ATTENTION
+/// message and decides what to do, like Vim's findswapname().  Swap files
+/// for other files with the same name are skipped.
+pub fn open_swap(
+    fname: &Path,
+    dirs: &str,
+    opts: SwapOptions,
+    ask: &mut dyn FnMut(&Attention) -> SwapChoice,
+) -> Result<SwapOpen, String> {
+    for dir in dirs.split(',').filter(|dir| !dir.is_empty()) {
+        if !swap_dir(fname, dir).is_dir() {
+            continue;
+        }
+        let mut names = swap_names(fname, dir);
+        loop {
+            let Some(name) = names.next() else {
+                return Err("E326: Too many swap files found".to_string());
+            };
+            if name.exists() {
+                if !swap_is_for(&name, fname) {
+                    continue;
+                }
+                match ask(&attention(&name, fname)) {
+                    SwapChoice::Edit => continue,
+                    SwapChoice::Delete => {
+                        if std::fs::remove_file(&name).is_err() {
+                            continue;
+                        }
+                    }
+                    SwapChoice::ReadOnly => return Ok(SwapOpen::ReadOnly),
+                    SwapChoice::Recover => return Ok(SwapOpen::Recover(name)),
+                    SwapChoice::Quit => return Ok(SwapOpen::Quit),
+                    SwapChoice::Abort => return Ok(SwapOpen::Abort),
+                }
+            }
+            match SwapFile::create(&name, fname, opts) {
+                Ok(swap) => return Ok(SwapOpen::Created(Box::new(swap))),
+                // Another Vim created it in the meantime.
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                // Try the next directory.
+                Err(_) => break,
+            }
+        }
+    }
+    Err(format!("E303: Unable to open swap file for \"{}\", recovery impossible", fname.to_string_lossy()))
+}
+
+/// Whether swap file `swap` is for `fname`: it cannot be read or the tail
+/// of the file name matches.
+fn swap_is_for(swap: &Path, fname: &Path) -> bool {
+    let Ok(b0) = read_block0(swap) else {
+        return true;
+    };
+    let tail = |name: &str| name.rsplit('/').next().unwrap_or("").to_string();
+    b0.fname.is_empty() || tail(&b0.fname) == tail(&fname.to_string_lossy())
+}
+
+/// The `vim -r` listing of the swap files in the directories of
+/// 'directory' `dirs`, only those for `fname` when given, like Vim's
+/// recover_names().
+pub fn recover_names(fname: Option<&Path>, dirs: &str) -> Vec<String> {
+    let mut lines = vec!["Swap files found:".to_string()];
+    let mut count = 0;
+    for dir in dirs.split(',').filter(|dir| !dir.is_empty()) {
+        let files: Vec<PathBuf> = match fname {
+            Some(fname) => swap_names(fname, dir).take_while(|name| name.exists()).collect(),
+            None => {
+                let mut files: Vec<PathBuf> = std::fs::read_dir(expand_home(dir))
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().is_some_and(|ext| is_swap_ext(&ext.to_string_lossy())))
+                    .collect();
+                files.sort();
+                files
+            }
+        };
+        lines.push(match (dir, fname) {
+            (".", None) => "   In current directory:".to_string(),
+            (".", Some(_)) => "   Using specified name:".to_string(),
+            _ => format!("   In directory {}:", dir),
+        });
+        if files.is_empty() {
+            lines.push("      -- none --".to_string());
+        }
+        for file in files {
+            count += 1;
+            let tail = file.file_name().map_or_else(String::new, |t| t.to_string_lossy().into_owned());
+            lines.push(format!("{}.    {}", count, tail));
+            lines.extend(swap_info(&file).lines);
+        }
+    }
+    lines
+}
+
+/// "swp" to "saa", the extensions of swap files.
+pub fn is_swap_ext(ext: &str) -> bool {
+    let bytes = ext.as_bytes();
+    bytes.len() == 3 && bytes[0] == b's' && bytes[1].is_ascii_lowercase() && bytes[2].is_ascii_lowercase()
+}
+
+pub(crate) struct SwapInfo {
+    pub(crate) lines: Vec<String>,
+    /// The modification time of the edited file stored in block 0.
+    pub(crate) mtime: u32,
+    pub(crate) still_running: bool,
+}
+
+/// What `vim -r` and the ATTENTION message show about swap file `path`,
+/// like Vim's swapfile_info().
+pub(crate) fn swap_info(path: &Path) -> SwapInfo {
+    let mut lines = Vec::new();
+    let mut info = SwapInfo { lines: Vec::new(), mtime: 0, still_running: false };
+    if let Ok(meta) = std::fs::metadata(path) {
+        let (mtime, _) = file_stat(path);
+        lines.push(match file_owner(&meta) {
+            Some(owner) => format!("          owned by: {}   dated: {}", owner, ctime(mtime)),
+            None => format!("             dated: {}", ctime(mtime)),
+        });
+    }
+    match read_block0(path) {
+        Ok(b0) => {
+            let fname = if b0.fname.is_empty() { "[No Name]" } else { &b0.fname };
+            lines.push(format!("         file name: {}", fname));
+            lines.push(format!("          modified: {}", if b0.dirty { "YES" } else { "no" }));
+            let mut names = String::new();
+            if !b0.uname.is_empty() {
+                names = format!("         user name: {}", b0.uname);
+            }
+            if !b0.hname.is_empty() {
+                if names.is_empty() {
+                    names = format!("         host name: {}", b0.hname);
+                } else {
+                    names.push_str(&format!("   host name: {}", b0.hname));
+                }
+            }
+            if !names.is_empty() {
+                lines.push(names);
+            }
+            if b0.pid != 0 {
+                info.still_running = process_running(&b0);
+                let running = if info.still_running { " (STILL RUNNING)" } else { "" };
+                lines.push(format!("        process ID: {}{}", b0.pid, running));
+            }
+            info.mtime = b0.mtime;
+        }
+        Err(ReadError::Io) => lines.push("         [cannot be read]".to_string()),
+        Err(ReadError::Block0(Block0Error::Version3)) => lines.push("         [from Vim version 3.0]".to_string()),
+        Err(ReadError::Block0(Block0Error::NotSwapFile)) => {
+            lines.push("         [does not look like a Vim swap file]".to_string())
+        }
+        Err(ReadError::Block0(Block0Error::WrongMagic)) => {
+            lines.push("         [not usable on this computer]".to_string())
+        }
+        Err(ReadError::Block0(Block0Error::UnknownCrypt)) => {
+            lines.push("         [encrypted with unknown method]".to_string())
+        }
+    }
+    info.lines = lines;
+    info
+}
+
+pub(crate) enum ReadError {
+    Io,
+    Block0(Block0Error),
+}
+
+/// Block 0 of swap file `path`.
+pub(crate) fn read_block0(path: &Path) -> Result<Block0, ReadError> {
+    let mut mf = MemFile::open(path, crate::block::B0_SIZE).map_err(|_| ReadError::Io)?;
+    let block = mf.get(0, 1).map_err(|_| ReadError::Io)?;
+    Block0::decode(block).map_err(ReadError::Block0)
+}
+
+/// Whether the process that wrote `b0` is still running: it runs on this
+/// host and a signal can be sent to it.
+pub(crate) fn process_running(b0: &Block0) -> bool {
+    if b0.pid == 0 || (!b0.hname.is_empty() && b0.hname != host_name()) {
+        return false;
+    }
+    #[cfg(unix)]
+    {
+        let pid = b0.pid as libc::pid_t;
+        unsafe { libc::kill(pid, 0) == 0 || *libc::__errno_location() == libc::EPERM }
+    }
+    #[cfg(not(unix))]
+    {
+        b0.pid == std::process::id()
+    }
+}
+
+/// The modification time and the inode number of `path`, zero when it does
+/// not exist.
+pub(crate) fn file_stat(path: &Path) -> (u32, u32) {
+    let Ok(meta) = std::fs::metadata(path) else {
+        return (0, 0);
+    };
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as u32);
+    #[cfg(unix)]
+    let ino = std::os::unix::fs::MetadataExt::ino(&meta) as u32;
+    #[cfg(not(unix))]
+    let ino = 0;
+    (mtime, ino)
+}
+
+/// `time` like ctime() does, without the newline.
+pub(crate) fn ctime(time: u32) -> String {
+    let p = unsafe { rust_time::get_ctime(time as libc::time_t, 0) };
+    unsafe { CStr::from_ptr(p) }.to_string_lossy().into_owned()
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+#[cfg(not(unix))]
+fn current_uid() -> u32 {
+    0
+}
+
+/// The name of user `uid`, the number when it has no name.
+#[cfg(unix)]
+fn user_name(uid: u32) -> String {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result = std::ptr::null_mut();
+    let ok = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) } == 0;
+    if ok && !result.is_null() {
+        return unsafe { CStr::from_ptr(pwd.pw_name) }.to_string_lossy().into_owned();
+    }
+    uid.to_string()
+}
+
+#[cfg(not(unix))]
+fn user_name(_uid: u32) -> String {
+    std::env::var("USERNAME").unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn file_owner(meta: &std::fs::Metadata) -> Option<String> {
+    Some(user_name(std::os::unix::fs::MetadataExt::uid(meta)))
+}
+
+#[cfg(not(unix))]
+fn file_owner(_meta: &std::fs::Metadata) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+fn host_name() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len() - 1) } != 0 {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned()
+}
+
+#[cfg(not(unix))]
+fn host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// `name` with the home directory replaced by "~".
+pub(crate) fn home_replace(name: &str) -> String {
+    match std::env::var("HOME") {
+        Ok(home) if !home.is_empty() && name.starts_with(&home) && name[home.len()..].starts_with('/') => {
+            format!("~{}", &name[home.len()..])
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// `name` with a leading "~" replaced by the home directory.
+pub(crate) fn expand_home(name: &str) -> PathBuf {
+    match (name.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => PathBuf::from(format!("{}{}", home, rest)),
+        _ => PathBuf::from(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_choices() {
+        let names: Vec<PathBuf> = swap_names(Path::new("/src/main.c"), ".").take(3).collect();
+        assert_eq!(names, ["/src/.main.c.swp", "/src/.main.c.swo", "/src/.main.c.swn"].map(PathBuf::from));
+        assert_eq!(swap_names(Path::new("/src/main.c"), ".").nth(15), Some(PathBuf::from("/src/.main.c.swa")));
+        assert_eq!(swap_names(Path::new("/src/main.c"), ".").nth(16), Some(PathBuf::from("/src/.main.c.svz")));
+        assert_eq!(swap_names(Path::new("/src/main.c"), "/tmp").next(), Some(PathBuf::from("/tmp/main.c.swp")));
+        assert_eq!(swap_names(Path::new("/src/main.c"), "/tmp//").next(), Some(PathBuf::from("/tmp/%src%main.c.swp")));
+        assert!(is_swap_ext("swp") && is_swap_ext("sab") && !is_swap_ext("rs"));
+
+        assert_eq!(
+            SwapChoice::prompt(false),
+            "[O]pen Read-Only, (E)dit anyway, (R)ecover, (D)elete it, (Q)uit, (A)bort: "
+        );
+        assert_eq!(SwapChoice::prompt(true), "[O]pen Read-Only, (E)dit anyway, (R)ecover, (Q)uit, (A)bort: ");
+        assert_eq!(SwapChoice::from_key('r', false), Some(SwapChoice::Recover));
+        assert_eq!(SwapChoice::from_key('d', true), None);
+        assert_eq!(SwapChoice::from_key('\r', true), Some(SwapChoice::ReadOnly));
+    }
+}
@@ -0,0 +1,205 @@
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use rust_memline::{
+    attention, open_swap, recover, recover_names, MemBuffer, SwapChoice, SwapFile, SwapOpen, SwapOptions,
+};
+
+/// Set in the child process that edits the file and gets killed.
+const WRITER_ENV: &str = "MEMLINE_RECOVERY_WRITER";
+
+fn create_swap(fname: &Path, opts: SwapOptions) -> SwapFile {
+    match open_swap(fname, ".", opts, &mut |att| panic!("{:?}", att.lines)) {
+        Ok(SwapOpen::Created(swap)) => *swap,
+        _ => panic!("no swap file"),
+    }
+}
+
+/// Edit `fname` with a swap file synced every five changes, then wait to be
+/// killed.
+fn writer(fname: &Path) -> ! {
+    let mut buf = MemBuffer::new();
+    buf.ml_append(0, "original");
+    buf.set_swap(create_swap(fname, SwapOptions { updatecount: 5, updatetime: 60_000, fsync: true })).unwrap();
+    buf.set_modified(true).unwrap();
+    buf.ml_replace(1, "changed");
+    for n in 1..=12 {
+        buf.ml_append(n, &format!("line {}", n));
+    }
+    println!("edited");
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+#[test]
+fn recover_after_kill() {
+    if let Some(fname) = std::env::var_os(WRITER_ENV) {
+        writer(Path::new(&fname));
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let fname = dir.path().join("notes.txt");
+    std::fs::write(&fname, "original\n").unwrap();
+
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "recover_after_kill", "--nocapture", "--test-threads=1"])
+        .env(WRITER_ENV, &fname)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    assert!(stdout.lines().map_while(Result::ok).any(|line| line.ends_with("edited")));
+    let pid = child.id();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // The ATTENTION message when editing the file again.
+    let swap = dir.path().join(".notes.txt.swp");
+    let att = attention(&swap, &fname);
+    assert!(!att.still_running);
+    assert_eq!(att.lines[0], "E325: ATTENTION");
+    assert!(att.lines.contains(&"          modified: YES".to_string()));
+    assert!(att.lines.contains(&format!("        process ID: {}", pid)));
+    let mut asked = 0;
+    let opened = open_swap(&fname, ".", SwapOptions::default(), &mut |att| {
+        asked += 1;
+        assert!(SwapChoice::choices(att.still_running).contains(&SwapChoice::Delete));
+        SwapChoice::Recover
+    });
+    assert!(matches!(opened, Ok(SwapOpen::Recover(path)) if path == swap));
+    assert_eq!(asked, 1);
+
+    // Only the changes up to the last sync, after ten changes, are there.
+    let recovered = recover(&swap, None).unwrap();
+    assert_eq!(recovered.fname, fname);
+    assert_eq!(recovered.errors, 0);
+    let expected: Vec<String> =
+        std::iter::once("changed".to_string()).chain((1..=9).map(|n| format!("line {}", n))).collect();
+    assert_eq!(recovered.lines, expected);
+    assert_eq!(recovered.messages[0], format!("Using swap file \"{}\"", swap.display()));
+    assert!(recovered.messages.contains(&"Recovery completed. You should check if everything is OK.".to_string()));
+
+    let mut buf = MemBuffer::new();
+    buf.ml_recover(&swap, None).unwrap();
+    assert_eq!(buf.line_count(), 10);
+    assert_eq!(buf.ml_get(2).as_deref(), Some("line 1"));
+}
+
+#[test]
+fn listing_and_damaged_blocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let fname = dir.path().join("big.txt");
+    let mut buf = MemBuffer::new();
+    for n in 0..6000 {
+        buf.ml_append(n, &format!("{:0>100}", n));
+    }
+    buf.set_swap(create_swap(&fname, SwapOptions::default())).unwrap();
+    let swap = dir.path().join(".big.txt.swp");
+
+    // Enough data blocks for a pointer block below the root.
+    let recovered = recover(&swap, None).unwrap();
+    assert_eq!(recovered.lines.len(), 6000);
+    assert_eq!(recovered.lines[5999], format!("{:0>100}", 5999));
+    assert!(recovered.messages.contains(&format!("Note: process STILL RUNNING: {}", std::process::id())));
+
+    let att = attention(&swap, &fname);
+    assert!(att.still_running);
+    assert!(att.lines.contains(&"      CANNOT BE FOUND".to_string()));
+    let listing = recover_names(Some(&fname), ".");
+    assert_eq!(listing[..3], ["Swap files found:", "   Using specified name:", "1.    .big.txt.swp"]);
+    assert!(listing.contains(&format!("        process ID: {} (STILL RUNNING)", std::process::id())));
+    let other = tempfile::tempdir().unwrap();
+    let dirs = format!(".,{}", other.path().display());
+    assert_eq!(recover_names(Some(&fname), &dirs).last().unwrap(), "      -- none --");
+
+    // A damaged data block.
+    let mut data = std::fs::read(&swap).unwrap();
+    data[3 * 4096] = 0;
+    std::fs::write(&swap, data).unwrap();
+    let recovered = recover(&swap, None).unwrap();
+    assert_eq!(recovered.errors, 1);
+    assert!(recovered.lines.contains(&"???BLOCK MISSING".to_string()));
+    assert!(recovered.messages.contains(&"E312: Errors detected while recovering; look for lines starting with ???".to_string()));
+
+    std::fs::write(dir.path().join("not.swp"), [b'x'; 4096]).unwrap();
+    assert_eq!(recover(&dir.path().join("not.swp"), None).map(|r| r.lines), Err(format!(
+        "E307: {} does not look like a Vim swap file",
+        dir.path().join("not.swp").display()
+    )));
+    buf.close_swap(true).unwrap();
+    assert!(!swap.exists());
+}
+
+#[test]
+fn encrypted_swap_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let fname = dir.path().join("secret.txt");
+    let mut buf = MemBuffer::new();
+    buf.ml_append(0, "the secret text");
+    buf.set_swap(create_swap(&fname, SwapOptions::default())).unwrap();
+    buf.set_crypt_key(Some("key")).unwrap();
+    let swap = dir.path().join(".secret.txt.swp");
+    let data = std::fs::read(&swap).unwrap();
+    assert_eq!(&data[..2], b"bx");
+    assert!(!data.windows(11).any(|w| w == b"secret text"));
+    assert!(recover(&swap, None).unwrap_err().starts_with("Swap file is encrypted:"));
+    assert_eq!(recover(&swap, Some("key")).unwrap().lines, ["the secret text"]);
+
+    // A block written again is encrypted differently, also with the same text.
+    buf.ml_replace(1, "the secret text");
+    buf.ml_sync_all().unwrap();
+    assert_ne!(std::fs::read(&swap).unwrap(), data);
+    assert_eq!(recover(&swap, Some("key")).unwrap().lines, ["the secret text"]);
+    assert_ne!(recover(&swap, Some("wrong")).unwrap().lines, ["the secret text"]);
+
+    buf.set_crypt_key(None).unwrap();
+    assert_eq!(recover(&swap, None).unwrap().lines, ["the secret text"]);
+    buf.close_swap(true).unwrap();
+}
+
+#[test]
+fn sync_writes_changed_blocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let fname = dir.path().join("long.txt");
+    let swap = dir.path().join(".long.txt.swp");
+    let text = |n: usize| format!("line {} {}", n, "x".repeat(60));
+    let mut buf = MemBuffer::new();
+    for n in 0..20_000 {
+        buf.ml_append(n, &text(n + 1));
+    }
+    buf.set_swap(create_swap(&fname, SwapOptions { updatecount: 0, ..SwapOptions::default() })).unwrap();
+    buf.set_crypt_key(Some("key")).unwrap();
+    // The pages that differ from the swap file as it was before.
+    let changed_pages = |before: &[u8]| {
+        let after = std::fs::read(&swap).unwrap();
+        let pages = after.len().max(before.len()).div_ceil(4096);
+        let page = |data: &[u8], i: usize| data.get(i * 4096..(i + 1) * 4096).map(<[u8]>::to_vec);
+        (0..pages).filter(|&i| page(before, i) != page(&after, i)).count()
+    };
+
+    // A changed line: its data block and the pointer block above it, the
+    // line counts in the root stay the same.
+    let before = std::fs::read(&swap).unwrap();
+    buf.ml_replace(10_000, "changed");
+    buf.ml_sync_all().unwrap();
+    assert_eq!(changed_pages(&before), 2);
+    let before = std::fs::read(&swap).unwrap();
+    buf.ml_sync_all().unwrap();
+    assert_eq!(changed_pages(&before), 0);
+
+    // A block that grows is split, one that becomes empty is freed.
+    for n in 0..100 {
+        buf.ml_append(5_000, &format!("new {}", n));
+    }
+    for _ in 0..200 {
+        buf.ml_delete(15_000);
+    }
+    buf.ml_sync_all().unwrap();
+    let mut expected: Vec<String> = (1..=20_000).map(text).collect();
+    expected[9_999] = "changed".to_string();
+    expected.splice(5_000..5_000, (0..100).rev().map(|n| format!("new {}", n)));
+    expected.drain(14_999..15_199);
+    assert_eq!(recover(&swap, Some("key")).unwrap().lines, expected);
+    buf.close_swap(true).unwrap();
+}
@@ -1,6 +1,10 @@
+use std::collections::BTreeMap;
 use std::ffi::CString;
+use std::fmt::Write;
+use std::fs;
 use std::os::raw::{c_char, c_long};
-use std::time::Instant;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use once_cell::sync::Lazy;
 use std::sync::Mutex;
 
@@ -38,11 +42,186 @@ pub extern "C" fn rs_profiler_string_free(s: *mut c_char) {
     }
 }
 
+/// Counts and times of one profiled function.
+#[derive(Debug, Default, Clone)]
+pub struct FuncProfile {
+    pub count: u64,
+    pub total: Duration,
+    pub self_time: Duration,
+    pub source: Vec<String>,
+    pub lines: Vec<LineProfile>,
+}
+
+/// Counts and times of one line of a profiled function.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LineProfile {
+    pub count: u64,
+    pub total: Duration,
+    pub self_time: Duration,
+}
+
+/// Function profiling as done with `:profile start {fname}` and
+/// `:profile func {pattern}`.  The interpreter reports calls and executed
+/// lines; the collected data is written to the file on `:profile dump` and
+/// `:profile stop`.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    file: Option<PathBuf>,
+    patterns: Vec<String>,
+    paused: bool,
+    funcs: BTreeMap<String, FuncProfile>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Execute `:profile {arg}`.
+    pub fn ex_profile(&mut self, arg: &str) -> Result<(), String> {
+        let arg = arg.trim();
+        let (cmd, rest) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+        let rest = rest.trim();
+        match cmd {
+            "start" if !rest.is_empty() => {
+                self.file = Some(PathBuf::from(rest));
+                self.paused = false;
+                self.funcs.clear();
+            }
+            "func" if !rest.is_empty() => {
+                if self.file.is_none() {
+                    return Err("E750: First use \":profile start {fname}\"".to_string());
+                }
+                self.patterns.push(rest.to_string());
+            }
+            "pause" => self.paused = true,
+            "continue" => self.paused = false,
+            "dump" => self.dump()?,
+            "stop" => {
+                self.dump()?;
+                *self = Profiler::default();
+            }
+            _ => return Err(format!("E475: Invalid argument: {}", arg)),
+        }
+        Ok(())
+    }
+
+    /// Whether calls of function `name` are to be profiled now.
+    pub fn profiles(&self, name: &str) -> bool {
+        self.file.is_some()
+            && !self.paused
+            && !name.is_empty()
+            && self.patterns.iter().any(|pat| pattern_match(pat, name))
+    }
+
+    /// The data collected for function `name`.
+    pub fn func(&self, name: &str) -> Option<&FuncProfile> {
+        self.funcs.get(name)
+    }
+
+    fn entry(&mut self, name: &str, source: &[String]) -> &mut FuncProfile {
+        let prof = self.funcs.entry(name.to_string()).or_default();
+        if prof.lines.len() < source.len() {
+            prof.source = source.to_vec();
+            prof.lines.resize(source.len(), LineProfile::default());
+        }
+        prof
+    }
+
+    /// Record a finished call of `name`, whose body is `source`.
+    pub fn add_call(&mut self, name: &str, source: &[String], total: Duration, self_time: Duration) {
+        let prof = self.entry(name, source);
+        prof.count += 1;
+        prof.total += total;
+        prof.self_time += self_time;
+    }
+
+    /// Record one execution of line `line` (zero based) of `name`.
+    pub fn add_line(&mut self, name: &str, source: &[String], line: usize, total: Duration, self_time: Duration) {
+        let prof = self.entry(name, source);
+        if let Some(lp) = prof.lines.get_mut(line) {
+            lp.count += 1;
+            lp.total += total;
+            lp.self_time += self_time;
+        }
+    }
+
+    /// The profile in the format Vim writes to the profile file.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for (name, prof) in &self.funcs {
+            let _ = writeln!(out, "FUNCTION  {}()", name);
+            let _ = writeln!(out, "Called {} time{}", prof.count, if prof.count == 1 { "" } else { "s" });
+            let _ = writeln!(out, "Total time: {}", secs(prof.total));
+            let _ = writeln!(out, " Self time: {}", secs(prof.self_time));
+            out.push_str("\ncount  total (s)   self (s)\n");
+            for (lp, text) in prof.lines.iter().zip(&prof.source) {
+                let line = if lp.count == 0 {
+                    format!("{:29}{}", "", text)
+                } else {
+                    format!("{:5} {} {}   {}", lp.count, secs(lp.total), secs(lp.self_time), text)
+                };
+                let _ = writeln!(out, "{}", line.trim_end());
+            }
+            out.push('\n');
+        }
+        let mut funcs: Vec<(&String, &FuncProfile)> = self.funcs.iter().collect();
+        for (title, by_self) in [("TOTAL", false), ("SELF", true)] {
+            funcs.sort_by_key(|(_, p)| std::cmp::Reverse(if by_self { p.self_time } else { p.total }));
+            let _ = writeln!(out, "FUNCTIONS SORTED ON {} TIME", title);
+            out.push_str("count  total (s)   self (s)  function\n");
+            for (name, p) in &funcs {
+                let _ = writeln!(out, "{:5} {} {}  {}()", p.count, secs(p.total), secs(p.self_time), name);
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Write the profile to the file given with `:profile start`.
+    pub fn dump(&self) -> Result<(), String> {
+        match &self.file {
+            Some(file) => fs::write(file, self.report())
+                .map_err(|_| format!("E484: Can't open file {}", file.display())),
+            None => Ok(()),
+        }
+    }
+}
+
+fn secs(d: Duration) -> String {
+    format!("{:10.6}", d.as_secs_f64())
+}
+
+/// Match `name` against a `:profile func` pattern, where `*` matches any
+/// text and `?` a single character.
+pub fn pattern_match(pat: &str, name: &str) -> bool {
+    let pat: Vec<char> = pat.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Position in `pat` after the last `*` and in `name` where it matched.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        if p < pat.len() && (pat[p] == '?' || pat[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pat.len() && pat[p] == '*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some((sp, sn)) = star {
+            p = sp;
+            n = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    pat[p..].iter().all(|&c| c == '*')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::ffi::CStr;
-    use std::time::Duration;
 
     #[test]
     fn measure_time() {
@@ -59,4 +238,55 @@ mod tests {
         assert_eq!(c.to_str().unwrap(), "0.123456 sec");
         rs_profiler_string_free(ptr);
     }
+
+    #[test]
+    fn patterns() {
+        assert!(pattern_match("*", "Foo"));
+        assert!(pattern_match("<SNR>*_Foo", "<SNR>3_Foo"));
+        assert!(pattern_match("F?o*", "Foobar"));
+        assert!(!pattern_match("Foo", "Foobar"));
+        assert!(!pattern_match("*bar", "barfoo"));
+    }
+
+    #[test]
+    fn profile_commands_and_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("prof.txt");
+        let mut prof = Profiler::new();
+        assert!(prof.ex_profile("func *").unwrap_err().starts_with("E750:"));
+        prof.ex_profile(&format!("start {}", file.display())).unwrap();
+        assert!(!prof.profiles("Foo"));
+        prof.ex_profile("func Fo*").unwrap();
+        assert!(prof.profiles("Foo"));
+        prof.ex_profile("pause").unwrap();
+        assert!(!prof.profiles("Foo"));
+        prof.ex_profile("continue").unwrap();
+
+        let source = vec!["  var x = 1".to_string(), "  return x".to_string()];
+        let ms = Duration::from_millis(1);
+        for _ in 0..2 {
+            prof.add_line("Foo", &source, 0, ms, ms);
+            prof.add_line("Foo", &source, 1, ms, ms);
+            prof.add_call("Foo", &source, 2 * ms, 2 * ms);
+        }
+        let foo = prof.func("Foo").unwrap();
+        assert_eq!(foo.count, 2);
+        assert_eq!(foo.lines[1], LineProfile { count: 2, total: 2 * ms, self_time: 2 * ms });
+
+        prof.ex_profile("stop").unwrap();
+        assert!(!prof.profiles("Foo"));
+        let text = fs::read_to_string(&file).unwrap();
+        let expected_head = "\
+FUNCTION  Foo()
+Called 2 times
+Total time:   0.004000
+ Self time:   0.004000
+
+count  total (s)   self (s)
+    2   0.002000   0.002000     var x = 1
+    2   0.002000   0.002000     return x
+";
+        assert!(text.starts_with(expected_head), "{}", text);
+        assert!(text.contains("FUNCTIONS SORTED ON SELF TIME\ncount  total (s)   self (s)  function\n    2   0.004000   0.004000  Foo()\n"));
+    }
 }
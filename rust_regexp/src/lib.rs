@@ -19,9 +19,11 @@ use std::thread;
 use std::time::Duration;
 
 mod linematch;
+mod pattern;
 
 pub use rust_fuzzy::fuzzy_match;
 pub use linematch::line_match;
+pub use pattern::{set_prev_sub, VimMatch, VimRegex};
 
 /// Search for a match of `pat` anywhere in `text`.
 ///
@@ -0,0 +1,431 @@
+//! Vim patterns on top of the `regex` crate.
+//!
+//! A Vim pattern in 'magic' mode is translated to the syntax of the `regex`
+//! crate: "\(" becomes a group, "\+" a repeat, "(" a literal parenthesis and
+//! so on.  "\v" switches to very magic and "\V" to very nomagic, "\c" and
+//! "\C" override the case of the match.  The part between "\zs" and "\ze" is
+//! reported as the match; the text around it must match but is consumed too,
+//! which only matters for repeated matches in the same text.
+//!
+//! "~" matches the previous substitute string, set with [`set_prev_sub`],
+//! like Vim's reg_prev_sub.
+
+use std::cell::RefCell;
+
+use regex::{Regex, RegexBuilder};
+
+/// Name of the group that holds the "\zs" .. "\ze" part of a match.
+const MATCH_GROUP: &str = "vimmatch";
+
+thread_local! {
+    /// The replacement string of the last substitute command, for "~".
+    static PREV_SUB: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Remember `sub` as the previous substitute string, which "~" in a
+/// pattern matches.  To be called by the substitute command.
+pub fn set_prev_sub(sub: &str) {
+    PREV_SUB.with(|prev| *prev.borrow_mut() = Some(sub.to_string()));
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    VeryNomagic,
+    Nomagic,
+    Magic,
+    VeryMagic,
+}
+
+/// A compiled Vim pattern.
+#[derive(Debug, Clone)]
+pub struct VimRegex {
+    regex: Regex,
+    has_match_group: bool,
+}
+
+/// Where a pattern matched: byte offsets of the whole match and of the
+/// submatches "\1" to "\9".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VimMatch {
+    pub start: usize,
+    pub end: usize,
+    /// Where the text that must match ends, past "\ze".  The next match is
+    /// searched from here.
+    pub next: usize,
+    pub groups: [Option<(usize, usize)>; 9],
+}
+
+impl VimMatch {
+    /// The text of submatch `nr`, zero for the whole match.
+    pub fn group<'a>(&self, text: &'a str, nr: usize) -> &'a str {
+        match nr {
+            0 => &text[self.start..self.end],
+            _ => self.groups.get(nr - 1).copied().flatten().map_or("", |(s, e)| &text[s..e]),
+        }
+    }
+}
+
+struct Translator<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    out: String,
+    magic: Mode,
+    ic: Option<bool>,
+    groups: usize,
+    /// The output before "\zs" and after "\ze".
+    zs: Option<usize>,
+    ze: Option<usize>,
+}
+
+impl Translator<'_> {
+    fn literal(&mut self, c: char) {
+        let mut buf = [0; 4];
+        self.out.push_str(&regex::escape(c.encode_utf8(&mut buf)));
+    }
+
+    /// Translate item `c`, `escaped` when it was preceded by a backslash.
+    /// What is special depends on the magic mode.
+    fn item(&mut self, c: char, escaped: bool) -> Result<(), String> {
+        // Whether `c` has its special meaning: in magic mode "*" does
+        // without a backslash and "+" with one, etc.
+        let special = match c {
+            '^' | '$' => !escaped && self.magic != Mode::VeryNomagic || escaped && self.magic == Mode::VeryNomagic,
+            '.' | '[' | '~' | '*' => match self.magic {
+                Mode::VeryNomagic | Mode::Nomagic => escaped,
+                _ => !escaped,
+            },
+            '(' | ')' | '|' | '+' | '?' | '=' | '{' | '@' | '%' | '<' | '>' => match self.magic {
+                Mode::VeryMagic => !escaped,
+                _ => escaped,
+            },
+            _ => false,
+        };
+        if !special {
+            if escaped {
+                return self.escape_class(c);
+            }
+            self.literal(c);
+            return Ok(());
+        }
+        match c {
+            '^' => self.out.push('^'),
+            '$' => self.out.push('$'),
+            '.' => self.out.push_str("[^\\n]"),
+            '~' => match PREV_SUB.with(|prev| prev.borrow().clone()) {
+                Some(sub) => self.out.push_str(&format!("(?:{})", regex::escape(&sub))),
+                None => return Err("E33: No previous substitute regular expression".to_string()),
+            },
+            '*' => self.out.push('*'),
+            '+' => self.out.push('+'),
+            '?' | '=' => self.out.push('?'),
+            '|' => self.out.push('|'),
+            '<' => self.out.push_str("\\b{start}"),
+            '>' => self.out.push_str("\\b{end}"),
+            '(' => {
+                self.groups += 1;
+                self.out.push_str(&format!("(?P<g{}>", self.groups));
+            }
+            ')' => self.out.push(')'),
+            '%' => {
+                // Only "\%(" is supported, a group that is not numbered.
+                if self.next_is('(', false) {
+                    self.out.push_str("(?:");
+                } else {
+                    return Err("E71: Invalid character after \\%".to_string());
+                }
+            }
+            '{' => self.braces()?,
+            '[' => self.collection(),
+            _ => return Err(format!("E869: Unknown operator '\\{}'", c)),
+        }
+        Ok(())
+    }
+
+    /// Consume `c` if it is next, optionally preceded by a backslash when not
+    /// in very magic mode.
+    fn next_is(&mut self, c: char, escaped: bool) -> bool {
+        if escaped && self.magic != Mode::VeryMagic {
+            let mut probe = self.chars.clone();
+            if probe.next() == Some('\\') && probe.next() == Some(c) {
+                self.chars = probe;
+                return true;
+            }
+            return false;
+        }
+        if self.chars.peek() == Some(&c) {
+            self.chars.next();
+            return true;
+        }
+        false
+    }
+
+    /// "\{n,m}", "\{-}" and friends, after the "{".
+    fn braces(&mut self) -> Result<(), String> {
+        let lazy = self.next_is('-', false);
+        let mut spec = String::new();
+        loop {
+            match self.chars.next() {
+                Some('\\') if self.chars.peek() == Some(&'}') => {}
+                Some('}') => break,
+                Some(c) if c.is_ascii_digit() || c == ',' => spec.push(c),
+                _ => return Err("E554: Syntax error in \\{...}".to_string()),
+            }
+        }
+        let quantifier = match spec.split_once(',') {
+            None if spec.is_empty() => "*".to_string(),
+            None => format!("{{{}}}", spec),
+            Some(("", "")) => "*".to_string(),
+            Some(("", max)) => format!("{{0,{}}}", max),
+            Some((min, max)) => format!("{{{},{}}}", min, max),
+        };
+        self.out.push_str(&quantifier);
+        if lazy {
+            self.out.push('?');
+        }
+        Ok(())
+    }
+
+    /// A "[abc]" collection, after the "[".  Character classes such as
+    /// "[:alpha:]" are the same in both syntaxes.
+    fn collection(&mut self) {
+        let mut probe = self.chars.clone();
+        let mut body = String::new();
+        if probe.peek() == Some(&'^') {
+            body.push('^');
+            probe.next();
+        }
+        if probe.peek() == Some(&']') {
+            body.push_str("\\]");
+            probe.next();
+        }
+        loop {
+            match probe.next() {
+                // Without a closing "]" the "[" is a literal.
+                None => {
+                    self.out.push_str("\\[");
+                    return;
+                }
+                Some(']') => break,
+                Some('[') if probe.peek() == Some(&':') => {
+                    body.push('[');
+                    for c in probe.by_ref() {
+                        body.push(c);
+                        if c == ']' {
+                            break;
+                        }
+                    }
+                }
+                Some('\\') => match probe.next() {
+                    Some('e') => body.push_str("\\x1b"),
+                    Some('t') => body.push_str("\\t"),
+                    Some('n') => body.push_str("\\n"),
+                    Some('\\') => body.push_str("\\\\"),
+                    Some(']') => body.push_str("\\]"),
+                    Some('-') => body.push_str("\\-"),
+                    Some('^') => body.push_str("\\^"),
+                    Some('r') => body.push_str("\\r"),
+                    // "\d123", "\o40", "\x20", "\u20AC": a character by number.
+                    Some(base @ ('d' | 'o' | 'x' | 'u' | 'U')) => {
+                        let (radix, max) = match base {
+                            'd' => (10, 3),
+                            'o' => (8, 3),
+                            'x' => (16, 2),
+                            'u' => (16, 4),
+                            _ => (16, 8),
+                        };
+                        let mut digits = String::new();
+                        while digits.len() < max && probe.peek().is_some_and(|c| c.is_digit(radix)) {
+                            digits.extend(probe.next());
+                        }
+                        match u32::from_str_radix(&digits, radix).ok().and_then(char::from_u32) {
+                            Some(c) => body.push_str(&format!("\\x{{{:x}}}", c as u32)),
+                            None => {
+                                body.push_str("\\\\");
+                                body.push(base);
+                                body.push_str(&digits);
+                            }
+                        }
+                    }
+                    Some(c) => {
+                        body.push_str("\\\\");
+                        body.push(c);
+                    }
+                    None => body.push_str("\\\\"),
+                },
+                Some(c @ ('[' | '&' | '~')) => {
+                    body.push('\\');
+                    body.push(c);
+                }
+                Some(c) => body.push(c),
+            }
+        }
+        self.chars = probe;
+        self.out.push('[');
+        self.out.push_str(&body);
+        self.out.push(']');
+    }
+
+    /// A backslash item that is not a magic character: a character class
+    /// such as "\s", or an escaped literal.
+    fn escape_class(&mut self, c: char) -> Result<(), String> {
+        let class = match c {
+            's' => "[ \\t]",
+            'S' => "[^ \\t]",
+            'd' => "[0-9]",
+            'D' => "[^0-9]",
+            'w' => "[0-9A-Za-z_]",
+            'W' => "[^0-9A-Za-z_]",
+            'a' => "[A-Za-z]",
+            'A' => "[^A-Za-z]",
+            'l' => "[a-z]",
+            'L' => "[^a-z]",
+            'u' => "[A-Z]",
+            'U' => "[^A-Z]",
+            'x' => "[0-9A-Fa-f]",
+            'X' => "[^0-9A-Fa-f]",
+            'o' => "[0-7]",
+            'O' => "[^0-7]",
+            'h' => "[A-Za-z_]",
+            'H' => "[^A-Za-z_]",
+            'k' | 'i' => "[0-9A-Za-z_\\x{80}-\\x{10FFFF}]",
+            'f' | 'p' => "[^ \\t\\n]",
+            'n' => "\\n",
+            't' => "\\t",
+            'e' => "\\x1b",
+            'r' => "\\r",
+            c @ '1'..='9' => {
+                return Err(format!("E65: Illegal back reference: \\{}", c));
+            }
+            c if c.is_ascii_alphanumeric() => return Err(format!("E867: Unknown operator: \\{}", c)),
+            c => {
+                self.literal(c);
+                return Ok(());
+            }
+        };
+        self.out.push_str(class);
+        Ok(())
+    }
+
+    fn translate(&mut self) -> Result<(), String> {
+        while let Some(c) = self.chars.next() {
+            if c != '\\' {
+                self.item(c, false)?;
+                continue;
+            }
+            let Some(next) = self.chars.next() else {
+                return Err("E10: \\ should be followed by /, ? or &".to_string());
+            };
+            match next {
+                'v' => self.magic = Mode::VeryMagic,
+                'm' => self.magic = Mode::Magic,
+                'M' => self.magic = Mode::Nomagic,
+                'V' => self.magic = Mode::VeryNomagic,
+                'c' => self.ic = Some(true),
+                'C' => self.ic = Some(false),
+                'z' => match self.chars.next() {
+                    Some('s') => self.zs = Some(self.out.len()),
+                    Some('e') => self.ze = Some(self.out.len()),
+                    _ => return Err("E68: Invalid character after \\z".to_string()),
+                },
+                _ => self.item(next, true)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl VimRegex {
+    /// Compile `pat`; `ic` ignores case unless the pattern has "\c" or "\C".
+    pub fn new(pat: &str, ic: bool) -> Result<Self, String> {
+        let mut tr = Translator {
+            chars: pat.chars().peekable(),
+            out: String::new(),
+            magic: Mode::Magic,
+            ic: None,
+            groups: 0,
+            zs: None,
+            ze: None,
+        };
+        tr.translate()?;
+        let mut out = tr.out;
+        let has_match_group = tr.zs.is_some() || tr.ze.is_some();
+        if has_match_group {
+            let start = tr.zs.unwrap_or(0);
+            let end = tr.ze.unwrap_or(out.len()).max(start);
+            out = format!("{}(?P<{}>{}){}", &out[..start], MATCH_GROUP, &out[start..end], &out[end..]);
+        }
+        let regex = RegexBuilder::new(&out)
+            .case_insensitive(tr.ic.unwrap_or(ic))
+            .build()
+            .map_err(|_| format!("E486: Pattern not found: {}", pat))?;
+        Ok(VimRegex { regex, has_match_group })
+    }
+
+    /// The first match at or after byte `start`.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<VimMatch> {
+        let caps = self.regex.captures_at(text, start)?;
+        let whole = caps.get(0)?;
+        let (mstart, mend) = match caps.name(MATCH_GROUP).filter(|_| self.has_match_group) {
+            Some(m) => (m.start(), m.end()),
+            None => (whole.start(), whole.end()),
+        };
+        let mut groups = [None; 9];
+        for (i, group) in groups.iter_mut().enumerate() {
+            *group = caps.name(&format!("g{}", i + 1)).map(|m| (m.start(), m.end()));
+        }
+        Some(VimMatch { start: mstart, end: mend, next: whole.end(), groups })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matched(pat: &str, text: &str) -> Option<String> {
+        let re = VimRegex::new(pat, false).unwrap();
+        re.find_at(text, 0).map(|m| m.group(text, 0).to_string())
+    }
+
+    #[test]
+    fn magic_items() {
+        assert_eq!(matched("a\\+", "baaa"), Some("aaa".to_string()));
+        assert_eq!(matched("a+", "aa+"), Some("a+".to_string()));
+        assert_eq!(matched("^\\s*\\d\\{2}", "  123"), Some("  12".to_string()));
+        assert_eq!(matched("x\\{-1,}", "xxx"), Some("x".to_string()));
+        assert_eq!(matched("\\<is\\>", "this is"), Some("is".to_string()));
+        assert_eq!(matched("\\>.\\+\\<", "ab, cd"), Some(", ".to_string()));
+        assert_eq!(matched("a\\<", "a b"), None);
+        assert_eq!(matched("a\\|b", "cb"), Some("b".to_string()));
+        assert_eq!(matched("[a-c]\\+", "xabcd"), Some("abc".to_string()));
+        assert_eq!(matched("[\\x01- ]\\+", "a \t b"), Some(" \t ".to_string()));
+        assert_eq!(matched("\\v(ab)+", "ababx"), Some("abab".to_string()));
+        assert_eq!(matched("\\V.*", "a.*b"), Some(".*".to_string()));
+        assert_eq!(matched("\\cABC", "xabc"), Some("abc".to_string()));
+        assert_eq!(matched("foo\\zsbar", "foobar"), Some("bar".to_string()));
+        assert_eq!(matched("foo\\zebar", "foo foobar"), Some("foo".to_string()));
+        assert!(VimRegex::new("\\z", false).is_err());
+    }
+
+    #[test]
+    fn prev_sub() {
+        let err = VimRegex::new("a~c", false).err();
+        assert_eq!(err.as_deref(), Some("E33: No previous substitute regular expression"));
+        set_prev_sub("b.");
+        assert_eq!(matched("a~c", "ab.c abxc"), Some("ab.c".to_string()));
+        assert_eq!(matched("a\\~c", "a~c"), Some("a~c".to_string()));
+        assert_eq!(matched("\\Va\\~c", "ab.c"), Some("ab.c".to_string()));
+    }
+
+    #[test]
+    fn submatches() {
+        let text = "key = value";
+        let re = VimRegex::new("\\(\\w\\+\\) = \\%(x\\)\\=\\(\\w*\\)", false).unwrap();
+        let m = re.find_at(text, 0).unwrap();
+        assert_eq!(m.group(text, 1), "key");
+        assert_eq!(m.group(text, 2), "value");
+        assert_eq!(m.group(text, 3), "");
+    }
+}
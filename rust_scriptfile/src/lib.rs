@@ -1,4 +1,7 @@
+use std::fs;
+use std::io;
 use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
 use rust_fileio::readfile;
 
 /// Attempt to read a Vim script file at `path`.
@@ -15,6 +18,61 @@ pub extern "C" fn read_scriptfile_rs(path: *const c_char) -> bool {
     result == 0
 }
 
+/// Read the script at `path` into a string.  A leading UTF-8 BOM is dropped
+/// and CR-LF line endings are accepted, like when sourcing a file.
+pub fn read_script(path: &Path) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&bytes);
+    let text = String::from_utf8_lossy(bytes);
+    Ok(text.replace("\r\n", "\n"))
+}
+
+/// Split a comma separated option value such as 'runtimepath'.  A backslash
+/// escapes a comma that is part of a directory name.
+pub fn split_option_dirs(value: &str) -> Vec<String> {
+    let mut dirs = Vec::new();
+    let mut cur = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    cur.push(next);
+                }
+            }
+            ',' => {
+                if !cur.is_empty() {
+                    dirs.push(std::mem::take(&mut cur));
+                }
+            }
+            _ => cur.push(c),
+        }
+    }
+    if !cur.is_empty() {
+        dirs.push(cur);
+    }
+    dirs
+}
+
+/// Find `name`, a path relative to a runtime directory such as
+/// "autoload/pkg/mod.vim", in the directories of `runtimepath`.  The first
+/// match is returned.
+pub fn find_runtime_file(runtimepath: &str, name: &str) -> Option<PathBuf> {
+    split_option_dirs(runtimepath)
+        .into_iter()
+        .map(|dir| expand_home(&dir).join(name))
+        .find(|p| p.is_file())
+}
+
+fn expand_home(dir: &str) -> PathBuf {
+    match dir.strip_prefix("~/") {
+        Some(rest) => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(rest))
+            .unwrap_or_else(|| PathBuf::from(dir)),
+        None => PathBuf::from(dir),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -32,4 +90,23 @@ mod tests {
         let c_path = CString::new(file_path.to_str().unwrap()).unwrap();
         assert!(read_scriptfile_rs(c_path.as_ptr()));
     }
+
+    #[test]
+    fn finds_file_in_runtimepath() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let autoload = second.path().join("autoload").join("pkg");
+        std::fs::create_dir_all(&autoload).unwrap();
+        std::fs::write(autoload.join("mod.vim"), "\u{feff}vim9script\r\n").unwrap();
+        let rtp = format!("{},{}", first.path().display(), second.path().display());
+        let found = find_runtime_file(&rtp, "autoload/pkg/mod.vim").unwrap();
+        assert_eq!(found, autoload.join("mod.vim"));
+        assert_eq!(read_script(&found).unwrap(), "vim9script\n");
+        assert!(find_runtime_file(&rtp, "autoload/other.vim").is_none());
+    }
+
+    #[test]
+    fn splits_escaped_commas() {
+        assert_eq!(split_option_dirs("a,b\\,c,,d"), vec!["a", "b,c", "d"]);
+    }
 }
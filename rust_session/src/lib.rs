@@ -1,12 +1,37 @@
+//! Session and view files: `:mksession`, `:mkview` and `:loadview`, and the
+//! version of viminfo files.
+
+mod mksession;
+mod model;
+mod options;
+mod restore;
+
+pub use mksession::{mksession, mkview};
+pub use model::{Fold, Frame, Session, SessionBuffer, TabPage, Value, Window};
+pub use options::{SessionOptions, SESSIONOPTIONS_DEFAULT, VIEWOPTIONS_DEFAULT};
+
 use std::ffi::{CStr, CString};
 use std::io::{self, Write};
 use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
 
 /// Errors that can occur when handling session or viminfo files.
 #[derive(Debug)]
 pub enum SessionError {
     Io(io::Error),
     Parse,
+    /// A command in a session or view file that cannot be executed.
+    Command(String),
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::Io(err) => write!(f, "{}", err),
+            SessionError::Parse => f.write_str("E484: Can't open file"),
+            SessionError::Command(msg) => f.write_str(msg),
+        }
+    }
 }
 
 impl From<io::Error> for SessionError {
@@ -18,13 +43,14 @@ impl From<io::Error> for SessionError {
 /// Current version of the session file format.
 pub const SESSION_VERSION: u32 = 1;
 
-/// Escape spaces and backslashes in a file name so that it can be safely
-/// stored in a session file.  This mimics Vim's fnameescape().
+/// Escape the characters that are special in a file name argument, like
+/// spaces and backslashes, so that it can be safely stored in a session
+/// file.  This mimics Vim's fnameescape().
 pub fn escape_filename(name: &str) -> String {
     let mut escaped = String::with_capacity(name.len());
     for ch in name.chars() {
         match ch {
-            ' ' | '\\' => {
+            ' ' | '\t' | '\\' | '*' | '?' | '[' | '{' | '`' | '$' | '%' | '#' | '\'' | '"' | '|' | '!' | '<' => {
                 escaped.push('\\');
                 escaped.push(ch);
             }
@@ -87,6 +113,44 @@ pub fn parse_viminfo_version(contents: &str) -> Result<u32, SessionError> {
     Err(SessionError::Parse)
 }
 
+/// Write the script of a session or view to `path`.  An existing file is
+/// only overwritten when `force` is set, like with `:mksession!`.
+pub fn write_script(path: &Path, script: &str, force: bool) -> Result<(), String> {
+    if !force && path.exists() {
+        return Err(format!("E189: \"{}\" exists (add ! to override)", path.display()));
+    }
+    std::fs::write(path, script).map_err(|_| format!("E190: Cannot open \"{}\" for writing", path.display()))
+}
+
+/// The file `:mkview {nr}` writes for the file `fname` in 'viewdir': the
+/// name with "=" doubled and path separators replaced with "=+", then "="
+/// and the number.  Without a number the name ends in "=".
+pub fn view_file_name(viewdir: &Path, fname: &str, nr: Option<char>) -> PathBuf {
+    let mut name = String::with_capacity(fname.len() + 6);
+    for c in fname.chars() {
+        match c {
+            '=' => name.push_str("=="),
+            '/' | '\\' => name.push_str("=+"),
+            c => name.push(c),
+        }
+    }
+    name.push('=');
+    if let Some(nr) = nr {
+        name.push(nr);
+        name.push_str(".vim");
+    }
+    viewdir.join(name)
+}
+
+/// Write the view script for `:mkview {nr}` of `fname` in `viewdir`,
+/// creating the directory when needed.
+pub fn write_view(viewdir: &Path, fname: &str, nr: Option<char>, script: &str) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(viewdir).map_err(|_| format!("E739: Cannot create directory: {}", viewdir.display()))?;
+    let path = view_file_name(viewdir, fname, nr);
+    write_script(&path, script, true)?;
+    Ok(path)
+}
+
 // Internal helpers ------------------------------------------------------------
 
 fn c_ptr_to_str<'a>(ptr: *const c_char) -> Result<&'a str, c_int> {
@@ -0,0 +1,347 @@
+//! Writing a session or view as a Vim script that, when sourced, restores
+//! it: `:mksession` and `:mkview`.
+//!
+//! The script is what Vim writes, it can be sourced by Vim too.  The window
+//! layout is rebuilt by splitting windows with 'splitbelow' and 'splitright'
+//! set: for each frame the windows for its children are created, then each
+//! child is made in its window, going to the next one with `CTRL-W w`.
+
+use crate::escape_filename;
+use crate::model::{Fold, Frame, Session, Value, Window};
+use crate::options::SessionOptions;
+
+/// The escaped file name `name` for a command.
+fn fname(name: &str, opts: &SessionOptions) -> String {
+    if opts.slash {
+        escape_filename(&name.replace('\\', "/"))
+    } else {
+        escape_filename(name)
+    }
+}
+
+/// `s` in a double quoted string.
+fn quoted(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' | '"' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `s` as the value in a `:set` command.
+fn escape_option(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, ' ' | '\t' | '\\' | '"' | '|') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// The `:set` or `:setlocal` command for an option.
+fn option_line(cmd: &str, name: &str, value: &Value) -> String {
+    match value {
+        Value::Bool(true) => format!("{} {}", cmd, name),
+        Value::Bool(false) => format!("{} no{}", cmd, name),
+        Value::Number(n) => format!("{} {}={}", cmd, name, n),
+        Value::Str(s) => format!("{} {}={}", cmd, name, escape_option(s)),
+    }
+}
+
+fn value_expr(value: &Value) -> String {
+    match value {
+        Value::Bool(b) => if *b { "v:true" } else { "v:false" }.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::Str(s) => quoted(s),
+    }
+}
+
+/// Whether window `win` is stored.
+fn included(win: &Window, opts: &SessionOptions) -> bool {
+    (opts.blank || !win.buffer.is_empty() || win.help) && (opts.help || !win.help)
+}
+
+/// Create the windows for `frame`, starting in its first window.
+fn put_frame(out: &mut Vec<String>, frame: &Frame, opts: &SessionOptions) {
+    let (frames, vertical) = match frame {
+        Frame::Leaf(_) => return,
+        Frame::Row(frames) => (frames, true),
+        Frame::Col(frames) => (frames, false),
+    };
+    let frames: Vec<&Frame> = frames.iter().filter(|f| f.windows().iter().any(|w| included(w, opts))).collect();
+    for _ in 1..frames.len() {
+        out.push(if vertical { "vsplit" } else { "split" }.to_string());
+    }
+    if frames.len() > 1 {
+        // Back to the first window.
+        out.push(format!("{}wincmd {}", frames.len(), if vertical { 'h' } else { 'k' }));
+    }
+    for (i, frame) in frames.iter().enumerate() {
+        put_frame(out, frame, opts);
+        if i + 1 < frames.len() {
+            out.push("wincmd w".to_string());
+        }
+    }
+}
+
+/// The commands that create `folds`, nested ones first.
+fn put_folds(out: &mut Vec<String>, folds: &[Fold]) {
+    for fold in folds {
+        put_folds(out, &fold.nested);
+        out.push(format!("{},{}fold", fold.start, fold.end));
+    }
+}
+
+/// The commands that open the folds that are open, folds are created
+/// closed.
+fn put_fold_open(out: &mut Vec<String>, folds: &[Fold]) {
+    fn any_open(folds: &[Fold]) -> bool {
+        folds.iter().any(|f| f.open || any_open(&f.nested))
+    }
+    for fold in folds {
+        if fold.open || any_open(&fold.nested) {
+            out.push(format!("{}normal! zo", fold.start));
+            put_fold_open(out, &fold.nested);
+            if !fold.open {
+                out.push(format!("{}normal! zc", fold.start));
+            }
+        }
+    }
+}
+
+/// The commands that restore window `win`: the buffer when `session` is
+/// set, local options, folds, cursor position and directory.
+fn put_view(out: &mut Vec<String>, win: &Window, opts: &SessionOptions, session: bool) {
+    match &win.arglist {
+        Some(args) => {
+            out.push("arglocal".to_string());
+            out.push("%argdel".to_string());
+            for arg in args {
+                out.push(format!("$argadd {}", fname(arg, opts)));
+            }
+        }
+        None if session => out.push("argglobal".to_string()),
+        None => {}
+    }
+    if session {
+        if win.help {
+            let tail = win.buffer.rsplit(['/', '\\']).next().unwrap_or_default();
+            out.push("enew | setl bt=help".to_string());
+            out.push(format!("help {}", fname(tail, opts)));
+        } else if win.buffer.is_empty() {
+            out.push("enew".to_string());
+        } else {
+            let name = fname(&win.buffer, opts);
+            out.push(format!(
+                "if bufexists(fnamemodify({}, \":p\")) | buffer {} | else | edit {} | endif",
+                quoted(&win.buffer),
+                name,
+                name
+            ));
+        }
+        if let Some(alt) = &win.alt {
+            out.push(format!("balt {}", fname(alt, opts)));
+        }
+    }
+    if opts.local_options() {
+        for (name, value) in &win.options {
+            out.push(option_line("setlocal", name, value));
+        }
+    }
+    if opts.folds {
+        out.push("silent! normal! zE".to_string());
+        put_folds(out, &win.folds);
+        out.push("let &fdl = &fdl".to_string());
+        put_fold_open(out, &win.folds);
+    }
+    if session || opts.cursor {
+        // The cursor is put at the same line in the window, also when its
+        // height changed.
+        let height = win.height.max(1);
+        out.push(format!(
+            "let s:l = {} - (({} * winheight(0) + {}) / {})",
+            win.lnum,
+            win.lnum.saturating_sub(win.topline),
+            height / 2,
+            height
+        ));
+        out.push("if s:l < 1 | let s:l = 1 | endif".to_string());
+        out.push("keepjumps exe s:l".to_string());
+        out.push("normal! zt".to_string());
+        out.push(format!("keepjumps {}", win.lnum));
+        out.push(if win.col == 0 { "normal! 0".to_string() } else { format!("normal! 0{}|", win.col + 1) });
+    }
+    if let (true, Some(cwd)) = (opts.curdir, &win.cwd) {
+        out.push(format!("lcd {}", fname(cwd, opts)));
+    }
+}
+
+const SO_SAVE: &str = "let s:so_save = &g:so | let s:siso_save = &g:siso | setg so=0 siso=0 | setl so=-1 siso=-1";
+const SO_RESTORE: &str = "let &g:so = s:so_save | let &g:siso = s:siso_save";
+
+fn finish(out: Vec<String>) -> String {
+    let mut text = out.join("\n");
+    text.push('\n');
+    text
+}
+
+/// The script for `:mksession`: restores `session` as `opts`,
+/// 'sessionoptions', says.
+pub fn mksession(session: &Session, opts: &SessionOptions) -> String {
+    let mut out = vec!["let SessionLoad = 1".to_string()];
+    if opts.options {
+        out.push("if &cp | set nocp | endif".to_string());
+        for (name, value) in &session.options {
+            out.push(option_line("set", name, value));
+        }
+    }
+    if opts.globals {
+        for (name, value) in &session.globals {
+            out.push(format!("let {} = {}", name, value_expr(value)));
+        }
+    }
+    out.push(SO_SAVE.to_string());
+    out.push("let v:this_session=expand(\"<sfile>:p\")".to_string());
+    out.push("silent only".to_string());
+    out.push("silent tabonly".to_string());
+    if opts.sesdir {
+        out.push("exe \"cd \" . escape(expand(\"<sfile>:p:h\"), ' ')".to_string());
+    } else if let (true, Some(cwd)) = (opts.curdir, &session.cwd) {
+        out.push(format!("cd {}", fname(cwd, opts)));
+    }
+    out.push("if expand('%') == '' && !&modified && line('$') <= 1 && getline(1) == ''".to_string());
+    out.push("  let s:wipebuf = bufnr('%')".to_string());
+    out.push("endif".to_string());
+    out.push("let s:shortmess_save = &shortmess".to_string());
+    out.push("set shortmess+=aoO".to_string());
+
+    let tabpages: Vec<(usize, &crate::model::TabPage)> = if opts.tabpages {
+        session.tabpages.iter().enumerate().collect()
+    } else {
+        session.tabpages.iter().enumerate().skip(session.cur_tab).take(1).collect()
+    };
+    if opts.buffers {
+        for buf in session.buffers.iter().filter(|b| !b.name.is_empty()) {
+            out.push(format!("badd +{} {}", buf.lnum, fname(&buf.name, opts)));
+        }
+    } else {
+        // Only the buffers in the windows.
+        let mut names: Vec<&str> = Vec::new();
+        for (_, tab) in &tabpages {
+            for win in tab.layout.windows().into_iter().filter(|w| included(w, opts) && !w.help) {
+                if !win.buffer.is_empty() && !names.contains(&win.buffer.as_str()) {
+                    names.push(&win.buffer);
+                    out.push(format!("badd +{} {}", win.lnum, fname(&win.buffer, opts)));
+                }
+            }
+        }
+    }
+    out.push("argglobal".to_string());
+    out.push("%argdel".to_string());
+    for arg in &session.arglist {
+        out.push(format!("$argadd {}", fname(arg, opts)));
+    }
+    if opts.resize {
+        out.push(format!("set lines={} columns={}", session.lines, session.columns));
+    }
+    if opts.winsize {
+        out.push("let s:save_winminheight = &winminheight".to_string());
+        out.push("let s:save_winminwidth = &winminwidth".to_string());
+    }
+
+    let mut cur_tab = 0;
+    for (i, (nr, tab)) in tabpages.iter().enumerate() {
+        if *nr == session.cur_tab {
+            cur_tab = i;
+        }
+        if i > 0 {
+            out.push("tabnew +setlocal\\ bufhidden=wipe".to_string());
+        }
+        let windows: Vec<(usize, &Window)> =
+            tab.layout.windows().into_iter().enumerate().filter(|(_, w)| included(w, opts)).collect();
+        if windows.len() > 1 {
+            out.push("let s:save_splitbelow = &splitbelow".to_string());
+            out.push("let s:save_splitright = &splitright".to_string());
+            out.push("set splitbelow splitright".to_string());
+            out.push("wincmd _ | wincmd |".to_string());
+            put_frame(&mut out, &tab.layout, opts);
+            out.push("let &splitbelow = s:save_splitbelow".to_string());
+            out.push("let &splitright = s:save_splitright".to_string());
+        }
+        out.push("wincmd t".to_string());
+        if opts.winsize && windows.len() > 1 {
+            out.push("set winminheight=0".to_string());
+            out.push("set winheight=1".to_string());
+            out.push("set winminwidth=0".to_string());
+            out.push("set winwidth=1".to_string());
+            let (lines, columns) = (session.lines.max(1), session.columns.max(1));
+            for (n, (_, win)) in windows.iter().enumerate() {
+                out.push(format!("exe '{}resize ' . ((&lines * {} + {}) / {})", n + 1, win.height, lines / 2, lines));
+                out.push(format!(
+                    "exe 'vert {}resize ' . ((&columns * {} + {}) / {})",
+                    n + 1,
+                    win.width,
+                    columns / 2,
+                    columns
+                ));
+            }
+        }
+        if let (true, Some(cwd)) = (opts.curdir, &tab.cwd) {
+            out.push(format!("tcd {}", fname(cwd, opts)));
+        }
+        for (n, (_, win)) in windows.iter().enumerate() {
+            put_view(&mut out, win, opts, true);
+            if n + 1 < windows.len() {
+                out.push("wincmd w".to_string());
+            }
+        }
+        if windows.len() > 1 {
+            let cur = windows.iter().position(|(nr, _)| *nr >= tab.cur_win).unwrap_or(0);
+            out.push(format!("{}wincmd w", cur + 1));
+        }
+    }
+    out.push(format!("tabnext {}", cur_tab + 1));
+    out.push(format!(
+        "if exists('s:wipebuf') && len(win_findbuf(s:wipebuf)) == 0 && {}",
+        "getbufvar(s:wipebuf, '&buftype') isnot# 'terminal'"
+    ));
+    out.push("  silent exe 'bwipe ' . s:wipebuf".to_string());
+    out.push("endif".to_string());
+    out.push("unlet! s:wipebuf".to_string());
+    out.push("set winheight=1 winwidth=20".to_string());
+    out.push("let &shortmess = s:shortmess_save".to_string());
+    if opts.winsize {
+        out.push("let &winminheight = s:save_winminheight".to_string());
+        out.push("let &winminwidth = s:save_winminwidth".to_string());
+    }
+    out.push("let s:sx = expand(\"<sfile>:p:r\").\"x.vim\"".to_string());
+    out.push("if filereadable(s:sx)".to_string());
+    out.push("  exe \"source \" . fnameescape(s:sx)".to_string());
+    out.push("endif".to_string());
+    out.push(SO_RESTORE.to_string());
+    out.push("doautoall SessionLoadPost".to_string());
+    out.push("unlet SessionLoad".to_string());
+    out.push("\" vim: set ft=vim :".to_string());
+    finish(out)
+}
+
+/// The script for `:mkview`: restores window `win` as `opts`,
+/// 'viewoptions', says.
+pub fn mkview(win: &Window, opts: &SessionOptions) -> String {
+    let mut out = vec![SO_SAVE.to_string()];
+    put_view(&mut out, win, opts, false);
+    out.push(SO_RESTORE.to_string());
+    out.push("\" vim: set ft=vim :".to_string());
+    finish(out)
+}
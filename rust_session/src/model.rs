@@ -0,0 +1,328 @@
+//! What a session file restores: tab pages with their window layout, the
+//! buffers in the windows with cursor position and folds, the buffer list,
+//! the argument list, directories and options.
+
+/// The value of an option or a global variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    Number(i64),
+    Str(String),
+}
+
+/// A manual fold, lines `start` to `end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fold {
+    pub start: usize,
+    pub end: usize,
+    pub open: bool,
+    pub nested: Vec<Fold>,
+}
+
+impl Fold {
+    pub fn new(start: usize, end: usize) -> Fold {
+        Fold { start, end, open: false, nested: Vec::new() }
+    }
+
+    fn contains(&self, lnum: usize) -> bool {
+        (self.start..=self.end).contains(&lnum)
+    }
+}
+
+/// Add a closed fold for lines `start` to `end` to `folds`, inside a fold
+/// that contains it and around the folds it contains, like `:fold` does.
+pub fn add_fold(folds: &mut Vec<Fold>, start: usize, end: usize) {
+    let around = |f: &&mut Fold| f.start <= start && end <= f.end && (f.start, f.end) != (start, end);
+    if let Some(outer) = folds.iter_mut().find(around) {
+        add_fold(&mut outer.nested, start, end);
+        return;
+    }
+    let mut fold = Fold::new(start, end);
+    let (inner, rest): (Vec<Fold>, Vec<Fold>) =
+        std::mem::take(folds).into_iter().partition(|f| start <= f.start && f.end <= end);
+    fold.nested = inner;
+    *folds = rest;
+    let at = folds.iter().position(|f| f.start > start).unwrap_or(folds.len());
+    folds.insert(at, fold);
+}
+
+/// The folds containing `lnum`, outermost first, as indexes into the
+/// nested lists.
+fn fold_path(folds: &[Fold], lnum: usize) -> Vec<usize> {
+    let mut path = Vec::new();
+    let mut level = folds;
+    while let Some(i) = level.iter().position(|f| f.contains(lnum)) {
+        path.push(i);
+        level = &level[i].nested;
+    }
+    path
+}
+
+fn fold_at<'a>(folds: &'a mut [Fold], path: &[usize]) -> &'a mut Fold {
+    let (first, rest) = path.split_first().expect("a fold path is not empty");
+    rest.iter().fold(&mut folds[*first], |fold, &i| &mut fold.nested[i])
+}
+
+/// `zo` in line `lnum`: open the outermost closed fold.
+pub fn open_fold(folds: &mut [Fold], lnum: usize) {
+    let path = fold_path(folds, lnum);
+    for depth in 1..=path.len() {
+        let fold = fold_at(folds, &path[..depth]);
+        if !fold.open {
+            fold.open = true;
+            return;
+        }
+    }
+}
+
+/// `zc` in line `lnum`: close the innermost open fold.
+pub fn close_fold(folds: &mut [Fold], lnum: usize) {
+    let path = fold_path(folds, lnum);
+    for depth in (1..=path.len()).rev() {
+        let fold = fold_at(folds, &path[..depth]);
+        if fold.open {
+            fold.open = false;
+            return;
+        }
+    }
+}
+
+/// A window and the buffer it shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    /// The name of the buffer, empty when it has none.
+    pub buffer: String,
+    /// The buffer is a help file.
+    pub help: bool,
+    /// The alternate file, for CTRL-^.
+    pub alt: Option<String>,
+    /// The cursor line, starting at 1.
+    pub lnum: usize,
+    /// The cursor byte column, starting at 0.
+    pub col: usize,
+    /// The line at the top of the window.
+    pub topline: usize,
+    pub width: usize,
+    pub height: usize,
+    pub folds: Vec<Fold>,
+    /// The directory set with `:lcd`.
+    pub cwd: Option<String>,
+    /// Window and buffer local option values.
+    pub options: Vec<(String, Value)>,
+    /// The argument list set with `:arglocal`, None for the global one.
+    pub arglist: Option<Vec<String>>,
+}
+
+impl Window {
+    pub fn new(buffer: &str, width: usize, height: usize) -> Window {
+        Window {
+            buffer: buffer.to_string(),
+            help: false,
+            alt: None,
+            lnum: 1,
+            col: 0,
+            topline: 1,
+            width,
+            height,
+            folds: Vec::new(),
+            cwd: None,
+            options: Vec::new(),
+            arglist: None,
+        }
+    }
+}
+
+/// The layout of windows in a tab page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Leaf(Window),
+    /// Frames side by side, made with `:vsplit`.
+    Row(Vec<Frame>),
+    /// Frames above each other, made with `:split`.
+    Col(Vec<Frame>),
+}
+
+impl Frame {
+    /// The windows, in the order of `CTRL-W w`.
+    pub fn windows(&self) -> Vec<&Window> {
+        match self {
+            Frame::Leaf(win) => vec![win],
+            Frame::Row(frames) | Frame::Col(frames) => frames.iter().flat_map(Frame::windows).collect(),
+        }
+    }
+
+    pub fn windows_mut(&mut self) -> Vec<&mut Window> {
+        match self {
+            Frame::Leaf(win) => vec![win],
+            Frame::Row(frames) | Frame::Col(frames) => frames.iter_mut().flat_map(Frame::windows_mut).collect(),
+        }
+    }
+
+    pub fn window_count(&self) -> usize {
+        match self {
+            Frame::Leaf(_) => 1,
+            Frame::Row(frames) | Frame::Col(frames) => frames.iter().map(Frame::window_count).sum(),
+        }
+    }
+
+    /// Split window `nr` (from zero), `vertical` for side by side.  The new
+    /// window shows the same buffer and goes below or right of it when
+    /// `after` is set.  Returns the number of the new window.
+    pub fn split(&mut self, nr: usize, vertical: bool, after: bool) -> usize {
+        self.split_rec(nr, vertical, after);
+        if after {
+            nr + 1
+        } else {
+            nr
+        }
+    }
+
+    fn split_rec(&mut self, nr: usize, vertical: bool, after: bool) {
+        let (frames, same) = match self {
+            Frame::Leaf(win) => {
+                let mut old = win.clone();
+                let mut new = win.clone();
+                halve(&mut old, &mut new, vertical);
+                let (first, second) = if after { (old, new) } else { (new, old) };
+                let frames = vec![Frame::Leaf(first), Frame::Leaf(second)];
+                *self = if vertical { Frame::Row(frames) } else { Frame::Col(frames) };
+                return;
+            }
+            Frame::Row(frames) => (frames, vertical),
+            Frame::Col(frames) => (frames, !vertical),
+        };
+        let mut nr = nr;
+        for i in 0..frames.len() {
+            let count = frames[i].window_count();
+            if nr >= count {
+                nr -= count;
+                continue;
+            }
+            // A window split in the direction of its parent gets a sibling.
+            if let (true, Frame::Leaf(win)) = (same, &mut frames[i]) {
+                let mut new = win.clone();
+                halve(win, &mut new, vertical);
+                frames.insert(if after { i + 1 } else { i }, Frame::Leaf(new));
+            } else {
+                frames[i].split_rec(nr, vertical, after);
+            }
+            return;
+        }
+    }
+
+    /// The number of the window `count` steps up (`k`) or left (`h`) of
+    /// window `nr`, within the frame it is in.
+    pub fn neighbour(&self, nr: usize, vertical: bool, count: usize) -> usize {
+        let mut result = nr;
+        for _ in 0..count {
+            match self.previous(result, vertical) {
+                Some(prev) => result = prev,
+                None => break,
+            }
+        }
+        result
+    }
+
+    fn previous(&self, nr: usize, vertical: bool) -> Option<usize> {
+        // Moving left is within a row, moving up within a column.
+        let (frames, matching) = match self {
+            Frame::Leaf(_) => return None,
+            Frame::Row(frames) => (frames, vertical),
+            Frame::Col(frames) => (frames, !vertical),
+        };
+        let mut start = 0;
+        for (i, frame) in frames.iter().enumerate() {
+            let count = frame.window_count();
+            if nr < start + count {
+                if let Some(prev) = frame.previous(nr - start, vertical) {
+                    return Some(start + prev);
+                }
+                // The last window of the frame before this one.
+                return (matching && i > 0).then(|| start - 1);
+            }
+            start += count;
+        }
+        None
+    }
+}
+
+/// Divide the size of `old` between it and `new`, one line or column goes
+/// to the status line or separator.
+fn halve(old: &mut Window, new: &mut Window, vertical: bool) {
+    let size = if vertical { &mut old.width } else { &mut old.height };
+    let half = size.saturating_sub(1) / 2;
+    *size = size.saturating_sub(1 + half);
+    if vertical {
+        new.width = half;
+    } else {
+        new.height = half;
+    }
+}
+
+/// A tab page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TabPage {
+    pub layout: Frame,
+    /// The number of the current window, from zero.
+    pub cur_win: usize,
+    /// The directory set with `:tcd`.
+    pub cwd: Option<String>,
+}
+
+impl TabPage {
+    pub fn new(window: Window) -> TabPage {
+        TabPage { layout: Frame::Leaf(window), cur_win: 0, cwd: None }
+    }
+}
+
+/// An entry of the buffer list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionBuffer {
+    pub name: String,
+    /// The line the cursor was in last.
+    pub lnum: usize,
+}
+
+/// Everything a session file restores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub tabpages: Vec<TabPage>,
+    /// The number of the current tab page, from zero.
+    pub cur_tab: usize,
+    pub buffers: Vec<SessionBuffer>,
+    pub arglist: Vec<String>,
+    pub cwd: Option<String>,
+    /// Global option values.
+    pub options: Vec<(String, Value)>,
+    /// Global variables with an uppercase name.
+    pub globals: Vec<(String, Value)>,
+    /// The size of the screen.
+    pub columns: usize,
+    pub lines: usize,
+}
+
+impl Session {
+    /// A session with one empty window filling a screen of `columns` by
+    /// `lines`, less the command line and the status line.
+    pub fn new(columns: usize, lines: usize) -> Session {
+        Session {
+            tabpages: vec![TabPage::new(Window::new("", columns, lines.saturating_sub(2)))],
+            cur_tab: 0,
+            buffers: Vec::new(),
+            arglist: Vec::new(),
+            cwd: None,
+            options: Vec::new(),
+            globals: Vec::new(),
+            columns,
+            lines,
+        }
+    }
+}
+
+/// Set `name` to `value` in `options`.
+pub(crate) fn set_value(options: &mut Vec<(String, Value)>, name: &str, value: Value) {
+    match options.iter_mut().find(|(n, _)| n == name) {
+        Some((_, v)) => *v = value,
+        None => options.push((name.to_string(), value)),
+    }
+}
@@ -0,0 +1,99 @@
+//! 'sessionoptions' and 'viewoptions': what `:mksession` and `:mkview`
+//! store.
+
+/// The default of 'sessionoptions'.
+pub const SESSIONOPTIONS_DEFAULT: &str = "blank,buffers,curdir,folds,help,options,tabpages,winsize,terminal";
+
+/// The default of 'viewoptions'.
+pub const VIEWOPTIONS_DEFAULT: &str = "folds,cursor,curdir";
+
+/// The parsed value of 'sessionoptions' or 'viewoptions'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SessionOptions {
+    /// Windows editing a buffer without a name.
+    pub blank: bool,
+    /// Hidden and unloaded buffers, not only those in windows.
+    pub buffers: bool,
+    /// The current directory.
+    pub curdir: bool,
+    /// The cursor position, for views.
+    pub cursor: bool,
+    /// Manually created folds and their open or closed state.
+    pub folds: bool,
+    /// Global variables with an uppercase name.
+    pub globals: bool,
+    /// The help window.
+    pub help: bool,
+    /// Options and mappings local to a window or buffer.
+    pub localoptions: bool,
+    /// All options and mappings.
+    pub options: bool,
+    /// The size of the Vim window: 'lines' and 'columns'.
+    pub resize: bool,
+    /// The directory of the session file is made the current directory.
+    pub sesdir: bool,
+    /// Backslashes in file names are replaced with forward slashes.
+    pub slash: bool,
+    /// All tab pages, not only the current one.
+    pub tabpages: bool,
+    /// Windows with a terminal.  Not supported, accepted for the default.
+    pub terminal: bool,
+    /// Unix line endings, they are always used.
+    pub unix: bool,
+    /// The position of the GUI window.
+    pub winpos: bool,
+    /// The sizes of the windows.
+    pub winsize: bool,
+}
+
+impl SessionOptions {
+    /// Parse the value of 'sessionoptions', `name` is the option for the
+    /// error message.
+    pub fn parse(value: &str, name: &str) -> Result<SessionOptions, String> {
+        let mut opts = SessionOptions::default();
+        for item in value.split(',').filter(|item| !item.is_empty()) {
+            let flag = match item {
+                "blank" => &mut opts.blank,
+                "buffers" => &mut opts.buffers,
+                "curdir" => &mut opts.curdir,
+                "cursor" => &mut opts.cursor,
+                "folds" => &mut opts.folds,
+                "globals" => &mut opts.globals,
+                "help" => &mut opts.help,
+                "localoptions" => &mut opts.localoptions,
+                "options" => &mut opts.options,
+                "resize" => &mut opts.resize,
+                "sesdir" => &mut opts.sesdir,
+                // Vim stores the runtimepath, there is none.
+                "skiprtp" => continue,
+                "slash" => &mut opts.slash,
+                "tabpages" => &mut opts.tabpages,
+                "terminal" => &mut opts.terminal,
+                "unix" => &mut opts.unix,
+                "winpos" => &mut opts.winpos,
+                "winsize" => &mut opts.winsize,
+                _ => return Err(format!("E474: Invalid argument: {}={}", name, value)),
+            };
+            *flag = true;
+        }
+        if opts.curdir && opts.sesdir {
+            return Err(format!("E474: Invalid argument: {}={}", name, value));
+        }
+        Ok(opts)
+    }
+
+    /// The default 'sessionoptions'.
+    pub fn session() -> SessionOptions {
+        SessionOptions::parse(SESSIONOPTIONS_DEFAULT, "sessionoptions").expect("the default is valid")
+    }
+
+    /// The default 'viewoptions'.
+    pub fn view() -> SessionOptions {
+        SessionOptions::parse(VIEWOPTIONS_DEFAULT, "viewoptions").expect("the default is valid")
+    }
+
+    /// Window and buffer local options are stored.
+    pub fn local_options(&self) -> bool {
+        self.options || self.localoptions
+    }
+}
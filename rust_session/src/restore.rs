@@ -0,0 +1,408 @@
+//! Restoring a session or view from the script `:mksession` or `:mkview`
+//! wrote, by executing its commands on a [`Session`].
+//!
+//! Only the commands these scripts contain are understood, the ones that
+//! only matter to Vim, like saving and restoring 'scrolloff', are skipped.
+
+use crate::model::{add_fold, close_fold, open_fold, set_value, Session, SessionBuffer, TabPage, Value, Window};
+use crate::{unescape_filename, SessionError};
+
+/// Lines starting with one of these do nothing here.
+const IGNORED: &[&str] = &[
+    "let SessionLoad",
+    "let v:this_session",
+    "let s:",
+    "let &",
+    "if &cp",
+    "if expand(",
+    "if exists(",
+    "if filereadable(",
+    "if s:l < 1",
+    "endif",
+    "unlet",
+    "silent exe 'bwipe",
+    "exe \"source",
+    "doautoall",
+    "setg ",
+    "wincmd _",
+    "silent tabonly",
+    "nohlsearch",
+];
+
+struct Loader<'a> {
+    session: Session,
+    /// The current window in the current tab page.
+    win: usize,
+    splitbelow: bool,
+    splitright: bool,
+    /// `:silent only` was done: `:set` is no longer for global options.
+    started: bool,
+    /// `:arglocal` was used for the current window.
+    arglocal: bool,
+    /// The line computed for the top of the window.
+    topline: usize,
+    sesdir: Option<&'a str>,
+}
+
+fn bad_command(line: &str) -> SessionError {
+    SessionError::Command(format!("E492: Not an editor command: {}", line))
+}
+
+/// The arguments of `:set`, with the backslashes removed.
+fn set_args(arg: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut cur = String::new();
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => cur.extend(chars.next()),
+            ' ' => {
+                if !cur.is_empty() {
+                    args.push(std::mem::take(&mut cur));
+                }
+            }
+            c => cur.push(c),
+        }
+    }
+    if !cur.is_empty() {
+        args.push(cur);
+    }
+    args
+}
+
+fn parse_option(arg: &str) -> (String, Value) {
+    match arg.split_once('=') {
+        Some((name, value)) => match value.parse() {
+            Ok(n) => (name.to_string(), Value::Number(n)),
+            Err(_) => (name.to_string(), Value::Str(value.to_string())),
+        },
+        None => match arg.strip_prefix("no") {
+            Some(name) => (name.to_string(), Value::Bool(false)),
+            None => (arg.to_string(), Value::Bool(true)),
+        },
+    }
+}
+
+/// The value of a `:let` expression: a number or a double quoted string.
+fn parse_value(expr: &str) -> Option<Value> {
+    match expr {
+        "v:true" => return Some(Value::Bool(true)),
+        "v:false" => return Some(Value::Bool(false)),
+        _ => {}
+    }
+    if let Ok(n) = expr.parse() {
+        return Some(Value::Number(n));
+    }
+    let inner = expr.strip_prefix('"')?.strip_suffix('"')?;
+    let mut s = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => s.push('\n'),
+            c => s.push(c),
+        }
+    }
+    Some(Value::Str(s))
+}
+
+/// `((&lines * 10 + 12) / 24)`: the size for `size`, the number of lines
+/// or columns now.
+fn scaled(expr: &str, var: &str, size: usize) -> Option<usize> {
+    let rest = expr.trim().strip_prefix("((")?.strip_prefix(var)?.trim_start().strip_prefix('*')?;
+    let (n, rest) = rest.split_once('+')?;
+    let (half, rest) = rest.split_once(')')?;
+    let total = rest.trim().strip_prefix('/')?.trim().strip_suffix(')')?;
+    let number = |s: &str| s.trim().parse::<usize>().ok();
+    Some((size * number(n)? + number(half)?) / number(total)?.max(1))
+}
+
+impl<'a> Loader<'a> {
+    fn tab(&mut self) -> &mut TabPage {
+        let cur = self.session.cur_tab;
+        &mut self.session.tabpages[cur]
+    }
+
+    fn window(&mut self) -> &mut Window {
+        let win = self.win;
+        self.tab().layout.windows_mut().into_iter().nth(win).expect("the current window exists")
+    }
+
+    fn window_count(&mut self) -> usize {
+        self.tab().layout.window_count()
+    }
+
+    /// The argument list `:%argdel` and `:$argadd` change.
+    fn arglist(&mut self) -> &mut Vec<String> {
+        if self.arglocal {
+            self.window().arglist.get_or_insert_with(Vec::new)
+        } else {
+            &mut self.session.arglist
+        }
+    }
+
+    fn edit(&mut self, name: &str) {
+        let lnum = self.session.buffers.iter().find(|b| b.name == name).map_or(1, |b| b.lnum);
+        let win = self.window();
+        win.buffer = name.to_string();
+        win.help = false;
+        win.lnum = lnum;
+        win.col = 0;
+        win.topline = lnum;
+        win.folds.clear();
+    }
+
+    fn execute(&mut self, line: &str) -> Result<(), SessionError> {
+        // A line number or range before the command.
+        let cmd_start = line.find(|c: char| !c.is_ascii_digit() && c != ',').unwrap_or(line.len());
+        let (range, cmd) = line.split_at(cmd_start);
+        let (name, arg) = match cmd.split_once(' ') {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (cmd, ""),
+        };
+        let count: Option<usize> = range.parse().ok();
+        match name {
+            "set" if !self.started => {
+                for arg in set_args(arg) {
+                    let (name, value) = parse_option(&arg);
+                    set_value(&mut self.session.options, &name, value);
+                }
+            }
+            "set" => {
+                for arg in set_args(arg) {
+                    match parse_option(&arg) {
+                        (name, Value::Number(n)) if name == "lines" => self.session.lines = n as usize,
+                        (name, Value::Number(n)) if name == "columns" => self.session.columns = n as usize,
+                        (name, Value::Bool(b)) if name == "splitbelow" => self.splitbelow = b,
+                        (name, Value::Bool(b)) if name == "splitright" => self.splitright = b,
+                        _ => {}
+                    }
+                }
+            }
+            "setlocal" => {
+                for arg in set_args(arg) {
+                    let (name, value) = parse_option(&arg);
+                    set_value(&mut self.window().options, &name, value);
+                }
+            }
+            "let" => {
+                let (var, expr) = arg.split_once('=').ok_or_else(|| bad_command(line))?;
+                let var = var.trim();
+                if !var.starts_with(|c: char| c.is_ascii_uppercase()) {
+                    return Err(bad_command(line));
+                }
+                let value = parse_value(expr.trim()).ok_or_else(|| bad_command(line))?;
+                set_value(&mut self.session.globals, var, value);
+            }
+            "silent" if arg == "only" => self.started = true,
+            "cd" => self.session.cwd = Some(unescape_filename(arg)),
+            "exe" if arg.starts_with("\"cd \"") => self.session.cwd = self.sesdir.map(str::to_string),
+            "lcd" => self.window().cwd = Some(unescape_filename(arg)),
+            "tcd" => self.tab().cwd = Some(unescape_filename(arg)),
+            "badd" => {
+                let (lnum, name) = match arg.strip_prefix('+').and_then(|a| a.split_once(' ')) {
+                    Some((lnum, name)) => (lnum.parse().unwrap_or(1), name),
+                    None => (1, arg),
+                };
+                let name = unescape_filename(name);
+                if !self.session.buffers.iter().any(|b| b.name == name) {
+                    self.session.buffers.push(SessionBuffer { name, lnum });
+                }
+            }
+            "argglobal" => {
+                self.arglocal = false;
+                self.window().arglist = None;
+            }
+            "arglocal" => {
+                self.arglocal = true;
+                let args = self.session.arglist.clone();
+                self.window().arglist = Some(args);
+            }
+            "%argdel" => self.arglist().clear(),
+            "$argadd" => {
+                let name = unescape_filename(arg);
+                self.arglist().push(name);
+            }
+            "if" if arg.starts_with("bufexists(") => {
+                let name = arg.split_once("| edit ").and_then(|(_, rest)| rest.strip_suffix(" | endif"));
+                let name = name.ok_or_else(|| bad_command(line))?;
+                self.edit(&unescape_filename(name));
+            }
+            "edit" | "e" | "buffer" | "b" => self.edit(&unescape_filename(arg)),
+            "enew" => {
+                self.edit("");
+                self.window().help = arg == "| setl bt=help";
+            }
+            "help" => {
+                self.edit(&unescape_filename(arg));
+                self.window().help = true;
+            }
+            "balt" => self.window().alt = Some(unescape_filename(arg)),
+            "tabnew" => {
+                self.tab().cur_win = self.win;
+                let window = Window::new("", self.session.columns, self.session.lines.saturating_sub(2));
+                self.session.tabpages.push(TabPage::new(window));
+                self.session.cur_tab = self.session.tabpages.len() - 1;
+                self.win = 0;
+            }
+            "tabnext" => {
+                let nr = arg.parse::<usize>().ok().filter(|nr| (1..=self.session.tabpages.len()).contains(nr));
+                let nr = nr.ok_or_else(|| bad_command(line))?;
+                self.tab().cur_win = self.win;
+                self.session.cur_tab = nr - 1;
+                self.win = self.tab().cur_win;
+            }
+            "split" | "sp" | "vsplit" | "vs" => {
+                let vertical = name.starts_with('v');
+                let after = if vertical { self.splitright } else { self.splitbelow };
+                let win = self.win;
+                self.win = self.tab().layout.split(win, vertical, after);
+            }
+            "wincmd" => {
+                let n = self.window_count();
+                match arg {
+                    "w" => self.win = count.map_or((self.win + 1) % n, |c| c.clamp(1, n) - 1),
+                    "t" => self.win = 0,
+                    "k" | "h" => {
+                        let win = self.win;
+                        self.win = self.tab().layout.neighbour(win, arg == "h", count.unwrap_or(1));
+                    }
+                    _ => return Err(bad_command(line)),
+                }
+            }
+            "exe" if arg.starts_with("'vert ") || arg.contains("resize ' .") => {
+                let vertical = arg.starts_with("'vert ");
+                let rest = arg.trim_start_matches("'vert ").trim_start_matches('\'');
+                let (nr, expr) = rest.split_once("resize ' .").ok_or_else(|| bad_command(line))?;
+                let nr: usize = nr.parse().map_err(|_| bad_command(line))?;
+                let size = if vertical {
+                    scaled(expr, "&columns", self.session.columns)
+                } else {
+                    scaled(expr, "&lines", self.session.lines)
+                };
+                let size = size.ok_or_else(|| bad_command(line))?;
+                let win = self.tab().layout.windows_mut().into_iter().nth(nr.wrapping_sub(1));
+                let win = win.ok_or_else(|| bad_command(line))?;
+                if vertical {
+                    win.width = size;
+                } else {
+                    win.height = size;
+                }
+            }
+            "silent!" if arg == "normal! zE" => self.window().folds.clear(),
+            "fold" | "fo" => {
+                let (start, end) = range.split_once(',').unwrap_or((range, range));
+                let (Ok(start), Ok(end)) = (start.parse(), end.parse()) else {
+                    return Err(bad_command(line));
+                };
+                add_fold(&mut self.window().folds, start, end);
+            }
+            "normal!" => match (arg, count) {
+                ("zo", Some(lnum)) => open_fold(&mut self.window().folds, lnum),
+                ("zc", Some(lnum)) => close_fold(&mut self.window().folds, lnum),
+                ("zt", None) => {
+                    let win = self.window();
+                    win.topline = win.lnum;
+                }
+                ("0", None) => self.window().col = 0,
+                (arg, None) if arg.starts_with('0') && arg.ends_with('|') => {
+                    let col: usize = arg[1..arg.len() - 1].parse().map_err(|_| bad_command(line))?;
+                    self.window().col = col.saturating_sub(1);
+                }
+                _ => return Err(bad_command(line)),
+            },
+            "keepjumps" if arg == "exe s:l" => {
+                let topline = self.topline;
+                self.window().lnum = topline;
+            }
+            "keepjumps" => self.window().lnum = arg.parse().map_err(|_| bad_command(line))?,
+            _ => return Err(bad_command(line)),
+        }
+        Ok(())
+    }
+
+    /// `let s:l = 12 - ((3 * winheight(0) + 10) / 20)`: the line at the top
+    /// of the window with the cursor in the same screen line.
+    fn topline(&mut self, expr: &str) -> Option<()> {
+        let (lnum, rest) = expr.split_once(" - ((")?;
+        let (off, rest) = rest.split_once(" * winheight(0) + ")?;
+        let (half, rest) = rest.split_once(") / ")?;
+        let height = rest.strip_suffix(')')?;
+        let (lnum, off, half, height): (usize, usize, usize, usize) =
+            (lnum.parse().ok()?, off.parse().ok()?, half.parse().ok()?, height.parse().ok()?);
+        let rows = (off * self.window().height + half) / height.max(1);
+        self.topline = lnum.saturating_sub(rows).max(1);
+        Some(())
+    }
+
+    fn run(&mut self, script: &str) -> Result<(), SessionError> {
+        for line in script.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('"') {
+                continue;
+            }
+            if let Some(expr) = line.strip_prefix("let s:l = ") {
+                self.topline(expr).ok_or_else(|| bad_command(line))?;
+                continue;
+            }
+            if line.starts_with("if bufexists(") {
+                self.execute(line)?;
+                continue;
+            }
+            if IGNORED.iter().any(|prefix| line.starts_with(prefix)) {
+                continue;
+            }
+            self.execute(line)?;
+        }
+        self.tab().cur_win = self.win;
+        Ok(())
+    }
+}
+
+impl Session {
+    /// Restore a session from `script`, written by `:mksession`, on a
+    /// screen of `columns` by `lines`.  `sesdir` is the directory of the
+    /// session file.
+    pub fn restore(script: &str, columns: usize, lines: usize, sesdir: Option<&str>) -> Result<Session, SessionError> {
+        let mut loader = Loader {
+            session: Session::new(columns, lines),
+            win: 0,
+            splitbelow: false,
+            splitright: false,
+            started: false,
+            arglocal: false,
+            topline: 1,
+            sesdir,
+        };
+        loader.run(script)?;
+        Ok(loader.session)
+    }
+}
+
+impl Window {
+    /// Restore the view in `script`, written by `:mkview`, in this window:
+    /// `:loadview`.
+    pub fn load_view(&mut self, script: &str) -> Result<(), SessionError> {
+        let mut session = Session::new(self.width, self.height);
+        session.tabpages = vec![TabPage::new(self.clone())];
+        let mut loader = Loader {
+            session,
+            win: 0,
+            splitbelow: false,
+            splitright: false,
+            started: true,
+            arglocal: false,
+            topline: self.topline,
+            sesdir: None,
+        };
+        loader.run(script)?;
+        if loader.window_count() != 1 || loader.session.tabpages.len() != 1 {
+            return Err(SessionError::Parse);
+        }
+        *self = loader.window().clone();
+        Ok(())
+    }
+}
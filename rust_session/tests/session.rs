@@ -0,0 +1,159 @@
+use rust_session::{
+    mksession, mkview, view_file_name, write_script, Fold, Frame, Session, SessionBuffer, SessionOptions, TabPage,
+    Value, Window,
+};
+
+fn window(name: &str, lnum: usize, width: usize, height: usize) -> Window {
+    let mut win = Window::new(name, width, height);
+    win.lnum = lnum;
+    win.topline = lnum.saturating_sub(3).max(1);
+    win
+}
+
+/// Two tab pages: the first with a window on the left and two above each
+/// other on the right, the second with a single window.
+fn sample() -> Session {
+    let mut session = Session::new(80, 24);
+    let mut left = window("src/main.rs", 40, 40, 22);
+    left.col = 7;
+    left.folds = vec![
+        Fold { start: 10, end: 20, open: true, nested: vec![Fold::new(12, 14)] },
+        Fold::new(30, 35),
+    ];
+    left.options = vec![("number".into(), Value::Bool(true)), ("tabstop".into(), Value::Number(4))];
+    left.alt = Some("README.md".into());
+    let mut top = window("my notes.txt", 5, 39, 10);
+    top.cwd = Some("/tmp/notes".into());
+    top.arglist = Some(vec!["a.txt".into()]);
+    let bottom = window("README.md", 1, 39, 11);
+    session.tabpages = vec![
+        TabPage {
+            layout: Frame::Row(vec![
+                Frame::Leaf(left),
+                Frame::Col(vec![Frame::Leaf(top), Frame::Leaf(bottom)]),
+            ]),
+            cur_win: 2,
+            cwd: None,
+        },
+        TabPage { layout: Frame::Leaf(window("Cargo.toml", 3, 80, 22)), cur_win: 0, cwd: Some("/src".into()) },
+    ];
+    session.buffers = ["src/main.rs", "my notes.txt", "README.md", "Cargo.toml", "hidden.txt"]
+        .iter()
+        .map(|name| SessionBuffer { name: name.to_string(), lnum: 1 })
+        .collect();
+    session.arglist = vec!["src/main.rs".into(), "README.md".into()];
+    session.cwd = Some("/home/user/project".into());
+    session.options = vec![
+        ("hidden".into(), Value::Bool(true)),
+        ("shiftwidth".into(), Value::Number(4)),
+        ("path".into(), Value::Str(".,/usr/include, src".into())),
+    ];
+    session
+}
+
+#[test]
+fn restore_multi_window_layout() {
+    let session = sample();
+    let script = mksession(&session, &SessionOptions::session());
+    assert!(script.contains("\nbadd +1 my\\ notes.txt\n"), "{}", script);
+    assert!(script.contains("\nset path=.,/usr/include,\\ src\n"));
+    assert!(script.contains("\nvsplit\n2wincmd h\nwincmd w\nsplit\n2wincmd k\n"));
+    assert!(script.contains("\n10,20fold\n"));
+    assert!(script.ends_with("unlet SessionLoad\n\" vim: set ft=vim :\n"));
+
+    let restored = Session::restore(&script, 80, 24, None).unwrap();
+    assert_eq!(restored, session);
+}
+
+#[test]
+fn restore_on_other_screen_size() {
+    let session = sample();
+    let script = mksession(&session, &SessionOptions::session());
+    let restored = Session::restore(&script, 160, 48, None).unwrap();
+    let windows = restored.tabpages[0].layout.windows();
+    let sizes: Vec<(usize, usize)> = windows.iter().map(|w| (w.width, w.height)).collect();
+    assert_eq!(sizes, [(80, 44), (78, 20), (78, 22)]);
+    // The cursor stays in the same screen line, with a larger window too.
+    let left = restored.tabpages[0].layout.windows()[0];
+    assert_eq!((left.lnum, left.topline), (40, 34));
+}
+
+#[test]
+fn sessionoptions() {
+    let session = sample();
+    let opts = SessionOptions::parse("sesdir,winsize", "sessionoptions").unwrap();
+    let script = mksession(&session, &opts);
+    assert!(!script.contains("tabnew") && !script.contains("set hidden") && !script.contains("fold"));
+    assert!(!script.contains("hidden.txt"));
+    let restored = Session::restore(&script, 80, 24, Some("/sessions")).unwrap();
+    assert_eq!(restored.cwd.as_deref(), Some("/sessions"));
+    assert_eq!(restored.tabpages.len(), 1);
+    assert!(restored.options.is_empty());
+    assert!(restored.tabpages[0].layout.windows().iter().all(|w| w.cwd.is_none() && w.options.is_empty()));
+    let names: Vec<&str> = restored.buffers.iter().map(|b| b.name.as_str()).collect();
+    assert_eq!(names, ["src/main.rs", "my notes.txt", "README.md"]);
+
+    let mut session = Session::new(80, 24);
+    session.tabpages[0].layout = Frame::Col(vec![
+        Frame::Leaf(window("", 1, 80, 11)),
+        Frame::Leaf(window("a.txt", 1, 80, 10)),
+    ]);
+    session.tabpages[0].cur_win = 1;
+    let script = mksession(&session, &SessionOptions::parse("curdir", "sessionoptions").unwrap());
+    let restored = Session::restore(&script, 80, 24, None).unwrap();
+    assert_eq!(restored.tabpages[0].layout.windows().len(), 1);
+
+    assert_eq!(
+        SessionOptions::parse("curdir,sesdir", "sessionoptions"),
+        Err("E474: Invalid argument: sessionoptions=curdir,sesdir".to_string())
+    );
+    assert!(SessionOptions::parse("folds,bogus", "viewoptions").is_err());
+}
+
+#[test]
+fn bad_command() {
+    let err = Session::restore("let SessionLoad = 1\nfrobnicate\n", 80, 24, None).unwrap_err();
+    assert_eq!(err.to_string(), "E492: Not an editor command: frobnicate");
+}
+
+#[test]
+fn mkview_and_loadview() {
+    let mut win = window("src/lib.rs", 50, 80, 20);
+    win.col = 3;
+    let nested = Fold { start: 2, end: 4, open: true, nested: vec![] };
+    win.folds = vec![Fold { start: 1, end: 9, open: false, nested: vec![nested] }];
+    win.options = vec![("wrap".into(), Value::Bool(false))];
+    win.cwd = Some("/src".into());
+    let script = mkview(&win, &SessionOptions::view());
+    assert!(!script.contains("nowrap") && !script.contains("edit"));
+
+    let mut other = Window::new("src/lib.rs", 80, 20);
+    other.load_view(&script).unwrap();
+    assert_eq!((other.lnum, other.col, other.topline), (50, 3, 47));
+    assert_eq!(other.folds, win.folds);
+    assert_eq!(other.cwd.as_deref(), Some("/src"));
+
+    let script = mkview(&win, &SessionOptions::parse("options", "viewoptions").unwrap());
+    let mut other = Window::new("src/lib.rs", 80, 20);
+    other.load_view(&script).unwrap();
+    assert_eq!((other.lnum, other.options.clone()), (1, win.options.clone()));
+}
+
+#[test]
+fn view_and_session_files() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(
+        view_file_name(dir.path(), "~/a=b/c.txt", Some('1')),
+        dir.path().join("~=+a==b=+c.txt=1.vim")
+    );
+    assert_eq!(view_file_name(dir.path(), "/x", None), dir.path().join("=+x="));
+
+    let path = dir.path().join("Session.vim");
+    write_script(&path, "one", false).unwrap();
+    assert_eq!(
+        write_script(&path, "two", false),
+        Err(format!("E189: \"{}\" exists (add ! to override)", path.display()))
+    );
+    write_script(&path, "two", true).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "two");
+}
@@ -3,6 +3,10 @@
 use libc::{c_char, c_int, strftime, time, time_t, tm};
 use std::ptr;
 
+pub mod timer;
+
+pub use timer::{Timer, TimerQueue};
+
 /// Return the current time in seconds.
 /// When Vim is built with testing support, a global `time_for_testing`
 /// value may be used instead of the system time.
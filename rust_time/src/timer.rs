@@ -0,0 +1,215 @@
+//! A queue of timers ordered by the time they are due, as used by
+//! timer_start().
+//!
+//! The queue only keeps the timing; what a timer does when it fires is up to
+//! the owner, who looks it up by the timer ID.  Times are in milliseconds
+//! since the queue was created.  With a virtual clock time only moves when
+//! [`TimerQueue::sleep`] or [`TimerQueue::advance`] is called, which makes
+//! tests deterministic.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+enum Clock {
+    Real(Instant),
+    /// The current time.
+    Virtual(u64),
+}
+
+/// A timer in the queue.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timer {
+    pub id: u64,
+    /// The interval.
+    pub time: u64,
+    /// How many more times the timer fires, -1 for forever.
+    pub repeat: i64,
+    pub paused: bool,
+    /// When the timer fires next.
+    pub due: u64,
+}
+
+#[derive(Debug)]
+pub struct TimerQueue {
+    timers: BTreeMap<u64, Timer>,
+    last_id: u64,
+    clock: Clock,
+}
+
+impl TimerQueue {
+    /// A queue that uses the system clock.
+    pub fn new() -> Self {
+        TimerQueue { timers: BTreeMap::new(), last_id: 0, clock: Clock::Real(Instant::now()) }
+    }
+
+    /// A queue with a virtual clock that starts at zero.
+    pub fn with_virtual_clock() -> Self {
+        TimerQueue { clock: Clock::Virtual(0), ..Self::new() }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        matches!(self.clock, Clock::Virtual(_))
+    }
+
+    /// Switch to a virtual clock, starting at the current time.  Timers
+    /// keep their due time.
+    pub fn use_virtual_clock(&mut self) {
+        self.clock = Clock::Virtual(self.now());
+    }
+
+    /// The current time in milliseconds.
+    pub fn now(&self) -> u64 {
+        match self.clock {
+            Clock::Real(start) => start.elapsed().as_millis() as u64,
+            Clock::Virtual(now) => now,
+        }
+    }
+
+    /// Move a virtual clock forward.  Does nothing for the system clock.
+    pub fn advance(&mut self, ms: u64) {
+        if let Clock::Virtual(now) = &mut self.clock {
+            *now += ms;
+        }
+    }
+
+    /// Wait `ms` milliseconds: sleep with the system clock, advance a
+    /// virtual clock.
+    pub fn sleep(&mut self, ms: u64) {
+        match self.clock {
+            Clock::Real(_) => std::thread::sleep(Duration::from_millis(ms)),
+            Clock::Virtual(_) => self.advance(ms),
+        }
+    }
+
+    /// Add a timer that fires after `time` ms, and then `repeat` more times
+    /// every `time` ms; -1 repeats forever.  Returns the timer ID, IDs start
+    /// at one.
+    pub fn start(&mut self, time: u64, repeat: i64) -> u64 {
+        self.last_id += 1;
+        let id = self.last_id;
+        let timer = Timer { id, time, repeat: repeat.max(-1), paused: false, due: self.now() + time };
+        self.timers.insert(id, timer);
+        id
+    }
+
+    /// Remove a timer.  Returns false when there is no timer `id`.
+    pub fn stop(&mut self, id: u64) -> bool {
+        self.timers.remove(&id).is_some()
+    }
+
+    pub fn stop_all(&mut self) {
+        self.timers.clear();
+    }
+
+    /// Pause or unpause a timer.  A paused timer does not fire, but its due
+    /// time keeps running: when unpaused after that time it fires soon.
+    pub fn pause(&mut self, id: u64, paused: bool) {
+        if let Some(timer) = self.timers.get_mut(&id) {
+            timer.paused = paused;
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Timer> {
+        self.timers.get(&id)
+    }
+
+    /// All timers, by ID.
+    pub fn timers(&self) -> impl Iterator<Item = &Timer> {
+        self.timers.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Milliseconds until `timer` is due, negative when it is overdue.
+    pub fn remaining(&self, timer: &Timer) -> i64 {
+        timer.due as i64 - self.now() as i64
+    }
+
+    /// Milliseconds until the next timer that is not paused is due, zero
+    /// when one is due already.  None when no timer can fire.
+    pub fn next_due(&self) -> Option<u64> {
+        let now = self.now();
+        self.timers.values().filter(|t| !t.paused).map(|t| t.due.saturating_sub(now)).min()
+    }
+
+    /// Take the timers that are due, in order of their due time.  A timer
+    /// that repeats is scheduled again, others are removed.  The owner
+    /// fires the returned IDs.
+    pub fn take_due(&mut self) -> Vec<u64> {
+        let now = self.now();
+        let mut due: Vec<(u64, u64)> = self
+            .timers
+            .values()
+            .filter(|t| !t.paused && t.due <= now)
+            .map(|t| (t.due, t.id))
+            .collect();
+        due.sort_unstable();
+        for &(_, id) in &due {
+            let timer = self.timers.get_mut(&id).expect("due timer");
+            match timer.repeat {
+                0 => {
+                    self.timers.remove(&id);
+                }
+                n => {
+                    if n > 0 {
+                        timer.repeat -= 1;
+                    }
+                    timer.due = now + timer.time;
+                }
+            }
+        }
+        due.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+impl Default for TimerQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fires_in_order_of_due_time() {
+        let mut queue = TimerQueue::with_virtual_clock();
+        let slow = queue.start(30, 0);
+        let fast = queue.start(10, 0);
+        assert_eq!(queue.next_due(), Some(10));
+        assert!(queue.take_due().is_empty());
+        queue.advance(40);
+        assert_eq!(queue.take_due(), [fast, slow]);
+        assert!(queue.is_empty());
+        assert_eq!(queue.next_due(), None);
+    }
+
+    #[test]
+    fn repeat_and_pause() {
+        let mut queue = TimerQueue::with_virtual_clock();
+        let id = queue.start(10, 2);
+        let mut fired = 0;
+        for _ in 0..5 {
+            queue.sleep(10);
+            fired += queue.take_due().len();
+        }
+        assert_eq!(fired, 3);
+        assert!(queue.get(id).is_none());
+
+        let id = queue.start(10, -1);
+        queue.pause(id, true);
+        queue.advance(20);
+        assert!(queue.take_due().is_empty());
+        assert_eq!(queue.next_due(), None);
+        assert_eq!(queue.remaining(queue.get(id).unwrap()), -10);
+        queue.pause(id, false);
+        assert_eq!(queue.take_due(), [id]);
+        assert_eq!(queue.get(id).unwrap().repeat, -1);
+        assert!(queue.stop(id));
+        assert!(!queue.stop(id));
+    }
+}
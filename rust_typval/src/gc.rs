@@ -0,0 +1,143 @@
+//! Cycle collection of values.
+//!
+//! Lists, dicts, tuples, objects and partials created with the constructors
+//! of [`Value`] are tracked by a heap per thread.  Reference counting frees
+//! them as usual, [`collect`] frees the ones that only a cycle keeps alive,
+//! like Vim's garbage_collect().
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rust_gc::GcHeap;
+pub use rust_gc::{Trace, Tracer, DEFAULT_THRESHOLD};
+
+use crate::value::{Dict, List, Object, Partial, Tuple, Value};
+
+thread_local! {
+    static HEAP: GcHeap = GcHeap::new();
+    static ROOTS: RefCell<Vec<fn(&mut Tracer)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Add a container that was not created by [`Value`], such as the scope of a
+/// closure.
+pub fn track<T: Trace>(obj: &Rc<T>) {
+    // Values may still be created while the thread exits.
+    let _ = HEAP.try_with(|heap| heap.track(obj));
+}
+
+/// Add a function that visits the values kept outside of variables, e.g. the
+/// callbacks of timers.  It is used by every collection on this thread.
+pub fn add_root(root: fn(&mut Tracer)) {
+    ROOTS.with(|roots| roots.borrow_mut().push(root));
+}
+
+/// Free the values that are only kept alive by cycles.  `roots` visits the
+/// variables; values held elsewhere are found without it, but are not freed
+/// when they are part of a cycle.  Returns the number of containers freed.
+pub fn collect(roots: impl FnOnce(&mut Tracer)) -> usize {
+    let extra = ROOTS.with(|r| r.borrow().clone());
+    HEAP.with(|heap| {
+        heap.collect(|tracer| {
+            roots(tracer);
+            for root in extra {
+                root(tracer);
+            }
+        })
+    })
+}
+
+/// Whether enough values were created since the last collection to make
+/// another one worthwhile.
+pub fn should_collect() -> bool {
+    HEAP.with(GcHeap::should_collect)
+}
+
+/// Set the number of created values after which [`should_collect`] returns
+/// true.
+pub fn set_threshold(threshold: usize) {
+    HEAP.with(|heap| heap.set_threshold(threshold));
+}
+
+/// Number of tracked values that are alive.
+pub fn tracked() -> usize {
+    HEAP.with(GcHeap::len)
+}
+
+impl Value {
+    /// Visit the containers this value refers to.
+    pub fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Value::List(l) => tracer.visit(l),
+            Value::Dict(d) => tracer.visit(d),
+            Value::Tuple(t) => tracer.visit(t),
+            Value::Object(o) => tracer.visit(o),
+            Value::Func(pt) => tracer.visit(pt),
+            _ => {}
+        }
+    }
+}
+
+impl Trace for List {
+    fn trace(&self, tracer: &mut Tracer) {
+        for item in self.borrow().iter() {
+            item.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        // Drop the items after the borrow ends.
+        let items = std::mem::take(&mut *self.borrow_mut());
+        drop(items);
+    }
+}
+
+impl Trace for Dict {
+    fn trace(&self, tracer: &mut Tracer) {
+        for item in self.borrow().values() {
+            item.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        let items = std::mem::take(&mut *self.borrow_mut());
+        drop(items);
+    }
+}
+
+// A tuple cannot be changed, a cycle through it also goes through a list or
+// dict, clearing that one breaks the cycle.
+impl Trace for Tuple {
+    fn trace(&self, tracer: &mut Tracer) {
+        for item in self.iter() {
+            item.trace(tracer);
+        }
+    }
+}
+
+impl Trace for Object {
+    fn trace(&self, tracer: &mut Tracer) {
+        for member in self.borrow().iter() {
+            member.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        let members = std::mem::take(&mut *self.borrow_mut());
+        drop(members);
+    }
+}
+
+// Likewise a partial: its dict or the scope of its function is cleared.
+impl Trace for Partial {
+    fn trace(&self, tracer: &mut Tracer) {
+        for arg in &self.args {
+            arg.trace(tracer);
+        }
+        if let Some(dict) = &self.dict {
+            tracer.visit(dict);
+        }
+        if let Some(func) = &self.func {
+            tracer.visit_dyn(func);
+        }
+    }
+}
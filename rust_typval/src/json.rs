@@ -0,0 +1,312 @@
+//! JSON encoding and decoding of values, as json_encode() and json_decode()
+//! do it.
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::value::{Special, Value};
+
+fn encode_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn encode_array(items: &[Value], out: &mut String, seen: &mut Vec<*const ()>) -> Result<(), String> {
+    out.push('[');
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        item.encode(out, seen)?;
+    }
+    out.push(']');
+    Ok(())
+}
+
+impl Value {
+    /// Encode the value as JSON.  A Funcref, Job, Channel, Object or Class
+    /// cannot be encoded; a Blob becomes an array of numbers and a Tuple an
+    /// array.
+    pub fn to_json(&self) -> Result<String, String> {
+        let mut out = String::new();
+        self.encode(&mut out, &mut Vec::new())?;
+        Ok(out)
+    }
+
+    fn encode(&self, out: &mut String, seen: &mut Vec<*const ()>) -> Result<(), String> {
+        let ptr = match self {
+            Value::List(l) => Rc::as_ptr(l) as *const (),
+            Value::Dict(d) => Rc::as_ptr(d) as *const (),
+            Value::Tuple(t) => Rc::as_ptr(t) as *const (),
+            _ => std::ptr::null(),
+        };
+        if !ptr.is_null() {
+            if seen.contains(&ptr) {
+                return Err("E724: Variable nested too deep for displaying".to_string());
+            }
+            seen.push(ptr);
+        }
+        match self {
+            Value::Number(n) => out.push_str(&n.to_string()),
+            Value::Float(f) if f.is_nan() => out.push_str("NaN"),
+            Value::Float(f) if f.is_infinite() => out.push_str(if *f > 0.0 { "Infinity" } else { "-Infinity" }),
+            Value::Float(f) => out.push_str(&crate::format_float(*f)),
+            Value::Str(s) => encode_string(s, out),
+            Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::Special(Special::None | Special::Null) => out.push_str("null"),
+            Value::List(l) => encode_array(&l.borrow(), out, seen)?,
+            Value::Tuple(t) => encode_array(t, out, seen)?,
+            Value::Blob(b) => {
+                let items: Vec<String> = b.borrow().iter().map(|b| b.to_string()).collect();
+                out.push_str(&format!("[{}]", items.join(",")));
+            }
+            Value::Dict(d) => {
+                out.push('{');
+                for (i, (key, val)) in d.borrow().iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    encode_string(key, out);
+                    out.push(':');
+                    val.encode(out, seen)?;
+                }
+                out.push('}');
+            }
+            Value::Object(_) => return Err("E1161: Cannot json encode a object".to_string()),
+            Value::Class(_) => return Err("E1161: Cannot json encode a class".to_string()),
+            Value::Func(_) | Value::Job(_) | Value::Channel(_) => return Err("E474: Invalid argument".to_string()),
+        }
+        if !ptr.is_null() {
+            seen.pop();
+        }
+        Ok(())
+    }
+}
+
+/// Parser for json_decode().  `pos` is a byte offset in `text`.
+struct Decoder<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn error(&self) -> String {
+        // For truncated input the whole text is shown, like Vim.
+        let at = if self.pos >= self.text.len() { self.text } else { &self.text[self.pos..] };
+        format!("E491: JSON decode error at '{}'", at)
+    }
+
+    fn skip_white(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn keyword(&mut self, word: &str, val: Value) -> Result<Value, String> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(val)
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_white();
+        match self.peek() {
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_white();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Value::new_list(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_white();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::new_list(items));
+                        }
+                        _ => return Err(self.error()),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut items = BTreeMap::new();
+                self.skip_white();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Value::new_dict(items));
+                }
+                loop {
+                    self.skip_white();
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error());
+                    }
+                    let key = self.string()?;
+                    self.skip_white();
+                    if self.peek() != Some(b':') {
+                        return Err(self.error());
+                    }
+                    self.pos += 1;
+                    let val = self.value()?;
+                    if items.insert(key.clone(), val).is_some() {
+                        return Err(format!("E938: Duplicate key in JSON: \"{}\"", key));
+                    }
+                    self.skip_white();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::new_dict(items));
+                        }
+                        _ => return Err(self.error()),
+                    }
+                }
+            }
+            Some(b'"') => self.string().map(Value::Str),
+            Some(b't') => self.keyword("true", Value::Bool(true)),
+            Some(b'f') => self.keyword("false", Value::Bool(false)),
+            Some(b'n') => self.keyword("null", Value::Special(Special::Null)),
+            Some(b'N') => self.keyword("NaN", Value::Float(f64::NAN)),
+            Some(b'I') => self.keyword("Infinity", Value::Float(f64::INFINITY)),
+            Some(b'-') if self.text[self.pos..].starts_with("-Infinity") => {
+                self.keyword("-Infinity", Value::Float(f64::NEG_INFINITY))
+            }
+            Some(c) if c == b'-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error()),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        let bytes = self.text.as_bytes();
+        let mut end = start;
+        if bytes.get(end) == Some(&b'-') {
+            end += 1;
+        }
+        let digits_start = end;
+        while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+            end += 1;
+        }
+        if end == digits_start {
+            return Err(self.error());
+        }
+        let mut is_float = false;
+        if bytes.get(end) == Some(&b'.') && bytes.get(end + 1).is_some_and(u8::is_ascii_digit) {
+            is_float = true;
+            end += 1;
+            while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+                end += 1;
+            }
+        }
+        if matches!(bytes.get(end), Some(b'e' | b'E')) {
+            let mut exp = end + 1;
+            if matches!(bytes.get(exp), Some(b'+' | b'-')) {
+                exp += 1;
+            }
+            if bytes.get(exp).is_some_and(u8::is_ascii_digit) {
+                is_float = true;
+                end = exp;
+                while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+                    end += 1;
+                }
+            }
+        }
+        let num = &self.text[start..end];
+        self.pos = end;
+        if is_float {
+            num.parse().map(Value::Float).map_err(|_| self.error())
+        } else {
+            // Like Vim a number that does not fit is clipped.
+            Ok(Value::Number(num.parse().unwrap_or(if num.starts_with('-') { i64::MIN } else { i64::MAX })))
+        }
+    }
+
+    /// A string, `pos` is at the opening quote.
+    fn string(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut s = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(s);
+                }
+                '\\' => {
+                    let Some((_, esc)) = chars.next() else { break };
+                    match esc {
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'u' => {
+                            let mut code = hex4(&mut chars).ok_or_else(|| self.error())?;
+                            // A surrogate pair is one character.
+                            if (0xd800..0xdc00).contains(&code) {
+                                let mut probe = chars.clone();
+                                if let (Some((_, '\\')), Some((_, 'u'))) = (probe.next(), probe.next()) {
+                                    if let Some(low @ 0xdc00..=0xdfff) = hex4(&mut probe) {
+                                        code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                                        chars = probe;
+                                    }
+                                }
+                            }
+                            s.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                        }
+                        c => s.push(c),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+        self.pos = start;
+        Err(self.error())
+    }
+}
+
+fn hex4(chars: &mut std::str::CharIndices) -> Option<u32> {
+    let mut code = 0;
+    for _ in 0..4 {
+        code = code * 16 + chars.next()?.1.to_digit(16)?;
+    }
+    Some(code)
+}
+
+impl Value {
+    /// Decode JSON `text`, like json_decode().  An object becomes a Dict and
+    /// null becomes v:null.
+    pub fn from_json(text: &str) -> Result<Value, String> {
+        let mut decoder = Decoder { text, pos: 0 };
+        let val = decoder.value()?;
+        decoder.skip_white();
+        if decoder.pos < text.len() {
+            return Err(format!("E474: Trailing characters: {}", &text[decoder.pos..]));
+        }
+        Ok(val)
+    }
+}
@@ -1,21 +1,84 @@
-use libc::{c_char, c_long, c_uchar};
+#![allow(clippy::missing_safety_doc)]
+//! Vim script values: the C `typval_T` and the safe [`Value`] it converts
+//! to and from without loss.
+//!
+//! Shared values (lists, dicts, blobs, tuples, partials, jobs, channels,
+//! objects and classes) are passed to C as a strong reference in the union
+//! pointer; [`tv_free`] gives the reference back.
+
+use libc::{c_char, c_uchar, c_void};
 use std::ffi::{CStr, CString};
+use std::rc::Rc;
+
+pub mod gc;
+mod json;
+mod string;
+mod value;
+
+pub use string::format_float;
+pub use value::{
+    Blob, BlobRef, Channel, Class, ClassRef, Dict, DictRef, Job, JobStatus, List, ListRef, Object, ObjectRef,
+    Partial, Special, Tuple, TupleRef, Value, VarLock,
+};
 
+#[allow(non_camel_case_types)]
+pub type varnumber_T = i64;
+
+/// The type of a typval, in the order of Vim's vartype_T.
 #[allow(non_camel_case_types)]
 #[repr(C)]
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum vartype_T {
     VAR_UNKNOWN = 0,
-    VAR_NUMBER = 1,
-    VAR_STRING = 2,
+    VAR_ANY,
+    VAR_VOID,
+    VAR_BOOL,
+    VAR_SPECIAL,
+    VAR_NUMBER,
+    VAR_FLOAT,
+    VAR_STRING,
+    VAR_BLOB,
+    VAR_FUNC,
+    VAR_PARTIAL,
+    VAR_LIST,
+    VAR_DICT,
+    VAR_JOB,
+    VAR_CHANNEL,
+    VAR_INSTR,
+    VAR_CLASS,
+    VAR_OBJECT,
+    VAR_TYPEALIAS,
+    VAR_TUPLE,
 }
 
+/// The v_number of a VAR_BOOL or VAR_SPECIAL typval.
+pub const VVAL_FALSE: varnumber_T = 0;
+pub const VVAL_TRUE: varnumber_T = 1;
+pub const VVAL_NONE: varnumber_T = 2;
+pub const VVAL_NULL: varnumber_T = 3;
+
+/// Values of v_lock.
+pub const VAR_UNLOCKED: c_char = 0;
+pub const VAR_LOCKED: c_char = 1;
+pub const VAR_FIXED: c_char = 2;
+
 #[repr(C)]
 pub union typval_vval {
-    pub v_number: c_long,
+    pub v_number: varnumber_T,
+    pub v_float: f64,
     pub v_string: *mut c_uchar,
+    pub v_list: *mut c_void,
+    pub v_dict: *mut c_void,
+    pub v_blob: *mut c_void,
+    pub v_tuple: *mut c_void,
+    pub v_partial: *mut c_void,
+    pub v_job: *mut c_void,
+    pub v_channel: *mut c_void,
+    pub v_object: *mut c_void,
+    pub v_class: *mut c_void,
 }
 
+#[allow(non_camel_case_types)]
 #[repr(C)]
 pub struct typval_T {
     pub v_type: vartype_T,
@@ -23,56 +86,151 @@ pub struct typval_T {
     pub vval: typval_vval,
 }
 
-/// Safe Rust representation of a Vim typval.
-#[derive(Clone, Debug, PartialEq)]
-pub enum TypVal {
-    Number(i64),
-    String(String),
+impl VarLock {
+    pub fn to_c(self) -> c_char {
+        match self {
+            VarLock::Unlocked => VAR_UNLOCKED,
+            VarLock::Locked => VAR_LOCKED,
+            VarLock::Fixed => VAR_FIXED,
+        }
+    }
+
+    pub fn from_c(lock: c_char) -> Self {
+        if lock & VAR_FIXED != 0 {
+            VarLock::Fixed
+        } else if lock & VAR_LOCKED != 0 {
+            VarLock::Locked
+        } else {
+            VarLock::Unlocked
+        }
+    }
 }
 
-impl From<&typval_T> for TypVal {
-    #[allow(clippy::unnecessary_cast)]
-    fn from(tv: &typval_T) -> Self {
-        unsafe {
-            match tv.v_type {
-                vartype_T::VAR_NUMBER => TypVal::Number(tv.vval.v_number as i64),
-                vartype_T::VAR_STRING => {
-                    let ptr = tv.vval.v_string;
-                    let s = if ptr.is_null() {
-                        String::new()
-                    } else {
-                        CStr::from_ptr(ptr as *const c_char)
-                            .to_string_lossy()
-                            .into_owned()
-                    };
-                    TypVal::String(s)
-                }
-                _ => TypVal::Number(0),
-            }
+fn c_string(s: &str) -> *mut c_uchar {
+    // A NUL cannot be in a C string, Vim stores it as a NL.
+    CString::new(s.replace('\0', "\n")).unwrap().into_raw() as *mut c_uchar
+}
+
+/// Store `val` in `out`, which must not hold a value that still needs to be
+/// freed.  The lock of the typval itself is not changed.
+pub unsafe fn to_typval(val: Value, out: *mut typval_T) {
+    let tv = &mut *out;
+    let (v_type, vval) = match val {
+        Value::Number(n) => (vartype_T::VAR_NUMBER, typval_vval { v_number: n }),
+        Value::Float(f) => (vartype_T::VAR_FLOAT, typval_vval { v_float: f }),
+        Value::Str(s) => (vartype_T::VAR_STRING, typval_vval { v_string: c_string(&s) }),
+        Value::Bool(b) => (vartype_T::VAR_BOOL, typval_vval { v_number: if b { VVAL_TRUE } else { VVAL_FALSE } }),
+        Value::Special(Special::None) => (vartype_T::VAR_SPECIAL, typval_vval { v_number: VVAL_NONE }),
+        Value::Special(Special::Null) => (vartype_T::VAR_SPECIAL, typval_vval { v_number: VVAL_NULL }),
+        Value::List(l) => (vartype_T::VAR_LIST, typval_vval { v_list: Rc::into_raw(l) as *mut c_void }),
+        Value::Dict(d) => (vartype_T::VAR_DICT, typval_vval { v_dict: Rc::into_raw(d) as *mut c_void }),
+        Value::Blob(b) => (vartype_T::VAR_BLOB, typval_vval { v_blob: Rc::into_raw(b) as *mut c_void }),
+        Value::Tuple(t) => (vartype_T::VAR_TUPLE, typval_vval { v_tuple: Rc::into_raw(t) as *mut c_void }),
+        Value::Func(pt) if pt.is_plain() && pt.func.is_none() => {
+            (vartype_T::VAR_FUNC, typval_vval { v_string: c_string(&pt.name) })
         }
+        Value::Func(pt) => (vartype_T::VAR_PARTIAL, typval_vval { v_partial: Rc::into_raw(pt) as *mut c_void }),
+        Value::Job(j) => (vartype_T::VAR_JOB, typval_vval { v_job: Rc::into_raw(j) as *mut c_void }),
+        Value::Channel(c) => (vartype_T::VAR_CHANNEL, typval_vval { v_channel: Rc::into_raw(c) as *mut c_void }),
+        Value::Object(o) => (vartype_T::VAR_OBJECT, typval_vval { v_object: Rc::into_raw(o) as *mut c_void }),
+        Value::Class(c) => (vartype_T::VAR_CLASS, typval_vval { v_class: Rc::into_raw(c) as *mut c_void }),
+    };
+    tv.v_type = v_type;
+    tv.vval = vval;
+}
+
+/// A new reference to the shared value `ptr` points to, or `empty` for a
+/// NULL pointer, which is what test_null_list() and friends return.
+unsafe fn clone_raw<T>(ptr: *mut c_void, empty: impl FnOnce() -> T) -> Rc<T> {
+    if ptr.is_null() {
+        return Rc::new(empty());
     }
+    let ptr = ptr as *const T;
+    Rc::increment_strong_count(ptr);
+    Rc::from_raw(ptr)
+}
+
+unsafe fn from_c_string(s: *const c_uchar) -> String {
+    if s.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(s as *const c_char).to_string_lossy().into_owned()
+    }
+}
+
+/// The value of typval `tv`.  Shared values are not copied, the result
+/// holds a new reference.  Returns None for a NULL pointer and for types
+/// without a value, such as VAR_UNKNOWN and VAR_VOID.
+pub unsafe fn from_typval(tv: *const typval_T) -> Option<Value> {
+    let tv = tv.as_ref()?;
+    let vval = &tv.vval;
+    Some(match tv.v_type {
+        vartype_T::VAR_NUMBER => Value::Number(vval.v_number),
+        vartype_T::VAR_FLOAT => Value::Float(vval.v_float),
+        vartype_T::VAR_STRING => Value::Str(from_c_string(vval.v_string)),
+        vartype_T::VAR_BOOL => Value::Bool(vval.v_number != VVAL_FALSE),
+        vartype_T::VAR_SPECIAL if vval.v_number == VVAL_NONE => Value::Special(Special::None),
+        vartype_T::VAR_SPECIAL => Value::Special(Special::Null),
+        vartype_T::VAR_LIST => Value::List(clone_raw(vval.v_list, List::default)),
+        vartype_T::VAR_DICT => Value::Dict(clone_raw(vval.v_dict, Dict::default)),
+        vartype_T::VAR_BLOB => Value::Blob(clone_raw(vval.v_blob, Blob::default)),
+        vartype_T::VAR_TUPLE => Value::Tuple(clone_raw(vval.v_tuple, Tuple::default)),
+        vartype_T::VAR_FUNC => Value::new_func(Partial::new(&from_c_string(vval.v_string))),
+        vartype_T::VAR_PARTIAL => Value::Func(clone_raw(vval.v_partial, Partial::default)),
+        vartype_T::VAR_JOB if vval.v_job.is_null() => return None,
+        vartype_T::VAR_JOB => Value::Job(clone_raw(vval.v_job, || unreachable!())),
+        vartype_T::VAR_CHANNEL if vval.v_channel.is_null() => return None,
+        vartype_T::VAR_CHANNEL => Value::Channel(clone_raw(vval.v_channel, || unreachable!())),
+        vartype_T::VAR_OBJECT if vval.v_object.is_null() => return None,
+        vartype_T::VAR_OBJECT => Value::Object(clone_raw(vval.v_object, || unreachable!())),
+        vartype_T::VAR_CLASS if vval.v_class.is_null() => return None,
+        vartype_T::VAR_CLASS => Value::Class(clone_raw(vval.v_class, || unreachable!())),
+        _ => return None,
+    })
 }
 
-impl From<TypVal> for typval_T {
-    fn from(val: TypVal) -> Self {
-        match val {
-            TypVal::Number(n) => typval_T {
-                v_type: vartype_T::VAR_NUMBER,
-                v_lock: 0,
-                vval: typval_vval {
-                    v_number: n as c_long,
-                },
-            },
-            TypVal::String(s) => {
-                let c = CString::new(s).unwrap();
-                let ptr = c.into_raw() as *mut c_uchar;
-                typval_T {
-                    v_type: vartype_T::VAR_STRING,
-                    v_lock: 0,
-                    vval: typval_vval { v_string: ptr },
-                }
-            }
+/// Free the value in `tv`, like Vim's clear_tv().  The typval becomes
+/// VAR_UNKNOWN.
+#[no_mangle]
+pub unsafe extern "C" fn tv_free(tv: *mut typval_T) {
+    let Some(tv) = tv.as_mut() else { return };
+    let vval = &tv.vval;
+    unsafe fn drop_raw<T>(ptr: *mut c_void) {
+        if !ptr.is_null() {
+            drop(Rc::from_raw(ptr as *const T));
+        }
+    }
+    match tv.v_type {
+        vartype_T::VAR_STRING | vartype_T::VAR_FUNC if !vval.v_string.is_null() => {
+            drop(CString::from_raw(vval.v_string as *mut c_char));
         }
+        vartype_T::VAR_LIST => drop_raw::<List>(vval.v_list),
+        vartype_T::VAR_DICT => drop_raw::<Dict>(vval.v_dict),
+        vartype_T::VAR_BLOB => drop_raw::<Blob>(vval.v_blob),
+        vartype_T::VAR_TUPLE => drop_raw::<Tuple>(vval.v_tuple),
+        vartype_T::VAR_PARTIAL => drop_raw::<Partial>(vval.v_partial),
+        vartype_T::VAR_JOB => drop_raw::<Job>(vval.v_job),
+        vartype_T::VAR_CHANNEL => drop_raw::<Channel>(vval.v_channel),
+        vartype_T::VAR_OBJECT => drop_raw::<Object>(vval.v_object),
+        vartype_T::VAR_CLASS => drop_raw::<Class>(vval.v_class),
+        _ => {}
+    }
+    tv.v_type = vartype_T::VAR_UNKNOWN;
+    tv.vval.v_number = 0;
+}
+
+impl From<&typval_T> for Value {
+    /// The value of the typval; an unknown type is the Number zero.
+    fn from(tv: &typval_T) -> Self {
+        unsafe { from_typval(tv) }.unwrap_or(Value::Number(0))
+    }
+}
+
+impl From<Value> for typval_T {
+    fn from(val: Value) -> Self {
+        let mut tv = typval_T { v_type: vartype_T::VAR_UNKNOWN, v_lock: 0, vval: typval_vval { v_number: 0 } };
+        unsafe { to_typval(val, &mut tv) };
+        tv
     }
 }
 
@@ -86,19 +244,48 @@ pub extern "C" fn alloc_tv() -> *mut typval_T {
     }))
 }
 
-/// Free a typval previously allocated with `alloc_tv` or converted from `TypVal`.
+/// Free a typval previously allocated with `alloc_tv` or converted from a
+/// `Value`, and the value in it.
 ///
 /// # Safety
 /// `tv` must point to a valid `typval_T` that was allocated by `alloc_tv` or
-/// created from a `TypVal`.  After calling this function the pointer must not
+/// created from a `Value`.  After calling this function the pointer must not
 /// be used again.
 #[no_mangle]
 pub unsafe extern "C" fn free_tv(tv: *mut typval_T) {
     if tv.is_null() {
         return;
     }
-    if (*tv).v_type == vartype_T::VAR_STRING && !(*tv).vval.v_string.is_null() {
-        drop(CString::from_raw((*tv).vval.v_string as *mut c_char));
-    }
+    tv_free(tv);
     drop(Box::from_raw(tv));
 }
+
+/// Copy typval `from` to `to`, like Vim's copy_tv(): shared values get
+/// another reference.
+#[no_mangle]
+pub unsafe extern "C" fn copy_tv(from: *const typval_T, to: *mut typval_T) {
+    if from.is_null() || to.is_null() {
+        return;
+    }
+    (*to).v_lock = VAR_UNLOCKED;
+    match from_typval(from) {
+        Some(val) => to_typval(val, to),
+        None => {
+            (*to).v_type = (*from).v_type;
+            (*to).vval.v_number = 0;
+        }
+    }
+}
+
+/// Lock or unlock the value in `tv` up to `deep` levels, like
+/// `:lockvar`.  A negative `deep` has no limit.
+#[no_mangle]
+pub unsafe extern "C" fn item_lock(tv: *mut typval_T, deep: libc::c_int, lock: libc::c_int) {
+    let Some(tv) = tv.as_mut() else { return };
+    if tv.v_lock & VAR_FIXED == 0 {
+        tv.v_lock = if lock != 0 { VAR_LOCKED } else { VAR_UNLOCKED };
+    }
+    if let Some(val) = from_typval(tv) {
+        val.lock_items(deep, lock != 0);
+    }
+}
@@ -0,0 +1,180 @@
+//! Turning values into text: what `:echo` shows and what string() returns.
+//!
+//! A container that contains itself is shown as "[...]", "{...}" or "(...)"
+//! where it appears again.
+
+use std::rc::Rc;
+
+use crate::value::{JobStatus, Special, Value};
+
+/// Format a Float like Vim's "%g", with ".0" added when there is no dot:
+/// "1.0", "0.333333", "1.0e20".
+pub fn format_float(f: f64) -> String {
+    if f.is_nan() {
+        return "nan".to_string();
+    }
+    if f.is_infinite() {
+        return if f > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    if f == 0.0 {
+        return if f.is_sign_negative() { "-0.0" } else { "0.0" }.to_string();
+    }
+    let exp = f.abs().log10().floor() as i32;
+    if (-4..6).contains(&exp) {
+        let s = format!("{:.*}", (5 - exp) as usize, f);
+        let s = s.trim_end_matches('0');
+        return if s.ends_with('.') { format!("{}0", s) } else { s.to_string() };
+    }
+    let s = format!("{:.5e}", f);
+    let (mantissa, exp) = s.split_once('e').unwrap_or((&s, "0"));
+    let mantissa = mantissa.trim_end_matches('0');
+    let mantissa = if mantissa.ends_with('.') { format!("{}0", mantissa) } else { mantissa.to_string() };
+    format!("{}e{}", mantissa, exp)
+}
+
+/// The value quoted as a String, with a single quote doubled.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn blob_string(bytes: &[u8]) -> String {
+    let mut s = String::from("0z");
+    for (i, b) in bytes.iter().enumerate() {
+        // A dot every four bytes makes long blobs readable.
+        if i > 0 && i % 4 == 0 {
+            s.push('.');
+        }
+        s.push_str(&format!("{:02X}", b));
+    }
+    s
+}
+
+impl Value {
+    /// The value as it appears inside a list or dict, and as string()
+    /// returns it: strings are quoted.
+    pub fn to_quoted(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, &mut Vec::new(), true);
+        out
+    }
+
+    /// The value as `:echo` shows it: a String and a Funcref are not quoted.
+    #[allow(clippy::inherent_to_string_shadow_display)]
+    pub fn to_string(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, &mut Vec::new(), false);
+        out
+    }
+
+    fn write(&self, out: &mut String, seen: &mut Vec<*const ()>, quoted: bool) {
+        let ptr = match self {
+            Value::List(l) => Rc::as_ptr(l) as *const (),
+            Value::Dict(d) => Rc::as_ptr(d) as *const (),
+            Value::Tuple(t) => Rc::as_ptr(t) as *const (),
+            Value::Object(o) => Rc::as_ptr(o) as *const (),
+            _ => std::ptr::null(),
+        };
+        if !ptr.is_null() {
+            if seen.contains(&ptr) {
+                out.push_str(match self {
+                    Value::List(_) => "[...]",
+                    Value::Tuple(_) => "(...)",
+                    _ => "{...}",
+                });
+                return;
+            }
+            seen.push(ptr);
+        }
+        let write_items = |out: &mut String, seen: &mut Vec<*const ()>, items: &[Value]| {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                item.write(out, seen, true);
+            }
+        };
+        match self {
+            Value::Number(n) => out.push_str(&n.to_string()),
+            Value::Float(f) => out.push_str(&format_float(*f)),
+            Value::Str(s) if quoted => out.push_str(&quote(s)),
+            Value::Str(s) => out.push_str(s),
+            Value::Bool(b) => out.push_str(if *b { "v:true" } else { "v:false" }),
+            Value::Special(Special::None) => out.push_str("v:none"),
+            Value::Special(Special::Null) => out.push_str("v:null"),
+            Value::List(l) => {
+                out.push('[');
+                write_items(out, seen, &l.borrow());
+                out.push(']');
+            }
+            Value::Tuple(t) => {
+                out.push('(');
+                write_items(out, seen, t);
+                if t.len() == 1 {
+                    out.push(',');
+                }
+                out.push(')');
+            }
+            Value::Dict(d) => {
+                out.push('{');
+                for (i, (key, val)) in d.borrow().iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    out.push_str(&quote(key));
+                    out.push_str(": ");
+                    val.write(out, seen, true);
+                }
+                out.push('}');
+            }
+            Value::Blob(b) => out.push_str(&blob_string(&b.borrow())),
+            Value::Func(pt) if pt.is_plain() && quoted => out.push_str(&format!("function('{}')", pt.name)),
+            Value::Func(pt) if pt.is_plain() => out.push_str(&pt.name),
+            Value::Func(pt) => {
+                out.push_str(&format!("function('{}'", pt.name));
+                if !pt.args.is_empty() {
+                    out.push_str(", [");
+                    write_items(out, seen, &pt.args);
+                    out.push(']');
+                }
+                if let Some(d) = &pt.dict {
+                    out.push_str(", ");
+                    Value::Dict(d.clone()).write(out, seen, true);
+                }
+                out.push(')');
+            }
+            Value::Job(job) => {
+                let status = match job.status.get() {
+                    JobStatus::Run => "run",
+                    JobStatus::Fail => "fail",
+                    JobStatus::Dead => "dead",
+                };
+                out.push_str(&format!("process {} {}", job.pid, status));
+            }
+            Value::Channel(ch) => {
+                out.push_str(&format!("channel {} {}", ch.id, if ch.open.get() { "open" } else { "closed" }));
+            }
+            Value::Class(class) => out.push_str(&format!("class {}", class.name)),
+            Value::Object(obj) => {
+                out.push_str(&format!("object of {} {{", obj.class.name));
+                for (i, (name, val)) in obj.class.fields.iter().zip(obj.borrow().iter()).enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    out.push_str(name);
+                    out.push_str(": ");
+                    val.write(out, seen, true);
+                }
+                out.push('}');
+            }
+        }
+        if !ptr.is_null() {
+            seen.pop();
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_string())
+    }
+}
@@ -0,0 +1,564 @@
+//! The safe representation of a Vim script value.
+//!
+//! Lists, dicts, blobs, tuples, objects, jobs and channels are shared by
+//! reference, like in Vim: assigning a list to another variable does not copy
+//! it.  Each container carries its own lock, as set with `:lockvar`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::gc::{self, Trace};
+
+pub type ListRef = Rc<List>;
+pub type DictRef = Rc<Dict>;
+pub type BlobRef = Rc<Blob>;
+pub type TupleRef = Rc<Tuple>;
+pub type ObjectRef = Rc<Object>;
+pub type ClassRef = Rc<Class>;
+
+/// Maximum nesting when comparing values, like Vim's recursive_cnt: deeper
+/// values are considered equal.
+const MAX_EQUAL_DEPTH: usize = 1000;
+
+/// Lock of a variable or a container, Vim's VAR_LOCKED and VAR_FIXED.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VarLock {
+    #[default]
+    Unlocked,
+    /// Set with `:lockvar`.
+    Locked,
+    /// Can never be unlocked, e.g. v:version.
+    Fixed,
+}
+
+impl VarLock {
+    /// Check whether the value `name` can be changed.
+    pub fn check(self, name: &str) -> Result<(), String> {
+        match self {
+            VarLock::Unlocked => Ok(()),
+            VarLock::Locked => Err(format!("E741: Value is locked: {}", name)),
+            VarLock::Fixed => Err(format!("E742: Cannot change value of {}", name)),
+        }
+    }
+
+    fn set(cell: &Cell<VarLock>, lock: bool) {
+        match (cell.get(), lock) {
+            (VarLock::Fixed, _) => {}
+            (_, true) => cell.set(VarLock::Locked),
+            (_, false) => cell.set(VarLock::Unlocked),
+        }
+    }
+}
+
+/// The special values v:none and v:null.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Special {
+    None,
+    Null,
+}
+
+/// A list: the items and the locks set with `:lockvar`.
+#[derive(Default)]
+pub struct List {
+    items: RefCell<Vec<Value>>,
+    lock: Cell<VarLock>,
+    /// Lock of the items themselves, set with a depth of two or more.
+    items_lock: Cell<VarLock>,
+}
+
+impl List {
+    pub fn new(items: Vec<Value>) -> Self {
+        List { items: RefCell::new(items), ..Default::default() }
+    }
+
+    /// The lock on adding and removing items.
+    pub fn lock(&self) -> VarLock {
+        self.lock.get()
+    }
+
+    /// The lock on changing the value of an item.
+    pub fn items_lock(&self) -> VarLock {
+        self.items_lock.get()
+    }
+}
+
+impl Deref for List {
+    type Target = RefCell<Vec<Value>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+/// A dictionary.  Keys are kept sorted, so that output is predictable.
+#[derive(Default)]
+pub struct Dict {
+    items: RefCell<BTreeMap<String, Value>>,
+    lock: Cell<VarLock>,
+    items_lock: Cell<VarLock>,
+}
+
+impl Dict {
+    pub fn new(items: BTreeMap<String, Value>) -> Self {
+        Dict { items: RefCell::new(items), ..Default::default() }
+    }
+
+    pub fn lock(&self) -> VarLock {
+        self.lock.get()
+    }
+
+    pub fn items_lock(&self) -> VarLock {
+        self.items_lock.get()
+    }
+}
+
+impl Deref for Dict {
+    type Target = RefCell<BTreeMap<String, Value>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+#[derive(Default)]
+pub struct Blob {
+    bytes: RefCell<Vec<u8>>,
+    lock: Cell<VarLock>,
+}
+
+impl Blob {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Blob { bytes: RefCell::new(bytes), ..Default::default() }
+    }
+
+    pub fn lock(&self) -> VarLock {
+        self.lock.get()
+    }
+}
+
+impl Deref for Blob {
+    type Target = RefCell<Vec<u8>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.bytes
+    }
+}
+
+/// A tuple.  Items cannot be added or removed, a List item in a tuple can
+/// still be changed unless it is locked.
+#[derive(Default)]
+pub struct Tuple {
+    items: Vec<Value>,
+    lock: Cell<VarLock>,
+}
+
+impl Tuple {
+    pub fn new(items: Vec<Value>) -> Self {
+        Tuple { items, ..Default::default() }
+    }
+
+    pub fn lock(&self) -> VarLock {
+        self.lock.get()
+    }
+}
+
+impl Deref for Tuple {
+    type Target = [Value];
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+/// A funcref or partial: the function name plus the arguments and dict bound
+/// with function().  `func` keeps the function a funcref() or lambda refers
+/// to, so that it survives redefinition of `name`.  It is owned by the
+/// evaluator and opaque here, tracing it finds the scope of a closure.
+#[derive(Clone, Default)]
+pub struct Partial {
+    pub name: String,
+    pub args: Vec<Value>,
+    pub dict: Option<DictRef>,
+    pub func: Option<Rc<dyn Trace>>,
+}
+
+impl Partial {
+    pub fn new(name: &str) -> Self {
+        Partial { name: name.to_string(), ..Default::default() }
+    }
+
+    /// Whether this is a plain funcref that can be passed as a name.
+    pub fn is_plain(&self) -> bool {
+        self.args.is_empty() && self.dict.is_none()
+    }
+}
+
+impl PartialEq for Partial {
+    fn eq(&self, other: &Self) -> bool {
+        let same_func = match (&self.func, &other.func) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        let same_dict = match (&self.dict, &other.dict) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        self.name == other.name && self.args == other.args && same_dict && same_func
+    }
+}
+
+impl std::fmt::Debug for Partial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Partial")
+            .field("name", &self.name)
+            .field("args", &self.args)
+            .field("dict", &self.dict.as_ref().map(|d| Value::Dict(d.clone())))
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Run,
+    Fail,
+    Dead,
+}
+
+/// A job started with job_start().  The process is managed elsewhere, the
+/// value only refers to it.
+#[derive(Debug)]
+pub struct Job {
+    pub pid: i32,
+    pub status: Cell<JobStatus>,
+}
+
+/// A channel opened with ch_open() or for a job.
+#[derive(Debug)]
+pub struct Channel {
+    pub id: i32,
+    pub open: Cell<bool>,
+}
+
+/// A Vim9 class.  The members of its objects are in `fields` order.
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+/// An object: an instance of a Vim9 class.
+pub struct Object {
+    pub class: ClassRef,
+    members: RefCell<Vec<Value>>,
+    lock: Cell<VarLock>,
+}
+
+impl Object {
+    /// A new object of `class` with the values of its fields.
+    pub fn new(class: ClassRef, members: Vec<Value>) -> Self {
+        Object { class, members: RefCell::new(members), lock: Cell::default() }
+    }
+
+    pub fn member(&self, name: &str) -> Option<Value> {
+        let idx = self.class.fields.iter().position(|f| f == name)?;
+        self.members.borrow().get(idx).cloned()
+    }
+
+    pub fn lock(&self) -> VarLock {
+        self.lock.get()
+    }
+}
+
+impl Deref for Object {
+    type Target = RefCell<Vec<Value>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.members
+    }
+}
+
+#[derive(Clone)]
+pub enum Value {
+    Number(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    Special(Special),
+    List(ListRef),
+    Dict(DictRef),
+    Blob(BlobRef),
+    Tuple(TupleRef),
+    /// A funcref, or a partial when arguments or a dict are bound.
+    Func(Rc<Partial>),
+    Job(Rc<Job>),
+    Channel(Rc<Channel>),
+    Object(ObjectRef),
+    Class(ClassRef),
+}
+
+impl Value {
+    #[allow(clippy::result_unit_err)]
+    pub fn as_number(&self) -> Result<i64, ()> {
+        match self {
+            Value::Number(n) => Ok(*n),
+            Value::Float(f) => Ok(*f as i64),
+            Value::Str(s) => s.parse().map_err(|_| ()),
+            Value::Bool(b) => Ok(*b as i64),
+            Value::Special(_) => Ok(0),
+            _ => Err(()),
+        }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn as_float(&self) -> Result<f64, ()> {
+        match self {
+            Value::Number(n) => Ok(*n as f64),
+            Value::Float(f) => Ok(*f),
+            Value::Str(s) => s.parse().map_err(|_| ()),
+            _ => Err(()),
+        }
+    }
+
+    pub fn new_list(items: Vec<Value>) -> Value {
+        Value::List(tracked(List::new(items)))
+    }
+
+    pub fn new_dict(items: BTreeMap<String, Value>) -> Value {
+        Value::Dict(tracked(Dict::new(items)))
+    }
+
+    pub fn new_blob(bytes: Vec<u8>) -> Value {
+        Value::Blob(Rc::new(Blob::new(bytes)))
+    }
+
+    pub fn new_tuple(items: Vec<Value>) -> Value {
+        Value::Tuple(tracked(Tuple::new(items)))
+    }
+
+    pub fn new_func(pt: Partial) -> Value {
+        Value::Func(tracked(pt))
+    }
+
+    pub fn new_object(class: ClassRef, members: Vec<Value>) -> Value {
+        Value::Object(tracked(Object::new(class, members)))
+    }
+
+    /// The number type() returns, v:t_number and friends.
+    pub fn type_nr(&self) -> i64 {
+        match self {
+            Value::Number(_) => 0,
+            Value::Str(_) => 1,
+            Value::Func(_) => 2,
+            Value::List(_) => 3,
+            Value::Dict(_) => 4,
+            Value::Float(_) => 5,
+            Value::Bool(_) => 6,
+            Value::Special(_) => 7,
+            Value::Job(_) => 8,
+            Value::Channel(_) => 9,
+            Value::Blob(_) => 10,
+            Value::Class(_) => 12,
+            Value::Object(_) => 13,
+            Value::Tuple(_) => 17,
+        }
+    }
+
+    /// A copy of the value: containers are new, the items in them are
+    /// shared, like copy().
+    pub fn copy(&self) -> Value {
+        match self {
+            Value::List(l) => Value::new_list(l.borrow().clone()),
+            Value::Dict(d) => Value::new_dict(d.borrow().clone()),
+            Value::Blob(b) => Value::new_blob(b.borrow().clone()),
+            Value::Tuple(t) => Value::new_tuple(t.items.clone()),
+            _ => self.clone(),
+        }
+    }
+
+    /// A copy of the value and everything it contains, like deepcopy().  A
+    /// container that appears more than once, also in a cycle, is copied
+    /// once, so that the copy has the same structure.
+    pub fn deep_copy(&self) -> Value {
+        self.deep_copy_with(&mut HashMap::new())
+    }
+
+    fn deep_copy_with(&self, copies: &mut HashMap<*const (), Value>) -> Value {
+        let key = match self {
+            Value::List(l) => Rc::as_ptr(l) as *const (),
+            Value::Dict(d) => Rc::as_ptr(d) as *const (),
+            Value::Tuple(t) => Rc::as_ptr(t) as *const (),
+            Value::Object(o) => Rc::as_ptr(o) as *const (),
+            Value::Blob(b) => return Value::new_blob(b.borrow().clone()),
+            _ => return self.clone(),
+        };
+        if let Some(copy) = copies.get(&key) {
+            return copy.clone();
+        }
+        match self {
+            Value::List(l) => {
+                // Register the new list before copying the items, an item
+                // may refer back to it.
+                let new = tracked(List::default());
+                copies.insert(key, Value::List(new.clone()));
+                let items: Vec<Value> = l.borrow().iter().map(|v| v.deep_copy_with(copies)).collect();
+                *new.borrow_mut() = items;
+                Value::List(new)
+            }
+            Value::Dict(d) => {
+                let new = tracked(Dict::default());
+                copies.insert(key, Value::Dict(new.clone()));
+                let items = d.borrow().iter().map(|(k, v)| (k.clone(), v.deep_copy_with(copies))).collect();
+                *new.borrow_mut() = items;
+                Value::Dict(new)
+            }
+            Value::Object(o) => {
+                let new = tracked(Object::new(o.class.clone(), Vec::new()));
+                copies.insert(key, Value::Object(new.clone()));
+                let members = o.borrow().iter().map(|v| v.deep_copy_with(copies)).collect();
+                *new.borrow_mut() = members;
+                Value::Object(new)
+            }
+            // A tuple cannot contain itself, only through a list or dict.
+            Value::Tuple(t) => {
+                let copy = Value::new_tuple(t.items.iter().map(|v| v.deep_copy_with(copies)).collect());
+                copies.insert(key, copy.clone());
+                copy
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Lock or unlock the containers in this value, like `:lockvar {depth}`
+    /// does for the value of a variable; the lock of the variable itself is
+    /// kept by its scope.  A depth of one locks the container, two also its
+    /// items, and so on.  A negative depth has no limit.
+    pub fn lock_items(&self, depth: i32, lock: bool) {
+        if depth == 0 {
+            return;
+        }
+        let deeper = !(0..=1).contains(&depth);
+        match self {
+            Value::List(l) => {
+                VarLock::set(&l.lock, lock);
+                if deeper {
+                    VarLock::set(&l.items_lock, lock);
+                    let items = l.borrow().clone();
+                    for item in items {
+                        item.lock_items(depth - 1, lock);
+                    }
+                }
+            }
+            Value::Dict(d) => {
+                VarLock::set(&d.lock, lock);
+                if deeper {
+                    VarLock::set(&d.items_lock, lock);
+                    let items: Vec<Value> = d.borrow().values().cloned().collect();
+                    for item in items {
+                        item.lock_items(depth - 1, lock);
+                    }
+                }
+            }
+            Value::Blob(b) => VarLock::set(&b.lock, lock),
+            Value::Tuple(t) => {
+                VarLock::set(&t.lock, lock);
+                if deeper {
+                    for item in t.iter() {
+                        item.lock_items(depth - 1, lock);
+                    }
+                }
+            }
+            Value::Object(o) => {
+                VarLock::set(&o.lock, lock);
+                if deeper {
+                    let members = o.borrow().clone();
+                    for member in members {
+                        member.lock_items(depth - 1, lock);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether two values are equal, like `==` for items of a List: a
+    /// Number and a Float are never equal.  With `ic` case is ignored for
+    /// Strings.
+    pub fn equal(&self, other: &Value, ic: bool) -> bool {
+        self.equal_depth(other, ic, 0)
+    }
+
+    fn equal_depth(&self, other: &Value, ic: bool, depth: usize) -> bool {
+        if depth > MAX_EQUAL_DEPTH {
+            return true;
+        }
+        let depth = depth + 1;
+        let items_equal = |a: &[Value], b: &[Value]| {
+            a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.equal_depth(y, ic, depth))
+        };
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) if ic => a.to_lowercase() == b.to_lowercase(),
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Bool(_) | Value::Special(_), Value::Bool(_) | Value::Special(_)) => {
+                special_nr(self) == special_nr(other)
+            }
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b) || items_equal(&a.borrow(), &b.borrow()),
+            (Value::Dict(a), Value::Dict(b)) => {
+                Rc::ptr_eq(a, b) || {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len()
+                        && a.iter().all(|(k, v)| b.get(k).is_some_and(|w| v.equal_depth(w, ic, depth)))
+                }
+            }
+            (Value::Blob(a), Value::Blob(b)) => *a.borrow() == *b.borrow(),
+            (Value::Tuple(a), Value::Tuple(b)) => Rc::ptr_eq(a, b) || items_equal(a, b),
+            (Value::Func(a), Value::Func(b)) => a == b,
+            (Value::Job(a), Value::Job(b)) => Rc::ptr_eq(a, b),
+            (Value::Channel(a), Value::Channel(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Object(a), Value::Object(b)) => {
+                Rc::ptr_eq(a, b) || (Rc::ptr_eq(&a.class, &b.class) && items_equal(&a.borrow(), &b.borrow()))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A new container that takes part in cycle collection.
+fn tracked<T: Trace>(value: T) -> Rc<T> {
+    let rc = Rc::new(value);
+    gc::track(&rc);
+    rc
+}
+
+/// The number of a Bool or special value, Vim's VVAL_FALSE and friends.
+pub(crate) fn special_nr(val: &Value) -> i64 {
+    match val {
+        Value::Bool(false) => 0,
+        Value::Bool(true) => 1,
+        Value::Special(Special::None) => 2,
+        _ => 3,
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.equal(other, false)
+    }
+}
+
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "Number({})", n),
+            Value::Float(fl) => write!(f, "Float({:?})", fl),
+            Value::Str(s) => write!(f, "Str({:?})", s),
+            _ => write!(f, "{}", self.to_quoted()),
+        }
+    }
+}
@@ -1,5 +1,11 @@
-use rust_typval::{alloc_tv, free_tv, typval_T, vartype_T, TypVal};
+use rust_typval::{
+    alloc_tv, gc, free_tv, from_typval, item_lock, to_typval, tv_free, typval_T, vartype_T, Channel, Class, Job, JobStatus,
+    Object, Special, Value, VarLock,
+};
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::ffi::CString;
+use std::rc::Rc;
 
 #[test]
 fn alloc_and_free_string_tv() {
@@ -14,11 +20,221 @@ fn alloc_and_free_string_tv() {
 
 #[test]
 fn typval_enum_roundtrip() {
-    let val = TypVal::String("hello".to_string());
+    let val = Value::Str("hello".to_string());
     let raw = Box::into_raw(Box::new(typval_T::from(val.clone())));
     unsafe {
-        let back = TypVal::from(&*raw);
+        let back = Value::from(&*raw);
         assert_eq!(back, val);
         free_tv(raw);
     }
 }
+
+fn sample_values() -> Vec<Value> {
+    let class = Rc::new(Class { name: "Point".to_string(), fields: vec!["x".to_string(), "y".to_string()] });
+    vec![
+        Value::Number(-3),
+        Value::Float(0.5),
+        Value::Str("x".to_string()),
+        Value::Bool(true),
+        Value::Special(Special::None),
+        Value::Special(Special::Null),
+        Value::new_list(vec![Value::Number(1)]),
+        Value::new_dict(BTreeMap::from([("a".to_string(), Value::Number(1))])),
+        Value::new_blob(vec![1, 2]),
+        Value::new_tuple(vec![Value::Number(1), Value::Str("b".to_string())]),
+        Value::Func(Rc::new(rust_typval::Partial::new("strlen"))),
+        Value::Job(Rc::new(Job { pid: 42, status: Cell::new(JobStatus::Run) })),
+        Value::Channel(Rc::new(Channel { id: 3, open: Cell::new(true) })),
+        Value::Object(Rc::new(Object::new(class.clone(), vec![Value::Number(1), Value::Number(2)]))),
+        Value::Class(class),
+    ]
+}
+
+#[test]
+fn every_type_converts_without_loss() {
+    for val in sample_values() {
+        let mut tv = typval_T::from(Value::Number(0));
+        unsafe {
+            to_typval(val.clone(), &mut tv);
+            let back = from_typval(&tv).unwrap();
+            assert_eq!(back, val, "{}", val.to_quoted());
+            assert_eq!(back.type_nr(), val.type_nr());
+            tv_free(&mut tv);
+        }
+        assert_eq!(tv.v_type, vartype_T::VAR_UNKNOWN);
+    }
+    // A shared value is the same one after the round trip.
+    let list = Value::new_list(Vec::new());
+    let tv = typval_T::from(list.clone());
+    if let (Value::List(a), Value::List(b)) = (&list, Value::from(&tv)) {
+        assert!(Rc::ptr_eq(a, &b));
+        b.borrow_mut().push(Value::Number(1));
+    }
+    assert_eq!(list.to_string(), "[1]");
+}
+
+#[test]
+fn string_representation() {
+    let strings: Vec<String> = sample_values().iter().map(Value::to_quoted).collect();
+    assert_eq!(
+        strings,
+        [
+            "-3",
+            "0.5",
+            "'x'",
+            "v:true",
+            "v:none",
+            "v:null",
+            "[1]",
+            "{'a': 1}",
+            "0z0102",
+            "(1, 'b')",
+            "function('strlen')",
+            "process 42 run",
+            "channel 3 open",
+            "object of Point {x: 1, y: 2}",
+            "class Point",
+        ]
+    );
+    assert_eq!(Value::Float(1.0).to_string(), "1.0");
+    assert_eq!(Value::Float(1.0 / 3.0).to_string(), "0.333333");
+    assert_eq!(Value::Float(1.5e20).to_string(), "1.5e20");
+    assert_eq!(Value::Float(-1e-5).to_string(), "-1.0e-5");
+    assert_eq!(Value::new_blob(vec![1, 2, 3, 4, 255]).to_string(), "0z01020304.FF");
+    assert_eq!(Value::new_tuple(vec![Value::Number(1)]).to_string(), "(1,)");
+
+    // A list that contains itself.
+    let list = Value::new_list(vec![Value::Number(1)]);
+    if let Value::List(l) = &list {
+        l.borrow_mut().push(list.clone());
+    }
+    assert_eq!(list.to_string(), "[1, [...]]");
+    assert!(list.to_json().unwrap_err().starts_with("E724:"));
+    if let Value::List(l) = &list {
+        l.borrow_mut().clear();
+    }
+}
+
+#[test]
+fn json_encoding() {
+    let dict = Value::new_dict(BTreeMap::from([
+        ("s".to_string(), Value::Str("a\"b\n\u{1}".to_string())),
+        ("l".to_string(), Value::new_list(vec![Value::Bool(false), Value::Special(Special::Null), Value::Float(2.5)])),
+        ("b".to_string(), Value::new_blob(vec![0, 255])),
+    ]));
+    assert_eq!(dict.to_json().unwrap(), r#"{"b":[0,255],"l":[false,null,2.5],"s":"a\"b\n\u0001"}"#);
+    assert!(Value::Func(Rc::new(rust_typval::Partial::new("f"))).to_json().is_err());
+
+    let decoded = Value::from_json(r#" {"a": [1, -2.5e1, true, null], "s": "x\"\u00e9\ud83d\ude00"} "#).unwrap();
+    assert_eq!(decoded.to_string(), "{'a': [1, -25.0, v:true, v:null], 's': 'x\"é😀'}");
+    assert_eq!(Value::from_json(&decoded.to_json().unwrap()).unwrap(), decoded);
+    assert_eq!(Value::from_json("[1,").unwrap_err(), "E491: JSON decode error at '[1,'");
+    assert_eq!(Value::from_json(r#"{"a":1,"a":2}"#).unwrap_err(), "E938: Duplicate key in JSON: \"a\"");
+    assert!(Value::from_json("1 2").unwrap_err().starts_with("E474:"));
+}
+
+#[test]
+fn equality_and_copies() {
+    let inner = Value::new_list(vec![Value::Str("A".to_string())]);
+    let outer = Value::new_list(vec![inner.clone(), inner.clone()]);
+    assert!(Value::new_list(vec![Value::Number(1)]) != Value::new_list(vec![Value::Float(1.0)]));
+    assert!(Value::Str("abc".to_string()).equal(&Value::Str("ABC".to_string()), true));
+    assert!(Value::Bool(false) != Value::Special(Special::Null));
+
+    // copy() shares the items, deepcopy() does not but keeps the structure.
+    let copy = outer.copy();
+    let deep = outer.deep_copy();
+    assert_eq!(copy, outer);
+    assert_eq!(deep, outer);
+    if let Value::List(l) = &inner {
+        l.borrow_mut().push(Value::Number(2));
+    }
+    assert_eq!(copy.to_string(), "[['A', 2], ['A', 2]]");
+    assert_eq!(deep.to_string(), "[['A'], ['A']]");
+    if let Value::List(l) = &deep {
+        let items = l.borrow();
+        match (&items[0], &items[1]) {
+            (Value::List(a), Value::List(b)) => assert!(Rc::ptr_eq(a, b)),
+            _ => unreachable!(),
+        }
+    }
+
+    // A cycle is copied as a cycle.
+    let cyclic = Value::new_dict(BTreeMap::new());
+    if let Value::Dict(d) = &cyclic {
+        d.borrow_mut().insert("self".to_string(), cyclic.clone());
+    }
+    let copy = cyclic.deep_copy();
+    if let Value::Dict(d) = &copy {
+        match d.borrow().get("self") {
+            Some(Value::Dict(inner)) => assert!(Rc::ptr_eq(d, inner)),
+            _ => unreachable!(),
+        }
+        d.borrow_mut().clear();
+    }
+    if let Value::Dict(d) = &cyclic {
+        d.borrow_mut().clear();
+    }
+}
+
+#[test]
+fn locking() {
+    let inner = Value::new_list(vec![Value::Number(1)]);
+    let outer = Value::new_dict(BTreeMap::from([("l".to_string(), inner.clone())]));
+    let (Value::Dict(d), Value::List(l)) = (&outer, &inner) else { unreachable!() };
+
+    outer.lock_items(1, true);
+    assert_eq!(d.lock(), VarLock::Locked);
+    assert_eq!(d.items_lock(), VarLock::Unlocked);
+    assert_eq!(l.lock(), VarLock::Unlocked);
+    assert_eq!(d.lock().check("d").unwrap_err(), "E741: Value is locked: d");
+
+    outer.lock_items(-1, true);
+    assert_eq!(d.items_lock(), VarLock::Locked);
+    assert_eq!(l.lock(), VarLock::Locked);
+    assert_eq!(l.items_lock(), VarLock::Locked);
+    outer.lock_items(-1, false);
+    assert_eq!(l.lock(), VarLock::Unlocked);
+
+    // The typval itself is locked too, and the lock survives conversion.
+    let mut tv = typval_T::from(outer.clone());
+    unsafe {
+        item_lock(&mut tv, 2, 1);
+        assert_eq!(VarLock::from_c(tv.v_lock), VarLock::Locked);
+        tv_free(&mut tv);
+    }
+    assert_eq!(d.items_lock(), VarLock::Locked);
+    assert_eq!(l.lock(), VarLock::Locked);
+    assert_eq!(l.items_lock(), VarLock::Unlocked);
+    // Copies are not locked.
+    let Value::Dict(copy) = outer.copy() else { unreachable!() };
+    assert_eq!(copy.lock(), VarLock::Unlocked);
+}
+
+#[test]
+fn cycles_are_collected() {
+    let list = Value::new_list(Vec::new());
+    let dict = Value::new_dict(BTreeMap::from([("l".to_string(), list.clone())]));
+    let (Value::List(l), Value::Dict(d)) = (&list, &dict) else { unreachable!() };
+    l.borrow_mut().push(dict.clone());
+    // A dict function bound to its own dict.
+    let mut pt = rust_typval::Partial::new("F");
+    pt.dict = Some(d.clone());
+    d.borrow_mut().insert("f".to_string(), Value::new_func(pt));
+    let weak = Rc::downgrade(l);
+
+    // Values that are still referenced are kept, also without roots.
+    assert_eq!(gc::collect(|_| {}), 0);
+    drop(dict);
+    assert_eq!(gc::collect(|_| {}), 0);
+    let root = list.clone();
+    drop(list);
+    assert_eq!(gc::collect(|tracer| root.trace(tracer)), 0);
+    assert_eq!(root.to_string(), "[{'f': function('F', {...}), 'l': [...]}]");
+
+    drop(root);
+    assert!(weak.upgrade().is_some());
+    assert_eq!(gc::collect(|_| {}), 3);
+    assert!(weak.upgrade().is_none());
+    assert_eq!(gc::tracked(), 0);
+}
@@ -1,6 +1,12 @@
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 
+mod tree;
+mod undofile;
+
+pub use tree::{parse_step, StepUnit, TreeEntry, UndoBuffer, UndoEntry, UndoHeader, UndoTree, UH_CHANGED, UH_EMPTYBUF};
+pub use undofile::{read_undo_file, text_hash, undo_file_name, write_undo_file, UndoFileError};
+
 #[repr(C)]
 pub struct UEntry {
     pub next: *mut UEntry,
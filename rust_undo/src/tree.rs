@@ -0,0 +1,1130 @@
+//! The undo tree of a buffer, like Vim's undo.c.
+//!
+//! The changes made between two syncs form an undo block, a header with one
+//! entry per saved range of lines.  Undoing a block swaps the saved lines
+//! with the text in the buffer, so that the entry then holds what is needed
+//! to redo it.
+//!
+//! Headers are linked like Vim does: `next` is the older header, `prev` the
+//! newer one on the current branch.  Making a change after undoing starts a
+//! new branch, the undone headers become an alternative reached through
+//! `alt_next`.  `oldhead` is the oldest header, `newhead` the newest one on
+//! the current branch and `curhead` the header that is redone next, None
+//! when nothing was undone.
+
+/// The text undo works on.  Line numbers are one based, as in Vim.
+pub trait UndoBuffer {
+    fn line_count(&self) -> usize;
+
+    fn line(&self, lnum: usize) -> &str;
+
+    /// Replace the `count` lines below line `top` with `lines`.  Returns
+    /// the replaced lines.
+    fn replace_lines(&mut self, top: usize, count: usize, lines: Vec<String>) -> Vec<String>;
+
+    /// Whether the buffer has changes that were not written.
+    fn modified(&self) -> bool {
+        false
+    }
+
+    fn set_modified(&mut self, _modified: bool) {}
+
+    /// The cursor line and byte column, both one based.
+    fn cursor(&self) -> (usize, usize) {
+        (1, 1)
+    }
+
+    fn set_cursor(&mut self, _lnum: usize, _col: usize) {}
+}
+
+impl UndoBuffer for Vec<String> {
+    fn line_count(&self) -> usize {
+        self.len()
+    }
+
+    fn line(&self, lnum: usize) -> &str {
+        &self[lnum - 1]
+    }
+
+    fn replace_lines(&mut self, top: usize, count: usize, lines: Vec<String>) -> Vec<String> {
+        self.splice(top..top + count, lines).collect()
+    }
+}
+
+/// Header flag: the buffer was modified before the change.
+pub const UH_CHANGED: u16 = 1;
+/// Header flag: the buffer was empty before the change.
+pub const UH_EMPTYBUF: u16 = 2;
+
+/// The lines below `top` and above `bot` as they were before a change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndoEntry {
+    /// The line above the changed lines, zero for the first line.
+    pub top: i64,
+    /// The line below the changed lines after the change, zero when they go
+    /// to the end of the buffer.
+    pub bot: i64,
+    /// The line count when the entry was saved, to compute `bot` later.
+    pub lcount: i64,
+    pub lines: Vec<String>,
+}
+
+type Id = usize;
+
+/// An undo block.
+#[derive(Debug, Clone)]
+pub struct UndoHeader {
+    pub(crate) next: Option<Id>,
+    pub(crate) prev: Option<Id>,
+    pub(crate) alt_next: Option<Id>,
+    pub(crate) alt_prev: Option<Id>,
+    pub seq: i64,
+    /// The entries, the last saved one first.
+    pub entries: Vec<UndoEntry>,
+    /// The cursor position before the change.
+    pub cursor: (i64, i64),
+    pub flags: u16,
+    /// When the block was started, in seconds.
+    pub time: i64,
+    /// The number of the write after the block, zero when not written.
+    pub save_nr: i64,
+    /// Used by undo_time() to find a path through the tree.
+    walk: u64,
+    /// The `bot` of the first entry is still to be computed.
+    getbot: bool,
+}
+
+impl UndoHeader {
+    /// A header read from an undo file, linked later.
+    pub(crate) fn read(
+        seq: i64,
+        entries: Vec<UndoEntry>,
+        cursor: (i64, i64),
+        flags: u16,
+        time: i64,
+        save_nr: i64,
+    ) -> Self {
+        UndoHeader {
+            next: None,
+            prev: None,
+            alt_next: None,
+            alt_prev: None,
+            seq,
+            entries,
+            cursor,
+            flags,
+            time,
+            save_nr,
+            walk: 0,
+            getbot: false,
+        }
+    }
+
+    pub(crate) fn set_links(&mut self, next: Option<Id>, prev: Option<Id>, alt_next: Option<Id>, alt_prev: Option<Id>) {
+        self.next = next;
+        self.prev = prev;
+        self.alt_next = alt_next;
+        self.alt_prev = alt_prev;
+    }
+}
+
+/// A header as undotree() shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub seq: i64,
+    pub time: i64,
+    pub newhead: bool,
+    pub curhead: bool,
+    pub save: i64,
+    /// The alternative branches, oldest header first.
+    pub alt: Vec<TreeEntry>,
+}
+
+/// The unit of a step for [`UndoTree::undo_time`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepUnit {
+    /// Changes, as `g-` and `g+` use.
+    Changes,
+    Seconds,
+    /// File writes, the "f" of `:earlier 1f`.
+    Writes,
+}
+
+/// The argument of `:earlier` and `:later`: "", "N", or N followed by "s",
+/// "m", "h", "d" or "f".  Minutes, hours and days are given in seconds.
+pub fn parse_step(arg: &str) -> Option<(i64, StepUnit)> {
+    if arg.is_empty() {
+        return Some((1, StepUnit::Changes));
+    }
+    let digits = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let count: i64 = arg[..digits].parse().ok()?;
+    match &arg[digits..] {
+        "" => Some((count, StepUnit::Changes)),
+        "s" => Some((count, StepUnit::Seconds)),
+        "m" => Some((count * 60, StepUnit::Seconds)),
+        "h" => Some((count * 60 * 60, StepUnit::Seconds)),
+        "d" => Some((count * 24 * 60 * 60, StepUnit::Seconds)),
+        "f" => Some((count, StepUnit::Writes)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UndoTree {
+    /// Headers by id, None for a freed one.
+    headers: Vec<Option<UndoHeader>>,
+    pub(crate) oldhead: Option<Id>,
+    pub(crate) newhead: Option<Id>,
+    pub(crate) curhead: Option<Id>,
+    pub(crate) numhead: usize,
+    /// The sequence number of the last header made.
+    pub(crate) seq_last: i64,
+    /// The sequence number of the last change that is not undone.
+    pub(crate) seq_cur: i64,
+    /// The time of the current state, for `:earlier 10s`.
+    pub(crate) time_cur: i64,
+    pub(crate) save_nr_last: i64,
+    pub(crate) save_nr_cur: i64,
+    /// The next change starts a new undo block.
+    pub(crate) synced: bool,
+    /// 'undolevels': the maximum number of undo blocks, negative for none.
+    undolevels: i64,
+    lastmark: u64,
+    /// The lines removed and inserted by the last undo or redo, for the
+    /// message.
+    counts: (i64, i64),
+    time_for_testing: Option<i64>,
+}
+
+impl Default for UndoTree {
+    fn default() -> Self {
+        UndoTree {
+            headers: Vec::new(),
+            oldhead: None,
+            newhead: None,
+            curhead: None,
+            numhead: 0,
+            seq_last: 0,
+            seq_cur: 0,
+            time_cur: 0,
+            save_nr_last: 0,
+            save_nr_cur: 0,
+            synced: true,
+            undolevels: 1000,
+            lastmark: 0,
+            counts: (0, 0),
+            time_for_testing: None,
+        }
+    }
+}
+
+impl UndoTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn h(&self, id: Id) -> &UndoHeader {
+        self.headers[id].as_ref().expect("freed undo header")
+    }
+
+    fn hm(&mut self, id: Id) -> &mut UndoHeader {
+        self.headers[id].as_mut().expect("freed undo header")
+    }
+
+    /// The current time, in seconds.
+    pub(crate) fn now(&self) -> i64 {
+        self.time_for_testing.unwrap_or_else(|| unsafe { rust_time::vim_time() } as i64)
+    }
+
+    /// Use `time` as the current time, None to use the clock, like
+    /// test_settime().
+    pub fn set_time_for_testing(&mut self, time: Option<i64>) {
+        self.time_for_testing = time;
+    }
+
+    pub fn time_for_testing(&self) -> Option<i64> {
+        self.time_for_testing
+    }
+
+    pub fn set_undolevels(&mut self, undolevels: i64) {
+        self.undolevels = undolevels;
+    }
+
+    pub fn seq_last(&self) -> i64 {
+        self.seq_last
+    }
+
+    pub fn seq_cur(&self) -> i64 {
+        self.seq_cur
+    }
+
+    pub fn time_cur(&self) -> i64 {
+        self.time_cur
+    }
+
+    pub fn save_last(&self) -> i64 {
+        self.save_nr_last
+    }
+
+    pub fn save_cur(&self) -> i64 {
+        self.save_nr_cur
+    }
+
+    pub fn synced(&self) -> bool {
+        self.synced
+    }
+
+    /// The number of undo blocks.
+    pub fn len(&self) -> usize {
+        self.numhead
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.numhead == 0
+    }
+
+    /// Forget all undo information, e.g. when the buffer is reloaded.
+    pub fn clear(&mut self) {
+        *self = UndoTree { undolevels: self.undolevels, time_for_testing: self.time_for_testing, ..UndoTree::default() };
+    }
+
+    /// Add a header, used when reading an undo file.  The links are set
+    /// later.
+    pub(crate) fn push_header(&mut self, header: UndoHeader) -> Id {
+        self.headers.push(Some(header));
+        self.headers.len() - 1
+    }
+
+    pub(crate) fn header_ids(&self) -> impl Iterator<Item = Id> + '_ {
+        self.headers.iter().enumerate().filter(|(_, h)| h.is_some()).map(|(id, _)| id)
+    }
+
+    pub(crate) fn header(&self, id: Id) -> &UndoHeader {
+        self.h(id)
+    }
+
+    pub(crate) fn header_mut(&mut self, id: Id) -> &mut UndoHeader {
+        self.hm(id)
+    }
+
+    /// Save lines `top + 1` to `bot - 1` before changing them, like Vim's
+    /// u_save().  Starts a new undo block when the last one was synced.
+    /// Returns false when the line numbers are invalid.
+    pub fn save(&mut self, buf: &impl UndoBuffer, top: usize, bot: usize) -> bool {
+        let line_count = buf.line_count();
+        if top >= bot || bot > line_count + 1 {
+            return false;
+        }
+        if self.synced {
+            if !self.new_header(buf) {
+                return true;
+            }
+        } else {
+            if self.undolevels < 0 {
+                return true;
+            }
+            self.resolve_bot(line_count);
+        }
+        let Some(newhead) = self.newhead else {
+            return true;
+        };
+        let mut entry = UndoEntry {
+            top: top as i64,
+            bot: 0,
+            lcount: 0,
+            lines: (top + 1..bot).map(|lnum| buf.line(lnum).to_string()).collect(),
+        };
+        let getbot = bot <= line_count;
+        if getbot {
+            entry.lcount = line_count as i64;
+        }
+        let header = self.hm(newhead);
+        header.entries.insert(0, entry);
+        header.getbot = getbot;
+        self.synced = false;
+        true
+    }
+
+    /// Start a new undo block for a change of `buf`.  Returns false when
+    /// undo is disabled.
+    fn new_header(&mut self, buf: &impl UndoBuffer) -> bool {
+        // A change after undo: the undone blocks become an alternative
+        // branch.
+        let mut old_curhead = self.curhead.take();
+        if let Some(cur) = old_curhead {
+            self.newhead = self.h(cur).next;
+        }
+        while self.numhead as i64 > self.undolevels {
+            let Some(mut free) = self.oldhead else {
+                break;
+            };
+            if Some(free) == old_curhead {
+                self.free_branch(free, &mut old_curhead);
+            } else if self.h(free).alt_next.is_none() {
+                self.free_header(free, &mut old_curhead);
+            } else {
+                while let Some(alt) = self.h(free).alt_next {
+                    free = alt;
+                }
+                self.free_branch(free, &mut old_curhead);
+            }
+        }
+        if self.undolevels < 0 {
+            if let Some(cur) = old_curhead {
+                self.free_branch(cur, &mut None);
+            }
+            self.synced = false;
+            return false;
+        }
+
+        self.seq_last += 1;
+        self.seq_cur = self.seq_last;
+        let time = self.now();
+        self.time_cur = time + 1;
+        let (lnum, col) = buf.cursor();
+        let flags = if buf.modified() { UH_CHANGED } else { 0 } | if buf.line_count() == 0 { UH_EMPTYBUF } else { 0 };
+        let id = self.push_header(UndoHeader {
+            next: self.newhead,
+            prev: None,
+            alt_next: old_curhead,
+            alt_prev: None,
+            seq: self.seq_last,
+            entries: Vec::new(),
+            cursor: (lnum as i64, col as i64),
+            flags,
+            time,
+            save_nr: 0,
+            walk: 0,
+            getbot: false,
+        });
+        if let Some(cur) = old_curhead {
+            let alt_prev = self.h(cur).alt_prev;
+            self.hm(id).alt_prev = alt_prev;
+            if let Some(alt_prev) = alt_prev {
+                self.hm(alt_prev).alt_next = Some(id);
+            }
+            self.hm(cur).alt_prev = Some(id);
+            if self.oldhead == Some(cur) {
+                self.oldhead = Some(id);
+            }
+        }
+        if let Some(newhead) = self.newhead {
+            self.hm(newhead).prev = Some(id);
+        }
+        self.newhead = Some(id);
+        if self.oldhead.is_none() {
+            self.oldhead = Some(id);
+        }
+        self.numhead += 1;
+        true
+    }
+
+    /// Free header `id`, and the alternatives of it, which cannot be reached
+    /// anymore.
+    fn free_header(&mut self, id: Id, keep: &mut Option<Id>) {
+        if let Some(alt) = self.h(id).alt_next {
+            self.free_branch(alt, keep);
+        }
+        if let Some(alt_prev) = self.h(id).alt_prev {
+            self.hm(alt_prev).alt_next = None;
+        }
+        let (next, prev) = (self.h(id).next, self.h(id).prev);
+        match next {
+            None => self.oldhead = prev,
+            Some(next) => self.hm(next).prev = prev,
+        }
+        match prev {
+            None => self.newhead = next,
+            Some(prev) => {
+                let mut alt = Some(prev);
+                while let Some(a) = alt {
+                    self.hm(a).next = next;
+                    alt = self.h(a).alt_next;
+                }
+            }
+        }
+        self.free_entries(id, keep);
+    }
+
+    /// Free header `id` and all the headers below it.
+    fn free_branch(&mut self, id: Id, keep: &mut Option<Id>) {
+        if Some(id) == self.oldhead {
+            while let Some(old) = self.oldhead {
+                self.free_header(old, keep);
+            }
+            return;
+        }
+        if let Some(alt_prev) = self.h(id).alt_prev {
+            self.hm(alt_prev).alt_next = None;
+        }
+        let mut next = Some(id);
+        while let Some(free) = next {
+            if let Some(alt) = self.h(free).alt_next {
+                self.free_branch(alt, keep);
+            }
+            next = self.h(free).prev;
+            self.free_entries(free, keep);
+        }
+    }
+
+    fn free_entries(&mut self, id: Id, keep: &mut Option<Id>) {
+        if self.curhead == Some(id) {
+            self.curhead = None;
+        }
+        if self.newhead == Some(id) {
+            self.newhead = None;
+        }
+        if *keep == Some(id) {
+            *keep = None;
+        }
+        self.headers[id] = None;
+        self.numhead -= 1;
+    }
+
+    /// Compute the `bot` of the entry saved last, now that the change is
+    /// done.
+    fn resolve_bot(&mut self, line_count: usize) {
+        let Some(newhead) = self.newhead else {
+            return;
+        };
+        let header = self.hm(newhead);
+        if !header.getbot {
+            return;
+        }
+        header.getbot = false;
+        let line_count = line_count as i64;
+        let entry = &mut header.entries[0];
+        let extra = line_count - entry.lcount;
+        entry.bot = entry.top + entry.lines.len() as i64 + 1 + extra;
+        if entry.bot < 1 || entry.bot > line_count {
+            entry.bot = entry.top + 1;
+        }
+    }
+
+    /// End the undo block, the next change starts a new one.
+    pub fn sync(&mut self, buf: &impl UndoBuffer) {
+        if self.synced {
+            return;
+        }
+        if self.undolevels >= 0 {
+            self.resolve_bot(buf.line_count());
+        }
+        self.synced = true;
+    }
+
+    /// `:undojoin`: add the next change to the last undo block.
+    pub fn undojoin(&mut self) -> Result<(), String> {
+        if self.newhead.is_none() {
+            return Ok(());
+        }
+        if self.curhead.is_some() {
+            return Err("E790: Undojoin is not allowed after undo".to_string());
+        }
+        if self.synced && self.undolevels >= 0 {
+            self.synced = false;
+        }
+        Ok(())
+    }
+
+    /// Remember that the buffer was written, for `:earlier 1f`.
+    pub fn set_saved(&mut self) {
+        self.save_nr_last += 1;
+        self.save_nr_cur = self.save_nr_last;
+        let header = match self.curhead {
+            Some(cur) => self.h(cur).next,
+            None => self.newhead,
+        };
+        if let Some(header) = header {
+            self.hm(header).save_nr = self.save_nr_last;
+        }
+    }
+
+    /// `u`: undo `count` undo blocks.  Returns the message.
+    pub fn undo(&mut self, buf: &mut impl UndoBuffer, count: usize) -> Result<String, String> {
+        self.doit(buf, count, true)
+    }
+
+    /// CTRL-R: redo `count` undo blocks.  Returns the message.
+    pub fn redo(&mut self, buf: &mut impl UndoBuffer, count: usize) -> Result<String, String> {
+        self.doit(buf, count, false)
+    }
+
+    fn doit(&mut self, buf: &mut impl UndoBuffer, mut count: usize, undo: bool) -> Result<String, String> {
+        if !self.synced {
+            self.sync(buf);
+            count = 1;
+        }
+        self.counts = (0, 0);
+        for i in 0..count.max(1) {
+            if undo {
+                self.curhead = match self.curhead {
+                    None => self.newhead,
+                    Some(cur) if self.undolevels > 0 => self.h(cur).next,
+                    cur => cur,
+                };
+                if self.numhead == 0 || self.curhead.is_none() {
+                    self.curhead = self.oldhead;
+                    if i == 0 {
+                        return Ok("Already at oldest change".to_string());
+                    }
+                    break;
+                }
+                self.undoredo(buf, true)?;
+            } else {
+                let Some(cur) = self.curhead.filter(|_| self.undolevels > 0) else {
+                    if i == 0 {
+                        return Ok("Already at newest change".to_string());
+                    }
+                    break;
+                };
+                self.undoredo(buf, false)?;
+                if self.h(cur).prev.is_none() {
+                    self.newhead = Some(cur);
+                }
+                self.curhead = self.h(cur).prev;
+            }
+        }
+        Ok(self.undo_end(undo, false))
+    }
+
+    /// Undo or redo `curhead`: swap the text of each entry with the lines
+    /// in the buffer.
+    fn undoredo(&mut self, buf: &mut impl UndoBuffer, undo: bool) -> Result<(), String> {
+        let cur = self.curhead.expect("no header to undo");
+        let new_flags = if buf.modified() { UH_CHANGED } else { 0 } | if buf.line_count() == 0 { UH_EMPTYBUF } else { 0 };
+        let header = self.h(cur);
+        let (old_flags, uh_cursor) = (header.flags, header.cursor);
+        let mut entries = std::mem::take(&mut self.hm(cur).entries);
+        let mut newlist = Vec::with_capacity(entries.len());
+        let mut newlnum: Option<i64> = None;
+        let mut cursor_lnum: Option<i64> = None;
+        let entry_count = entries.len();
+        for (i, mut entry) in entries.drain(..).enumerate() {
+            let line_count = buf.line_count() as i64;
+            let top = entry.top;
+            let bot = if entry.bot == 0 { line_count + 1 } else { entry.bot };
+            if top > line_count || top >= bot || bot > line_count + 1 {
+                newlist.insert(0, entry);
+                let header = self.hm(cur);
+                header.entries = newlist;
+                return Err("E438: u_undo: line numbers wrong".to_string());
+            }
+            let oldsize = bot - top - 1;
+            let newsize = entry.lines.len() as i64;
+
+            // Put the cursor on the first line that changes, or where it was
+            // before the change when that is in this block.
+            if newlnum.is_none_or(|n| top < n) {
+                let lnum = uh_cursor.0;
+                if lnum >= top && lnum <= top + newsize + 1 {
+                    newlnum = Some(lnum - 1);
+                    cursor_lnum = Some(lnum);
+                } else {
+                    let same = (0..newsize.min(oldsize))
+                        .take_while(|&k| entry.lines[k as usize] == buf.line((top + 1 + k) as usize))
+                        .count() as i64;
+                    if same == newsize && newlnum.is_none() && i + 1 == entry_count {
+                        newlnum = Some(top);
+                        cursor_lnum = Some(top + 1);
+                    } else if same < newsize {
+                        newlnum = Some(top + same);
+                        cursor_lnum = Some(top + same + 1);
+                    }
+                }
+            }
+
+            let lines = std::mem::take(&mut entry.lines);
+            entry.lines = buf.replace_lines(top as usize, oldsize as usize, lines);
+            self.counts.0 += oldsize;
+            self.counts.1 += newsize;
+            entry.bot = top + newsize + 1;
+            newlist.insert(0, entry);
+        }
+        let header = self.hm(cur);
+        header.entries = newlist;
+        header.flags = new_flags;
+        buf.set_modified(old_flags & UH_CHANGED != 0);
+
+        let line_count = buf.line_count().max(1) as i64;
+        if let Some(lnum) = cursor_lnum {
+            let lnum = lnum.clamp(1, line_count);
+            let col = if lnum == uh_cursor.0 { uh_cursor.1 } else { 1 };
+            buf.set_cursor(lnum as usize, col.max(1) as usize);
+        } else {
+            let (lnum, col) = buf.cursor();
+            buf.set_cursor((lnum as i64).clamp(1, line_count) as usize, col);
+        }
+
+        // Remember where we are for "g-" and ":earlier 10s".
+        let header = self.h(cur);
+        let (seq, next, save_nr, time) = (header.seq, header.next, header.save_nr, header.time);
+        self.seq_cur = seq;
+        if undo {
+            // Just above the undone change, so that ":earlier 1s" works.
+            self.seq_cur = next.map_or(0, |next| self.h(next).seq);
+        }
+        if save_nr != 0 {
+            self.save_nr_cur = if undo { save_nr - 1 } else { save_nr };
+        }
+        self.time_cur = time;
+        Ok(())
+    }
+
+    /// The message after undo or redo: "1 line less; before #3  2 seconds
+    /// ago".
+    fn undo_end(&self, mut did_undo: bool, absolute: bool) -> String {
+        let (old, new) = self.counts;
+        let oldcount = old - new;
+        let (count, what) = match oldcount {
+            -1 => (1, "more line"),
+            n if n < 0 => (-n, "more lines"),
+            1 => (1, "line less"),
+            n if n > 1 => (n, "fewer lines"),
+            _ => (new, if new == 1 { "change" } else { "changes" }),
+        };
+        let header = match self.curhead {
+            Some(cur) if absolute && self.h(cur).next.is_some() => {
+                did_undo = false;
+                self.h(cur).next
+            }
+            Some(cur) if did_undo => Some(cur),
+            Some(cur) => self.h(cur).next,
+            None => self.newhead,
+        };
+        let time = header.map_or_else(String::new, |id| self.time_string(self.h(id).time));
+        format!(
+            "{} {}; {} #{}  {}",
+            count,
+            what,
+            if did_undo { "before" } else { "after" },
+            header.map_or(0, |id| self.h(id).seq),
+            time
+        )
+    }
+
+    /// `time` for a message: how many seconds ago, or the time of day.
+    pub(crate) fn time_string(&self, time: i64) -> String {
+        let seconds = self.now() - time;
+        if seconds >= 100 {
+            let format = if seconds < 60 * 60 * 12 { "%H:%M:%S" } else { "%Y/%m/%d %H:%M:%S" };
+            return strftime_local(time, format);
+        }
+        format!("{} second{} ago", seconds, if seconds == 1 { "" } else { "s" })
+    }
+
+    fn unvisited(&self, id: Option<Id>, mark: u64, nomark: u64) -> Option<Id> {
+        id.filter(|&id| self.h(id).walk != mark && self.h(id).walk != nomark)
+    }
+
+    /// Go to another state in the tree, like Vim's undo_time(): `g-` and
+    /// `g+` with [`StepUnit::Changes`], `:earlier` and `:later` and, with
+    /// `absolute`, `:undo N`.  Returns the message.
+    pub fn undo_time(
+        &mut self,
+        buf: &mut impl UndoBuffer,
+        step: i64,
+        unit: StepUnit,
+        absolute: bool,
+    ) -> Result<String, String> {
+        if !self.synced {
+            self.sync(buf);
+        }
+        self.counts = (0, 0);
+        let mut dosec = unit == StepUnit::Seconds;
+        let mut dofile = unit == StepUnit::Writes;
+        let mut target;
+        let mut closest;
+        if absolute {
+            target = step;
+            closest = -1;
+        } else {
+            if dosec {
+                target = self.time_cur + step;
+            } else if dofile {
+                if step < 0 {
+                    // Changes after the last write count as one write, so
+                    // that ":earlier 1f" undoes them.
+                    let header = match self.curhead {
+                        Some(cur) => self.h(cur).next,
+                        None => self.newhead,
+                    };
+                    target = if header.is_some_and(|id| self.h(id).save_nr != 0) {
+                        self.save_nr_cur + step
+                    } else {
+                        self.save_nr_cur + step + 1
+                    };
+                    if target <= 0 {
+                        // Before the first write: before the oldest change.
+                        dofile = false;
+                    }
+                } else {
+                    target = self.save_nr_cur + step;
+                    if target > self.save_nr_last {
+                        // After the last write: after the newest change.
+                        target = self.seq_last + 1;
+                        dofile = false;
+                    }
+                }
+            } else {
+                target = self.seq_cur + step;
+            }
+            if step < 0 {
+                target = target.max(0);
+                closest = -1;
+            } else {
+                closest = if dosec {
+                    self.now() + 1
+                } else if dofile {
+                    self.save_nr_last + 2
+                } else {
+                    self.seq_last + 2
+                };
+                if target >= closest {
+                    target = closest - 1;
+                }
+            }
+        }
+        let closest_start = closest;
+        let mut closest_seq = self.seq_cur;
+        let mut above = false;
+        let mut did_undo = true;
+        let mut mark = 0;
+        let mut nomark = 0;
+        let mut found = None;
+
+        if target != 0 {
+            // Search for "target", remembering the closest header.  When it
+            // is not found search for the closest one in a second round, by
+            // sequence number, because several may have the same time.
+            for _round in 1..=2 {
+                self.lastmark += 2;
+                mark = self.lastmark - 1;
+                nomark = self.lastmark;
+                let first_round = _round == 1;
+                let mut uhp = self.curhead.or(self.newhead);
+                while let Some(id) = uhp {
+                    self.hm(id).walk = mark;
+                    let header = self.h(id);
+                    let val = if dosec {
+                        header.time
+                    } else if dofile {
+                        header.save_nr
+                    } else {
+                        header.seq
+                    };
+                    if first_round && !(dofile && val == 0) {
+                        let seq = header.seq;
+                        let right_direction = if step < 0 { seq <= self.seq_cur } else { seq > self.seq_cur };
+                        let better = if dosec && val == closest {
+                            if step < 0 {
+                                seq < closest_seq
+                            } else {
+                                seq > closest_seq
+                            }
+                        } else {
+                            closest == closest_start || (val - target).abs() <= (closest - target).abs()
+                        };
+                        if right_direction && better {
+                            closest = val;
+                            closest_seq = seq;
+                        }
+                    }
+
+                    // Stop at a match, but for a time look for the best
+                    // sequence number.
+                    if target == val && !dosec {
+                        target = header.seq;
+                        break;
+                    }
+
+                    if let Some(prev) = self.unvisited(header.prev, mark, nomark) {
+                        // Go down in the tree.
+                        uhp = Some(prev);
+                    } else if let Some(alt) = self.unvisited(header.alt_next, mark, nomark) {
+                        // Go to an alternate branch.
+                        uhp = Some(alt);
+                    } else if let Some(next) =
+                        self.unvisited(header.next, mark, nomark).filter(|_| header.alt_prev.is_none())
+                    {
+                        // Go up at the start of the alternate branches.
+                        if Some(id) == self.curhead {
+                            self.hm(id).walk = nomark;
+                        }
+                        uhp = Some(next);
+                    } else {
+                        // Backtrack, nothing to find here.
+                        let header = self.hm(id);
+                        header.walk = nomark;
+                        uhp = header.alt_prev.or(header.next);
+                    }
+                }
+                if uhp.is_some() {
+                    found = uhp;
+                    break;
+                }
+                if absolute {
+                    return Err(format!("E830: Undo number {} not found", step));
+                }
+                if closest == closest_start {
+                    let msg = if step < 0 { "Already at oldest change" } else { "Already at newest change" };
+                    return Ok(msg.to_string());
+                }
+                target = closest_seq;
+                dosec = false;
+                dofile = false;
+                if step < 0 {
+                    // Stop above the header.
+                    above = true;
+                }
+            }
+        }
+
+        if found.is_some() || target == 0 {
+            // First go up the tree as much as needed.
+            loop {
+                let header = match self.curhead {
+                    None => self.newhead,
+                    Some(cur) => self.h(cur).next,
+                };
+                let Some(id) = header else {
+                    break;
+                };
+                if (target > 0 && self.h(id).walk != mark) || (self.h(id).seq == target && !above) {
+                    break;
+                }
+                self.curhead = Some(id);
+                self.undoredo(buf, true)?;
+                if target > 0 {
+                    // Don't go back down here.
+                    self.hm(id).walk = nomark;
+                }
+            }
+
+            // Then go down, redoing, taking the marked branches.
+            while let Some(mut id) = self.curhead.filter(|_| target > 0) {
+                while let Some(alt) = self.h(id).alt_prev.filter(|&alt| self.h(alt).walk == mark) {
+                    id = alt;
+                }
+                let mut last = id;
+                while let Some(alt) = self.h(last).alt_next.filter(|&alt| self.h(alt).walk == mark) {
+                    last = alt;
+                }
+                if last != id {
+                    // Make the branch used the first alternative, so that
+                    // it is what gets redone next.
+                    while let Some(alt) = self.h(id).alt_prev {
+                        id = alt;
+                    }
+                    let (last_prev, last_next) = (self.h(last).alt_prev, self.h(last).alt_next);
+                    if let Some(next) = last_next {
+                        self.hm(next).alt_prev = last_prev;
+                    }
+                    if let Some(prev) = last_prev {
+                        self.hm(prev).alt_next = last_next;
+                    }
+                    self.hm(last).alt_prev = None;
+                    self.hm(last).alt_next = Some(id);
+                    self.hm(id).alt_prev = Some(last);
+                    if self.oldhead == Some(id) {
+                        self.oldhead = Some(last);
+                    }
+                    id = last;
+                    if let Some(next) = self.h(id).next {
+                        self.hm(next).prev = Some(id);
+                    }
+                }
+                self.curhead = Some(id);
+                if self.h(id).walk != mark {
+                    // Must have reached the target.
+                    break;
+                }
+                if self.h(id).seq == target && above {
+                    // Going back in time, stop above the closest header.
+                    self.seq_cur = target - 1;
+                    break;
+                }
+                self.undoredo(buf, false)?;
+                let prev = self.h(id).prev;
+                if prev.is_none() {
+                    self.newhead = Some(id);
+                }
+                self.curhead = prev;
+                did_undo = false;
+                if self.h(id).seq == target {
+                    break;
+                }
+                if prev.is_none_or(|prev| self.h(prev).walk != mark) {
+                    return Err("E685: Internal error: undo_time()".to_string());
+                }
+            }
+        }
+        Ok(self.undo_end(did_undo, absolute))
+    }
+
+    /// The headers for undotree(), oldest first, with their alternatives.
+    pub fn entries(&self) -> Vec<TreeEntry> {
+        self.branch_entries(self.oldhead)
+    }
+
+    fn branch_entries(&self, first: Option<Id>) -> Vec<TreeEntry> {
+        let mut entries = Vec::new();
+        let mut uhp = first;
+        while let Some(id) = uhp {
+            let header = self.h(id);
+            entries.push(TreeEntry {
+                seq: header.seq,
+                time: header.time,
+                newhead: self.newhead == Some(id),
+                curhead: self.curhead == Some(id),
+                save: header.save_nr,
+                alt: self.branch_entries(header.alt_next),
+            });
+            uhp = header.prev;
+        }
+        entries
+    }
+}
+
+/// `time` as local time in `format`, for strftime().
+fn strftime_local(time: i64, format: &str) -> String {
+    let Ok(format) = std::ffi::CString::new(format) else {
+        return String::new();
+    };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let time = time as libc::time_t;
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return String::new();
+    }
+    let mut buf = [0 as libc::c_char; 64];
+    let len = unsafe { libc::strftime(buf.as_mut_ptr(), buf.len(), format.as_ptr(), &tm) };
+    let bytes: Vec<u8> = buf[..len].iter().map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split(' ').map(str::to_string).collect()
+    }
+
+    /// Replace line `lnum` of `buf` as one change.
+    fn change(tree: &mut UndoTree, buf: &mut Vec<String>, lnum: usize, text: &str) {
+        tree.sync(buf);
+        assert!(tree.save(buf, lnum - 1, lnum + 1));
+        buf[lnum - 1] = text.to_string();
+    }
+
+    #[test]
+    fn undo_redo_and_branches() {
+        let mut tree = UndoTree::new();
+        tree.set_time_for_testing(Some(1000));
+        let mut buf = lines("one two");
+        change(&mut tree, &mut buf, 1, "ONE");
+        change(&mut tree, &mut buf, 2, "TWO");
+        tree.sync(&buf);
+        assert_eq!(tree.undo(&mut buf, 1).unwrap(), "1 change; before #2  0 seconds ago");
+        assert_eq!(buf, lines("ONE two"));
+        assert_eq!(tree.redo(&mut buf, 1).unwrap(), "1 change; after #2  0 seconds ago");
+        assert_eq!(tree.undo(&mut buf, 5).unwrap(), "2 changes; before #1  0 seconds ago");
+        assert_eq!(buf, lines("one two"));
+        assert_eq!(tree.undo(&mut buf, 1).unwrap(), "Already at oldest change");
+
+        // A change after undo starts a new branch, g- and g+ go through all
+        // states in the order they were made.
+        change(&mut tree, &mut buf, 1, "uno");
+        tree.sync(&buf);
+        assert_eq!(tree.seq_cur(), 3);
+        assert_eq!(tree.redo(&mut buf, 1).unwrap(), "Already at newest change");
+        tree.undo_time(&mut buf, -1, StepUnit::Changes, false).unwrap();
+        assert_eq!(buf, lines("ONE TWO"));
+        tree.undo_time(&mut buf, -1, StepUnit::Changes, false).unwrap();
+        assert_eq!(buf, lines("ONE two"));
+        tree.undo_time(&mut buf, 2, StepUnit::Changes, false).unwrap();
+        assert_eq!(buf, lines("uno two"));
+        assert_eq!(tree.undo_time(&mut buf, 1, StepUnit::Changes, false).unwrap(), "Already at newest change");
+        tree.undo_time(&mut buf, 2, StepUnit::Changes, true).unwrap();
+        assert_eq!(buf, lines("ONE TWO"));
+        tree.undo_time(&mut buf, 0, StepUnit::Changes, true).unwrap();
+        assert_eq!(buf, lines("one two"));
+        assert_eq!(tree.undo_time(&mut buf, 7, StepUnit::Changes, true), Err("E830: Undo number 7 not found".to_string()));
+
+        // Going to a branch makes it the first alternative.
+        let entries = tree.entries();
+        assert_eq!(entries.iter().map(|e| e.seq).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(entries[0].alt.iter().map(|e| e.seq).collect::<Vec<_>>(), [3]);
+        assert!(entries[0].curhead && !entries[0].alt[0].newhead);
+    }
+
+    #[test]
+    fn lines_inserted_and_deleted() {
+        let mut tree = UndoTree::new();
+        let mut buf = lines("a b c");
+        // Insert two lines below "a", then delete "c".
+        assert!(tree.save(&buf, 1, 2));
+        buf.splice(1..1, lines("x y"));
+        tree.sync(&buf);
+        assert!(tree.save(&buf, 4, 6));
+        buf.remove(4);
+        tree.sync(&buf);
+        assert_eq!(buf, lines("a x y b"));
+        assert!(tree.undo(&mut buf, 1).unwrap().starts_with("1 more line; before #2"));
+        assert!(tree.undo(&mut buf, 1).unwrap().starts_with("2 fewer lines; before #1"));
+        assert_eq!(buf, lines("a b c"));
+        tree.redo(&mut buf, 2).unwrap();
+        assert_eq!(buf, lines("a x y b"));
+
+        // Joined changes are undone together.
+        assert!(tree.save(&buf, 0, 2));
+        buf[0] = "A".to_string();
+        tree.sync(&buf);
+        tree.undojoin().unwrap();
+        assert!(tree.save(&buf, 1, 3));
+        buf[1] = "X".to_string();
+        tree.sync(&buf);
+        assert_eq!(tree.len(), 3);
+        tree.undo(&mut buf, 1).unwrap();
+        assert_eq!(buf, lines("a x y b"));
+        assert_eq!(tree.undojoin(), Err("E790: Undojoin is not allowed after undo".to_string()));
+    }
+
+    #[test]
+    fn earlier_and_later_by_time_and_writes() {
+        let mut tree = UndoTree::new();
+        let mut buf = lines("0");
+        for (n, time) in [(1, 100), (2, 200), (3, 300), (4, 400)] {
+            tree.set_time_for_testing(Some(time));
+            change(&mut tree, &mut buf, 1, &n.to_string());
+            if n == 2 {
+                tree.set_saved();
+            }
+        }
+        tree.set_time_for_testing(Some(450));
+        tree.undo_time(&mut buf, -150, StepUnit::Seconds, false).unwrap();
+        assert_eq!(buf, lines("2"));
+        tree.undo_time(&mut buf, 10, StepUnit::Seconds, false).unwrap();
+        assert_eq!(buf, lines("3"));
+        tree.undo_time(&mut buf, -1, StepUnit::Writes, false).unwrap();
+        assert_eq!(buf, lines("2"));
+        tree.undo_time(&mut buf, -1, StepUnit::Writes, false).unwrap();
+        assert_eq!(buf, lines("0"));
+        tree.undo_time(&mut buf, 1, StepUnit::Writes, false).unwrap();
+        assert_eq!(buf, lines("2"));
+        tree.undo_time(&mut buf, 1, StepUnit::Writes, false).unwrap();
+        assert_eq!(buf, lines("4"));
+
+        assert_eq!(parse_step("10m"), Some((600, StepUnit::Seconds)));
+        assert_eq!(parse_step("3f"), Some((3, StepUnit::Writes)));
+        assert_eq!(parse_step(""), Some((1, StepUnit::Changes)));
+        assert_eq!(parse_step("3x"), None);
+
+        // Old blocks are dropped beyond 'undolevels'.
+        tree.set_undolevels(2);
+        change(&mut tree, &mut buf, 1, "5");
+        tree.sync(&buf);
+        assert_eq!(tree.len(), 3);
+        tree.undo(&mut buf, 10).unwrap();
+        assert_eq!(buf, lines("2"));
+    }
+}
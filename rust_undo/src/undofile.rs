@@ -0,0 +1,498 @@
+//! Persistent undo: writing the undo tree to a file and reading it back,
+//! in the format of Vim's undo files.
+//!
+//! All numbers are big-endian.  The file starts with a header holding the
+//! hash of the text, so that the undo information is only used for the text
+//! it was written for, followed by the undo headers and their entries.
+//!
+//! The undo file of an encrypted buffer is encrypted with the same key:
+//! after the start magic and the crypt version comes the rest of the file,
+//! encrypted like a file with 'cryptmethod'.
+
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::tree::{UndoBuffer, UndoEntry, UndoHeader, UndoTree};
+
+const UF_START_MAGIC: &[u8] = b"Vim\x9fUnDo\xe5";
+const UF_VERSION: u16 = 2;
+const UF_VERSION_CRYPT: u16 = 0x8002;
+const UF_HEADER_MAGIC: u16 = 0x5fd0;
+const UF_HEADER_END_MAGIC: u16 = 0xe7aa;
+const UF_ENTRY_MAGIC: u16 = 0xf518;
+const UF_ENTRY_END_MAGIC: u16 = 0x3581;
+const UF_LAST_SAVE_NR: u8 = 1;
+const UHP_SAVE_NR: u8 = 1;
+/// The named marks and visual area kept with each header.
+const NMARKS: usize = 26;
+
+/// A failure to read or write an undo file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UndoFileError {
+    /// An error message, "E8xx: ...".
+    Error(String),
+    /// Not an error, the file is skipped with this message.
+    Message(String),
+}
+
+impl std::fmt::Display for UndoFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UndoFileError::Error(msg) | UndoFileError::Message(msg) => f.write_str(msg),
+        }
+    }
+}
+
+/// The undo file for `fname` in the first usable directory of 'undodir'.
+/// "." is the directory of the file, using ".name.un~"; for other
+/// directories the full path is used with "/" replaced by "%".  When
+/// `reading` the file must exist, otherwise the directory.
+pub fn undo_file_name(fname: &Path, undodir: &str, reading: bool) -> Option<PathBuf> {
+    let full = std::path::absolute(fname).ok()?;
+    for dir in undodir.split(',').filter(|d| !d.is_empty()) {
+        let path = if dir == "." {
+            let name = full.file_name()?.to_string_lossy();
+            full.with_file_name(format!(".{}.un~", name))
+        } else {
+            let dir = Path::new(dir);
+            if !dir.is_dir() {
+                continue;
+            }
+            dir.join(full.to_string_lossy().replace('/', "%"))
+        };
+        if !reading || path.exists() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// The hash of the text: SHA-256 of each line followed by a NUL.
+pub fn text_hash(buf: &impl UndoBuffer) -> [u8; 32] {
+    let mut data = Vec::new();
+    for lnum in 1..=buf.line_count() {
+        data.extend_from_slice(buf.line(lnum).as_bytes());
+        data.push(0);
+    }
+    rust_sha256::sha256_digest(&data)
+}
+
+fn put2(out: &mut Vec<u8>, n: u16) {
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+fn put4(out: &mut Vec<u8>, n: i64) {
+    out.extend_from_slice(&(n as i32).to_be_bytes());
+}
+
+fn put8(out: &mut Vec<u8>, n: i64) {
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+/// The sequence number of a linked header, zero for none.
+fn seq_of(tree: &UndoTree, id: Option<usize>) -> i64 {
+    id.map_or(0, |id| tree.header(id).seq)
+}
+
+fn serialize_header(tree: &UndoTree, id: usize, out: &mut Vec<u8>) {
+    let header = tree.header(id);
+    put2(out, UF_HEADER_MAGIC);
+    put4(out, seq_of(tree, header.next));
+    put4(out, seq_of(tree, header.prev));
+    put4(out, seq_of(tree, header.alt_next));
+    put4(out, seq_of(tree, header.alt_prev));
+    put4(out, header.seq);
+    // Cursor lnum, col and coladd, then the virtual column.
+    put4(out, header.cursor.0);
+    put4(out, header.cursor.1 - 1);
+    put4(out, 0);
+    put4(out, 0);
+    put2(out, header.flags);
+    // Named marks and the visual area are not kept.
+    out.extend(std::iter::repeat_n(0, NMARKS * 12 + 2 * 12 + 8));
+    put8(out, header.time);
+    if header.save_nr != 0 {
+        out.push(4);
+        out.push(UHP_SAVE_NR);
+        put4(out, header.save_nr);
+    }
+    out.push(0);
+    for entry in header.entries.iter() {
+        put2(out, UF_ENTRY_MAGIC);
+        put4(out, entry.top);
+        put4(out, entry.bot);
+        put4(out, entry.lcount);
+        put4(out, entry.lines.len() as i64);
+        for line in entry.lines.iter() {
+            put4(out, line.len() as i64);
+            out.extend_from_slice(line.as_bytes());
+        }
+    }
+    put2(out, UF_ENTRY_END_MAGIC);
+}
+
+/// The undo file contents for `tree` and the text in `buf`.
+pub fn serialize(tree: &UndoTree, buf: &impl UndoBuffer) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(UF_START_MAGIC);
+    put2(&mut out, UF_VERSION);
+    out.extend_from_slice(&text_hash(buf));
+    put4(&mut out, buf.line_count() as i64);
+    // The "U" line is not kept: length zero, lnum and col.
+    put4(&mut out, 0);
+    put4(&mut out, 0);
+    put4(&mut out, 0);
+    put4(&mut out, seq_of(tree, tree.oldhead));
+    put4(&mut out, seq_of(tree, tree.newhead));
+    put4(&mut out, seq_of(tree, tree.curhead));
+    put4(&mut out, tree.numhead as i64);
+    put4(&mut out, tree.seq_last);
+    put4(&mut out, tree.seq_cur);
+    put8(&mut out, tree.time_cur);
+    out.push(4);
+    out.push(UF_LAST_SAVE_NR);
+    put4(&mut out, tree.save_nr_last);
+    out.push(0);
+    for id in tree.header_ids() {
+        serialize_header(tree, id, &mut out);
+    }
+    put2(&mut out, UF_HEADER_END_MAGIC);
+    out
+}
+
+/// Write the undo file `path` for `tree`, like `:wundo`, encrypted with the
+/// passphrase `key` when it is not empty.  Without `force` an existing file
+/// is only overwritten when it is an undo file.
+pub fn write_undo_file(
+    tree: &UndoTree,
+    buf: &impl UndoBuffer,
+    path: &Path,
+    force: bool,
+    key: Option<&str>,
+) -> Result<(), UndoFileError> {
+    if !force && path.exists() {
+        let mut start = [0u8; UF_START_MAGIC.len()];
+        let is_undo = std::fs::File::open(path)
+            .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut start))
+            .is_ok_and(|_| start == UF_START_MAGIC);
+        if !is_undo {
+            return Err(UndoFileError::Message(format!(
+                "Will not overwrite, this is not an undo file: {}",
+                path.display()
+            )));
+        }
+    }
+    let mut data = serialize(tree, buf);
+    if let Some(key) = key.filter(|key| !key.is_empty()) {
+        let start = UF_START_MAGIC.len() + 2;
+        let encrypted = rust_crypt::encrypt(rust_crypt::CryptMethod::default(), key, &data[start..])
+            .map_err(|err| UndoFileError::Error(err.to_string()))?;
+        data.truncate(UF_START_MAGIC.len());
+        put2(&mut data, UF_VERSION_CRYPT);
+        data.extend(encrypted);
+    }
+    let _ = std::fs::remove_file(path);
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|_| UndoFileError::Error(format!("E828: Cannot open undo file for writing: {}", path.display())))?;
+    file.write_all(&data)
+        .and_then(|_| file.sync_all())
+        .map_err(|_| UndoFileError::Error(format!("E829: Write error in undo file: {}", path.display())))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> Option<&[u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn get1(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn get2(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn get4(&mut self) -> Option<i64> {
+        self.bytes(4).map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as i64)
+    }
+
+    fn get8(&mut self) -> Option<i64> {
+        self.bytes(8).map(|b| i64::from_be_bytes(b.try_into().unwrap()))
+    }
+
+    /// Optional fields: a length byte, an id and the value, until a zero
+    /// length.  Returns the value of field `id`.
+    fn optional_fields(&mut self, id: u8) -> Option<i64> {
+        let mut value = 0;
+        loop {
+            let len = self.get1()?;
+            if len == 0 {
+                return Some(value);
+            }
+            let field = self.get1()?;
+            if field == id && len == 4 {
+                value = self.get4()?;
+            } else {
+                self.bytes(len as usize)?;
+            }
+        }
+    }
+}
+
+/// Links of a header by sequence number, resolved when all are read.
+struct Links {
+    next: i64,
+    prev: i64,
+    alt_next: i64,
+    alt_prev: i64,
+}
+
+fn corrupted(what: &str, path: &Path) -> UndoFileError {
+    UndoFileError::Error(format!("E825: Corrupted undo file ({}): {}", what, path.display()))
+}
+
+fn read_header(r: &mut Reader, path: &Path) -> Result<(UndoHeader, Links), UndoFileError> {
+    let truncated = || corrupted("truncated", path);
+    let links = Links {
+        next: r.get4().ok_or_else(truncated)?,
+        prev: r.get4().ok_or_else(truncated)?,
+        alt_next: r.get4().ok_or_else(truncated)?,
+        alt_prev: r.get4().ok_or_else(truncated)?,
+    };
+    let seq = r.get4().ok_or_else(truncated)?;
+    if seq <= 0 {
+        return Err(corrupted("seq", path));
+    }
+    let lnum = r.get4().ok_or_else(truncated)?;
+    let col = r.get4().ok_or_else(truncated)?;
+    r.bytes(8).ok_or_else(truncated)?;
+    let flags = r.get2().ok_or_else(truncated)?;
+    r.bytes(NMARKS * 12 + 2 * 12 + 8).ok_or_else(truncated)?;
+    let time = r.get8().ok_or_else(truncated)?;
+    let save_nr = r.optional_fields(UHP_SAVE_NR).ok_or_else(truncated)?;
+    let mut entries = Vec::new();
+    loop {
+        match r.get2().ok_or_else(truncated)? {
+            UF_ENTRY_END_MAGIC => break,
+            UF_ENTRY_MAGIC => {}
+            _ => return Err(corrupted("entry end", path)),
+        }
+        let top = r.get4().ok_or_else(truncated)?;
+        let bot = r.get4().ok_or_else(truncated)?;
+        let lcount = r.get4().ok_or_else(truncated)?;
+        let size = r.get4().ok_or_else(truncated)?;
+        if size < 0 {
+            return Err(corrupted("entry size", path));
+        }
+        let mut lines = Vec::new();
+        for _ in 0..size {
+            let len = r.get4().ok_or_else(truncated)?;
+            let bytes = usize::try_from(len).ok().and_then(|len| r.bytes(len)).ok_or_else(truncated)?;
+            lines.push(String::from_utf8_lossy(bytes).into_owned());
+        }
+        entries.push(UndoEntry { top, bot, lcount, lines });
+    }
+    let header = UndoHeader::read(seq, entries, (lnum, col + 1), flags, time, save_nr);
+    Ok((header, links))
+}
+
+/// Build the undo tree from the contents of undo file `path`, for the text
+/// in `buf`.
+pub fn parse(data: &[u8], buf: &impl UndoBuffer, path: &Path) -> Result<UndoTree, UndoFileError> {
+    let mut r = Reader { data, pos: 0 };
+    if r.bytes(UF_START_MAGIC.len()) != Some(UF_START_MAGIC) {
+        return Err(UndoFileError::Error(format!("E823: Not an undo file: {}", path.display())));
+    }
+    let version = r.get2().ok_or_else(|| corrupted("truncated", path))?;
+    if version != UF_VERSION {
+        return Err(UndoFileError::Error(format!("E824: Incompatible undo file: {}", path.display())));
+    }
+    let hash = r.bytes(32).ok_or_else(|| corrupted("truncated", path))?.to_vec();
+    let line_count = r.get4().ok_or_else(|| corrupted("truncated", path))?;
+    if hash != text_hash(buf) || line_count != buf.line_count() as i64 {
+        return Err(UndoFileError::Message("File contents changed, cannot use undo info".to_string()));
+    }
+    let truncated = || corrupted("truncated", path);
+    let u_len = r.get4().ok_or_else(truncated)?;
+    usize::try_from(u_len).ok().and_then(|len| r.bytes(len + 8)).ok_or_else(truncated)?;
+    let old_seq = r.get4().ok_or_else(truncated)?;
+    let new_seq = r.get4().ok_or_else(truncated)?;
+    let cur_seq = r.get4().ok_or_else(truncated)?;
+    let numhead = r.get4().ok_or_else(truncated)?;
+    let seq_last = r.get4().ok_or_else(truncated)?;
+    let seq_cur = r.get4().ok_or_else(truncated)?;
+    let time_cur = r.get8().ok_or_else(truncated)?;
+    let save_nr_last = r.optional_fields(UF_LAST_SAVE_NR).ok_or_else(truncated)?;
+
+    let mut tree = UndoTree::new();
+    let mut links = Vec::new();
+    loop {
+        match r.get2().ok_or_else(truncated)? {
+            UF_HEADER_END_MAGIC => break,
+            UF_HEADER_MAGIC => {}
+            _ => return Err(corrupted("header magic", path)),
+        }
+        let (header, header_links) = read_header(&mut r, path)?;
+        if header.seq > seq_last || links.len() as i64 >= numhead {
+            return Err(corrupted("seq", path));
+        }
+        links.push((tree.push_header(header), header_links));
+    }
+    if links.len() as i64 != numhead {
+        return Err(corrupted("numhead", path));
+    }
+
+    let by_seq = |seq: i64| links.iter().find(|(id, _)| tree.header(*id).seq == seq).map(|(id, _)| *id);
+    let mut resolved = Vec::new();
+    for (id, l) in links.iter() {
+        let find = |seq: i64, what: &str| {
+            if seq == 0 {
+                Ok(None)
+            } else {
+                by_seq(seq).map(Some).ok_or_else(|| corrupted(what, path))
+            }
+        };
+        resolved.push((
+            *id,
+            find(l.next, "next")?,
+            find(l.prev, "prev")?,
+            find(l.alt_next, "alt_next")?,
+            find(l.alt_prev, "alt_prev")?,
+        ));
+    }
+    let oldhead = by_seq(old_seq);
+    let newhead = by_seq(new_seq);
+    let curhead = by_seq(cur_seq);
+    for (id, next, prev, alt_next, alt_prev) in resolved {
+        tree.header_mut(id).set_links(next, prev, alt_next, alt_prev);
+    }
+    tree.oldhead = oldhead;
+    tree.newhead = newhead;
+    tree.curhead = curhead;
+    tree.numhead = numhead as usize;
+    tree.seq_last = seq_last;
+    tree.seq_cur = seq_cur;
+    tree.time_cur = time_cur;
+    tree.save_nr_last = save_nr_last;
+    tree.save_nr_cur = save_nr_last;
+    Ok(tree)
+}
+
+/// The contents of undo file `path` with the encrypted part decrypted with
+/// the passphrase `key`.
+fn decrypt<'a>(data: &'a [u8], key: Option<&str>, path: &Path) -> Result<Cow<'a, [u8]>, UndoFileError> {
+    let start = UF_START_MAGIC.len() + 2;
+    if !data.starts_with(&[UF_START_MAGIC, &UF_VERSION_CRYPT.to_be_bytes()].concat()) {
+        return Ok(Cow::Borrowed(data));
+    }
+    let Some(key) = key.filter(|key| !key.is_empty()) else {
+        return Err(UndoFileError::Error(format!(
+            "E832: Non-encrypted file has encrypted undo file: {}",
+            path.display()
+        )));
+    };
+    let (_, text) = rust_crypt::decrypt(key, &data[start..])
+        .map_err(|_| UndoFileError::Error(format!("E826: Undo file decryption failed: {}", path.display())))?;
+    let mut plain = UF_START_MAGIC.to_vec();
+    put2(&mut plain, UF_VERSION);
+    plain.extend(text);
+    Ok(Cow::Owned(plain))
+}
+
+/// Read the undo file `path` for the text in `buf`, like `:rundo`.  An
+/// encrypted undo file is decrypted with the passphrase `key`.
+pub fn read_undo_file(buf: &impl UndoBuffer, path: &Path, key: Option<&str>) -> Result<UndoTree, UndoFileError> {
+    let data = std::fs::read(path)
+        .map_err(|_| UndoFileError::Error(format!("E822: Cannot open undo file for reading: {}", path.display())))?;
+    parse(&decrypt(&data, key, path)?, buf, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.un~");
+        let mut tree = UndoTree::new();
+        let mut buf: Vec<String> = vec!["one".to_string()];
+        for text in ["two", "three"] {
+            assert!(tree.save(&buf, 0, 2));
+            buf[0] = text.to_string();
+            tree.sync(&buf);
+        }
+        tree.undo(&mut buf, 1).unwrap();
+        assert!(tree.save(&buf, 1, 2));
+        buf.push("four".to_string());
+        tree.sync(&buf);
+        tree.set_saved();
+        write_undo_file(&tree, &buf, &path, false, None).unwrap();
+        assert_eq!(&std::fs::read(&path).unwrap()[..9], UF_START_MAGIC);
+
+        let mut read = read_undo_file(&buf, &path, None).unwrap();
+        assert_eq!(read.entries(), tree.entries());
+        assert_eq!((read.seq_last(), read.seq_cur(), read.save_last()), (3, 3, 1));
+        read.undo_time(&mut buf, 2, crate::StepUnit::Changes, true).unwrap();
+        assert_eq!(buf, ["three"]);
+
+        // Undo information is only used for the text it was written for.
+        assert_eq!(
+            read_undo_file(&buf, &path, None).map(|t| t.len()),
+            Err(UndoFileError::Message("File contents changed, cannot use undo info".to_string()))
+        );
+        let data = std::fs::read(&path).unwrap();
+        assert!(matches!(parse(&data[..data.len() - 3], &vec!["two".to_string(), "four".to_string()], &path),
+            Err(UndoFileError::Error(msg)) if msg.starts_with("E825: Corrupted undo file (truncated)")));
+
+        let other = dir.path().join("other");
+        std::fs::write(&other, "text").unwrap();
+        assert!(matches!(write_undo_file(&tree, &buf, &other, false, None), Err(UndoFileError::Message(_))));
+        assert!(matches!(read_undo_file(&buf, &other, None),
+            Err(UndoFileError::Error(msg)) if msg.starts_with("E823:")));
+        write_undo_file(&tree, &buf, &other, true, None).unwrap();
+
+        let fname = dir.path().join("file.txt");
+        assert_eq!(undo_file_name(&fname, ".", false), Some(dir.path().join(".file.txt.un~")));
+        assert_eq!(undo_file_name(&fname, ".", true), None);
+        let undodir = dir.path().to_string_lossy();
+        assert_eq!(
+            undo_file_name(&fname, &format!("/nonexistent,{}", undodir), false),
+            Some(dir.path().join(fname.to_string_lossy().replace('/', "%")))
+        );
+    }
+
+    #[test]
+    fn encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.un~");
+        let mut tree = UndoTree::new();
+        let mut buf: Vec<String> = vec!["secret one".to_string()];
+        assert!(tree.save(&buf, 0, 2));
+        buf[0] = "secret two".to_string();
+        tree.sync(&buf);
+        write_undo_file(&tree, &buf, &path, false, Some("key")).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[..11], b"Vim\x9fUnDo\xe5\x80\x02");
+        assert!(!data.windows(6).any(|w| w == b"secret"));
+
+        assert!(matches!(read_undo_file(&buf, &path, None),
+            Err(UndoFileError::Error(msg)) if msg.starts_with("E832:")));
+        assert!(matches!(read_undo_file(&buf, &path, Some("wrong")),
+            Err(UndoFileError::Error(msg)) if msg.starts_with("E826:")));
+        let mut read = read_undo_file(&buf, &path, Some("key")).unwrap();
+        read.undo(&mut buf, 1).unwrap();
+        assert_eq!(buf, ["secret one"]);
+    }
+}
@@ -0,0 +1,319 @@
+//! The attributes of a user command: `-nargs`, `-range`, `-count`,
+//! `-complete` and the flags, as given to `:command`.
+
+/// The number of arguments a user command takes, `-nargs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Nargs {
+    /// `-nargs=0`, the default.
+    #[default]
+    Zero,
+    /// `-nargs=1`: one argument, which includes white space.
+    One,
+    /// `-nargs=*`
+    Any,
+    /// `-nargs=?`
+    ZeroOrOne,
+    /// `-nargs=+`
+    OneOrMore,
+}
+
+impl Nargs {
+    fn from_char(c: char) -> Option<Nargs> {
+        Some(match c {
+            '0' => Nargs::Zero,
+            '1' => Nargs::One,
+            '*' => Nargs::Any,
+            '?' => Nargs::ZeroOrOne,
+            '+' => Nargs::OneOrMore,
+            _ => return None,
+        })
+    }
+
+    pub fn as_char(self) -> char {
+        match self {
+            Nargs::Zero => '0',
+            Nargs::One => '1',
+            Nargs::Any => '*',
+            Nargs::ZeroOrOne => '?',
+            Nargs::OneOrMore => '+',
+        }
+    }
+
+    /// Whether arguments may be given.
+    pub fn allows_args(self) -> bool {
+        self != Nargs::Zero
+    }
+
+    /// Whether an argument is required.
+    pub fn needs_arg(self) -> bool {
+        matches!(self, Nargs::One | Nargs::OneOrMore)
+    }
+}
+
+/// The range or count a user command accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Addr {
+    /// No range and no count.
+    #[default]
+    None,
+    /// `-range`: a range, the current line by default.
+    Range,
+    /// `-range=%`: a range, the whole file by default.
+    All,
+    /// `-range=N`: a count in the line number position, N by default.
+    RangeCount(i64),
+    /// `-count=N`: a count in the line number position or as the first
+    /// argument, N by default.
+    Count(i64),
+}
+
+impl Addr {
+    /// The default count, for `<count>`.
+    pub fn default_count(self) -> i64 {
+        match self {
+            Addr::RangeCount(n) | Addr::Count(n) => n,
+            _ => 0,
+        }
+    }
+
+    /// What `:command` shows in the "Address" column, and the type of
+    /// address: a count is not a line number.
+    fn listing(self) -> (String, &'static str) {
+        match self {
+            Addr::None => (String::new(), ""),
+            Addr::Range => (".".to_string(), ""),
+            Addr::All => ("%".to_string(), ""),
+            Addr::RangeCount(n) => (n.to_string(), ""),
+            Addr::Count(n) => (format!("{}c", n), "?"),
+        }
+    }
+}
+
+/// The kinds of completion `-complete` accepts, besides "custom" and
+/// "customlist".
+pub const COMPLETE_NAMES: &[&str] = &[
+    "arglist",
+    "augroup",
+    "breakpoint",
+    "buffer",
+    "color",
+    "command",
+    "compiler",
+    "cscope",
+    "diff_buffer",
+    "dir",
+    "dir_in_path",
+    "environment",
+    "event",
+    "expression",
+    "file",
+    "file_in_path",
+    "filetype",
+    "filetypecmd",
+    "function",
+    "help",
+    "highlight",
+    "history",
+    "keymap",
+    "locale",
+    "mapclear",
+    "mapping",
+    "menu",
+    "messages",
+    "option",
+    "packadd",
+    "runtime",
+    "scriptnames",
+    "shellcmd",
+    "shellcmdline",
+    "sign",
+    "syntax",
+    "syntime",
+    "tag",
+    "tag_listfiles",
+    "user",
+    "var",
+];
+
+/// How the arguments of a user command are completed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Complete {
+    /// One of [`COMPLETE_NAMES`].
+    Kind(&'static str),
+    /// `custom,{func}`: {func} returns the candidates in a String, one per
+    /// line.  They are filtered by the text being completed.
+    Custom(String),
+    /// `customlist,{func}`: {func} returns a List of matches.
+    CustomList(String),
+}
+
+impl Complete {
+    /// Parse the value of `-complete`.
+    fn parse(val: &str) -> Result<Complete, String> {
+        let (kind, func) = match val.split_once(',') {
+            Some((kind, func)) => (kind, Some(func)),
+            None => (val, None),
+        };
+        match (kind, func) {
+            ("custom" | "customlist", None | Some("")) => {
+                Err("E467: Custom completion requires a function argument".to_string())
+            }
+            ("custom", Some(func)) => Ok(Complete::Custom(func.to_string())),
+            ("customlist", Some(func)) => Ok(Complete::CustomList(func.to_string())),
+            (_, Some(_)) => Err("E468: Completion argument only allowed for custom completion".to_string()),
+            (kind, None) => match COMPLETE_NAMES.iter().find(|&&name| name == kind) {
+                Some(name) => Ok(Complete::Kind(name)),
+                None => Err(format!("E180: Invalid complete value: {}", val)),
+            },
+        }
+    }
+
+    /// The value as given to `-complete`.
+    pub fn value(&self) -> String {
+        match self {
+            Complete::Kind(kind) => kind.to_string(),
+            Complete::Custom(func) => format!("custom,{}", func),
+            Complete::CustomList(func) => format!("customlist,{}", func),
+        }
+    }
+
+    /// What `:command` shows in the "Complete" column.
+    fn listing(&self) -> &str {
+        match self {
+            Complete::Kind(kind) => kind,
+            Complete::Custom(_) => "custom",
+            Complete::CustomList(_) => "customlist",
+        }
+    }
+}
+
+/// The attributes of a user command.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Attrs {
+    pub nargs: Nargs,
+    pub addr: Addr,
+    pub complete: Option<Complete>,
+    /// `-bang`: the command can be followed by a "!".
+    pub bang: bool,
+    /// `-bar`: a "|" ends the arguments and another command may follow.
+    pub bar: bool,
+    /// `-register`: the first argument may be a register name.
+    pub register: bool,
+    /// `-buffer`: the command is only available in the current buffer.
+    pub buffer: bool,
+    /// `-keepscript`: errors are not reported in the script that defined
+    /// the command.
+    pub keepscript: bool,
+}
+
+impl Attrs {
+    /// Parse one attribute, `attr` without the leading "-".
+    pub(crate) fn parse(&mut self, attr: &str) -> Result<(), String> {
+        let (name, val) = match attr.split_once('=') {
+            Some((name, val)) => (name, Some(val)),
+            None => (attr, None),
+        };
+        match (name, val) {
+            ("", _) => return Err("E175: No attribute specified".to_string()),
+            ("bang", None) => self.bang = true,
+            ("bar", None) => self.bar = true,
+            ("buffer", None) => self.buffer = true,
+            ("register", None) => self.register = true,
+            ("keepscript", None) => self.keepscript = true,
+            ("nargs", val) => {
+                let mut chars = val.unwrap_or("").chars();
+                self.nargs = match (chars.next().and_then(Nargs::from_char), chars.next()) {
+                    (Some(nargs), None) => nargs,
+                    _ => return Err("E176: Invalid number of arguments".to_string()),
+                };
+            }
+            // A count cannot be combined with a range or another count.
+            ("range" | "count", _) if self.has_addr(name == "count" || val.is_some_and(|val| val != "%")) => {
+                return Err("E177: Count cannot be specified twice".to_string());
+            }
+            ("range", None) => self.addr = Addr::Range,
+            ("range", Some("%")) => self.addr = Addr::All,
+            ("range", Some(val)) => self.addr = Addr::RangeCount(self.count_value(val)?),
+            ("count", val) => self.addr = Addr::Count(val.map_or(Ok(0), |val| self.count_value(val))?),
+            ("complete", None) => return Err("E179: Argument required for -complete".to_string()),
+            ("complete", Some(val)) => self.complete = Some(Complete::parse(val)?),
+            _ => return Err(format!("E181: Invalid attribute: -{}", attr)),
+        }
+        Ok(())
+    }
+
+    /// Whether a range or count was given that conflicts with another one,
+    /// a `count` or a range.
+    fn has_addr(&self, count: bool) -> bool {
+        match self.addr {
+            Addr::None => false,
+            Addr::Range | Addr::All => count,
+            Addr::RangeCount(_) | Addr::Count(_) => true,
+        }
+    }
+
+    fn count_value(&self, val: &str) -> Result<i64, String> {
+        if val.is_empty() || !val.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("E178: Invalid default value for count".to_string());
+        }
+        val.parse().map_err(|_| "E178: Invalid default value for count".to_string())
+    }
+
+    /// The "Args", "Address" and "Complete" columns of `:command`, with
+    /// `over` the number of characters the name took more than its column.
+    /// Each column is followed by at least one space.
+    pub(crate) fn listing(&self, over: usize) -> String {
+        let mut line = String::new();
+        let pad = |line: &mut String, width: usize| loop {
+            line.push(' ');
+            if line.len() + over >= width {
+                break;
+            }
+        };
+        let (addr, addr_type) = self.addr.listing();
+        line.push(self.nargs.as_char());
+        pad(&mut line, 5);
+        line.push_str(&addr);
+        pad(&mut line, 8);
+        line.push_str(addr_type);
+        pad(&mut line, 13);
+        line.push_str(self.complete.as_ref().map_or("", Complete::listing));
+        pad(&mut line, 25);
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_attributes() {
+        let mut attrs = Attrs::default();
+        for attr in ["nargs=*", "range=%", "bang", "complete=customlist,s:Complete"] {
+            attrs.parse(attr).unwrap();
+        }
+        assert_eq!(attrs.nargs, Nargs::Any);
+        assert_eq!(attrs.addr, Addr::All);
+        assert_eq!(attrs.complete, Some(Complete::CustomList("s:Complete".to_string())));
+        assert_eq!(attrs.listing(0), "*    %       customlist  ");
+
+        let err = |attr: &str| Attrs::default().parse(attr).unwrap_err();
+        assert_eq!(err("nargs=2"), "E176: Invalid number of arguments");
+        assert_eq!(err("count=x"), "E178: Invalid default value for count");
+        assert_eq!(err("complete=files"), "E180: Invalid complete value: files");
+        assert_eq!(err("complete=custom"), "E467: Custom completion requires a function argument");
+        assert_eq!(err("complete=file,Fn"), "E468: Completion argument only allowed for custom completion");
+        assert_eq!(err("foo"), "E181: Invalid attribute: -foo");
+
+        let mut attrs = Attrs::default();
+        attrs.parse("count=3").unwrap();
+        assert_eq!(attrs.parse("range=2").unwrap_err(), "E177: Count cannot be specified twice");
+        assert_eq!(attrs.parse("range").unwrap_err(), "E177: Count cannot be specified twice");
+        let mut range = Attrs::default();
+        range.parse("range").unwrap();
+        assert_eq!(range.parse("count").unwrap_err(), "E177: Count cannot be specified twice");
+        range.parse("range=%").unwrap();
+        assert_eq!(attrs.listing(0), format!("0    3c ?{}", " ".repeat(16)));
+    }
+}
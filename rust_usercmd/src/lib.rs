@@ -1,9 +1,388 @@
+//! User-defined commands: `:command`, `:delcommand` and `:comclear`, and
+//! what executing one does with its arguments.
+//!
+//! [`UserCmds`] keeps the commands, global and buffer-local.  A command is
+//! found by a unique abbreviation of its name.  Executing it means
+//! replacing the `<args>`, `<line1>`, `<bang>`, etc. in its replacement
+//! text with the [`Invocation`] and executing the result, which is left to
+//! the caller.
+
 use std::ffi::{CStr};
 use std::os::raw::{c_char, c_int, c_long, c_ulong};
 use std::sync::Mutex;
 
 use once_cell::sync::Lazy;
 
+mod attr;
+
+pub use attr::{Addr, Attrs, Complete, Nargs, COMPLETE_NAMES};
+
+/// A user-defined command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserCmd {
+    pub name: String,
+    /// The replacement text.
+    pub rep: String,
+    pub attrs: Attrs,
+    /// The buffer of a buffer-local command.
+    pub buffer: Option<usize>,
+    /// The script the command was defined in.
+    pub sid: usize,
+}
+
+/// How a user command is executed: the arguments and the range after they
+/// have been checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation<'a> {
+    pub args: &'a str,
+    pub bang: bool,
+    pub line1: i64,
+    pub line2: i64,
+    /// The number of addresses given, zero to two.
+    pub addr_count: usize,
+    /// The register name given with `-register`.
+    pub reg: Option<char>,
+    /// The command modifiers, e.g. "silent vertical".
+    pub mods: &'a str,
+}
+
+/// Registers that can be given to a command with `-register`.
+fn is_register(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "\"-*+_:.%#/=".contains(c)
+}
+
+impl UserCmd {
+    /// Check the arguments given to the command, like Vim's do_one_cmd()
+    /// does.  `range` is the first and last line and the number of
+    /// addresses, None when no range was given.  `curline` and `lastline`
+    /// are the defaults for a range.
+    pub fn invocation<'a>(
+        &self,
+        bang: bool,
+        range: Option<(i64, i64, usize)>,
+        (curline, lastline): (i64, i64),
+        args: &'a str,
+        mods: &'a str,
+    ) -> Result<Invocation<'a>, String> {
+        let attrs = &self.attrs;
+        if bang && !attrs.bang {
+            return Err("E477: No ! allowed".to_string());
+        }
+        if range.is_some() && attrs.addr == Addr::None {
+            return Err("E481: No range allowed".to_string());
+        }
+        let (mut line1, mut line2, mut addr_count) = match (range, attrs.addr) {
+            (Some(range), _) => range,
+            (None, Addr::All) => (1, lastline, 0),
+            (None, Addr::Count(n) | Addr::RangeCount(n)) => (curline, n, 0),
+            (None, _) => (curline, curline, 0),
+        };
+        let mut args = args.trim_start();
+        let mut reg = None;
+        // The register comes before a count.
+        if attrs.register && !args.is_empty() {
+            let first = args.chars().next().filter(|&c| is_register(c));
+            if let Some(c) = first.filter(|c| !(c.is_ascii_digit() && matches!(attrs.addr, Addr::Count(_)))) {
+                reg = Some(c);
+                args = args[c.len_utf8()..].trim_start();
+            }
+        }
+        if let Addr::Count(_) = attrs.addr {
+            // A count may also be given as an argument.
+            let digits = args.find(|c: char| !c.is_ascii_digit()).unwrap_or(args.len());
+            if digits > 0 && args[digits..].chars().next().is_none_or(char::is_whitespace) {
+                let n: i64 = args[..digits].parse().map_err(|_| format!("E488: Trailing characters: {}", args))?;
+                line1 = line2;
+                line2 = if addr_count > 0 { line2 + n - 1 } else { n };
+                addr_count += 1;
+                args = args[digits..].trim_start();
+            }
+        }
+        if !attrs.nargs.allows_args() && !args.is_empty() && !args.starts_with('"') {
+            return Err(format!("E488: Trailing characters: {}", args));
+        }
+        if attrs.nargs.needs_arg() && args.is_empty() {
+            return Err("E471: Argument required".to_string());
+        }
+        if attrs.nargs == Nargs::ZeroOrOne || !attrs.nargs.allows_args() {
+            args = args.trim_end();
+        }
+        Ok(Invocation { args, bang, line1, line2, addr_count, reg, mods })
+    }
+
+    /// The replacement text with the `<...>` codes replaced for `inv`, like
+    /// Vim's uc_check_code().  A "q-" prefix quotes the value as a String,
+    /// `<f-args>` gives the arguments as separate Strings.  Unknown codes
+    /// are kept.
+    pub fn replace(&self, inv: &Invocation) -> String {
+        let mut result = String::new();
+        let mut rest = self.rep.as_str();
+        while let Some(start) = rest.find('<') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(end) = rest.find('>') else { break };
+            let code = rest[1..end].to_ascii_lowercase();
+            let (quote, name) = match code.split_once('-') {
+                Some(("q", name)) => (Some('q'), name),
+                Some(("f", name)) => (Some('f'), name),
+                _ => (None, code.as_str()),
+            };
+            let value = match name {
+                "args" => inv.args.to_string(),
+                "bang" => if inv.bang { "!" } else { "" }.to_string(),
+                "line1" => inv.line1.to_string(),
+                "line2" => inv.line2.to_string(),
+                "range" => inv.addr_count.to_string(),
+                "count" => {
+                    if inv.addr_count > 0 { inv.line2 } else { self.attrs.addr.default_count() }.to_string()
+                }
+                "reg" | "register" => inv.reg.map(String::from).unwrap_or_default(),
+                "mods" => inv.mods.to_string(),
+                "lt" if quote.is_none() => "<".to_string(),
+                _ => {
+                    result.push('<');
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            match quote {
+                Some('f') if name == "args" && self.attrs.nargs != Nargs::One => {
+                    result.push_str(&split_args(&value).iter().map(|arg| quote_string(arg)).collect::<Vec<_>>().join(","));
+                }
+                Some(_) => result.push_str(&quote_string(&value)),
+                None => result.push_str(&value),
+            }
+            rest = &rest[end + 1..];
+        }
+        result.push_str(rest);
+        result
+    }
+
+    /// The line `:command` shows for the command, like Vim's uc_list().
+    pub fn listing(&self) -> String {
+        let attrs = &self.attrs;
+        let mut line = String::new();
+        line.push(if attrs.bang { '!' } else { ' ' });
+        line.push(if attrs.register { '"' } else { ' ' });
+        line.push(if self.buffer.is_some() { 'b' } else { ' ' });
+        line.push(if attrs.bar { '|' } else { ' ' });
+        line.push_str(&self.name);
+        let width = (self.name.len() + 5).max(22);
+        line.push_str(&" ".repeat(width - 4 - self.name.len()));
+        line.push_str(&attrs.listing(width - 22));
+        line.push_str(&self.rep);
+        line
+    }
+}
+
+/// `text` as a String in double quotes.
+fn quote_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Split arguments for `<f-args>` at white space.  A backslash before white
+/// space or another backslash is removed, other backslashes are kept.
+pub fn split_args(args: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut arg = String::new();
+    let mut chars = args.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|&next| next == '\\' || next.is_whitespace()) => {
+                arg.push(chars.next().unwrap_or(c));
+            }
+            c if c.is_whitespace() => {
+                if !arg.is_empty() {
+                    result.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        result.push(arg);
+    }
+    result
+}
+
+/// Names that cannot be used for a user command.
+const RESERVED: &[&str] = &["X", "Next", "Print"];
+
+/// The user-defined commands.
+#[derive(Debug, Default)]
+pub struct UserCmds {
+    cmds: Vec<UserCmd>,
+}
+
+impl UserCmds {
+    pub fn new() -> Self {
+        UserCmds { cmds: Vec::new() }
+    }
+
+    /// The commands for buffer `buf`, the buffer-local ones first.
+    pub fn commands(&self, buf: usize) -> impl Iterator<Item = &UserCmd> {
+        let local = self.cmds.iter().filter(move |cmd| cmd.buffer == Some(buf));
+        local.chain(self.cmds.iter().filter(|cmd| cmd.buffer.is_none()))
+    }
+
+    /// Execute `:command[!] [{attr}...] [{cmd} [{rep}]]` with buffer
+    /// `curbuf` current, in script `sid`.  Without {rep} the commands
+    /// starting with {cmd} are listed, returns the lines.
+    pub fn do_command(&mut self, arg: &str, bang: bool, curbuf: usize, sid: usize) -> Result<Vec<String>, String> {
+        let mut attrs = Attrs::default();
+        let mut arg = arg.trim_start();
+        while let Some(rest) = arg.strip_prefix('-') {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            attrs.parse(&rest[..end])?;
+            arg = rest[end..].trim_start();
+        }
+        let end = arg.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(arg.len());
+        let (name, rep) = arg.split_at(end);
+        if !rep.is_empty() && !rep.starts_with(char::is_whitespace) {
+            return Err("E182: Invalid command name".to_string());
+        }
+        let rep = rep.trim_start();
+        let buffer = attrs.buffer.then_some(curbuf);
+        if rep.is_empty() {
+            return Ok(self.list(name, buffer, curbuf));
+        }
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            return Err("E183: User defined commands must start with an uppercase letter".to_string());
+        }
+        if RESERVED.contains(&name) {
+            return Err("E841: Reserved name, cannot be used for user defined command".to_string());
+        }
+        if attrs.complete.is_some() && !attrs.nargs.allows_args() {
+            return Err("E1208: -complete used without allowing arguments".to_string());
+        }
+        let cmd = UserCmd { name: name.to_string(), rep: rep.to_string(), attrs, buffer, sid };
+        match self.cmds.iter().position(|c| c.name == name && c.buffer == buffer) {
+            Some(_) if !bang => Err(format!("E174: Command already exists: add ! to replace it: {}", name)),
+            Some(idx) => {
+                self.cmds[idx] = cmd;
+                Ok(Vec::new())
+            }
+            None => {
+                self.cmds.push(cmd);
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// The `:command` listing of the commands starting with `prefix`, only
+    /// those local to `buffer` when given.
+    pub fn list(&self, prefix: &str, buffer: Option<usize>, curbuf: usize) -> Vec<String> {
+        let mut cmds: Vec<&UserCmd> = self
+            .commands(curbuf)
+            .filter(|cmd| cmd.name.starts_with(prefix) && (buffer.is_none() || cmd.buffer == buffer))
+            .collect();
+        if cmds.is_empty() {
+            return vec!["No user-defined commands found".to_string()];
+        }
+        // Buffer-local commands first, each sorted by name.
+        cmds.sort_by(|a, b| (a.buffer.is_none(), &a.name).cmp(&(b.buffer.is_none(), &b.name)));
+        let mut lines = vec!["    Name              Args Address Complete    Definition".to_string()];
+        lines.extend(cmds.iter().map(|cmd| cmd.listing()));
+        lines
+    }
+
+    /// Execute `:delcommand [-buffer] {cmd}`.
+    pub fn do_delcommand(&mut self, arg: &str, curbuf: usize) -> Result<(), String> {
+        let (buffer, name) = match arg.strip_prefix("-buffer") {
+            Some(name) => (Some(curbuf), name.trim()),
+            None => (None, arg.trim()),
+        };
+        match self.cmds.iter().position(|cmd| cmd.name == name && cmd.buffer == buffer) {
+            Some(idx) => {
+                self.cmds.remove(idx);
+                Ok(())
+            }
+            None if buffer.is_some() => Err(format!("E1237: No such user-defined command in current buffer: {}", name)),
+            None => Err(format!("E184: No such user-defined command: {}", name)),
+        }
+    }
+
+    /// Execute `:comclear`: delete the global commands and the ones local to
+    /// `curbuf`.
+    pub fn clear(&mut self, curbuf: usize) {
+        self.cmds.retain(|cmd| cmd.buffer.is_some_and(|buf| buf != curbuf));
+    }
+
+    /// Delete the commands local to buffer `buf`, when it is wiped out.
+    pub fn buf_deleted(&mut self, buf: usize) {
+        self.cmds.retain(|cmd| cmd.buffer != Some(buf));
+    }
+
+    /// The command `name` refers to with buffer `buf` current: an exact
+    /// match, or the only command that starts with `name`.  A buffer-local
+    /// command is used before a global one with the same name.  None when
+    /// there is no such command.
+    pub fn find(&self, name: &str, buf: usize) -> Result<Option<&UserCmd>, String> {
+        let mut found: Option<&UserCmd> = None;
+        for cmd in self.commands(buf).filter(|cmd| cmd.name.starts_with(name)) {
+            if cmd.name == name {
+                return Ok(Some(cmd));
+            }
+            match found {
+                Some(other) if other.name != cmd.name => {
+                    return Err("E464: Ambiguous use of user-defined command".to_string());
+                }
+                Some(_) => {}
+                None => found = Some(cmd),
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// The command modifiers, with the length of their shortest abbreviation,
+/// in the order `<mods>` gives them.
+const MODIFIERS: &[(&str, usize)] = &[
+    ("aboveleft", 3),
+    ("belowright", 3),
+    ("botright", 2),
+    ("browse", 3),
+    ("confirm", 4),
+    ("hide", 3),
+    ("horizontal", 3),
+    ("keepalt", 5),
+    ("keepjumps", 5),
+    ("keepmarks", 3),
+    ("keeppatterns", 5),
+    ("leftabove", 5),
+    ("lockmarks", 3),
+    ("noautocmd", 3),
+    ("noswapfile", 3),
+    ("rightbelow", 6),
+    ("sandbox", 3),
+    ("silent", 3),
+    ("tab", 3),
+    ("topleft", 2),
+    ("unsilent", 3),
+    ("verbose", 4),
+    ("vertical", 4),
+];
+
+/// The full name of command modifier `name`, which may be abbreviated.
+pub fn modifier(name: &str) -> Option<&'static str> {
+    MODIFIERS
+        .iter()
+        .find(|(full, min)| name.len() >= *min && full.starts_with(name))
+        .map(|(full, _)| *full)
+}
+
+/// The value of `<mods>` for modifiers `mods`, which are full names that
+/// may have a count before them and a "!" after them.
+pub fn mods_string(mods: &[String]) -> String {
+    let mut sorted: Vec<&String> = mods.iter().collect();
+    let index = |m: &str| {
+        let name = m.trim_start_matches(|c: char| c.is_ascii_digit()).trim_end_matches('!');
+        MODIFIERS.iter().position(|(full, _)| *full == name)
+    };
+    sorted.sort_by_key(|m| index(m));
+    sorted.iter().map(|m| m.as_str()).collect::<Vec<_>>().join(" ")
+}
+
 #[derive(Clone)]
 #[allow(dead_code)]
 struct UserCommand {
@@ -79,6 +458,66 @@ mod tests {
     use super::*;
     use std::ffi::CString;
 
+    fn define(cmds: &mut UserCmds, arg: &str) {
+        cmds.do_command(arg, false, 1, 0).unwrap();
+    }
+
+    #[test]
+    fn define_find_and_list() {
+        let mut cmds = UserCmds::new();
+        define(&mut cmds, "-nargs=* -bang Grep echo <q-args>");
+        define(&mut cmds, "-buffer -bar Gdiff echo 'diff'");
+        define(&mut cmds, "-count=5 -register Put echo <count> <q-reg>");
+        assert_eq!(cmds.do_command("Grep echo", false, 1, 0).unwrap_err(), "E174: Command already exists: add ! to replace it: Grep");
+        assert_eq!(cmds.do_command("grep x", false, 1, 0).unwrap_err(), "E183: User defined commands must start with an uppercase letter");
+        assert_eq!(cmds.do_command("-nargs=0 -complete=file Foo x", false, 1, 0).unwrap_err(), "E1208: -complete used without allowing arguments");
+        assert_eq!(cmds.do_command("Foo-x y", false, 1, 0).unwrap_err(), "E182: Invalid command name");
+
+        assert_eq!(cmds.find("Gr", 1).unwrap().unwrap().name, "Grep");
+        assert_eq!(cmds.find("G", 1).unwrap_err(), "E464: Ambiguous use of user-defined command");
+        assert_eq!(cmds.find("G", 2).unwrap().unwrap().name, "Grep");
+        assert_eq!(cmds.find("Nope", 1), Ok(None));
+
+        assert_eq!(cmds.do_command("G", false, 1, 0).unwrap(), [
+            "    Name              Args Address Complete    Definition",
+            "  b|Gdiff             0                        echo 'diff'",
+            "!   Grep              *                        echo <q-args>",
+        ]);
+        assert_eq!(cmds.list("X", None, 1), ["No user-defined commands found"]);
+        assert_eq!(cmds.do_delcommand("-buffer Grep", 1).unwrap_err(), "E1237: No such user-defined command in current buffer: Grep");
+        cmds.do_delcommand("Grep", 1).unwrap();
+        cmds.clear(2);
+        assert_eq!(cmds.commands(1).map(|cmd| cmd.name.as_str()).collect::<Vec<_>>(), ["Gdiff"]);
+    }
+
+    #[test]
+    fn invocation_and_replace() {
+        let mut cmds = UserCmds::new();
+        define(&mut cmds, "-nargs=* -range -bang Cmd <line1>,<line2> <range> <bang>|<args>|<q-args>|<f-args>|<lt>x<mods>");
+        define(&mut cmds, "-nargs=1 One <f-args>");
+        define(&mut cmds, "-count=5 -register -nargs=* Put <count> <reg> <q-args>");
+        let run = |name: &str, bang: bool, range: Option<(i64, i64, usize)>, args: &str| {
+            let cmd = cmds.find(name, 1).unwrap().unwrap();
+            cmd.invocation(bang, range, (7, 20), args, "silent").map(|inv| cmd.replace(&inv))
+        };
+        assert_eq!(
+            run("Cmd", true, Some((2, 4, 2)), r#"a\ b  "c""#),
+            Ok(r#"2,4 2 !|a\ b  "c"|"a\\ b  \"c\""|"a b","\"c\""|<xsilent"#.to_string())
+        );
+        assert_eq!(run("Cmd", false, None, ""), Ok(r#"7,7 0 ||""||<xsilent"#.to_string()));
+        assert_eq!(run("One", false, None, "a b"), Ok(r#""a b""#.to_string()));
+        assert_eq!(run("One", false, None, ""), Err("E471: Argument required".to_string()));
+        assert_eq!(run("One", true, None, "x"), Err("E477: No ! allowed".to_string()));
+        assert_eq!(run("One", false, Some((1, 1, 1)), "x"), Err("E481: No range allowed".to_string()));
+        assert_eq!(run("Put", false, None, ""), Ok(r#"5  """#.to_string()));
+        assert_eq!(run("Put", false, None, "a 3 text"), Ok(r#"3 a "text""#.to_string()));
+        assert_eq!(run("Put", false, None, "3 a text"), Ok(r#"3  "a text""#.to_string()));
+        assert_eq!(run("Put", false, Some((9, 9, 1)), "x"), Ok(r#"9 x """#.to_string()));
+
+        assert_eq!(modifier("sil"), Some("silent"));
+        assert_eq!(mods_string(&["vertical".to_string(), "silent!".to_string()]), "silent! vertical");
+    }
+
     #[test]
     fn register_and_delete() {
         let name = CString::new("Cmd").unwrap();
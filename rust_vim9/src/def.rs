@@ -0,0 +1,574 @@
+use rust_vim9execute::CompiledFunc;
+use rust_vim9expr::{compile_expr, parse_expr, Scope, ScriptVar};
+use rust_vim9generics::{parse_type_params, Instance, InstanceCache, Signature};
+use rust_vim9instr::Vim9Instr;
+use rust_vim9type::{parse_type, Vim9Type};
+
+/// A `:def` function before compilation: the parsed header and the body
+/// lines.
+#[derive(Debug, Clone)]
+pub struct DefSource {
+    pub name: String,
+    pub type_params: Vec<String>,
+    pub params: Vec<(String, Vim9Type)>,
+    pub ret: Vim9Type,
+    pub body: Vec<String>,
+}
+
+impl DefSource {
+    pub fn signature(&self) -> Signature {
+        Signature {
+            name: self.name.clone(),
+            type_params: self.type_params.clone(),
+            params: self.params.iter().map(|(_, t)| t.clone()).collect(),
+            ret: self.ret.clone(),
+        }
+    }
+}
+
+/// Parse the header of a `:def` line, the text after "def ".
+pub fn parse_def_header(text: &str) -> Result<DefSource, String> {
+    let text = text.trim();
+    let end = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '#'))
+        .unwrap_or(text.len());
+    let (name, rest) = text.split_at(end);
+    if name.is_empty() {
+        return Err(format!("E1267: Function name must start with a capital: {}", text));
+    }
+    let (type_params, rest) = parse_type_params(rest, name)?;
+    let mut rest = rest
+        .trim_start()
+        .strip_prefix('(')
+        .ok_or_else(|| format!("E125: Illegal argument: {}", rest))?;
+    let mut params = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(r) = rest.strip_prefix(')') {
+            rest = r;
+            break;
+        }
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let (pname, r) = rest.split_at(end);
+        if pname.is_empty() {
+            return Err(format!("E125: Illegal argument: {}", rest));
+        }
+        let r = r
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or_else(|| format!("E1077: Missing argument type for {}", pname))?;
+        let (ty, r) = parse_type(r, &type_params).ok_or_else(|| format!("E1010: Type not recognized: {}", r.trim()))?;
+        if params.iter().any(|(n, _)| n == pname) {
+            return Err(format!("E853: Duplicate argument name: {}", pname));
+        }
+        params.push((pname.to_string(), ty));
+        rest = r.trim_start();
+        if let Some(r) = rest.strip_prefix(',') {
+            rest = r;
+        } else if !rest.starts_with(')') {
+            return Err(format!("E1004: Missing ')' at \"{}\"", rest));
+        }
+    }
+    let ret = match rest.trim_start().strip_prefix(':') {
+        Some(r) => Vim9Type::parse(r, &type_params).ok_or_else(|| format!("E1010: Type not recognized: {}", r.trim()))?,
+        None => Vim9Type::Void,
+    };
+    Ok(DefSource {
+        name: name.to_string(),
+        type_params,
+        params,
+        ret,
+        body: Vec::new(),
+    })
+}
+
+/// Split `source` into its `:def` functions.  Lines outside functions are
+/// returned separately, in order.
+pub fn split_defs(source: &str) -> Result<(Vec<DefSource>, Vec<String>), String> {
+    let mut defs = Vec::new();
+    let mut other = Vec::new();
+    let mut current: Option<DefSource> = None;
+    for line in source.lines() {
+        let trimmed = line.trim();
+        match current.as_mut() {
+            Some(def) => {
+                if trimmed == "enddef" {
+                    defs.push(current.take().unwrap());
+                } else {
+                    def.body.push(line.to_string());
+                }
+            }
+            None => {
+                if let Some(rest) = trimmed.strip_prefix("def ") {
+                    current = Some(parse_def_header(rest)?);
+                } else {
+                    other.push(line.to_string());
+                }
+            }
+        }
+    }
+    if let Some(def) = current {
+        return Err(format!("E1057: Missing :enddef for {}", def.name));
+    }
+    Ok((defs, other))
+}
+
+enum Block {
+    If { jump_false: Option<usize>, end_jumps: Vec<usize>, locals: usize },
+    Loop { start: usize, exits: Vec<usize>, locals: usize, is_for: bool },
+}
+
+/// Resolves the names a function refers to outside of its own locals.
+pub trait Resolver {
+    /// Signature of the function visible as `name`, see [`Scope::signature`].
+    fn signature(&mut self, name: &str) -> Result<Option<Signature>, String>;
+
+    /// A script variable visible as `name`.
+    fn script_var(&mut self, name: &str) -> Result<Option<ScriptVar>, String>;
+
+    /// Declare a variable at the script level.
+    fn declare_script_var(&mut self, name: &str, ty: Vim9Type, is_const: bool) -> Result<ScriptVar, String>;
+
+    fn instances(&mut self) -> &mut InstanceCache;
+}
+
+/// Compiles the body of one function, the script level code of a Vim9
+/// script, or a single expression.
+pub struct FuncCompiler<'a> {
+    resolver: &'a mut dyn Resolver,
+    /// When set, `var` at the outer level declares a script variable.
+    script_level: bool,
+    type_vars: Vec<String>,
+    locals: Vec<(String, usize, Vim9Type)>,
+    /// Name and type of every slot allocated so far.
+    slots: Vec<(String, Vim9Type)>,
+    pub instrs: Vec<Vim9Instr>,
+    /// Body line index of each instruction.
+    lines: Vec<usize>,
+    blocks: Vec<Block>,
+    ret: Vim9Type,
+    /// Compiled names of the functions referred to, these must be compiled
+    /// before the code runs.
+    pub referenced: Vec<String>,
+}
+
+impl Scope for FuncCompiler<'_> {
+    fn local(&self, name: &str) -> Option<(usize, Vim9Type)> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|(n, ..)| n == name)
+            .map(|(_, slot, ty)| (*slot, ty.clone()))
+    }
+
+    fn script_var(&mut self, name: &str) -> Result<Option<ScriptVar>, String> {
+        self.resolver.script_var(name)
+    }
+
+    fn signature(&mut self, name: &str) -> Result<Option<Signature>, String> {
+        let sig = self.resolver.signature(name)?;
+        if let Some(sig) = &sig {
+            if !self.referenced.contains(&sig.name) {
+                self.referenced.push(sig.name.clone());
+            }
+        }
+        Ok(sig)
+    }
+
+    fn resolve_call(
+        &mut self,
+        sig: &Signature,
+        type_args: Option<&[Vim9Type]>,
+        arg_types: &[Vim9Type],
+    ) -> Result<Instance, String> {
+        let inst = sig.resolve(type_args, arg_types)?;
+        if sig.is_generic() {
+            self.resolver.instances().get(sig, &inst.type_args)?;
+        }
+        Ok(inst)
+    }
+}
+
+impl<'a> FuncCompiler<'a> {
+    pub fn new(resolver: &'a mut dyn Resolver) -> Self {
+        FuncCompiler {
+            resolver,
+            script_level: false,
+            type_vars: Vec::new(),
+            locals: Vec::new(),
+            slots: Vec::new(),
+            instrs: Vec::new(),
+            lines: Vec::new(),
+            blocks: Vec::new(),
+            ret: Vim9Type::Void,
+            referenced: Vec::new(),
+        }
+    }
+
+    /// Compile the script level commands of a Vim9 script.
+    pub fn new_script_level(resolver: &'a mut dyn Resolver) -> Self {
+        FuncCompiler { script_level: true, ..FuncCompiler::new(resolver) }
+    }
+
+    /// Compile an expression, returning its type.
+    pub fn expr(&mut self, text: &str) -> Result<Vim9Type, String> {
+        let expr = parse_expr(text, &self.type_vars)?;
+        let mut instrs = std::mem::take(&mut self.instrs);
+        let res = compile_expr(&expr, self, &mut instrs);
+        self.instrs = instrs;
+        res
+    }
+
+    /// Compile a whole function.  Also returns the compiled names of the
+    /// functions it refers to.
+    pub fn compile(mut self, def: &DefSource) -> Result<(CompiledFunc, Vec<String>), String> {
+        self.type_vars = def.type_params.clone();
+        self.ret = def.ret.clone();
+        for (name, ty) in &def.params {
+            self.declare(name, ty.clone())?;
+        }
+        let mut last_was_return = false;
+        for (lnum, line) in def.body.iter().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            last_was_return = self.blocks.is_empty() && (line == "return" || line.starts_with("return "));
+            self.statement(line)
+                .map_err(|e| format!("{}\nError in function {}, line: {}", e, def.name, line))?;
+            self.lines.resize(self.instrs.len(), lnum);
+        }
+        if !self.blocks.is_empty() {
+            return Err(match self.blocks.last() {
+                Some(Block::If { .. }) => "E171: Missing :endif".to_string(),
+                Some(Block::Loop { is_for: true, .. }) => "E170: Missing :endfor".to_string(),
+                _ => "E170: Missing :endwhile".to_string(),
+            });
+        }
+        if !last_was_return {
+            if def.ret != Vim9Type::Void {
+                return Err(format!("E1027: Missing return statement in function {}", def.name));
+            }
+            self.instrs.push(Vim9Instr::ReturnVoid);
+            self.lines.push(def.body.len());
+        }
+        let func = CompiledFunc {
+            name: def.name.clone(),
+            type_params: def.type_params.clone(),
+            params: def.params.clone(),
+            ret: def.ret.clone(),
+            locals: self.slots.len(),
+            instrs: self.instrs,
+            slots: self.slots,
+            source: def.body.clone(),
+            lines: self.lines,
+        };
+        Ok((func, self.referenced))
+    }
+
+    fn declare(&mut self, name: &str, ty: Vim9Type) -> Result<usize, String> {
+        let scope_start = match self.blocks.last() {
+            Some(Block::If { locals, .. }) | Some(Block::Loop { locals, .. }) => *locals,
+            None => 0,
+        };
+        if self.locals[scope_start..].iter().any(|(n, ..)| n == name) {
+            return Err(format!("E1017: Variable already declared: {}", name));
+        }
+        let slot = self.alloc_slots(1);
+        self.slots[slot] = (name.to_string(), ty.clone());
+        self.locals.push((name.to_string(), slot, ty));
+        Ok(slot)
+    }
+
+    fn alloc_slots(&mut self, n: usize) -> usize {
+        let slot = self.slots.len();
+        self.slots.resize(slot + n, (String::new(), Vim9Type::Any));
+        slot
+    }
+
+    /// Emit a check when a value of `actual` type is assigned where
+    /// `expected` is declared.
+    fn check_assign(&mut self, expected: &Vim9Type, actual: &Vim9Type) -> Result<(), String> {
+        if !expected.accepts(actual) {
+            return Err(format!("E1012: Type mismatch; expected {} but got {}", expected, actual));
+        }
+        if *actual == Vim9Type::Any && *expected != Vim9Type::Any && !expected.is_generic() {
+            self.instrs.push(Vim9Instr::CheckType { ty: expected.clone(), offset: -1, argnr: 0 });
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, line: &str) -> Result<(), String> {
+        let (cmd, arg) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        match cmd {
+            "var" | "final" | "const" => self.var(arg, cmd == "const"),
+            "return" => self.return_stmt(arg),
+            "echo" => {
+                self.expr(arg)?;
+                self.instrs.push(Vim9Instr::Echo);
+                Ok(())
+            }
+            "if" => {
+                self.condition(arg)?;
+                let jump = self.instrs.len();
+                self.instrs.push(Vim9Instr::JumpIfFalse(0));
+                self.blocks.push(Block::If { jump_false: Some(jump), end_jumps: Vec::new(), locals: self.locals.len() });
+                Ok(())
+            }
+            "elseif" | "else" => {
+                let here = self.instrs.len() + 1;
+                let Some(Block::If { jump_false, end_jumps, locals }) = self.blocks.last_mut() else {
+                    return Err(if cmd == "else" { "E581: :else without :if" } else { "E582: :elseif without :if" }.to_string());
+                };
+                let Some(jf) = jump_false.take() else {
+                    return Err("E583: Multiple :else".to_string());
+                };
+                end_jumps.push(here - 1);
+                self.instrs[jf] = Vim9Instr::JumpIfFalse(here);
+                let scope = *locals;
+                self.instrs.push(Vim9Instr::Jump(0));
+                self.locals.truncate(scope);
+                if cmd == "elseif" {
+                    self.condition(arg)?;
+                    let jump = self.instrs.len();
+                    self.instrs.push(Vim9Instr::JumpIfFalse(0));
+                    if let Some(Block::If { jump_false, .. }) = self.blocks.last_mut() {
+                        *jump_false = Some(jump);
+                    }
+                }
+                Ok(())
+            }
+            "endif" => {
+                let Some(Block::If { jump_false, end_jumps, locals }) = self.blocks.pop() else {
+                    return Err("E580: :endif without :if".to_string());
+                };
+                let end = self.instrs.len();
+                if let Some(jf) = jump_false {
+                    self.instrs[jf] = Vim9Instr::JumpIfFalse(end);
+                }
+                for j in end_jumps {
+                    self.instrs[j] = Vim9Instr::Jump(end);
+                }
+                self.locals.truncate(locals);
+                Ok(())
+            }
+            "while" => {
+                let start = self.instrs.len();
+                self.condition(arg)?;
+                let exit = self.instrs.len();
+                self.instrs.push(Vim9Instr::JumpIfFalse(0));
+                self.blocks.push(Block::Loop { start, exits: vec![exit], locals: self.locals.len(), is_for: false });
+                Ok(())
+            }
+            "for" => self.for_stmt(arg),
+            "endwhile" | "endfor" => {
+                let Some(Block::Loop { start, exits, locals, is_for }) = self.blocks.pop() else {
+                    return Err(if cmd == "endfor" { "E588: :endfor without :for" } else { "E588: :endwhile without :while" }.to_string());
+                };
+                if is_for != (cmd == "endfor") {
+                    return Err(format!("E732: Using :{} with :{}", cmd, if is_for { "for" } else { "while" }));
+                }
+                self.instrs.push(Vim9Instr::Jump(start));
+                let end = self.instrs.len();
+                for j in exits {
+                    self.instrs[j] = match self.instrs[j] {
+                        Vim9Instr::JumpIfFalse(_) => Vim9Instr::JumpIfFalse(end),
+                        Vim9Instr::For { list, .. } => Vim9Instr::For { list, end },
+                        _ => Vim9Instr::Jump(end),
+                    };
+                }
+                self.locals.truncate(locals);
+                Ok(())
+            }
+            "break" | "continue" => {
+                let Some(Block::Loop { start, exits, .. }) =
+                    self.blocks.iter_mut().rev().find(|b| matches!(b, Block::Loop { .. }))
+                else {
+                    return Err(if cmd == "break" { "E587: :break without :while or :for" } else { "E586: :continue without :while or :for" }.to_string());
+                };
+                if cmd == "break" {
+                    exits.push(self.instrs.len());
+                    self.instrs.push(Vim9Instr::Jump(0));
+                } else {
+                    let start = *start;
+                    self.instrs.push(Vim9Instr::Jump(start));
+                }
+                Ok(())
+            }
+            _ => self.assign_or_call(line),
+        }
+    }
+
+    fn condition(&mut self, arg: &str) -> Result<(), String> {
+        let ty = self.expr(arg)?;
+        if !matches!(ty, Vim9Type::Bool | Vim9Type::Number | Vim9Type::Any) {
+            return Err(format!("E1012: Type mismatch; expected bool but got {}", ty));
+        }
+        Ok(())
+    }
+
+    fn var(&mut self, arg: &str, is_const: bool) -> Result<(), String> {
+        let (decl, init) = match arg.split_once(" = ") {
+            Some((d, i)) => (d.trim(), Some(i.trim())),
+            None => (arg.trim(), None),
+        };
+        let (name, declared) = match decl.split_once(':') {
+            Some((n, t)) => {
+                let ty = Vim9Type::parse(t, &self.type_vars).ok_or_else(|| format!("E1010: Type not recognized: {}", t.trim()))?;
+                (n.trim(), Some(ty))
+            }
+            None => (decl, None),
+        };
+        let ty = match (init, declared) {
+            (Some(init), declared) => {
+                let actual = self.expr(init)?;
+                match declared {
+                    Some(d) => {
+                        self.check_assign(&d, &actual)?;
+                        d
+                    }
+                    None => actual,
+                }
+            }
+            (None, Some(d)) => {
+                self.push_default(&d);
+                d
+            }
+            (None, None) => return Err(format!("E1022: Type or initialization required: {}", name)),
+        };
+        if self.script_level && self.blocks.is_empty() {
+            let var = self.resolver.declare_script_var(name, ty, is_const)?;
+            self.instrs.push(Vim9Instr::StoreScript { sid: var.sid, idx: var.idx });
+            return Ok(());
+        }
+        let slot = self.declare(name, ty)?;
+        self.instrs.push(Vim9Instr::StoreLocal(slot));
+        Ok(())
+    }
+
+    fn push_default(&mut self, ty: &Vim9Type) {
+        self.instrs.push(match ty {
+            Vim9Type::Number => Vim9Instr::PushNumber(0),
+            Vim9Type::Float => Vim9Instr::PushFloat(0.0),
+            Vim9Type::Bool => Vim9Instr::PushBool(false),
+            Vim9Type::String => Vim9Instr::PushString(String::new()),
+            Vim9Type::List(_) => Vim9Instr::NewList(0),
+            _ => Vim9Instr::PushNumber(0),
+        });
+    }
+
+    fn return_stmt(&mut self, arg: &str) -> Result<(), String> {
+        if arg.is_empty() {
+            if self.ret != Vim9Type::Void {
+                return Err("E1003: Missing return value".to_string());
+            }
+            self.instrs.push(Vim9Instr::ReturnVoid);
+            return Ok(());
+        }
+        if self.ret == Vim9Type::Void {
+            return Err("E1096: Returning a value in a function without a return type".to_string());
+        }
+        let actual = self.expr(arg)?;
+        let ret = self.ret.clone();
+        self.check_assign(&ret, &actual)?;
+        self.instrs.push(Vim9Instr::Return);
+        Ok(())
+    }
+
+    fn for_stmt(&mut self, arg: &str) -> Result<(), String> {
+        let (var, list) = arg
+            .split_once(" in ")
+            .ok_or_else(|| format!("E690: Missing \"in\" after :for: {}", arg))?;
+        let list_ty = self.expr(list.trim())?;
+        let item_ty = match list_ty {
+            Vim9Type::List(m) => *m,
+            Vim9Type::String => Vim9Type::String,
+            Vim9Type::Any => Vim9Type::Any,
+            other => return Err(format!("E1177: For loop on {} not supported", other)),
+        };
+        let list_slot = self.alloc_slots(2);
+        self.instrs.push(Vim9Instr::StoreLocal(list_slot));
+        self.instrs.push(Vim9Instr::PushNumber(0));
+        self.instrs.push(Vim9Instr::StoreLocal(list_slot + 1));
+        let start = self.instrs.len();
+        self.instrs.push(Vim9Instr::For { list: list_slot, end: 0 });
+        self.blocks.push(Block::Loop { start, exits: vec![start], locals: self.locals.len(), is_for: true });
+        let (name, declared) = match var.split_once(':') {
+            Some((n, t)) => (
+                n.trim(),
+                Some(Vim9Type::parse(t, &self.type_vars).ok_or_else(|| format!("E1010: Type not recognized: {}", t.trim()))?),
+            ),
+            None => (var.trim(), None),
+        };
+        let ty = match declared {
+            Some(d) => {
+                self.check_assign(&d, &item_ty)?;
+                d
+            }
+            None => item_ty,
+        };
+        let slot = self.declare(name, ty)?;
+        self.instrs.push(Vim9Instr::StoreLocal(slot));
+        Ok(())
+    }
+
+    fn assign_or_call(&mut self, line: &str) -> Result<(), String> {
+        const OPS: [(&str, Option<Vim9Instr>); 6] = [
+            (" = ", None),
+            (" += ", Some(Vim9Instr::Add)),
+            (" -= ", Some(Vim9Instr::Sub)),
+            (" *= ", Some(Vim9Instr::Mul)),
+            (" /= ", Some(Vim9Instr::Div)),
+            (" ..= ", Some(Vim9Instr::Concat)),
+        ];
+        for (op, instr) in OPS {
+            if let Some((name, expr)) = line.split_once(op) {
+                let name = name.trim();
+                if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+                    continue;
+                }
+                let (load, store, ty) = match self.local(name) {
+                    Some((slot, ty)) => (Vim9Instr::LoadLocal(slot), Vim9Instr::StoreLocal(slot), ty),
+                    None => match self.resolver.script_var(name)? {
+                        Some(var) if var.is_const => {
+                            return Err(format!("E1018: Cannot assign to a constant: {}", name))
+                        }
+                        Some(var) => (
+                            Vim9Instr::LoadScript { sid: var.sid, idx: var.idx },
+                            Vim9Instr::StoreScript { sid: var.sid, idx: var.idx },
+                            var.ty,
+                        ),
+                        None => return Err(format!("E1001: Variable not found: {}", name)),
+                    },
+                };
+                if let Some(instr) = instr {
+                    self.instrs.push(load);
+                    let actual = self.expr(expr)?;
+                    let result = if instr == Vim9Instr::Concat { Vim9Type::String } else { actual };
+                    self.instrs.push(instr);
+                    self.check_assign(&ty, &result)?;
+                } else {
+                    let actual = self.expr(expr)?;
+                    self.check_assign(&ty, &actual)?;
+                }
+                self.instrs.push(store);
+                return Ok(());
+            }
+        }
+        let expr = parse_expr(line, &self.type_vars)?;
+        if !matches!(expr, rust_vim9expr::Expr::Call { .. }) {
+            return Err(format!("E1207: Expression without an effect: {}", line));
+        }
+        let mut instrs = std::mem::take(&mut self.instrs);
+        let res = compile_expr(&expr, self, &mut instrs);
+        self.instrs = instrs;
+        res?;
+        self.instrs.push(Vim9Instr::Pop);
+        Ok(())
+    }
+}
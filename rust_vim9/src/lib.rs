@@ -1,6 +1,13 @@
-pub use rust_vim9execute::{execute, Vim9Program};
+mod def;
+mod script;
+
+pub use def::{parse_def_header, split_defs, DefSource, FuncCompiler, Resolver};
+pub use script::{ScriptItem, Vim9Context};
+pub use rust_debugger::{DebugInput, Debugger};
+pub use rust_profiler::Profiler;
+pub use rust_vim9execute::{execute, CompiledFunc, FuncTable, Interpreter, Vim9Program, Vim9Value};
 pub use rust_vim9expr::{compile, eval_bool_expr, eval_expr, parse_line};
-pub use rust_vim9generics::repeat;
+pub use rust_vim9generics::{repeat, InstanceCache, Signature};
 pub use rust_vim9instr::Vim9Instr;
 pub use rust_vim9type::Vim9Type;
 
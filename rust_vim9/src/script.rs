@@ -0,0 +1,611 @@
+//! Vim9 script files: `vim9script`, `export`, `import` and autoload
+//! modules.
+//!
+//! Every sourced script gets a script ID.  Its functions are compiled under
+//! the name `<SNR>{sid}_{name}` and its variables live in the interpreter's
+//! script variable table, so that each script has its own namespace.  Other
+//! scripts see the exported items through an import alias, `util.Func()`.
+//! Functions are compiled on their first call; an `import autoload` script
+//! is only read when a function using it is compiled.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rust_debugger::{DebugInput, Debugger};
+use rust_profiler::Profiler;
+use rust_scriptfile::{find_runtime_file, read_script};
+use rust_vim9execute::{CompiledFunc, DisassembleMode, FuncTable, Interpreter, Vim9Value};
+use rust_vim9expr::ScriptVar;
+use rust_vim9generics::{InstanceCache, Signature};
+use rust_vim9instr::Vim9Instr;
+use rust_vim9type::Vim9Type;
+
+use crate::def::{parse_def_header, DefSource, FuncCompiler, Resolver};
+
+/// An item defined at the script level.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptItem {
+    /// A function, by its compiled name.
+    Func(String),
+    /// A variable, by its index in the script variable table.
+    Var(usize),
+    Class,
+}
+
+#[derive(Debug, Clone)]
+enum Import {
+    Loaded(usize),
+    /// `import autoload`, the script is read on first use.
+    Autoload(PathBuf),
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    path: PathBuf,
+    text: String,
+    vim9: bool,
+    items: HashMap<String, (ScriptItem, bool)>,
+    vars: Vec<(String, Vim9Type, bool)>,
+    imports: HashMap<String, Import>,
+    /// Names of variables declared with `export`, filled before the script
+    /// level code is compiled.
+    exported_vars: Vec<String>,
+}
+
+/// Compiled `:def` functions and sourced Vim9 scripts, together with what is
+/// needed to call them.
+pub struct Vim9Context {
+    funcs: FuncTable,
+    sigs: HashMap<String, Signature>,
+    /// Functions that have not been compiled yet, by compiled name, with the
+    /// ID of the script that defines them.
+    pending: HashMap<String, (usize, DefSource)>,
+    instances: InstanceCache,
+    interp: Interpreter,
+    /// Script ID zero is used for global functions defined with
+    /// [`Vim9Context::define`].
+    scripts: Vec<Script>,
+    by_path: HashMap<PathBuf, usize>,
+    runtimepath: String,
+}
+
+impl Default for Vim9Context {
+    fn default() -> Self {
+        Vim9Context {
+            funcs: FuncTable::new(),
+            sigs: HashMap::new(),
+            pending: HashMap::new(),
+            instances: InstanceCache::new(),
+            interp: Interpreter::new(),
+            scripts: vec![Script::default()],
+            by_path: HashMap::new(),
+            runtimepath: String::new(),
+        }
+    }
+}
+
+impl Vim9Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the value of 'runtimepath', used to find `import` and
+    /// `import autoload` scripts given by a relative name.
+    pub fn set_runtimepath(&mut self, rtp: &str) {
+        self.runtimepath = rtp.to_string();
+    }
+
+    /// Compile all `:def` functions in `source` as global functions.
+    pub fn define(&mut self, source: &str) -> Result<(), String> {
+        let (defs, _) = crate::def::split_defs(source)?;
+        for def in &defs {
+            self.sigs.insert(def.name.clone(), def.signature());
+            self.pending.insert(def.name.clone(), (0, def.clone()));
+        }
+        for def in &defs {
+            self.ensure_compiled(&def.name)?;
+        }
+        Ok(())
+    }
+
+    /// The compiled function `name`, compiling it when needed.
+    pub fn func(&mut self, name: &str) -> Option<&Rc<CompiledFunc>> {
+        self.ensure_compiled(name).ok()?;
+        self.funcs.get(name)
+    }
+
+    /// The instances of generic functions created by calls compiled so far.
+    pub fn instances(&self) -> &InstanceCache {
+        &self.instances
+    }
+
+    /// Output of `:echo` commands executed so far.
+    pub fn output(&self) -> &[String] {
+        &self.interp.output
+    }
+
+    /// Call a function by its compiled name with already evaluated
+    /// arguments.
+    pub fn call(&mut self, name: &str, args: Vec<Vim9Value>) -> Result<Vim9Value, String> {
+        self.ensure_compiled(name)?;
+        self.interp.call(&self.funcs, name, args)
+    }
+
+    /// Compile and evaluate an expression that may call the global
+    /// functions, e.g. `Map<number, string>([1, 2], Show)`.
+    pub fn eval(&mut self, expr: &str) -> Result<Vim9Value, String> {
+        self.eval_in_script(0, expr)
+    }
+
+    /// Evaluate an expression in the namespace of script `sid`.
+    pub fn eval_in_script(&mut self, sid: usize, expr: &str) -> Result<Vim9Value, String> {
+        let mut ns = Namespace { ctx: self, sid };
+        let mut compiler = FuncCompiler::new(&mut ns);
+        compiler.expr(expr)?;
+        let mut instrs = compiler.instrs;
+        let referenced = compiler.referenced;
+        instrs.push(Vim9Instr::Return);
+        for name in &referenced {
+            self.ensure_compiled(name)?;
+        }
+        self.run_code(instrs)
+    }
+
+    fn run_code(&mut self, instrs: Vec<Vim9Instr>) -> Result<Vim9Value, String> {
+        let func = CompiledFunc {
+            name: String::new(),
+            type_params: Vec::new(),
+            params: Vec::new(),
+            ret: Vim9Type::Any,
+            locals: 0,
+            instrs,
+            slots: Vec::new(),
+            source: Vec::new(),
+            lines: Vec::new(),
+        };
+        let mut funcs = self.funcs.clone();
+        funcs.insert(func);
+        self.interp.call(&funcs, "", Vec::new())
+    }
+
+    /// The `:disassemble [debug|profile] {func}` command.  `{func}` is
+    /// looked up from script `sid`; the listing is added to the output.
+    pub fn ex_disassemble(&mut self, sid: usize, arg: &str) -> Result<(), String> {
+        let listing = self.disassemble(sid, arg)?;
+        self.interp.output.extend(listing.lines().map(str::to_string));
+        Ok(())
+    }
+
+    /// The `:disassemble` listing of a function, see
+    /// [`Vim9Context::ex_disassemble`].
+    pub fn disassemble(&mut self, sid: usize, arg: &str) -> Result<String, String> {
+        let (mode, name) = DisassembleMode::parse(arg);
+        if name.is_empty() {
+            return Err("E471: Argument required".to_string());
+        }
+        let local = name.strip_prefix("s:").unwrap_or(name);
+        let compiled = match self.lookup(sid, local)? {
+            Some((_, ScriptItem::Func(compiled))) => compiled,
+            _ => name.to_string(),
+        };
+        if !self.pending.contains_key(&compiled) && self.funcs.get(&compiled).is_none() {
+            return Err(format!("E1061: Cannot find function {}", name));
+        }
+        self.ensure_compiled(&compiled)?;
+        Ok(self.funcs.get(&compiled).unwrap().disassemble(mode))
+    }
+
+    /// The `:profile {arg}` command.
+    pub fn ex_profile(&mut self, arg: &str) -> Result<(), String> {
+        self.interp.profiler.get_or_insert_with(Profiler::new).ex_profile(arg)
+    }
+
+    /// The profile collected since `:profile start`.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.interp.profiler.as_ref()
+    }
+
+    /// The `:breakadd {arg}` command.
+    pub fn ex_breakadd(&mut self, arg: &str) -> Result<(), String> {
+        self.interp.debugger.get_or_insert_with(Debugger::default).ex_breakadd(arg)?;
+        Ok(())
+    }
+
+    /// The `:breakdel {arg}` command.
+    pub fn ex_breakdel(&mut self, arg: &str) -> Result<(), String> {
+        match self.interp.debugger.as_mut() {
+            Some(debugger) => debugger.ex_breakdel(arg),
+            None => Err(format!("E161: Breakpoint not found: {}", arg.trim())),
+        }
+    }
+
+    /// The `:breaklist` command, the list is added to the output.
+    pub fn ex_breaklist(&mut self) {
+        let list = match &self.interp.debugger {
+            Some(debugger) => debugger.breaklist(),
+            None => vec!["No breakpoints defined".to_string()],
+        };
+        self.interp.output.extend(list);
+    }
+
+    /// Read the commands of the debug prompt from `input` instead of stdin.
+    pub fn set_debug_input(&mut self, input: Box<dyn DebugInput>) {
+        self.interp.debugger.get_or_insert_with(Debugger::default).set_input(input);
+    }
+
+    /// Execute a command that takes effect immediately at the script level.
+    fn ex_command(&mut self, sid: usize, name: &str, arg: &str) -> Result<(), String> {
+        match name {
+            "disassemble" => self.ex_disassemble(sid, arg),
+            "profile" => self.ex_profile(arg),
+            "breakadd" => self.ex_breakadd(arg),
+            "breakdel" => self.ex_breakdel(arg),
+            _ => {
+                self.ex_breaklist();
+                Ok(())
+            }
+        }
+    }
+
+    /// Compile `name` and every function it refers to that has not been
+    /// compiled yet.
+    fn ensure_compiled(&mut self, name: &str) -> Result<(), String> {
+        let mut todo = vec![name.to_string()];
+        while let Some(name) = todo.pop() {
+            if self.funcs.get(&name).is_some() {
+                continue;
+            }
+            let Some((sid, def)) = self.pending.remove(&name) else {
+                continue;
+            };
+            let mut ns = Namespace { ctx: self, sid };
+            match FuncCompiler::new(&mut ns).compile(&def) {
+                Ok((func, referenced)) => {
+                    self.funcs.insert(func);
+                    todo.extend(referenced);
+                }
+                Err(e) => {
+                    self.pending.insert(name, (sid, def));
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The script ID of a sourced script.
+    pub fn script_id(&self, path: &Path) -> Option<usize> {
+        let path = path.canonicalize().ok()?;
+        self.by_path.get(&path).copied()
+    }
+
+    /// The current value of a script-level variable.
+    pub fn script_var(&self, sid: usize, name: &str) -> Option<Vim9Value> {
+        match self.scripts.get(sid)?.items.get(name)? {
+            (ScriptItem::Var(idx), _) => Some(self.interp.script_var(sid, *idx)),
+            _ => None,
+        }
+    }
+
+    /// The item `name` defined in script `sid` and whether it is exported.
+    pub fn script_item(&self, sid: usize, name: &str) -> Option<(ScriptItem, bool)> {
+        self.scripts.get(sid)?.items.get(name).cloned()
+    }
+
+    /// Source the script at `path` and return its script ID.  Sourcing a
+    /// script again clears its variables and runs it again.  When the text
+    /// did not change the compiled functions are kept, otherwise they are
+    /// compiled again on their next call.
+    pub fn source_file(&mut self, path: &Path) -> Result<usize, String> {
+        let path = path
+            .canonicalize()
+            .map_err(|_| format!("E484: Can't open file {}", path.display()))?;
+        let text = read_script(&path).map_err(|_| format!("E484: Can't open file {}", path.display()))?;
+        let sid = match self.by_path.get(&path) {
+            Some(&sid) => {
+                let unchanged = self.scripts[sid].text == text;
+                self.reset_script(sid, !unchanged);
+                sid
+            }
+            None => self.new_script(&path),
+        };
+        self.scripts[sid].text = text.clone();
+        self.run_script(sid, &text)?;
+        Ok(sid)
+    }
+
+    fn new_script(&mut self, path: &Path) -> usize {
+        let sid = self.scripts.len();
+        self.scripts.push(Script { path: path.to_path_buf(), ..Script::default() });
+        self.by_path.insert(path.to_path_buf(), sid);
+        sid
+    }
+
+    /// Forget the items of script `sid` before it is sourced again.
+    fn reset_script(&mut self, sid: usize, drop_funcs: bool) {
+        let script = &mut self.scripts[sid];
+        let old_items = std::mem::take(&mut script.items);
+        script.vim9 = false;
+        script.vars.clear();
+        script.imports.clear();
+        script.exported_vars.clear();
+        self.interp.clear_script_vars(sid);
+        if drop_funcs {
+            let mut funcs = FuncTable::new();
+            for name in self.funcs.names() {
+                let owned = old_items.values().any(|(item, _)| *item == ScriptItem::Func(name.clone()));
+                if !owned {
+                    funcs.insert((**self.funcs.get(name).unwrap()).clone());
+                }
+            }
+            self.funcs = funcs;
+        }
+    }
+
+    /// Load a script for `import`.  A script that was already sourced is not
+    /// read again.
+    fn import_script(&mut self, path: &Path) -> Result<usize, String> {
+        let canon = path.canonicalize().map_err(|_| format!("E1053: Could not import \"{}\"", path.display()))?;
+        match self.by_path.get(&canon) {
+            Some(&sid) => Ok(sid),
+            None => self.source_file(&canon),
+        }
+    }
+
+    fn run_script(&mut self, sid: usize, text: &str) -> Result<(), String> {
+        let mut lines = text.lines().enumerate().peekable();
+        let mut main = Vec::new();
+        let mut seen_command = false;
+        while let Some((lnum, line)) = lines.next() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('"') {
+                continue;
+            }
+            let first = !seen_command;
+            seen_command = true;
+            if trimmed == "vim9script" || trimmed == "vim9script noclear" {
+                if !first {
+                    return Err("E1039: \"vim9script\" must be the first command in a script".to_string());
+                }
+                self.scripts[sid].vim9 = true;
+                continue;
+            }
+            let (exported, cmd) = match trimmed.strip_prefix("export ") {
+                Some(rest) => {
+                    if !self.scripts[sid].vim9 {
+                        return Err("E1042: Export can only be used in vim9script".to_string());
+                    }
+                    (true, rest.trim_start())
+                }
+                None => (false, trimmed),
+            };
+            if let Some(rest) = cmd.strip_prefix("def ") {
+                let mut def = parse_def_header(rest)?;
+                loop {
+                    match lines.next() {
+                        Some((_, l)) if l.trim() == "enddef" => break,
+                        Some((_, l)) => def.body.push(l.to_string()),
+                        None => return Err(format!("E1057: Missing :enddef for {}", def.name)),
+                    }
+                }
+                self.add_function(sid, def, exported)?;
+            } else if let Some(rest) = cmd.strip_prefix("class ").or_else(|| cmd.strip_prefix("abstract class ")) {
+                let name = rest.split_whitespace().next().unwrap_or("").to_string();
+                loop {
+                    match lines.next() {
+                        Some((_, l)) if l.trim() == "endclass" => break,
+                        Some(_) => {}
+                        None => return Err(format!("E1065: Missing :endclass for {}", name)),
+                    }
+                }
+                self.add_item(sid, &name, ScriptItem::Class, exported)?;
+            } else if !exported && ex_command_name(cmd).is_some() {
+                // Script level code runs in order, so first run what comes
+                // before the command.  These commands see a "|" as the
+                // start of the next command.
+                for part in cmd.split('|').map(str::trim) {
+                    let part = part.strip_prefix(':').unwrap_or(part);
+                    match ex_command_name(part) {
+                        Some(name) => {
+                            self.run_script_level(sid, std::mem::take(&mut main))?;
+                            let arg = part.split_once(char::is_whitespace).map_or("", |(_, a)| a);
+                            self.ex_command(sid, name, arg)?;
+                        }
+                        None => main.push(part.to_string()),
+                    }
+                }
+            } else if let Some(rest) = cmd.strip_prefix("import ") {
+                if exported {
+                    return Err(format!("E1043: Invalid command after :export: {}", cmd));
+                }
+                self.add_import(sid, rest)
+                    .map_err(|e| format!("{}\nline {}: {}", e, lnum + 1, trimmed))?;
+            } else if exported {
+                let Some(decl) = ["var ", "const ", "final "].iter().find_map(|kw| cmd.strip_prefix(kw)) else {
+                    return Err(format!("E1043: Invalid command after :export: {}", cmd));
+                };
+                let name = decl
+                    .split(|c: char| c == ':' || c == '=' || c.is_whitespace())
+                    .next()
+                    .unwrap_or("")
+                    .to_string();
+                self.scripts[sid].exported_vars.push(name);
+                main.push(cmd.to_string());
+            } else {
+                main.push(line.to_string());
+            }
+        }
+        self.run_script_level(sid, main)
+    }
+
+    fn add_item(&mut self, sid: usize, name: &str, item: ScriptItem, exported: bool) -> Result<(), String> {
+        let script = &mut self.scripts[sid];
+        if script.items.contains_key(name) {
+            return Err(format!("E1073: Name already defined: {}", name));
+        }
+        script.items.insert(name.to_string(), (item, exported));
+        Ok(())
+    }
+
+    fn add_function(&mut self, sid: usize, mut def: DefSource, exported: bool) -> Result<(), String> {
+        let name = def.name.clone();
+        let compiled_name = if sid == 0 { name.clone() } else { format!("<SNR>{}_{}", sid, name) };
+        def.name = compiled_name.clone();
+        self.add_item(sid, &name, ScriptItem::Func(compiled_name.clone()), exported)?;
+        self.sigs.insert(compiled_name.clone(), def.signature());
+        if self.funcs.get(&compiled_name).is_none() {
+            self.pending.insert(compiled_name, (sid, def));
+        }
+        Ok(())
+    }
+
+    /// Handle `import [autoload] 'name' [as Alias]`.
+    fn add_import(&mut self, sid: usize, arg: &str) -> Result<(), String> {
+        let (autoload, arg) = match arg.trim().strip_prefix("autoload ") {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, arg.trim()),
+        };
+        let quote = arg.chars().next().filter(|c| *c == '\'' || *c == '"');
+        let Some(quote) = quote else {
+            return Err(format!("E1071: Invalid string for :import: {}", arg));
+        };
+        let end = arg[1..]
+            .find(quote)
+            .ok_or_else(|| format!("E1071: Invalid string for :import: {}", arg))?;
+        let name = &arg[1..end + 1];
+        let rest = arg[end + 2..].trim();
+        let alias = match rest.strip_prefix("as ") {
+            Some(a) => a.trim().to_string(),
+            None if rest.is_empty() => Path::new(name)
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("")
+                .to_string(),
+            None => return Err(format!("E488: Trailing characters: {}", rest)),
+        };
+        if alias.is_empty() || !alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("E1236: Cannot use {} itself, it is imported", alias));
+        }
+        if self.scripts[sid].imports.contains_key(&alias) || self.scripts[sid].items.contains_key(&alias) {
+            return Err(format!("E1073: Name already defined: {}", alias));
+        }
+        let path = if name.starts_with("./") || name.starts_with("../") {
+            let dir = self.scripts[sid].path.parent().map(Path::to_path_buf).unwrap_or_default();
+            Some(dir.join(name)).filter(|p| p.is_file())
+        } else if Path::new(name).is_absolute() {
+            Some(PathBuf::from(name)).filter(|p| p.is_file())
+        } else {
+            let sub = if autoload { "autoload" } else { "import" };
+            find_runtime_file(&self.runtimepath, &format!("{}/{}", sub, name))
+        };
+        let path = path.ok_or_else(|| format!("E1053: Could not import \"{}\"", name))?;
+        let import = if autoload {
+            Import::Autoload(path)
+        } else {
+            Import::Loaded(self.import_script(&path)?)
+        };
+        self.scripts[sid].imports.insert(alias, import);
+        Ok(())
+    }
+
+    fn run_script_level(&mut self, sid: usize, lines: Vec<String>) -> Result<(), String> {
+        let def = DefSource {
+            name: format!("<SNR>{}_", sid),
+            type_params: Vec::new(),
+            params: Vec::new(),
+            ret: Vim9Type::Void,
+            body: lines,
+        };
+        let mut ns = Namespace { ctx: self, sid };
+        let (func, referenced) = FuncCompiler::new_script_level(&mut ns).compile(&def)?;
+        for name in &referenced {
+            self.ensure_compiled(name)?;
+        }
+        self.run_code(func.instrs).map(|_| ())
+    }
+
+    /// Resolve `alias` in script `sid` to the script ID of the imported
+    /// script, loading an autoload script on first use.
+    fn imported_script(&mut self, sid: usize, alias: &str) -> Result<Option<usize>, String> {
+        match self.scripts[sid].imports.get(alias).cloned() {
+            Some(Import::Loaded(target)) => Ok(Some(target)),
+            Some(Import::Autoload(path)) => {
+                let target = self.import_script(&path)?;
+                self.scripts[sid].imports.insert(alias.to_string(), Import::Loaded(target));
+                Ok(Some(target))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Find the item `name` as seen from script `sid`: a local item or
+    /// `alias.item` of an imported script.
+    fn lookup(&mut self, sid: usize, name: &str) -> Result<Option<(usize, ScriptItem)>, String> {
+        if let Some((alias, item)) = name.split_once('.') {
+            let Some(target) = self.imported_script(sid, alias)? else {
+                return Ok(None);
+            };
+            return match self.scripts[target].items.get(item) {
+                Some((found, true)) => Ok(Some((target, found.clone()))),
+                Some((_, false)) => Err(format!("E1049: Item not exported in script: {}", item)),
+                None => Err(format!("E1048: Item not found in script: {}", item)),
+            };
+        }
+        Ok(self.scripts[sid].items.get(name).map(|(item, _)| (sid, item.clone())))
+    }
+}
+
+/// Commands executed right away at the script level, with the length of
+/// their shortest abbreviation.
+const EX_COMMANDS: [(&str, usize); 5] =
+    [("disassemble", 4), ("profile", 4), ("breakadd", 6), ("breakdel", 6), ("breaklist", 6)];
+
+/// The full name of `cmd` when it is one of [`EX_COMMANDS`].
+fn ex_command_name(cmd: &str) -> Option<&'static str> {
+    let word = cmd.split_whitespace().next().unwrap_or("");
+    EX_COMMANDS
+        .iter()
+        .find(|(name, min)| word.len() >= *min && name.starts_with(word))
+        .map(|(name, _)| *name)
+}
+
+/// The names visible from one script while compiling.
+struct Namespace<'c> {
+    ctx: &'c mut Vim9Context,
+    sid: usize,
+}
+
+impl Resolver for Namespace<'_> {
+    fn signature(&mut self, name: &str) -> Result<Option<Signature>, String> {
+        match self.ctx.lookup(self.sid, name)? {
+            Some((_, ScriptItem::Func(compiled))) => Ok(self.ctx.sigs.get(&compiled).cloned()),
+            Some(_) => Ok(None),
+            // Global functions are visible everywhere.
+            None => Ok(self.ctx.sigs.get(name).cloned()),
+        }
+    }
+
+    fn script_var(&mut self, name: &str) -> Result<Option<ScriptVar>, String> {
+        match self.ctx.lookup(self.sid, name)? {
+            Some((sid, ScriptItem::Var(idx))) => {
+                let (_, ty, is_const) = self.ctx.scripts[sid].vars[idx].clone();
+                Ok(Some(ScriptVar { sid, idx, ty, is_const }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn declare_script_var(&mut self, name: &str, ty: Vim9Type, is_const: bool) -> Result<ScriptVar, String> {
+        let script = &mut self.ctx.scripts[self.sid];
+        let exported = script.exported_vars.iter().any(|n| n == name);
+        let idx = script.vars.len();
+        script.vars.push((name.to_string(), ty.clone(), is_const));
+        self.ctx.add_item(self.sid, name, ScriptItem::Var(idx), exported)?;
+        Ok(ScriptVar { sid: self.sid, idx, ty, is_const })
+    }
+
+    fn instances(&mut self) -> &mut InstanceCache {
+        &mut self.ctx.instances
+    }
+}
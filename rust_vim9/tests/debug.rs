@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+
+use rust_vim9::{DebugInput, Vim9Context, Vim9Value};
+
+const FUNCS: &str = "
+def Inner(x: number): number
+  var y = x * 2
+  return y + 1
+enddef
+
+def Outer(): number
+  var a = 5
+  var r = Inner(a)
+  return r
+enddef
+";
+
+fn input(cmds: &[&str]) -> Box<dyn DebugInput> {
+    Box::new(cmds.iter().map(|c| c.to_string()).collect::<VecDeque<_>>())
+}
+
+#[test]
+fn breakpoint_backtrace_and_inspection() {
+    let mut ctx = Vim9Context::new();
+    ctx.define(FUNCS).unwrap();
+    ctx.ex_breakadd("func 2 Inner").unwrap();
+    ctx.set_debug_input(input(&[
+        "echo x y", "bt", "up", "echo a", "echo zz", "down", "next", "echo r", "cont",
+    ]));
+    assert_eq!(ctx.call("Outer", Vec::new()).unwrap(), Vim9Value::Number(11));
+    assert_eq!(
+        ctx.output(),
+        [
+            "Breakpoint in \"Inner\" line 2",
+            "Entering Debug mode.  Type \"cont\" to continue.",
+            "function Outer[2]..Inner",
+            "line 2: return y + 1",
+            "5 10",
+            "  1 function Outer[2]",
+            "->0 Inner",
+            "line 2: return y + 1",
+            "->1 function Outer[2]",
+            "  0 Inner",
+            "line 2: var r = Inner(a)",
+            "5",
+            "E121: Undefined variable: zz",
+            "  1 function Outer[2]",
+            "->0 Inner",
+            "line 2: return y + 1",
+            "Entering Debug mode.  Type \"cont\" to continue.",
+            "function Outer",
+            "line 3: return r",
+            "11",
+        ]
+    );
+}
+
+#[test]
+fn step_finish_and_quit() {
+    let mut ctx = Vim9Context::new();
+    ctx.define(FUNCS).unwrap();
+    ctx.ex_breakadd("func Outer 2").unwrap();
+    ctx.set_debug_input(input(&["step", "finish", "quit"]));
+    assert_eq!(ctx.call("Outer", Vec::new()).unwrap_err(), "Interrupted");
+    let stops: Vec<&String> = ctx.output().iter().filter(|l| l.starts_with("line ")).collect();
+    assert_eq!(stops, ["line 2: var r = Inner(a)", "line 1: var y = x * 2", "line 3: return r"]);
+
+    // Execution is not stopped after the breakpoint was deleted.
+    ctx.ex_breakdel("func Outer").unwrap();
+    ctx.ex_breaklist();
+    assert_eq!(ctx.output().last().unwrap(), "No breakpoints defined");
+    assert_eq!(ctx.call("Outer", Vec::new()).unwrap(), Vim9Value::Number(11));
+}
@@ -0,0 +1,90 @@
+use std::fs;
+
+use rust_vim9::Vim9Context;
+
+#[test]
+fn disassemble_global_function() {
+    let mut ctx = Vim9Context::new();
+    ctx.define(
+        "def Count(l: list<number>): number
+  var total = 0
+  for n in l
+    total += n
+  endfor
+  return total
+enddef
+def Show(n: number)
+  echo Count([n, n])
+enddef",
+    )
+    .unwrap();
+    let listing = ctx.disassemble(0, "Count").unwrap();
+    let expected = "\
+Count(l: list<number>): number
+  var total = 0
+   0 PUSHNR 0                         +1  number
+   1 STORE $1 total                   -1  number
+  for n in l
+   2 LOAD $0 l                        +1  list<number>
+   3 STORE $2                         -1  any
+   4 PUSHNR 0                         +1  number
+   5 STORE $3                         -1  any
+   6 FOR $2 -> 13                     +1
+   7 STORE $4 n                       -1  number
+  total += n
+   8 LOAD $1 total                    +1  number
+   9 LOAD $4 n                        +1  number
+  10 OPANY +                          -1
+  11 STORE $1 total                   -1  number
+  endfor
+  12 JUMP -> 6                        +0
+  return total
+  13 LOAD $1 total                    +1  number
+  14 RETURN                           -1
+";
+    assert_eq!(listing, expected);
+
+    let show = ctx.disassemble(0, "debug Show").unwrap();
+    let lines: Vec<&str> = show.lines().collect();
+    assert_eq!(lines[0], "Show(n: number)");
+    assert_eq!(lines[1], "  echo Count([n, n])");
+    assert_eq!(lines[2], "     DEBUG line 1");
+    assert!(lines.iter().any(|l| l.contains("DCALL Count(argc 1)")));
+    assert_eq!(&lines[lines.len() - 3..], ["  enddef", "     DEBUG line 2", "   5 RETURN void                      +0"]);
+
+    assert_eq!(ctx.disassemble(0, "Missing").unwrap_err(), "E1061: Cannot find function Missing");
+    assert_eq!(ctx.disassemble(0, "profile").unwrap_err(), "E471: Argument required");
+}
+
+#[test]
+fn disassemble_command_in_script() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("s.vim");
+    fs::write(
+        &path,
+        "vim9script
+def Twice(x: number): number
+  return x * 2
+enddef
+echo Twice(2)
+disassemble profile Twice
+echo 'done'
+",
+    )
+    .unwrap();
+    let mut ctx = Vim9Context::new();
+    let sid = ctx.source_file(&path).unwrap();
+    let expected = [
+        "4".to_string(),
+        format!("<SNR>{}_Twice(x: number): number", sid),
+        "  return x * 2".to_string(),
+        "     PROFILE START line 1".to_string(),
+        "   0 LOAD $0 x                        +1  number".to_string(),
+        "   1 PUSHNR 2                         +1  number".to_string(),
+        "   2 OPANY *                          -1".to_string(),
+        "   3 RETURN                           -1".to_string(),
+        "     PROFILE END".to_string(),
+        "done".to_string(),
+    ];
+    assert_eq!(ctx.output(), expected);
+}
@@ -0,0 +1,95 @@
+use rust_vim9::{Vim9Context, Vim9Value};
+
+const SCRIPT: &str = "
+def Map<T, U>(l: list<T>, F: func(T): U): list<U>
+  var result: list<U> = []
+  for item in l
+    add(result, F(item))
+  endfor
+  return result
+enddef
+
+def Double(n: number): number
+  return n * 2
+enddef
+
+def Show(n: number): string
+  return 'n' .. n
+enddef
+
+def First<T>(l: list<T>): T
+  return l[0]
+enddef
+";
+
+fn context() -> Vim9Context {
+    let mut ctx = Vim9Context::new();
+    ctx.define(SCRIPT).unwrap();
+    ctx
+}
+
+#[test]
+fn infers_type_arguments() {
+    let mut ctx = context();
+    let res = ctx.eval("Map([1, 2, 3], Double)").unwrap();
+    assert_eq!(res.to_string(), "[2, 4, 6]");
+    let res = ctx.eval("Map([1, 2], Show)").unwrap();
+    assert_eq!(res.to_string(), "['n1', 'n2']");
+    assert_eq!(ctx.eval("First(['a', 'b']) .. 'c'").unwrap(), Vim9Value::String("ac".to_string()));
+}
+
+#[test]
+fn explicit_instantiation() {
+    let mut ctx = context();
+    let res = ctx.eval("Map<number, string>([4], Show)").unwrap();
+    assert_eq!(res.to_string(), "['n4']");
+    assert_eq!(ctx.instances().len(), 1);
+    let err = ctx.eval("Map<string, string>([4], Show)").unwrap_err();
+    assert_eq!(err, "E1013: Argument 1: type mismatch, expected list<string> but got list<number>");
+    let err = ctx.eval("Map<number>([4], Show)").unwrap_err();
+    assert!(err.starts_with("E1557:"), "{}", err);
+    let err = ctx.eval("Double<number>(4)").unwrap_err();
+    assert!(err.starts_with("E1560:"), "{}", err);
+}
+
+#[test]
+fn generic_calls_inside_functions() {
+    let mut ctx = context();
+    ctx.define(
+        "
+def DoubleAll(l: list<number>): list<number>
+  return Map<number, number>(l, Double)
+enddef
+
+def Bad(): number
+  return First(['x'])
+enddef
+",
+    )
+    .unwrap_err();
+    ctx.define(
+        "
+def DoubleAll(l: list<number>): list<number>
+  return Map<number, number>(l, Double)
+enddef
+",
+    )
+    .unwrap();
+    let list = Vim9Value::list(vec![Vim9Value::Number(5)]);
+    assert_eq!(ctx.call("DoubleAll", vec![list]).unwrap().to_string(), "[10]");
+}
+
+#[test]
+fn type_errors_in_generic_body() {
+    let mut ctx = Vim9Context::new();
+    let err = ctx
+        .define(
+            "
+def Wrong<T>(x: T): T
+  return 1
+enddef
+",
+        )
+        .unwrap_err();
+    assert!(err.starts_with("E1012: Type mismatch; expected T but got number"), "{}", err);
+}
@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use rust_vim9::{ScriptItem, Vim9Context, Vim9Value};
+
+fn write(path: &Path, text: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, text).unwrap();
+}
+
+#[test]
+fn imports_exported_items() {
+    let dir = tempfile::tempdir().unwrap();
+    write(
+        &dir.path().join("util.vim"),
+        "vim9script
+export const GREETING = 'hello'
+export var count = 1
+var hidden = 2
+
+export def Greet(name: string): string
+  count += 1
+  return GREETING .. ' ' .. name
+enddef
+
+def Local(): number
+  return hidden
+enddef
+",
+    );
+    let main = dir.path().join("main.vim");
+    write(
+        &main,
+        "vim9script
+import './util.vim' as util
+
+var msg = util.Greet('vim')
+echo msg
+echo util.count
+",
+    );
+    let mut ctx = Vim9Context::new();
+    let sid = ctx.source_file(&main).unwrap();
+    assert_eq!(ctx.output(), ["hello vim", "2"]);
+    assert_eq!(ctx.script_var(sid, "msg"), Some(Vim9Value::String("hello vim".to_string())));
+
+    let util = ctx.script_id(&dir.path().join("util.vim")).unwrap();
+    assert_ne!(util, sid);
+    assert_eq!(
+        ctx.script_item(util, "Greet"),
+        Some((ScriptItem::Func(format!("<SNR>{}_Greet", util)), true))
+    );
+    // Script-local functions do not clash with functions of other scripts.
+    assert!(ctx.eval("Greet('x')").is_err());
+    assert_eq!(ctx.eval_in_script(util, "Local()").unwrap(), Vim9Value::Number(2));
+
+    assert_eq!(
+        ctx.eval_in_script(sid, "util.Local()").unwrap_err(),
+        "E1049: Item not exported in script: Local"
+    );
+    assert_eq!(
+        ctx.eval_in_script(sid, "util.Missing()").unwrap_err(),
+        "E1048: Item not found in script: Missing"
+    );
+}
+
+#[test]
+fn import_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let main = dir.path().join("main.vim");
+    write(&main, "vim9script\nimport './nothere.vim' as x\n");
+    let mut ctx = Vim9Context::new();
+    let err = ctx.source_file(&main).unwrap_err();
+    assert!(err.starts_with("E1053: Could not import \"./nothere.vim\""), "{}", err);
+
+    write(&main, "export var x = 1\n");
+    assert_eq!(ctx.source_file(&main).unwrap_err(), "E1042: Export can only be used in vim9script");
+
+    write(&main, "vim9script\nconst LIMIT = 3\ndef Change()\n  LIMIT = 4\nenddef\nChange()\n");
+    let err = ctx.source_file(&main).unwrap_err();
+    assert!(err.starts_with("E1018: Cannot assign to a constant: LIMIT"), "{}", err);
+}
+
+#[test]
+fn autoload_is_loaded_lazily_from_runtimepath() {
+    let rtp = tempfile::tempdir().unwrap();
+    let modfile = rtp.path().join("autoload").join("pkg").join("mod.vim");
+    write(
+        &modfile,
+        "vim9script
+echo 'mod loaded'
+export def Twice(n: number): number
+  return n * 2
+enddef
+",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let main = dir.path().join("main.vim");
+    write(
+        &main,
+        "vim9script
+import autoload 'pkg/mod.vim'
+
+def Use(): number
+  return mod.Twice(21)
+enddef
+echo 'main done'
+",
+    );
+    let mut ctx = Vim9Context::new();
+    ctx.set_runtimepath(&rtp.path().display().to_string());
+    let sid = ctx.source_file(&main).unwrap();
+    assert_eq!(ctx.output(), ["main done"]);
+    assert!(ctx.script_id(&modfile).is_none());
+
+    assert_eq!(ctx.eval_in_script(sid, "Use()").unwrap(), Vim9Value::Number(42));
+    assert_eq!(ctx.output(), ["main done", "mod loaded"]);
+    assert!(ctx.script_id(&modfile).is_some());
+}
+
+#[test]
+fn resourcing_keeps_compiled_functions_when_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("s.vim");
+    write(&path, "vim9script\nvar n = 5\ndef F(): number\n  return n\nenddef\n");
+    let mut ctx = Vim9Context::new();
+    let sid = ctx.source_file(&path).unwrap();
+    let name = format!("<SNR>{}_F", sid);
+    assert_eq!(ctx.call(&name, Vec::new()).unwrap(), Vim9Value::Number(5));
+    let first = ctx.func(&name).unwrap().clone();
+
+    assert_eq!(ctx.source_file(&path).unwrap(), sid);
+    assert!(Rc::ptr_eq(&first, ctx.func(&name).unwrap()));
+
+    write(&path, "vim9script\nvar n = 7\ndef F(): number\n  return n + 1\nenddef\n");
+    ctx.source_file(&path).unwrap();
+    assert!(!Rc::ptr_eq(&first, ctx.func(&name).unwrap()));
+    assert_eq!(ctx.call(&name, Vec::new()).unwrap(), Vim9Value::Number(8));
+}
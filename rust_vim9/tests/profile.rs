@@ -0,0 +1,52 @@
+use std::fs;
+
+use rust_vim9::{Vim9Context, Vim9Value};
+
+#[test]
+fn profile_counts_calls_and_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("profile.txt");
+    let script = dir.path().join("s.vim");
+    fs::write(
+        &script,
+        format!(
+            "vim9script
+profile start {} | profile func *
+def Add(a: number, b: number): number
+  var s = a + b
+  return s
+enddef
+def Twice(n: number): number
+  return Add(n, n)
+enddef
+echo Twice(3)
+echo Twice(4)
+profile dump
+",
+            out.display()
+        ),
+    )
+    .unwrap();
+    let mut ctx = Vim9Context::new();
+    let sid = ctx.source_file(&script).unwrap();
+    assert_eq!(ctx.output(), ["6", "8"]);
+
+    let prof = ctx.profiler().unwrap();
+    let add = prof.func(&format!("<SNR>{}_Add", sid)).unwrap();
+    assert_eq!(add.count, 2);
+    assert_eq!(add.lines.iter().map(|l| l.count).collect::<Vec<_>>(), [2, 2]);
+    let twice = prof.func(&format!("<SNR>{}_Twice", sid)).unwrap();
+    assert_eq!(twice.count, 2);
+    // The time spent in Add() is not part of the self time of Twice().
+    assert!(twice.self_time <= twice.total - add.total);
+    assert!(twice.lines[0].total <= twice.total);
+
+    let text = fs::read_to_string(&out).unwrap();
+    assert!(text.contains(&format!("FUNCTION  <SNR>{}_Add()\nCalled 2 times\n", sid)), "{}", text);
+    assert!(text.contains("FUNCTIONS SORTED ON TOTAL TIME"));
+
+    ctx.ex_profile("pause").unwrap();
+    ctx.call(&format!("<SNR>{}_Add", sid), vec![Vim9Value::Number(1), Vim9Value::Number(2)]).unwrap();
+    assert_eq!(ctx.profiler().unwrap().func(&format!("<SNR>{}_Add", sid)).unwrap().count, 2);
+    assert!(ctx.ex_profile("bogus").unwrap_err().starts_with("E475:"));
+}
@@ -0,0 +1,230 @@
+//! Profiling and debugging hooks of the interpreter.  Every call pushes a
+//! [`CallFrame`]; when a profiler or debugger is active the interpreter
+//! reports each line it starts executing to [`Interpreter::line_event`].
+
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use rust_debugger::{DebugCommand, StepMode};
+
+use crate::{CompiledFunc, Interpreter, Vim9Value};
+
+/// A function being executed, for the backtrace of the debugger.
+pub(crate) struct CallFrame {
+    func: Rc<CompiledFunc>,
+    /// The line executed last, zero based.
+    line: usize,
+    /// The local variables at the start of that line.  Only kept while
+    /// debugging, to inspect variables of calling functions.
+    locals: Vec<Vim9Value>,
+    prof: Option<ProfTimer>,
+}
+
+/// Timing of a call of a profiled function.
+struct ProfTimer {
+    start: Instant,
+    /// Time spent in profiled functions called from this one.
+    children: Duration,
+    line: Option<usize>,
+    line_start: Instant,
+    line_children: Duration,
+}
+
+impl ProfTimer {
+    /// Finish timing the current line, returning it with its total and
+    /// self time.
+    fn end_line(&mut self, now: Instant) -> Option<(usize, Duration, Duration)> {
+        let line = self.line.take()?;
+        let total = now - self.line_start;
+        Some((line, total, total.saturating_sub(self.line_children)))
+    }
+}
+
+impl Interpreter {
+    pub(crate) fn enter_func(&mut self, func: &Rc<CompiledFunc>) {
+        let profiled = self.profiler.as_ref().is_some_and(|p| p.profiles(&func.name));
+        let now = Instant::now();
+        self.frames.push(CallFrame {
+            func: func.clone(),
+            line: 0,
+            locals: Vec::new(),
+            prof: profiled.then_some(ProfTimer {
+                start: now,
+                children: Duration::ZERO,
+                line: None,
+                line_start: now,
+                line_children: Duration::ZERO,
+            }),
+        });
+    }
+
+    pub(crate) fn leave_func(&mut self) {
+        let Some(frame) = self.frames.pop() else { return };
+        let Some(mut timer) = frame.prof else { return };
+        let now = Instant::now();
+        let total = now - timer.start;
+        if let Some(profiler) = self.profiler.as_mut() {
+            let func = &frame.func;
+            if let Some((line, t, s)) = timer.end_line(now) {
+                profiler.add_line(&func.name, &func.source, line, t, s);
+            }
+            profiler.add_call(&func.name, &func.source, total, total.saturating_sub(timer.children));
+        }
+        if let Some(caller) = self.frames.last_mut().and_then(|f| f.prof.as_mut()) {
+            caller.children += total;
+            caller.line_children += total;
+        }
+    }
+
+    /// Called when execution of line `line` of the current function starts.
+    pub(crate) fn line_event(&mut self, line: usize, locals: &[Vim9Value]) -> Result<(), String> {
+        let debugging = self.debugger.is_some();
+        let Some(frame) = self.frames.last_mut() else { return Ok(()) };
+        frame.line = line;
+        if debugging {
+            frame.locals = locals.to_vec();
+        }
+        if let Some(timer) = frame.prof.as_mut() {
+            let now = Instant::now();
+            let ended = timer.end_line(now);
+            timer.line = Some(line);
+            timer.line_start = now;
+            timer.line_children = Duration::ZERO;
+            if let (Some((l, t, s)), Some(profiler)) = (ended, self.profiler.as_mut()) {
+                profiler.add_line(&frame.func.name, &frame.func.source, l, t, s);
+            }
+        }
+        let Some(debugger) = self.debugger.as_ref() else { return Ok(()) };
+        let name = &frame.func.name;
+        if name.is_empty() {
+            return Ok(());
+        }
+        let breakpoint = debugger.breakpoint_at(name, line + 1);
+        if breakpoint || debugger.step_stop(self.depth) {
+            if breakpoint {
+                let msg = format!("Breakpoint in \"{}\" line {}", name, line + 1);
+                self.message(msg);
+            }
+            self.debug_prompt()?;
+        }
+        Ok(())
+    }
+
+    fn message(&mut self, msg: String) {
+        self.output.push(msg);
+    }
+
+    /// Indexes in `frames` of the frames shown in a backtrace, innermost
+    /// first.  Script level code has no name and is not shown.
+    fn named_frames(&self) -> Vec<usize> {
+        (0..self.frames.len()).rev().filter(|&i| !self.frames[i].func.name.is_empty()).collect()
+    }
+
+    fn line_message(&self, idx: usize) -> String {
+        let frame = &self.frames[idx];
+        let text = frame.func.source.get(frame.line).map_or("enddef", |l| l.trim());
+        format!("line {}: {}", frame.line + 1, text)
+    }
+
+    /// Enter the debug prompt and execute commands until one of them
+    /// continues execution.
+    fn debug_prompt(&mut self) -> Result<(), String> {
+        let frames = self.named_frames();
+        let chain = frames
+            .iter()
+            .rev()
+            .map(|&i| {
+                let f = &self.frames[i];
+                if i == frames[0] { f.func.name.clone() } else { format!("{}[{}]", f.func.name, f.line + 1) }
+            })
+            .collect::<Vec<_>>()
+            .join("..");
+        self.message("Entering Debug mode.  Type \"cont\" to continue.".to_string());
+        self.message(format!("function {}", chain));
+        self.message(self.line_message(frames[0]));
+        let mut selected = 0;
+        loop {
+            let debugger = self.debugger.as_mut().expect("debug prompt without debugger");
+            let mode = match debugger.read_command() {
+                DebugCommand::Cont => StepMode::Run,
+                DebugCommand::Step => StepMode::Step,
+                DebugCommand::Next => StepMode::Next(self.depth),
+                DebugCommand::Finish => StepMode::Finish(self.depth),
+                DebugCommand::Quit => {
+                    debugger.mode = StepMode::Run;
+                    return Err("Interrupted".to_string());
+                }
+                DebugCommand::Backtrace => {
+                    self.backtrace(&frames, selected);
+                    continue;
+                }
+                DebugCommand::Up(n) => {
+                    if selected + 1 >= frames.len() {
+                        self.message(format!("frame at highest level: {}", selected));
+                    } else {
+                        selected = (selected + n).min(frames.len() - 1);
+                        self.backtrace(&frames, selected);
+                    }
+                    continue;
+                }
+                DebugCommand::Down(n) => {
+                    if selected == 0 {
+                        self.message("frame is zero".to_string());
+                    } else {
+                        selected = selected.saturating_sub(n);
+                        self.backtrace(&frames, selected);
+                    }
+                    continue;
+                }
+                DebugCommand::Frame(n) => {
+                    selected = n.min(frames.len() - 1);
+                    self.backtrace(&frames, selected);
+                    continue;
+                }
+                DebugCommand::Ex(cmd) => {
+                    let msg = self.debug_ex(&cmd, frames[selected]).unwrap_or_else(|e| e);
+                    self.message(msg);
+                    continue;
+                }
+            };
+            debugger.mode = mode;
+            return Ok(());
+        }
+    }
+
+    fn backtrace(&mut self, frames: &[usize], selected: usize) {
+        for (level, &idx) in frames.iter().enumerate().rev() {
+            let frame = &self.frames[idx];
+            let marker = if level == selected { "->" } else { "  " };
+            let msg = if level == 0 {
+                format!("{}{} {}", marker, level, frame.func.name)
+            } else {
+                format!("{}{} function {}[{}]", marker, level, frame.func.name, frame.line + 1)
+            };
+            self.message(msg);
+        }
+        self.message(self.line_message(frames[selected]));
+    }
+
+    /// Execute a command typed at the debug prompt in the context of frame
+    /// `idx`.  Only `:echo` of local variables is supported.
+    fn debug_ex(&self, cmd: &str, idx: usize) -> Result<String, String> {
+        let (name, arg) = cmd.split_once(char::is_whitespace).unwrap_or((cmd, ""));
+        if name.len() < 2 || !"echo".starts_with(name) {
+            return Err(format!("E492: Not an editor command: {}", cmd));
+        }
+        let frame = &self.frames[idx];
+        let mut values = Vec::new();
+        for var in arg.split(|c: char| c == ',' || c.is_whitespace()).filter(|v| !v.is_empty()) {
+            let value = frame
+                .func
+                .slots
+                .iter()
+                .rposition(|(slot, _)| slot == var)
+                .and_then(|i| frame.locals.get(i))
+                .ok_or_else(|| format!("E121: Undefined variable: {}", var))?;
+            values.push(value.to_string());
+        }
+        Ok(values.join(" "))
+    }
+}
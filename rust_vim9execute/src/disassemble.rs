@@ -0,0 +1,203 @@
+//! `:disassemble`: a listing of the instructions of a compiled function.
+//!
+//! Each instruction is shown with its index, its stack effect and, where it
+//! is known, the type of the value it pushes or stores.  The source lines
+//! are interleaved before the instructions compiled from them.
+
+use std::fmt::Write;
+
+use rust_vim9instr::Vim9Instr;
+use rust_vim9type::Vim9Type;
+
+use crate::{CompiledFunc, Vim9Program};
+
+/// What `:disassemble` shows besides the instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisassembleMode {
+    #[default]
+    Normal,
+    /// `:disassemble debug`: where the debugger checks for breakpoints, at
+    /// the start of every line.
+    Debug,
+    /// `:disassemble profile`: where the time spent in each line is
+    /// measured.
+    Profile,
+}
+
+impl DisassembleMode {
+    /// Split an optional "debug" or "profile" argument off `arg`.
+    pub fn parse(arg: &str) -> (Self, &str) {
+        let arg = arg.trim();
+        let (first, rest) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+        match first {
+            "debug" => (DisassembleMode::Debug, rest.trim()),
+            "profile" => (DisassembleMode::Profile, rest.trim()),
+            _ => (DisassembleMode::Normal, arg),
+        }
+    }
+}
+
+/// The type of the value `instr` pushes or stores, when it is known
+/// without running the code.
+fn instr_type(instr: &Vim9Instr, slots: &[(String, Vim9Type)]) -> Option<Vim9Type> {
+    match instr {
+        Vim9Instr::PushNumber(_) => Some(Vim9Type::Number),
+        Vim9Instr::PushFloat(_) => Some(Vim9Type::Float),
+        Vim9Instr::PushString(_) | Vim9Instr::Concat => Some(Vim9Type::String),
+        Vim9Instr::PushBool(_) | Vim9Instr::Not => Some(Vim9Type::Bool),
+        Vim9Instr::CompareLT
+        | Vim9Instr::CompareGT
+        | Vim9Instr::CompareLE
+        | Vim9Instr::CompareGE
+        | Vim9Instr::CompareEQ
+        | Vim9Instr::CompareNE => Some(Vim9Type::Bool),
+        Vim9Instr::LoadLocal(slot) | Vim9Instr::StoreLocal(slot) => slots.get(*slot).map(|(_, ty)| ty.clone()),
+        Vim9Instr::CheckType { ty, .. } => Some(ty.clone()),
+        _ => None,
+    }
+}
+
+fn write_instr(out: &mut String, idx: usize, instr: &Vim9Instr, slots: &[(String, Vim9Type)]) {
+    let mut text = instr.to_string();
+    if let Vim9Instr::LoadLocal(slot) | Vim9Instr::StoreLocal(slot) = instr {
+        if let Some((name, _)) = slots.get(*slot).filter(|(name, _)| !name.is_empty()) {
+            let _ = write!(text, " {}", name);
+        }
+    }
+    let ty = instr_type(instr, slots).map(|t| t.to_string()).unwrap_or_default();
+    let line = format!("{:4} {:<32} {:+}  {}", idx, text, instr.stack_effect(), ty);
+    let _ = writeln!(out, "{}", line.trim_end());
+}
+
+impl CompiledFunc {
+    /// The `:disassemble` listing of the function.
+    pub fn disassemble(&self, mode: DisassembleMode) -> String {
+        let mut out = String::new();
+        let params: Vec<String> = self.params.iter().map(|(n, t)| format!("{}: {}", n, t)).collect();
+        let _ = write!(out, "{}({})", self.name, params.join(", "));
+        if self.ret != Vim9Type::Void {
+            let _ = write!(out, ": {}", self.ret);
+        }
+        out.push('\n');
+        // Index of the next source line to show.
+        let mut next_line = 0;
+        let mut current = None;
+        for (idx, instr) in self.instrs.iter().enumerate() {
+            let line = self.lines.get(idx).copied().filter(|l| Some(*l) != current);
+            if let Some(line) = line {
+                if current.is_some() && mode == DisassembleMode::Profile {
+                    out.push_str("     PROFILE END\n");
+                }
+                while next_line <= line {
+                    match self.source.get(next_line) {
+                        Some(text) if !text.trim().is_empty() => {
+                            let _ = writeln!(out, "  {}", text.trim());
+                        }
+                        Some(_) => {}
+                        None => out.push_str("  enddef\n"),
+                    }
+                    next_line += 1;
+                }
+                match mode {
+                    DisassembleMode::Normal => {}
+                    DisassembleMode::Debug => {
+                        let _ = writeln!(out, "     DEBUG line {}", line + 1);
+                    }
+                    DisassembleMode::Profile => {
+                        let _ = writeln!(out, "     PROFILE START line {}", line + 1);
+                    }
+                }
+                current = Some(line);
+            }
+            write_instr(&mut out, idx, instr, &self.slots);
+        }
+        if current.is_some() && mode == DisassembleMode::Profile {
+            out.push_str("     PROFILE END\n");
+        }
+        out
+    }
+}
+
+impl Vim9Program {
+    /// The `:disassemble` listing of a compiled expression.
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        for (idx, instr) in self.instrs.iter().enumerate() {
+            write_instr(&mut out, idx, instr, &[]);
+        }
+        let _ = writeln!(out, "result: {}", self.result_type);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn func() -> CompiledFunc {
+        CompiledFunc {
+            name: "Inc".to_string(),
+            type_params: Vec::new(),
+            params: vec![("n".to_string(), Vim9Type::Number)],
+            ret: Vim9Type::Number,
+            locals: 2,
+            instrs: vec![
+                Vim9Instr::LoadLocal(0),
+                Vim9Instr::PushNumber(1),
+                Vim9Instr::Add,
+                Vim9Instr::StoreLocal(1),
+                Vim9Instr::LoadLocal(1),
+                Vim9Instr::Return,
+            ],
+            slots: vec![("n".to_string(), Vim9Type::Number), ("m".to_string(), Vim9Type::Number)],
+            source: vec!["  var m = n + 1".to_string(), "".to_string(), "  return m".to_string()],
+            lines: vec![0, 0, 0, 0, 2, 2],
+        }
+    }
+
+    #[test]
+    fn interleaves_source_lines() {
+        let expected = "\
+Inc(n: number): number
+  var m = n + 1
+   0 LOAD $0 n                        +1  number
+   1 PUSHNR 1                         +1  number
+   2 OPANY +                          -1
+   3 STORE $1 m                       -1  number
+  return m
+   4 LOAD $1 m                        +1  number
+   5 RETURN                           -1
+";
+        assert_eq!(func().disassemble(DisassembleMode::Normal), expected);
+    }
+
+    #[test]
+    fn debug_and_profile_variants() {
+        let debug = func().disassemble(DisassembleMode::Debug);
+        let lines: Vec<&str> = debug.lines().collect();
+        assert_eq!(lines[1], "  var m = n + 1");
+        assert_eq!(lines[2], "     DEBUG line 1");
+        assert_eq!(lines[8], "     DEBUG line 3");
+
+        let profile = func().disassemble(DisassembleMode::Profile);
+        let marks: Vec<&str> = profile.lines().filter(|l| l.starts_with("     PROFILE")).collect();
+        assert_eq!(
+            marks,
+            ["     PROFILE START line 1", "     PROFILE END", "     PROFILE START line 3", "     PROFILE END"]
+        );
+        assert_eq!(DisassembleMode::parse("profile  Inc"), (DisassembleMode::Profile, "Inc"));
+        assert_eq!(DisassembleMode::parse("Inc"), (DisassembleMode::Normal, "Inc"));
+    }
+
+    #[test]
+    fn program_listing() {
+        let prog = Vim9Program {
+            instrs: vec![Vim9Instr::PushNumber(1), Vim9Instr::PushNumber(2), Vim9Instr::CompareLT],
+            result_type: Vim9Type::Bool,
+        };
+        assert_eq!(
+            prog.disassemble(),
+            "   0 PUSHNR 1                         +1  number\n   1 PUSHNR 2                         +1  number\n   2 COMPAREANY <                     -1  bool\nresult: bool\n"
+        );
+    }
+}
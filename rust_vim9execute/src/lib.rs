@@ -1,15 +1,206 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use rust_debugger::Debugger;
+use rust_profiler::Profiler;
 use rust_vim9instr::Vim9Instr;
 use rust_vim9type::Vim9Type;
 
+mod debug;
+mod disassemble;
+
+pub use disassemble::DisassembleMode;
+
 #[derive(Debug, Clone)]
 pub struct Vim9Program {
     pub instrs: Vec<Vim9Instr>,
     pub result_type: Vim9Type,
 }
 
+/// A runtime value.  Lists have reference semantics like in Vim: copies of a
+/// `List` value share the same items.
+#[derive(Debug, Clone)]
+pub enum Vim9Value {
+    Void,
+    Number(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    List(Rc<RefCell<Vec<Vim9Value>>>),
+    Func(String),
+}
+
+impl Vim9Value {
+    pub fn list(items: Vec<Vim9Value>) -> Self {
+        Vim9Value::List(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn as_number(&self) -> i64 {
+        match self {
+            Vim9Value::Number(n) => *n,
+            Vim9Value::Float(f) => *f as i64,
+            Vim9Value::Bool(b) => *b as i64,
+            Vim9Value::String(s) => s.trim().parse().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Vim9Value::Void => false,
+            Vim9Value::Number(n) => *n != 0,
+            Vim9Value::Float(f) => *f != 0.0,
+            Vim9Value::Bool(b) => *b,
+            Vim9Value::String(s) => !s.is_empty(),
+            Vim9Value::List(l) => !l.borrow().is_empty(),
+            Vim9Value::Func(_) => true,
+        }
+    }
+
+    /// The type of the value as far as it can be determined at runtime.
+    /// Lists take their member type from the items, an empty list has
+    /// `list<any>`.  Funcrefs are typed by the caller from the function table.
+    pub fn type_of(&self) -> Vim9Type {
+        match self {
+            Vim9Value::Void => Vim9Type::Void,
+            Vim9Value::Number(_) => Vim9Type::Number,
+            Vim9Value::Float(_) => Vim9Type::Float,
+            Vim9Value::Bool(_) => Vim9Type::Bool,
+            Vim9Value::String(_) => Vim9Type::String,
+            Vim9Value::List(l) => {
+                let items = l.borrow();
+                let mut member: Option<Vim9Type> = None;
+                for item in items.iter() {
+                    let t = item.type_of();
+                    member = match member {
+                        None => Some(t),
+                        Some(m) if m == t => Some(m),
+                        Some(_) => Some(Vim9Type::Any),
+                    };
+                }
+                Vim9Type::List(Box::new(member.unwrap_or(Vim9Type::Any)))
+            }
+            Vim9Value::Func(_) => Vim9Type::Func(Vec::new(), Box::new(Vim9Type::Any)),
+        }
+    }
+}
+
+impl PartialEq for Vim9Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Vim9Value::Void, Vim9Value::Void) => true,
+            (Vim9Value::Number(a), Vim9Value::Number(b)) => a == b,
+            (Vim9Value::Float(a), Vim9Value::Float(b)) => a == b,
+            (Vim9Value::Number(a), Vim9Value::Float(b)) | (Vim9Value::Float(b), Vim9Value::Number(a)) => {
+                *a as f64 == *b
+            }
+            (Vim9Value::Bool(a), Vim9Value::Bool(b)) => a == b,
+            (Vim9Value::String(a), Vim9Value::String(b)) => a == b,
+            (Vim9Value::List(a), Vim9Value::List(b)) => *a.borrow() == *b.borrow(),
+            (Vim9Value::Func(a), Vim9Value::Func(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Vim9Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Vim9Value::Void => Ok(()),
+            Vim9Value::Number(n) => write!(f, "{}", n),
+            Vim9Value::Float(v) => write!(f, "{}", v),
+            Vim9Value::Bool(b) => write!(f, "{}", if *b { "true" } else { "false" }),
+            Vim9Value::String(s) => write!(f, "{}", s),
+            Vim9Value::List(l) => {
+                write!(f, "[")?;
+                for (i, item) in l.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match item {
+                        Vim9Value::String(s) => write!(f, "'{}'", s.replace('\'', "''"))?,
+                        other => write!(f, "{}", other)?,
+                    }
+                }
+                write!(f, "]")
+            }
+            Vim9Value::Func(name) => write!(f, "function('{}')", name),
+        }
+    }
+}
+
+/// A compiled `:def` function.  Generic functions are compiled once with
+/// their type variables erased; `type_params` lists the variable names.
+#[derive(Debug, Clone)]
+pub struct CompiledFunc {
+    pub name: String,
+    pub type_params: Vec<String>,
+    pub params: Vec<(String, Vim9Type)>,
+    pub ret: Vim9Type,
+    /// Number of local slots, including the arguments.
+    pub locals: usize,
+    pub instrs: Vec<Vim9Instr>,
+    /// Name and type of the local slots, for `:disassemble`.  Hidden slots,
+    /// such as the loop index of a `:for`, have an empty name.
+    pub slots: Vec<(String, Vim9Type)>,
+    /// The body lines the function was compiled from and, for each
+    /// instruction, the index of its line.  A line index equal to the number
+    /// of lines stands for `enddef`.  Both are empty when there is no source.
+    pub source: Vec<String>,
+    pub lines: Vec<usize>,
+}
+
+impl CompiledFunc {
+    pub fn func_type(&self) -> Vim9Type {
+        Vim9Type::Func(
+            self.params.iter().map(|(_, t)| t.clone()).collect(),
+            Box::new(self.ret.clone()),
+        )
+    }
+}
+
+/// The compiled functions that can be called by name.
+#[derive(Debug, Default, Clone)]
+pub struct FuncTable {
+    funcs: HashMap<String, Rc<CompiledFunc>>,
+}
+
+impl FuncTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, func: CompiledFunc) {
+        self.funcs.insert(func.name.clone(), Rc::new(func));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Rc<CompiledFunc>> {
+        self.funcs.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.funcs.keys()
+    }
+}
+
+/// Maximum call depth, like 'maxfuncdepth'.
+const MAX_FUNC_DEPTH: usize = 100;
+
 #[derive(Default)]
 pub struct Interpreter {
-    stack: Vec<i64>,
+    stack: Vec<Vim9Value>,
+    depth: usize,
+    /// Script-local variables, indexed by script ID and variable index.
+    script_vars: Vec<Vec<Vim9Value>>,
+    /// Output of `echo`, one entry per executed instruction.
+    pub output: Vec<String>,
+    frames: Vec<debug::CallFrame>,
+    /// Set by `:profile start`, collects the profile of called functions.
+    pub profiler: Option<Profiler>,
+    /// Set by `:breakadd`, stops at breakpoints in the debug prompt.
+    pub debugger: Option<Debugger>,
 }
 
 impl Interpreter {
@@ -17,29 +208,385 @@ impl Interpreter {
         Self::default()
     }
 
+    /// Run a top level program and return its result as a number.
     pub fn run(&mut self, prog: &Vim9Program) -> i64 {
         self.stack.clear();
-        for instr in &prog.instrs {
-            match *instr {
-                Vim9Instr::PushNumber(n) => self.stack.push(n),
-                Vim9Instr::Add => {
-                    if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                        self.stack.push(a + b);
+        let funcs = FuncTable::new();
+        let mut locals = Vec::new();
+        match self.exec(&prog.instrs, &[], &mut locals, &funcs) {
+            Ok(v) => v.as_number(),
+            Err(_) => 0,
+        }
+    }
+
+    pub fn script_var(&self, sid: usize, idx: usize) -> Vim9Value {
+        self.script_vars
+            .get(sid)
+            .and_then(|vars| vars.get(idx))
+            .cloned()
+            .unwrap_or(Vim9Value::Void)
+    }
+
+    pub fn set_script_var(&mut self, sid: usize, idx: usize, value: Vim9Value) {
+        if sid >= self.script_vars.len() {
+            self.script_vars.resize(sid + 1, Vec::new());
+        }
+        let vars = &mut self.script_vars[sid];
+        if idx >= vars.len() {
+            vars.resize(idx + 1, Vim9Value::Void);
+        }
+        vars[idx] = value;
+    }
+
+    /// Forget the variables of script `sid`, used when it is sourced again.
+    pub fn clear_script_vars(&mut self, sid: usize) {
+        if let Some(vars) = self.script_vars.get_mut(sid) {
+            vars.clear();
+        }
+    }
+
+    /// Call the compiled function `name` with `args`.
+    pub fn call(&mut self, funcs: &FuncTable, name: &str, args: Vec<Vim9Value>) -> Result<Vim9Value, String> {
+        let func = funcs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("E117: Unknown function: {}", name))?;
+        if args.len() < func.params.len() {
+            return Err(format!("E119: Not enough arguments for function: {}", name));
+        }
+        if args.len() > func.params.len() {
+            return Err(format!("E118: Too many arguments for function: {}", name));
+        }
+        for (i, (arg, (_, ty))) in args.iter().zip(&func.params).enumerate() {
+            check_type(ty, arg, i + 1)?;
+        }
+        if self.depth >= MAX_FUNC_DEPTH {
+            return Err(format!("E132: Function call depth is higher than 'maxfuncdepth': {}", name));
+        }
+        self.depth += 1;
+        let mut locals = args;
+        locals.resize(func.locals.max(locals.len()), Vim9Value::Void);
+        let base = self.stack.len();
+        self.enter_func(&func);
+        let res = self.exec(&func.instrs, &func.lines, &mut locals, funcs);
+        self.leave_func();
+        self.stack.truncate(base);
+        self.depth -= 1;
+        res
+    }
+
+    fn pop(&mut self) -> Vim9Value {
+        self.stack.pop().unwrap_or(Vim9Value::Void)
+    }
+
+    /// Execute `instrs`; `lines` has the source line of each instruction,
+    /// used for profiling and debugging.
+    fn exec(
+        &mut self,
+        instrs: &[Vim9Instr],
+        lines: &[usize],
+        locals: &mut Vec<Vim9Value>,
+        funcs: &FuncTable,
+    ) -> Result<Vim9Value, String> {
+        let base = self.stack.len();
+        let hooks = self.profiler.is_some() || self.debugger.is_some();
+        let mut cur_line = None;
+        let mut pc = 0;
+        while let Some(instr) = instrs.get(pc) {
+            if hooks {
+                if let Some(&line) = lines.get(pc).filter(|&&l| Some(l) != cur_line) {
+                    cur_line = Some(line);
+                    self.line_event(line, locals)?;
+                }
+            }
+            pc += 1;
+            match instr {
+                Vim9Instr::PushNumber(n) => self.stack.push(Vim9Value::Number(*n)),
+                Vim9Instr::PushFloat(f) => self.stack.push(Vim9Value::Float(*f)),
+                Vim9Instr::PushString(s) => self.stack.push(Vim9Value::String(s.clone())),
+                Vim9Instr::PushBool(b) => self.stack.push(Vim9Value::Bool(*b)),
+                Vim9Instr::PushFunc(name) => self.stack.push(Vim9Value::Func(name.clone())),
+                Vim9Instr::Add
+                | Vim9Instr::Sub
+                | Vim9Instr::Mul
+                | Vim9Instr::Div
+                | Vim9Instr::Mod
+                | Vim9Instr::CompareLT
+                | Vim9Instr::CompareGT
+                | Vim9Instr::CompareLE
+                | Vim9Instr::CompareGE => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack.push(binary_op(instr, &a, &b)?);
+                }
+                Vim9Instr::CompareEQ | Vim9Instr::CompareNE => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let eq = a == b;
+                    self.stack.push(Vim9Value::Bool(eq == matches!(instr, Vim9Instr::CompareEQ)));
+                }
+                Vim9Instr::Concat => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack.push(Vim9Value::String(format!("{}{}", a, b)));
+                }
+                Vim9Instr::Negate => match self.pop() {
+                    Vim9Value::Float(f) => self.stack.push(Vim9Value::Float(-f)),
+                    v => self.stack.push(Vim9Value::Number(-v.as_number())),
+                },
+                Vim9Instr::Not => {
+                    let v = self.pop();
+                    self.stack.push(Vim9Value::Bool(!v.is_truthy()));
+                }
+                Vim9Instr::LoadLocal(idx) => {
+                    let v = locals.get(*idx).cloned().unwrap_or(Vim9Value::Void);
+                    self.stack.push(v);
+                }
+                Vim9Instr::StoreLocal(idx) => {
+                    let v = self.pop();
+                    if *idx >= locals.len() {
+                        locals.resize(*idx + 1, Vim9Value::Void);
                     }
+                    locals[*idx] = v;
+                }
+                Vim9Instr::LoadScript { sid, idx } => {
+                    let v = self.script_var(*sid, *idx);
+                    self.stack.push(v);
+                }
+                Vim9Instr::StoreScript { sid, idx } => {
+                    let v = self.pop();
+                    self.set_script_var(*sid, *idx, v);
                 }
-                Vim9Instr::Sub => {
-                    if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                        self.stack.push(a - b);
+                Vim9Instr::NewList(n) => {
+                    let items = self.stack.split_off(self.stack.len().saturating_sub(*n));
+                    self.stack.push(Vim9Value::list(items));
+                }
+                Vim9Instr::Index => {
+                    let idx = self.pop().as_number();
+                    let v = self.pop();
+                    self.stack.push(index_value(&v, idx)?);
+                }
+                Vim9Instr::Jump(target) => pc = *target,
+                Vim9Instr::JumpIfFalse(target) => {
+                    if !self.pop().is_truthy() {
+                        pc = *target;
                     }
                 }
-                Vim9Instr::CompareLT => {
-                    if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                        self.stack.push((a < b) as i64);
+                Vim9Instr::For { list, end } => {
+                    let counter = locals.get(list + 1).map(|v| v.as_number()).unwrap_or(0);
+                    let item = match locals.get(*list) {
+                        Some(Vim9Value::List(l)) => l.borrow().get(counter as usize).cloned(),
+                        Some(Vim9Value::Void) | None => None,
+                        Some(other) => {
+                            return Err(format!("E1098: String, List or Blob required: {}", other.type_of()))
+                        }
+                    };
+                    match item {
+                        Some(item) => {
+                            if list + 1 >= locals.len() {
+                                locals.resize(list + 2, Vim9Value::Void);
+                            }
+                            locals[list + 1] = Vim9Value::Number(counter + 1);
+                            self.stack.push(item);
+                        }
+                        None => pc = *end,
                     }
                 }
+                Vim9Instr::Call { name, argc } => {
+                    let args = self.stack.split_off(self.stack.len().saturating_sub(*argc));
+                    let v = self.call(funcs, name, args)?;
+                    self.stack.push(v);
+                }
+                Vim9Instr::CallBuiltin { name, argc } => {
+                    let args = self.stack.split_off(self.stack.len().saturating_sub(*argc));
+                    let v = call_builtin(name, args)?;
+                    self.stack.push(v);
+                }
+                Vim9Instr::CallFuncref { argc } => {
+                    let args = self.stack.split_off(self.stack.len().saturating_sub(*argc));
+                    let v = match self.pop() {
+                        Vim9Value::Func(name) if funcs.get(&name).is_some() => self.call(funcs, &name, args)?,
+                        Vim9Value::Func(name) => call_builtin(&name, args)?,
+                        other => return Err(format!("E1085: Not a callable type: {}", other)),
+                    };
+                    self.stack.push(v);
+                }
+                Vim9Instr::CheckType { ty, offset, argnr } => {
+                    let pos = self.stack.len() as isize + offset;
+                    if let Some(v) = usize::try_from(pos).ok().and_then(|p| self.stack.get(p)) {
+                        check_type(ty, v, *argnr)?;
+                    }
+                }
+                Vim9Instr::Pop => {
+                    self.pop();
+                }
+                Vim9Instr::Echo => {
+                    let v = self.pop();
+                    self.output.push(v.to_string());
+                }
+                Vim9Instr::Return => {
+                    let v = self.pop();
+                    self.stack.truncate(base);
+                    return Ok(v);
+                }
+                Vim9Instr::ReturnVoid => {
+                    self.stack.truncate(base);
+                    return Ok(Vim9Value::Void);
+                }
             }
         }
-        self.stack.pop().unwrap_or(0)
+        let v = if self.stack.len() > base { self.pop() } else { Vim9Value::Void };
+        self.stack.truncate(base);
+        Ok(v)
+    }
+}
+
+/// Give an error when `value` does not match `ty`.  `argnr` is used in the
+/// message, zero means the value is not a function argument.  Type variables
+/// of generic functions are erased and accept any value.
+fn check_type(ty: &Vim9Type, value: &Vim9Value, argnr: usize) -> Result<(), String> {
+    let actual = value.type_of();
+    let ty = ty.erased();
+    let ok = match (&ty, value) {
+        // The signature of a funcref is checked by the compiler.
+        (Vim9Type::Func(..), Vim9Value::Func(_)) => true,
+        _ => ty.accepts(&actual),
+    };
+    if ok {
+        Ok(())
+    } else if argnr == 0 {
+        Err(format!("E1012: Type mismatch; expected {} but got {}", ty, actual))
+    } else {
+        Err(format!(
+            "E1013: Argument {}: type mismatch, expected {} but got {}",
+            argnr, ty, actual
+        ))
+    }
+}
+
+fn binary_op(instr: &Vim9Instr, a: &Vim9Value, b: &Vim9Value) -> Result<Vim9Value, String> {
+    if let (Vim9Value::String(x), Vim9Value::String(y)) = (a, b) {
+        let r = match instr {
+            Vim9Instr::CompareLT => x < y,
+            Vim9Instr::CompareGT => x > y,
+            Vim9Instr::CompareLE => x <= y,
+            Vim9Instr::CompareGE => x >= y,
+            _ => return Err("E1035: wrong argument type for +".to_string()),
+        };
+        return Ok(Vim9Value::Bool(r));
+    }
+    if matches!(a, Vim9Value::Float(_)) || matches!(b, Vim9Value::Float(_)) {
+        let x = match a {
+            Vim9Value::Float(f) => *f,
+            v => v.as_number() as f64,
+        };
+        let y = match b {
+            Vim9Value::Float(f) => *f,
+            v => v.as_number() as f64,
+        };
+        return Ok(match instr {
+            Vim9Instr::Add => Vim9Value::Float(x + y),
+            Vim9Instr::Sub => Vim9Value::Float(x - y),
+            Vim9Instr::Mul => Vim9Value::Float(x * y),
+            Vim9Instr::Div => Vim9Value::Float(x / y),
+            Vim9Instr::Mod => return Err("E804: Cannot use '%' with Float".to_string()),
+            Vim9Instr::CompareLT => Vim9Value::Bool(x < y),
+            Vim9Instr::CompareGT => Vim9Value::Bool(x > y),
+            Vim9Instr::CompareLE => Vim9Value::Bool(x <= y),
+            _ => Vim9Value::Bool(x >= y),
+        });
+    }
+    if let (Vim9Instr::Add, Vim9Value::List(x), Vim9Value::List(y)) = (instr, a, b) {
+        let mut items = x.borrow().clone();
+        items.extend(y.borrow().iter().cloned());
+        return Ok(Vim9Value::list(items));
+    }
+    let (x, y) = (a.as_number(), b.as_number());
+    Ok(match instr {
+        Vim9Instr::Add => Vim9Value::Number(x.wrapping_add(y)),
+        Vim9Instr::Sub => Vim9Value::Number(x.wrapping_sub(y)),
+        Vim9Instr::Mul => Vim9Value::Number(x.wrapping_mul(y)),
+        Vim9Instr::Div => {
+            if y == 0 {
+                return Err("E1154: Divide by zero".to_string());
+            }
+            Vim9Value::Number(x / y)
+        }
+        Vim9Instr::Mod => {
+            if y == 0 {
+                return Err("E1154: Divide by zero".to_string());
+            }
+            Vim9Value::Number(x % y)
+        }
+        Vim9Instr::CompareLT => Vim9Value::Bool(x < y),
+        Vim9Instr::CompareGT => Vim9Value::Bool(x > y),
+        Vim9Instr::CompareLE => Vim9Value::Bool(x <= y),
+        _ => Vim9Value::Bool(x >= y),
+    })
+}
+
+fn index_value(v: &Vim9Value, idx: i64) -> Result<Vim9Value, String> {
+    match v {
+        Vim9Value::List(l) => {
+            let items = l.borrow();
+            let i = if idx < 0 { items.len() as i64 + idx } else { idx };
+            usize::try_from(i)
+                .ok()
+                .and_then(|i| items.get(i).cloned())
+                .ok_or_else(|| format!("E684: List index out of range: {}", idx))
+        }
+        Vim9Value::String(s) => Ok(Vim9Value::String(
+            usize::try_from(idx)
+                .ok()
+                .and_then(|i| s.chars().nth(i))
+                .map(String::from)
+                .unwrap_or_default(),
+        )),
+        other => Err(format!("E1107: String, List, Dict or Blob required: {}", other.type_of())),
+    }
+}
+
+/// Names of the builtin functions known to [`call_builtin`], with their
+/// minimum and maximum argument count and return type.
+pub const BUILTINS: &[(&str, usize, usize, Vim9Type)] = &[
+    ("add", 2, 2, Vim9Type::Any),
+    ("len", 1, 1, Vim9Type::Number),
+    ("string", 1, 1, Vim9Type::String),
+    ("range", 1, 2, Vim9Type::Any),
+    ("toupper", 1, 1, Vim9Type::String),
+    ("tolower", 1, 1, Vim9Type::String),
+];
+
+pub fn call_builtin(name: &str, args: Vec<Vim9Value>) -> Result<Vim9Value, String> {
+    let arg = |i: usize| args.get(i).cloned().unwrap_or(Vim9Value::Void);
+    match name {
+        "add" => match arg(0) {
+            Vim9Value::List(l) => {
+                l.borrow_mut().push(arg(1));
+                Ok(Vim9Value::List(l))
+            }
+            other => Err(format!("E897: List or Blob required: {}", other.type_of())),
+        },
+        "len" => Ok(Vim9Value::Number(match arg(0) {
+            Vim9Value::List(l) => l.borrow().len() as i64,
+            Vim9Value::String(s) => s.len() as i64,
+            other => other.to_string().len() as i64,
+        })),
+        "string" => Ok(Vim9Value::String(match arg(0) {
+            Vim9Value::String(s) => format!("'{}'", s.replace('\'', "''")),
+            other => other.to_string(),
+        })),
+        "range" => {
+            let (start, end) = if args.len() == 2 {
+                (arg(0).as_number(), arg(1).as_number())
+            } else {
+                (0, arg(0).as_number() - 1)
+            };
+            Ok(Vim9Value::list((start..=end).map(Vim9Value::Number).collect()))
+        }
+        "toupper" => Ok(Vim9Value::String(arg(0).to_string().to_uppercase())),
+        "tolower" => Ok(Vim9Value::String(arg(0).to_string().to_lowercase())),
+        _ => Err(format!("E117: Unknown function: {}", name)),
     }
 }
 
@@ -89,4 +636,49 @@ mod tests {
         };
         assert_eq!(execute(&prog), 3);
     }
+
+    #[test]
+    fn calls_function_with_loop() {
+        // def Sum(l: list<number>): number
+        //   var total = 0
+        //   for n in l
+        //     total += n
+        //   endfor
+        //   return total
+        // enddef
+        let mut funcs = FuncTable::new();
+        funcs.insert(CompiledFunc {
+            name: "Sum".to_string(),
+            type_params: Vec::new(),
+            params: vec![("l".to_string(), Vim9Type::List(Box::new(Vim9Type::Number)))],
+            ret: Vim9Type::Number,
+            locals: 5,
+            instrs: vec![
+                Vim9Instr::PushNumber(0),
+                Vim9Instr::StoreLocal(1),
+                Vim9Instr::LoadLocal(0),
+                Vim9Instr::StoreLocal(2),
+                Vim9Instr::For { list: 2, end: 11 },
+                Vim9Instr::StoreLocal(4),
+                Vim9Instr::LoadLocal(1),
+                Vim9Instr::LoadLocal(4),
+                Vim9Instr::Add,
+                Vim9Instr::StoreLocal(1),
+                Vim9Instr::Jump(4),
+                Vim9Instr::LoadLocal(1),
+                Vim9Instr::Return,
+            ],
+            slots: Vec::new(),
+            source: Vec::new(),
+            lines: Vec::new(),
+        });
+        let list = Vim9Value::list(vec![Vim9Value::Number(1), Vim9Value::Number(2), Vim9Value::Number(3)]);
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.call(&funcs, "Sum", vec![list]), Ok(Vim9Value::Number(6)));
+        let bad = Vim9Value::list(vec![Vim9Value::String("x".to_string())]);
+        assert_eq!(
+            interp.call(&funcs, "Sum", vec![bad]),
+            Err("E1013: Argument 1: type mismatch, expected list<number> but got list<string>".to_string())
+        );
+    }
 }
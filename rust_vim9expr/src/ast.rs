@@ -0,0 +1,341 @@
+use rust_vim9generics::parse_type_args;
+use rust_vim9type::Vim9Type;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Add,
+    Sub,
+    Concat,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Name(String),
+    List(Vec<Expr>),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    /// A call of a function or funcref variable by name.  `type_args` holds
+    /// explicit type arguments of a generic call, `Map<number, string>(...)`.
+    Call {
+        name: String,
+        type_args: Option<Vec<Vim9Type>>,
+        args: Vec<Expr>,
+    },
+}
+
+/// Parse a complete Vim9 expression.  `type_vars` are the type variables in
+/// scope, used for explicit type arguments inside a generic function.
+pub fn parse_expr(text: &str, type_vars: &[String]) -> Result<Expr, String> {
+    let mut p = Parser { text, pos: 0, type_vars };
+    let expr = p.parse_or()?;
+    p.skip_ws();
+    if p.pos < p.text.len() {
+        return Err(format!("E488: Trailing characters: {}", &p.text[p.pos..]));
+    }
+    Ok(expr)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    type_vars: &'a [String],
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    /// Consume `op` after optional white space when it is next.
+    fn eat(&mut self, op: &str) -> bool {
+        self.skip_ws();
+        if self.rest().starts_with(op) {
+            self.pos += op.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.eat(op) {
+            Ok(())
+        } else {
+            Err(format!("E1004: Missing '{}' at \"{}\"", op, self.rest()))
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.eat("||") {
+            let right = self.parse_and()?;
+            left = Expr::Binary(BinOp::Or, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_compare()?;
+        while self.eat("&&") {
+            let right = self.parse_compare()?;
+            left = Expr::Binary(BinOp::And, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_compare(&mut self) -> Result<Expr, String> {
+        let left = self.parse_add()?;
+        const OPS: [(&str, BinOp); 6] = [
+            ("==", BinOp::Eq),
+            ("!=", BinOp::Ne),
+            ("<=", BinOp::Le),
+            (">=", BinOp::Ge),
+            ("<", BinOp::Lt),
+            (">", BinOp::Gt),
+        ];
+        for (text, op) in OPS {
+            if self.eat(text) {
+                let right = self.parse_add()?;
+                return Ok(Expr::Binary(op, Box::new(left), Box::new(right)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn parse_add(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_mul()?;
+        loop {
+            let op = if self.eat("..") {
+                BinOp::Concat
+            } else if self.eat("+") {
+                BinOp::Add
+            } else if self.eat("-") {
+                BinOp::Sub
+            } else {
+                return Ok(left);
+            };
+            let right = self.parse_mul()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_mul(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = if self.eat("*") {
+                BinOp::Mul
+            } else if self.eat("/") {
+                BinOp::Div
+            } else if self.eat("%") {
+                BinOp::Mod
+            } else {
+                return Ok(left);
+            };
+            let right = self.parse_unary()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.parse_unary()?)));
+        }
+        let mut expr = self.parse_primary()?;
+        while self.peek() == Some('[') {
+            self.pos += 1;
+            let idx = self.parse_or()?;
+            self.expect("]")?;
+            expr = Expr::Index(Box::new(expr), Box::new(idx));
+        }
+        Ok(expr)
+    }
+
+    fn parse_args(&mut self) -> Result<Vec<Expr>, String> {
+        let mut args = Vec::new();
+        if self.eat(")") {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.parse_or()?);
+            if self.eat(")") {
+                return Ok(args);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        self.skip_ws();
+        let rest = self.rest();
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => return Err("E15: Invalid expression: \"\"".to_string()),
+        };
+        if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let num = &rest[..end];
+            self.pos += end;
+            return if num.contains('.') {
+                num.parse().map(Expr::Float).map_err(|_| format!("E15: Invalid expression: \"{}\"", num))
+            } else {
+                num.parse().map(Expr::Number).map_err(|_| format!("E15: Invalid expression: \"{}\"", num))
+            };
+        }
+        if c == '"' || c == '\'' {
+            return self.parse_string(c);
+        }
+        if c == '(' {
+            self.pos += 1;
+            let expr = self.parse_or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if c == '[' {
+            self.pos += 1;
+            let mut items = Vec::new();
+            if !self.eat("]") {
+                loop {
+                    items.push(self.parse_or()?);
+                    if self.eat("]") {
+                        break;
+                    }
+                    self.expect(",")?;
+                    if self.eat("]") {
+                        break;
+                    }
+                }
+            }
+            return Ok(Expr::List(items));
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '#' || c == ':'))
+                .unwrap_or(rest.len());
+            let mut name = rest[..end].to_string();
+            self.pos += end;
+            // "alias.item" refers to an item of an imported script.
+            let r = self.rest();
+            if r.starts_with('.') && r[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                let end = r[1..]
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(r.len() - 1);
+                name.push_str(&r[..end + 1]);
+                self.pos += end + 1;
+            }
+            match name.as_str() {
+                "true" => return Ok(Expr::Bool(true)),
+                "false" => return Ok(Expr::Bool(false)),
+                _ => {}
+            }
+            // A '<' directly after the name starts type arguments, a
+            // comparison operator must be surrounded by white space.
+            let mut type_args = None;
+            if self.peek() == Some('<') {
+                let (types, r) = parse_type_args(self.rest(), &name, self.type_vars)?;
+                self.pos = self.text.len() - r.len();
+                type_args = Some(types);
+                if self.peek() != Some('(') {
+                    return Err(format!("E1004: Missing '(' at \"{}\"", self.rest()));
+                }
+            }
+            if self.peek() == Some('(') {
+                self.pos += 1;
+                let args = self.parse_args()?;
+                return Ok(Expr::Call { name, type_args, args });
+            }
+            return Ok(Expr::Name(name));
+        }
+        Err(format!("E15: Invalid expression: \"{}\"", rest))
+    }
+
+    fn parse_string(&mut self, quote: char) -> Result<Expr, String> {
+        let mut s = String::new();
+        let mut chars = self.rest()[1..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            if c == quote {
+                if quote == '\'' && self.rest()[1 + i + 1..].starts_with('\'') {
+                    chars.next();
+                    s.push('\'');
+                    continue;
+                }
+                self.pos += i + 2;
+                return Ok(Expr::String(s));
+            }
+            if c == '\\' && quote == '"' {
+                match chars.next().map(|(_, c)| c) {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(other) => s.push(other),
+                    None => break,
+                }
+                continue;
+            }
+            s.push(c);
+        }
+        Err(format!("E114: Missing double quote: {}", self.rest()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_precedence() {
+        let e = parse_expr("1 + 2 * 3", &[]).unwrap();
+        assert_eq!(
+            e,
+            Expr::Binary(
+                BinOp::Add,
+                Box::new(Expr::Number(1)),
+                Box::new(Expr::Binary(BinOp::Mul, Box::new(Expr::Number(2)), Box::new(Expr::Number(3))))
+            )
+        );
+    }
+
+    #[test]
+    fn parses_generic_call() {
+        let e = parse_expr("Map<number, string>([1, 2], F)", &[]).unwrap();
+        match e {
+            Expr::Call { name, type_args, args } => {
+                assert_eq!(name, "Map");
+                assert_eq!(type_args, Some(vec![Vim9Type::Number, Vim9Type::String]));
+                assert_eq!(args.len(), 2);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // With white space it is a comparison.
+        assert!(matches!(parse_expr("a < b", &[]).unwrap(), Expr::Binary(BinOp::Lt, ..)));
+    }
+}
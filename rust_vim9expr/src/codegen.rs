@@ -0,0 +1,299 @@
+use rust_vim9execute::BUILTINS;
+use rust_vim9generics::{Instance, Signature};
+use rust_vim9instr::Vim9Instr;
+use rust_vim9type::Vim9Type;
+
+use crate::ast::{BinOp, Expr};
+
+/// A script-local variable, possibly found through an import.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptVar {
+    pub sid: usize,
+    pub idx: usize,
+    pub ty: Vim9Type,
+    pub is_const: bool,
+}
+
+/// Names visible to the expression compiler.
+pub trait Scope {
+    /// Slot and declared type of a local variable or argument.
+    fn local(&self, name: &str) -> Option<(usize, Vim9Type)>;
+
+    /// A script variable visible by `name`, either declared in the current
+    /// script or imported as `alias.name`.
+    fn script_var(&mut self, _name: &str) -> Result<Option<ScriptVar>, String> {
+        Ok(None)
+    }
+
+    /// Signature of the function called `name` in this scope.  The name in
+    /// the returned signature is the one it is compiled under, which can
+    /// differ for script-local and imported functions.
+    fn signature(&mut self, name: &str) -> Result<Option<Signature>, String>;
+
+    /// Resolve the types of a call to `sig`.  The default resolves every call
+    /// separately, a scope may cache the instances of generic functions.
+    fn resolve_call(
+        &mut self,
+        sig: &Signature,
+        type_args: Option<&[Vim9Type]>,
+        arg_types: &[Vim9Type],
+    ) -> Result<Instance, String> {
+        sig.resolve(type_args, arg_types)
+    }
+}
+
+/// Compile `expr`, appending to `instrs`.  Returns the static type of the
+/// result.
+pub fn compile_expr(expr: &Expr, scope: &mut dyn Scope, instrs: &mut Vec<Vim9Instr>) -> Result<Vim9Type, String> {
+    match expr {
+        Expr::Number(n) => {
+            instrs.push(Vim9Instr::PushNumber(*n));
+            Ok(Vim9Type::Number)
+        }
+        Expr::Float(f) => {
+            instrs.push(Vim9Instr::PushFloat(*f));
+            Ok(Vim9Type::Float)
+        }
+        Expr::String(s) => {
+            instrs.push(Vim9Instr::PushString(s.clone()));
+            Ok(Vim9Type::String)
+        }
+        Expr::Bool(b) => {
+            instrs.push(Vim9Instr::PushBool(*b));
+            Ok(Vim9Type::Bool)
+        }
+        Expr::Name(name) => {
+            if let Some((slot, ty)) = scope.local(name) {
+                instrs.push(Vim9Instr::LoadLocal(slot));
+                return Ok(ty);
+            }
+            if let Some(var) = scope.script_var(name)? {
+                instrs.push(Vim9Instr::LoadScript { sid: var.sid, idx: var.idx });
+                return Ok(var.ty);
+            }
+            if let Some(sig) = scope.signature(name)? {
+                instrs.push(Vim9Instr::PushFunc(sig.name.clone()));
+                let ty = Vim9Type::Func(sig.params.clone(), Box::new(sig.ret.clone()));
+                return Ok(if sig.is_generic() { ty.erased() } else { ty });
+            }
+            if BUILTINS.iter().any(|(n, ..)| n == name) {
+                instrs.push(Vim9Instr::PushFunc(name.clone()));
+                return Ok(Vim9Type::Func(Vec::new(), Box::new(Vim9Type::Any)));
+            }
+            Err(format!("E1001: Variable not found: {}", name))
+        }
+        Expr::List(items) => {
+            let mut member: Option<Vim9Type> = None;
+            for item in items {
+                let t = compile_expr(item, scope, instrs)?;
+                member = match member {
+                    None => Some(t),
+                    Some(m) if m == t => Some(m),
+                    Some(_) => Some(Vim9Type::Any),
+                };
+            }
+            instrs.push(Vim9Instr::NewList(items.len()));
+            Ok(Vim9Type::List(Box::new(member.unwrap_or(Vim9Type::Any))))
+        }
+        Expr::Negate(e) => {
+            let t = compile_expr(e, scope, instrs)?;
+            instrs.push(Vim9Instr::Negate);
+            Ok(if t == Vim9Type::Float { t } else { Vim9Type::Number })
+        }
+        Expr::Not(e) => {
+            compile_expr(e, scope, instrs)?;
+            instrs.push(Vim9Instr::Not);
+            Ok(Vim9Type::Bool)
+        }
+        Expr::Binary(op, a, b) => compile_binary(*op, a, b, scope, instrs),
+        Expr::Index(e, idx) => {
+            let t = compile_expr(e, scope, instrs)?;
+            let it = compile_expr(idx, scope, instrs)?;
+            if !Vim9Type::Number.accepts(&it) {
+                return Err(format!("E1012: Type mismatch; expected number but got {}", it));
+            }
+            instrs.push(Vim9Instr::Index);
+            Ok(match t {
+                Vim9Type::List(m) => *m,
+                Vim9Type::String => Vim9Type::String,
+                Vim9Type::Any => Vim9Type::Any,
+                other => return Err(format!("E1107: String, List, Dict or Blob required: {}", other)),
+            })
+        }
+        Expr::Call { name, type_args, args } => compile_call(name, type_args.as_deref(), args, scope, instrs),
+    }
+}
+
+fn compile_binary(
+    op: BinOp,
+    a: &Expr,
+    b: &Expr,
+    scope: &mut dyn Scope,
+    instrs: &mut Vec<Vim9Instr>,
+) -> Result<Vim9Type, String> {
+    if matches!(op, BinOp::And | BinOp::Or) {
+        // Short-circuit: "a && b" is "a ? b : false".
+        compile_expr(a, scope, instrs)?;
+        if op == BinOp::Or {
+            instrs.push(Vim9Instr::Not);
+        }
+        let jump = instrs.len();
+        instrs.push(Vim9Instr::JumpIfFalse(0));
+        compile_expr(b, scope, instrs)?;
+        instrs.push(Vim9Instr::Not);
+        instrs.push(Vim9Instr::Not);
+        let done = instrs.len();
+        instrs.push(Vim9Instr::Jump(0));
+        instrs[jump] = Vim9Instr::JumpIfFalse(instrs.len());
+        instrs.push(Vim9Instr::PushBool(op == BinOp::Or));
+        instrs[done] = Vim9Instr::Jump(instrs.len());
+        return Ok(Vim9Type::Bool);
+    }
+    let ta = compile_expr(a, scope, instrs)?;
+    let tb = compile_expr(b, scope, instrs)?;
+    let (instr, ty) = match op {
+        BinOp::Eq => (Vim9Instr::CompareEQ, Vim9Type::Bool),
+        BinOp::Ne => (Vim9Instr::CompareNE, Vim9Type::Bool),
+        BinOp::Lt => (Vim9Instr::CompareLT, Vim9Type::Bool),
+        BinOp::Gt => (Vim9Instr::CompareGT, Vim9Type::Bool),
+        BinOp::Le => (Vim9Instr::CompareLE, Vim9Type::Bool),
+        BinOp::Ge => (Vim9Instr::CompareGE, Vim9Type::Bool),
+        BinOp::Concat => (Vim9Instr::Concat, Vim9Type::String),
+        BinOp::Add if matches!(ta, Vim9Type::List(_)) => (Vim9Instr::Add, ta.clone()),
+        BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod => {
+            for t in [&ta, &tb] {
+                if !matches!(t, Vim9Type::Number | Vim9Type::Float | Vim9Type::Any) {
+                    return Err(format!("E1036: {} requires number or float arguments", op_name(op)));
+                }
+            }
+            let ty = if ta == Vim9Type::Float || tb == Vim9Type::Float {
+                Vim9Type::Float
+            } else if ta == Vim9Type::Any || tb == Vim9Type::Any {
+                Vim9Type::Any
+            } else {
+                Vim9Type::Number
+            };
+            let instr = match op {
+                BinOp::Add => Vim9Instr::Add,
+                BinOp::Sub => Vim9Instr::Sub,
+                BinOp::Mul => Vim9Instr::Mul,
+                BinOp::Div => Vim9Instr::Div,
+                _ => Vim9Instr::Mod,
+            };
+            (instr, ty)
+        }
+        BinOp::And | BinOp::Or => unreachable!(),
+    };
+    instrs.push(instr);
+    Ok(ty)
+}
+
+fn op_name(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        _ => "%",
+    }
+}
+
+fn compile_call(
+    name: &str,
+    type_args: Option<&[Vim9Type]>,
+    args: &[Expr],
+    scope: &mut dyn Scope,
+    instrs: &mut Vec<Vim9Instr>,
+) -> Result<Vim9Type, String> {
+    // A local variable holding a funcref.
+    if let Some((slot, ty)) = scope.local(name) {
+        if type_args.is_some() {
+            return Err(format!("E1560: Not a generic function: {}", name));
+        }
+        instrs.push(Vim9Instr::LoadLocal(slot));
+        let mut arg_types = Vec::new();
+        for a in args {
+            arg_types.push(compile_expr(a, scope, instrs)?);
+        }
+        let ret = match &ty {
+            Vim9Type::Func(params, ret) if !params.is_empty() || **ret != Vim9Type::Any => {
+                if args.len() < params.len() {
+                    return Err(format!("E119: Not enough arguments for function: {}", name));
+                }
+                if args.len() > params.len() {
+                    return Err(format!("E118: Too many arguments for function: {}", name));
+                }
+                for (i, (p, a)) in params.iter().zip(&arg_types).enumerate() {
+                    if !p.accepts(a) {
+                        return Err(format!(
+                            "E1013: Argument {}: type mismatch, expected {} but got {}",
+                            i + 1,
+                            p,
+                            a
+                        ));
+                    }
+                }
+                (**ret).clone()
+            }
+            Vim9Type::Func(..) | Vim9Type::Any => Vim9Type::Any,
+            other => return Err(format!("E1085: Not a callable type: {}", other)),
+        };
+        instrs.push(Vim9Instr::CallFuncref { argc: args.len() });
+        return Ok(ret);
+    }
+
+    if let Some(sig) = scope.signature(name)? {
+        let mut arg_types = Vec::new();
+        for a in args {
+            arg_types.push(compile_expr(a, scope, instrs)?);
+        }
+        if args.len() < sig.params.len() {
+            return Err(format!("E119: Not enough arguments for function: {}", name));
+        }
+        if args.len() > sig.params.len() {
+            return Err(format!("E118: Too many arguments for function: {}", name));
+        }
+        let inst = scope.resolve_call(&sig, type_args, &arg_types)?;
+        // Arguments of type "any" are checked when the call is executed.
+        for (i, (expected, actual)) in inst.params.iter().zip(&arg_types).enumerate() {
+            if *actual == Vim9Type::Any && *expected != Vim9Type::Any && !expected.is_generic() {
+                instrs.push(Vim9Instr::CheckType {
+                    ty: expected.clone(),
+                    offset: i as isize - args.len() as isize,
+                    argnr: i + 1,
+                });
+            }
+        }
+        instrs.push(Vim9Instr::Call { name: sig.name.clone(), argc: args.len() });
+        // The body of a generic function is type-erased, check the result
+        // against the instantiated return type.
+        if sig.is_generic() && inst.ret != Vim9Type::Void && !inst.ret.is_generic() {
+            instrs.push(Vim9Instr::CheckType { ty: inst.ret.clone(), offset: -1, argnr: 0 });
+        }
+        return Ok(inst.ret);
+    }
+
+    if let Some((_, min, max, ret)) = BUILTINS.iter().find(|(n, ..)| *n == name) {
+        if type_args.is_some() {
+            return Err(format!("E1560: Not a generic function: {}", name));
+        }
+        if args.len() < *min {
+            return Err(format!("E119: Not enough arguments for function: {}", name));
+        }
+        if args.len() > *max {
+            return Err(format!("E118: Too many arguments for function: {}", name));
+        }
+        let mut arg_types = Vec::new();
+        for a in args {
+            arg_types.push(compile_expr(a, scope, instrs)?);
+        }
+        instrs.push(Vim9Instr::CallBuiltin { name: name.to_string(), argc: args.len() });
+        return Ok(match name {
+            "add" => arg_types[0].clone(),
+            "range" => Vim9Type::List(Box::new(Vim9Type::Number)),
+            _ => ret.clone(),
+        });
+    }
+
+    Err(format!("E117: Unknown function: {}", name))
+}
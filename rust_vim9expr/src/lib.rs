@@ -1,3 +1,9 @@
+mod ast;
+mod codegen;
+
+pub use ast::{parse_expr, BinOp, Expr};
+pub use codegen::{compile_expr, Scope, ScriptVar};
+
 use rust_vim9execute::{execute, Vim9Program};
 use rust_vim9instr::Vim9Instr;
 use rust_vim9type::Vim9Type;
@@ -1,14 +1,337 @@
+//! Support for Vim9 generic functions such as
+//! `def Map<T, U>(l: list<T>, F: func(T): U): list<U>`.
+//!
+//! A generic function is compiled once with its type variables erased to
+//! `any`.  At each call site the type arguments are either given explicitly,
+//! `Map<number, string>(...)`, or inferred from the argument types.  The
+//! resulting [`Instance`] carries the concrete parameter and return types
+//! which the caller checks, at compile time when possible and otherwise with
+//! runtime type checks.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use rust_vim9type::{parse_type, Vim9Type};
+
 pub fn repeat<T: Clone>(value: T, count: usize) -> Vec<T> {
     (0..count).map(|_| value.clone()).collect()
 }
 
+/// Mapping from type variable name to the concrete type.
+pub type TypeBindings = HashMap<String, Vim9Type>;
+
+/// Parse the type parameter list of a generic function declaration,
+/// `<T, U>`.  `text` starts right after the function name.  When there is no
+/// `<` an empty list is returned.
+pub fn parse_type_params<'a>(text: &'a str, func_name: &str) -> Result<(Vec<String>, &'a str), String> {
+    let Some(mut rest) = text.strip_prefix('<') else {
+        return Ok((Vec::new(), text));
+    };
+    let mut names: Vec<String> = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(r) = rest.strip_prefix('>') {
+            if names.is_empty() {
+                return Err(format!("E1555: Empty type list specified for generic function '{}'", func_name));
+            }
+            return Ok((names, r));
+        }
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let (name, r) = rest.split_at(end);
+        if name.is_empty() {
+            return Err(format!("E1554: Missing '>' in generic function: {}", func_name));
+        }
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            return Err(format!("E1552: Type variable name must start with an uppercase letter: {}", name));
+        }
+        if names.iter().any(|n| n == name) {
+            return Err(format!("E1561: Duplicate type variable name: {}", name));
+        }
+        names.push(name.to_string());
+        rest = r.trim_start();
+        if let Some(r) = rest.strip_prefix(',') {
+            rest = r;
+        } else if !rest.starts_with('>') {
+            return Err(format!("E1553: Missing comma after type in generic function: {}", func_name));
+        }
+    }
+}
+
+/// Parse an explicit type argument list at a call site, `<number, string>`.
+/// `type_vars` are the type variables in scope of the caller.
+pub fn parse_type_args<'a>(
+    text: &'a str,
+    func_name: &str,
+    type_vars: &[String],
+) -> Result<(Vec<Vim9Type>, &'a str), String> {
+    let Some(mut rest) = text.strip_prefix('<') else {
+        return Ok((Vec::new(), text));
+    };
+    let mut types = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(r) = rest.strip_prefix('>') {
+            if types.is_empty() {
+                return Err(format!("E1555: Empty type list specified for generic function '{}'", func_name));
+            }
+            return Ok((types, r));
+        }
+        let Some((ty, r)) = parse_type(rest, type_vars) else {
+            return Err(format!("E1554: Missing '>' in generic function: {}", func_name));
+        };
+        types.push(ty);
+        rest = r.trim_start();
+        if let Some(r) = rest.strip_prefix(',') {
+            rest = r;
+        } else if !rest.starts_with('>') {
+            return Err(format!("E1553: Missing comma after type in generic function: {}", func_name));
+        }
+    }
+}
+
+/// Replace the type variables in `ty` using `bindings`.  Unbound variables
+/// become `any`.
+pub fn substitute(ty: &Vim9Type, bindings: &TypeBindings) -> Vim9Type {
+    match ty {
+        Vim9Type::TypeVar(name) => bindings.get(name).cloned().unwrap_or(Vim9Type::Any),
+        Vim9Type::List(t) => Vim9Type::List(Box::new(substitute(t, bindings))),
+        Vim9Type::Dict(t) => Vim9Type::Dict(Box::new(substitute(t, bindings))),
+        Vim9Type::Func(args, ret) => Vim9Type::Func(
+            args.iter().map(|a| substitute(a, bindings)).collect(),
+            Box::new(substitute(ret, bindings)),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Match `formal` against `actual`, binding type variables on the way.
+/// Returns false when the types conflict.
+pub fn unify(formal: &Vim9Type, actual: &Vim9Type, bindings: &mut TypeBindings) -> bool {
+    match (formal, actual) {
+        (Vim9Type::TypeVar(name), _) => match bindings.get(name) {
+            Some(Vim9Type::Any) => {
+                bindings.insert(name.clone(), actual.clone());
+                true
+            }
+            Some(bound) => bound.accepts(actual),
+            None => {
+                bindings.insert(name.clone(), actual.clone());
+                true
+            }
+        },
+        (_, Vim9Type::Any) => true,
+        (Vim9Type::List(f), Vim9Type::List(a)) | (Vim9Type::Dict(f), Vim9Type::Dict(a)) => {
+            unify(f, a, bindings)
+        }
+        (Vim9Type::Func(fa, fr), Vim9Type::Func(aa, ar)) => {
+            fa.len() == aa.len()
+                && fa.iter().zip(aa).all(|(f, a)| unify(f, a, bindings))
+                && unify(fr, ar, bindings)
+        }
+        (f, a) => f.accepts(a),
+    }
+}
+
+/// Signature of a (possibly generic) function as seen by callers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub name: String,
+    pub type_params: Vec<String>,
+    pub params: Vec<Vim9Type>,
+    pub ret: Vim9Type,
+}
+
+/// A signature with all type variables replaced by concrete types.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub type_args: Vec<Vim9Type>,
+    pub params: Vec<Vim9Type>,
+    pub ret: Vim9Type,
+}
+
+impl Signature {
+    pub fn is_generic(&self) -> bool {
+        !self.type_params.is_empty()
+    }
+
+    /// Instantiate with explicitly given type arguments.
+    pub fn instantiate(&self, type_args: &[Vim9Type]) -> Result<Instance, String> {
+        if !self.is_generic() {
+            return Err(format!("E1560: Not a generic function: {}", self.name));
+        }
+        if type_args.len() > self.type_params.len() {
+            return Err(format!("E1556: Too many types specified for generic function '{}'", self.name));
+        }
+        if type_args.len() < self.type_params.len() {
+            return Err(format!("E1557: Not enough types specified for generic function '{}'", self.name));
+        }
+        let bindings: TypeBindings = self
+            .type_params
+            .iter()
+            .cloned()
+            .zip(type_args.iter().cloned())
+            .collect();
+        Ok(self.apply(&bindings))
+    }
+
+    /// Resolve the concrete types for a call with arguments of types
+    /// `arg_types`, using `explicit` type arguments when given and inference
+    /// otherwise.  The argument types are checked against the instance.
+    pub fn resolve(&self, explicit: Option<&[Vim9Type]>, arg_types: &[Vim9Type]) -> Result<Instance, String> {
+        let inst = match explicit {
+            Some(args) => self.instantiate(args)?,
+            None if self.is_generic() => self.infer(arg_types)?,
+            None => Instance {
+                type_args: Vec::new(),
+                params: self.params.clone(),
+                ret: self.ret.clone(),
+            },
+        };
+        for (i, (expected, actual)) in inst.params.iter().zip(arg_types).enumerate() {
+            if !expected.accepts(actual) {
+                return Err(format!(
+                    "E1013: Argument {}: type mismatch, expected {} but got {}",
+                    i + 1,
+                    expected,
+                    actual
+                ));
+            }
+        }
+        Ok(inst)
+    }
+
+    fn infer(&self, arg_types: &[Vim9Type]) -> Result<Instance, String> {
+        let mut bindings = TypeBindings::new();
+        for (i, (formal, actual)) in self.params.iter().zip(arg_types).enumerate() {
+            if !unify(formal, actual, &mut bindings) {
+                return Err(format!(
+                    "E1013: Argument {}: type mismatch, expected {} but got {}",
+                    i + 1,
+                    substitute(formal, &bindings),
+                    actual
+                ));
+            }
+        }
+        if self.type_params.iter().any(|p| !bindings.contains_key(p)) {
+            return Err(format!("E1559: Type arguments missing for generic function '{}'", self.name));
+        }
+        Ok(self.apply(&bindings))
+    }
+
+    fn apply(&self, bindings: &TypeBindings) -> Instance {
+        Instance {
+            type_args: self
+                .type_params
+                .iter()
+                .map(|p| bindings.get(p).cloned().unwrap_or(Vim9Type::Any))
+                .collect(),
+            params: self.params.iter().map(|p| substitute(p, bindings)).collect(),
+            ret: substitute(&self.ret, bindings),
+        }
+    }
+}
+
+/// Cache of instances per function and type argument list, so that every
+/// distinct instantiation is computed once.
+#[derive(Debug, Default)]
+pub struct InstanceCache {
+    instances: HashMap<(String, Vec<Vim9Type>), Rc<Instance>>,
+}
+
+impl InstanceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the instance of `sig` for `type_args`, creating it when needed.
+    pub fn get(&mut self, sig: &Signature, type_args: &[Vim9Type]) -> Result<Rc<Instance>, String> {
+        let key = (sig.name.clone(), type_args.to_vec());
+        if let Some(inst) = self.instances.get(&key) {
+            return Ok(inst.clone());
+        }
+        let inst = Rc::new(sig.instantiate(type_args)?);
+        self.instances.insert(key, inst.clone());
+        Ok(inst)
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn map_sig() -> Signature {
+        let vars = vec!["T".to_string(), "U".to_string()];
+        Signature {
+            name: "Map".to_string(),
+            params: vec![
+                Vim9Type::parse("list<T>", &vars).unwrap(),
+                Vim9Type::parse("func(T): U", &vars).unwrap(),
+            ],
+            ret: Vim9Type::parse("list<U>", &vars).unwrap(),
+            type_params: vars,
+        }
+    }
+
     #[test]
     fn repeats_value() {
         let v = repeat(1, 3);
         assert_eq!(v, vec![1, 1, 1]);
     }
+
+    #[test]
+    fn parses_type_params() {
+        let (names, rest) = parse_type_params("<T, U>(l: list<T>)", "Map").unwrap();
+        assert_eq!(names, vec!["T", "U"]);
+        assert_eq!(rest, "(l: list<T>)");
+        assert!(parse_type_params("<>()", "F").unwrap_err().starts_with("E1555:"));
+        assert!(parse_type_params("<t>()", "F").unwrap_err().starts_with("E1552:"));
+        assert!(parse_type_params("<T, T>()", "F").unwrap_err().starts_with("E1561:"));
+        assert!(parse_type_params("<T U>()", "F").unwrap_err().starts_with("E1553:"));
+    }
+
+    #[test]
+    fn infers_type_args_from_arguments() {
+        let sig = map_sig();
+        let args = [
+            Vim9Type::List(Box::new(Vim9Type::Number)),
+            Vim9Type::Func(vec![Vim9Type::Number], Box::new(Vim9Type::String)),
+        ];
+        let inst = sig.resolve(None, &args).unwrap();
+        assert_eq!(inst.type_args, vec![Vim9Type::Number, Vim9Type::String]);
+        assert_eq!(inst.ret, Vim9Type::List(Box::new(Vim9Type::String)));
+    }
+
+    #[test]
+    fn explicit_type_args_are_checked() {
+        let sig = map_sig();
+        let (types, _) = parse_type_args("<number, string>(", "Map", &[]).unwrap();
+        let args = [
+            Vim9Type::List(Box::new(Vim9Type::String)),
+            Vim9Type::Func(vec![Vim9Type::Number], Box::new(Vim9Type::String)),
+        ];
+        let err = sig.resolve(Some(&types), &args).unwrap_err();
+        assert_eq!(err, "E1013: Argument 1: type mismatch, expected list<number> but got list<string>");
+        assert!(sig.instantiate(&[Vim9Type::Number]).unwrap_err().starts_with("E1557:"));
+    }
+
+    #[test]
+    fn cache_reuses_instances() {
+        let sig = map_sig();
+        let mut cache = InstanceCache::new();
+        let a = cache.get(&sig, &[Vim9Type::Number, Vim9Type::String]).unwrap();
+        let b = cache.get(&sig, &[Vim9Type::Number, Vim9Type::String]).unwrap();
+        assert!(Rc::ptr_eq(&a, &b));
+        cache.get(&sig, &[Vim9Type::String, Vim9Type::String]).unwrap();
+        assert_eq!(cache.len(), 2);
+    }
 }
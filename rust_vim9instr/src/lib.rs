@@ -1,9 +1,126 @@
+use rust_vim9type::Vim9Type;
+
+/// A single instruction of a compiled Vim9 function or expression.
+///
+/// Local variable slots are numbered from zero, the arguments of a function
+/// occupy the first slots.  Jump targets are instruction indexes.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Vim9Instr {
     PushNumber(i64),
+    PushFloat(f64),
+    PushString(String),
+    PushBool(bool),
+    /// Push a reference to the named function.
+    PushFunc(String),
     Add,
     Sub,
+    Mul,
+    Div,
+    Mod,
+    Concat,
+    Negate,
+    Not,
     CompareLT,
+    CompareGT,
+    CompareLE,
+    CompareGE,
+    CompareEQ,
+    CompareNE,
+    LoadLocal(usize),
+    StoreLocal(usize),
+    /// Load or store variable `idx` of the script with ID `sid`.
+    LoadScript { sid: usize, idx: usize },
+    StoreScript { sid: usize, idx: usize },
+    /// Build a list from the top `n` stack items.
+    NewList(usize),
+    /// Pop an index and a list or string, push the item.
+    Index,
+    Jump(usize),
+    JumpIfFalse(usize),
+    /// Iterate over the list in local slot `list`; the loop index is kept in
+    /// slot `list + 1`.  Pushes the next item or jumps to `end` when done.
+    For { list: usize, end: usize },
+    /// Call a compiled function by name with `argc` arguments.
+    Call { name: String, argc: usize },
+    /// Call a builtin function with `argc` arguments.
+    CallBuiltin { name: String, argc: usize },
+    /// Call the funcref found below the `argc` arguments on the stack.
+    CallFuncref { argc: usize },
+    /// Check the value at stack offset `offset` (-1 is the top) against a
+    /// type, used where the type is only known at runtime.  `argnr` is the
+    /// argument number for the error message, zero when not an argument.
+    CheckType { ty: Vim9Type, offset: isize, argnr: usize },
+    Pop,
+    Echo,
+    Return,
+    ReturnVoid,
+}
+
+impl Vim9Instr {
+    /// The change in stack size when the instruction is executed and does
+    /// not jump.  A call counts as popping its arguments and pushing the
+    /// result.
+    pub fn stack_effect(&self) -> isize {
+        use Vim9Instr::*;
+        match self {
+            PushNumber(_) | PushFloat(_) | PushString(_) | PushBool(_) | PushFunc(_) => 1,
+            LoadLocal(_) | LoadScript { .. } => 1,
+            Add | Sub | Mul | Div | Mod | Concat => -1,
+            CompareLT | CompareGT | CompareLE | CompareGE | CompareEQ | CompareNE => -1,
+            Negate | Not | Jump(_) | CheckType { .. } | ReturnVoid => 0,
+            StoreLocal(_) | StoreScript { .. } | Index | JumpIfFalse(_) | Pop | Echo | Return => -1,
+            NewList(n) => 1 - *n as isize,
+            For { .. } => 1,
+            Call { argc, .. } | CallBuiltin { argc, .. } => 1 - *argc as isize,
+            CallFuncref { argc } => -(*argc as isize),
+        }
+    }
+}
+
+/// The instruction as shown by `:disassemble`.
+impl std::fmt::Display for Vim9Instr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Vim9Instr::*;
+        match self {
+            PushNumber(n) => write!(f, "PUSHNR {}", n),
+            PushFloat(n) => write!(f, "PUSHF {:?}", n),
+            PushString(s) => write!(f, "PUSHS {:?}", s),
+            PushBool(b) => write!(f, "PUSH {}", if *b { "true" } else { "false" }),
+            PushFunc(name) => write!(f, "PUSHFUNC \"{}\"", name),
+            Add => write!(f, "OPANY +"),
+            Sub => write!(f, "OPANY -"),
+            Mul => write!(f, "OPANY *"),
+            Div => write!(f, "OPANY /"),
+            Mod => write!(f, "OPANY %"),
+            Concat => write!(f, "CONCAT size 2"),
+            Negate => write!(f, "NEGATENR"),
+            Not => write!(f, "INVERT (!val)"),
+            CompareLT => write!(f, "COMPAREANY <"),
+            CompareGT => write!(f, "COMPAREANY >"),
+            CompareLE => write!(f, "COMPAREANY <="),
+            CompareGE => write!(f, "COMPAREANY >="),
+            CompareEQ => write!(f, "COMPAREANY =="),
+            CompareNE => write!(f, "COMPAREANY !="),
+            LoadLocal(slot) => write!(f, "LOAD ${}", slot),
+            StoreLocal(slot) => write!(f, "STORE ${}", slot),
+            LoadScript { sid, idx } => write!(f, "LOADSCRIPT [{}] in script {}", idx, sid),
+            StoreScript { sid, idx } => write!(f, "STORESCRIPT [{}] in script {}", idx, sid),
+            NewList(n) => write!(f, "NEWLIST size {}", n),
+            Index => write!(f, "ANYINDEX"),
+            Jump(target) => write!(f, "JUMP -> {}", target),
+            JumpIfFalse(target) => write!(f, "JUMP_IF_FALSE -> {}", target),
+            For { list, end } => write!(f, "FOR ${} -> {}", list, end),
+            Call { name, argc } => write!(f, "DCALL {}(argc {})", name, argc),
+            CallBuiltin { name, argc } => write!(f, "BCALL {}(argc {})", name, argc),
+            CallFuncref { argc } => write!(f, "PCALL (argc {})", argc),
+            CheckType { ty, offset, argnr: 0 } => write!(f, "CHECKTYPE {} stack[{}]", ty, offset),
+            CheckType { ty, offset, argnr } => write!(f, "CHECKTYPE {} stack[{}] arg {}", ty, offset, argnr),
+            Pop => write!(f, "DROP"),
+            Echo => write!(f, "ECHO 1"),
+            Return => write!(f, "RETURN"),
+            ReturnVoid => write!(f, "RETURN void"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -21,4 +138,16 @@ mod tests {
         let sub = Vim9Instr::Sub;
         assert_eq!(sub, Vim9Instr::Sub);
     }
+
+    #[test]
+    fn display_and_stack_effect() {
+        let call = Vim9Instr::Call { name: "Add".to_string(), argc: 2 };
+        assert_eq!(call.to_string(), "DCALL Add(argc 2)");
+        assert_eq!(call.stack_effect(), -1);
+        let check = Vim9Instr::CheckType { ty: Vim9Type::List(Box::new(Vim9Type::Number)), offset: -2, argnr: 1 };
+        assert_eq!(check.to_string(), "CHECKTYPE list<number> stack[-2] arg 1");
+        assert_eq!(Vim9Instr::PushString("a\"b".to_string()).to_string(), "PUSHS \"a\\\"b\"");
+        assert_eq!(Vim9Instr::NewList(3).stack_effect(), -2);
+        assert_eq!(Vim9Instr::CallFuncref { argc: 1 }.stack_effect(), -1);
+    }
 }
@@ -1,13 +1,172 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use std::fmt;
+
+/// Static type of a Vim9 value or expression.
+///
+/// `TypeVar` is a placeholder for a type parameter of a generic function,
+/// e.g. the `T` in `def Map<T, U>(l: list<T>): list<U>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Vim9Type {
+    Any,
+    Void,
     Number,
+    Float,
     Bool,
+    String,
+    List(Box<Vim9Type>),
+    Dict(Box<Vim9Type>),
+    Func(Vec<Vim9Type>, Box<Vim9Type>),
+    TypeVar(String),
 }
 
 impl Vim9Type {
-    pub fn is_number(self) -> bool {
+    pub fn is_number(&self) -> bool {
         matches!(self, Vim9Type::Number)
     }
+
+    /// Return true when the type mentions a type variable anywhere.
+    pub fn is_generic(&self) -> bool {
+        match self {
+            Vim9Type::TypeVar(_) => true,
+            Vim9Type::List(t) | Vim9Type::Dict(t) => t.is_generic(),
+            Vim9Type::Func(args, ret) => args.iter().any(|a| a.is_generic()) || ret.is_generic(),
+            _ => false,
+        }
+    }
+
+    /// Return the type with all type variables replaced by `any`, as used
+    /// when executing a generic function.
+    pub fn erased(&self) -> Vim9Type {
+        match self {
+            Vim9Type::TypeVar(_) => Vim9Type::Any,
+            Vim9Type::List(t) => Vim9Type::List(Box::new(t.erased())),
+            Vim9Type::Dict(t) => Vim9Type::Dict(Box::new(t.erased())),
+            Vim9Type::Func(args, ret) => {
+                Vim9Type::Func(args.iter().map(|a| a.erased()).collect(), Box::new(ret.erased()))
+            }
+            other => other.clone(),
+        }
+    }
+
+    /// Check whether a value of type `actual` can be used where `self` is
+    /// expected.  `any` on either side is accepted, the value is then checked
+    /// at runtime.  A number is accepted where a float is expected.
+    pub fn accepts(&self, actual: &Vim9Type) -> bool {
+        match (self, actual) {
+            (Vim9Type::Any, _) | (_, Vim9Type::Any) => true,
+            (Vim9Type::Float, Vim9Type::Number) => true,
+            (Vim9Type::List(e), Vim9Type::List(a)) | (Vim9Type::Dict(e), Vim9Type::Dict(a)) => {
+                e.accepts(a)
+            }
+            (Vim9Type::Func(ea, er), Vim9Type::Func(aa, ar)) => {
+                ea.len() == aa.len()
+                    && ea.iter().zip(aa).all(|(e, a)| a.accepts(e))
+                    && (**er == Vim9Type::Void || er.accepts(ar))
+            }
+            (e, a) => e == a,
+        }
+    }
+
+    /// Parse a type name such as `list<number>` or `func(string): bool`.
+    /// `type_vars` lists the names that refer to type parameters.
+    pub fn parse(text: &str, type_vars: &[String]) -> Option<Vim9Type> {
+        let (ty, rest) = parse_type(text, type_vars)?;
+        if rest.trim().is_empty() {
+            Some(ty)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parse a type at the start of `text`, returning it together with the
+/// remaining text.
+pub fn parse_type<'a>(text: &'a str, type_vars: &[String]) -> Option<(Vim9Type, &'a str)> {
+    let text = text.trim_start();
+    let end = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(text.len());
+    let (name, rest) = text.split_at(end);
+    let ty = match name {
+        "any" => Vim9Type::Any,
+        "void" => Vim9Type::Void,
+        "number" => Vim9Type::Number,
+        "float" => Vim9Type::Float,
+        "bool" => Vim9Type::Bool,
+        "string" => Vim9Type::String,
+        "list" | "dict" => {
+            let rest = rest.strip_prefix('<')?;
+            let (member, rest) = parse_type(rest, type_vars)?;
+            let rest = rest.trim_start().strip_prefix('>')?;
+            let ty = if name == "list" {
+                Vim9Type::List(Box::new(member))
+            } else {
+                Vim9Type::Dict(Box::new(member))
+            };
+            return Some((ty, rest));
+        }
+        "func" => return parse_func_type(rest, type_vars),
+        _ if type_vars.iter().any(|v| v == name) => Vim9Type::TypeVar(name.to_string()),
+        _ => return None,
+    };
+    Some((ty, rest))
+}
+
+fn parse_func_type<'a>(text: &'a str, type_vars: &[String]) -> Option<(Vim9Type, &'a str)> {
+    let Some(mut rest) = text.strip_prefix('(') else {
+        return Some((Vim9Type::Func(Vec::new(), Box::new(Vim9Type::Any)), text));
+    };
+    let mut args = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(r) = rest.strip_prefix(')') {
+            rest = r;
+            break;
+        }
+        let (arg, r) = parse_type(rest, type_vars)?;
+        args.push(arg);
+        rest = r.trim_start();
+        if let Some(r) = rest.strip_prefix(',') {
+            rest = r;
+        } else if !rest.starts_with(')') {
+            return None;
+        }
+    }
+    if let Some(r) = rest.strip_prefix(':') {
+        let (ret, r) = parse_type(r, type_vars)?;
+        Some((Vim9Type::Func(args, Box::new(ret)), r))
+    } else {
+        Some((Vim9Type::Func(args, Box::new(Vim9Type::Void)), rest))
+    }
+}
+
+impl fmt::Display for Vim9Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Vim9Type::Any => write!(f, "any"),
+            Vim9Type::Void => write!(f, "void"),
+            Vim9Type::Number => write!(f, "number"),
+            Vim9Type::Float => write!(f, "float"),
+            Vim9Type::Bool => write!(f, "bool"),
+            Vim9Type::String => write!(f, "string"),
+            Vim9Type::List(t) => write!(f, "list<{}>", t),
+            Vim9Type::Dict(t) => write!(f, "dict<{}>", t),
+            Vim9Type::Func(args, ret) => {
+                write!(f, "func(")?;
+                for (i, a) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", a)?;
+                }
+                write!(f, ")")?;
+                if **ret != Vim9Type::Void {
+                    write!(f, ": {}", ret)?;
+                }
+                Ok(())
+            }
+            Vim9Type::TypeVar(name) => write!(f, "{}", name),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -19,4 +178,24 @@ mod tests {
         assert!(Vim9Type::Number.is_number());
         assert!(!Vim9Type::Bool.is_number());
     }
+
+    #[test]
+    fn parse_and_display_roundtrip() {
+        let vars = vec!["T".to_string(), "U".to_string()];
+        for text in ["list<number>", "dict<list<string>>", "func(T): U", "func(number)", "list<T>"] {
+            let ty = Vim9Type::parse(text, &vars).unwrap();
+            assert_eq!(ty.to_string(), text);
+        }
+        assert!(Vim9Type::parse("list<T>", &[]).is_none());
+        assert!(Vim9Type::parse("list<T>", &vars).unwrap().is_generic());
+    }
+
+    #[test]
+    fn accepts_compatible_types() {
+        let list_num = Vim9Type::List(Box::new(Vim9Type::Number));
+        assert!(list_num.accepts(&Vim9Type::List(Box::new(Vim9Type::Any))));
+        assert!(!list_num.accepts(&Vim9Type::List(Box::new(Vim9Type::String))));
+        assert!(Vim9Type::Float.accepts(&Vim9Type::Number));
+        assert!(!Vim9Type::Number.accepts(&Vim9Type::String));
+    }
 }
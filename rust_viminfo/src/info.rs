@@ -0,0 +1,928 @@
+//! The contents of a viminfo file and its text format, compatible with Vim.
+//!
+//! Each item is written as the line older Vims understand and, when it has
+//! a timestamp, a "bar line" starting with "|" that carries it:
+//!
+//! ```text
+//! :edit foo
+//! |2,0,1700000000,,"edit foo"
+//! ```
+//!
+//! Bar lines of types this version does not know are kept and written back,
+//! so that the information of a newer Vim is not lost.
+
+use std::collections::BTreeMap;
+
+use crate::options::ViminfoOptions;
+
+/// The version of the viminfo format, in the first bar line.
+const VIMINFO_VERSION: u32 = 4;
+
+const BARTYPE_VERSION: u32 = 1;
+const BARTYPE_HISTORY: u32 = 2;
+const BARTYPE_REGISTER: u32 = 3;
+const BARTYPE_MARK: u32 = 4;
+
+/// The mark name of a jumplist entry in a bar line.
+const JUMPLIST_MARK: char = '\'';
+
+/// The maximum number of jumplist entries.
+pub const JUMPLIST_SIZE: usize = 100;
+
+/// After this many errors the rest of a viminfo file is skipped.
+const MAX_ERRORS: usize = 10;
+
+const CTRL_V: char = '\x16';
+
+/// The kinds of history, in the order of the numbers in bar lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HistType {
+    Cmd,
+    Search,
+    Expr,
+    Input,
+    Debug,
+}
+
+impl HistType {
+    pub const ALL: [HistType; 5] = [HistType::Cmd, HistType::Search, HistType::Expr, HistType::Input, HistType::Debug];
+
+    fn from_bar(nr: u32) -> Option<HistType> {
+        HistType::ALL.get(nr as usize).copied()
+    }
+
+    /// The first character of a line of this history.  The debug history
+    /// only has bar lines.
+    fn line_char(self) -> Option<char> {
+        match self {
+            HistType::Cmd => Some(':'),
+            HistType::Search => Some('?'),
+            HistType::Expr => Some('='),
+            HistType::Input => Some('@'),
+            HistType::Debug => None,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            HistType::Cmd => "Command Line",
+            HistType::Search => "Search String",
+            HistType::Expr => "Expression",
+            HistType::Input => "Input Line",
+            HistType::Debug => "Debug Line",
+        }
+    }
+}
+
+/// A history entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistEntry {
+    pub text: String,
+    /// When the entry was added, in seconds since the epoch, zero when not
+    /// known.
+    pub timestamp: i64,
+    /// The separator of a search pattern, '/' or '?'.
+    pub sep: Option<char>,
+}
+
+/// The type of a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegType {
+    Char,
+    Line,
+    /// Blockwise, with the width of the block.
+    Block(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Register {
+    /// '0' to '9', 'a' to 'z' or '-'.
+    pub name: char,
+    pub kind: RegType,
+    pub lines: Vec<String>,
+    /// The unnamed register points to this one.
+    pub unnamed: bool,
+    pub timestamp: i64,
+}
+
+/// A file mark, '0 to '9 or 'A to 'Z, or a jumplist entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mark {
+    pub name: char,
+    pub lnum: usize,
+    pub col: usize,
+    pub fname: String,
+    pub timestamp: i64,
+}
+
+/// The marks within a file: '"', '^', '.', 'a' to 'z' and the change list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMarks {
+    pub fname: String,
+    /// When the file was last edited.
+    pub timestamp: i64,
+    /// Name, line and column.
+    pub marks: Vec<(char, usize, usize)>,
+}
+
+/// The last search or substitute pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchPattern {
+    pub pattern: String,
+    pub magic: bool,
+    /// 'smartcase' applies, it does not for a `*` search.
+    pub smartcase: bool,
+    /// The offset is in lines.
+    pub line_offset: bool,
+    /// The offset is from the end of the match.
+    pub end_offset: bool,
+    pub offset: i64,
+    /// This pattern was used last, for `n`.
+    pub last: bool,
+}
+
+/// An entry of the buffer list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferEntry {
+    pub fname: String,
+    pub lnum: usize,
+    pub col: usize,
+}
+
+/// A global variable with an uppercase name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobalVar {
+    pub name: String,
+    /// "STR", "NUM", "FLO", "DIC", "LIS", "BLO", "XPL" or "XFL".
+    pub kind: String,
+    pub value: String,
+}
+
+/// Everything stored in a viminfo file.  Lists are ordered newest first.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VimInfo {
+    /// 'encoding' when the file was written.
+    pub encoding: Option<String>,
+    /// Whether 'hlsearch' highlighting was on.
+    pub hlsearch: Option<bool>,
+    pub search_pattern: Option<SearchPattern>,
+    pub substitute_pattern: Option<SearchPattern>,
+    /// The replacement string of the last `:s`.
+    pub substitute_string: Option<String>,
+    pub history: BTreeMap<HistType, Vec<HistEntry>>,
+    pub registers: Vec<Register>,
+    /// '0 to '9 newest first, then 'A to 'Z.
+    pub file_marks: Vec<Mark>,
+    pub jumplist: Vec<Mark>,
+    pub marks: Vec<FileMarks>,
+    pub buffers: Vec<BufferEntry>,
+    pub globals: Vec<GlobalVar>,
+    /// Bar lines of a newer Vim, written back as they are.
+    pub unknown: Vec<String>,
+}
+
+/// The name of register number `nr` in a bar line.
+fn register_name(nr: u32) -> Option<char> {
+    match nr {
+        0..=9 => char::from_digit(nr, 10),
+        10..=35 => Some((b'a' + (nr - 10) as u8) as char),
+        36 => Some('-'),
+        _ => None,
+    }
+}
+
+fn register_nr(name: char) -> u32 {
+    match name {
+        '0'..='9' => name as u32 - '0' as u32,
+        'a'..='z' => name as u32 - 'a' as u32 + 10,
+        _ => 36,
+    }
+}
+
+/// `s` for a line of the viminfo file: a CTRL-V before a CTRL-V and a line
+/// break, which becomes "n".
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            CTRL_V => out.push_str("\x16\x16"),
+            '\n' => out.push_str("\x16n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != CTRL_V {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/// `s` as a string in a bar line: in double quotes, with a backslash
+/// before a backslash and a double quote, a line break as "\n".
+fn bar_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' | '"' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A value in a bar line.
+#[derive(Debug, Clone, PartialEq)]
+enum BarValue {
+    Number(i64),
+    Str(String),
+    Empty,
+}
+
+/// The values of a bar line after the "|".  None when it is malformed.
+fn bar_values(line: &str) -> Option<Vec<BarValue>> {
+    let mut values = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        match chars.peek() {
+            Some('"') => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            'n' => s.push('\n'),
+                            c => s.push(c),
+                        },
+                        c => s.push(c),
+                    }
+                }
+                values.push(BarValue::Str(s));
+            }
+            Some(',') | None => values.push(BarValue::Empty),
+            Some(_) => {
+                let mut n = String::new();
+                while let Some(&c) = chars.peek().filter(|&&c| c != ',') {
+                    n.push(c);
+                    chars.next();
+                }
+                values.push(BarValue::Number(n.parse().ok()?));
+            }
+        }
+        match chars.next() {
+            None => return Some(values),
+            Some(',') => {}
+            Some(_) => return None,
+        }
+    }
+}
+
+impl BarValue {
+    fn number(&self) -> Option<i64> {
+        match self {
+            BarValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn string(&self) -> Option<String> {
+        match self {
+            BarValue::Str(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// What the tab-indented lines after a line belong to.
+enum Block {
+    None,
+    Register(usize),
+    /// The lines of a register that a bar line already gave.
+    Skip,
+    Marks(usize),
+}
+
+/// Replace an item that is already there when `same` says it is the same
+/// one and `item` is not older, otherwise add it.
+fn add_newer<T>(list: &mut Vec<T>, item: T, same: impl Fn(&T) -> bool, timestamp: impl Fn(&T) -> i64) {
+    match list.iter().position(same) {
+        Some(i) if timestamp(&item) >= timestamp(&list[i]) => list[i] = item,
+        Some(_) => {}
+        None => list.push(item),
+    }
+}
+
+fn parse_usize(s: Option<&str>) -> Option<usize> {
+    s?.trim().parse().ok()
+}
+
+impl VimInfo {
+    /// Parse the text of a viminfo file.  Lines that cannot be parsed are
+    /// skipped with an error message, after ten errors the rest is skipped.
+    pub fn parse(text: &str) -> (VimInfo, Vec<String>) {
+        let mut info = VimInfo::default();
+        let mut errors = Vec::new();
+        let mut block = Block::None;
+        for line in text.lines() {
+            if errors.len() >= MAX_ERRORS {
+                errors.push("E136: viminfo: Too many errors, skipping rest of file".to_string());
+                break;
+            }
+            if let Some(rest) = line.strip_prefix('\t') {
+                if !info.parse_block_line(&block, rest) {
+                    errors.push(format!("E575: viminfo: Illegal starting char in line: {}", line));
+                }
+                continue;
+            }
+            block = Block::None;
+            let ok = match line.chars().next() {
+                None | Some('#') => true,
+                Some('|') => info.parse_bar_line(&line[1..]),
+                Some('*') => match line[1..].split_once('=') {
+                    Some(("encoding", enc)) => {
+                        info.encoding = Some(enc.to_string());
+                        true
+                    }
+                    _ => false,
+                },
+                Some('~') => info.parse_pattern(&line[1..]),
+                Some('"') => match info.parse_register(&line[1..]) {
+                    Some(b) => {
+                        block = b;
+                        true
+                    }
+                    None => false,
+                },
+                Some('\'') => info.parse_mark(&line[1..], false),
+                Some('-') => line[1..].strip_prefix('\'').is_some_and(|rest| info.parse_mark(rest, true)),
+                Some('>') => {
+                    let fname = unescape(line[1..].trim_start());
+                    info.marks.push(FileMarks { fname, timestamp: 0, marks: Vec::new() });
+                    block = Block::Marks(info.marks.len() - 1);
+                    true
+                }
+                Some('%') => {
+                    let mut fields = line[1..].split('\t');
+                    let fname = unescape(fields.next().unwrap_or_default());
+                    let lnum = parse_usize(fields.next()).unwrap_or(1);
+                    let col = parse_usize(fields.next()).unwrap_or(0);
+                    info.buffers.push(BufferEntry { fname, lnum, col });
+                    true
+                }
+                Some('!') => {
+                    let mut fields = line[1..].splitn(3, '\t');
+                    match (fields.next(), fields.next(), fields.next()) {
+                        (Some(name), Some(kind), Some(value)) => {
+                            let (name, kind) = (name.to_string(), kind.to_string());
+                            let var = GlobalVar { name: name.clone(), kind, value: unescape(value) };
+                            add_newer(&mut info.globals, var, |v| v.name == *name, |_| 0);
+                            true
+                        }
+                        _ => false,
+                    }
+                }
+                Some('&') => {
+                    info.substitute_string = Some(unescape(&line[1..]));
+                    true
+                }
+                Some(c) => match HistType::ALL.iter().find(|h| h.line_char() == Some(c)) {
+                    Some(&hist) => {
+                        let rest = &line[c.len_utf8()..];
+                        let (sep, text) = match hist {
+                            HistType::Search => match rest.chars().next() {
+                                Some(sep) => (Some(sep), &rest[sep.len_utf8()..]),
+                                None => (None, rest),
+                            },
+                            _ => (None, rest),
+                        };
+                        info.add_history(hist, HistEntry { text: unescape(text), timestamp: 0, sep });
+                        true
+                    }
+                    None => false,
+                },
+            };
+            if !ok {
+                errors.push(format!("E575: viminfo: Illegal starting char in line: {}", line));
+            }
+        }
+        for list in info.history.values_mut() {
+            list.sort_by_key(|entry| std::cmp::Reverse(entry.timestamp));
+        }
+        (info, errors)
+    }
+
+    /// Add `entry` to history `hist`, an entry with the same text is kept
+    /// once, with the newest timestamp.
+    fn add_history(&mut self, hist: HistType, entry: HistEntry) {
+        let list = self.history.entry(hist).or_default();
+        let text = entry.text.clone();
+        add_newer(list, entry, |e| e.text == text, |e| e.timestamp);
+    }
+
+    fn parse_block_line(&mut self, block: &Block, rest: &str) -> bool {
+        match *block {
+            Block::Register(i) => {
+                self.registers[i].lines.push(unescape(rest));
+                true
+            }
+            Block::Marks(i) => {
+                let mut fields = rest.split('\t');
+                let Some(name) = fields.next().and_then(|f| f.chars().next()) else {
+                    return false;
+                };
+                let (Some(a), Some(b)) = (fields.next(), fields.next()) else {
+                    return false;
+                };
+                let file = &mut self.marks[i];
+                if name == '*' {
+                    file.timestamp = a.trim().parse().unwrap_or(0);
+                } else {
+                    file.marks.push((name, a.trim().parse().unwrap_or(0), b.trim().parse().unwrap_or(0)));
+                }
+                true
+            }
+            Block::Skip => true,
+            Block::None => false,
+        }
+    }
+
+    /// `"a\tCHAR\t0`: the start of a register, `""a` for the one the
+    /// unnamed register points to.  Returns where its lines go.
+    fn parse_register(&mut self, rest: &str) -> Option<Block> {
+        let (unnamed, rest) = match rest.strip_prefix('"') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let mut fields = rest.split('\t');
+        let name = fields.next()?.chars().next()?;
+        let kind = match (fields.next()?, parse_usize(fields.next())) {
+            ("CHAR", _) => RegType::Char,
+            ("LINE", _) => RegType::Line,
+            ("BLOCK", width) => RegType::Block(width.unwrap_or(0)),
+            _ => return None,
+        };
+        let reg = Register { name, kind, lines: Vec::new(), unnamed, timestamp: 0 };
+        match self.registers.iter().position(|r| r.name == name) {
+            // A bar line with a timestamp is newer.
+            Some(i) if self.registers[i].timestamp > 0 => Some(Block::Skip),
+            Some(i) => {
+                self.registers[i] = reg;
+                Some(Block::Register(i))
+            }
+            None => {
+                self.registers.push(reg);
+                Some(Block::Register(self.registers.len() - 1))
+            }
+        }
+    }
+
+    /// `'0  12  0  ~/file` or, for the jumplist, `-'  12  0  ~/file`.
+    fn parse_mark(&mut self, rest: &str, jump: bool) -> bool {
+        let Some(name) = rest.chars().next() else {
+            return false;
+        };
+        // Fields are separated by white space, the file name may contain it.
+        let field = |s: &str| {
+            let s = s.trim_start();
+            let end = s.find(char::is_whitespace).unwrap_or(s.len());
+            (s[..end].to_string(), s[end..].to_string())
+        };
+        let (lnum, rest) = field(&rest[name.len_utf8()..]);
+        let (col, rest) = field(&rest);
+        let (Some(lnum), Some(col)) = (parse_usize(Some(&lnum)), parse_usize(Some(&col))) else {
+            return false;
+        };
+        let fname = unescape(rest.trim_start());
+        if jump {
+            self.add_jump(Mark { name: JUMPLIST_MARK, lnum, col, fname, timestamp: 0 });
+        } else {
+            self.add_file_mark(Mark { name, lnum, col, fname, timestamp: 0 });
+        }
+        true
+    }
+
+    fn add_file_mark(&mut self, mark: Mark) {
+        let name = mark.name;
+        add_newer(&mut self.file_marks, mark, |m| m.name == name, |m| m.timestamp);
+    }
+
+    fn add_jump(&mut self, mark: Mark) {
+        let (fname, lnum) = (mark.fname.clone(), mark.lnum);
+        add_newer(&mut self.jumplist, mark, |m| m.fname == fname && m.lnum == lnum, |m| m.timestamp);
+    }
+
+    /// `~h`, `~H` or a pattern: `~MSle0~/pat`, `~MSle0&pat` for the
+    /// substitute pattern.
+    fn parse_pattern(&mut self, rest: &str) -> bool {
+        match rest {
+            "h" | "H" => {
+                self.hlsearch = Some(rest == "H");
+                return true;
+            }
+            _ => {}
+        }
+        let b = rest.as_bytes();
+        if b.len() < 5 {
+            return false;
+        }
+        let digits = rest[4..].find(|c: char| !c.is_ascii_digit() && c != '-').map_or(rest.len(), |i| i + 4);
+        let Ok(offset) = rest[4..digits].parse() else {
+            return false;
+        };
+        let after = &rest[digits..];
+        let (last, after) = match after.strip_prefix('~') {
+            Some(after) => (true, after),
+            None => (false, after),
+        };
+        let pattern = |p: &str| SearchPattern {
+            pattern: unescape(p),
+            magic: b[0] == b'M',
+            smartcase: b[1] == b'S',
+            line_offset: b[2] == b'L',
+            end_offset: b[3] == b'E',
+            offset,
+            last,
+        };
+        if let Some(p) = after.strip_prefix('/') {
+            self.search_pattern = Some(pattern(p));
+        } else if let Some(p) = after.strip_prefix('&') {
+            self.substitute_pattern = Some(pattern(p));
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn parse_bar_line(&mut self, line: &str) -> bool {
+        let Some(values) = bar_values(line) else {
+            return false;
+        };
+        let Some(kind) = values.first().and_then(BarValue::number) else {
+            return false;
+        };
+        let num = |i: usize| values.get(i).and_then(BarValue::number);
+        let string = |i: usize| values.get(i).and_then(BarValue::string);
+        match kind as u32 {
+            BARTYPE_VERSION => true,
+            BARTYPE_HISTORY => {
+                let (Some(hist), Some(timestamp), Some(text)) =
+                    (num(1).and_then(|n| HistType::from_bar(n as u32)), num(2), string(4))
+                else {
+                    return false;
+                };
+                let sep = num(3).and_then(|c| char::from_u32(c as u32));
+                self.add_history(hist, HistEntry { text, timestamp, sep });
+                true
+            }
+            BARTYPE_REGISTER => {
+                let (Some(flags), Some(name), Some(kind), Some(width), Some(timestamp)) =
+                    (num(1), num(2).and_then(|n| register_name(n as u32)), num(3), num(5), num(6))
+                else {
+                    return false;
+                };
+                let kind = match kind {
+                    0 => RegType::Char,
+                    1 => RegType::Line,
+                    _ => RegType::Block(width as usize),
+                };
+                let lines = values[7..].iter().filter_map(BarValue::string).collect();
+                let reg = Register { name, kind, lines, unnamed: flags & 1 != 0, timestamp };
+                add_newer(&mut self.registers, reg, |r| r.name == name, |r| r.timestamp);
+                true
+            }
+            BARTYPE_MARK => {
+                let (Some(name), Some(lnum), Some(col), Some(timestamp), Some(fname)) =
+                    (num(1).and_then(|n| char::from_u32(n as u32)), num(2), num(3), num(4), string(5))
+                else {
+                    return false;
+                };
+                let mark = Mark { name, lnum: lnum as usize, col: col as usize, fname, timestamp };
+                if name == JUMPLIST_MARK {
+                    self.add_jump(mark);
+                } else {
+                    self.add_file_mark(mark);
+                }
+                true
+            }
+            _ => {
+                self.unknown.push(format!("|{}", line));
+                true
+            }
+        }
+    }
+
+    /// The text of the viminfo file for this information, with what is
+    /// stored limited as `opts` say.
+    pub fn render(&self, opts: &ViminfoOptions) -> String {
+        let mut out = String::new();
+        out.push_str("# This viminfo file was generated by Vim 9.1.\n");
+        out.push_str("# You may edit it if you're careful!\n\n");
+        out.push_str("# Viminfo version\n");
+        out.push_str(&format!("|{},{}\n", BARTYPE_VERSION, VIMINFO_VERSION));
+        for line in &self.unknown {
+            out.push_str(line);
+            out.push('\n');
+        }
+        if let Some(enc) = &self.encoding {
+            out.push_str("\n# Value of 'encoding' when this file was written\n");
+            out.push_str(&format!("*encoding={}\n", enc));
+        }
+        self.render_patterns(&mut out, opts);
+        for hist in HistType::ALL {
+            self.render_history(&mut out, hist, opts.history_len(hist));
+        }
+        self.render_registers(&mut out, opts);
+        if opts.file_marks {
+            self.render_file_marks(&mut out, opts);
+        }
+        if opts.files > 0 {
+            self.render_jumplist(&mut out, opts);
+            self.render_marks(&mut out, opts);
+        }
+        if let Some(max) = opts.buffers {
+            out.push_str("\n# Buffer list:\n");
+            for buf in self.buffers.iter().take(max) {
+                out.push_str(&format!("%{}\t{}\t{}\n", escape(&buf.fname), buf.lnum, buf.col));
+            }
+        }
+        if opts.globals && !self.globals.is_empty() {
+            out.push_str("\n# global variables:\n");
+            for var in &self.globals {
+                out.push_str(&format!("!{}\t{}\t{}\n", var.name, var.kind, escape(&var.value)));
+            }
+        }
+        out
+    }
+
+    fn render_patterns(&self, out: &mut String, opts: &ViminfoOptions) {
+        if let Some(hlsearch) = self.hlsearch {
+            out.push_str("\n# hlsearch on (H) or off (h):\n");
+            out.push_str(if hlsearch && !opts.no_hlsearch { "~H\n" } else { "~h\n" });
+        }
+        if opts.search_history == 0 {
+            return;
+        }
+        for (what, sep, pat) in [("", '/', &self.search_pattern), ("Substitute ", '&', &self.substitute_pattern)] {
+            let Some(p) = pat else { continue };
+            out.push_str(&format!("\n# Last {}Search Pattern:\n~", what));
+            out.push_str(&format!(
+                "{}{}{}{}{}{}{}{}\n",
+                if p.magic { 'M' } else { 'm' },
+                if p.smartcase { 'S' } else { 's' },
+                if p.line_offset { 'L' } else { 'l' },
+                if p.end_offset { 'E' } else { 'e' },
+                p.offset,
+                if p.last { "~" } else { "" },
+                sep,
+                escape(&p.pattern)
+            ));
+        }
+        if let Some(s) = &self.substitute_string {
+            out.push_str("\n# Last Substitute String:\n$\n");
+            out.push_str(&format!("&{}\n", escape(s)));
+        }
+    }
+
+    fn render_history(&self, out: &mut String, hist: HistType, max: usize) {
+        let Some(list) = self.history.get(&hist).filter(|list| !list.is_empty() && max > 0) else {
+            return;
+        };
+        out.push_str(&format!("\n# {} History (newest to oldest):\n", hist.title()));
+        for entry in list.iter().take(max) {
+            if let Some(c) = hist.line_char() {
+                let sep = entry.sep.map(String::from).unwrap_or_default();
+                out.push_str(&format!("{}{}{}\n", c, sep, escape(&entry.text)));
+            }
+            let sep = entry.sep.map(|c| (c as u32).to_string()).unwrap_or_default();
+            out.push_str(&format!(
+                "|{},{},{},{},{}\n",
+                BARTYPE_HISTORY,
+                hist as u32,
+                entry.timestamp,
+                sep,
+                bar_string(&entry.text)
+            ));
+        }
+    }
+
+    fn render_registers(&self, out: &mut String, opts: &ViminfoOptions) {
+        if opts.register_lines == Some(0) || self.registers.is_empty() {
+            return;
+        }
+        out.push_str("\n# Registers:\n");
+        for reg in &self.registers {
+            let size = reg.lines.iter().map(|l| l.len() + 1).sum::<usize>();
+            if opts.register_kbyte.is_some_and(|kb| size > kb * 1024) {
+                continue;
+            }
+            let lines = &reg.lines[..reg.lines.len().min(opts.register_lines.unwrap_or(usize::MAX))];
+            let (kind, kind_nr, width) = match reg.kind {
+                RegType::Char => ("CHAR", 0, 0),
+                RegType::Line => ("LINE", 1, 0),
+                RegType::Block(width) => ("BLOCK", 2, width),
+            };
+            let unnamed = if reg.unnamed { "\"" } else { "" };
+            out.push_str(&format!("\"{}{}\t{}\t{}\n", unnamed, reg.name, kind, width));
+            for line in lines {
+                out.push_str(&format!("\t{}\n", escape(line)));
+            }
+            out.push_str(&format!(
+                "|{},{},{},{},{},{},{}",
+                BARTYPE_REGISTER,
+                reg.unnamed as u8,
+                register_nr(reg.name),
+                kind_nr,
+                lines.len(),
+                width,
+                reg.timestamp
+            ));
+            for line in lines {
+                out.push(',');
+                out.push_str(&bar_string(line));
+            }
+            out.push('\n');
+        }
+    }
+
+    fn render_mark(out: &mut String, prefix: &str, mark: &Mark) {
+        out.push_str(&format!("{}  {}  {}  {}\n", prefix, mark.lnum, mark.col, escape(&mark.fname)));
+        out.push_str(&format!(
+            "|{},{},{},{},{},{}\n",
+            BARTYPE_MARK,
+            mark.name as u32,
+            mark.lnum,
+            mark.col,
+            mark.timestamp,
+            bar_string(&mark.fname)
+        ));
+    }
+
+    fn render_file_marks(&self, out: &mut String, opts: &ViminfoOptions) {
+        let marks: Vec<&Mark> = self.file_marks.iter().filter(|m| !opts.is_removable(&m.fname)).collect();
+        if marks.is_empty() {
+            return;
+        }
+        out.push_str("\n# File marks:\n");
+        for mark in marks {
+            VimInfo::render_mark(out, &format!("'{}", mark.name), mark);
+        }
+    }
+
+    fn render_jumplist(&self, out: &mut String, opts: &ViminfoOptions) {
+        let jumps: Vec<&Mark> = self.jumplist.iter().filter(|m| !opts.is_removable(&m.fname)).collect();
+        if jumps.is_empty() {
+            return;
+        }
+        out.push_str("\n# Jumplist (newest first):\n");
+        for mark in jumps.into_iter().take(JUMPLIST_SIZE) {
+            VimInfo::render_mark(out, "-'", mark);
+        }
+    }
+
+    fn render_marks(&self, out: &mut String, opts: &ViminfoOptions) {
+        out.push_str("\n# History of marks within files (newest to oldest):\n");
+        for file in self.marks.iter().filter(|f| !opts.is_removable(&f.fname)).take(opts.files) {
+            out.push_str(&format!("\n> {}\n", escape(&file.fname)));
+            out.push_str(&format!("\t*\t{}\t0\n", file.timestamp));
+            for (name, lnum, col) in &file.marks {
+                out.push_str(&format!("\t{}\t{}\t{}\n", name, lnum, col));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+# This viminfo file was generated by Vim 9.1.
+# You may edit it if you're careful!
+
+# Viminfo version
+|1,4
+|9,1,\"from a newer Vim\"
+
+# Value of 'encoding' when this file was written
+*encoding=utf-8
+
+# hlsearch on (H) or off (h):
+~H
+
+# Last Search Pattern:
+~MSle0~/foo\\|bar
+
+# Command Line History (newest to oldest):
+:edit x
+|2,0,1700000300,,\"edit x\"
+:s/a/b/
+|2,0,1700000100,,\"s/a/b/\"
+
+# Search String History (newest to oldest):
+?/foo\\|bar
+|2,1,1700000200,47,\"foo\\\\|bar\"
+
+# Registers:
+\"\"a\tLINE\t0
+\tfirst
+\tsecond
+|3,1,10,1,2,0,1700000000,\"first\",\"second\"
+
+# File marks:
+'A  3  4  ~/notes.txt
+|4,65,3,4,1700000000,\"~/notes.txt\"
+
+# Jumplist (newest first):
+-'  10  0  ~/notes.txt
+|4,39,10,0,1700000000,\"~/notes.txt\"
+
+# History of marks within files (newest to oldest):
+
+> ~/notes.txt
+\t*\t1700000000\t0
+\t\"\t3\t4
+\ta\t1\t0
+
+# Buffer list:
+%~/notes.txt\t3\t4
+
+# global variables:
+!COUNT\tNUM\t42
+";
+
+    #[test]
+    fn parse_and_render() {
+        let (info, errors) = VimInfo::parse(SAMPLE);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(info.encoding.as_deref(), Some("utf-8"));
+        assert_eq!(info.hlsearch, Some(true));
+        let search = info.search_pattern.clone().unwrap();
+        assert_eq!((search.pattern.as_str(), search.magic, search.last), ("foo\\|bar", true, true));
+        let cmds: Vec<&str> = info.history[&HistType::Cmd].iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(cmds, ["edit x", "s/a/b/"]);
+        let search = &info.history[&HistType::Search];
+        assert_eq!((search.len(), search[0].sep, search[0].timestamp), (1, Some('/'), 1_700_000_200));
+        assert_eq!(info.registers.len(), 1);
+        assert_eq!(info.registers[0].lines, ["first", "second"]);
+        assert!(info.registers[0].unnamed && info.registers[0].kind == RegType::Line);
+        assert_eq!(info.file_marks[0].timestamp, 1_700_000_000);
+        assert_eq!(info.jumplist.len(), 1);
+        assert_eq!(info.marks[0].marks, [('"', 3, 4), ('a', 1, 0)]);
+        assert_eq!(info.buffers[0], BufferEntry { fname: "~/notes.txt".into(), lnum: 3, col: 4 });
+        assert_eq!(info.globals[0].value, "42");
+        assert_eq!(info.unknown, ["|9,1,\"from a newer Vim\""]);
+
+        // Everything survives writing and reading back.
+        let opts = ViminfoOptions::parse("'100,<50,s10,%,!", 50).unwrap();
+        let text = info.render(&opts);
+        assert_eq!(VimInfo::parse(&text), (info.clone(), Vec::new()));
+        assert!(text.contains("|2,1,1700000200,47,\"foo\\\\|bar\"\n"));
+        assert!(text.contains("|3,1,10,1,2,0,1700000000,\"first\",\"second\"\n"));
+
+        // The limits of 'viminfo'.
+        let text = info.render(&ViminfoOptions::parse("'0,:1,/0,<1,f0,h", 50).unwrap());
+        let (limited, _) = VimInfo::parse(&text);
+        assert_eq!(limited.history[&HistType::Cmd].len(), 1);
+        assert!(!limited.history.contains_key(&HistType::Search) && limited.search_pattern.is_none());
+        assert_eq!(limited.registers[0].lines, ["first"]);
+        assert!(limited.file_marks.is_empty() && limited.jumplist.is_empty() && limited.marks.is_empty());
+        assert!(limited.buffers.is_empty() && limited.globals.is_empty());
+        assert_eq!(limited.hlsearch, Some(false));
+    }
+
+    #[test]
+    fn escaping_and_errors() {
+        let mut info = VimInfo::default();
+        let text = "line one\nwith \"quotes\" and \\ \x16";
+        info.add_history(HistType::Input, HistEntry { text: text.to_string(), timestamp: 5, sep: None });
+        let rendered = info.render(&ViminfoOptions::default());
+        assert!(rendered.contains("@line one\x16nwith \"quotes\" and \\ \x16\x16\n"));
+        assert_eq!(VimInfo::parse(&rendered).0.history[&HistType::Input][0].text, text);
+
+        let (_, errors) = VimInfo::parse("x bad line\n:ok\n");
+        assert_eq!(errors, ["E575: viminfo: Illegal starting char in line: x bad line"]);
+        let (info, errors) = VimInfo::parse(&"x\n".repeat(20));
+        assert_eq!(errors.len(), 11);
+        assert_eq!(errors[10], "E136: viminfo: Too many errors, skipping rest of file");
+        assert_eq!(info, VimInfo::default());
+    }
+}
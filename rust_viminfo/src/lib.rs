@@ -1,22 +1,91 @@
+//! The viminfo file: command line and search history, registers, marks,
+//! the jumplist, the buffer list and global variables, remembered between
+//! Vim sessions.
+//!
+//! Several Vims may write the same file.  Before writing, the file is read
+//! again and merged, so that the newest of each item is kept, and it is
+//! replaced at once by renaming a temp file, so that a Vim reading it never
+//! sees half of it.
+
+mod info;
+mod merge;
+mod options;
+
+pub use info::{
+    BufferEntry, FileMarks, GlobalVar, HistEntry, HistType, Mark, RegType, Register, SearchPattern, VimInfo,
+    JUMPLIST_SIZE,
+};
+pub use options::{ViminfoOptions, HISTORY_DEFAULT, VIMINFO_DEFAULT};
+
 use std::ffi::{CStr, CString};
 use std::fs;
+use std::io::Write;
 use std::os::raw::{c_char, c_int};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Default)]
-pub struct VimInfo {
-    pub lines: Vec<String>,
+/// Read the viminfo file `path`.  Lines that cannot be parsed are skipped.
+pub fn read(path: &Path) -> std::io::Result<VimInfo> {
+    let content = fs::read(path)?;
+    Ok(VimInfo::parse(&String::from_utf8_lossy(&content)).0)
 }
 
-pub fn read(path: &Path) -> std::io::Result<VimInfo> {
-    let content = fs::read_to_string(path)?;
-    Ok(VimInfo {
-        lines: content.lines().map(|s| s.to_string()).collect(),
-    })
+/// The name of the temp file to write `path` with: "viminfo.tmp", when it
+/// exists, maybe because another Vim is writing, "viminfo.tmz" down to
+/// "viminfo.tma".
+fn temp_name(path: &Path) -> Result<PathBuf, String> {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".tmp");
+    let mut temp = PathBuf::from(name);
+    let mut next = b'z';
+    while temp.symlink_metadata().is_ok() {
+        if next < b'a' {
+            return Err(format!("E929: Too many viminfo temp files, like {}!", temp.display()));
+        }
+        temp.set_extension(format!("tm{}", next as char));
+        next -= 1;
+    }
+    Ok(temp)
 }
 
-pub fn write(path: &Path, info: &VimInfo) -> std::io::Result<()> {
-    fs::write(path, info.lines.join("\n"))
+/// Write `info` to the viminfo file `path`, merged with what is in it, as
+/// `opts` says.
+pub fn write(path: &Path, info: &VimInfo, opts: &ViminfoOptions) -> Result<(), String> {
+    let mut info = info.clone();
+    let old = fs::metadata(path).ok();
+    if old.is_some() {
+        match fs::read(path) {
+            Ok(content) => info.merge(VimInfo::parse(&String::from_utf8_lossy(&content)).0),
+            Err(_) => return Err(format!("E137: Viminfo file is not writable: {}", path.display())),
+        }
+    }
+
+    let temp = temp_name(path)?;
+    let cannot_write = |_| format!("E574: Cannot write viminfo file {}!", temp.display());
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&temp).map_err(cannot_write)?;
+    let result = (|| {
+        // A new viminfo file is only readable by the user: it may contain
+        // text of files that others cannot read.
+        match &old {
+            Some(meta) => fs::set_permissions(&temp, meta.permissions())?,
+            #[cfg(unix)]
+            None => {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&temp, fs::Permissions::from_mode(0o600))?
+            }
+            #[cfg(not(unix))]
+            None => {}
+        }
+        file.write_all(info.render(opts).as_bytes())?;
+        file.sync_all()
+    })();
+    drop(file);
+    if let Err(err) = result.map_err(cannot_write).and_then(|()| {
+        fs::rename(&temp, path).map_err(|_| format!("E138: Can't write viminfo file {}!", path.display()))
+    }) {
+        let _ = fs::remove_file(&temp);
+        return Err(err);
+    }
+    Ok(())
 }
 
 #[no_mangle]
@@ -26,11 +95,12 @@ pub extern "C" fn rs_viminfo_read(path: *const c_char) -> *mut c_char {
     }
     let path = unsafe { CStr::from_ptr(path) }.to_string_lossy().into_owned();
     match read(Path::new(&path)) {
-        Ok(info) => CString::new(info.lines.join("\n")).unwrap().into_raw(),
+        Ok(info) => CString::new(info.render(&ViminfoOptions::default())).unwrap().into_raw(),
         Err(_) => std::ptr::null_mut(),
     }
 }
 
+/// Merge `data`, in the viminfo format, into the file `path`.
 #[no_mangle]
 pub extern "C" fn rs_viminfo_write(path: *const c_char, data: *const c_char) -> c_int {
     if path.is_null() || data.is_null() {
@@ -38,10 +108,8 @@ pub extern "C" fn rs_viminfo_write(path: *const c_char, data: *const c_char) ->
     }
     let path = unsafe { CStr::from_ptr(path) }.to_string_lossy().into_owned();
     let data = unsafe { CStr::from_ptr(data) }.to_string_lossy();
-    let info = VimInfo {
-        lines: data.lines().map(|s| s.to_string()).collect(),
-    };
-    match write(Path::new(&path), &info) {
+    let (info, _) = VimInfo::parse(&data);
+    match write(Path::new(&path), &info, &ViminfoOptions::default()) {
         Ok(()) => 0,
         Err(_) => -1,
     }
@@ -59,14 +127,56 @@ mod tests {
     use super::*;
     use std::env;
 
+    fn entry(text: &str, timestamp: i64) -> HistEntry {
+        HistEntry { text: text.into(), timestamp, sep: None }
+    }
+
     #[test]
     fn roundtrip() {
         let dir = env::temp_dir();
         let file = dir.join("viminfo_test.txt");
-        let info = VimInfo { lines: vec!["alpha".into(), "beta".into()] };
-        write(&file, &info).unwrap();
+        let _ = fs::remove_file(&file);
+        let mut info = VimInfo::default();
+        info.history.insert(HistType::Cmd, vec![entry("alpha", 2), entry("beta", 1)]);
+        write(&file, &info, &ViminfoOptions::default()).unwrap();
         let read_back = read(&file).unwrap();
-        assert_eq!(read_back.lines, info.lines);
+        assert_eq!(read_back.history, info.history);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o600);
+        }
         fs::remove_file(file).unwrap();
     }
+
+    #[test]
+    fn merge_with_file() {
+        let dir = env::temp_dir().join(format!("viminfo_merge_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("viminfo");
+        let mut first = VimInfo::default();
+        first.history.insert(HistType::Cmd, vec![entry("first", 10), entry("both", 5)]);
+        write(&file, &first, &ViminfoOptions::default()).unwrap();
+
+        // Another Vim writes later, what the first one wrote is kept.
+        let mut second = VimInfo::default();
+        second.history.insert(HistType::Cmd, vec![entry("both", 20), entry("second", 15)]);
+        write(&file, &second, &ViminfoOptions::parse("'100,:2", 50).unwrap()).unwrap();
+        let texts: Vec<String> = read(&file).unwrap().history[&HistType::Cmd].iter().map(|e| e.text.clone()).collect();
+        assert_eq!(texts, ["both", "second"]);
+
+        // A temp file left behind by another Vim is not used.
+        fs::write(dir.join("viminfo.tmp"), "").unwrap();
+        write(&file, &first, &ViminfoOptions::default()).unwrap();
+        assert_eq!(fs::read(dir.join("viminfo.tmp")).unwrap(), b"");
+        assert!(!dir.join("viminfo.tmz").exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        for c in b'a'..=b'z' {
+            fs::write(dir.join(format!("viminfo.tm{}", c as char)), "").unwrap();
+        }
+        let err = write(&file, &first, &ViminfoOptions::default()).unwrap_err();
+        assert!(err.starts_with("E929: Too many viminfo temp files"), "{}", err);
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
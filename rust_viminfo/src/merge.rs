@@ -0,0 +1,168 @@
+//! Merging with the viminfo file written by another Vim: for each item the
+//! newest one is kept, by the timestamps in the bar lines.
+
+use crate::info::{HistEntry, Mark, VimInfo, JUMPLIST_SIZE};
+
+/// The number of numbered file marks, '0 to '9.
+const NUMBERED_MARKS: usize = 10;
+
+impl VimInfo {
+    /// Merge `other`, read from the viminfo file, into this information
+    /// from the running Vim.
+    ///
+    /// Histories, registers, marks and the jumplist keep the newest of both.
+    /// The buffer list and global variables are those of this Vim, the
+    /// search patterns and 'hlsearch' too unless it has none.
+    pub fn merge(&mut self, other: VimInfo) {
+        for (hist, entries) in other.history {
+            let list = self.history.entry(hist).or_default();
+            for entry in entries {
+                merge_entry(list, entry);
+            }
+            list.sort_by_key(|entry| std::cmp::Reverse(entry.timestamp));
+        }
+
+        for reg in other.registers {
+            match self.registers.iter_mut().find(|r| r.name == reg.name) {
+                Some(r) if reg.timestamp > r.timestamp => *r = reg,
+                Some(_) => {}
+                None => self.registers.push(reg),
+            }
+        }
+        // Only one register can be pointed to, the newest.
+        if let Some(newest) = self.registers.iter().filter(|r| r.unnamed).max_by_key(|r| r.timestamp).map(|r| r.name) {
+            for r in &mut self.registers {
+                r.unnamed = r.name == newest;
+            }
+        }
+        self.registers.sort_by_key(|r| r.name);
+
+        let (mut numbered, mut named): (Vec<Mark>, Vec<Mark>) =
+            std::mem::take(&mut self.file_marks).into_iter().partition(|m| m.name.is_ascii_digit());
+        let (other_numbered, other_named): (Vec<Mark>, Vec<Mark>) =
+            other.file_marks.into_iter().partition(|m| m.name.is_ascii_digit());
+        for mark in other_named {
+            match named.iter_mut().find(|m| m.name == mark.name) {
+                Some(m) if mark.timestamp > m.timestamp => *m = mark,
+                Some(_) => {}
+                None => named.push(mark),
+            }
+        }
+        named.sort_by_key(|m| m.name);
+        // '0 is the newest position of all Vims, the others shift down.
+        for mark in other_numbered {
+            if !numbered.iter().any(|m| m.fname == mark.fname && m.lnum == mark.lnum) {
+                numbered.push(mark);
+            }
+        }
+        numbered.sort_by_key(|m| std::cmp::Reverse(m.timestamp));
+        numbered.truncate(NUMBERED_MARKS);
+        for (i, mark) in numbered.iter_mut().enumerate() {
+            mark.name = char::from_digit(i as u32, 10).unwrap();
+        }
+        self.file_marks = numbered;
+        self.file_marks.extend(named);
+
+        for jump in other.jumplist {
+            match self.jumplist.iter_mut().find(|m| m.fname == jump.fname && m.lnum == jump.lnum) {
+                Some(m) if jump.timestamp > m.timestamp => *m = jump,
+                Some(_) => {}
+                None => self.jumplist.push(jump),
+            }
+        }
+        self.jumplist.sort_by_key(|m| std::cmp::Reverse(m.timestamp));
+        self.jumplist.truncate(JUMPLIST_SIZE);
+
+        for file in other.marks {
+            match self.marks.iter_mut().find(|f| f.fname == file.fname) {
+                Some(f) if file.timestamp > f.timestamp => *f = file,
+                Some(_) => {}
+                None => self.marks.push(file),
+            }
+        }
+        self.marks.sort_by_key(|f| std::cmp::Reverse(f.timestamp));
+
+        if self.search_pattern.is_none() {
+            self.search_pattern = other.search_pattern;
+        }
+        if self.substitute_pattern.is_none() {
+            self.substitute_pattern = other.substitute_pattern;
+        }
+        if self.substitute_string.is_none() {
+            self.substitute_string = other.substitute_string;
+        }
+        if self.hlsearch.is_none() {
+            self.hlsearch = other.hlsearch;
+        }
+        if self.encoding.is_none() {
+            self.encoding = other.encoding;
+        }
+        for line in other.unknown {
+            if !self.unknown.contains(&line) {
+                self.unknown.push(line);
+            }
+        }
+    }
+}
+
+/// Add `entry` to `list`, when the text is already there keep the newest
+/// timestamp.
+fn merge_entry(list: &mut Vec<HistEntry>, entry: HistEntry) {
+    match list.iter_mut().find(|e| e.text == entry.text && e.sep == entry.sep) {
+        Some(e) => e.timestamp = e.timestamp.max(entry.timestamp),
+        None => list.push(entry),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::info::{FileMarks, HistType, RegType, Register};
+
+    use super::*;
+
+    fn hist(text: &str, timestamp: i64) -> HistEntry {
+        HistEntry { text: text.to_string(), timestamp, sep: None }
+    }
+
+    fn mark(name: char, fname: &str, timestamp: i64) -> Mark {
+        Mark { name, lnum: 1, col: 0, fname: fname.to_string(), timestamp }
+    }
+
+    fn reg(name: char, text: &str, timestamp: i64) -> Register {
+        Register { name, kind: RegType::Char, lines: vec![text.to_string()], unnamed: true, timestamp }
+    }
+
+    #[test]
+    fn newest_wins() {
+        let mut ours = VimInfo::default();
+        ours.history.insert(HistType::Cmd, vec![hist("w", 30), hist("e a", 10)]);
+        ours.registers = vec![reg('a', "ours", 20), reg('b', "ours", 5)];
+        ours.file_marks = vec![mark('0', "x", 20), mark('A', "ours", 5)];
+        ours.marks = vec![FileMarks { fname: "x".into(), timestamp: 20, marks: vec![('a', 1, 0)] }];
+        ours.hlsearch = Some(true);
+
+        let mut theirs = VimInfo::default();
+        theirs.history.insert(HistType::Cmd, vec![hist("e b", 40), hist("e a", 15), hist("q", 1)]);
+        theirs.registers = vec![reg('a', "theirs", 10), reg('b', "theirs", 25)];
+        theirs.file_marks = vec![mark('0', "y", 25), mark('1', "x", 15), mark('A', "theirs", 8)];
+        theirs.marks = vec![
+            FileMarks { fname: "x".into(), timestamp: 10, marks: vec![('a', 5, 0)] },
+            FileMarks { fname: "y".into(), timestamp: 30, marks: Vec::new() },
+        ];
+        theirs.hlsearch = Some(false);
+        theirs.unknown = vec!["|9,\"new\"".into()];
+        ours.merge(theirs);
+
+        let cmds: Vec<(&str, i64)> =
+            ours.history[&HistType::Cmd].iter().map(|e| (e.text.as_str(), e.timestamp)).collect();
+        assert_eq!(cmds, [("e b", 40), ("w", 30), ("e a", 15), ("q", 1)]);
+        let regs: Vec<(&str, bool)> = ours.registers.iter().map(|r| (r.lines[0].as_str(), r.unnamed)).collect();
+        assert_eq!(regs, [("ours", false), ("theirs", true)]);
+        let marks: Vec<(char, &str)> = ours.file_marks.iter().map(|m| (m.name, m.fname.as_str())).collect();
+        assert_eq!(marks, [('0', "y"), ('1', "x"), ('A', "theirs")]);
+        let files: Vec<(&str, i64)> = ours.marks.iter().map(|f| (f.fname.as_str(), f.timestamp)).collect();
+        assert_eq!(files, [("y", 30), ("x", 20)]);
+        assert_eq!(ours.hlsearch, Some(true));
+        assert_eq!(ours.unknown, ["|9,\"new\""]);
+    }
+}
@@ -0,0 +1,155 @@
+//! The 'viminfo' option: what is stored in the viminfo file and how much of
+//! it, like `'100,<50,s10,h`.
+
+use crate::info::HistType;
+
+/// The default of 'viminfo'.
+pub const VIMINFO_DEFAULT: &str = "'100,<50,s10,h";
+
+/// The default of 'history'.
+pub const HISTORY_DEFAULT: usize = 50;
+
+/// The parsed 'viminfo' option.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViminfoOptions {
+    /// `'`: the number of files for which marks are remembered.  Zero also
+    /// leaves out the jumplist.
+    pub files: usize,
+    /// `<` or `"`: the maximum number of lines of a register, None for no
+    /// limit.  Zero leaves out registers.
+    pub register_lines: Option<usize>,
+    /// `s`: the maximum size of a register in Kbyte, larger ones are left
+    /// out.
+    pub register_kbyte: Option<usize>,
+    /// `:`: the number of command lines, 'history' when not given.
+    pub cmd_history: usize,
+    /// `/`: the number of search patterns, 'history' when not given.  Zero
+    /// also leaves out the last search and substitute patterns.
+    pub search_history: usize,
+    /// `@`: the number of input lines, 'history' when not given.
+    pub input_history: usize,
+    /// 'history', for the expression and debug histories.
+    pub history: usize,
+    /// `%`: store the buffer list, at most this many buffers.
+    pub buffers: Option<usize>,
+    /// `!`: store global variables with an uppercase name.
+    pub globals: bool,
+    /// `f`: store file marks '0 to '9 and 'A to 'Z.
+    pub file_marks: bool,
+    /// `h`: do not restore 'hlsearch'.
+    pub no_hlsearch: bool,
+    /// `n`: the name of the viminfo file.
+    pub name: Option<String>,
+    /// `r`: files starting with one of these are on removable media, no
+    /// marks are stored for them.
+    pub removable: Vec<String>,
+}
+
+impl Default for ViminfoOptions {
+    fn default() -> Self {
+        ViminfoOptions::parse(VIMINFO_DEFAULT, HISTORY_DEFAULT).expect("the default is valid")
+    }
+}
+
+impl ViminfoOptions {
+    /// Parse the value of 'viminfo', with `history` the value of 'history'.
+    pub fn parse(value: &str, history: usize) -> Result<ViminfoOptions, String> {
+        let mut opts = ViminfoOptions {
+            files: 0,
+            register_lines: None,
+            register_kbyte: None,
+            cmd_history: history,
+            search_history: history,
+            input_history: history,
+            history,
+            buffers: None,
+            globals: false,
+            file_marks: true,
+            no_hlsearch: false,
+            name: None,
+            removable: Vec::new(),
+        };
+        let mut files = None;
+        let mut rest = value;
+        while !rest.is_empty() {
+            let flag = rest.chars().next().unwrap();
+            rest = &rest[flag.len_utf8()..];
+            // "n" takes the rest of the option, "r" a name up to the comma.
+            let arg_len = match flag {
+                'n' => rest.len(),
+                'r' => rest.find(',').unwrap_or(rest.len()),
+                _ => rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len()),
+            };
+            let (arg, after) = rest.split_at(arg_len);
+            let number = || arg.parse::<usize>().map_err(|_| format!("E526: Missing number after <{}>", flag));
+            match flag {
+                '\'' => files = Some(number()?),
+                '<' | '"' => opts.register_lines = Some(number()?),
+                's' => opts.register_kbyte = Some(number()?),
+                ':' => opts.cmd_history = number()?,
+                '/' => opts.search_history = number()?,
+                '@' => opts.input_history = number()?,
+                'f' => opts.file_marks = number()? != 0,
+                '%' => opts.buffers = Some(if arg.is_empty() { usize::MAX } else { number()? }),
+                '!' => opts.globals = true,
+                'h' => opts.no_hlsearch = true,
+                'c' => {}
+                'n' => opts.name = Some(arg.to_string()),
+                'r' => opts.removable.push(arg.to_string()),
+                _ => return Err(format!("E539: Illegal character <{}>", flag)),
+            }
+            rest = match after.strip_prefix(',') {
+                Some(after) => after,
+                None if after.is_empty() => after,
+                None => return Err("E527: Missing comma".to_string()),
+            };
+        }
+        if !value.is_empty() {
+            opts.files = files.ok_or("E528: Must specify a ' value")?;
+        }
+        Ok(opts)
+    }
+
+    /// The number of entries stored for history `hist`.
+    pub fn history_len(&self, hist: HistType) -> usize {
+        match hist {
+            HistType::Cmd => self.cmd_history,
+            HistType::Search => self.search_history,
+            HistType::Input => self.input_history,
+            HistType::Expr | HistType::Debug => self.history,
+        }
+    }
+
+    /// Whether `fname` is on removable media.
+    pub fn is_removable(&self, fname: &str) -> bool {
+        let fname = fname.to_lowercase();
+        self.removable.iter().any(|prefix| !prefix.is_empty() && fname.starts_with(&prefix.to_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_option() {
+        let opts = ViminfoOptions::default();
+        assert_eq!((opts.files, opts.register_lines, opts.register_kbyte), (100, Some(50), Some(10)));
+        assert!(opts.no_hlsearch && opts.file_marks && !opts.globals);
+        assert_eq!(opts.history_len(HistType::Cmd), 50);
+
+        let opts = ViminfoOptions::parse("'10,:20,/5,%,!,f0,r/tmp,r/mnt,n~/.vim/info", 50).unwrap();
+        assert_eq!((opts.files, opts.cmd_history, opts.search_history), (10, 20, 5));
+        assert_eq!((opts.buffers, opts.globals, opts.file_marks), (Some(usize::MAX), true, false));
+        assert_eq!(opts.removable, ["/tmp", "/mnt"]);
+        assert_eq!(opts.name.as_deref(), Some("~/.vim/info"));
+        assert!(opts.is_removable("/MNT/usb/file"));
+        assert_eq!(opts.history_len(HistType::Expr), 50);
+
+        assert_eq!(ViminfoOptions::parse("<50", 50), Err("E528: Must specify a ' value".to_string()));
+        assert_eq!(ViminfoOptions::parse("'x", 50), Err("E526: Missing number after <'>".to_string()));
+        assert_eq!(ViminfoOptions::parse("'10:5", 50), Err("E527: Missing comma".to_string()));
+        assert_eq!(ViminfoOptions::parse("'10,z", 50), Err("E539: Illegal character <z>".to_string()));
+        assert_eq!(ViminfoOptions::parse("", 50).map(|o| o.files), Ok(0));
+    }
+}